@@ -0,0 +1,216 @@
+//! Classification of agent tool-loop calls, to separate tool overhead from new content.
+//!
+//! An agent turn is usually one call that asks for tools, then follow-up calls that
+//! re-send the conversation with the tool results appended. The rules, applied to each
+//! successful chat request on its own:
+//!
+//! - The *new turn* is every message after the last assistant message (the whole array
+//!   when there is none). Everything before it was already sent on an earlier call.
+//! - A call whose new turn contains a `tool` or `function` message carries tool results.
+//!   It is `final` when the model answers without asking for more tools, and
+//!   `tool_followup` when it asks for more (`finish_reason` of `tool_calls`).
+//! - Any other call is `initial`, whether or not it asks for tools.
+//!
+//! Token counts use the model's tokenizer on message text, so they don't include the
+//! chat template's framing and won't add up to the upstream prompt tokens exactly.
+
+use serde_json::Value;
+
+use crate::db::RequestRecord;
+use crate::tokenizer::Tokenizers;
+
+pub const STEP_INITIAL: &str = "initial";
+pub const STEP_TOOL_FOLLOWUP: &str = "tool_followup";
+pub const STEP_FINAL: &str = "final";
+
+/// Text of the messages after the last assistant message, split by what they carry.
+#[derive(Debug, Default, PartialEq)]
+pub struct NewTurn {
+    pub tool_results: Vec<String>,
+    pub user_content: Vec<String>,
+}
+
+/// The messages added since the model last spoke.
+pub fn new_turn(messages: &[Value]) -> NewTurn {
+    let start = messages
+        .iter()
+        .rposition(|message| role(message) == Some("assistant"))
+        .map_or(0, |index| index + 1);
+
+    let mut turn = NewTurn::default();
+    for message in &messages[start..] {
+        let text = content_text(message.get("content").unwrap_or(&Value::Null));
+        match role(message) {
+            Some("tool" | "function") => turn.tool_results.push(text),
+            Some("user") => turn.user_content.push(text),
+            _ => {}
+        }
+    }
+    turn
+}
+
+pub fn classify(turn: &NewTurn, finish_reason: Option<&str>) -> &'static str {
+    let asks_for_tools = matches!(finish_reason, Some("tool_calls" | "function_call"));
+    match (turn.tool_results.is_empty(), asks_for_tools) {
+        (true, _) => STEP_INITIAL,
+        (false, true) => STEP_TOOL_FOLLOWUP,
+        (false, false) => STEP_FINAL,
+    }
+}
+
+/// Records the step and the tokens of new tool results and new user content for a
+/// successful request sent as a `messages` array.
+pub fn annotate(tokenizers: &Tokenizers, record: &mut RequestRecord) {
+    if record.is_error {
+        return;
+    }
+    let Ok(messages) = serde_json::from_str::<Vec<Value>>(&record.prompt) else {
+        return;
+    };
+
+    let turn = new_turn(&messages);
+    let tokenizer = tokenizers.for_model(&record.model);
+    let count = |texts: &[String]| texts.iter().map(|text| tokenizer.count(text)).sum();
+    record.agent_step = Some(classify(&turn, record.finish_reason.as_deref()).to_string());
+    record.tool_result_tokens = Some(count(&turn.tool_results));
+    record.new_user_tokens = Some(count(&turn.user_content));
+}
+
+fn role(message: &Value) -> Option<&str> {
+    message.get("role").and_then(Value::as_str)
+}
+
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TokenizerRule;
+    use chrono::Utc;
+    use serde_json::json;
+
+    /// A weather lookup for two cities: one call asking for a tool, one carrying its
+    /// result and asking for another, and the final answer.
+    const TWO_ROUNDS: &str = include_str!("../tests/fixtures/agent_two_rounds.json");
+
+    fn chars_tokenizer() -> Tokenizers {
+        Tokenizers::load(&[TokenizerRule {
+            pattern: "*".to_string(),
+            source: "chars/4".to_string(),
+        }])
+    }
+
+    fn annotated(call: &Value) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "qwen-7b".to_string(),
+            Utc::now(),
+            call["messages"].to_string(),
+        );
+        record.finish_reason = call["finish_reason"].as_str().map(str::to_string);
+        annotate(&chars_tokenizer(), &mut record);
+        record
+    }
+
+    #[test]
+    fn two_round_tool_interaction_is_classified_step_by_step() {
+        let calls: Vec<Value> = serde_json::from_str(TWO_ROUNDS).unwrap();
+        let steps: Vec<_> = calls
+            .iter()
+            .map(|call| {
+                let record = annotated(call);
+                (record.agent_step, record.tool_result_tokens, record.new_user_tokens)
+            })
+            .collect();
+
+        // The question is 40 characters and each tool result 43 and 41
+        assert_eq!(
+            steps,
+            [
+                (Some(STEP_INITIAL.to_string()), Some(0), Some(10)),
+                (Some(STEP_TOOL_FOLLOWUP.to_string()), Some(11), Some(0)),
+                (Some(STEP_FINAL.to_string()), Some(11), Some(0)),
+            ]
+        );
+        for call in &calls {
+            assert_eq!(annotated(call).agent_step.as_deref(), call["step"].as_str());
+        }
+    }
+
+    #[test]
+    fn new_turn_starts_after_the_last_assistant_message() {
+        let messages = vec![
+            json!({"role": "user", "content": "old question"}),
+            json!({"role": "assistant", "content": "old answer"}),
+            json!({"role": "tool", "content": "result"}),
+            json!({"role": "function", "content": "legacy result"}),
+            json!({
+                "role": "user",
+                "content": [{"type": "text", "text": "a"}, {"type": "image_url"}]
+            }),
+            json!({"role": "system", "content": "ignored"}),
+        ];
+        assert_eq!(
+            new_turn(&messages),
+            NewTurn {
+                tool_results: vec!["result".to_string(), "legacy result".to_string()],
+                user_content: vec!["a".to_string()],
+            }
+        );
+        assert_eq!(new_turn(&messages[..1]).user_content, ["old question"]);
+        assert_eq!(new_turn(&messages[..2]), NewTurn::default());
+    }
+
+    #[test]
+    fn steps_follow_tool_results_and_finish_reason() {
+        let plain = NewTurn {
+            tool_results: vec![],
+            user_content: vec!["q".to_string()],
+        };
+        let with_results = NewTurn {
+            tool_results: vec!["r".to_string()],
+            user_content: vec![],
+        };
+
+        assert_eq!(classify(&plain, Some("tool_calls")), STEP_INITIAL);
+        assert_eq!(classify(&plain, None), STEP_INITIAL);
+        assert_eq!(classify(&with_results, Some("tool_calls")), STEP_TOOL_FOLLOWUP);
+        assert_eq!(classify(&with_results, Some("function_call")), STEP_TOOL_FOLLOWUP);
+        assert_eq!(classify(&with_results, Some("stop")), STEP_FINAL);
+        assert_eq!(classify(&with_results, None), STEP_FINAL);
+    }
+
+    #[test]
+    fn errors_and_plain_prompts_are_not_annotated() {
+        let tokenizers = chars_tokenizer();
+        let mut failed = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            Utc::now(),
+            json!([{"role": "tool", "content": "r"}]).to_string(),
+        );
+        failed.is_error = true;
+        annotate(&tokenizers, &mut failed);
+        assert_eq!(failed.agent_step, None);
+
+        let mut completion = RequestRecord::new(
+            "/v1/completions".to_string(),
+            "m".to_string(),
+            Utc::now(),
+            "Once upon a time".to_string(),
+        );
+        annotate(&tokenizers, &mut completion);
+        assert_eq!(completion.agent_step, None);
+        assert_eq!(completion.tool_result_tokens, None);
+    }
+}
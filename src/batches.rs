@@ -0,0 +1,201 @@
+//! Background processing of JSONL batches submitted to `/v1/batches`.
+//!
+//! Every line goes through the regular proxy handler, so it is logged as a normal request
+//! (tagged with the batch id) and subject to the same checks as interactive traffic.
+//! Lines are stored before anything runs and marked done one by one, so a batch cut short
+//! by a restart resumes from its pending lines. A line that was in flight at the time runs
+//! again.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    response::IntoResponse,
+};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use crate::db::batches::{
+    self as store, BATCH_CANCELLED, BATCH_COMPLETED, BATCH_RUNNING, ITEM_FAILED, ITEM_SUCCEEDED,
+    ItemResult, PendingItem,
+};
+use crate::proxy::AppState;
+
+/// Marks a request as one line of a batch. Set as a request extension, so clients can't.
+#[derive(Debug, Clone)]
+pub struct BatchTag(pub String);
+
+/// User-Agent for batch lines when the submitter didn't send one
+const BATCH_USER_AGENT: &str = "lms-metrics-proxy-batch";
+
+/// Runs batches and relays cancellation to the ones in progress.
+#[derive(Default)]
+pub struct Batches {
+    /// Batch id -> cancel flag for batches running in this process
+    running: Mutex<HashMap<String, watch::Sender<bool>>>,
+}
+
+impl Batches {
+    /// Starts processing a stored batch unless it is already running.
+    pub fn start(self: &Arc<Self>, state: Arc<AppState>, id: String) {
+        let (tx, rx) = watch::channel(false);
+        {
+            let mut running = self.lock();
+            if running.contains_key(&id) {
+                return;
+            }
+            running.insert(id.clone(), tx);
+        }
+
+        let batches = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run(&state, &id, rx).await {
+                tracing::error!("Batch {} stopped: {}", id, e);
+            }
+            batches.lock().remove(&id);
+        });
+    }
+
+    /// Picks up batches left queued or running by the last shutdown.
+    pub async fn resume(self: &Arc<Self>, state: Arc<AppState>) -> Result<usize, sqlx::Error> {
+        let ids = store::get_unfinished_batches(&state.db).await?;
+        let count = ids.len();
+        for id in ids {
+            self.start(state.clone(), id);
+        }
+        Ok(count)
+    }
+
+    /// Stops a running batch from starting more lines; `false` if it isn't running here.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.lock().get(id) {
+            Some(tx) => tx.send(true).is_ok(),
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, watch::Sender<bool>>> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+async fn run(
+    state: &Arc<AppState>,
+    id: &str,
+    mut cancel: watch::Receiver<bool>,
+) -> Result<(), sqlx::Error> {
+    let Some(batch) = store::get_batch(&state.db, id).await? else {
+        return Ok(());
+    };
+    store::set_batch_status(&state.db, id, BATCH_RUNNING).await?;
+    let pending = store::get_pending_items(&state.db, id).await?;
+    tracing::info!("Running batch {} ({} pending lines)", id, pending.len());
+
+    let peer = SocketAddr::new(
+        batch
+            .client_ip
+            .as_deref()
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .unwrap_or(IpAddr::from([127, 0, 0, 1])),
+        0,
+    );
+    let user_agent = batch
+        .user_agent
+        .unwrap_or_else(|| BATCH_USER_AGENT.to_string());
+
+    let concurrency = state.config.batch_concurrency.max(1);
+    let mut items = pending.into_iter();
+    let mut in_flight = JoinSet::new();
+    loop {
+        while in_flight.len() < concurrency && !*cancel.borrow_and_update() {
+            let Some(item) = items.next() else { break };
+            let state = state.clone();
+            let batch_id = id.to_string();
+            let user_agent = user_agent.clone();
+            in_flight.spawn(async move {
+                let result = run_item(state.clone(), &batch_id, peer, &user_agent, item).await;
+                store::finish_item(&state.db, &batch_id, &result).await
+            });
+        }
+
+        match in_flight.join_next().await {
+            Some(Ok(stored)) => stored?,
+            Some(Err(e)) => tracing::error!("Batch {} line panicked: {}", id, e),
+            None => break,
+        }
+    }
+
+    if *cancel.borrow() {
+        store::cancel_pending_items(&state.db, id).await?;
+        store::set_batch_status(&state.db, id, BATCH_CANCELLED).await?;
+        tracing::info!("Batch {} cancelled", id);
+    } else {
+        store::set_batch_status(&state.db, id, BATCH_COMPLETED).await?;
+        tracing::info!("Batch {} completed", id);
+    }
+    Ok(())
+}
+
+/// Sends one line through the proxy handler and captures the response.
+async fn run_item(
+    state: Arc<AppState>,
+    batch_id: &str,
+    peer: SocketAddr,
+    user_agent: &str,
+    item: PendingItem,
+) -> ItemResult {
+    // Results are collected whole, so never ask upstream to stream
+    let body = match serde_json::from_str::<Value>(&item.body) {
+        Ok(Value::Object(mut body)) => {
+            body.insert("stream".to_string(), Value::Bool(false));
+            Value::Object(body).to_string()
+        }
+        _ => item.body,
+    };
+
+    let request = Request::builder()
+        .method("POST")
+        .uri(&item.url)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(hyper::header::USER_AGENT, user_agent)
+        .extension(BatchTag(batch_id.to_string()))
+        .body(Body::from(body));
+    let response = match request {
+        Ok(request) => {
+            match crate::proxy::proxy_handler(State(state), ConnectInfo(peer), request).await {
+                Ok(response) => response,
+                Err(e) => e.into_response(),
+            }
+        }
+        Err(e) => crate::error::ProxyError::BadRequest(e.to_string()).into_response(),
+    };
+
+    let status = response.status();
+    let proxy_request_id = response
+        .headers()
+        .get(crate::proxy::handler::REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let body = match response.into_body().collect().await {
+        Ok(body) => String::from_utf8_lossy(&body.to_bytes()).to_string(),
+        Err(e) => e.to_string(),
+    };
+
+    ItemResult {
+        line: item.line,
+        custom_id: None,
+        status: if status.is_success() {
+            ITEM_SUCCEEDED
+        } else {
+            ITEM_FAILED
+        }
+        .to_string(),
+        http_status: Some(status.as_u16() as i64),
+        response: Some(body),
+        proxy_request_id,
+    }
+}
@@ -0,0 +1,202 @@
+//! Background runs of `/admin/benchmark`: stored prompts replayed against several models.
+//!
+//! Like batch lines, every run goes through the regular proxy handler, so it is logged as
+//! a normal request (tagged with the benchmark id) and subject to the same guardrails and
+//! deadlines as interactive traffic. Runs are streamed so the proxy measures time to first
+//! token itself. They are stored with their bodies before anything is sent and marked done
+//! one by one, so a benchmark cut short by a restart resumes from its pending runs; a run
+//! that was in flight at the time is sent again.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    response::IntoResponse,
+};
+use http_body_util::BodyExt;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use crate::db::benchmarks::{
+    self as store, BENCHMARK_BUDGET_EXCEEDED, BENCHMARK_CANCELLED, BENCHMARK_COMPLETED,
+    BENCHMARK_RUNNING, PendingRun,
+};
+use crate::proxy::AppState;
+
+/// Marks a request as a benchmark run. Set as a request extension, so clients can't.
+#[derive(Debug, Clone)]
+pub struct BenchmarkTag(pub String);
+
+/// User-Agent of benchmark runs
+const BENCHMARK_USER_AGENT: &str = "lms-metrics-proxy-benchmark";
+
+/// Runs benchmarks and relays cancellation to the ones in progress.
+#[derive(Default)]
+pub struct Benchmarks {
+    /// Benchmark id -> cancel flag for benchmarks running in this process
+    running: Mutex<HashMap<String, watch::Sender<bool>>>,
+}
+
+impl Benchmarks {
+    /// Starts a stored benchmark unless it is already running.
+    pub fn start(self: &Arc<Self>, state: Arc<AppState>, id: String) {
+        let (tx, rx) = watch::channel(false);
+        {
+            let mut running = self.lock();
+            if running.contains_key(&id) {
+                return;
+            }
+            running.insert(id.clone(), tx);
+        }
+
+        let benchmarks = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run(&state, &id, rx).await {
+                tracing::error!("Benchmark {} stopped: {}", id, e);
+            }
+            benchmarks.lock().remove(&id);
+        });
+    }
+
+    /// Picks up benchmarks left queued or running by the last shutdown.
+    pub async fn resume(self: &Arc<Self>, state: Arc<AppState>) -> Result<usize, sqlx::Error> {
+        let ids = store::get_unfinished_benchmarks(&state.db).await?;
+        let count = ids.len();
+        for id in ids {
+            self.start(state.clone(), id);
+        }
+        Ok(count)
+    }
+
+    /// Stops a running benchmark from sending more runs; `false` if it isn't running here.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.lock().get(id) {
+            Some(tx) => tx.send(true).is_ok(),
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, watch::Sender<bool>>> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+async fn run(
+    state: &Arc<AppState>,
+    id: &str,
+    mut cancel: watch::Receiver<bool>,
+) -> Result<(), sqlx::Error> {
+    let Some(benchmark) = store::get_benchmark(&state.db, id).await? else {
+        return Ok(());
+    };
+    store::set_benchmark_status(&state.db, id, BENCHMARK_RUNNING).await?;
+    let pending = store::get_pending_runs(&state.db, id).await?;
+    tracing::info!("Running benchmark {} ({} pending runs)", id, pending.len());
+
+    let concurrency = benchmark
+        .spec
+        .get("concurrency")
+        .and_then(|v| v.as_u64())
+        .map_or(state.config.batch_concurrency, |n| n as usize)
+        .max(1);
+    let max_cost = benchmark.spec.get("max_cost").and_then(|v| v.as_f64());
+
+    let peer = SocketAddr::new(IpAddr::from([127, 0, 0, 1]), 0);
+    let mut runs = pending.into_iter();
+    let mut in_flight = JoinSet::new();
+    let mut over_budget = false;
+    loop {
+        while in_flight.len() < concurrency && !*cancel.borrow_and_update() && !over_budget {
+            // Checked before every run, so at most `concurrency` runs overshoot the budget
+            if let Some(max_cost) = max_cost
+                && store::get_spent(&state.db, id).await? >= max_cost
+            {
+                over_budget = true;
+                break;
+            }
+            let Some(pending_run) = runs.next() else {
+                break;
+            };
+            let state = state.clone();
+            let benchmark_id = id.to_string();
+            in_flight.spawn(async move {
+                let run = pending_run.run;
+                let (status, proxy_request_id) =
+                    send_run(state.clone(), &benchmark_id, peer, pending_run).await;
+                store::finish_run(
+                    &state.db,
+                    &benchmark_id,
+                    run,
+                    status,
+                    proxy_request_id.as_deref(),
+                )
+                .await
+            });
+        }
+
+        match in_flight.join_next().await {
+            Some(Ok(stored)) => stored?,
+            Some(Err(e)) => tracing::error!("Benchmark {} run panicked: {}", id, e),
+            None => break,
+        }
+    }
+
+    let status = if *cancel.borrow() {
+        BENCHMARK_CANCELLED
+    } else if over_budget {
+        BENCHMARK_BUDGET_EXCEEDED
+    } else {
+        BENCHMARK_COMPLETED
+    };
+    if status != BENCHMARK_COMPLETED {
+        store::cancel_pending_runs(&state.db, id).await?;
+    }
+    store::set_benchmark_status(&state.db, id, status).await?;
+    tracing::info!("Benchmark {} {}", id, status);
+    Ok(())
+}
+
+/// Sends one run through the proxy handler and reads the stream to the end, returning
+/// the status and the proxy request id of the logged request.
+async fn send_run(
+    state: Arc<AppState>,
+    benchmark_id: &str,
+    peer: SocketAddr,
+    pending_run: PendingRun,
+) -> (u16, Option<String>) {
+    let run = pending_run.run;
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(hyper::header::USER_AGENT, BENCHMARK_USER_AGENT)
+        .extension(BenchmarkTag(benchmark_id.to_string()))
+        .body(Body::from(pending_run.body));
+    let response = match request {
+        Ok(request) => {
+            match crate::proxy::proxy_handler(State(state), ConnectInfo(peer), request).await {
+                Ok(response) => response,
+                Err(e) => e.into_response(),
+            }
+        }
+        Err(e) => crate::error::ProxyError::BadRequest(e.to_string()).into_response(),
+    };
+
+    let status = response.status().as_u16();
+    let proxy_request_id = response
+        .headers()
+        .get(crate::proxy::handler::REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    if let Err(e) = response.into_body().collect().await {
+        tracing::warn!(
+            "Benchmark {} run {} stream failed: {}",
+            benchmark_id,
+            run,
+            e
+        );
+    }
+    (status, proxy_request_id)
+}
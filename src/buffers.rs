@@ -0,0 +1,352 @@
+//! Accounting for the memory proxied requests hold in buffers.
+//!
+//! Request bodies, collected responses, streamed output, SSE lines waiting for their end
+//! and raw incident captures each hold a [`BufferGuard`] sized to what they keep, so one
+//! atomic tally shows the total at any moment. With `MAX_BUFFERED_BYTES` set, a new
+//! request that would take the tally past it is refused with 503, and a stream's buffers
+//! stop growing there while its chunks still reach the client. Sizes are string and
+//! body lengths, not allocator capacity, so the real footprint runs somewhat higher.
+
+use axum::body::{Body, Bytes};
+use http_body_util::BodyExt;
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub struct BufferBudget {
+    /// 0 means no cap
+    cap: u64,
+    current: AtomicU64,
+    peak: AtomicU64,
+    requests_rejected: AtomicU64,
+    growth_refused: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BufferSnapshot {
+    /// `MAX_BUFFERED_BYTES`; `None` without a cap
+    pub cap_bytes: Option<u64>,
+    pub current_bytes: u64,
+    /// Most bytes buffered at once since startup
+    pub peak_bytes: u64,
+    /// Requests refused with 503 because their body would not fit, or arrived while the
+    /// tally was already past the cap
+    pub requests_rejected: u64,
+    /// Times a stream's buffer was kept from growing past the cap
+    pub growth_refused: u64,
+}
+
+impl BufferSnapshot {
+    /// The figures in the Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        let mut metrics = vec![
+            (
+                "lms_proxy_buffered_bytes",
+                "gauge",
+                "Bytes held in request and response buffers",
+                self.current_bytes,
+            ),
+            (
+                "lms_proxy_buffered_bytes_peak",
+                "gauge",
+                "Most bytes buffered at once since startup",
+                self.peak_bytes,
+            ),
+            (
+                "lms_proxy_buffer_rejected_requests_total",
+                "counter",
+                "Requests refused because buffers were full",
+                self.requests_rejected,
+            ),
+            (
+                "lms_proxy_buffer_growth_refused_total",
+                "counter",
+                "Buffer growth refused at the cap",
+                self.growth_refused,
+            ),
+        ];
+        if let Some(cap) = self.cap_bytes {
+            metrics.push((
+                "lms_proxy_buffered_bytes_cap",
+                "gauge",
+                "MAX_BUFFERED_BYTES",
+                cap,
+            ));
+        }
+        metrics
+            .into_iter()
+            .map(|(name, kind, help, value)| {
+                format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n")
+            })
+            .collect()
+    }
+}
+
+impl BufferBudget {
+    pub fn new(cap: u64) -> Arc<Self> {
+        Arc::new(Self {
+            cap,
+            current: AtomicU64::new(0),
+            peak: AtomicU64::new(0),
+            requests_rejected: AtomicU64::new(0),
+            growth_refused: AtomicU64::new(0),
+        })
+    }
+
+    /// Takes `bytes` for a new request's body, or `None` when they would pass the cap.
+    pub fn admit(self: &Arc<Self>, bytes: u64) -> Option<BufferGuard> {
+        if !self.grow(bytes, false) {
+            self.requests_rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(BufferGuard {
+            budget: self.clone(),
+            bytes,
+        })
+    }
+
+    /// Reads a request body that announced `declared` bytes, holding what arrives against
+    /// the cap. `None` when the body doesn't fit, counted like a refused admission.
+    pub async fn read_body(
+        self: &Arc<Self>,
+        body: Body,
+        declared: u64,
+    ) -> Result<Option<(Bytes, BufferGuard)>, axum::Error> {
+        let Some(mut guard) = self.admit(declared) else {
+            return Ok(None);
+        };
+        let mut body = body;
+        let mut read = Vec::new();
+        while let Some(frame) = body.frame().await {
+            if let Ok(data) = frame?.into_data() {
+                let size = (read.len() + data.len()) as u64;
+                if size > guard.bytes && !guard.try_resize(size) {
+                    self.requests_rejected.fetch_add(1, Ordering::Relaxed);
+                    return Ok(None);
+                }
+                read.extend_from_slice(&data);
+            }
+        }
+        guard.resize(read.len() as u64);
+        Ok(Some((Bytes::from(read), guard)))
+    }
+
+    /// An empty guard for another buffer of a request already admitted.
+    pub fn guard(self: &Arc<Self>) -> BufferGuard {
+        BufferGuard {
+            budget: self.clone(),
+            bytes: 0,
+        }
+    }
+
+    pub fn snapshot(&self) -> BufferSnapshot {
+        BufferSnapshot {
+            cap_bytes: (self.cap > 0).then_some(self.cap),
+            current_bytes: self.current.load(Ordering::Relaxed),
+            peak_bytes: self.peak.load(Ordering::Relaxed),
+            requests_rejected: self.requests_rejected.load(Ordering::Relaxed),
+            growth_refused: self.growth_refused.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Adds `bytes` to the tally if the cap allows it, or regardless with `force`.
+    fn grow(&self, bytes: u64, force: bool) -> bool {
+        let grown = self
+            .current
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                let total = current.saturating_add(bytes);
+                (force || self.cap == 0 || total <= self.cap).then_some(total)
+            });
+        match grown {
+            Ok(previous) => {
+                self.peak.fetch_max(previous + bytes, Ordering::Relaxed);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Bytes one buffer holds, given back to the budget when dropped.
+pub struct BufferGuard {
+    budget: Arc<BufferBudget>,
+    bytes: u64,
+}
+
+impl BufferGuard {
+    /// Sets the buffer's size to `bytes`, refusing growth past the cap. Shrinking always
+    /// succeeds.
+    pub fn try_resize(&mut self, bytes: u64) -> bool {
+        self.resize_to(bytes, false)
+    }
+
+    /// Sets the buffer's size to `bytes` even past the cap, for data already in memory.
+    pub fn resize(&mut self, bytes: u64) {
+        self.resize_to(bytes, true);
+    }
+
+    fn resize_to(&mut self, bytes: u64, force: bool) -> bool {
+        if bytes <= self.bytes {
+            self.budget
+                .current
+                .fetch_sub(self.bytes - bytes, Ordering::Relaxed);
+        } else if !self.budget.grow(bytes - self.bytes, force) {
+            self.budget.growth_refused.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.bytes = bytes;
+        true
+    }
+}
+
+impl Drop for BufferGuard {
+    fn drop(&mut self) {
+        self.budget.current.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunked(parts: &[&'static str]) -> Body {
+        let frames = parts.iter().map(|part| Ok::<_, std::io::Error>(Bytes::from(*part)));
+        Body::from_stream(tokio_stream::iter(frames.collect::<Vec<_>>()))
+    }
+
+    #[test]
+    fn guards_give_their_bytes_back_when_dropped() {
+        let budget = BufferBudget::new(0);
+        let body = budget.admit(300).unwrap();
+        let mut response = budget.guard();
+        response.resize(500);
+        assert_eq!(budget.snapshot().current_bytes, 800);
+
+        drop(body);
+        assert_eq!(budget.snapshot().current_bytes, 500);
+        response.resize(200);
+        assert_eq!(budget.snapshot().current_bytes, 200);
+        drop(response);
+
+        let snapshot = budget.snapshot();
+        assert_eq!(snapshot.current_bytes, 0);
+        assert_eq!(snapshot.peak_bytes, 800);
+        assert_eq!(snapshot.cap_bytes, None);
+        assert_eq!(snapshot.requests_rejected, 0);
+    }
+
+    #[test]
+    fn admission_past_the_cap_is_refused_and_counted() {
+        let budget = BufferBudget::new(1000);
+        let first = budget.admit(600).unwrap();
+        assert!(budget.admit(401).is_none());
+        let second = budget.admit(400).unwrap();
+        assert!(budget.admit(1).is_none());
+
+        drop(first);
+        assert!(budget.admit(600).is_some());
+        drop(second);
+
+        let snapshot = budget.snapshot();
+        assert_eq!(snapshot.cap_bytes, Some(1000));
+        assert_eq!(snapshot.current_bytes, 0);
+        assert_eq!(snapshot.peak_bytes, 1000);
+        assert_eq!(snapshot.requests_rejected, 2);
+        assert_eq!(snapshot.growth_refused, 0);
+    }
+
+    #[test]
+    fn growth_stops_at_the_cap_unless_forced() {
+        let budget = BufferBudget::new(100);
+        let mut stream = budget.guard();
+        assert!(stream.try_resize(80));
+        assert!(!stream.try_resize(101));
+        // A refused resize leaves the guard at what it held
+        assert_eq!(budget.snapshot().current_bytes, 80);
+        assert!(stream.try_resize(10));
+        assert_eq!(budget.snapshot().current_bytes, 10);
+
+        // Data already in memory is counted regardless
+        stream.resize(150);
+        let snapshot = budget.snapshot();
+        assert_eq!(snapshot.current_bytes, 150);
+        assert_eq!(snapshot.peak_bytes, 150);
+        assert_eq!(snapshot.growth_refused, 1);
+        assert!(budget.admit(1).is_none());
+    }
+
+    #[tokio::test]
+    async fn bodies_are_held_at_their_read_size() {
+        let budget = BufferBudget::new(1000);
+        let (bytes, guard) = budget
+            .read_body(chunked(&["hello ", "world"]), 500)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&bytes[..], b"hello world");
+        // Declared more than arrived: the tally follows what was read
+        assert_eq!(budget.snapshot().current_bytes, 11);
+        drop(guard);
+        assert_eq!(budget.snapshot().current_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn bodies_outgrowing_their_declared_size_stop_at_the_cap() {
+        let budget = BufferBudget::new(10);
+        let parts = ["12345", "67890", "x"];
+        assert!(budget.read_body(chunked(&parts), 0).await.unwrap().is_none());
+        // The same body fits without a cap
+        let unbounded = BufferBudget::new(0);
+        assert!(unbounded.read_body(chunked(&parts), 0).await.unwrap().is_some());
+
+        let snapshot = budget.snapshot();
+        assert_eq!(snapshot.current_bytes, 0);
+        assert_eq!(snapshot.peak_bytes, 10);
+        assert_eq!(snapshot.requests_rejected, 1);
+    }
+
+    #[test]
+    fn concurrent_admissions_never_pass_the_cap() {
+        let budget = BufferBudget::new(10_000);
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let budget = budget.clone();
+                std::thread::spawn(move || {
+                    let mut admitted = 0;
+                    for size in (1..500).map(|i| (i * 37) % 3000) {
+                        if let Some(mut guard) = budget.admit(size) {
+                            admitted += 1;
+                            guard.try_resize(size * 2);
+                            assert!(budget.snapshot().current_bytes <= 10_000);
+                        }
+                    }
+                    admitted
+                })
+            })
+            .collect();
+        let admitted: u64 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+
+        let snapshot = budget.snapshot();
+        assert_eq!(snapshot.current_bytes, 0);
+        assert!(snapshot.peak_bytes <= 10_000, "{:?}", snapshot);
+        assert_eq!(admitted + snapshot.requests_rejected, 8 * 499);
+    }
+
+    #[test]
+    fn prometheus_lists_the_cap_only_when_set() {
+        let budget = BufferBudget::new(2048);
+        let guard = budget.admit(512).unwrap();
+        let text = budget.snapshot().prometheus();
+        assert!(text.contains(
+            "# TYPE lms_proxy_buffered_bytes gauge\nlms_proxy_buffered_bytes 512\n"
+        ));
+        assert!(text.contains("lms_proxy_buffered_bytes_peak 512\n"));
+        assert!(text.contains("lms_proxy_buffer_rejected_requests_total 0\n"));
+        assert!(text.contains("lms_proxy_buffered_bytes_cap 2048\n"));
+        drop(guard);
+
+        let text = BufferBudget::new(0).snapshot().prometheus();
+        assert!(!text.contains("lms_proxy_buffered_bytes_cap"));
+        assert!(text.contains("# TYPE lms_proxy_buffer_growth_refused_total counter\n"));
+    }
+}
@@ -0,0 +1,459 @@
+//! Weighted routing between the primary upstream and canaries.
+//!
+//! Each canary takes a percentage of the traffic, so a new LM Studio install can be given
+//! 5%, then 25%, then everything while `/stats/canary` compares it with the primary.
+//! Requests are assigned by hashing their session key into one of [`BUCKETS`] buckets;
+//! the canaries' shares are laid out from the start of the bucket space in configured
+//! order and the primary takes the rest. A session therefore stays on one upstream for
+//! as long as the weights don't change, and raising a canary's share only moves sessions
+//! onto it, never between other upstreams ahead of it.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::config::CanaryConfig;
+use crate::db::canary::UpstreamStats;
+
+/// Name of the upstream at `LM_STUDIO_URL`
+pub const PRIMARY_UPSTREAM: &str = "primary";
+
+/// `proxy_events` kind of a weight change
+pub const ROUTING_WEIGHTS_EVENT: &str = "routing_weights";
+
+/// Buckets sessions are hashed into, so shares are honoured to a hundredth of a percent
+const BUCKETS: u64 = 10_000;
+
+/// Requests each side needs in the window before a canary gets a verdict
+pub const MIN_VERDICT_REQUESTS: i64 = 20;
+
+#[derive(Debug)]
+pub struct Upstream {
+    pub name: String,
+    pub url: String,
+}
+
+/// Picks the upstream for each request from the current canary weights.
+pub struct CanaryRouter {
+    primary: Upstream,
+    canaries: Vec<Upstream>,
+    /// Percent of sessions per canary, in the order of `canaries`
+    weights: Mutex<Vec<f64>>,
+}
+
+impl CanaryRouter {
+    pub fn new(primary_url: &str, config: &CanaryConfig) -> anyhow::Result<Self> {
+        let mut canaries: Vec<Upstream> = Vec::new();
+        for (name, url) in &config.upstreams {
+            if name == PRIMARY_UPSTREAM || canaries.iter().any(|canary| &canary.name == name) {
+                return Err(anyhow::anyhow!(
+                    "Invalid CANARY_UPSTREAMS entry {}: names must be unique and not {}",
+                    name,
+                    PRIMARY_UPSTREAM
+                ));
+            }
+            canaries.push(Upstream {
+                name: name.clone(),
+                url: url.clone(),
+            });
+        }
+
+        let router = Self {
+            primary: Upstream {
+                name: PRIMARY_UPSTREAM.to_string(),
+                url: primary_url.to_string(),
+            },
+            weights: Mutex::new(vec![0.0; canaries.len()]),
+            canaries,
+        };
+        let weights = router
+            .merged_weights(&config.weights)
+            .map_err(|e| anyhow::anyhow!("Invalid CANARY_WEIGHTS: {}", e))?;
+        *router.lock() = weights;
+        Ok(router)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<f64>> {
+        self.weights.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The upstream a session's requests go to under the current weights.
+    pub fn route(&self, session: &str) -> &Upstream {
+        let bucket = bucket(session);
+        let weights = self.lock();
+        let mut end = 0;
+        for (canary, weight) in self.canaries.iter().zip(weights.iter()) {
+            end += (weight * (BUCKETS / 100) as f64).round() as u64;
+            if bucket < end {
+                return canary;
+            }
+        }
+        &self.primary
+    }
+
+    /// Current percentage per upstream, the primary's being whatever the canaries leave.
+    pub fn weights(&self) -> BTreeMap<String, f64> {
+        let weights = self.lock();
+        let mut shares: BTreeMap<String, f64> = self
+            .canaries
+            .iter()
+            .zip(weights.iter())
+            .map(|(canary, weight)| (canary.name.clone(), *weight))
+            .collect();
+        let canary_total: f64 = weights.iter().sum();
+        shares.insert(PRIMARY_UPSTREAM.to_string(), 100.0 - canary_total);
+        shares
+    }
+
+    /// Applies new percentages for the named canaries, leaving the others as they are,
+    /// and returns the shares in force before.
+    pub fn set_weights(&self, changes: &[(String, f64)]) -> Result<BTreeMap<String, f64>, String> {
+        let previous = self.weights();
+        let weights = self.merged_weights(changes)?;
+        *self.lock() = weights;
+        Ok(previous)
+    }
+
+    /// The current weights with `changes` applied, if every name is a canary and the
+    /// canaries' shares stay within 100%.
+    fn merged_weights(&self, changes: &[(String, f64)]) -> Result<Vec<f64>, String> {
+        let mut weights = self.lock().clone();
+        for (name, percent) in changes {
+            let Some(index) = self.canaries.iter().position(|canary| &canary.name == name) else {
+                let known: Vec<&str> = self.canaries.iter().map(|c| c.name.as_str()).collect();
+                return Err(format!(
+                    "unknown canary '{}', expected one of: {}",
+                    name,
+                    if known.is_empty() {
+                        "(none configured)".to_string()
+                    } else {
+                        known.join(", ")
+                    }
+                ));
+            };
+            if !percent.is_finite() || !(0.0..=100.0).contains(percent) {
+                return Err(format!("weight for '{}' must be between 0 and 100", name));
+            }
+            weights[index] = *percent;
+        }
+        let total: f64 = weights.iter().sum();
+        if total > 100.0 {
+            return Err(format!(
+                "canary weights add up to {}%, more than 100%",
+                total
+            ));
+        }
+        Ok(weights)
+    }
+}
+
+/// The bucket a session key falls into, stable across restarts and machines.
+fn bucket(session: &str) -> u64 {
+    let digest = Sha256::digest(session.as_bytes());
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(prefix) % BUCKETS
+}
+
+/// How a canary compares with the primary over the report window.
+#[derive(Debug, Serialize)]
+pub struct CanaryVerdict {
+    pub upstream: String,
+    /// `within_tolerance`, `outside_tolerance` or `insufficient_data`
+    pub verdict: &'static str,
+    /// Canary error rate minus the primary's
+    pub error_rate_delta: Option<f64>,
+    /// Canary average latency over the primary's
+    pub latency_ratio: Option<f64>,
+    /// Why the canary is outside tolerance, or what data is missing
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CanaryReport {
+    pub since: Option<String>,
+    /// Current percentage of sessions per upstream
+    pub weights: BTreeMap<String, f64>,
+    pub max_error_rate_delta: f64,
+    pub max_latency_ratio: f64,
+    /// Primary first, then canaries in configured order
+    pub upstreams: Vec<UpstreamStats>,
+    pub verdicts: Vec<CanaryVerdict>,
+}
+
+impl CanaryRouter {
+    /// Lays per-upstream stats out in routing order and judges each canary against the
+    /// primary. Upstreams without traffic in the window are reported with zero requests.
+    pub fn report(
+        &self,
+        since: Option<String>,
+        stats: Vec<UpstreamStats>,
+        config: &CanaryConfig,
+    ) -> CanaryReport {
+        let mut stats: BTreeMap<String, UpstreamStats> = stats
+            .into_iter()
+            .map(|stats| (stats.upstream.clone(), stats))
+            .collect();
+        let mut upstreams: Vec<UpstreamStats> = std::iter::once(&self.primary)
+            .chain(&self.canaries)
+            .map(|upstream| {
+                stats
+                    .remove(&upstream.name)
+                    .unwrap_or_else(|| UpstreamStats::empty(&upstream.name))
+            })
+            .collect();
+        // Keep traffic from canaries that have since been removed from the config
+        upstreams.extend(stats.into_values());
+
+        let primary = &upstreams[0];
+        let verdicts = upstreams[1..=self.canaries.len()]
+            .iter()
+            .map(|canary| judge(primary, canary, config))
+            .collect();
+
+        CanaryReport {
+            since,
+            weights: self.weights(),
+            max_error_rate_delta: config.max_error_rate_delta,
+            max_latency_ratio: config.max_latency_ratio,
+            upstreams,
+            verdicts,
+        }
+    }
+}
+
+fn judge(primary: &UpstreamStats, canary: &UpstreamStats, config: &CanaryConfig) -> CanaryVerdict {
+    let mut reasons = Vec::new();
+    for side in [primary, canary] {
+        if side.requests < MIN_VERDICT_REQUESTS {
+            reasons.push(format!(
+                "{} has {} request(s), {} needed",
+                side.upstream, side.requests, MIN_VERDICT_REQUESTS
+            ));
+        }
+    }
+    if !reasons.is_empty() {
+        return CanaryVerdict {
+            upstream: canary.upstream.clone(),
+            verdict: "insufficient_data",
+            error_rate_delta: None,
+            latency_ratio: None,
+            reasons,
+        };
+    }
+
+    let error_rate_delta = canary.error_rate - primary.error_rate;
+    if error_rate_delta > config.max_error_rate_delta {
+        reasons.push(format!(
+            "error rate {:.3} is {:.3} above the primary's, limit {}",
+            canary.error_rate, error_rate_delta, config.max_error_rate_delta
+        ));
+    }
+    let latency_ratio =
+        (primary.avg_duration_ms > 0.0).then(|| canary.avg_duration_ms / primary.avg_duration_ms);
+    if let Some(ratio) = latency_ratio
+        && ratio > config.max_latency_ratio
+    {
+        reasons.push(format!(
+            "average latency is {:.2}x the primary's, limit {}x",
+            ratio, config.max_latency_ratio
+        ));
+    }
+
+    CanaryVerdict {
+        upstream: canary.upstream.clone(),
+        verdict: if reasons.is_empty() {
+            "within_tolerance"
+        } else {
+            "outside_tolerance"
+        },
+        error_rate_delta: Some(error_rate_delta),
+        latency_ratio,
+        reasons,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(weights: &[(&str, f64)]) -> CanaryConfig {
+        CanaryConfig {
+            upstreams: vec![
+                ("new".to_string(), "http://new:1234".to_string()),
+                ("other".to_string(), "http://other:1234".to_string()),
+            ],
+            weights: weights
+                .iter()
+                .map(|(name, percent)| (name.to_string(), *percent))
+                .collect(),
+            max_error_rate_delta: 0.02,
+            max_latency_ratio: 1.25,
+        }
+    }
+
+    fn router(weights: &[(&str, f64)]) -> CanaryRouter {
+        CanaryRouter::new("http://primary:1234", &config(weights)).unwrap()
+    }
+
+    fn sessions(count: usize) -> impl Iterator<Item = String> {
+        (0..count).map(|i| format!("session-{}", i))
+    }
+
+    /// Share of `count` sessions per upstream, in percent.
+    fn shares(router: &CanaryRouter, count: usize) -> BTreeMap<String, f64> {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for session in sessions(count) {
+            *counts.entry(router.route(&session).name.clone()).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .map(|(name, n)| (name, n as f64 * 100.0 / count as f64))
+            .collect()
+    }
+
+    #[test]
+    fn buckets_are_stable() {
+        // Pinned, so a change to the hash can't silently move every session
+        assert_eq!(bucket("session-0"), bucket("session-0"));
+        assert_eq!(bucket(""), 1_652);
+        assert_eq!(bucket("session-0"), 6_629);
+    }
+
+    #[test]
+    fn assignment_is_deterministic() {
+        let first = router(&[("new", 30.0), ("other", 20.0)]);
+        let restarted = router(&[("new", 30.0), ("other", 20.0)]);
+        for session in sessions(1_000) {
+            let upstream = &first.route(&session).name;
+            assert_eq!(upstream, &first.route(&session).name);
+            assert_eq!(upstream, &restarted.route(&session).name);
+        }
+    }
+
+    #[test]
+    fn proportions_follow_the_weights() {
+        for (new, other) in [(5.0, 0.0), (25.0, 10.0), (50.0, 50.0), (0.5, 99.5)] {
+            let router = router(&[("new", new), ("other", other)]);
+            let shares = shares(&router, 100_000);
+            let share = |name: &str| shares.get(name).copied().unwrap_or(0.0);
+            let primary = 100.0 - new - other;
+            for (name, expected) in [("new", new), ("other", other), (PRIMARY_UPSTREAM, primary)] {
+                assert!((share(name) - expected).abs() < 0.5, "{}: {:?}", name, shares);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_and_full_weights_are_exact() {
+        let router = router(&[]);
+        assert!(sessions(10_000).all(|s| router.route(&s).name == PRIMARY_UPSTREAM));
+        router.set_weights(&[("new".to_string(), 100.0)]).unwrap();
+        assert!(sessions(10_000).all(|s| router.route(&s).name == "new"));
+        assert_eq!(router.route("session-0").url, "http://new:1234");
+    }
+
+    #[test]
+    fn raising_a_share_only_moves_sessions_onto_that_canary() {
+        let router = router(&[("new", 5.0), ("other", 10.0)]);
+        let before: Vec<String> = sessions(20_000).map(|s| router.route(&s).name.clone()).collect();
+
+        router.set_weights(&[("other".to_string(), 25.0)]).unwrap();
+        let mut moved = 0;
+        for (session, before) in sessions(20_000).zip(&before) {
+            let after = &router.route(&session).name;
+            if before != after {
+                // Only from the primary, whose share shrank, onto the raised canary
+                assert_eq!((before.as_str(), after.as_str()), (PRIMARY_UPSTREAM, "other"));
+                moved += 1;
+            }
+        }
+        assert!((2_500..3_500).contains(&moved), "{}", moved);
+    }
+
+    #[test]
+    fn weight_changes_are_validated_and_atomic() {
+        let router = router(&[("new", 5.0)]);
+
+        let previous = router.set_weights(&[("new".to_string(), 25.0)]).unwrap();
+        assert_eq!(previous["new"], 5.0);
+        assert_eq!(previous[PRIMARY_UPSTREAM], 95.0);
+
+        for changes in [
+            vec![("missing".to_string(), 1.0)],
+            vec![("new".to_string(), -1.0)],
+            vec![("new".to_string(), f64::NAN)],
+            // The first change is valid on its own, the total is not
+            vec![("new".to_string(), 60.0), ("other".to_string(), 50.0)],
+        ] {
+            assert!(router.set_weights(&changes).is_err(), "{:?}", changes);
+            assert_eq!(router.weights()["new"], 25.0);
+        }
+        let weights = router.weights();
+        assert_eq!(weights.values().sum::<f64>(), 100.0);
+        assert_eq!(weights["other"], 0.0);
+    }
+
+    #[test]
+    fn canary_names_must_be_unique() {
+        let mut duplicated = config(&[]);
+        duplicated.upstreams.push(("new".to_string(), "http://again:1234".to_string()));
+        assert!(CanaryRouter::new("http://primary:1234", &duplicated).is_err());
+        duplicated.upstreams = vec![(PRIMARY_UPSTREAM.to_string(), "http://x:1".to_string())];
+        assert!(CanaryRouter::new("http://primary:1234", &duplicated).is_err());
+        let too_much = config(&[("new", 70.0), ("other", 40.0)]);
+        assert!(CanaryRouter::new("http://primary:1234", &too_much).is_err());
+    }
+
+    fn stats(upstream: &str, requests: i64, errors: i64, avg_duration_ms: f64) -> UpstreamStats {
+        UpstreamStats {
+            error_rate: errors as f64 / requests.max(1) as f64,
+            avg_duration_ms,
+            requests,
+            errors,
+            ..UpstreamStats::empty(upstream)
+        }
+    }
+
+    #[test]
+    fn verdicts_compare_each_canary_with_the_primary() {
+        let router = router(&[("new", 10.0)]);
+        let config = config(&[]);
+        let report = router.report(
+            None,
+            vec![
+                stats("new", 100, 10, 900.0),
+                stats(PRIMARY_UPSTREAM, 100, 1, 1_000.0),
+                stats("retired", 3, 0, 10.0),
+            ],
+            &config,
+        );
+
+        let order: Vec<_> = report.upstreams.iter().map(|u| u.upstream.as_str()).collect();
+        assert_eq!(order, [PRIMARY_UPSTREAM, "new", "other", "retired"]);
+        assert_eq!(report.verdicts.len(), 2);
+        let new = &report.verdicts[0];
+        assert_eq!(new.verdict, "outside_tolerance");
+        assert_eq!(new.reasons.len(), 1, "{:?}", new.reasons);
+        assert!((new.error_rate_delta.unwrap() - 0.09).abs() < 1e-9);
+        assert_eq!(new.latency_ratio, Some(0.9));
+        let other = &report.verdicts[1];
+        assert_eq!(other.verdict, "insufficient_data");
+        assert_eq!(other.error_rate_delta, None);
+
+        let slow = judge(
+            &stats(PRIMARY_UPSTREAM, 50, 0, 1_000.0),
+            &stats("new", 50, 0, 1_300.0),
+            &config,
+        );
+        assert_eq!(slow.verdict, "outside_tolerance");
+        assert!(slow.reasons[0].contains("latency"), "{:?}", slow.reasons);
+        let fine = judge(
+            &stats(PRIMARY_UPSTREAM, 50, 1, 1_000.0),
+            &stats("new", 50, 2, 1_200.0),
+            &config,
+        );
+        assert_eq!(fine.verdict, "within_tolerance", "{:?}", fine.reasons);
+    }
+}
@@ -0,0 +1,262 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::json;
+use sqlx::SqlitePool;
+use sqlx::sqlite::SqlitePoolOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tokio_stream::StreamExt;
+
+use crate::db;
+use crate::db::models::SummaryFilter;
+use crate::format;
+use crate::stats::params::parse_duration;
+
+/// Exit code for a command that ran successfully but found nothing to report.
+/// Distinct from 1 (error) and 2 (usage error, reported by clap).
+pub const EXIT_EMPTY: u8 = 3;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Token usage tracking proxy for LM Studio")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print summary and per-model statistics from a database file
+    Stats {
+        /// SQLite database path (or sqlite: URL)
+        #[arg(long)]
+        db: String,
+
+        /// Print JSON instead of human-readable tables
+        #[arg(long)]
+        json: bool,
+    },
+    /// Export the request history from a database file
+    Export {
+        /// SQLite database path (or sqlite: URL)
+        #[arg(long)]
+        db: String,
+
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+
+        /// Output file; defaults to stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Delete requests older than a given age
+    Prune {
+        /// SQLite database path (or sqlite: URL)
+        #[arg(long)]
+        db: String,
+
+        /// Age threshold such as 90d, 12h or 2w
+        #[arg(long, value_parser = parse_age)]
+        older_than: chrono::Duration,
+
+        /// Report what would be deleted without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+}
+
+fn parse_age(value: &str) -> Result<chrono::Duration, String> {
+    parse_duration(value).ok_or_else(|| format!("invalid age '{}', expected e.g. 90d", value))
+}
+
+/// Runs an offline subcommand directly against the database file.
+pub async fn run(command: Command) -> anyhow::Result<ExitCode> {
+    match command {
+        Command::Stats { db, json } => stats(&open_db(&db).await?, json).await,
+        Command::Export { db, format, out } => export(&open_db(&db).await?, format, out).await,
+        Command::Prune {
+            db,
+            older_than,
+            dry_run,
+        } => prune(&open_db(&db).await?, older_than, dry_run).await,
+    }
+}
+
+async fn open_db(db: &str) -> anyhow::Result<SqlitePool> {
+    let path = db.strip_prefix("sqlite:").unwrap_or(db);
+    if !Path::new(path).exists() {
+        anyhow::bail!("Database file not found: {}", path);
+    }
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&format!("sqlite:{}?mode=rw", path))
+        .await?;
+
+    // Bring older databases up to date so queries can rely on every column
+    db::init_db(&pool).await?;
+    Ok(pool)
+}
+
+fn exit_code(found: bool) -> ExitCode {
+    if found {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_EMPTY)
+    }
+}
+
+async fn stats(pool: &SqlitePool, as_json: bool) -> anyhow::Result<ExitCode> {
+    let summary = db::get_summary_stats(pool, &SummaryFilter::default(), true).await?;
+    let models = db::get_model_stats(pool, false, false, false).await?;
+    let found = summary.total_requests > 0;
+
+    if as_json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({ "summary": summary, "models": models }))?
+        );
+        return Ok(exit_code(found));
+    }
+
+    let detail = summary.detail.as_ref().expect("asked for the summary detail");
+    println!("Summary");
+    if let (Some(from), Some(to)) = (&detail.data_from, &detail.data_to) {
+        println!("  {:<22}{} to {}", "Data range", from, to);
+    }
+    if let Some(rollup_from) = &detail.rollup_from {
+        println!("  {:<22}{}", "Rollups from", rollup_from);
+    }
+    println!("  {:<22}{}", "Total requests", summary.total_requests);
+    println!("  {:<22}{}", "Successful", summary.successful_requests);
+    println!("  {:<22}{}", "Failed", summary.failed_requests);
+    println!("  {:<22}{}", "Input tokens", summary.total_input_tokens);
+    println!("  {:<22}{}", "Output tokens", summary.total_output_tokens);
+    if summary.total_tokens >= 1000 {
+        println!(
+            "  {:<22}{} ({})",
+            "Total tokens",
+            summary.total_tokens,
+            format::si(summary.total_tokens as f64)
+        );
+    } else {
+        println!("  {:<22}{}", "Total tokens", summary.total_tokens);
+    }
+    println!("  {:<22}{:.1}", "Avg input tokens", summary.avg_input_tokens);
+    println!("  {:<22}{:.1}", "Avg output tokens", summary.avg_output_tokens);
+    println!("  {:<22}{:.1}", "Avg duration (ms)", summary.avg_duration_ms);
+    println!(
+        "  {:<22}{} / {} / {} / {}",
+        "p50/p90/p95/p99 (ms)",
+        detail.p50_duration_ms,
+        detail.p90_duration_ms,
+        detail.p95_duration_ms,
+        detail.p99_duration_ms
+    );
+    println!(
+        "  {:<22}{:.1} (max {:.1})",
+        "Avg tokens/sec", summary.avg_tokens_per_second, summary.max_tokens_per_second
+    );
+    if let (Some(avg), Some(p95)) = (summary.avg_ttft_ms, detail.p95_ttft_ms) {
+        println!("  {:<22}{:.1} / {}", "Avg/p95 TTFT (ms)", avg, p95);
+    }
+    if let Some(cost) = summary.estimated_cost {
+        println!("  {:<22}${:.4}", "Estimated cost", cost);
+    }
+    println!();
+
+    let width = models
+        .iter()
+        .map(|m| m.model.len())
+        .max()
+        .unwrap_or(0)
+        .max("Model".len());
+    println!(
+        "{:<width$}  {:>10}  {:>12}  {:>12}  {:>12}  {:>10}  {:>10}",
+        "Model", "Requests", "Input", "Output", "Total", "Avg/req", "Tok/s"
+    );
+    for model in &models {
+        println!(
+            "{:<width$}  {:>10}  {:>12}  {:>12}  {:>12}  {:>10.1}  {:>10.1}",
+            model.model,
+            model.requests,
+            model.input_tokens,
+            model.output_tokens,
+            model.total_tokens,
+            model.avg_tokens_per_request,
+            model.avg_tokens_per_second
+        );
+    }
+
+    Ok(exit_code(found))
+}
+
+async fn export(
+    pool: &SqlitePool,
+    format: ExportFormat,
+    out: Option<PathBuf>,
+) -> anyhow::Result<ExitCode> {
+    let mut writer: Box<dyn Write> = match &out {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::BufWriter::new(std::io::stdout().lock())),
+    };
+
+    let mut rows = 0u64;
+    match format {
+        ExportFormat::Csv => {
+            writeln!(writer, "{}", crate::export::csv_header())?;
+            let mut stream = db::stream_requests(pool, &db::ExportFilter::default());
+            while let Some(request) = stream.next().await {
+                writeln!(writer, "{}", crate::export::csv_line(&request?))?;
+                rows += 1;
+            }
+        }
+    }
+    writer.flush()?;
+
+    if let Some(path) = &out {
+        eprintln!("Exported {} requests to {}", rows, path.display());
+    }
+    Ok(exit_code(rows > 0))
+}
+
+async fn prune(
+    pool: &SqlitePool,
+    older_than: chrono::Duration,
+    dry_run: bool,
+) -> anyhow::Result<ExitCode> {
+    let cutoff = (chrono::Utc::now() - older_than).to_rfc3339();
+
+    let affected = if dry_run {
+        let simulation = db::simulate_prune(pool, &cutoff).await?;
+        println!(
+            "Would delete {} requests started before {}",
+            simulation.requests, cutoff
+        );
+        if let (Some(oldest), Some(newest)) = (&simulation.oldest, &simulation.newest) {
+            println!("  {:<22}{} to {}", "Date range", oldest, newest);
+            println!(
+                "  {:<22}{} bytes ({} freed)",
+                "Prompt/output text", simulation.text_bytes, simulation.freed_text_bytes
+            );
+            for model in &simulation.models {
+                println!("  {:<22}{}", model.model, model.requests);
+            }
+            println!(
+                "  {:<22}{} -> ~{} bytes",
+                "Size after VACUUM", simulation.database_bytes, simulation.projected_database_bytes
+            );
+        }
+        simulation.requests as u64
+    } else {
+        let deleted = db::delete_requests_before(pool, &cutoff).await?;
+        println!("Deleted {} requests started before {}", deleted, cutoff);
+        deleted
+    };
+
+    Ok(exit_code(affected > 0))
+}
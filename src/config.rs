@@ -1,13 +1,256 @@
+use hyper::header::HeaderName;
 use std::env;
+use std::net::IpAddr;
+
+/// Maps model names matching `pattern` to a tokenizer: `cl100k`, `o200k`, `chars/4`,
+/// or the path of a HuggingFace `tokenizer.json`.
+#[derive(Clone, Debug)]
+pub struct TokenizerRule {
+    pub pattern: String,
+    pub source: String,
+}
 
 #[derive(Clone, Debug)]
 pub struct Config {
     pub port: u16,
     pub lm_studio_url: String,
     pub database_url: String,
+    pub known_bad_sdks: Vec<String>,
+    pub upstream_retries: u32,
+    pub admin_port: Option<u16>,
+    pub admin_bind_addr: IpAddr,
+    pub deadline_overhead_ms: u64,
+    pub tokenizers: Vec<TokenizerRule>,
+    pub energy: EnergyConfig,
+    pub admin_token: Option<String>,
+    /// Bearer tokens that may only read aggregate statistics
+    pub viewer_tokens: Vec<String>,
+    pub unload_advisor_threshold: f64,
+    pub pricing: PricingConfig,
+    /// Per-model `(pattern, tokens)` context windows, first match wins
+    pub model_contexts: Vec<(String, i64)>,
+    pub recent_ring_size: usize,
+    pub prompt_warn_message_chars: usize,
+    /// How system prompts are normalized before fingerprinting: `exact`, `whitespace` or
+    /// `lowercase`
+    pub prompt_version_normalization: String,
+    /// How request bodies are checked against the OpenAI schema: `off`, `warn` (record
+    /// violations as prompt warnings) or `strict` (refuse the request)
+    pub schema_validation: String,
+    pub batch_concurrency: usize,
+    pub forward_paths: Vec<String>,
+    pub incident: IncidentConfig,
+    pub stream_pacing: bool,
+    pub stream_pacing_buffer_bytes: usize,
+    pub stream_stats_event: bool,
+    /// Bytes proxied requests may hold in buffers before new ones are refused; 0 for no cap
+    pub max_buffered_bytes: u64,
+    pub stream_write_timeout_ms: u64,
+    pub spool_capacity: usize,
+    pub persist_lag_alert_ms: u64,
+    /// WAL size in bytes above which the maintenance check checkpoints it
+    pub wal_checkpoint_bytes: Option<u64>,
+    /// Idle gap after which a slow request is taken for a model reload
+    pub reload_min_gap_secs: u64,
+    /// How many times the model's usual prompt processing time a reload takes
+    pub reload_latency_multiple: f64,
+    pub guardrails: Vec<GuardrailRule>,
+    pub adjusted_params_header: bool,
+    pub probes: ProbeConfig,
+    /// Request header naming the conversation a request belongs to
+    pub session_header: HeaderName,
+    /// Attribute requests to the address a reverse proxy reports in `X-Forwarded-For`
+    pub trust_forwarded_for: bool,
+    pub canary: CanaryConfig,
+    pub export_sink: SinkConfig,
+    pub badge: BadgeConfig,
+    /// Serve `/status` without a token and probe the upstream for it
+    pub status_page: bool,
+    pub status_probe_interval_secs: u64,
+}
+
+/// Who may fetch `/stats/badge`, and the colors its metrics are shown in.
+#[derive(Clone, Debug)]
+pub struct BadgeConfig {
+    /// Serve the badge without a token even where stats otherwise require one
+    pub public: bool,
+    pub colors: Vec<BadgeColor>,
+}
+
+/// Color of a badge metric once its value reaches `threshold`. The highest threshold
+/// reached wins.
+#[derive(Clone, Debug)]
+pub struct BadgeColor {
+    pub metric: String,
+    pub threshold: f64,
+    pub color: String,
+}
+
+/// Where and how stored requests are shipped to an external HTTP endpoint.
+#[derive(Clone, Debug)]
+pub struct SinkConfig {
+    /// Nothing is exported when unset
+    pub url: Option<String>,
+    /// Extra `(name, value)` headers sent with every batch, e.g. credentials
+    pub headers: Vec<(String, String)>,
+    /// `ndjson` (one record per line, as ClickHouse's `JSONEachRow` takes) or `json` (an array)
+    pub format: String,
+    /// Send prompts, outputs and incident captures too
+    pub include_bodies: bool,
+    pub batch_size: usize,
+    /// Longest a record waits for its batch to fill before the batch is sent anyway
+    pub batch_interval_ms: u64,
+    /// Records waiting to be sent; further ones are dropped
+    pub buffer: usize,
+    /// File batches are appended to once the sink has been down for `dead_letter_after_secs`
+    pub dead_letter_file: Option<String>,
+    pub dead_letter_after_secs: u64,
+}
+
+/// Extra upstreams that take a weighted share of the traffic, and how far a canary may
+/// trail the primary before `/stats/canary` calls it out.
+#[derive(Clone, Debug)]
+pub struct CanaryConfig {
+    /// `(name, url)` per canary, in the order their shares are laid out
+    pub upstreams: Vec<(String, String)>,
+    /// Starting `(name, percent)` shares; canaries left out get none
+    pub weights: Vec<(String, f64)>,
+    /// Largest error rate a canary may have above the primary's, e.g. 0.02 for two points
+    pub max_error_rate_delta: f64,
+    /// Largest ratio of a canary's average latency to the primary's
+    pub max_latency_ratio: f64,
+}
+
+/// Allowed range of a sampling parameter for models matching `pattern`, and the value
+/// filled in when a client leaves it out.
+#[derive(Clone, Debug)]
+pub struct GuardrailRule {
+    pub pattern: String,
+    /// `temperature` or `top_p`
+    pub param: String,
+    pub min: f64,
+    pub max: f64,
+    pub default: Option<f64>,
+}
+
+/// What makes a request a client library's warm-up ping rather than real work. A request
+/// is a probe when its prompt text is at most `max_prompt_chars` long and it either asks
+/// for at most `max_tokens` tokens or is one of `prompts`.
+#[derive(Clone, Debug)]
+pub struct ProbeConfig {
+    /// Nothing is classified as a probe when 0
+    pub max_prompt_chars: usize,
+    pub max_tokens: i64,
+    /// Known warm-up prompts, compared ignoring case and surrounding punctuation
+    pub prompts: Vec<String>,
+}
+
+/// When incident mode starts on its own and how long it lasts.
+#[derive(Clone, Debug)]
+pub struct IncidentConfig {
+    pub duration_minutes: i64,
+    /// Share of failed requests that starts an incident; never automatic when unset
+    pub error_rate: Option<f64>,
+    /// Latest requests the error rate is taken over
+    pub window: usize,
+}
+
+/// Factors for the energy and CO2 estimates. These are rough approximations: energy is
+/// the configured power draw multiplied by how long each request took.
+#[derive(Clone, Debug)]
+pub struct EnergyConfig {
+    /// Watts assumed for models without an override; estimates are off when unset
+    pub default_watts: Option<f64>,
+    /// Per-model `(pattern, watts)` overrides, first match wins
+    pub model_watts: Vec<(String, f64)>,
+    /// Grams of CO2 emitted per kWh drawn from the grid
+    pub grid_co2_g_per_kwh: f64,
+}
+
+impl EnergyConfig {
+    pub fn enabled(&self) -> bool {
+        self.default_watts.is_some() || !self.model_watts.is_empty()
+    }
+
+    pub fn watts_for(&self, model: &str) -> Option<f64> {
+        self.model_watts
+            .iter()
+            .find(|(pattern, _)| model_pattern_matches(pattern, model))
+            .map(|(_, watts)| *watts)
+            .or(self.default_watts)
+    }
+
+    /// Estimated watt-hours for a request, or `None` if it has no generation time to go by.
+    pub fn estimate_wh(&self, model: &str, duration_ms: i64) -> Option<f64> {
+        if duration_ms <= 0 {
+            return None;
+        }
+        self.watts_for(model)
+            .map(|watts| watts * duration_ms as f64 / 3_600_000.0)
+    }
+}
+
+/// Token prices in dollars per million tokens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Price {
+    pub input_per_m: f64,
+    pub output_per_m: f64,
+}
+
+/// Per-model prices, with overrides for namespaces billed at their own rates.
+#[derive(Clone, Debug, Default)]
+pub struct PricingConfig {
+    /// `(model pattern, price)`, first match wins
+    pub model_prices: Vec<(String, Price)>,
+    /// `(namespace, model pattern, price)`, tried before the model prices
+    pub namespace_prices: Vec<(String, String, Price)>,
+    /// `(client IP pattern, namespace)`, first match wins
+    pub namespaces: Vec<(String, String)>,
+}
+
+impl PricingConfig {
+    pub fn namespace_for(&self, client_ip: &str) -> Option<&str> {
+        self.namespaces
+            .iter()
+            .find(|(pattern, _)| model_pattern_matches(pattern, client_ip))
+            .map(|(_, namespace)| namespace.as_str())
+    }
+
+    /// The rate a request is billed at: its namespace's override for the model if there
+    /// is one, otherwise the model's own price.
+    pub fn price_for(&self, namespace: Option<&str>, model: &str) -> Option<Price> {
+        namespace
+            .and_then(|namespace| {
+                self.namespace_prices
+                    .iter()
+                    .find(|(ns, pattern, _)| ns == namespace && model_pattern_matches(pattern, model))
+            })
+            .map(|(_, _, price)| *price)
+            .or_else(|| {
+                self.model_prices
+                    .iter()
+                    .find(|(pattern, _)| model_pattern_matches(pattern, model))
+                    .map(|(_, price)| *price)
+            })
+    }
 }
 
 impl Config {
+    /// The context window configured for `model`, in tokens.
+    pub fn context_for(&self, model: &str) -> Option<i64> {
+        self.model_contexts
+            .iter()
+            .find(|(pattern, _)| model_pattern_matches(pattern, model))
+            .map(|(_, tokens)| *tokens)
+    }
+
+    /// Path of the database file, without the `sqlite:` prefix
+    pub fn database_path(&self) -> &str {
+        self.database_url
+            .strip_prefix("sqlite:")
+            .unwrap_or(&self.database_url)
+    }
+
     pub fn from_env() -> anyhow::Result<Self> {
         // Load .env file if it exists (for development)
         dotenvy::dotenv().ok();
@@ -23,10 +266,627 @@ impl Config {
         let database_url =
             env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:./metrics.db".to_string());
 
+        // Comma-separated `name` or `name/version` SDK fingerprints to warn about
+        let known_bad_sdks = env::var("KNOWN_BAD_SDKS")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|entry| entry.trim().to_string())
+                    .filter(|entry| !entry.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let upstream_retries = env::var("UPSTREAM_RETRIES")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid UPSTREAM_RETRIES value: {}", e))?;
+
+        // Serve stats on a separate listener when ADMIN_PORT is set
+        let admin_port = env::var("ADMIN_PORT")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid ADMIN_PORT value: {}", e))?;
+
+        let admin_bind_addr = env::var("ADMIN_BIND_ADDR")
+            .unwrap_or_else(|_| "127.0.0.1".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid ADMIN_BIND_ADDR value: {}", e))?;
+
+        // Share of a client deadline kept back for the proxy's own work
+        let deadline_overhead_ms = env::var("DEADLINE_OVERHEAD_MS")
+            .unwrap_or_else(|_| "100".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid DEADLINE_OVERHEAD_MS value: {}", e))?;
+
+        // Comma-separated `pattern=source` tokenizer rules, first match wins
+        let tokenizers = pattern_rules("TOKENIZERS")?
+            .into_iter()
+            .map(|(pattern, source)| TokenizerRule { pattern, source })
+            .collect();
+
+        // Approximate power draw of the inference machine while generating
+        let default_watts = env::var("ENERGY_WATTS")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid ENERGY_WATTS value: {}", e))?;
+        let model_watts = pattern_rules("ENERGY_MODEL_WATTS")?
+            .into_iter()
+            .map(|(pattern, watts)| {
+                watts
+                    .parse()
+                    .map(|watts| (pattern, watts))
+                    .map_err(|e| anyhow::anyhow!("Invalid ENERGY_MODEL_WATTS value {}: {}", watts, e))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let grid_co2_g_per_kwh = env::var("GRID_CO2_G_PER_KWH")
+            .unwrap_or_else(|_| "400".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid GRID_CO2_G_PER_KWH value: {}", e))?;
+        let energy = EnergyConfig {
+            default_watts,
+            model_watts,
+            grid_co2_g_per_kwh,
+        };
+
+        // Bearer token for admin actions; they're refused entirely when unset
+        let admin_token = env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+
+        // Comma-separated bearer tokens limited to aggregate statistics, for dashboards
+        let viewer_tokens = env::var("VIEWER_TOKENS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        let unload_advisor_threshold = env::var("UNLOAD_ADVISOR_THRESHOLD")
+            .unwrap_or_else(|_| "1.0".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid UNLOAD_ADVISOR_THRESHOLD value: {}", e))?;
+
+        // Comma-separated `model-pattern:input:output` prices per million tokens
+        let model_prices = env::var("MODEL_PRICING")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| parse_price("MODEL_PRICING", entry))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Comma-separated `namespace=model-pattern:input:output` overrides
+        let namespace_prices = pattern_rules("NAMESPACE_PRICING")?
+            .into_iter()
+            .map(|(namespace, entry)| {
+                parse_price("NAMESPACE_PRICING", &entry)
+                    .map(|(pattern, price)| (namespace, pattern, price))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Comma-separated `client-ip-pattern=namespace` assignments
+        let namespaces = pattern_rules("NAMESPACES")?;
+        let pricing = PricingConfig {
+            model_prices,
+            namespace_prices,
+            namespaces,
+        };
+
+        // Comma-separated `model-pattern:tokens` context windows
+        let model_contexts = env::var("MODEL_CONTEXT")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(parse_context)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Completed requests kept in memory for /stats/recent when the database is down
+        let recent_ring_size = env::var("RECENT_RING_SIZE")
+            .unwrap_or_else(|_| "500".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid RECENT_RING_SIZE value: {}", e))?;
+
+        // Single messages longer than this are flagged in prompt warnings
+        let prompt_warn_message_chars = env::var("PROMPT_WARN_MESSAGE_CHARS")
+            .unwrap_or_else(|_| "100000".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid PROMPT_WARN_MESSAGE_CHARS value: {}", e))?;
+
+        // How system prompts are normalized before they are fingerprinted
+        let prompt_version_normalization = env::var("PROMPT_VERSION_NORMALIZATION")
+            .unwrap_or_else(|_| "whitespace".to_string());
+        if !matches!(
+            prompt_version_normalization.as_str(),
+            "exact" | "whitespace" | "lowercase"
+        ) {
+            return Err(anyhow::anyhow!(
+                "Invalid PROMPT_VERSION_NORMALIZATION value: {}, expected exact, whitespace or lowercase",
+                prompt_version_normalization
+            ));
+        }
+
+        // Whether request bodies are checked against the OpenAI schema, and what happens then
+        let schema_validation =
+            env::var("SCHEMA_VALIDATION").unwrap_or_else(|_| "off".to_string());
+        if !matches!(schema_validation.as_str(), "off" | "warn" | "strict") {
+            return Err(anyhow::anyhow!(
+                "Invalid SCHEMA_VALIDATION value: {}, expected off, warn or strict",
+                schema_validation
+            ));
+        }
+
+        // Lines of a batch sent upstream at the same time
+        let batch_concurrency = env::var("BATCH_CONCURRENCY")
+            .unwrap_or_else(|_| "2".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid BATCH_CONCURRENCY value: {}", e))?;
+
+        // Comma-separated /v1 path patterns forwarded even when the proxy serves them itself
+        let forward_paths = env::var("FORWARD_PATHS")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|entry| entry.trim().to_string())
+                    .filter(|entry| !entry.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Incident mode: extra capture for a while after errors spike
+        let duration_minutes = env::var("INCIDENT_MINUTES")
+            .unwrap_or_else(|_| "15".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid INCIDENT_MINUTES value: {}", e))?;
+        let error_rate = env::var("INCIDENT_ERROR_RATE")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid INCIDENT_ERROR_RATE value: {}", e))?;
+        let window = env::var("INCIDENT_WINDOW")
+            .unwrap_or_else(|_| "20".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid INCIDENT_WINDOW value: {}", e))?;
+        let incident = IncidentConfig {
+            duration_minutes,
+            error_rate,
+            window,
+        };
+
+        // Whether clients may ask for paced streams with X-Proxy-Pace-Tokens-Per-Sec
+        let stream_pacing = env_flag("STREAM_PACING");
+
+        // Whether clients may ask for a closing proxy_stats event with X-Proxy-Append-Stats
+        let stream_stats_event = env_flag("STREAM_STATS_EVENT");
+
+        // Bytes of a paced stream held back before it falls behind the requested pace
+        let stream_pacing_buffer_bytes = env::var("STREAM_PACING_BUFFER_BYTES")
+            .unwrap_or_else(|_| "262144".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid STREAM_PACING_BUFFER_BYTES value: {}", e))?;
+
+        // Buffered bytes across all requests past which new requests are refused
+        let max_buffered_bytes = env::var("MAX_BUFFERED_BYTES")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid MAX_BUFFERED_BYTES value: {}", e))?;
+
+        // A stream's insert taking longer than this moves to the write spool
+        let stream_write_timeout_ms = env::var("STREAM_WRITE_TIMEOUT_MS")
+            .unwrap_or_else(|_| "2000".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid STREAM_WRITE_TIMEOUT_MS value: {}", e))?;
+
+        // Spooled requests waiting to be written before further ones are dropped
+        let spool_capacity = env::var("SPOOL_CAPACITY")
+            .unwrap_or_else(|_| "200".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid SPOOL_CAPACITY value: {}", e))?;
+
+        // Oldest unwritten request age at which /health reports persistence as lagging
+        let persist_lag_alert_ms = env::var("PERSIST_LAG_ALERT_MS")
+            .unwrap_or_else(|_| "10000".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid PERSIST_LAG_ALERT_MS value: {}", e))?;
+
+        // The WAL is truncated by a checkpoint once it grows past this many megabytes
+        let wal_checkpoint_bytes = env::var("WAL_CHECKPOINT_MB")
+            .ok()
+            .map(|value| value.parse::<u64>())
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid WAL_CHECKPOINT_MB value: {}", e))?
+            .map(|mb| mb * 1024 * 1024);
+
+        // A request after this long without traffic for its model may have paid for a reload
+        let reload_min_gap_secs = env::var("RELOAD_MIN_GAP_SECS")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid RELOAD_MIN_GAP_SECS value: {}", e))?;
+        let reload_latency_multiple = env::var("RELOAD_LATENCY_MULTIPLE")
+            .unwrap_or_else(|_| "3.0".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid RELOAD_LATENCY_MULTIPLE value: {}", e))?;
+
+        // Comma-separated `pattern=param:min:max[:default]` sampling guardrails
+        let guardrails = pattern_rules("SAMPLING_GUARDRAILS")?
+            .into_iter()
+            .map(|(pattern, rule)| parse_guardrail(pattern, &rule))
+            .collect::<anyhow::Result<_>>()?;
+        let adjusted_params_header = env_flag("ADJUSTED_PARAMS_HEADER");
+
+        // Tiny prompts asking for a token or two, or saying only "hi", are warm-up pings
+        let probes = ProbeConfig {
+            max_prompt_chars: env::var("PROBE_MAX_PROMPT_CHARS")
+                .unwrap_or_else(|_| "32".to_string())
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid PROBE_MAX_PROMPT_CHARS value: {}", e))?,
+            max_tokens: env::var("PROBE_MAX_TOKENS")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid PROBE_MAX_TOKENS value: {}", e))?,
+            prompts: env::var("PROBE_PROMPTS")
+                .unwrap_or_else(|_| DEFAULT_PROBE_PROMPTS.to_string())
+                .split(',')
+                .map(|entry| entry.trim().to_string())
+                .filter(|entry| !entry.is_empty())
+                .collect(),
+        };
+
+        // Chat frontends tag each request with the conversation it belongs to
+        let session_header =
+            env::var("SESSION_HEADER").unwrap_or_else(|_| "X-Session-Id".to_string());
+        let session_header = HeaderName::try_from(session_header.trim())
+            .map_err(|e| anyhow::anyhow!("Invalid SESSION_HEADER value: {}", e))?;
+
+        // Only safe when every client reaches the proxy through a reverse proxy that sets it
+        let trust_forwarded_for = env_flag("TRUST_FORWARDED_FOR");
+
+        // Comma-separated `name=url` canary upstreams and their starting `name=percent` shares
+        let canary_weights = pattern_rules("CANARY_WEIGHTS")?
+            .into_iter()
+            .map(|(name, percent)| {
+                percent
+                    .parse()
+                    .map(|percent| (name, percent))
+                    .map_err(|e| anyhow::anyhow!("Invalid CANARY_WEIGHTS value {}: {}", percent, e))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let max_error_rate_delta = env::var("CANARY_MAX_ERROR_RATE_DELTA")
+            .unwrap_or_else(|_| "0.02".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid CANARY_MAX_ERROR_RATE_DELTA value: {}", e))?;
+        let max_latency_ratio = env::var("CANARY_MAX_LATENCY_RATIO")
+            .unwrap_or_else(|_| "1.25".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid CANARY_MAX_LATENCY_RATIO value: {}", e))?;
+        let canary = CanaryConfig {
+            upstreams: pattern_rules("CANARY_UPSTREAMS")?,
+            weights: canary_weights,
+            max_error_rate_delta,
+            max_latency_ratio,
+        };
+
+        // Ship every stored request to an HTTP endpoint in batches
+        let sink_format = env::var("EXPORT_SINK_FORMAT").unwrap_or_else(|_| "ndjson".to_string());
+        if !matches!(sink_format.as_str(), "ndjson" | "json") {
+            return Err(anyhow::anyhow!(
+                "Invalid EXPORT_SINK_FORMAT value: {}, expected ndjson or json",
+                sink_format
+            ));
+        }
+        let export_sink = SinkConfig {
+            url: env::var("EXPORT_SINK_URL").ok().filter(|url| !url.is_empty()),
+            headers: pattern_rules("EXPORT_SINK_HEADERS")?,
+            format: sink_format,
+            include_bodies: env_flag("EXPORT_SINK_INCLUDE_BODIES"),
+            batch_size: env::var("EXPORT_SINK_BATCH_SIZE")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid EXPORT_SINK_BATCH_SIZE value: {}", e))?,
+            batch_interval_ms: env::var("EXPORT_SINK_INTERVAL_MS")
+                .unwrap_or_else(|_| "1000".to_string())
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid EXPORT_SINK_INTERVAL_MS value: {}", e))?,
+            buffer: env::var("EXPORT_SINK_BUFFER")
+                .unwrap_or_else(|_| "10000".to_string())
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid EXPORT_SINK_BUFFER value: {}", e))?,
+            dead_letter_file: env::var("EXPORT_SINK_DEAD_LETTER_FILE")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            dead_letter_after_secs: env::var("EXPORT_SINK_DEAD_LETTER_AFTER_SECS")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()
+                .map_err(|e| {
+                    anyhow::anyhow!("Invalid EXPORT_SINK_DEAD_LETTER_AFTER_SECS value: {}", e)
+                })?,
+        };
+
+        // Badge colors as comma-separated `metric=threshold:color` entries
+        let badge = BadgeConfig {
+            public: env_flag("PUBLIC_BADGE"),
+            colors: pattern_rules("BADGE_COLORS")?
+                .into_iter()
+                .map(|(metric, rule)| parse_badge_color(metric, &rule))
+                .collect::<anyhow::Result<_>>()?,
+        };
+
+        // An unauthenticated page saying whether the upstream is up, from periodic checks
+        let status_page = env_flag("STATUS_PAGE");
+        let status_probe_interval_secs: u64 = env::var("STATUS_PROBE_INTERVAL_SECS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid STATUS_PROBE_INTERVAL_SECS value: {}", e))?;
+        if status_probe_interval_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "Invalid STATUS_PROBE_INTERVAL_SECS value: must be at least 1"
+            ));
+        }
+
         Ok(Config {
             port,
             lm_studio_url,
             database_url,
+            known_bad_sdks,
+            upstream_retries,
+            admin_port,
+            admin_bind_addr,
+            deadline_overhead_ms,
+            tokenizers,
+            energy,
+            admin_token,
+            viewer_tokens,
+            unload_advisor_threshold,
+            pricing,
+            model_contexts,
+            recent_ring_size,
+            prompt_warn_message_chars,
+            prompt_version_normalization,
+            schema_validation,
+            batch_concurrency,
+            forward_paths,
+            incident,
+            stream_pacing,
+            stream_pacing_buffer_bytes,
+            stream_stats_event,
+            max_buffered_bytes,
+            stream_write_timeout_ms,
+            spool_capacity,
+            persist_lag_alert_ms,
+            wal_checkpoint_bytes,
+            reload_min_gap_secs,
+            reload_latency_multiple,
+            guardrails,
+            adjusted_params_header,
+            probes,
+            session_header,
+            trust_forwarded_for,
+            canary,
+            export_sink,
+            badge,
+            status_page,
+            status_probe_interval_secs,
         })
     }
 }
+
+/// Parses a `pattern:input:output` price. The pattern may itself contain colons.
+fn parse_price(name: &str, entry: &str) -> anyhow::Result<(String, Price)> {
+    let mut fields = entry.rsplitn(3, ':');
+    let (Some(output), Some(input), Some(pattern)) = (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(anyhow::anyhow!(
+            "Invalid {} entry {}, expected pattern:input:output",
+            name,
+            entry
+        ));
+    };
+
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| anyhow::anyhow!("Invalid {} price {}: {}", name, value, e))
+    };
+    Ok((
+        pattern.trim().to_string(),
+        Price {
+            input_per_m: parse(input)?,
+            output_per_m: parse(output)?,
+        },
+    ))
+}
+
+/// Parses a `pattern:tokens` context window. The pattern may itself contain colons.
+fn parse_context(entry: &str) -> anyhow::Result<(String, i64)> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid MODEL_CONTEXT entry {}, expected pattern:tokens with tokens above 0",
+            entry
+        )
+    };
+    let (pattern, tokens) = entry.rsplit_once(':').ok_or_else(invalid)?;
+    let tokens = tokens
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .filter(|tokens| *tokens > 0)
+        .ok_or_else(invalid)?;
+    Ok((pattern.trim().to_string(), tokens))
+}
+
+/// Warm-up prompts client libraries are known to send, used when `PROBE_PROMPTS` is unset
+const DEFAULT_PROBE_PROMPTS: &str = "hi,hello,hey,ping,test,testing,say hi,say hello,hello world,are you there";
+
+/// Sampling parameters guardrails can be set for
+const GUARDRAIL_PARAMS: &[&str] = &["temperature", "top_p"];
+
+/// Parses a `param:min:max[:default]` guardrail for models matching `pattern`.
+fn parse_guardrail(pattern: String, rule: &str) -> anyhow::Result<GuardrailRule> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid SAMPLING_GUARDRAILS entry {}={}, expected pattern=param:min:max[:default]",
+            pattern,
+            rule
+        )
+    };
+    let fields: Vec<&str> = rule.split(':').map(str::trim).collect();
+    let (param, min, max, default) = match fields.as_slice() {
+        [param, min, max] => (*param, *min, *max, None),
+        [param, min, max, default] => (*param, *min, *max, Some(*default)),
+        _ => return Err(invalid()),
+    };
+    if !GUARDRAIL_PARAMS.contains(&param) {
+        return Err(anyhow::anyhow!(
+            "Invalid SAMPLING_GUARDRAILS parameter {}, expected one of {}",
+            param,
+            GUARDRAIL_PARAMS.join(", ")
+        ));
+    }
+
+    let number = |value: &str| value.parse::<f64>().ok().filter(|n| n.is_finite());
+    let (Some(min), Some(max)) = (number(min), number(max)) else {
+        return Err(invalid());
+    };
+    let default = default.map(|value| number(value).ok_or_else(invalid)).transpose()?;
+    if min > max || default.is_some_and(|default| default < min || default > max) {
+        return Err(invalid());
+    }
+    Ok(GuardrailRule {
+        pattern,
+        param: param.to_string(),
+        min,
+        max,
+        default,
+    })
+}
+
+/// Metrics `/stats/badge` can show
+pub const BADGE_METRICS: &[&str] = &["tokens_month", "requests_today", "cost_month"];
+
+/// Parses a `threshold:color` badge color for `metric`.
+fn parse_badge_color(metric: String, rule: &str) -> anyhow::Result<BadgeColor> {
+    if !BADGE_METRICS.contains(&metric.as_str()) {
+        return Err(anyhow::anyhow!(
+            "Invalid BADGE_COLORS metric {}, expected one of {}",
+            metric,
+            BADGE_METRICS.join(", ")
+        ));
+    }
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid BADGE_COLORS entry {}={}, expected metric=threshold:color",
+            metric,
+            rule
+        )
+    };
+    let (threshold, color) = rule.split_once(':').ok_or_else(invalid)?;
+    let threshold = threshold
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|threshold| threshold.is_finite())
+        .ok_or_else(invalid)?;
+    let color = color.trim();
+    if color.is_empty() {
+        return Err(invalid());
+    }
+    Ok(BadgeColor {
+        metric,
+        threshold,
+        color: color.to_string(),
+    })
+}
+
+/// Reads an on/off setting; `1`, `true` and `yes` turn it on, anything else leaves it off.
+fn env_flag(name: &str) -> bool {
+    matches!(
+        env::var(name)
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str(),
+        "1" | "true" | "yes"
+    )
+}
+
+/// Parses a comma-separated list of `pattern=value` entries.
+fn pattern_rules(name: &str) -> anyhow::Result<Vec<(String, String)>> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .split_once('=')
+                .map(|(pattern, value)| (pattern.trim().to_string(), value.trim().to_string()))
+                .ok_or_else(|| anyhow::anyhow!("Invalid {} entry: {}", name, entry))
+        })
+        .collect()
+}
+
+/// Matches a model name against a case-insensitive pattern where `*` matches any run of
+/// characters.
+pub fn model_pattern_matches(pattern: &str, model: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let model = model.to_lowercase();
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = model.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard: the whole name must match
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn badge_colors_parse_threshold_and_color() {
+        let rule = parse_badge_color("tokens_month".to_string(), " 1e7 : red ").unwrap();
+        assert_eq!(rule.metric, "tokens_month");
+        assert_eq!(rule.threshold, 10_000_000.0);
+        assert_eq!(rule.color, "red");
+
+        let rule = parse_badge_color("cost_month".to_string(), "2.5:#ff8800").unwrap();
+        assert_eq!((rule.threshold, rule.color.as_str()), (2.5, "#ff8800"));
+    }
+
+    #[test]
+    fn bad_badge_colors_are_refused() {
+        let error = parse_badge_color("tokens_year".to_string(), "1:red").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid BADGE_COLORS metric tokens_year, expected one of tokens_month, \
+             requests_today, cost_month"
+        );
+        for rule in ["red", "1:", ":red", "many:red", "inf:red", "NaN:red"] {
+            let error = parse_badge_color("tokens_month".to_string(), rule).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "Invalid BADGE_COLORS entry tokens_month={}, expected metric=threshold:color",
+                    rule
+                )
+            );
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+
+use crate::db::RequestRecord;
+use crate::db::counters::{self, MinuteCounterRow};
+
+/// Minutes of per-minute counters kept in memory and in the database
+pub const RETENTION_MINUTES: i64 = 24 * 60;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MinuteCount {
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+}
+
+impl MinuteCount {
+    fn add(&mut self, other: &MinuteCount) {
+        self.requests += other.requests;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+}
+
+#[derive(Default)]
+struct Ring {
+    /// Minute key -> model -> counts
+    minutes: BTreeMap<String, HashMap<String, MinuteCount>>,
+    /// Minutes changed since the last flush
+    dirty: HashSet<String>,
+}
+
+impl Ring {
+    fn trim(&mut self, now: DateTime<Utc>) {
+        let cutoff = minute_key(now - Duration::minutes(RETENTION_MINUTES));
+        self.minutes = self.minutes.split_off(&cutoff);
+        self.dirty.retain(|minute| *minute >= cutoff);
+    }
+}
+
+/// Rolling per-minute request and token counters, keyed by completion minute and model.
+///
+/// Counters are flushed to the `minute_counters` table periodically and on shutdown,
+/// and restored at startup so short-window figures survive restarts.
+#[derive(Default)]
+pub struct MinuteCounters {
+    ring: Mutex<Ring>,
+}
+
+/// Formats a timestamp as the `YYYY-MM-DDTHH:MM` key of its UTC minute.
+pub fn minute_key(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M").to_string()
+}
+
+impl MinuteCounters {
+    /// Counts a completed request in the minute it finished.
+    pub fn record(&self, record: &RequestRecord) {
+        let end_time = DateTime::parse_from_rfc3339(&record.end_time)
+            .map(|t| t.with_timezone(&Utc))
+            .unwrap_or_else(|_| Utc::now());
+        let minute = minute_key(end_time);
+
+        let mut ring = self.ring.lock().unwrap_or_else(|e| e.into_inner());
+        let count = ring
+            .minutes
+            .entry(minute.clone())
+            .or_default()
+            .entry(record.model.clone())
+            .or_default();
+        count.requests += 1;
+        count.input_tokens += record.input_tokens;
+        count.output_tokens += record.output_tokens;
+        ring.dirty.insert(minute);
+        ring.trim(Utc::now());
+    }
+
+    /// Sums every model's counts over the last `window`, including the current minute.
+    pub fn totals_since(&self, window: Duration) -> MinuteCount {
+        let cutoff = minute_key(Utc::now() - window);
+        let ring = self.ring.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut total = MinuteCount::default();
+        for models in ring.minutes.range(cutoff..).map(|(_, models)| models) {
+            for count in models.values() {
+                total.add(count);
+            }
+        }
+        total
+    }
+
+    /// Per-model counts held for minutes in `[from_minute, until_minute)`.
+    pub fn minutes_between(&self, from_minute: &str, until_minute: &str) -> Vec<MinuteCounterRow> {
+        let ring = self.ring.lock().unwrap_or_else(|e| e.into_inner());
+        ring.minutes
+            .range(from_minute.to_string()..until_minute.to_string())
+            .flat_map(|(minute, models)| {
+                models.iter().map(|(model, count)| MinuteCounterRow {
+                    minute: minute.clone(),
+                    model: model.clone(),
+                    count: *count,
+                })
+            })
+            .collect()
+    }
+
+    /// Replaces the minutes in `[from_minute, until_minute)` with `rows`, such as counts
+    /// recomputed from the requests table.
+    pub fn replace_between(&self, from_minute: &str, until_minute: &str, rows: &[MinuteCounterRow]) {
+        let mut ring = self.ring.lock().unwrap_or_else(|e| e.into_inner());
+        let stale: Vec<String> = ring
+            .minutes
+            .range(from_minute.to_string()..until_minute.to_string())
+            .map(|(minute, _)| minute.clone())
+            .collect();
+        for minute in stale {
+            ring.minutes.remove(&minute);
+        }
+        for row in rows {
+            ring.minutes
+                .entry(row.minute.clone())
+                .or_default()
+                .insert(row.model.clone(), row.count);
+        }
+        ring.trim(Utc::now());
+    }
+
+    /// Writes every minute changed since the last flush in a single upsert.
+    pub async fn flush(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let (minutes, rows) = {
+            let mut ring = self.ring.lock().unwrap_or_else(|e| e.into_inner());
+            let minutes: Vec<String> = ring.dirty.drain().collect();
+            let rows: Vec<MinuteCounterRow> = minutes
+                .iter()
+                .filter_map(|minute| ring.minutes.get(minute).map(|models| (minute, models)))
+                .flat_map(|(minute, models)| {
+                    models.iter().map(|(model, count)| MinuteCounterRow {
+                        minute: minute.clone(),
+                        model: model.clone(),
+                        count: *count,
+                    })
+                })
+                .collect();
+            (minutes, rows)
+        };
+
+        if let Err(e) = counters::upsert_minute_counters(pool, &rows, &minute_key(Utc::now())).await
+        {
+            // Keep the minutes dirty so the next flush retries them
+            let mut ring = self.ring.lock().unwrap_or_else(|e| e.into_inner());
+            ring.dirty.extend(minutes);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Restores the persisted ring and rebuilds any minutes it may have missed.
+    ///
+    /// Minutes at or after the last flush are recomputed from the requests table and
+    /// replace the snapshot values, so nothing recorded on both sides is counted twice.
+    /// Snapshot minutes in the future (the clock moved backwards) are discarded.
+    pub async fn restore(pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let now = Utc::now();
+        let window_start = minute_key(now - Duration::minutes(RETENTION_MINUTES));
+        let now_minute = minute_key(now);
+
+        let snapshot = counters::load_minute_counters(pool, &window_start).await?;
+        let reconcile_from = match snapshot.last_flushed_minute {
+            Some(flushed) if flushed <= now_minute => flushed.max(window_start.clone()),
+            _ => window_start.clone(),
+        };
+
+        let mut ring = Ring::default();
+        for row in snapshot.rows {
+            if row.minute > now_minute || row.minute >= reconcile_from {
+                continue;
+            }
+            ring.minutes
+                .entry(row.minute)
+                .or_default()
+                .insert(row.model, row.count);
+        }
+
+        let rebuilt = counters::count_requests_by_minute(pool, &reconcile_from).await?;
+        for row in rebuilt {
+            ring.dirty.insert(row.minute.clone());
+            ring.minutes
+                .entry(row.minute)
+                .or_default()
+                .insert(row.model, row.count);
+        }
+
+        ring.trim(now);
+        tracing::info!(
+            "Restored {} minutes of rolling counters (rebuilt from {})",
+            ring.minutes.len(),
+            reconcile_from
+        );
+
+        Ok(Self {
+            ring: Mutex::new(ring),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::insert_request;
+    use crate::db::testing::memory_pool;
+
+    fn finished(now: DateTime<Utc>, minutes_ago: i64, input_tokens: i64) -> RequestRecord {
+        let end_time = now - Duration::minutes(minutes_ago);
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            end_time,
+            "hi".to_string(),
+        );
+        record.end_time = end_time.to_rfc3339();
+        record.input_tokens = input_tokens;
+        record.output_tokens = 1;
+        record
+    }
+
+    fn row(
+        now: DateTime<Utc>,
+        minutes_ago: i64,
+        requests: i64,
+        input_tokens: i64,
+    ) -> MinuteCounterRow {
+        MinuteCounterRow {
+            minute: minute_key(now - Duration::minutes(minutes_ago)),
+            model: "m".to_string(),
+            count: MinuteCount {
+                requests,
+                input_tokens,
+                output_tokens: requests,
+            },
+        }
+    }
+
+    /// Requests held per minute, oldest first.
+    fn requests_by_minute(counters: &MinuteCounters) -> Vec<(String, i64)> {
+        counters
+            .minutes_between("", "~")
+            .into_iter()
+            .map(|row| (row.minute, row.count.requests))
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn restart_after_flush_does_not_double_count() {
+        let pool = memory_pool().await;
+        let now = Utc::now();
+        let counters = MinuteCounters::default();
+        let records = [finished(now, 30, 10), finished(now, 30, 20), finished(now, 0, 5)];
+        for record in records {
+            counters.record(&record);
+            insert_request(&pool, &record).await.unwrap();
+        }
+        counters.flush(&pool).await.unwrap();
+
+        let restored = MinuteCounters::restore(&pool).await.unwrap();
+        assert_eq!(requests_by_minute(&restored), requests_by_minute(&counters));
+        assert_eq!(restored.totals_since(Duration::hours(1)).input_tokens, 35);
+    }
+
+    #[tokio::test]
+    async fn requests_after_the_last_flush_are_rebuilt() {
+        let pool = memory_pool().await;
+        let now = Utc::now();
+        let flushed_at = minute_key(now - Duration::minutes(10));
+        let snapshot = [row(now, 20, 4, 40), row(now, 10, 1, 1)];
+        counters::upsert_minute_counters(&pool, &snapshot, &flushed_at)
+            .await
+            .unwrap();
+        // The flushed minute kept taking requests, then more came in before the crash
+        let records = [finished(now, 10, 1), finished(now, 10, 1), finished(now, 5, 7)];
+        for record in records {
+            insert_request(&pool, &record).await.unwrap();
+        }
+
+        let restored = MinuteCounters::restore(&pool).await.unwrap();
+        let key = |minutes_ago| minute_key(now - Duration::minutes(minutes_ago));
+        assert_eq!(
+            requests_by_minute(&restored),
+            [(key(20), 4), (key(10), 2), (key(5), 1)]
+        );
+        assert_eq!(restored.totals_since(Duration::hours(1)).input_tokens, 49);
+    }
+
+    #[tokio::test]
+    async fn snapshot_from_the_future_is_rebuilt_from_requests() {
+        let pool = memory_pool().await;
+        let now = Utc::now();
+        // Flushed before the clock was set back an hour
+        let flushed_at = minute_key(now + Duration::minutes(60));
+        let snapshot = [row(now, -60, 9, 90), row(now, 30, 9, 90)];
+        counters::upsert_minute_counters(&pool, &snapshot, &flushed_at)
+            .await
+            .unwrap();
+        insert_request(&pool, &finished(now, 30, 3)).await.unwrap();
+
+        let restored = MinuteCounters::restore(&pool).await.unwrap();
+        let key = minute_key(now - Duration::minutes(30));
+        assert_eq!(requests_by_minute(&restored), [(key, 1)]);
+    }
+
+    #[tokio::test]
+    async fn flush_overwrites_with_absolute_counts() {
+        let pool = memory_pool().await;
+        let now = Utc::now();
+        let counters = MinuteCounters::default();
+        counters.record(&finished(now, 0, 1));
+        counters.flush(&pool).await.unwrap();
+        counters.record(&finished(now, 0, 1));
+        counters.flush(&pool).await.unwrap();
+        // Nothing dirty, so nothing written
+        counters.flush(&pool).await.unwrap();
+
+        let stored = counters::get_minute_counters(&pool, "", "~").await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].count.requests, 2);
+    }
+
+    #[tokio::test]
+    async fn minutes_past_retention_are_dropped() {
+        let pool = memory_pool().await;
+        let now = Utc::now();
+        let flushed_at = minute_key(now);
+        let expired = RETENTION_MINUTES + 5;
+        let snapshot = [row(now, expired, 1, 1), row(now, 60, 1, 1)];
+        counters::upsert_minute_counters(&pool, &snapshot, &flushed_at)
+            .await
+            .unwrap();
+
+        let restored = MinuteCounters::restore(&pool).await.unwrap();
+        assert_eq!(restored.minutes_between("", "~").len(), 1);
+        let stored = counters::get_minute_counters(&pool, "", "~").await.unwrap();
+        assert_eq!(stored.len(), 1);
+    }
+}
@@ -0,0 +1,56 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use super::models::TERMINATION_ABANDONED;
+
+#[derive(Debug, Serialize)]
+pub struct AbandonedStats {
+    /// Requests whose client hung up before any response byte was forwarded
+    pub total: i64,
+    pub last_24h: i64,
+    /// The 24 hours before that, for comparison
+    pub previous_24h: i64,
+}
+
+/// Abandoned requests between `from` and `to` (exclusive) to the models in `models`, a
+/// JSON array; every model when `None`. The 24-hour counts end at `to`, or now.
+pub async fn get_abandoned_stats(
+    pool: &SqlitePool,
+    from: Option<&str>,
+    to: Option<&str>,
+    models: Option<&str>,
+) -> Result<AbandonedStats, sqlx::Error> {
+    let end = to
+        .and_then(|to| DateTime::parse_from_rfc3339(to).ok())
+        .map_or_else(Utc::now, |to| to.with_timezone(&Utc));
+    let day_ago = (end - Duration::hours(24)).to_rfc3339();
+    let two_days_ago = (end - Duration::hours(48)).to_rfc3339();
+
+    let row = sqlx::query(
+        r#"
+        SELECT
+            COUNT(*) as total,
+            COALESCE(SUM(CASE WHEN start_time >= ?2 THEN 1 ELSE 0 END), 0) as last_24h,
+            COALESCE(SUM(CASE WHEN start_time >= ?3 AND start_time < ?2 THEN 1 ELSE 0 END), 0)
+                as previous_24h
+        FROM requests
+        WHERE termination = ?1 AND (?4 IS NULL OR model IN (SELECT value FROM json_each(?4)))
+          AND (?5 IS NULL OR start_time >= ?5) AND (?6 IS NULL OR start_time < ?6)
+        "#,
+    )
+    .bind(TERMINATION_ABANDONED)
+    .bind(day_ago)
+    .bind(two_days_ago)
+    .bind(models)
+    .bind(from)
+    .bind(to)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(AbandonedStats {
+        total: row.try_get("total")?,
+        last_24h: row.try_get("last_24h")?,
+        previous_24h: row.try_get("previous_24h")?,
+    })
+}
@@ -0,0 +1,300 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::agent::STEP_INITIAL;
+
+/// Deepest descendant level followed when assigning requests to their tree
+const MAX_TREE_DEPTH: i64 = 64;
+
+#[derive(Debug, Default, Serialize)]
+pub struct AgentOverhead {
+    /// Request trees (a root and its descendants) that contain a tool loop
+    pub trees: i64,
+    pub requests: i64,
+    /// `tool_followup` and `final` calls, the ones carrying tool results
+    pub tool_result_requests: i64,
+    pub input_tokens: i64,
+    /// User content new to each request
+    pub new_user_tokens: i64,
+    /// Input tokens of calls carrying tool results, minus any new user content in them
+    pub overhead_tokens: i64,
+    /// The tool results themselves, part of `overhead_tokens`
+    pub tool_result_tokens: i64,
+    /// Conversation re-sent alongside the tool results, the rest of `overhead_tokens`
+    pub resent_tokens: i64,
+    /// `overhead_tokens` / `input_tokens`
+    pub overhead_ratio: f64,
+    #[serde(skip)]
+    roots: HashSet<String>,
+}
+
+impl AgentOverhead {
+    fn add(&mut self, row: &StepRow) {
+        if self.roots.insert(row.root_id.clone()) {
+            self.trees += 1;
+        }
+        self.requests += 1;
+        self.input_tokens += row.input_tokens;
+        self.new_user_tokens += row.new_user_tokens;
+        if row.step != STEP_INITIAL {
+            let overhead = (row.input_tokens - row.new_user_tokens).max(0);
+            self.tool_result_requests += 1;
+            self.overhead_tokens += overhead;
+            self.tool_result_tokens += row.tool_result_tokens.min(overhead);
+        }
+    }
+
+    fn finish(mut self) -> Self {
+        self.resent_tokens = self.overhead_tokens - self.tool_result_tokens;
+        if self.input_tokens > 0 {
+            self.overhead_ratio = self.overhead_tokens as f64 / self.input_tokens as f64;
+        }
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientOverhead {
+    pub client_id: Option<String>,
+    #[serde(flatten)]
+    pub overhead: AgentOverhead,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelOverhead {
+    pub model: String,
+    #[serde(flatten)]
+    pub overhead: AgentOverhead,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AgentOverheadReport {
+    pub since: Option<String>,
+    pub totals: AgentOverhead,
+    pub by_client: Vec<ClientOverhead>,
+    pub by_model: Vec<ModelOverhead>,
+}
+
+struct StepRow {
+    root_id: String,
+    client_id: Option<String>,
+    model: String,
+    step: String,
+    input_tokens: i64,
+    tool_result_tokens: i64,
+    new_user_tokens: i64,
+}
+
+/// Tool-loop overhead across request trees rooted since `since`, counting only trees
+/// where at least one call carried tool results.
+pub async fn get_agent_overhead(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<AgentOverheadReport, sqlx::Error> {
+    // A request whose parent isn't stored roots its own tree, as in the tree endpoint
+    let rows = sqlx::query(
+        r#"
+        WITH RECURSIVE tree(proxy_request_id, root_id, depth) AS (
+            SELECT r.proxy_request_id, r.proxy_request_id, 0
+            FROM requests r
+            WHERE r.proxy_request_id IS NOT NULL
+              AND (?1 IS NULL OR r.start_time >= ?1)
+              AND (r.parent_id IS NULL
+                   OR NOT EXISTS (SELECT 1 FROM requests p WHERE p.proxy_request_id = r.parent_id))
+            UNION
+            SELECT r.proxy_request_id, t.root_id, t.depth + 1
+            FROM requests r
+            JOIN tree t ON r.parent_id = t.proxy_request_id
+            WHERE t.depth < ?2 AND r.proxy_request_id != r.parent_id
+        ),
+        loops AS (
+            SELECT DISTINCT t.root_id
+            FROM tree t
+            JOIN requests r ON r.proxy_request_id = t.proxy_request_id
+            WHERE r.agent_step IS NOT NULL AND r.agent_step != ?3
+        )
+        SELECT
+            t.root_id,
+            root.client_id,
+            r.model,
+            r.agent_step,
+            r.input_tokens,
+            COALESCE(r.tool_result_tokens, 0) as tool_result_tokens,
+            COALESCE(r.new_user_tokens, 0) as new_user_tokens
+        FROM tree t
+        JOIN loops l ON l.root_id = t.root_id
+        JOIN requests r ON r.proxy_request_id = t.proxy_request_id
+        JOIN requests root ON root.proxy_request_id = t.root_id
+        WHERE r.agent_step IS NOT NULL
+        "#,
+    )
+    .bind(since)
+    .bind(MAX_TREE_DEPTH)
+    .bind(STEP_INITIAL)
+    .fetch_all(pool)
+    .await?;
+
+    let mut totals = AgentOverhead::default();
+    let mut by_client: BTreeMap<Option<String>, AgentOverhead> = BTreeMap::new();
+    let mut by_model: BTreeMap<String, AgentOverhead> = BTreeMap::new();
+    for row in &rows {
+        let row = StepRow {
+            root_id: row.try_get("root_id")?,
+            client_id: row.try_get("client_id")?,
+            model: row.try_get("model")?,
+            step: row.try_get("agent_step")?,
+            input_tokens: row.try_get("input_tokens")?,
+            tool_result_tokens: row.try_get("tool_result_tokens")?,
+            new_user_tokens: row.try_get("new_user_tokens")?,
+        };
+        totals.add(&row);
+        by_client
+            .entry(row.client_id.clone())
+            .or_default()
+            .add(&row);
+        by_model.entry(row.model.clone()).or_default().add(&row);
+    }
+
+    let mut by_client: Vec<_> = by_client
+        .into_iter()
+        .map(|(client_id, overhead)| ClientOverhead {
+            client_id,
+            overhead: overhead.finish(),
+        })
+        .collect();
+    by_client.sort_by_key(|client| std::cmp::Reverse(client.overhead.overhead_tokens));
+    let mut by_model: Vec<_> = by_model
+        .into_iter()
+        .map(|(model, overhead)| ModelOverhead {
+            model,
+            overhead: overhead.finish(),
+        })
+        .collect();
+    by_model.sort_by_key(|model| std::cmp::Reverse(model.overhead.overhead_tokens));
+
+    Ok(AgentOverheadReport {
+        since: since.map(|s| s.to_string()),
+        totals: totals.finish(),
+        by_client,
+        by_model,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{STEP_FINAL, STEP_TOOL_FOLLOWUP};
+    use crate::db::testing::memory_pool;
+    use crate::db::{insert_request, RequestRecord};
+    use chrono::{DateTime, Duration, Utc};
+
+    /// A call as annotated: `(step, input tokens, tool result tokens, new user tokens)`
+    type Step = (&'static str, i64, i64, i64);
+
+    /// The two-round weather lookup in `tests/fixtures/agent_two_rounds.json`, with the
+    /// counts the chars/4 tokenizer gives it.
+    const TWO_ROUNDS: [Step; 3] = [
+        (STEP_INITIAL, 90, 0, 10),
+        (STEP_TOOL_FOLLOWUP, 130, 11, 0),
+        (STEP_FINAL, 170, 11, 0),
+    ];
+
+    /// Stores `steps` as a chain, each call the child of the one before it.
+    async fn store_tree(
+        pool: &SqlitePool,
+        client_id: &str,
+        model: &str,
+        start_time: DateTime<Utc>,
+        steps: &[Step],
+    ) {
+        let mut parent_id = None;
+        for (offset, (step, input_tokens, tool_result_tokens, new_user_tokens)) in
+            steps.iter().enumerate()
+        {
+            let mut record = RequestRecord::new(
+                "/v1/chat/completions".to_string(),
+                model.to_string(),
+                start_time + Duration::seconds(offset as i64),
+                "[]".to_string(),
+            );
+            record.client_id = Some(client_id.to_string());
+            record.parent_id = parent_id.clone();
+            record.agent_step = Some(step.to_string());
+            record.input_tokens = *input_tokens;
+            record.tool_result_tokens = Some(*tool_result_tokens);
+            record.new_user_tokens = Some(*new_user_tokens);
+            insert_request(pool, &record).await.unwrap();
+            parent_id = record.proxy_request_id;
+        }
+    }
+
+    #[tokio::test]
+    async fn two_round_tool_interaction_splits_overhead_from_new_content() {
+        let pool = memory_pool().await;
+        store_tree(&pool, "agent", "qwen-7b", Utc::now(), &TWO_ROUNDS).await;
+
+        let report = get_agent_overhead(&pool, None).await.unwrap();
+        let totals = &report.totals;
+
+        assert_eq!(totals.trees, 1);
+        assert_eq!(totals.requests, 3);
+        assert_eq!(totals.tool_result_requests, 2);
+        assert_eq!(totals.input_tokens, 390);
+        assert_eq!(totals.new_user_tokens, 10);
+        assert_eq!(totals.overhead_tokens, 300);
+        assert_eq!(totals.tool_result_tokens, 22);
+        assert_eq!(totals.resent_tokens, 278);
+        assert!((totals.overhead_ratio - 300.0 / 390.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn only_trees_with_a_tool_loop_since_the_cutoff_count() {
+        let pool = memory_pool().await;
+        let now = Utc::now();
+        store_tree(&pool, "agent", "qwen-7b", now, &TWO_ROUNDS).await;
+        store_tree(&pool, "chat", "qwen-7b", now, &[(STEP_INITIAL, 50, 0, 40)]).await;
+        store_tree(&pool, "agent", "llama-3", now, &TWO_ROUNDS[..2]).await;
+        store_tree(&pool, "old", "qwen-7b", now - Duration::days(2), &TWO_ROUNDS).await;
+
+        let since = (now - Duration::days(1)).to_rfc3339();
+        let report = get_agent_overhead(&pool, Some(&since)).await.unwrap();
+
+        assert_eq!(report.totals.trees, 2);
+        assert_eq!(report.totals.requests, 5);
+        let clients: Vec<_> = report
+            .by_client
+            .iter()
+            .map(|client| (client.client_id.as_deref(), client.overhead.trees))
+            .collect();
+        assert_eq!(clients, [(Some("agent"), 2)]);
+        let models: Vec<_> = report
+            .by_model
+            .iter()
+            .map(|model| (model.model.as_str(), model.overhead.overhead_tokens))
+            .collect();
+        assert_eq!(models, [("qwen-7b", 300), ("llama-3", 130)]);
+
+        let everything = get_agent_overhead(&pool, None).await.unwrap();
+        assert_eq!(everything.totals.trees, 3);
+    }
+
+    #[tokio::test]
+    async fn overhead_never_counts_new_user_content() {
+        let pool = memory_pool().await;
+        // A follow-up that also carries a new user message, more than its input tokens
+        store_tree(
+            &pool,
+            "agent",
+            "m",
+            Utc::now(),
+            &[(STEP_INITIAL, 20, 0, 20), (STEP_FINAL, 30, 50, 40)],
+        )
+        .await;
+
+        let totals = get_agent_overhead(&pool, None).await.unwrap().totals;
+        assert_eq!(totals.overhead_tokens, 0);
+        assert_eq!(totals.tool_result_tokens, 0);
+        assert_eq!(totals.resent_tokens, 0);
+    }
+}
@@ -0,0 +1,350 @@
+//! Upstream availability as seen by the status prober, stored as spans of one state.
+//!
+//! Each check extends the latest span when it agrees with it and came soon enough after
+//! it; a different answer starts a new span where the old one was last seen. A check
+//! after a longer silence, such as the first one after a restart, starts a new span at
+//! its own time, so the time the proxy wasn't looking counts as unknown rather than as
+//! up or down.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+pub const STATE_UP: &str = "up";
+pub const STATE_DOWN: &str = "down";
+pub const STATE_UNKNOWN: &str = "unknown";
+
+/// An unbroken stretch of checks with the same answer.
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub state: String,
+    pub started_at: DateTime<Utc>,
+    /// Time of the latest check in the span
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// How a window divides into up, down and unknown time.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Uptime {
+    pub up_secs: i64,
+    pub down_secs: i64,
+    /// Time without checks, e.g. while the proxy wasn't running
+    pub unknown_secs: i64,
+    /// Share of the observed time the upstream was up; `None` with nothing observed
+    pub uptime_percent: Option<f64>,
+}
+
+/// A stretch of time the upstream was down.
+#[derive(Debug, Serialize)]
+pub struct Outage {
+    pub started_at: String,
+    /// `None` while it lasts
+    pub ended_at: Option<String>,
+    pub duration_secs: i64,
+}
+
+/// Stores one check made at `at`, returning the state of the span it ended when the answer
+/// changed. Checks more than `max_gap` after the latest one don't continue its span.
+pub async fn record_check(
+    pool: &SqlitePool,
+    state: &str,
+    at: DateTime<Utc>,
+    max_gap: Duration,
+) -> Result<Option<String>, sqlx::Error> {
+    let latest = sqlx::query(
+        "SELECT id, state, last_seen_at FROM availability_spans ORDER BY id DESC LIMIT 1",
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let mut started_at = at;
+    let mut ended = None;
+    if let Some(latest) = latest {
+        let last_seen: String = latest.try_get("last_seen_at")?;
+        let contiguous = parse_time(&last_seen).is_some_and(|seen| at - seen <= max_gap);
+        let latest_state: String = latest.try_get("state")?;
+        if contiguous && latest_state == state {
+            sqlx::query("UPDATE availability_spans SET last_seen_at = ? WHERE id = ?")
+                .bind(at.to_rfc3339())
+                .bind(latest.try_get::<i64, _>("id")?)
+                .execute(pool)
+                .await?;
+            return Ok(None);
+        }
+        if contiguous {
+            // The change happened somewhere since the last check; it's put at the start
+            started_at = parse_time(&last_seen).unwrap_or(at);
+            ended = Some(latest_state);
+        }
+    }
+
+    sqlx::query("INSERT INTO availability_spans (state, started_at, last_seen_at) VALUES (?, ?, ?)")
+        .bind(state)
+        .bind(started_at.to_rfc3339())
+        .bind(at.to_rfc3339())
+        .execute(pool)
+        .await?;
+    Ok(ended)
+}
+
+/// Spans that reach into `[from, now]`, oldest first.
+pub async fn get_spans(pool: &SqlitePool, from: DateTime<Utc>) -> Result<Vec<Span>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT state, started_at, last_seen_at
+        FROM availability_spans
+        WHERE last_seen_at >= ?
+        ORDER BY id
+        "#,
+    )
+    .bind(from.to_rfc3339())
+    .fetch_all(pool)
+    .await?;
+
+    let mut spans = Vec::new();
+    for row in rows {
+        let started_at: String = row.try_get("started_at")?;
+        let last_seen_at: String = row.try_get("last_seen_at")?;
+        if let (Some(started_at), Some(last_seen_at)) =
+            (parse_time(&started_at), parse_time(&last_seen_at))
+        {
+            spans.push(Span {
+                state: row.try_get("state")?,
+                started_at,
+                last_seen_at,
+            });
+        }
+    }
+    Ok(spans)
+}
+
+/// Splits `[from, to]` into up, down and unknown time. Only time covered by a span is
+/// known; the stretch between a span's last check and `to` is unknown too, so a span that
+/// is still open counts up to its latest check. Spans are summed in milliseconds and up
+/// and down time rounded up to whole seconds, so an outage shorter than a second shows.
+pub fn uptime(spans: &[Span], from: DateTime<Utc>, to: DateTime<Utc>) -> Uptime {
+    let window = (to - from).num_milliseconds().max(0);
+    let (mut up, mut down) = (0, 0);
+    for span in spans {
+        let start = span.started_at.max(from);
+        let end = span.last_seen_at.min(to);
+        if end <= start {
+            continue;
+        }
+        let millis = (end - start).num_milliseconds();
+        match span.state.as_str() {
+            STATE_UP => up += millis,
+            STATE_DOWN => down += millis,
+            _ => {}
+        }
+    }
+    // Spans never overlap, but clamp in case the clock stepped back between checks
+    let known = (up + down).min(window);
+    Uptime {
+        up_secs: (up + 999) / 1000,
+        down_secs: (down + 999) / 1000,
+        unknown_secs: (window - known) / 1000,
+        uptime_percent: (up + down > 0)
+            .then(|| (up as f64 / (up + down) as f64 * 10000.0).round() / 100.0),
+    }
+}
+
+/// Down spans among `spans`, newest first. The latest span is still going when `ongoing`.
+pub fn outages(spans: &[Span], ongoing: bool) -> Vec<Outage> {
+    let last = spans.len().saturating_sub(1);
+    spans
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, span)| span.state == STATE_DOWN)
+        .map(|(index, span)| Outage {
+            started_at: span.started_at.to_rfc3339(),
+            ended_at: (!(ongoing && index == last)).then(|| span.last_seen_at.to_rfc3339()),
+            duration_secs: (span.last_seen_at - span.started_at).num_seconds(),
+        })
+        .collect()
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|time| time.to_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use chrono::TimeZone;
+
+    const GAP: Duration = Duration::seconds(65);
+
+    fn t(secs: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 4, 1, 0, 0, 0).unwrap() + Duration::seconds(secs)
+    }
+
+    fn span(state: &str, from: i64, to: i64) -> Span {
+        Span {
+            state: state.to_string(),
+            started_at: t(from),
+            last_seen_at: t(to),
+        }
+    }
+
+    fn summary(spans: &[Span]) -> Vec<(String, i64, i64)> {
+        spans
+            .iter()
+            .map(|span| {
+                let secs = |time: DateTime<Utc>| (time - t(0)).num_seconds();
+                (span.state.clone(), secs(span.started_at), secs(span.last_seen_at))
+            })
+            .collect()
+    }
+
+    async fn check(pool: &SqlitePool, state: &str, secs: i64) -> Option<String> {
+        record_check(pool, state, t(secs), GAP).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn checks_extend_spans_and_changes_start_new_ones() {
+        let pool = memory_pool().await;
+        for secs in [0, 30, 60] {
+            assert_eq!(check(&pool, STATE_UP, secs).await, None);
+        }
+        assert_eq!(check(&pool, STATE_DOWN, 90).await.as_deref(), Some(STATE_UP));
+        assert_eq!(check(&pool, STATE_DOWN, 120).await, None);
+        assert_eq!(check(&pool, STATE_UP, 150).await.as_deref(), Some(STATE_DOWN));
+
+        let spans = get_spans(&pool, t(0)).await.unwrap();
+        assert_eq!(
+            summary(&spans),
+            [
+                (STATE_UP.to_string(), 0, 60),
+                // A change is put at the previous check, so spans meet without overlapping
+                (STATE_DOWN.to_string(), 60, 120),
+                (STATE_UP.to_string(), 120, 150),
+            ]
+        );
+        // Spans ending before the window are left out
+        assert_eq!(get_spans(&pool, t(100)).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_restart_gap_is_unknown_not_down() {
+        let pool = memory_pool().await;
+        for secs in [0, 30, 60] {
+            check(&pool, STATE_UP, secs).await;
+        }
+        // The proxy was stopped for an hour, then finds the upstream down
+        assert_eq!(check(&pool, STATE_DOWN, 3660).await, None);
+        check(&pool, STATE_DOWN, 3690).await;
+        // and after another restart, up again
+        assert_eq!(check(&pool, STATE_UP, 7200).await, None);
+        check(&pool, STATE_UP, 7260).await;
+
+        let spans = get_spans(&pool, t(0)).await.unwrap();
+        assert_eq!(
+            summary(&spans),
+            [
+                (STATE_UP.to_string(), 0, 60),
+                (STATE_DOWN.to_string(), 3660, 3690),
+                (STATE_UP.to_string(), 7200, 7260),
+            ]
+        );
+        let uptime = uptime(&spans, t(0), t(7260));
+        assert_eq!((uptime.up_secs, uptime.down_secs), (120, 30));
+        assert_eq!(uptime.unknown_secs, 7260 - 150);
+        assert_eq!(uptime.uptime_percent, Some(80.0));
+    }
+
+    #[tokio::test]
+    async fn only_checks_within_the_gap_continue_the_span() {
+        let pool = memory_pool().await;
+        check(&pool, STATE_UP, 0).await;
+        check(&pool, STATE_UP, 65).await;
+        check(&pool, STATE_UP, 131).await;
+        let spans = get_spans(&pool, t(0)).await.unwrap();
+        assert_eq!(
+            summary(&spans),
+            [(STATE_UP.to_string(), 0, 65), (STATE_UP.to_string(), 131, 131)]
+        );
+    }
+
+    #[test]
+    fn uptime_splits_the_window_and_ignores_unknown_time() {
+        let spans = [
+            span(STATE_UP, 0, 3600),
+            // Proxy stopped from 3600 to 7200
+            span(STATE_DOWN, 7200, 7500),
+            span(STATE_UP, 7500, 9000),
+        ];
+        let whole = uptime(&spans, t(0), t(10_800));
+        assert_eq!((whole.up_secs, whole.down_secs), (5100, 300));
+        // Including the time after the last check
+        assert_eq!(whole.unknown_secs, 5400);
+        assert_eq!(whole.uptime_percent, Some(94.44));
+
+        // Only the parts of spans inside the window count
+        let clipped = uptime(&spans, t(3000), t(7400));
+        assert_eq!((clipped.up_secs, clipped.down_secs), (600, 200));
+        assert_eq!(clipped.unknown_secs, 3600);
+        assert_eq!(clipped.uptime_percent, Some(75.0));
+
+        let gap_only = uptime(&spans, t(4000), t(7000));
+        assert_eq!(gap_only.unknown_secs, 3000);
+        assert_eq!(gap_only.uptime_percent, None);
+    }
+
+    #[test]
+    fn uptime_with_no_checks_is_all_unknown() {
+        let uptime = uptime(&[], t(0), t(86_400));
+        assert_eq!((uptime.up_secs, uptime.down_secs, uptime.unknown_secs), (0, 0, 86_400));
+        assert_eq!(uptime.uptime_percent, None);
+        // A single check covers no time
+        let single = super::uptime(&[span(STATE_UP, 50, 50)], t(0), t(100));
+        assert_eq!((single.up_secs, single.unknown_secs), (0, 100));
+    }
+
+    #[test]
+    fn outages_shorter_than_a_second_still_count() {
+        let spans = [
+            span(STATE_UP, 0, 99),
+            Span {
+                state: STATE_DOWN.to_string(),
+                started_at: t(99),
+                last_seen_at: t(99) + Duration::milliseconds(400),
+            },
+        ];
+        let uptime = uptime(&spans, t(0), t(100));
+        assert_eq!((uptime.up_secs, uptime.down_secs), (99, 1));
+        assert_eq!(uptime.unknown_secs, 0);
+        assert_eq!(uptime.uptime_percent, Some(99.6));
+    }
+
+    #[test]
+    fn overlapping_spans_never_make_unknown_time_negative() {
+        // As after the clock stepped back between checks
+        let spans = [span(STATE_UP, 0, 100), span(STATE_DOWN, 50, 150)];
+        let uptime = uptime(&spans, t(0), t(100));
+        assert_eq!((uptime.up_secs, uptime.down_secs, uptime.unknown_secs), (100, 50, 0));
+    }
+
+    #[test]
+    fn outages_are_listed_newest_first() {
+        let spans = [
+            span(STATE_DOWN, 0, 60),
+            span(STATE_UP, 60, 600),
+            span(STATE_DOWN, 600, 700),
+        ];
+        let listed = outages(&spans, true);
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].started_at, t(600).to_rfc3339());
+        assert_eq!(listed[0].ended_at, None);
+        assert_eq!(listed[0].duration_secs, 100);
+        assert_eq!(listed[1].ended_at, Some(t(60).to_rfc3339()));
+
+        // A down span isn't ongoing once its checks stopped
+        assert_eq!(outages(&spans, false)[0].ended_at, Some(t(700).to_rfc3339()));
+        assert!(outages(&[span(STATE_UP, 0, 10)], true).is_empty());
+    }
+}
@@ -0,0 +1,69 @@
+use sqlx::{Row, SqlitePool};
+
+/// A successful request logged before token estimates were recorded.
+pub struct UnestimatedRequest {
+    pub id: i64,
+    pub model: String,
+    pub prompt: String,
+    pub output: String,
+}
+
+pub async fn count_unestimated(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM requests WHERE tokenizer IS NULL AND is_error = 0")
+        .fetch_one(pool)
+        .await
+}
+
+/// The next `limit` successful requests without token estimates, oldest first.
+pub async fn get_unestimated(
+    pool: &SqlitePool,
+    limit: i64,
+) -> Result<Vec<UnestimatedRequest>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, model, prompt_text, output_text
+        FROM request_rows
+        WHERE tokenizer IS NULL AND is_error = 0
+        ORDER BY id
+        LIMIT ?
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(UnestimatedRequest {
+                id: row.try_get("id")?,
+                model: row.try_get("model")?,
+                prompt: row.try_get("prompt_text")?,
+                output: row.try_get("output_text")?,
+            })
+        })
+        .collect()
+}
+
+/// Writes `(id, input, output, tokenizer)` estimates in one transaction.
+pub async fn store_token_estimates(
+    pool: &SqlitePool,
+    estimates: &[(i64, i64, i64, String)],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+    for (id, input, output, tokenizer) in estimates {
+        sqlx::query(
+            r#"
+            UPDATE requests
+            SET estimated_input_tokens = ?, estimated_output_tokens = ?, tokenizer = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(input)
+        .bind(output)
+        .bind(tokenizer)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
@@ -0,0 +1,281 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool, sqlite::SqliteRow};
+
+pub const BATCH_QUEUED: &str = "queued";
+pub const BATCH_RUNNING: &str = "running";
+pub const BATCH_COMPLETED: &str = "completed";
+pub const BATCH_CANCELLED: &str = "cancelled";
+
+pub const ITEM_PENDING: &str = "pending";
+/// Upstream answered with a 2xx status
+pub const ITEM_SUCCEEDED: &str = "succeeded";
+pub const ITEM_FAILED: &str = "failed";
+pub const ITEM_CANCELLED: &str = "cancelled";
+
+/// A batch line as submitted, before it runs.
+#[derive(Debug, Clone)]
+pub struct NewBatchItem {
+    pub custom_id: Option<String>,
+    pub url: String,
+    pub body: String,
+}
+
+/// A line waiting to run.
+#[derive(Debug, Clone)]
+pub struct PendingItem {
+    pub line: i64,
+    pub url: String,
+    pub body: String,
+}
+
+/// Outcome of one line, as stored once it has run.
+#[derive(Debug)]
+pub struct ItemResult {
+    pub line: i64,
+    pub custom_id: Option<String>,
+    pub status: String,
+    pub http_status: Option<i64>,
+    pub response: Option<String>,
+    pub proxy_request_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Batch {
+    pub id: String,
+    pub status: String,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub finished_at: Option<String>,
+    pub total: i64,
+    pub pending: i64,
+    pub succeeded: i64,
+    pub failed: i64,
+    pub cancelled: i64,
+}
+
+impl Batch {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            status: row.try_get("status")?,
+            client_ip: row.try_get("client_ip")?,
+            user_agent: row.try_get("user_agent")?,
+            error: row.try_get("error")?,
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+            finished_at: row.try_get("finished_at")?,
+            total: row.try_get("total")?,
+            pending: row.try_get("pending")?,
+            succeeded: row.try_get("succeeded")?,
+            failed: row.try_get("failed")?,
+            cancelled: row.try_get("cancelled")?,
+        })
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+/// Stores a batch and all of its lines in one transaction, queued to run.
+pub async fn create_batch(
+    pool: &SqlitePool,
+    id: &str,
+    client_ip: &str,
+    user_agent: Option<&str>,
+    items: &[NewBatchItem],
+) -> Result<(), sqlx::Error> {
+    let now = now();
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        INSERT INTO batches (id, status, client_ip, user_agent, created_at, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(id)
+    .bind(BATCH_QUEUED)
+    .bind(client_ip)
+    .bind(user_agent)
+    .bind(&now)
+    .bind(&now)
+    .execute(&mut *tx)
+    .await?;
+
+    for (line, item) in items.iter().enumerate() {
+        sqlx::query(
+            r#"
+            INSERT INTO batch_items (batch_id, line, custom_id, url, body, status)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(id)
+        .bind(line as i64 + 1)
+        .bind(&item.custom_id)
+        .bind(&item.url)
+        .bind(&item.body)
+        .bind(ITEM_PENDING)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn get_batch(pool: &SqlitePool, id: &str) -> Result<Option<Batch>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            b.*,
+            COUNT(i.line) as total,
+            COALESCE(SUM(i.status = ?2), 0) as pending,
+            COALESCE(SUM(i.status = ?3), 0) as succeeded,
+            COALESCE(SUM(i.status = ?4), 0) as failed,
+            COALESCE(SUM(i.status = ?5), 0) as cancelled
+        FROM batches b
+        LEFT JOIN batch_items i ON i.batch_id = b.id
+        WHERE b.id = ?1
+        GROUP BY b.id
+        "#,
+    )
+    .bind(id)
+    .bind(ITEM_PENDING)
+    .bind(ITEM_SUCCEEDED)
+    .bind(ITEM_FAILED)
+    .bind(ITEM_CANCELLED)
+    .fetch_optional(pool)
+    .await?;
+
+    row.as_ref().map(Batch::from_row).transpose()
+}
+
+/// Ids of batches that were queued or running when the process last stopped.
+pub async fn get_unfinished_batches(pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM batches WHERE status IN (?, ?) ORDER BY created_at")
+        .bind(BATCH_QUEUED)
+        .bind(BATCH_RUNNING)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn get_pending_items(
+    pool: &SqlitePool,
+    batch_id: &str,
+) -> Result<Vec<PendingItem>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT line, url, body FROM batch_items WHERE batch_id = ? AND status = ? ORDER BY line",
+    )
+    .bind(batch_id)
+    .bind(ITEM_PENDING)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(PendingItem {
+                line: row.try_get("line")?,
+                url: row.try_get("url")?,
+                body: row.try_get("body")?,
+            })
+        })
+        .collect()
+}
+
+pub async fn set_batch_status(
+    pool: &SqlitePool,
+    id: &str,
+    status: &str,
+) -> Result<(), sqlx::Error> {
+    let now = now();
+    let finished = matches!(status, BATCH_COMPLETED | BATCH_CANCELLED);
+    sqlx::query(
+        r#"
+        UPDATE batches
+        SET status = ?, updated_at = ?, finished_at = CASE WHEN ? THEN ? ELSE finished_at END
+        WHERE id = ?
+        "#,
+    )
+    .bind(status)
+    .bind(&now)
+    .bind(finished)
+    .bind(&now)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn finish_item(
+    pool: &SqlitePool,
+    batch_id: &str,
+    result: &ItemResult,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE batch_items
+        SET status = ?, http_status = ?, response = ?, proxy_request_id = ?, finished_at = ?
+        WHERE batch_id = ? AND line = ?
+        "#,
+    )
+    .bind(&result.status)
+    .bind(result.http_status)
+    .bind(&result.response)
+    .bind(&result.proxy_request_id)
+    .bind(now())
+    .bind(batch_id)
+    .bind(result.line)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks every line that hasn't run as cancelled.
+pub async fn cancel_pending_items(pool: &SqlitePool, batch_id: &str) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("UPDATE batch_items SET status = ? WHERE batch_id = ? AND status = ?")
+        .bind(ITEM_CANCELLED)
+        .bind(batch_id)
+        .bind(ITEM_PENDING)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Every line that has run, in submission order.
+pub async fn get_item_results(
+    pool: &SqlitePool,
+    batch_id: &str,
+) -> Result<Vec<ItemResult>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT line, custom_id, status, http_status, response, proxy_request_id
+        FROM batch_items
+        WHERE batch_id = ? AND status IN (?, ?)
+        ORDER BY line
+        "#,
+    )
+    .bind(batch_id)
+    .bind(ITEM_SUCCEEDED)
+    .bind(ITEM_FAILED)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(ItemResult {
+                line: row.try_get("line")?,
+                custom_id: row.try_get("custom_id")?,
+                status: row.try_get("status")?,
+                http_status: row.try_get("http_status")?,
+                response: row.try_get("response")?,
+                proxy_request_id: row.try_get("proxy_request_id")?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,705 @@
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Row, SqlitePool, sqlite::SqliteRow};
+
+use super::models::TERMINATION_COMPLETED;
+
+pub const BENCHMARK_QUEUED: &str = "queued";
+pub const BENCHMARK_RUNNING: &str = "running";
+pub const BENCHMARK_COMPLETED: &str = "completed";
+pub const BENCHMARK_CANCELLED: &str = "cancelled";
+/// Stopped early because the estimated cost reached `max_cost`
+pub const BENCHMARK_BUDGET_EXCEEDED: &str = "budget_exceeded";
+
+pub const RUN_PENDING: &str = "pending";
+/// Upstream answered with a 2xx status
+pub const RUN_SUCCEEDED: &str = "succeeded";
+pub const RUN_FAILED: &str = "failed";
+pub const RUN_CANCELLED: &str = "cancelled";
+
+/// A stored prompt picked for replay.
+#[derive(Debug)]
+pub struct SourcePrompt {
+    pub id: i64,
+    pub messages: Value,
+    pub max_tokens: Option<i64>,
+    pub sampling_params: Option<Value>,
+}
+
+/// Which stored requests a benchmark replays.
+#[derive(Debug)]
+pub struct PromptFilter<'a> {
+    /// RFC 3339 cutoff
+    pub since: Option<&'a str>,
+    pub client_id: Option<&'a str>,
+    /// Model the prompts were originally sent to
+    pub source_model: Option<&'a str>,
+    pub sample: i64,
+}
+
+/// A run waiting to be sent.
+#[derive(Debug, Clone)]
+pub struct PendingRun {
+    pub run: i64,
+    pub body: String,
+}
+
+/// Progress over a benchmark's runs.
+#[derive(Debug, Serialize)]
+pub struct RunCounts {
+    pub total: i64,
+    pub pending: i64,
+    pub succeeded: i64,
+    pub failed: i64,
+    pub cancelled: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Benchmark {
+    pub id: String,
+    pub status: String,
+    /// The request that started the benchmark
+    pub spec: Value,
+    pub created_at: String,
+    pub updated_at: String,
+    pub finished_at: Option<String>,
+    pub progress: RunCounts,
+}
+
+/// How one model did on the benchmark's prompts. Averages cover the runs that finished
+/// without an error; `None` until there is one.
+#[derive(Debug, Serialize)]
+pub struct ModelResult {
+    pub model: String,
+    pub runs: i64,
+    pub finished: i64,
+    pub errors: i64,
+    /// `errors` over `finished`
+    pub error_rate: f64,
+    pub avg_tokens_per_second: Option<f64>,
+    /// Time to the first streamed token, including any wait in the proxy's queue
+    pub avg_ttft_ms: Option<f64>,
+    pub p95_ttft_ms: Option<i64>,
+    pub avg_output_tokens: Option<f64>,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    /// From the prices in force when each run was logged; `None` without `MODEL_PRICING`
+    pub estimated_cost: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchmarkReport {
+    #[serde(flatten)]
+    pub benchmark: Benchmark,
+    /// In the order the models were listed
+    pub models: Vec<ModelResult>,
+}
+
+impl Benchmark {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        let spec: String = row.try_get("spec")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            status: row.try_get("status")?,
+            spec: serde_json::from_str(&spec).unwrap_or(Value::Null),
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+            finished_at: row.try_get("finished_at")?,
+            progress: RunCounts {
+                total: row.try_get("total")?,
+                pending: row.try_get("pending")?,
+                succeeded: row.try_get("succeeded")?,
+                failed: row.try_get("failed")?,
+                cancelled: row.try_get("cancelled")?,
+            },
+        })
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+/// A random sample of successful chat requests matching `filter`, leaving out earlier
+/// benchmark runs.
+pub async fn sample_prompts(
+    pool: &SqlitePool,
+    filter: &PromptFilter<'_>,
+) -> Result<Vec<SourcePrompt>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, prompt_text, max_tokens, sampling_params
+        FROM request_rows
+        WHERE is_error = 0 AND termination = ?1 AND endpoint = '/v1/chat/completions'
+          AND benchmark_id IS NULL
+          AND (?2 IS NULL OR start_time >= ?2)
+          AND (?3 IS NULL OR client_id = ?3)
+          AND (?4 IS NULL OR model = ?4)
+        ORDER BY RANDOM()
+        LIMIT ?5
+        "#,
+    )
+    .bind(TERMINATION_COMPLETED)
+    .bind(filter.since)
+    .bind(filter.client_id)
+    .bind(filter.source_model)
+    .bind(filter.sample)
+    .fetch_all(pool)
+    .await?;
+
+    let mut prompts = Vec::new();
+    for row in &rows {
+        let prompt: String = row.try_get("prompt_text")?;
+        // Only requests logged with their messages can be replayed
+        let Ok(messages @ Value::Array(_)) = serde_json::from_str::<Value>(&prompt) else {
+            continue;
+        };
+        prompts.push(SourcePrompt {
+            id: row.try_get("id")?,
+            messages,
+            max_tokens: row.try_get("max_tokens")?,
+            sampling_params: row
+                .try_get::<Option<String>, _>("sampling_params")?
+                .and_then(|params| serde_json::from_str(&params).ok()),
+        });
+    }
+    Ok(prompts)
+}
+
+/// Stores a benchmark and every run, `(source request id, model, body)`, in one
+/// transaction, queued to run.
+pub async fn create_benchmark(
+    pool: &SqlitePool,
+    id: &str,
+    spec: &Value,
+    runs: &[(i64, String, String)],
+) -> Result<(), sqlx::Error> {
+    let now = now();
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        INSERT INTO benchmarks (id, status, spec, created_at, updated_at)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(id)
+    .bind(BENCHMARK_QUEUED)
+    .bind(spec.to_string())
+    .bind(&now)
+    .bind(&now)
+    .execute(&mut *tx)
+    .await?;
+
+    for (run, (source_request_id, model, body)) in runs.iter().enumerate() {
+        sqlx::query(
+            r#"
+            INSERT INTO benchmark_runs (benchmark_id, run, source_request_id, model, body, status)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(id)
+        .bind(run as i64 + 1)
+        .bind(source_request_id)
+        .bind(model)
+        .bind(body)
+        .bind(RUN_PENDING)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn get_benchmark(pool: &SqlitePool, id: &str) -> Result<Option<Benchmark>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            b.*,
+            COUNT(r.run) as total,
+            COALESCE(SUM(r.status = ?2), 0) as pending,
+            COALESCE(SUM(r.status = ?3), 0) as succeeded,
+            COALESCE(SUM(r.status = ?4), 0) as failed,
+            COALESCE(SUM(r.status = ?5), 0) as cancelled
+        FROM benchmarks b
+        LEFT JOIN benchmark_runs r ON r.benchmark_id = b.id
+        WHERE b.id = ?1
+        GROUP BY b.id
+        "#,
+    )
+    .bind(id)
+    .bind(RUN_PENDING)
+    .bind(RUN_SUCCEEDED)
+    .bind(RUN_FAILED)
+    .bind(RUN_CANCELLED)
+    .fetch_optional(pool)
+    .await?;
+
+    row.as_ref().map(Benchmark::from_row).transpose()
+}
+
+/// The benchmark with per-model results of the runs finished so far.
+pub async fn get_report(
+    pool: &SqlitePool,
+    id: &str,
+) -> Result<Option<BenchmarkReport>, sqlx::Error> {
+    let Some(benchmark) = get_benchmark(pool, id).await? else {
+        return Ok(None);
+    };
+
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            r.model,
+            COUNT(*) as runs,
+            SUM(r.status IN (?2, ?3)) as finished,
+            SUM(r.status = ?3) as errors,
+            ROUND(AVG(CASE WHEN r.status = ?2 THEN q.tokens_per_second END), 2)
+                as avg_tokens_per_second,
+            ROUND(AVG(CASE WHEN r.status = ?2
+                THEN q.prompt_eval_ms + COALESCE(q.queue_wait_ms, 0) END), 1) as avg_ttft_ms,
+            ROUND(AVG(CASE WHEN r.status = ?2 THEN q.output_tokens END), 1)
+                as avg_output_tokens,
+            COALESCE(SUM(q.input_tokens), 0) as input_tokens,
+            COALESCE(SUM(q.output_tokens), 0) as output_tokens,
+            SUM(q.input_tokens * q.input_price_per_m / 1000000.0
+                + q.output_tokens * q.output_price_per_m / 1000000.0) as estimated_cost
+        FROM benchmark_runs r
+        LEFT JOIN requests q ON q.proxy_request_id = r.proxy_request_id
+        WHERE r.benchmark_id = ?1
+        GROUP BY r.model
+        ORDER BY MIN(r.run)
+        "#,
+    )
+    .bind(id)
+    .bind(RUN_SUCCEEDED)
+    .bind(RUN_FAILED)
+    .fetch_all(pool)
+    .await?;
+
+    let mut models = Vec::new();
+    for row in &rows {
+        let model: String = row.try_get("model")?;
+        let finished: i64 = row.try_get("finished")?;
+        let errors: i64 = row.try_get("errors")?;
+        models.push(ModelResult {
+            p95_ttft_ms: get_ttft_p95(pool, id, &model).await?,
+            model,
+            runs: row.try_get("runs")?,
+            finished,
+            errors,
+            error_rate: if finished > 0 {
+                (errors as f64 / finished as f64 * 1000.0).round() / 1000.0
+            } else {
+                0.0
+            },
+            avg_tokens_per_second: row.try_get("avg_tokens_per_second")?,
+            avg_ttft_ms: row.try_get("avg_ttft_ms")?,
+            avg_output_tokens: row.try_get("avg_output_tokens")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+            estimated_cost: row
+                .try_get::<Option<f64>, _>("estimated_cost")?
+                .map(|cost| (cost * 1_000_000.0).round() / 1_000_000.0),
+        });
+    }
+
+    Ok(Some(BenchmarkReport { benchmark, models }))
+}
+
+async fn get_ttft_p95(
+    pool: &SqlitePool,
+    id: &str,
+    model: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    let ttfts: Vec<i64> = sqlx::query_scalar(
+        r#"
+        SELECT q.prompt_eval_ms + COALESCE(q.queue_wait_ms, 0) as ttft
+        FROM benchmark_runs r
+        JOIN requests q ON q.proxy_request_id = r.proxy_request_id
+        WHERE r.benchmark_id = ? AND r.model = ? AND r.status = ?
+          AND q.prompt_eval_ms IS NOT NULL
+        ORDER BY ttft
+        "#,
+    )
+    .bind(id)
+    .bind(model)
+    .bind(RUN_SUCCEEDED)
+    .fetch_all(pool)
+    .await?;
+
+    Ok((!ttfts.is_empty()).then(|| super::models::duration_percentile(&ttfts, 0.95)))
+}
+
+/// Estimated cost of every run logged so far, 0 for runs without prices.
+pub async fn get_spent(pool: &SqlitePool, id: &str) -> Result<f64, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT COALESCE(SUM(
+            COALESCE(input_tokens * input_price_per_m, 0) / 1000000.0
+            + COALESCE(output_tokens * output_price_per_m, 0) / 1000000.0), 0.0)
+        FROM requests
+        WHERE benchmark_id = ?
+        "#,
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Ids of benchmarks that were queued or running when the process last stopped.
+pub async fn get_unfinished_benchmarks(pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM benchmarks WHERE status IN (?, ?) ORDER BY created_at")
+        .bind(BENCHMARK_QUEUED)
+        .bind(BENCHMARK_RUNNING)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn get_pending_runs(
+    pool: &SqlitePool,
+    benchmark_id: &str,
+) -> Result<Vec<PendingRun>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT run, body FROM benchmark_runs WHERE benchmark_id = ? AND status = ? ORDER BY run",
+    )
+    .bind(benchmark_id)
+    .bind(RUN_PENDING)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(PendingRun {
+                run: row.try_get("run")?,
+                body: row.try_get("body")?,
+            })
+        })
+        .collect()
+}
+
+pub async fn set_benchmark_status(
+    pool: &SqlitePool,
+    id: &str,
+    status: &str,
+) -> Result<(), sqlx::Error> {
+    let now = now();
+    let finished = matches!(
+        status,
+        BENCHMARK_COMPLETED | BENCHMARK_CANCELLED | BENCHMARK_BUDGET_EXCEEDED
+    );
+    sqlx::query(
+        r#"
+        UPDATE benchmarks
+        SET status = ?, updated_at = ?, finished_at = CASE WHEN ? THEN ? ELSE finished_at END
+        WHERE id = ?
+        "#,
+    )
+    .bind(status)
+    .bind(&now)
+    .bind(finished)
+    .bind(&now)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn finish_run(
+    pool: &SqlitePool,
+    benchmark_id: &str,
+    run: i64,
+    http_status: u16,
+    proxy_request_id: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let status = if (200..300).contains(&http_status) {
+        RUN_SUCCEEDED
+    } else {
+        RUN_FAILED
+    };
+    sqlx::query(
+        r#"
+        UPDATE benchmark_runs
+        SET status = ?, http_status = ?, proxy_request_id = ?, finished_at = ?
+        WHERE benchmark_id = ? AND run = ?
+        "#,
+    )
+    .bind(status)
+    .bind(http_status as i64)
+    .bind(proxy_request_id)
+    .bind(now())
+    .bind(benchmark_id)
+    .bind(run)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks every run that hasn't been sent as cancelled.
+pub async fn cancel_pending_runs(
+    pool: &SqlitePool,
+    benchmark_id: &str,
+) -> Result<u64, sqlx::Error> {
+    let result =
+        sqlx::query("UPDATE benchmark_runs SET status = ? WHERE benchmark_id = ? AND status = ?")
+            .bind(RUN_CANCELLED)
+            .bind(benchmark_id)
+            .bind(RUN_PENDING)
+            .execute(pool)
+            .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use crate::db::{RequestRecord, insert_request};
+    use serde_json::json;
+
+    const CHAT: &str = "/v1/chat/completions";
+    const MESSAGES: &str = r#"[{"role":"user","content":"Tell me a story"}]"#;
+
+    fn completed(endpoint: &str, model: &str, prompt: &str) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            endpoint.to_string(),
+            model.to_string(),
+            chrono::Utc::now(),
+            prompt.to_string(),
+        );
+        record.termination = Some(TERMINATION_COMPLETED.to_string());
+        record
+    }
+
+    /// Logs the request of a run and marks the run finished with `http_status`.
+    async fn finish_logged(
+        pool: &SqlitePool,
+        run: i64,
+        http_status: u16,
+        mut record: RequestRecord,
+    ) {
+        record.benchmark_id = Some("b1".to_string());
+        record.http_status = http_status as i32;
+        record.is_error = http_status >= 400;
+        insert_request(pool, &record).await.unwrap();
+        finish_run(pool, "b1", run, http_status, record.proxy_request_id.as_deref())
+            .await
+            .unwrap();
+    }
+
+    /// A successful run's request: `(input, output)` tokens and `(prompt eval, queue wait)`.
+    fn run_record(
+        model: &str,
+        tokens: (i64, i64),
+        ttft: (i64, Option<i64>),
+        tps: f64,
+    ) -> RequestRecord {
+        let mut record = completed(CHAT, model, MESSAGES);
+        (record.input_tokens, record.output_tokens) = tokens;
+        (record.prompt_eval_ms, record.queue_wait_ms) = (Some(ttft.0), ttft.1);
+        record.tokens_per_second = Some(tps);
+        record
+    }
+
+    /// Two prompts against models `a` and `b`, stored the way `/admin/benchmark` does.
+    async fn two_by_two(pool: &SqlitePool) {
+        let runs: Vec<_> = [(1, "a"), (1, "b"), (2, "a"), (2, "b")]
+            .into_iter()
+            .map(|(source, model)| {
+                (source, model.to_string(), json!({ "model": model }).to_string())
+            })
+            .collect();
+        create_benchmark(pool, "b1", &json!({ "models": ["a", "b"] }), &runs)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn report_shape() {
+        let pool = memory_pool().await;
+        two_by_two(&pool).await;
+
+        let mut priced = run_record("a", (10, 40), (100, Some(10)), 20.0);
+        (priced.input_price_per_m, priced.output_price_per_m) = (Some(1.0), Some(2.0));
+        finish_logged(&pool, 1, 200, priced).await;
+        finish_logged(&pool, 2, 200, run_record("b", (8, 20), (300, None), 10.0)).await;
+        let mut failed = completed(CHAT, "a", MESSAGES);
+        failed.input_tokens = 5;
+        finish_logged(&pool, 3, 500, failed).await;
+
+        let mut report = serde_json::to_value(get_report(&pool, "b1").await.unwrap().unwrap())
+            .unwrap();
+        for field in ["created_at", "updated_at"] {
+            assert!(report[field].is_string(), "{}", report);
+            report[field] = json!("<time>");
+        }
+
+        assert_eq!(
+            report,
+            json!({
+                "id": "b1",
+                "status": "queued",
+                "spec": { "models": ["a", "b"] },
+                "created_at": "<time>",
+                "updated_at": "<time>",
+                "finished_at": null,
+                "progress": {
+                    "total": 4,
+                    "pending": 1,
+                    "succeeded": 2,
+                    "failed": 1,
+                    "cancelled": 0
+                },
+                "models": [
+                    {
+                        "model": "a",
+                        "runs": 2,
+                        "finished": 2,
+                        "errors": 1,
+                        "error_rate": 0.5,
+                        "avg_tokens_per_second": 20.0,
+                        "avg_ttft_ms": 110.0,
+                        "p95_ttft_ms": 110,
+                        "avg_output_tokens": 40.0,
+                        "input_tokens": 15,
+                        "output_tokens": 40,
+                        "estimated_cost": 0.00009
+                    },
+                    {
+                        "model": "b",
+                        "runs": 2,
+                        "finished": 1,
+                        "errors": 0,
+                        "error_rate": 0.0,
+                        "avg_tokens_per_second": 10.0,
+                        "avg_ttft_ms": 300.0,
+                        "p95_ttft_ms": 300,
+                        "avg_output_tokens": 20.0,
+                        "input_tokens": 8,
+                        "output_tokens": 20,
+                        "estimated_cost": null
+                    }
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn report_before_any_run_finished() {
+        let pool = memory_pool().await;
+        two_by_two(&pool).await;
+
+        let report = get_report(&pool, "b1").await.unwrap().unwrap();
+        let a = serde_json::to_value(&report.models[0]).unwrap();
+        assert_eq!(
+            a,
+            json!({
+                "model": "a",
+                "runs": 2,
+                "finished": 0,
+                "errors": 0,
+                "error_rate": 0.0,
+                "avg_tokens_per_second": null,
+                "avg_ttft_ms": null,
+                "p95_ttft_ms": null,
+                "avg_output_tokens": null,
+                "input_tokens": 0,
+                "output_tokens": 0,
+                "estimated_cost": null
+            })
+        );
+        assert!(get_report(&pool, "missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn cancelling_and_finishing() {
+        let pool = memory_pool().await;
+        two_by_two(&pool).await;
+        assert_eq!(get_unfinished_benchmarks(&pool).await.unwrap(), ["b1"]);
+        let pending: Vec<_> = get_pending_runs(&pool, "b1").await.unwrap();
+        assert_eq!(pending.iter().map(|run| run.run).collect::<Vec<_>>(), [1, 2, 3, 4]);
+
+        finish_run(&pool, "b1", 1, 200, None).await.unwrap();
+        assert_eq!(cancel_pending_runs(&pool, "b1").await.unwrap(), 3);
+        set_benchmark_status(&pool, "b1", BENCHMARK_CANCELLED).await.unwrap();
+
+        let benchmark = get_benchmark(&pool, "b1").await.unwrap().unwrap();
+        assert_eq!(benchmark.status, BENCHMARK_CANCELLED);
+        assert!(benchmark.finished_at.is_some());
+        assert_eq!((benchmark.progress.succeeded, benchmark.progress.cancelled), (1, 3));
+        assert!(get_pending_runs(&pool, "b1").await.unwrap().is_empty());
+        assert!(get_unfinished_benchmarks(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn spent_counts_priced_runs_only() {
+        let pool = memory_pool().await;
+        two_by_two(&pool).await;
+        assert_eq!(get_spent(&pool, "b1").await.unwrap(), 0.0);
+
+        let mut priced = run_record("a", (1_000_000, 500_000), (100, None), 20.0);
+        (priced.input_price_per_m, priced.output_price_per_m) = (Some(0.5), Some(1.0));
+        finish_logged(&pool, 1, 200, priced).await;
+        finish_logged(&pool, 2, 200, run_record("b", (1_000, 1_000), (100, None), 20.0)).await;
+
+        assert_eq!(get_spent(&pool, "b1").await.unwrap(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn sampling_skips_what_cannot_be_replayed() {
+        let pool = memory_pool().await;
+        let mut keep = completed(CHAT, "m", MESSAGES);
+        keep.client_id = Some("ci".to_string());
+        keep.max_tokens = Some(64);
+        let keep_id = insert_request(&pool, &keep).await.unwrap();
+
+        let mut error = completed(CHAT, "m", MESSAGES);
+        error.is_error = true;
+        let mut replayed = completed(CHAT, "m", MESSAGES);
+        replayed.benchmark_id = Some("b0".to_string());
+        let mut abandoned = completed(CHAT, "m", MESSAGES);
+        abandoned.termination = None;
+        let mut other_client = completed(CHAT, "m", MESSAGES);
+        other_client.client_id = Some("other".to_string());
+        for record in [
+            error,
+            replayed,
+            abandoned,
+            completed(CHAT, "m", "plain text, not messages"),
+            completed("/v1/completions", "m", MESSAGES),
+            completed(CHAT, "other-model", MESSAGES),
+            other_client,
+        ] {
+            insert_request(&pool, &record).await.unwrap();
+        }
+
+        let filter = PromptFilter {
+            since: None,
+            client_id: None,
+            source_model: Some("m"),
+            sample: 10,
+        };
+        let prompts = sample_prompts(&pool, &filter).await.unwrap();
+        assert_eq!(prompts.len(), 2);
+
+        let filter = PromptFilter {
+            client_id: Some("ci"),
+            ..filter
+        };
+        let prompts = sample_prompts(&pool, &filter).await.unwrap();
+        assert_eq!(prompts.len(), 1);
+        assert_eq!(prompts[0].id, keep_id);
+        assert_eq!(prompts[0].messages[0]["content"], "Tell me a story");
+        assert_eq!(prompts[0].max_tokens, Some(64));
+
+        let since = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+        let filter = PromptFilter {
+            since: Some(&since),
+            ..filter
+        };
+        assert!(sample_prompts(&pool, &filter).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+use sha2::{Digest, Sha256};
+use sqlx::{Row, SqliteConnection, SqlitePool};
+
+/// Hex-encoded SHA-256 of a prompt or output, used as its blob key.
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Stores `content` under `hash` unless an identical blob already exists.
+///
+/// Reference counts are maintained by triggers on `requests`, so the blob must be
+/// stored before the row that references it is inserted.
+pub async fn store_blob(
+    conn: &mut SqliteConnection,
+    hash: &str,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO blobs (hash, content, refcount) VALUES (?, ?, 0) ON CONFLICT (hash) DO NOTHING")
+        .bind(hash)
+        .bind(content)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+async fn database_size(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()")
+        .fetch_one(pool)
+        .await
+}
+
+/// Rows whose prompt and output text still live inline in `requests`.
+pub async fn count_undeduplicated(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM requests WHERE prompt_hash IS NULL")
+        .fetch_one(pool)
+        .await
+}
+
+/// Moves the prompt and output text of up to `limit` rows written before blob storage
+/// into `blobs`, in one transaction. Returns how many rows were converted.
+///
+/// Each row switches from inline text to blob references atomically, and reads go
+/// through `request_rows`, so readers see the same content before and after.
+pub async fn deduplicate_batch(pool: &SqlitePool, limit: i64) -> Result<u64, sqlx::Error> {
+    // Take the write lock up front; upgrading a read transaction fails if a request
+    // insert got there first
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+    let rows = sqlx::query("SELECT id, prompt, output FROM requests WHERE prompt_hash IS NULL LIMIT ?")
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+
+    for row in &rows {
+        let id: i64 = row.try_get("id")?;
+        let prompt: String = row.try_get("prompt")?;
+        let output: String = row.try_get("output")?;
+        let prompt_hash = content_hash(&prompt);
+        let output_hash = content_hash(&output);
+
+        store_blob(&mut tx, &prompt_hash, &prompt).await?;
+        store_blob(&mut tx, &output_hash, &output).await?;
+        sqlx::query(
+            "UPDATE requests SET prompt_hash = ?, output_hash = ?, prompt = '', output = '' WHERE id = ?",
+        )
+        .bind(&prompt_hash)
+        .bind(&output_hash)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(rows.len() as u64)
+}
+
+/// Vacuums once every row is deduplicated and logs how much space was reclaimed.
+pub async fn reclaim_space(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let size_before = database_size(pool).await?;
+    sqlx::query("VACUUM").execute(pool).await?;
+    let size_after = database_size(pool).await?;
+    tracing::info!(
+        "Blob deduplication complete: database {} -> {} bytes ({} reclaimed)",
+        size_before,
+        size_after,
+        size_before - size_after
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::retention::delete_requests_before;
+    use crate::db::testing::memory_pool;
+    use crate::db::{insert_request, RequestRecord};
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn start(minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 1, 1, 0, minute, 0).unwrap()
+    }
+
+    async fn insert(pool: &SqlitePool, minute: u32, prompt: &str, output: &str) {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            start(minute),
+            prompt.to_string(),
+        );
+        record.output = output.to_string();
+        insert_request(pool, &record).await.unwrap();
+    }
+
+    /// Inserts a row the way they were written before blob storage.
+    async fn insert_inline(pool: &SqlitePool, minute: u32, prompt: &str, output: &str) {
+        sqlx::query(
+            r#"
+            INSERT INTO requests (
+                endpoint, model, start_time, end_time, duration_ms,
+                input_tokens, output_tokens, total_tokens, prompt, output, http_status
+            )
+            VALUES ('/v1/chat/completions', 'm', ?1, ?1, 1, 0, 0, 0, ?2, ?3, 200)
+            "#,
+        )
+        .bind(start(minute).to_rfc3339())
+        .bind(prompt)
+        .bind(output)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn refcount(pool: &SqlitePool, content: &str) -> Option<i64> {
+        sqlx::query_scalar("SELECT refcount FROM blobs WHERE hash = ?")
+            .bind(content_hash(content))
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn prune_before(pool: &SqlitePool, minute: u32) -> u64 {
+        delete_requests_before(pool, &start(minute).to_rfc3339())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn shared_blob_outlives_all_but_the_last_reference() {
+        let pool = memory_pool().await;
+        insert(&pool, 0, "system", "a").await;
+        insert(&pool, 1, "system", "b").await;
+        insert(&pool, 2, "system", "c").await;
+        assert_eq!(refcount(&pool, "system").await, Some(3));
+
+        assert_eq!(prune_before(&pool, 1).await, 1);
+        assert_eq!(refcount(&pool, "system").await, Some(2));
+        assert_eq!(refcount(&pool, "a").await, None);
+
+        assert_eq!(prune_before(&pool, 2).await, 1);
+        assert_eq!(refcount(&pool, "system").await, Some(1));
+        assert_eq!(refcount(&pool, "c").await, Some(1));
+
+        assert_eq!(prune_before(&pool, 3).await, 1);
+        assert_eq!(refcount(&pool, "system").await, None);
+        let blobs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM blobs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(blobs, 0);
+    }
+
+    #[tokio::test]
+    async fn deleting_newest_first_keeps_the_blob_for_older_rows() {
+        let pool = memory_pool().await;
+        insert(&pool, 0, "system", "a").await;
+        insert(&pool, 5, "system", "b").await;
+
+        sqlx::query("DELETE FROM requests WHERE start_time >= ?")
+            .bind(start(5).to_rfc3339())
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(refcount(&pool, "system").await, Some(1));
+
+        let prompt: String = sqlx::query_scalar("SELECT prompt_text FROM request_rows")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(prompt, "system");
+    }
+
+    #[tokio::test]
+    async fn prompt_equal_to_output_counts_twice() {
+        let pool = memory_pool().await;
+        insert(&pool, 0, "echo", "echo").await;
+        insert(&pool, 1, "echo", "other").await;
+        assert_eq!(refcount(&pool, "echo").await, Some(3));
+
+        prune_before(&pool, 1).await;
+        assert_eq!(refcount(&pool, "echo").await, Some(1));
+        prune_before(&pool, 2).await;
+        assert_eq!(refcount(&pool, "echo").await, None);
+    }
+
+    #[tokio::test]
+    async fn deduplication_shares_blobs_with_new_rows() {
+        let pool = memory_pool().await;
+        insert(&pool, 0, "system", "new").await;
+        insert_inline(&pool, 1, "system", "old").await;
+        insert_inline(&pool, 2, "system", "old").await;
+        assert_eq!(count_undeduplicated(&pool).await.unwrap(), 2);
+
+        assert_eq!(deduplicate_batch(&pool, 1).await.unwrap(), 1);
+        assert_eq!(deduplicate_batch(&pool, 10).await.unwrap(), 1);
+        assert_eq!(deduplicate_batch(&pool, 10).await.unwrap(), 0);
+        assert_eq!(count_undeduplicated(&pool).await.unwrap(), 0);
+        assert_eq!(refcount(&pool, "system").await, Some(3));
+        assert_eq!(refcount(&pool, "old").await, Some(2));
+
+        let rows: Vec<(String, String, String, String)> = sqlx::query_as(
+            "SELECT prompt, output, prompt_text, output_text FROM request_rows ORDER BY start_time",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        for (prompt, output, _, _) in &rows {
+            assert_eq!((prompt.as_str(), output.as_str()), ("", ""));
+        }
+        let texts: Vec<_> = rows.iter().map(|r| (r.2.as_str(), r.3.as_str())).collect();
+        assert_eq!(texts, [("system", "new"), ("system", "old"), ("system", "old")]);
+
+        prune_before(&pool, 2).await;
+        assert_eq!(refcount(&pool, "new").await, None);
+        assert_eq!(refcount(&pool, "old").await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn repointing_a_row_releases_its_old_blob() {
+        let pool = memory_pool().await;
+        insert(&pool, 0, "before", "out").await;
+        let mut conn = pool.acquire().await.unwrap();
+        store_blob(&mut conn, &content_hash("after"), "after").await.unwrap();
+        drop(conn);
+
+        sqlx::query("UPDATE requests SET prompt_hash = ?")
+            .bind(content_hash("after"))
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(refcount(&pool, "before").await, None);
+        assert_eq!(refcount(&pool, "after").await, Some(1));
+        assert_eq!(refcount(&pool, "out").await, Some(1));
+    }
+}
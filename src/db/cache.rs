@@ -0,0 +1,270 @@
+use chrono::DateTime;
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use tokio_stream::StreamExt;
+
+/// Cache lifetimes the report simulates, in seconds
+pub const TTL_CANDIDATES_SECS: &[i64] = &[60, 300, 900, 3600, 6 * 3600, 24 * 3600];
+
+/// Share of the hits an unbounded cache would get that the suggested TTL must reach
+const SUGGESTED_TTL_COVERAGE: f64 = 0.9;
+
+/// Request ids kept per cluster as examples
+const MAX_EXAMPLE_IDS: usize = 5;
+
+/// Identical requests (same prompt, model, sampling parameters and tools) that an
+/// exact-match cache would have answered after the first.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheCluster {
+    pub model: String,
+    pub prompt_hash: String,
+    pub params_hash: String,
+    pub occurrences: i64,
+    /// Tokens and time spent on every occurrence after the first
+    pub saved_tokens: i64,
+    pub saved_duration_ms: i64,
+    pub first_seen: String,
+    pub last_seen: String,
+    /// Time from the first occurrence to the last
+    pub spread_secs: i64,
+    /// Longest wait between consecutive occurrences; a TTL at least this long serves them all
+    pub max_gap_secs: i64,
+    /// The earliest occurrences
+    pub example_request_ids: Vec<String>,
+}
+
+/// What a cache with one TTL would have served: an entry lives for the TTL after the
+/// miss that filled it.
+#[derive(Debug, Clone, Serialize)]
+pub struct TtlSavings {
+    pub ttl_secs: i64,
+    pub hits: i64,
+    pub saved_tokens: i64,
+    pub saved_duration_ms: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheOpportunityReport {
+    pub since: Option<String>,
+    pub min_occurrences: i64,
+    /// Successful requests looked at
+    pub requests_scanned: i64,
+    /// Clusters with at least `min_occurrences` requests
+    pub clusters_found: i64,
+    /// Requests a cache that never expires would have answered, across those clusters
+    pub repeated_requests: i64,
+    pub saved_tokens: i64,
+    pub saved_duration_ms: i64,
+    /// Savings per candidate TTL, shortest first
+    pub ttl_options: Vec<TtlSavings>,
+    /// Shortest candidate TTL that gets 90% of `repeated_requests`
+    pub suggested_ttl_secs: Option<i64>,
+    /// Most tokens saved first
+    pub clusters: Vec<CacheCluster>,
+}
+
+/// A cluster being read, with the per-TTL cache state of its key.
+struct OpenCluster {
+    cluster: CacheCluster,
+    first_ms: i64,
+    last_ms: i64,
+    /// Per candidate TTL: when the cached entry was filled, and the savings so far
+    filled_ms: Vec<i64>,
+    ttl: Vec<TtlSavings>,
+}
+
+impl OpenCluster {
+    fn start(row: &Occurrence) -> Self {
+        Self {
+            cluster: CacheCluster {
+                model: row.model.clone(),
+                prompt_hash: row.prompt_hash.clone(),
+                params_hash: row.params_hash.clone(),
+                occurrences: 1,
+                saved_tokens: 0,
+                saved_duration_ms: 0,
+                first_seen: row.start_time.clone(),
+                last_seen: row.start_time.clone(),
+                spread_secs: 0,
+                max_gap_secs: 0,
+                example_request_ids: row.proxy_request_id.iter().cloned().collect(),
+            },
+            first_ms: row.start_ms,
+            last_ms: row.start_ms,
+            filled_ms: vec![row.start_ms; TTL_CANDIDATES_SECS.len()],
+            ttl: empty_ttl_savings(),
+        }
+    }
+
+    fn add(&mut self, row: &Occurrence) {
+        let cluster = &mut self.cluster;
+        cluster.occurrences += 1;
+        cluster.saved_tokens += row.total_tokens;
+        cluster.saved_duration_ms += row.duration_ms;
+        cluster.max_gap_secs = cluster
+            .max_gap_secs
+            .max((row.start_ms - self.last_ms) / 1000);
+        cluster.last_seen = row.start_time.clone();
+        if cluster.example_request_ids.len() < MAX_EXAMPLE_IDS {
+            cluster
+                .example_request_ids
+                .extend(row.proxy_request_id.clone());
+        }
+        self.last_ms = row.start_ms;
+
+        for ((filled_ms, savings), ttl_secs) in self
+            .filled_ms
+            .iter_mut()
+            .zip(&mut self.ttl)
+            .zip(TTL_CANDIDATES_SECS)
+        {
+            if row.start_ms - *filled_ms < ttl_secs * 1000 {
+                savings.hits += 1;
+                savings.saved_tokens += row.total_tokens;
+                savings.saved_duration_ms += row.duration_ms;
+            } else {
+                *filled_ms = row.start_ms;
+            }
+        }
+    }
+
+    fn finish(mut self) -> (CacheCluster, Vec<TtlSavings>) {
+        self.cluster.spread_secs = (self.last_ms - self.first_ms) / 1000;
+        (self.cluster, self.ttl)
+    }
+}
+
+struct Occurrence {
+    model: String,
+    prompt_hash: String,
+    params_hash: String,
+    proxy_request_id: Option<String>,
+    start_time: String,
+    start_ms: i64,
+    total_tokens: i64,
+    duration_ms: i64,
+}
+
+fn empty_ttl_savings() -> Vec<TtlSavings> {
+    TTL_CANDIDATES_SECS
+        .iter()
+        .map(|&ttl_secs| TtlSavings {
+            ttl_secs,
+            hits: 0,
+            saved_tokens: 0,
+            saved_duration_ms: 0,
+        })
+        .collect()
+}
+
+/// Groups successful requests since `since` by prompt and parameter hash and reports the
+/// clusters of at least `min_occurrences`. Rows are streamed in key order and only one
+/// cluster is open at a time, so memory stays bounded by `limit` however large the table.
+pub async fn get_cache_opportunities(
+    pool: &SqlitePool,
+    since: Option<&str>,
+    min_occurrences: i64,
+    limit: usize,
+) -> Result<CacheOpportunityReport, sqlx::Error> {
+    let mut rows = sqlx::query(
+        r#"
+        SELECT model, prompt_hash, params_hash, proxy_request_id, start_time, total_tokens,
+            duration_ms
+        FROM requests
+        WHERE is_error = 0 AND benchmark_id IS NULL AND prompt_hash IS NOT NULL
+          AND params_hash IS NOT NULL AND (?1 IS NULL OR start_time >= ?1)
+        ORDER BY prompt_hash, params_hash, start_time
+        "#,
+    )
+    .bind(since)
+    .fetch(pool);
+
+    let mut report = CacheOpportunityReport {
+        since: since.map(|s| s.to_string()),
+        min_occurrences,
+        requests_scanned: 0,
+        clusters_found: 0,
+        repeated_requests: 0,
+        saved_tokens: 0,
+        saved_duration_ms: 0,
+        ttl_options: empty_ttl_savings(),
+        suggested_ttl_secs: None,
+        clusters: Vec::new(),
+    };
+    let mut open: Option<OpenCluster> = None;
+    while let Some(row) = rows.next().await {
+        let row = row?;
+        let start_time: String = row.try_get("start_time")?;
+        let Ok(start) = DateTime::parse_from_rfc3339(&start_time) else {
+            continue;
+        };
+        let occurrence = Occurrence {
+            model: row.try_get("model")?,
+            prompt_hash: row.try_get("prompt_hash")?,
+            params_hash: row.try_get("params_hash")?,
+            proxy_request_id: row.try_get("proxy_request_id")?,
+            start_ms: start.timestamp_millis(),
+            start_time,
+            total_tokens: row.try_get("total_tokens")?,
+            duration_ms: row.try_get("duration_ms")?,
+        };
+        report.requests_scanned += 1;
+
+        match &mut open {
+            Some(cluster)
+                if cluster.cluster.prompt_hash == occurrence.prompt_hash
+                    && cluster.cluster.params_hash == occurrence.params_hash =>
+            {
+                cluster.add(&occurrence)
+            }
+            _ => {
+                if let Some(done) = open.replace(OpenCluster::start(&occurrence)) {
+                    close(&mut report, done, limit);
+                }
+            }
+        }
+    }
+    if let Some(done) = open {
+        close(&mut report, done, limit);
+    }
+
+    report
+        .clusters
+        .sort_by_key(|cluster| std::cmp::Reverse(cluster.saved_tokens));
+    report.clusters.truncate(limit);
+    report.suggested_ttl_secs = report
+        .ttl_options
+        .iter()
+        .find(|option| {
+            option.hits as f64 >= report.repeated_requests as f64 * SUGGESTED_TTL_COVERAGE
+        })
+        .filter(|_| report.repeated_requests > 0)
+        .map(|option| option.ttl_secs);
+    Ok(report)
+}
+
+/// Adds a finished cluster to the report if it repeated often enough, keeping at most a
+/// bounded number of the largest ones.
+fn close(report: &mut CacheOpportunityReport, open: OpenCluster, limit: usize) {
+    let (cluster, ttl) = open.finish();
+    if cluster.occurrences < report.min_occurrences || cluster.occurrences < 2 {
+        return;
+    }
+    report.clusters_found += 1;
+    report.repeated_requests += cluster.occurrences - 1;
+    report.saved_tokens += cluster.saved_tokens;
+    report.saved_duration_ms += cluster.saved_duration_ms;
+    for (total, savings) in report.ttl_options.iter_mut().zip(ttl) {
+        total.hits += savings.hits;
+        total.saved_tokens += savings.saved_tokens;
+        total.saved_duration_ms += savings.saved_duration_ms;
+    }
+
+    report.clusters.push(cluster);
+    if report.clusters.len() >= limit * 2 {
+        report
+            .clusters
+            .sort_by_key(|cluster| std::cmp::Reverse(cluster.saved_tokens));
+        report.clusters.truncate(limit);
+    }
+}
@@ -0,0 +1,78 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use crate::canary::PRIMARY_UPSTREAM;
+
+/// Outcomes of the requests one upstream served.
+#[derive(Debug, Serialize)]
+pub struct UpstreamStats {
+    pub upstream: String,
+    pub requests: i64,
+    pub errors: i64,
+    pub error_rate: f64,
+    /// Over successful requests only, so fast failures don't flatter an upstream
+    pub avg_duration_ms: f64,
+    pub max_duration_ms: i64,
+    pub avg_tokens_per_second: Option<f64>,
+}
+
+impl UpstreamStats {
+    pub fn empty(upstream: &str) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            requests: 0,
+            errors: 0,
+            error_rate: 0.0,
+            avg_duration_ms: 0.0,
+            max_duration_ms: 0,
+            avg_tokens_per_second: None,
+        }
+    }
+}
+
+/// Requests since `since` per upstream, leaving out benchmark runs. Requests logged
+/// before the upstream was recorded all went to the primary and count towards it.
+pub async fn get_upstream_stats(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<Vec<UpstreamStats>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            COALESCE(upstream, ?1) as upstream,
+            COUNT(*) as requests,
+            COALESCE(SUM(is_error), 0) as errors,
+            COALESCE(AVG(CASE WHEN is_error = 0 THEN CAST(duration_ms AS REAL) END), 0.0)
+                as avg_duration_ms,
+            COALESCE(MAX(CASE WHEN is_error = 0 THEN duration_ms END), 0) as max_duration_ms,
+            AVG(CASE WHEN is_error = 0 THEN tokens_per_second END) as avg_tokens_per_second
+        FROM requests
+        WHERE benchmark_id IS NULL AND (?2 IS NULL OR start_time >= ?2)
+        GROUP BY 1
+        "#,
+    )
+    .bind(PRIMARY_UPSTREAM)
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut upstreams = Vec::new();
+    for row in rows {
+        let requests: i64 = row.try_get("requests")?;
+        let errors: i64 = row.try_get("errors")?;
+        upstreams.push(UpstreamStats {
+            upstream: row.try_get("upstream")?,
+            requests,
+            errors,
+            error_rate: if requests > 0 {
+                errors as f64 / requests as f64
+            } else {
+                0.0
+            },
+            avg_duration_ms: row.try_get("avg_duration_ms")?,
+            max_duration_ms: row.try_get("max_duration_ms")?,
+            avg_tokens_per_second: row.try_get("avg_tokens_per_second")?,
+        });
+    }
+    Ok(upstreams)
+}
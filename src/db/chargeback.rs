@@ -0,0 +1,287 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// Usage billed at one rate. A price change mid-period splits a model into two lines.
+#[derive(Debug, Serialize)]
+pub struct ChargebackLine {
+    pub model: String,
+    pub input_price_per_m: Option<f64>,
+    pub output_price_per_m: Option<f64>,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    /// `None` for usage logged without a configured price
+    pub input_cost: Option<f64>,
+    pub output_cost: Option<f64>,
+    pub total: Option<f64>,
+    pub first_request: String,
+    pub last_request: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Chargeback {
+    pub namespace: String,
+    pub period_start: String,
+    pub period_end: String,
+    pub currency: &'static str,
+    pub lines: Vec<ChargebackLine>,
+    pub total_requests: i64,
+    pub total_input_tokens: i64,
+    pub total_output_tokens: i64,
+    pub grand_total: f64,
+    /// Requests logged without a price, and so missing from the grand total
+    pub unpriced_requests: i64,
+}
+
+/// Rounds a dollar amount to the precision shown on the report.
+pub(crate) fn round_cost(cost: f64) -> f64 {
+    (cost * 10_000.0).round() / 10_000.0
+}
+
+/// Successful requests in `namespace` started in `[start, end)`, grouped by model and
+/// the rates stored on each request.
+pub async fn get_chargeback(
+    pool: &SqlitePool,
+    namespace: &str,
+    start: &str,
+    end: &str,
+) -> Result<Chargeback, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            model,
+            input_price_per_m,
+            output_price_per_m,
+            COUNT(*) as requests,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens,
+            MIN(start_time) as first_request,
+            MAX(start_time) as last_request
+        FROM requests
+        WHERE namespace = ? AND start_time >= ? AND start_time < ? AND is_error = 0
+        GROUP BY model, input_price_per_m, output_price_per_m
+        ORDER BY model, first_request
+        "#,
+    )
+    .bind(namespace)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+
+    let mut lines = Vec::new();
+    for row in rows {
+        let input_price_per_m: Option<f64> = row.try_get("input_price_per_m")?;
+        let output_price_per_m: Option<f64> = row.try_get("output_price_per_m")?;
+        let input_tokens: i64 = row.try_get("input_tokens")?;
+        let output_tokens: i64 = row.try_get("output_tokens")?;
+        let input_cost =
+            input_price_per_m.map(|price| round_cost(input_tokens as f64 * price / 1e6));
+        let output_cost =
+            output_price_per_m.map(|price| round_cost(output_tokens as f64 * price / 1e6));
+
+        lines.push(ChargebackLine {
+            model: row.try_get("model")?,
+            input_price_per_m,
+            output_price_per_m,
+            requests: row.try_get("requests")?,
+            input_tokens,
+            output_tokens,
+            input_cost,
+            output_cost,
+            total: input_cost
+                .zip(output_cost)
+                .map(|(input, output)| round_cost(input + output)),
+            first_request: row.try_get("first_request")?,
+            last_request: row.try_get("last_request")?,
+        });
+    }
+
+    Ok(Chargeback {
+        namespace: namespace.to_string(),
+        period_start: start.to_string(),
+        period_end: end.to_string(),
+        currency: "USD",
+        total_requests: lines.iter().map(|line| line.requests).sum(),
+        total_input_tokens: lines.iter().map(|line| line.input_tokens).sum(),
+        total_output_tokens: lines.iter().map(|line| line.output_tokens).sum(),
+        grand_total: round_cost(
+            lines
+                .iter()
+                .filter_map(|line| line.total)
+                .fold(0.0, |sum, total| sum + total),
+        ),
+        unpriced_requests: lines
+            .iter()
+            .filter(|line| line.total.is_none())
+            .map(|line| line.requests)
+            .sum(),
+        lines,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Price, PricingConfig};
+    use crate::db::testing::memory_pool;
+    use crate::db::{insert_request, RequestRecord};
+    use chrono::{DateTime, TimeZone, Utc};
+
+    const START: &str = "2026-03-01T00:00:00+00:00";
+    const END: &str = "2026-04-01T00:00:00+00:00";
+
+    fn at(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, day, 12, 0, 0).unwrap()
+    }
+
+    /// A request billed the way the handler prices it: at whatever rate is configured
+    /// when it completes.
+    fn billed(
+        pricing: &PricingConfig,
+        namespace: &str,
+        model: &str,
+        start_time: DateTime<Utc>,
+        tokens: (i64, i64),
+    ) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            model.to_string(),
+            start_time,
+            "Tell me a story".to_string(),
+        );
+        record.namespace = Some(namespace.to_string());
+        record.input_tokens = tokens.0;
+        record.output_tokens = tokens.1;
+        if let Some(price) = pricing.price_for(Some(namespace), model) {
+            record.input_price_per_m = Some(price.input_per_m);
+            record.output_price_per_m = Some(price.output_per_m);
+        }
+        record
+    }
+
+    fn price(input_per_m: f64, output_per_m: f64) -> Price {
+        Price {
+            input_per_m,
+            output_per_m,
+        }
+    }
+
+    fn pricing(model_price: Price) -> PricingConfig {
+        PricingConfig {
+            model_prices: vec![("qwen*".to_string(), model_price)],
+            namespace_prices: vec![("research".to_string(), "qwen*".to_string(), price(0.5, 1.0))],
+            namespaces: vec![("10.1.*".to_string(), "team-a".to_string())],
+        }
+    }
+
+    #[test]
+    fn namespace_overrides_win_over_model_prices() {
+        let pricing = pricing(price(1.0, 2.0));
+
+        assert_eq!(pricing.price_for(Some("research"), "qwen-7b"), Some(price(0.5, 1.0)));
+        assert_eq!(pricing.price_for(Some("team-a"), "qwen-7b"), Some(price(1.0, 2.0)));
+        assert_eq!(pricing.price_for(None, "qwen-7b"), Some(price(1.0, 2.0)));
+        assert_eq!(pricing.price_for(Some("research"), "llama-3"), None);
+        assert_eq!(pricing.namespace_for("10.1.2.3"), Some("team-a"));
+        assert_eq!(pricing.namespace_for("192.168.0.1"), None);
+    }
+
+    #[tokio::test]
+    async fn a_mid_period_price_change_splits_the_line_and_keeps_old_charges() {
+        let pool = memory_pool().await;
+        let before = pricing(price(1.0, 2.0));
+        let after = pricing(price(3.0, 6.0));
+
+        for record in [
+            billed(&before, "team-a", "qwen-7b", at(3), (1_000_000, 500_000)),
+            billed(&before, "team-a", "qwen-7b", at(9), (1_000_000, 500_000)),
+            billed(&after, "team-a", "qwen-7b", at(20), (1_000_000, 1_000_000)),
+        ] {
+            insert_request(&pool, &record).await.unwrap();
+        }
+
+        let report = get_chargeback(&pool, "team-a", START, END).await.unwrap();
+
+        assert_eq!(report.lines.len(), 2);
+        let old = &report.lines[0];
+        assert_eq!(old.input_price_per_m, Some(1.0));
+        assert_eq!(old.requests, 2);
+        assert_eq!(old.input_cost, Some(2.0));
+        assert_eq!(old.output_cost, Some(2.0));
+        assert_eq!(old.total, Some(4.0));
+        assert_eq!(old.first_request, at(3).to_rfc3339());
+        assert_eq!(old.last_request, at(9).to_rfc3339());
+
+        let new = &report.lines[1];
+        assert_eq!(new.input_price_per_m, Some(3.0));
+        assert_eq!(new.requests, 1);
+        assert_eq!(new.total, Some(9.0));
+
+        assert_eq!(report.total_requests, 3);
+        assert_eq!(report.total_input_tokens, 3_000_000);
+        assert_eq!(report.grand_total, 13.0);
+        assert_eq!(report.unpriced_requests, 0);
+    }
+
+    #[tokio::test]
+    async fn only_successful_priced_requests_in_the_namespace_and_period_are_charged() {
+        let pool = memory_pool().await;
+        let pricing = pricing(price(1.0, 2.0));
+
+        let mut failed = billed(&pricing, "team-a", "qwen-7b", at(5), (1_000_000, 0));
+        failed.is_error = true;
+        let outside = billed(
+            &pricing,
+            "team-a",
+            "qwen-7b",
+            Utc.with_ymd_and_hms(2026, 4, 1, 0, 0, 0).unwrap(),
+            (1_000_000, 0),
+        );
+        for record in [
+            billed(&pricing, "team-a", "qwen-7b", at(5), (1_000_000, 0)),
+            billed(&pricing, "team-a", "llama-3", at(6), (2_000, 1_000)),
+            billed(&pricing, "research", "qwen-7b", at(5), (1_000_000, 0)),
+            failed,
+            outside,
+        ] {
+            insert_request(&pool, &record).await.unwrap();
+        }
+
+        let report = get_chargeback(&pool, "team-a", START, END).await.unwrap();
+
+        assert_eq!(report.total_requests, 2);
+        assert_eq!(report.grand_total, 1.0);
+        assert_eq!(report.unpriced_requests, 1);
+        let unpriced = report.lines.iter().find(|line| line.model == "llama-3").unwrap();
+        assert_eq!(unpriced.input_cost, None);
+        assert_eq!(unpriced.total, None);
+
+        let research = get_chargeback(&pool, "research", START, END).await.unwrap();
+        assert_eq!(research.grand_total, 0.5);
+    }
+
+    #[tokio::test]
+    async fn csv_has_a_row_per_rate_and_a_total() {
+        let pool = memory_pool().await;
+        let before = pricing(price(1.0, 2.0));
+        let after = pricing(price(3.0, 6.0));
+        for record in [
+            billed(&before, "team-a", "qwen-7b", at(3), (1_000_000, 0)),
+            billed(&after, "team-a", "qwen-7b", at(20), (1_000_000, 0)),
+        ] {
+            insert_request(&pool, &record).await.unwrap();
+        }
+
+        let report = get_chargeback(&pool, "team-a", START, END).await.unwrap();
+        let csv = crate::export::chargeback_csv(&report);
+        let rows: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(rows.len(), 4);
+        assert!(rows[0].starts_with("namespace,period_start,period_end,model,"));
+        let prefix = format!("team-a,{},{}", START, END);
+        assert_eq!(rows[1], format!("{},qwen-7b,1,2,1,1000000,0,1,0,1", prefix));
+        assert_eq!(rows[2], format!("{},qwen-7b,3,6,1,1000000,0,3,0,3", prefix));
+        assert_eq!(rows[3], format!("{},TOTAL,,,2,2000000,0,,,4", prefix));
+    }
+}
@@ -0,0 +1,89 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use super::models::TERMINATION_ABANDONED;
+
+/// Usage of one client, identified by its address together with its User-Agent.
+#[derive(Debug, Serialize)]
+pub struct ClientStats {
+    /// `None` for requests logged before clients were recorded
+    pub client_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub requests: i64,
+    pub errors: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub total_tokens: i64,
+    pub first_request: String,
+    pub last_request: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientReport {
+    pub since: Option<String>,
+    /// Busiest first
+    pub clients: Vec<ClientStats>,
+}
+
+/// Usage per client from `since` onward, with the same filters as `/stats/by-endpoint`.
+pub async fn get_client_stats(
+    pool: &SqlitePool,
+    since: Option<&str>,
+    include_abandoned: bool,
+    exclude_batches: bool,
+    include_benchmarks: bool,
+    limit: i64,
+) -> Result<ClientReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            client_id,
+            MAX(client_ip) as client_ip,
+            MAX(user_agent) as user_agent,
+            COUNT(*) as requests,
+            SUM(CASE WHEN is_error = 1 THEN 1 ELSE 0 END) as errors,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens,
+            COALESCE(SUM(total_tokens), 0) as total_tokens,
+            MIN(start_time) as first_request,
+            MAX(start_time) as last_request
+        FROM requests
+        WHERE (?1 IS NULL OR start_time >= ?1)
+          AND (?2 OR termination IS NOT ?3) AND (NOT ?4 OR batch_id IS NULL)
+          AND (?5 OR benchmark_id IS NULL)
+        GROUP BY client_id
+        ORDER BY requests DESC, last_request DESC
+        LIMIT ?6
+        "#,
+    )
+    .bind(since)
+    .bind(include_abandoned)
+    .bind(TERMINATION_ABANDONED)
+    .bind(exclude_batches)
+    .bind(include_benchmarks)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    let mut clients = Vec::new();
+    for row in rows {
+        clients.push(ClientStats {
+            client_id: row.try_get("client_id")?,
+            client_ip: row.try_get("client_ip")?,
+            user_agent: row.try_get("user_agent")?,
+            requests: row.try_get("requests")?,
+            errors: row.try_get("errors")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+            total_tokens: row.try_get("total_tokens")?,
+            first_request: row.try_get("first_request")?,
+            last_request: row.try_get("last_request")?,
+        });
+    }
+
+    Ok(ClientReport {
+        since: since.map(str::to_string),
+        clients,
+    })
+}
@@ -0,0 +1,207 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Inclusive upper bounds of the overflow-size buckets, in tokens
+const OVERFLOW_BUCKETS: [i64; 4] = [256, 1024, 4096, 16384];
+
+#[derive(Debug, Serialize)]
+pub struct OverflowBucket {
+    /// Upper bound of the bucket; `None` for the open-ended last bucket
+    pub le: Option<i64>,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContextFit {
+    pub requests: i64,
+    pub fits: i64,
+    pub overflows: i64,
+    /// Requests without a recorded input token count
+    pub unknown: i64,
+    /// Share of requests with known token counts that fit; `None` when none are known
+    pub fit_ratio: Option<f64>,
+    /// 95% Wilson score interval for `fit_ratio`
+    pub fit_ratio_ci95: Option<[f64; 2]>,
+    pub max_overflow_tokens: i64,
+    pub overflow_distribution: Vec<OverflowBucket>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelContextFit {
+    pub model: String,
+    #[serde(flatten)]
+    pub fit: ContextFit,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientContextFit {
+    pub client: String,
+    #[serde(flatten)]
+    pub fit: ContextFit,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContextFitReport {
+    pub candidate_context: i64,
+    pub since: Option<String>,
+    pub overall: ContextFit,
+    pub by_model: Vec<ModelContextFit>,
+    pub by_client: Vec<ClientContextFit>,
+}
+
+#[derive(Default)]
+struct FitTally {
+    fits: i64,
+    overflows: i64,
+    unknown: i64,
+    max_overflow: i64,
+    buckets: [i64; OVERFLOW_BUCKETS.len() + 1],
+}
+
+impl FitTally {
+    fn add(&mut self, required: Option<i64>, candidate_context: i64, count: i64) {
+        let Some(required) = required else {
+            self.unknown += count;
+            return;
+        };
+
+        if required <= candidate_context {
+            self.fits += count;
+            return;
+        }
+
+        let overflow = required - candidate_context;
+        let bucket = OVERFLOW_BUCKETS
+            .iter()
+            .position(|&edge| overflow <= edge)
+            .unwrap_or(OVERFLOW_BUCKETS.len());
+        self.overflows += count;
+        self.buckets[bucket] += count;
+        self.max_overflow = self.max_overflow.max(overflow);
+    }
+
+    fn finish(self) -> ContextFit {
+        let known = self.fits + self.overflows;
+        let (fit_ratio, fit_ratio_ci95) = if known > 0 {
+            (
+                Some(self.fits as f64 / known as f64),
+                Some(wilson_interval(self.fits, known)),
+            )
+        } else {
+            (None, None)
+        };
+
+        let overflow_distribution = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(index, &count)| OverflowBucket {
+                le: OVERFLOW_BUCKETS.get(index).copied(),
+                count,
+            })
+            .collect();
+
+        ContextFit {
+            requests: known + self.unknown,
+            fits: self.fits,
+            overflows: self.overflows,
+            unknown: self.unknown,
+            fit_ratio,
+            fit_ratio_ci95,
+            max_overflow_tokens: self.max_overflow,
+            overflow_distribution,
+        }
+    }
+}
+
+fn wilson_interval(successes: i64, trials: i64) -> [f64; 2] {
+    const Z: f64 = 1.96;
+    let n = trials as f64;
+    let p = successes as f64 / n;
+    let denominator = 1.0 + Z * Z / n;
+    let centre = p + Z * Z / (2.0 * n);
+    let spread = Z * (p * (1.0 - p) / n + Z * Z / (4.0 * n * n)).sqrt();
+    [
+        ((centre - spread) / denominator).max(0.0),
+        ((centre + spread) / denominator).min(1.0),
+    ]
+}
+
+/// Reports how many recorded requests would have fit within `candidate_context` tokens.
+///
+/// The context a request needs is its input tokens plus the requested `max_tokens`,
+/// falling back to the tokens actually generated when no ceiling was sent. Rows
+/// without an input token count are reported as unknown rather than assumed to fit.
+pub async fn get_context_fit(
+    pool: &SqlitePool,
+    candidate_context: i64,
+    since: Option<&str>,
+) -> Result<ContextFitReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            model,
+            COALESCE(client_id, 'unknown') as client,
+            CASE
+                WHEN input_tokens <= 0 THEN NULL
+                ELSE input_tokens + COALESCE(max_tokens, output_tokens)
+            END as required_tokens,
+            COUNT(*) as requests
+        FROM requests
+        WHERE (?1 IS NULL OR start_time >= ?1)
+        GROUP BY model, client, required_tokens
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut overall = FitTally::default();
+    let mut by_model: HashMap<String, FitTally> = HashMap::new();
+    let mut by_client: HashMap<String, FitTally> = HashMap::new();
+
+    for row in rows {
+        let model: String = row.try_get("model")?;
+        let client: String = row.try_get("client")?;
+        let required: Option<i64> = row.try_get("required_tokens")?;
+        let count: i64 = row.try_get("requests")?;
+
+        overall.add(required, candidate_context, count);
+        by_model
+            .entry(model)
+            .or_default()
+            .add(required, candidate_context, count);
+        by_client
+            .entry(client)
+            .or_default()
+            .add(required, candidate_context, count);
+    }
+
+    let mut by_model: Vec<ModelContextFit> = by_model
+        .into_iter()
+        .map(|(model, tally)| ModelContextFit {
+            model,
+            fit: tally.finish(),
+        })
+        .collect();
+    by_model.sort_by_key(|entry| Reverse(entry.fit.requests));
+
+    let mut by_client: Vec<ClientContextFit> = by_client
+        .into_iter()
+        .map(|(client, tally)| ClientContextFit {
+            client,
+            fit: tally.finish(),
+        })
+        .collect();
+    by_client.sort_by_key(|entry| Reverse(entry.fit.requests));
+
+    Ok(ContextFitReport {
+        candidate_context,
+        since: since.map(|s| s.to_string()),
+        overall: overall.finish(),
+        by_model,
+        by_client,
+    })
+}
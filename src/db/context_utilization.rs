@@ -0,0 +1,149 @@
+//! How much of each model's context window its prompts fill, from the `context_length`
+//! stored on each request, so changing `MODEL_CONTEXT` never rewrites past figures.
+
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// Share of the context window above which a prompt counts as close to filling it
+pub const HIGH_UTILIZATION: f64 = 0.9;
+
+/// Prompt size relative to the context window for one model with a configured window.
+#[derive(Debug, Serialize)]
+pub struct ModelUtilization {
+    pub model: String,
+    pub requests: i64,
+    /// The window stored on the model's latest request
+    pub context_length: i64,
+    /// Input tokens over the context window, as a fraction
+    pub avg_utilization: f64,
+    pub p95_utilization: f64,
+    pub max_utilization: f64,
+    /// Requests whose prompt took more than 90% of the window
+    pub over_90_pct: i64,
+}
+
+/// Prompt sizes for a model whose requests were logged without a context window.
+#[derive(Debug, Serialize)]
+pub struct UnconfiguredModel {
+    pub model: String,
+    pub requests: i64,
+    pub avg_input_tokens: f64,
+    pub max_input_tokens: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContextUtilizationReport {
+    pub since: Option<String>,
+    /// Highest p95 first
+    pub by_model: Vec<ModelUtilization>,
+    /// Most requests first; a model appears in both lists when only some of its
+    /// requests had a window
+    pub unconfigured: Vec<UnconfiguredModel>,
+}
+
+#[derive(Default)]
+struct Tally {
+    context_length: i64,
+    utilizations: Vec<f64>,
+}
+
+impl Tally {
+    fn finish(self, model: String) -> ModelUtilization {
+        let utilizations = self.utilizations;
+        let requests = utilizations.len() as i64;
+        let rank = ((utilizations.len() as f64) * 0.95).ceil() as usize;
+        ModelUtilization {
+            model,
+            requests,
+            context_length: self.context_length,
+            avg_utilization: round(utilizations.iter().sum::<f64>() / requests as f64),
+            p95_utilization: round(utilizations[rank.clamp(1, utilizations.len()) - 1]),
+            max_utilization: round(utilizations.last().copied().unwrap_or_default()),
+            over_90_pct: utilizations
+                .iter()
+                .filter(|utilization| **utilization > HIGH_UTILIZATION)
+                .count() as i64,
+        }
+    }
+}
+
+/// Context utilization per model of successful requests from `since` onward that
+/// reported input tokens. Warm-up probes are left out.
+pub async fn get_context_utilization(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<ContextUtilizationReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            model,
+            CAST(input_tokens AS REAL) / context_length as utilization,
+            context_length
+        FROM requests
+        WHERE context_length IS NOT NULL AND is_error = 0 AND input_tokens > 0
+          AND is_probe = 0 AND (?1 IS NULL OR start_time >= ?1)
+        ORDER BY model, utilization, id
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut by_model = Vec::new();
+    let mut current: Option<(String, Tally)> = None;
+    for row in rows {
+        let model: String = row.try_get("model")?;
+        if current.as_ref().is_none_or(|(name, _)| *name != model) {
+            if let Some((name, tally)) = current.take() {
+                by_model.push(tally.finish(name));
+            }
+            current = Some((model, Tally::default()));
+        }
+        if let Some((_, tally)) = current.as_mut() {
+            tally.utilizations.push(row.try_get("utilization")?);
+            tally.context_length = row.try_get("context_length")?;
+        }
+    }
+    if let Some((name, tally)) = current {
+        by_model.push(tally.finish(name));
+    }
+    by_model.sort_by(|a, b| b.p95_utilization.total_cmp(&a.p95_utilization));
+
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            model,
+            COUNT(*) as requests,
+            AVG(CAST(input_tokens AS REAL)) as avg_input_tokens,
+            MAX(input_tokens) as max_input_tokens
+        FROM requests
+        WHERE context_length IS NULL AND is_error = 0 AND input_tokens > 0
+          AND is_probe = 0 AND (?1 IS NULL OR start_time >= ?1)
+        GROUP BY model
+        ORDER BY requests DESC, model
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut unconfigured = Vec::new();
+    for row in rows {
+        unconfigured.push(UnconfiguredModel {
+            model: row.try_get("model")?,
+            requests: row.try_get("requests")?,
+            avg_input_tokens: (row.try_get::<f64, _>("avg_input_tokens")? * 10.0).round() / 10.0,
+            max_input_tokens: row.try_get("max_input_tokens")?,
+        });
+    }
+
+    Ok(ContextUtilizationReport {
+        since: since.map(str::to_string),
+        by_model,
+        unconfigured,
+    })
+}
+
+fn round(value: f64) -> f64 {
+    (value * 10_000.0).round() / 10_000.0
+}
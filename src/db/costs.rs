@@ -0,0 +1,97 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use super::chargeback::round_cost;
+
+/// Equivalent API cost of one model's successful requests.
+#[derive(Debug, Serialize)]
+pub struct ModelCost {
+    pub model: String,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    /// `None` when none of the model's requests were logged with a price
+    pub input_cost: Option<f64>,
+    pub output_cost: Option<f64>,
+    pub estimated_cost: Option<f64>,
+    /// Requests logged without a price, and so missing from the costs
+    pub unpriced_requests: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CostReport {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub currency: &'static str,
+    /// Most expensive first, then models without a price
+    pub models: Vec<ModelCost>,
+    pub total_requests: i64,
+    /// Sum over priced requests; `None` when there are none
+    pub total_cost: Option<f64>,
+    pub unpriced_requests: i64,
+}
+
+/// Successful requests started in `[from, to)`, priced per model at the rates stored on
+/// each request.
+pub async fn get_cost_report(
+    pool: &SqlitePool,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<CostReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            model,
+            COUNT(*) as requests,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens,
+            SUM(input_tokens * input_price_per_m) / 1e6 as input_cost,
+            SUM(output_tokens * output_price_per_m) / 1e6 as output_cost,
+            SUM(CASE WHEN input_price_per_m IS NULL THEN 1 ELSE 0 END) as unpriced_requests
+        FROM requests
+        WHERE is_error = 0
+          AND (?1 IS NULL OR start_time >= ?1)
+          AND (?2 IS NULL OR start_time < ?2)
+        GROUP BY model
+        "#,
+    )
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+
+    let mut models = Vec::new();
+    for row in rows {
+        let input_cost: Option<f64> = row.try_get("input_cost")?;
+        let output_cost: Option<f64> = row.try_get("output_cost")?;
+        models.push(ModelCost {
+            model: row.try_get("model")?,
+            requests: row.try_get("requests")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+            input_cost: input_cost.map(round_cost),
+            output_cost: output_cost.map(round_cost),
+            estimated_cost: input_cost
+                .zip(output_cost)
+                .map(|(input, output)| round_cost(input + output)),
+            unpriced_requests: row.try_get("unpriced_requests")?,
+        });
+    }
+    models.sort_by(|a, b| {
+        b.estimated_cost
+            .unwrap_or(-1.0)
+            .total_cmp(&a.estimated_cost.unwrap_or(-1.0))
+            .then_with(|| a.model.cmp(&b.model))
+    });
+
+    let priced: Vec<f64> = models.iter().filter_map(|m| m.estimated_cost).collect();
+    Ok(CostReport {
+        from: from.map(|s| s.to_string()),
+        to: to.map(|s| s.to_string()),
+        currency: "USD",
+        total_requests: models.iter().map(|m| m.requests).sum(),
+        total_cost: (!priced.is_empty()).then(|| round_cost(priced.iter().sum())),
+        unpriced_requests: models.iter().map(|m| m.unpriced_requests).sum(),
+        models,
+    })
+}
@@ -0,0 +1,172 @@
+use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool};
+
+use crate::counters::MinuteCount;
+
+#[derive(Debug, Clone)]
+pub struct MinuteCounterRow {
+    pub minute: String,
+    pub model: String,
+    pub count: MinuteCount,
+}
+
+pub struct MinuteCounterSnapshot {
+    pub rows: Vec<MinuteCounterRow>,
+    /// Minute of the most recent flush, if anything was ever persisted
+    pub last_flushed_minute: Option<String>,
+}
+
+/// Stores absolute per-minute counts in one statement; re-flushing a minute overwrites it.
+pub async fn upsert_minute_counters(
+    pool: &SqlitePool,
+    rows: &[MinuteCounterRow],
+    flushed_at: &str,
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    upsert_query(rows, flushed_at).build().execute(pool).await?;
+
+    Ok(())
+}
+
+/// Replaces every stored minute in `[from_minute, until_minute)` with `rows`, dropping
+/// stored models the rows don't mention.
+pub async fn replace_minute_counters(
+    pool: &SqlitePool,
+    from_minute: &str,
+    until_minute: &str,
+    rows: &[MinuteCounterRow],
+    flushed_at: &str,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM minute_counters WHERE minute >= ? AND minute < ?")
+        .bind(from_minute)
+        .bind(until_minute)
+        .execute(&mut *tx)
+        .await?;
+    if !rows.is_empty() {
+        upsert_query(rows, flushed_at).build().execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+fn upsert_query<'a>(rows: &'a [MinuteCounterRow], flushed_at: &'a str) -> QueryBuilder<'a, Sqlite> {
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(
+        "INSERT INTO minute_counters (minute, model, requests, input_tokens, output_tokens, flushed_at) ",
+    );
+    query.push_values(rows, |mut values, row| {
+        values
+            .push_bind(&row.minute)
+            .push_bind(&row.model)
+            .push_bind(row.count.requests)
+            .push_bind(row.count.input_tokens)
+            .push_bind(row.count.output_tokens)
+            .push_bind(flushed_at);
+    });
+    query.push(
+        r#"
+        ON CONFLICT (minute, model) DO UPDATE SET
+            requests = excluded.requests,
+            input_tokens = excluded.input_tokens,
+            output_tokens = excluded.output_tokens,
+            flushed_at = excluded.flushed_at
+        "#,
+    );
+    query
+}
+
+/// Stored counters for minutes in `[from_minute, until_minute)`.
+pub async fn get_minute_counters(
+    pool: &SqlitePool,
+    from_minute: &str,
+    until_minute: &str,
+) -> Result<Vec<MinuteCounterRow>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT minute, model, requests, input_tokens, output_tokens
+        FROM minute_counters
+        WHERE minute >= ? AND minute < ?
+        "#,
+    )
+    .bind(from_minute)
+    .bind(until_minute)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter().map(counter_row).collect()
+}
+
+/// Loads persisted counters from `since_minute` on, discarding older minutes.
+pub async fn load_minute_counters(
+    pool: &SqlitePool,
+    since_minute: &str,
+) -> Result<MinuteCounterSnapshot, sqlx::Error> {
+    sqlx::query("DELETE FROM minute_counters WHERE minute < ?")
+        .bind(since_minute)
+        .execute(pool)
+        .await?;
+
+    let rows = sqlx::query(
+        r#"
+        SELECT minute, model, requests, input_tokens, output_tokens
+        FROM minute_counters
+        WHERE minute >= ?
+        "#,
+    )
+    .bind(since_minute)
+    .fetch_all(pool)
+    .await?;
+
+    let last_flushed_minute: Option<String> =
+        sqlx::query_scalar("SELECT MAX(flushed_at) FROM minute_counters")
+            .fetch_one(pool)
+            .await?;
+
+    Ok(MinuteCounterSnapshot {
+        rows: rows
+            .iter()
+            .map(counter_row)
+            .collect::<Result<Vec<_>, sqlx::Error>>()?,
+        last_flushed_minute,
+    })
+}
+
+/// Recomputes per-minute counts from the requests table, keyed by completion minute.
+pub async fn count_requests_by_minute(
+    pool: &SqlitePool,
+    since_minute: &str,
+) -> Result<Vec<MinuteCounterRow>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            substr(end_time, 1, 16) as minute,
+            model,
+            COUNT(*) as requests,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens
+        FROM requests
+        WHERE substr(end_time, 1, 16) >= ?
+        GROUP BY minute, model
+        "#,
+    )
+    .bind(since_minute)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter().map(counter_row).collect()
+}
+
+fn counter_row(row: &sqlx::sqlite::SqliteRow) -> Result<MinuteCounterRow, sqlx::Error> {
+    Ok(MinuteCounterRow {
+        minute: row.try_get("minute")?,
+        model: row.try_get("model")?,
+        count: MinuteCount {
+            requests: row.try_get("requests")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+        },
+    })
+}
@@ -0,0 +1,221 @@
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Row, SqlitePool};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Characters of each output compared for the similarity score
+const MAX_COMPARE_CHARS: i64 = 8000;
+
+/// Repeats of one seeded prompt with the same model and sampling parameters.
+#[derive(Debug, Serialize)]
+pub struct SeedGroup {
+    pub model: String,
+    pub seed: i64,
+    pub sampling_params: Option<Value>,
+    pub prompt_hash: String,
+    pub requests: usize,
+    pub distinct_outputs: usize,
+    pub deterministic: bool,
+    /// Lowest word-level similarity (0-1) of a repeat to the first output
+    pub min_similarity: f64,
+    /// The earliest request, the one the others are compared against
+    pub reference_request_id: Option<String>,
+    pub differing_request_ids: Vec<String>,
+    pub system_fingerprints: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelDeterminism {
+    pub model: String,
+    pub groups: usize,
+    pub deterministic_groups: usize,
+    pub varying_groups: usize,
+    /// Groups that varied although every repeat reported the same backend fingerprint
+    pub unexplained_varying_groups: usize,
+    pub system_fingerprints: Vec<String>,
+    /// Set when seeded repeats varied without a backend change to explain it
+    pub flagged: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeterminismReport {
+    pub since: Option<String>,
+    pub groups_checked: usize,
+    pub varying_groups: usize,
+    pub by_model: Vec<ModelDeterminism>,
+    /// Only the groups whose outputs differed, least similar first
+    pub varying: Vec<SeedGroup>,
+}
+
+struct Repeat {
+    proxy_request_id: Option<String>,
+    output_hash: String,
+    output: String,
+    system_fingerprint: Option<String>,
+}
+
+/// Groups successful seeded requests by model, seed, sampling parameters and prompt, and
+/// checks whether repeats produced the same output.
+pub async fn get_determinism(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<DeterminismReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        WITH repeated AS (
+            SELECT model, seed, sampling_params, prompt_hash
+            FROM requests
+            WHERE seed IS NOT NULL AND is_error = 0 AND prompt_hash IS NOT NULL
+              AND output_hash IS NOT NULL AND (?1 IS NULL OR start_time >= ?1)
+            GROUP BY model, seed, sampling_params, prompt_hash
+            HAVING COUNT(*) > 1
+        )
+        SELECT
+            r.model,
+            r.seed,
+            r.sampling_params,
+            r.prompt_hash,
+            r.proxy_request_id,
+            r.output_hash,
+            substr(r.output_text, 1, ?2) as output,
+            r.system_fingerprint
+        FROM request_rows r
+        JOIN repeated g
+          ON g.model = r.model AND g.seed = r.seed AND g.prompt_hash = r.prompt_hash
+         AND g.sampling_params IS r.sampling_params
+        WHERE r.is_error = 0 AND r.output_hash IS NOT NULL AND (?1 IS NULL OR r.start_time >= ?1)
+        ORDER BY r.model, r.seed, r.prompt_hash, r.start_time
+        "#,
+    )
+    .bind(since)
+    .bind(MAX_COMPARE_CHARS)
+    .fetch_all(pool)
+    .await?;
+
+    let mut groups: BTreeMap<(String, i64, Option<String>, String), Vec<Repeat>> = BTreeMap::new();
+    for row in &rows {
+        let key = (
+            row.try_get("model")?,
+            row.try_get("seed")?,
+            row.try_get("sampling_params")?,
+            row.try_get("prompt_hash")?,
+        );
+        groups.entry(key).or_default().push(Repeat {
+            proxy_request_id: row.try_get("proxy_request_id")?,
+            output_hash: row.try_get("output_hash")?,
+            output: row.try_get("output")?,
+            system_fingerprint: row.try_get("system_fingerprint")?,
+        });
+    }
+
+    let mut by_model: BTreeMap<String, ModelDeterminism> = BTreeMap::new();
+    let mut varying = Vec::new();
+    let groups_checked = groups.len();
+    for ((model, seed, sampling_params, prompt_hash), repeats) in groups {
+        let group = compare(model, seed, sampling_params, prompt_hash, &repeats);
+        let summary = by_model
+            .entry(group.model.clone())
+            .or_insert_with(|| ModelDeterminism {
+                model: group.model.clone(),
+                groups: 0,
+                deterministic_groups: 0,
+                varying_groups: 0,
+                unexplained_varying_groups: 0,
+                system_fingerprints: Vec::new(),
+                flagged: false,
+            });
+        summary.groups += 1;
+        for fingerprint in &group.system_fingerprints {
+            if !summary.system_fingerprints.contains(fingerprint) {
+                summary.system_fingerprints.push(fingerprint.clone());
+            }
+        }
+        if group.deterministic {
+            summary.deterministic_groups += 1;
+        } else {
+            summary.varying_groups += 1;
+            // A single fingerprint (or none reported) means the backend didn't change
+            if group.system_fingerprints.len() <= 1 {
+                summary.unexplained_varying_groups += 1;
+                summary.flagged = true;
+            }
+            varying.push(group);
+        }
+    }
+    varying.sort_by(|a, b| a.min_similarity.total_cmp(&b.min_similarity));
+
+    Ok(DeterminismReport {
+        since: since.map(|s| s.to_string()),
+        groups_checked,
+        varying_groups: varying.len(),
+        by_model: by_model.into_values().collect(),
+        varying,
+    })
+}
+
+fn compare(
+    model: String,
+    seed: i64,
+    sampling_params: Option<String>,
+    prompt_hash: String,
+    repeats: &[Repeat],
+) -> SeedGroup {
+    let reference = &repeats[0];
+    let reference_words: Vec<&str> = reference.output.split_whitespace().collect();
+    let mut min_similarity = 1.0f64;
+    let mut differing_request_ids = Vec::new();
+    for repeat in &repeats[1..] {
+        if repeat.output_hash == reference.output_hash {
+            continue;
+        }
+        let words: Vec<&str> = repeat.output.split_whitespace().collect();
+        min_similarity = min_similarity.min(similarity(&reference_words, &words));
+        differing_request_ids.extend(repeat.proxy_request_id.clone());
+    }
+
+    let distinct_outputs = repeats
+        .iter()
+        .map(|repeat| repeat.output_hash.as_str())
+        .collect::<BTreeSet<_>>()
+        .len();
+    let system_fingerprints = repeats
+        .iter()
+        .filter_map(|repeat| repeat.system_fingerprint.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    SeedGroup {
+        model,
+        seed,
+        sampling_params: sampling_params.and_then(|params| serde_json::from_str(&params).ok()),
+        prompt_hash,
+        requests: repeats.len(),
+        distinct_outputs,
+        deterministic: distinct_outputs == 1,
+        min_similarity: (min_similarity * 1000.0).round() / 1000.0,
+        reference_request_id: reference.proxy_request_id.clone(),
+        differing_request_ids,
+        system_fingerprints,
+    }
+}
+
+/// `1 - edit distance / longer length`, over words.
+fn similarity(a: &[&str], b: &[&str]) -> f64 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, word_a) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, word_b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(word_a != word_b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
@@ -0,0 +1,72 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// Requests that sent the same prompt, after normalization.
+#[derive(Debug, Serialize)]
+pub struct DuplicatePrompt {
+    pub normalized_prompt_hash: String,
+    pub occurrences: i64,
+    /// Tokens spent across every occurrence
+    pub total_tokens: i64,
+    pub first_seen: String,
+    pub last_seen: String,
+    /// The earliest occurrence, to look the prompt up with `/stats/request/{id}`
+    pub sample_request_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateReport {
+    pub since: Option<String>,
+    /// Most occurrences first
+    pub duplicates: Vec<DuplicatePrompt>,
+}
+
+/// Prompts sent more than once from `since` onward. Warm-up probes and benchmark runs
+/// repeat their prompts by design and are left out.
+pub async fn get_duplicates(
+    pool: &SqlitePool,
+    since: Option<&str>,
+    limit: i64,
+) -> Result<DuplicateReport, sqlx::Error> {
+    // With MIN(id) in the select list SQLite takes the bare proxy_request_id from that row
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            normalized_prompt_hash,
+            COUNT(*) as occurrences,
+            COALESCE(SUM(total_tokens), 0) as total_tokens,
+            MIN(start_time) as first_seen,
+            MAX(start_time) as last_seen,
+            MIN(id) as first_id,
+            proxy_request_id
+        FROM requests
+        WHERE normalized_prompt_hash IS NOT NULL AND is_probe = 0 AND benchmark_id IS NULL
+          AND (?1 IS NULL OR start_time >= ?1)
+        GROUP BY normalized_prompt_hash
+        HAVING COUNT(*) > 1
+        ORDER BY occurrences DESC, total_tokens DESC
+        LIMIT ?2
+        "#,
+    )
+    .bind(since)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    let mut duplicates = Vec::new();
+    for row in rows {
+        duplicates.push(DuplicatePrompt {
+            normalized_prompt_hash: row.try_get("normalized_prompt_hash")?,
+            occurrences: row.try_get("occurrences")?,
+            total_tokens: row.try_get("total_tokens")?,
+            first_seen: row.try_get("first_seen")?,
+            last_seen: row.try_get("last_seen")?,
+            sample_request_id: row.try_get("proxy_request_id")?,
+        });
+    }
+
+    Ok(DuplicateReport {
+        since: since.map(str::to_string),
+        duplicates,
+    })
+}
@@ -0,0 +1,71 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+#[derive(Debug, Serialize)]
+pub struct ModelEnergy {
+    pub model: String,
+    /// Requests with a duration to estimate from
+    pub requests: i64,
+    pub estimated_kwh: f64,
+    pub estimated_co2_kg: f64,
+}
+
+/// Energy and CO2 estimates derived from configured power draw and request durations.
+#[derive(Debug, Serialize)]
+pub struct EnergyEstimate {
+    /// Watts assumed for models without an override
+    pub default_watts: Option<f64>,
+    pub grid_co2_g_per_kwh: f64,
+    pub requests: i64,
+    pub estimated_kwh: f64,
+    pub estimated_co2_kg: f64,
+    pub by_model: Vec<ModelEnergy>,
+}
+
+/// Totals the per-request energy estimates, converting to CO2 with `grid_co2_g_per_kwh`.
+///
+/// Requests logged without an estimate (no duration, or before power draw was
+/// configured) are left out.
+pub async fn get_energy_estimate(
+    pool: &SqlitePool,
+    default_watts: Option<f64>,
+    grid_co2_g_per_kwh: f64,
+) -> Result<EnergyEstimate, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            model,
+            COUNT(*) as requests,
+            SUM(estimated_energy_wh) as energy_wh
+        FROM requests
+        WHERE estimated_energy_wh IS NOT NULL
+        GROUP BY model
+        ORDER BY energy_wh DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let co2_kg = |kwh: f64| kwh * grid_co2_g_per_kwh / 1000.0;
+
+    let mut by_model = Vec::new();
+    for row in rows {
+        let kwh = row.try_get::<f64, _>("energy_wh")? / 1000.0;
+        by_model.push(ModelEnergy {
+            model: row.try_get("model")?,
+            requests: row.try_get("requests")?,
+            estimated_kwh: kwh,
+            estimated_co2_kg: co2_kg(kwh),
+        });
+    }
+
+    let estimated_kwh = by_model.iter().map(|m| m.estimated_kwh).sum();
+    Ok(EnergyEstimate {
+        default_watts,
+        grid_co2_g_per_kwh,
+        requests: by_model.iter().map(|m| m.requests).sum(),
+        estimated_kwh,
+        estimated_co2_kg: co2_kg(estimated_kwh),
+        by_model,
+    })
+}
@@ -0,0 +1,596 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool, sqlite::SqliteRow};
+
+use super::models::{FAILURE_BODY_READ, FAILURE_FORWARD, TERMINATION_ABANDONED};
+
+/// Source reported for errors logged before the source was recorded
+pub const SOURCE_UNKNOWN: &str = "unknown";
+
+/// Most characters of each recent error message returned
+pub const MAX_MESSAGE_CHARS: i64 = 300;
+
+/// Normalised kind of failure. Requests logged before `status_source` existed are
+/// classified from their message and status alone.
+const CATEGORY_SQL: &str = r#"
+    CASE
+        WHEN error_message LIKE 'LM Studio connection error%' THEN 'connection_error'
+        WHEN error_message LIKE 'Failed to parse response%'
+            OR error_message LIKE 'JSON parsing error%' THEN 'parse_error'
+        WHEN http_status = 499 THEN 'abandoned'
+        WHEN status_source IS 'proxy' AND http_status = 504 THEN 'timeout'
+        WHEN status_source IS 'proxy' AND http_status BETWEEN 400 AND 499 THEN 'bad_request'
+        WHEN status_source IS 'proxy' THEN 'proxy_error'
+        WHEN http_status BETWEEN 400 AND 499 THEN 'upstream_4xx'
+        WHEN http_status BETWEEN 500 AND 599 THEN 'upstream_5xx'
+        ELSE 'other'
+    END
+"#;
+
+/// Which failed requests a report covers.
+#[derive(Debug)]
+pub struct ErrorFilter<'a> {
+    /// RFC 3339 bounds on `start_time`, `to` exclusive
+    pub from: Option<&'a str>,
+    pub to: Option<&'a str>,
+    pub include_abandoned: bool,
+    /// How many of the latest messages to return
+    pub recent: i64,
+}
+
+/// Failed requests with one status class from one source, e.g. upstream `5xx`.
+#[derive(Debug, Serialize)]
+pub struct SourceClassErrors {
+    /// `upstream`, `proxy` or `unknown`
+    pub source: String,
+    /// `4xx`, `5xx`, ...
+    pub status_class: String,
+    pub errors: i64,
+}
+
+/// Failed requests of one category with one exact status.
+#[derive(Debug, Serialize)]
+pub struct CategoryErrors {
+    /// `connection_error`, `parse_error`, `upstream_4xx`, `upstream_5xx`, `timeout`,
+    /// `bad_request`, `abandoned`, `proxy_error` or `other`
+    pub category: String,
+    pub source: String,
+    pub http_status: i32,
+    pub errors: i64,
+    pub last_seen: String,
+}
+
+/// Failed requests that failed at one stage of proxying.
+#[derive(Debug, Serialize)]
+pub struct StageErrors {
+    /// `body_read`, `forward`, `upstream_error` or `parse`
+    pub stage: String,
+    pub errors: i64,
+    pub last_seen: String,
+}
+
+/// Failed requests of one model, or one client, by source and status class.
+#[derive(Debug, Serialize)]
+pub struct GroupErrors {
+    /// The model or the client id
+    pub key: String,
+    pub source: String,
+    pub status_class: String,
+    pub errors: i64,
+}
+
+/// One of the latest failures, with its message cut to [`MAX_MESSAGE_CHARS`].
+#[derive(Debug, Serialize)]
+pub struct RecentError {
+    pub proxy_request_id: Option<String>,
+    pub start_time: String,
+    pub model: String,
+    pub category: String,
+    pub source: String,
+    pub http_status: i32,
+    pub message: Option<String>,
+    pub truncated: bool,
+}
+
+/// Requests of one model whose stream had chunks that could not be parsed. They
+/// usually succeeded, but their stored output is missing those chunks' content.
+#[derive(Debug, Serialize)]
+pub struct StreamParseErrors {
+    pub model: String,
+    /// Requests with at least one unparseable chunk
+    pub requests: i64,
+    /// Unparseable chunks across those requests
+    pub chunks: i64,
+    pub last_seen: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub total_errors: i64,
+    /// Most errors first
+    pub by_source: Vec<SourceClassErrors>,
+    pub by_category: Vec<CategoryErrors>,
+    /// Most errors first; proxy refusals and rows from before stages were recorded have
+    /// no stage and are left out
+    pub by_stage: Vec<StageErrors>,
+    /// Failures at `body_read` or `forward`: requests LM Studio never received or never
+    /// answered
+    pub never_reached_upstream: i64,
+    pub by_model: Vec<GroupErrors>,
+    pub by_client: Vec<GroupErrors>,
+    /// Newest first
+    pub recent: Vec<RecentError>,
+    /// Most affected requests first
+    pub stream_parse_errors: Vec<StreamParseErrors>,
+}
+
+/// Failed requests matching `filter`, cross-tabulated by who produced the status and its
+/// class, and grouped into categories.
+pub async fn get_error_report(
+    pool: &SqlitePool,
+    filter: &ErrorFilter<'_>,
+) -> Result<ErrorReport, sqlx::Error> {
+    let by_source = error_query(
+        pool,
+        filter,
+        r#"
+        SELECT
+            COALESCE(status_source, ?4) as source,
+            (http_status / 100) || 'xx' as status_class,
+            COUNT(*) as errors
+        FROM requests
+        WHERE {errors}
+        GROUP BY source, status_class
+        ORDER BY errors DESC, source, status_class
+        "#,
+    )
+    .await?
+    .iter()
+    .map(|row| {
+        Ok(SourceClassErrors {
+            source: row.try_get("source")?,
+            status_class: row.try_get("status_class")?,
+            errors: row.try_get("errors")?,
+        })
+    })
+    .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+    let by_category = error_query(
+        pool,
+        filter,
+        r#"
+        SELECT
+            {category} as category,
+            COALESCE(status_source, ?4) as source,
+            http_status,
+            COUNT(*) as errors,
+            MAX(start_time) as last_seen
+        FROM requests
+        WHERE {errors}
+        GROUP BY category, source, http_status
+        ORDER BY errors DESC, category, http_status
+        "#,
+    )
+    .await?
+    .iter()
+    .map(|row| {
+        Ok(CategoryErrors {
+            category: row.try_get("category")?,
+            source: row.try_get("source")?,
+            http_status: row.try_get("http_status")?,
+            errors: row.try_get("errors")?,
+            last_seen: row.try_get("last_seen")?,
+        })
+    })
+    .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+    let by_stage = error_query(
+        pool,
+        filter,
+        r#"
+        SELECT
+            failure_stage as stage,
+            COUNT(*) as errors,
+            MAX(start_time) as last_seen
+        FROM requests
+        WHERE {errors} AND failure_stage IS NOT NULL
+        GROUP BY failure_stage
+        ORDER BY errors DESC, failure_stage
+        "#,
+    )
+    .await?
+    .iter()
+    .map(|row| {
+        Ok(StageErrors {
+            stage: row.try_get("stage")?,
+            errors: row.try_get("errors")?,
+            last_seen: row.try_get("last_seen")?,
+        })
+    })
+    .collect::<Result<Vec<_>, sqlx::Error>>()?;
+    let never_reached_upstream = by_stage
+        .iter()
+        .filter(|group| group.stage == FAILURE_BODY_READ || group.stage == FAILURE_FORWARD)
+        .map(|group| group.errors)
+        .sum();
+
+    let by_model = get_group_errors(pool, filter, "model").await?;
+    let by_client = get_group_errors(pool, filter, "client_id").await?;
+
+    let recent = error_query(
+        pool,
+        filter,
+        r#"
+        SELECT
+            proxy_request_id,
+            start_time,
+            model,
+            {category} as category,
+            COALESCE(status_source, ?4) as source,
+            http_status,
+            substr(error_message, 1, ?6) as message,
+            length(error_message) > ?6 as truncated
+        FROM requests
+        WHERE {errors}
+        ORDER BY id DESC
+        LIMIT ?5
+        "#,
+    )
+    .await?
+    .iter()
+    .map(|row| {
+        Ok(RecentError {
+            proxy_request_id: row.try_get("proxy_request_id")?,
+            start_time: row.try_get("start_time")?,
+            model: row.try_get("model")?,
+            category: row.try_get("category")?,
+            source: row.try_get("source")?,
+            http_status: row.try_get("http_status")?,
+            message: row.try_get("message")?,
+            truncated: row.try_get::<Option<bool>, _>("truncated")?.unwrap_or(false),
+        })
+    })
+    .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+    let stream_parse_errors = get_stream_parse_errors(pool, filter).await?;
+
+    Ok(ErrorReport {
+        from: filter.from.map(|s| s.to_string()),
+        to: filter.to.map(|s| s.to_string()),
+        total_errors: by_source.iter().map(|group| group.errors).sum(),
+        by_source,
+        by_category,
+        by_stage,
+        never_reached_upstream,
+        by_model,
+        by_client,
+        recent,
+        stream_parse_errors,
+    })
+}
+
+/// Requests per model with unparseable stream chunks, failed or not, in the filter's range.
+async fn get_stream_parse_errors(
+    pool: &SqlitePool,
+    filter: &ErrorFilter<'_>,
+) -> Result<Vec<StreamParseErrors>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            model,
+            COUNT(*) as requests,
+            SUM(stream_parse_errors) as chunks,
+            MAX(start_time) as last_seen
+        FROM requests
+        WHERE stream_parse_errors > 0
+          AND (?1 IS NULL OR start_time >= ?1) AND (?2 IS NULL OR start_time < ?2)
+          AND (?3 OR termination IS NOT ?4)
+        GROUP BY model
+        ORDER BY requests DESC, model
+        "#,
+    )
+    .bind(filter.from)
+    .bind(filter.to)
+    .bind(filter.include_abandoned)
+    .bind(TERMINATION_ABANDONED)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(StreamParseErrors {
+                model: row.try_get("model")?,
+                requests: row.try_get("requests")?,
+                chunks: row.try_get("chunks")?,
+                last_seen: row.try_get("last_seen")?,
+            })
+        })
+        .collect()
+}
+
+/// Errors grouped by `column` (a fixed column name, never user input), source and class.
+async fn get_group_errors(
+    pool: &SqlitePool,
+    filter: &ErrorFilter<'_>,
+    column: &str,
+) -> Result<Vec<GroupErrors>, sqlx::Error> {
+    let sql = format!(
+        r#"
+        SELECT
+            COALESCE({column}, ?4) as key,
+            COALESCE(status_source, ?4) as source,
+            (http_status / 100) || 'xx' as status_class,
+            COUNT(*) as errors
+        FROM requests
+        WHERE {{errors}}
+        GROUP BY key, source, status_class
+        ORDER BY key, errors DESC, source, status_class
+        "#
+    );
+    let rows = error_query(pool, filter, &sql).await?;
+    rows.iter().map(group_from_row).collect()
+}
+
+fn group_from_row(row: &SqliteRow) -> Result<GroupErrors, sqlx::Error> {
+    Ok(GroupErrors {
+        key: row.try_get("key")?,
+        source: row.try_get("source")?,
+        status_class: row.try_get("status_class")?,
+        errors: row.try_get("errors")?,
+    })
+}
+
+/// Runs `sql` with `{errors}` replaced by the filter's conditions and `{category}` by the
+/// category expression. Every query binds the same parameters: `?1`/`?2` the time range,
+/// `?3` whether to include abandoned requests, `?4` the unknown source, `?5` the recent
+/// limit, `?6` the message length and `?7` the abandoned termination.
+async fn error_query(
+    pool: &SqlitePool,
+    filter: &ErrorFilter<'_>,
+    sql: &str,
+) -> Result<Vec<SqliteRow>, sqlx::Error> {
+    let sql = sql
+        .replace(
+            "{errors}",
+            "is_error = 1 AND (?1 IS NULL OR start_time >= ?1) AND (?2 IS NULL OR start_time < ?2)
+          AND (?3 OR termination IS NOT ?7)",
+        )
+        .replace("{category}", CATEGORY_SQL);
+    sqlx::query(&sql)
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(filter.include_abandoned)
+        .bind(SOURCE_UNKNOWN)
+        .bind(filter.recent)
+        .bind(MAX_MESSAGE_CHARS)
+        .bind(TERMINATION_ABANDONED)
+        .fetch_all(pool)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::FAILURE_PARSE;
+    use crate::db::testing::memory_pool;
+    use crate::db::{RequestRecord, insert_request};
+    use chrono::{TimeZone, Utc};
+
+    fn failed(model: &str, client: Option<&str>, message: &str, status: i32) -> RequestRecord {
+        let start = Utc.with_ymd_and_hms(2026, 5, 1, 12, 0, 0).unwrap();
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            model.to_string(),
+            start,
+            "Tell me a story".to_string(),
+        );
+        record.client_id = client.map(str::to_string);
+        record.set_error(start, message.to_string(), status);
+        record
+    }
+
+    fn upstream(model: &str, status: i32) -> RequestRecord {
+        let mut record = failed(model, Some("ci"), "", status);
+        record.set_upstream_error(Utc::now(), format!("LM Studio answered {}", status), status);
+        record
+    }
+
+    async fn report(pool: &SqlitePool, include_abandoned: bool) -> ErrorReport {
+        let filter = ErrorFilter {
+            from: None,
+            to: None,
+            include_abandoned,
+            recent: 3,
+        };
+        get_error_report(pool, &filter).await.unwrap()
+    }
+
+    /// One error of each source and status class, plus an abandoned request and a row
+    /// from before sources were recorded.
+    async fn seed(pool: &SqlitePool) {
+        let mut unreachable = failed("a", None, "LM Studio connection error: refused", 502);
+        unreachable.failure_stage = Some(FAILURE_FORWARD.to_string());
+        let mut unparseable = failed("a", None, "Failed to parse response: EOF", 502);
+        unparseable.failure_stage = Some(FAILURE_PARSE.to_string());
+        let mut legacy = failed("b", None, "old failure", 500);
+        legacy.status_source = None;
+        let mut abandoned = failed("b", None, "client went away", 499);
+        abandoned.termination = Some(TERMINATION_ABANDONED.to_string());
+        let long = failed("b", None, &"x".repeat(MAX_MESSAGE_CHARS as usize + 1), 400);
+
+        for record in [
+            upstream("a", 400),
+            upstream("a", 500),
+            upstream("b", 503),
+            failed("a", Some("ci"), "Deadline cannot be met", 504),
+            unreachable,
+            unparseable,
+            legacy,
+            abandoned,
+            long,
+        ] {
+            insert_request(pool, &record).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn every_source_and_class_is_cross_tabulated() {
+        let pool = memory_pool().await;
+        seed(&pool).await;
+
+        let report = report(&pool, false).await;
+
+        assert_eq!(report.total_errors, 8);
+        let by_source: Vec<_> = report
+            .by_source
+            .iter()
+            .map(|group| (group.source.as_str(), group.status_class.as_str(), group.errors))
+            .collect();
+        assert_eq!(
+            by_source,
+            [
+                ("proxy", "5xx", 3),
+                ("upstream", "5xx", 2),
+                ("proxy", "4xx", 1),
+                ("unknown", "5xx", 1),
+                ("upstream", "4xx", 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn categories_follow_source_status_and_message() {
+        let pool = memory_pool().await;
+        seed(&pool).await;
+
+        let report = report(&pool, true).await;
+
+        let mut categories: Vec<_> = report
+            .by_category
+            .iter()
+            .map(|group| (group.category.as_str(), group.source.as_str(), group.http_status))
+            .collect();
+        categories.sort();
+        assert_eq!(
+            categories,
+            [
+                ("abandoned", "proxy", 499),
+                ("bad_request", "proxy", 400),
+                ("connection_error", "proxy", 502),
+                ("parse_error", "proxy", 502),
+                ("timeout", "proxy", 504),
+                ("upstream_4xx", "upstream", 400),
+                ("upstream_5xx", "unknown", 500),
+                ("upstream_5xx", "upstream", 500),
+                ("upstream_5xx", "upstream", 503),
+            ]
+        );
+        assert_eq!(report.total_errors, 9);
+    }
+
+    #[tokio::test]
+    async fn stages_models_clients_and_recent_messages() {
+        let pool = memory_pool().await;
+        seed(&pool).await;
+
+        let report = report(&pool, false).await;
+
+        let stages: Vec<_> = report
+            .by_stage
+            .iter()
+            .map(|group| (group.stage.as_str(), group.errors))
+            .collect();
+        assert_eq!(stages, [("upstream_error", 3), ("forward", 1), ("parse", 1)]);
+        assert_eq!(report.never_reached_upstream, 1);
+
+        let model_b: Vec<_> = report
+            .by_model
+            .iter()
+            .filter(|group| group.key == "b")
+            .map(|group| (group.source.as_str(), group.status_class.as_str(), group.errors))
+            .collect();
+        assert_eq!(
+            model_b,
+            [("proxy", "4xx", 1), ("unknown", "5xx", 1), ("upstream", "5xx", 1)]
+        );
+        let client: Vec<_> = report
+            .by_client
+            .iter()
+            .filter(|group| group.key == "ci")
+            .map(|group| (group.source.as_str(), group.status_class.as_str(), group.errors))
+            .collect();
+        assert_eq!(
+            client,
+            [("upstream", "5xx", 2), ("proxy", "5xx", 1), ("upstream", "4xx", 1)]
+        );
+        // Requests without a client id are grouped under the unknown key
+        assert!(report.by_client.iter().any(|group| group.key == SOURCE_UNKNOWN));
+
+        // Newest first, without the abandoned request, long messages cut short
+        assert_eq!(report.recent.len(), 3);
+        let latest = &report.recent[0];
+        assert_eq!(latest.category, "bad_request");
+        assert!(latest.truncated);
+        assert_eq!(latest.message.as_ref().unwrap().len(), MAX_MESSAGE_CHARS as usize);
+        assert_eq!(report.recent[1].category, "upstream_5xx");
+        assert!(!report.recent[1].truncated);
+    }
+
+    #[tokio::test]
+    async fn time_range_bounds_start_times() {
+        let pool = memory_pool().await;
+        seed(&pool).await;
+        let filter = ErrorFilter {
+            from: Some("2026-05-01T12:00:01+00:00"),
+            to: None,
+            include_abandoned: true,
+            recent: 3,
+        };
+        let report = get_error_report(&pool, &filter).await.unwrap();
+        assert_eq!(report.total_errors, 0);
+        assert!(report.recent.is_empty());
+    }
+
+    #[tokio::test]
+    async fn streams_with_unparseable_chunks_are_counted_per_model() {
+        let pool = memory_pool().await;
+        let start = Utc.with_ymd_and_hms(2026, 5, 1, 12, 0, 0).unwrap();
+        for (model, parse_errors, minute) in [
+            ("a", Some(2), 0),
+            ("a", Some(1), 5),
+            ("b", Some(4), 3),
+            ("b", Some(0), 9),
+            ("b", None, 9),
+        ] {
+            let mut record = RequestRecord::new(
+                "/v1/chat/completions".to_string(),
+                model.to_string(),
+                start + chrono::Duration::minutes(minute),
+                "Tell me a story".to_string(),
+            );
+            record.stream_parse_errors = parse_errors;
+            insert_request(&pool, &record).await.unwrap();
+        }
+        // An abandoned stream only counts when asked for
+        let mut abandoned = failed("c", None, "client went away", 499);
+        abandoned.stream_parse_errors = Some(1);
+        abandoned.termination = Some(TERMINATION_ABANDONED.to_string());
+        insert_request(&pool, &abandoned).await.unwrap();
+
+        let finished = report(&pool, false).await;
+        let rows: Vec<_> = finished
+            .stream_parse_errors
+            .iter()
+            .map(|row| (row.model.as_str(), row.requests, row.chunks))
+            .collect();
+        assert_eq!(rows, [("a", 2, 3), ("b", 1, 4)]);
+        let last_seen = (start + chrono::Duration::minutes(5)).to_rfc3339();
+        assert_eq!(finished.stream_parse_errors[0].last_seen, last_seen);
+        // Parse errors alone don't make a request failed
+        assert_eq!(finished.total_errors, 0);
+
+        let with_abandoned = report(&pool, true).await;
+        assert_eq!(with_abandoned.stream_parse_errors.len(), 3);
+    }
+}
@@ -0,0 +1,77 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// How many stored output tokens are missing or guessed rather than reported by LM Studio.
+#[derive(Debug, Serialize)]
+pub struct EstimationGap {
+    pub since: Option<String>,
+    /// Requests that stored any output text
+    pub requests_with_output: i64,
+    /// Of those, requests stored with 0 output tokens: upstream sent no usage and there was
+    /// no estimate to fall back on, typically streams logged before estimates existed
+    pub zero_token_requests: i64,
+    /// Share of `requests_with_output`; `None` without any
+    pub zero_token_rate: Option<f64>,
+    /// Characters of output on the zero-token requests
+    pub zero_token_output_chars: i64,
+    /// Output tokens the local tokenizer counted on the zero-token requests, i.e. tokens
+    /// missing from the totals; `None` when none of them has been estimated yet
+    pub estimated_tokens_lost: Option<i64>,
+    /// Zero-token requests still waiting for the token estimate backfill
+    pub unestimated_requests: i64,
+    /// Requests whose token counts are the proxy's estimates because upstream sent no
+    /// usage. Enabling `stream_options.include_usage` in clients brings this down.
+    pub estimated_requests: i64,
+    pub estimated_output_tokens: i64,
+}
+
+/// Requests from `since` onward whose output tokens are missing or estimated.
+pub async fn get_estimation_gap(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<EstimationGap, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        WITH produced AS (
+            SELECT
+                output_tokens,
+                estimated_output_tokens,
+                tokens_estimated,
+                length(output_text) as output_chars
+            FROM request_rows
+            WHERE output_text != '' AND (?1 IS NULL OR start_time >= ?1)
+        )
+        SELECT
+            COUNT(*) as requests_with_output,
+            COALESCE(SUM(output_tokens = 0), 0) as zero_token_requests,
+            COALESCE(SUM(CASE WHEN output_tokens = 0 THEN output_chars END), 0)
+                as zero_token_output_chars,
+            SUM(CASE WHEN output_tokens = 0 THEN estimated_output_tokens END)
+                as estimated_tokens_lost,
+            COALESCE(SUM(output_tokens = 0 AND estimated_output_tokens IS NULL), 0)
+                as unestimated_requests,
+            COALESCE(SUM(tokens_estimated = 1 AND output_tokens > 0), 0) as estimated_requests,
+            COALESCE(SUM(CASE WHEN tokens_estimated = 1 THEN output_tokens END), 0)
+                as estimated_output_tokens
+        FROM produced
+        "#,
+    )
+    .bind(since)
+    .fetch_one(pool)
+    .await?;
+
+    let requests_with_output: i64 = row.try_get("requests_with_output")?;
+    let zero_token_requests: i64 = row.try_get("zero_token_requests")?;
+    Ok(EstimationGap {
+        since: since.map(str::to_string),
+        requests_with_output,
+        zero_token_requests,
+        zero_token_rate: (requests_with_output > 0)
+            .then(|| zero_token_requests as f64 / requests_with_output as f64),
+        zero_token_output_chars: row.try_get("zero_token_output_chars")?,
+        estimated_tokens_lost: row.try_get("estimated_tokens_lost")?,
+        unestimated_requests: row.try_get("unestimated_requests")?,
+        estimated_requests: row.try_get("estimated_requests")?,
+        estimated_output_tokens: row.try_get("estimated_output_tokens")?,
+    })
+}
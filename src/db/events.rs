@@ -0,0 +1,18 @@
+use serde_json::Value;
+use sqlx::SqlitePool;
+
+/// Records an action the proxy took on its own, such as unloading a model.
+pub async fn record_event(
+    pool: &SqlitePool,
+    kind: &str,
+    detail: &Value,
+) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query("INSERT INTO proxy_events (time, kind, detail) VALUES (?, ?, ?)")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(kind)
+        .bind(detail.to_string())
+        .execute(pool)
+        .await?;
+
+    Ok(result.last_insert_rowid())
+}
@@ -0,0 +1,65 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use tokio_stream::{Stream, StreamExt};
+
+use super::models::RequestRecord;
+
+/// A persisted request together with its row id.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredRequest {
+    pub id: i64,
+    #[serde(flatten)]
+    pub record: RequestRecord,
+}
+
+/// Which requests an export covers; fields left unset match everything.
+#[derive(Debug)]
+pub struct ExportFilter {
+    /// RFC 3339 start time, inclusive
+    pub from: Option<String>,
+    /// RFC 3339 start time, exclusive
+    pub to: Option<String>,
+    pub model: Option<String>,
+    pub include_errors: bool,
+}
+
+impl Default for ExportFilter {
+    fn default() -> Self {
+        Self {
+            from: None,
+            to: None,
+            model: None,
+            include_errors: true,
+        }
+    }
+}
+
+/// Streams the stored requests matching `filter` in id order without buffering the
+/// table in memory.
+pub fn stream_requests<'a>(
+    pool: &'a SqlitePool,
+    filter: &ExportFilter,
+) -> impl Stream<Item = Result<StoredRequest, sqlx::Error>> + 'a {
+    sqlx::query(
+        r#"
+        SELECT * FROM request_rows
+        WHERE (?1 IS NULL OR start_time >= ?1)
+            AND (?2 IS NULL OR start_time < ?2)
+            AND (?3 IS NULL OR model = ?3)
+            AND (?4 OR is_error = 0)
+        ORDER BY id
+        "#,
+    )
+    .bind(filter.from.clone())
+    .bind(filter.to.clone())
+    .bind(filter.model.clone())
+    .bind(filter.include_errors)
+    .fetch(pool)
+    .map(|row| {
+        let row = row?;
+        Ok(StoredRequest {
+            id: row.try_get("id")?,
+            record: RequestRecord::from_row(&row)?,
+        })
+    })
+}
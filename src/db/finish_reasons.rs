@@ -0,0 +1,107 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::BTreeMap;
+
+/// Reported for requests whose response carried no finish reason
+pub const UNKNOWN_FINISH_REASON: &str = "unknown";
+
+#[derive(Debug, Serialize)]
+pub struct FinishReasonCount {
+    pub finish_reason: String,
+    pub requests: i64,
+    /// Share of the group's requests
+    pub rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelFinishReasons {
+    pub model: String,
+    pub requests: i64,
+    /// Share of requests cut off by their token limit (`length`)
+    pub length_rate: f64,
+    /// Most common first
+    pub reasons: Vec<FinishReasonCount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FinishReasonReport {
+    pub since: Option<String>,
+    pub requests: i64,
+    pub reasons: Vec<FinishReasonCount>,
+    /// Highest `length_rate` first
+    pub models: Vec<ModelFinishReasons>,
+}
+
+/// Counts successful requests since `since` by model and finish reason, leaving out
+/// benchmark runs.
+pub async fn get_finish_reasons(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<FinishReasonReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT model, COALESCE(finish_reason, ?1) as finish_reason, COUNT(*) as requests
+        FROM requests
+        WHERE is_error = 0 AND benchmark_id IS NULL AND (?2 IS NULL OR start_time >= ?2)
+        GROUP BY model, 2
+        "#,
+    )
+    .bind(UNKNOWN_FINISH_REASON)
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut overall: BTreeMap<String, i64> = BTreeMap::new();
+    let mut by_model: BTreeMap<String, BTreeMap<String, i64>> = BTreeMap::new();
+    for row in rows {
+        let model: String = row.try_get("model")?;
+        let reason: String = row.try_get("finish_reason")?;
+        let requests: i64 = row.try_get("requests")?;
+        *overall.entry(reason.clone()).or_default() += requests;
+        *by_model
+            .entry(model)
+            .or_default()
+            .entry(reason)
+            .or_default() += requests;
+    }
+
+    let mut models: Vec<ModelFinishReasons> = by_model
+        .into_iter()
+        .map(|(model, counts)| {
+            let requests = counts.values().sum();
+            let length = counts.get("length").copied().unwrap_or(0);
+            ModelFinishReasons {
+                model,
+                requests,
+                length_rate: length as f64 / requests as f64,
+                reasons: reason_counts(counts, requests),
+            }
+        })
+        .collect();
+    models.sort_by(|a, b| {
+        b.length_rate
+            .total_cmp(&a.length_rate)
+            .then(b.requests.cmp(&a.requests))
+    });
+
+    let requests = overall.values().sum();
+    Ok(FinishReasonReport {
+        since: since.map(|s| s.to_string()),
+        requests,
+        reasons: reason_counts(overall, requests),
+        models,
+    })
+}
+
+fn reason_counts(counts: BTreeMap<String, i64>, total: i64) -> Vec<FinishReasonCount> {
+    let mut reasons: Vec<FinishReasonCount> = counts
+        .into_iter()
+        .map(|(finish_reason, requests)| FinishReasonCount {
+            finish_reason,
+            requests,
+            rate: requests as f64 / total as f64,
+        })
+        .collect();
+    reasons.sort_by_key(|reason| std::cmp::Reverse(reason.requests));
+    reasons
+}
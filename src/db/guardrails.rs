@@ -0,0 +1,113 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::BTreeMap;
+
+use crate::proxy::guardrails::{ACTION_CLAMPED, ACTION_DEFAULTED};
+
+/// How often one parameter was changed for a client.
+#[derive(Debug, Serialize)]
+pub struct ParamAdjustments {
+    pub param: String,
+    pub clamped: i64,
+    pub defaulted: i64,
+    /// Lowest and highest value the client sent among the clamped ones
+    pub lowest_original: Option<f64>,
+    pub highest_original: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientGuardrails {
+    pub client_id: Option<String>,
+    /// Requests with at least one adjusted parameter
+    pub requests: i64,
+    pub clamped: i64,
+    pub defaulted: i64,
+    pub params: Vec<ParamAdjustments>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GuardrailReport {
+    pub since: Option<String>,
+    pub requests: i64,
+    pub clamped: i64,
+    pub defaulted: i64,
+    /// Most clamped first
+    pub by_client: Vec<ClientGuardrails>,
+}
+
+/// Parameter adjustments made by `SAMPLING_GUARDRAILS` since `since`, per client.
+pub async fn get_guardrail_report(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<GuardrailReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            r.client_id,
+            json_extract(a.value, '$.param') as param,
+            SUM(CASE WHEN json_extract(a.value, '$.action') = ?2 THEN 1 ELSE 0 END) as clamped,
+            SUM(CASE WHEN json_extract(a.value, '$.action') = ?3 THEN 1 ELSE 0 END) as defaulted,
+            MIN(CAST(json_extract(a.value, '$.original') AS REAL)) as lowest_original,
+            MAX(CAST(json_extract(a.value, '$.original') AS REAL)) as highest_original
+        FROM requests r, json_each(r.param_adjustments) a
+        WHERE r.param_adjustments IS NOT NULL AND (?1 IS NULL OR r.start_time >= ?1)
+        GROUP BY r.client_id, param
+        "#,
+    )
+    .bind(since)
+    .bind(ACTION_CLAMPED)
+    .bind(ACTION_DEFAULTED)
+    .fetch_all(pool)
+    .await?;
+
+    let requests: Vec<(Option<String>, i64)> = sqlx::query_as(
+        r#"
+        SELECT client_id, COUNT(*)
+        FROM requests
+        WHERE param_adjustments IS NOT NULL AND (?1 IS NULL OR start_time >= ?1)
+        GROUP BY client_id
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut clients: BTreeMap<Option<String>, ClientGuardrails> = requests
+        .into_iter()
+        .map(|(client_id, requests)| {
+            let client = ClientGuardrails {
+                client_id: client_id.clone(),
+                requests,
+                clamped: 0,
+                defaulted: 0,
+                params: Vec::new(),
+            };
+            (client_id, client)
+        })
+        .collect();
+    for row in &rows {
+        let client_id: Option<String> = row.try_get("client_id")?;
+        let params = ParamAdjustments {
+            param: row.try_get("param")?,
+            clamped: row.try_get("clamped")?,
+            defaulted: row.try_get("defaulted")?,
+            lowest_original: row.try_get("lowest_original")?,
+            highest_original: row.try_get("highest_original")?,
+        };
+        if let Some(client) = clients.get_mut(&client_id) {
+            client.clamped += params.clamped;
+            client.defaulted += params.defaulted;
+            client.params.push(params);
+        }
+    }
+
+    let mut by_client: Vec<ClientGuardrails> = clients.into_values().collect();
+    by_client.sort_by_key(|client| std::cmp::Reverse(client.clamped));
+    Ok(GuardrailReport {
+        since: since.map(|s| s.to_string()),
+        requests: by_client.iter().map(|client| client.requests).sum(),
+        clamped: by_client.iter().map(|client| client.clamped).sum(),
+        defaulted: by_client.iter().map(|client| client.defaulted).sum(),
+        by_client,
+    })
+}
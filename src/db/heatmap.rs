@@ -0,0 +1,80 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use super::models::TERMINATION_ABANDONED;
+
+/// Row labels, in the order of SQLite's `%w` (0 is Sunday)
+const DAYS: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+
+#[derive(Debug, Serialize)]
+pub struct Heatmap {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Row labels of the matrices, Sunday first
+    pub days: Vec<&'static str>,
+    /// `[day][hour]` in UTC, every cell present; cells without traffic are zeros
+    pub requests: Vec<Vec<i64>>,
+    pub input_tokens: Vec<Vec<i64>>,
+    pub output_tokens: Vec<Vec<i64>>,
+}
+
+/// Requests and tokens per day of the week and hour of the day over `[from, to)`,
+/// leaving out abandoned requests and benchmark runs.
+pub async fn get_heatmap(
+    pool: &SqlitePool,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<Heatmap, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            CAST(strftime('%w', start_time) AS INTEGER) as day,
+            CAST(strftime('%H', start_time) AS INTEGER) as hour,
+            COUNT(*) as requests,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens
+        FROM requests
+        WHERE (?1 IS NULL OR start_time >= ?1) AND (?2 IS NULL OR start_time < ?2)
+          AND termination IS NOT ?3 AND benchmark_id IS NULL
+        GROUP BY day, hour
+        "#,
+    )
+    .bind(from)
+    .bind(to)
+    .bind(TERMINATION_ABANDONED)
+    .fetch_all(pool)
+    .await?;
+
+    let grid = || vec![vec![0; 24]; DAYS.len()];
+    let mut requests = grid();
+    let mut input_tokens = grid();
+    let mut output_tokens = grid();
+    for row in rows {
+        let day: i64 = row.try_get("day")?;
+        let hour: i64 = row.try_get("hour")?;
+        let (day, hour) = (day as usize, hour as usize);
+        if day >= DAYS.len() || hour >= 24 {
+            continue;
+        }
+        requests[day][hour] = row.try_get("requests")?;
+        input_tokens[day][hour] = row.try_get("input_tokens")?;
+        output_tokens[day][hour] = row.try_get("output_tokens")?;
+    }
+
+    Ok(Heatmap {
+        from: from.map(|s| s.to_string()),
+        to: to.map(|s| s.to_string()),
+        days: DAYS.to_vec(),
+        requests,
+        input_tokens,
+        output_tokens,
+    })
+}
@@ -0,0 +1,203 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use super::models::TERMINATION_ABANDONED;
+use crate::format;
+
+/// Most buckets one histogram may have
+pub const MAX_HISTOGRAM_BUCKETS: i64 = 100;
+
+/// A numeric request column a histogram can be drawn over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistogramField {
+    InputTokens,
+    OutputTokens,
+    DurationMs,
+}
+
+impl HistogramField {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "input_tokens" => Some(Self::InputTokens),
+            "output_tokens" => Some(Self::OutputTokens),
+            "duration_ms" => Some(Self::DurationMs),
+            _ => None,
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            Self::InputTokens => "input_tokens",
+            Self::OutputTokens => "output_tokens",
+            Self::DurationMs => "duration_ms",
+        }
+    }
+
+    /// Lower edges of the logarithmic buckets after the first, each four times the last
+    fn log_edges(self) -> Vec<i64> {
+        let first = match self {
+            Self::InputTokens | Self::OutputTokens => 128,
+            Self::DurationMs => 250,
+        };
+        (0..6).map(|step| first * 4_i64.pow(step)).collect()
+    }
+}
+
+/// How values are grouped into buckets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binning {
+    /// Buckets growing fourfold, so small and very large values both stay readable
+    Log,
+    /// Buckets of equal width, up to the largest value present
+    Width(i64),
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistogramBucket {
+    /// Inclusive lower edge
+    pub min: i64,
+    /// Exclusive upper edge; `None` for the open last logarithmic bucket
+    pub max: Option<i64>,
+    /// Axis label such as `512-2k`, `32k+` or `250ms-1s`
+    pub label: String,
+    pub requests: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Histogram {
+    pub field: HistogramField,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Requests counted, the sum of the buckets
+    pub requests: i64,
+    /// Every bucket, lowest first; empty ones are zeros
+    pub buckets: Vec<HistogramBucket>,
+}
+
+/// The histogram would need more than [`MAX_HISTOGRAM_BUCKETS`] buckets.
+#[derive(Debug)]
+pub struct TooManyBuckets(pub i64);
+
+impl HistogramField {
+    /// Token counts read best in binary thousands (`2k`, `8k`), the way context sizes are
+    /// given; durations in milliseconds below a second and seconds above.
+    fn label(self, value: i64) -> String {
+        match self {
+            Self::InputTokens | Self::OutputTokens if value >= 1024 && value % 1024 == 0 => {
+                format!("{}k", value / 1024)
+            }
+            Self::InputTokens | Self::OutputTokens => format::si(value as f64),
+            Self::DurationMs if value < 1000 => format!("{}ms", value),
+            Self::DurationMs => format!("{}s", value as f64 / 1000.0),
+        }
+    }
+}
+
+/// Counts successful requests in `[from, to)` by `field`, leaving out abandoned requests,
+/// benchmark runs and, unless `include_probes` is set, warm-up probes, and keeping only
+/// `models` when it isn't empty.
+pub async fn get_histogram(
+    pool: &SqlitePool,
+    field: HistogramField,
+    binning: Binning,
+    from: Option<&str>,
+    to: Option<&str>,
+    models: &[String],
+    include_probes: bool,
+) -> Result<Result<Histogram, TooManyBuckets>, sqlx::Error> {
+    let models = (!models.is_empty()).then(|| serde_json::json!(models).to_string());
+    let probes = if include_probes {
+        ""
+    } else {
+        "AND is_probe = 0"
+    };
+    let filter = r#"
+        FROM requests
+        WHERE {column} IS NOT NULL AND is_error = 0
+          AND termination IS NOT ?1 AND benchmark_id IS NULL
+          AND (?2 IS NULL OR start_time >= ?2) AND (?3 IS NULL OR start_time < ?3)
+          AND (?4 IS NULL OR model IN (SELECT value FROM json_each(?4)))
+          {probes}
+        "#
+    .replace("{column}", field.column())
+    .replace("{probes}", probes);
+
+    let edges = match binning {
+        Binning::Log => field.log_edges(),
+        Binning::Width(width) => {
+            let largest: Option<i64> =
+                sqlx::query_scalar(&format!("SELECT MAX({}) {}", field.column(), filter))
+                    .bind(TERMINATION_ABANDONED)
+                    .bind(from)
+                    .bind(to)
+                    .bind(&models)
+                    .fetch_one(pool)
+                    .await?;
+            let buckets = largest.unwrap_or(0).max(0) / width + 1;
+            if buckets > MAX_HISTOGRAM_BUCKETS {
+                return Ok(Err(TooManyBuckets(buckets)));
+            }
+            (1..buckets).map(|step| step * width).collect()
+        }
+    };
+
+    // A value's bucket is the number of edges at or below it
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM json_each(?5) WHERE value <= {column}) as bucket,
+            COUNT(*) as requests
+        {filter}
+        GROUP BY bucket
+        "#,
+        column = field.column(),
+        filter = filter,
+    ))
+    .bind(TERMINATION_ABANDONED)
+    .bind(from)
+    .bind(to)
+    .bind(&models)
+    .bind(serde_json::json!(edges).to_string())
+    .fetch_all(pool)
+    .await?;
+
+    let mut counts = vec![0; edges.len() + 1];
+    for row in rows {
+        let bucket: i64 = row.try_get("bucket")?;
+        if let Some(count) = counts.get_mut(bucket as usize) {
+            *count = row.try_get("requests")?;
+        }
+    }
+
+    let buckets = counts
+        .iter()
+        .enumerate()
+        .map(|(index, &requests)| {
+            let min = if index == 0 { 0 } else { edges[index - 1] };
+            // Equal-width buckets stop at the largest value, so the last one is closed too
+            let max = edges.get(index).copied().or(match binning {
+                Binning::Log => None,
+                Binning::Width(width) => Some(min + width),
+            });
+            let label = match max {
+                Some(max) => format!("{}-{}", field.label(min), field.label(max)),
+                None => format!("{}+", field.label(min)),
+            };
+            HistogramBucket {
+                min,
+                max,
+                label,
+                requests,
+            }
+        })
+        .collect();
+
+    Ok(Ok(Histogram {
+        field,
+        from: from.map(|s| s.to_string()),
+        to: to.map(|s| s.to_string()),
+        requests: counts.iter().sum(),
+        buckets,
+    }))
+}
@@ -0,0 +1,87 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+#[derive(Debug, Serialize)]
+pub struct Incident {
+    pub id: String,
+    pub reason: String,
+    pub started_at: String,
+    pub ends_at: String,
+    /// Rows tagged with this incident, what a pull of the affected records returns
+    pub requests: i64,
+}
+
+pub async fn create_incident(
+    pool: &SqlitePool,
+    id: &str,
+    reason: &str,
+    started_at: &str,
+    ends_at: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO incidents (id, reason, started_at, ends_at) VALUES (?, ?, ?, ?)")
+        .bind(id)
+        .bind(reason)
+        .bind(started_at)
+        .bind(ends_at)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn extend_incident(
+    pool: &SqlitePool,
+    id: &str,
+    ends_at: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE incidents SET ends_at = ? WHERE id = ?")
+        .bind(ends_at)
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Every incident, newest first, with the number of requests recorded during it.
+pub async fn list_incidents(pool: &SqlitePool) -> Result<Vec<Incident>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            i.id,
+            i.reason,
+            i.started_at,
+            i.ends_at,
+            (SELECT COUNT(*) FROM requests r WHERE r.incident_id = i.id) as requests
+        FROM incidents i
+        ORDER BY i.started_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(Incident {
+                id: row.try_get("id")?,
+                reason: row.try_get("reason")?,
+                started_at: row.try_get("started_at")?,
+                ends_at: row.try_get("ends_at")?,
+                requests: row.try_get("requests")?,
+            })
+        })
+        .collect()
+}
+
+/// The incident still running at `now`, if any, as `(id, ends_at)`.
+pub async fn get_active_incident(
+    pool: &SqlitePool,
+    now: &str,
+) -> Result<Option<(String, String)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT id, ends_at FROM incidents WHERE ends_at > ? ORDER BY ends_at DESC LIMIT 1",
+    )
+    .bind(now)
+    .fetch_optional(pool)
+    .await
+}
@@ -0,0 +1,131 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool, sqlite::SqliteRow};
+
+pub const JOB_RUNNING: &str = "running";
+pub const JOB_PAUSED: &str = "paused";
+pub const JOB_COMPLETED: &str = "completed";
+pub const JOB_CANCELLED: &str = "cancelled";
+pub const JOB_FAILED: &str = "failed";
+/// The process stopped while the job was running or paused
+pub const JOB_INTERRUPTED: &str = "interrupted";
+
+#[derive(Debug, Serialize)]
+pub struct MaintenanceJob {
+    pub id: i64,
+    pub kind: String,
+    pub status: String,
+    /// Rows processed so far
+    pub processed: i64,
+    /// Rows pending when the job started
+    pub total: i64,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub finished_at: Option<String>,
+}
+
+impl MaintenanceJob {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            kind: row.try_get("kind")?,
+            status: row.try_get("status")?,
+            processed: row.try_get("processed")?,
+            total: row.try_get("total")?,
+            error: row.try_get("error")?,
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+            finished_at: row.try_get("finished_at")?,
+        })
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+pub async fn create_job(pool: &SqlitePool, kind: &str, total: i64) -> Result<i64, sqlx::Error> {
+    let now = now();
+    let result = sqlx::query(
+        r#"
+        INSERT INTO maintenance_jobs (kind, status, processed, total, created_at, updated_at)
+        VALUES (?, ?, 0, ?, ?, ?)
+        "#,
+    )
+    .bind(kind)
+    .bind(JOB_RUNNING)
+    .bind(total)
+    .bind(&now)
+    .bind(&now)
+    .execute(pool)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn update_job_progress(
+    pool: &SqlitePool,
+    id: i64,
+    status: &str,
+    processed: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE maintenance_jobs SET status = ?, processed = ?, updated_at = ? WHERE id = ?",
+    )
+    .bind(status)
+    .bind(processed)
+    .bind(now())
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn finish_job(
+    pool: &SqlitePool,
+    id: i64,
+    status: &str,
+    error: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let now = now();
+    sqlx::query(
+        "UPDATE maintenance_jobs SET status = ?, error = ?, updated_at = ?, finished_at = ? WHERE id = ?",
+    )
+    .bind(status)
+    .bind(error)
+    .bind(&now)
+    .bind(&now)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Marks jobs left running or paused by a previous process as interrupted.
+pub async fn interrupt_unfinished_jobs(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE maintenance_jobs SET status = ?, updated_at = ? WHERE status IN (?, ?)",
+    )
+    .bind(JOB_INTERRUPTED)
+    .bind(now())
+    .bind(JOB_RUNNING)
+    .bind(JOB_PAUSED)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn list_jobs(pool: &SqlitePool) -> Result<Vec<MaintenanceJob>, sqlx::Error> {
+    let rows = sqlx::query("SELECT * FROM maintenance_jobs ORDER BY id DESC")
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(MaintenanceJob::from_row).collect()
+}
+
+pub async fn get_job(pool: &SqlitePool, id: i64) -> Result<Option<MaintenanceJob>, sqlx::Error> {
+    let row = sqlx::query("SELECT * FROM maintenance_jobs WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    row.as_ref().map(MaintenanceJob::from_row).transpose()
+}
@@ -0,0 +1,125 @@
+//! The kind of work a request asked for, told apart by its endpoint, so embeddings'
+//! large inputs and empty outputs can be kept out of chat figures.
+
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use super::models::TERMINATION_ABANDONED;
+
+pub const KIND_CHAT: &str = "chat";
+pub const KIND_COMPLETION: &str = "completion";
+pub const KIND_EMBEDDING: &str = "embedding";
+pub const KIND_OTHER: &str = "other";
+
+/// The kind of a request to `endpoint`, under `/v1` or LM Studio's native `/api/v0`.
+pub fn kind_of(endpoint: &str) -> &'static str {
+    let path = endpoint.trim_end_matches('/');
+    if path.ends_with("/chat/completions") {
+        KIND_CHAT
+    } else if path.ends_with("/completions") {
+        KIND_COMPLETION
+    } else if path.ends_with("/embeddings") {
+        KIND_EMBEDDING
+    } else {
+        KIND_OTHER
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct KindStats {
+    pub kind: String,
+    pub requests: i64,
+    pub errors: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub total_tokens: i64,
+    pub avg_input_tokens: f64,
+    pub avg_output_tokens: f64,
+    pub avg_duration_ms: f64,
+    /// Over successful requests that produced output; `None` for embeddings
+    pub avg_tokens_per_second: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KindReport {
+    pub since: Option<String>,
+    /// Busiest first
+    pub kinds: Vec<KindStats>,
+}
+
+/// Sets the kind of requests stored before it was recorded, once per distinct endpoint.
+pub async fn backfill_kinds(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let endpoints: Vec<String> =
+        sqlx::query_scalar("SELECT DISTINCT endpoint FROM requests WHERE kind IS NULL")
+            .fetch_all(pool)
+            .await?;
+
+    for endpoint in endpoints {
+        sqlx::query("UPDATE requests SET kind = ? WHERE endpoint = ? AND kind IS NULL")
+            .bind(kind_of(&endpoint))
+            .bind(&endpoint)
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Usage per kind from `since` onward, with the same filters as `/stats/by-endpoint`.
+pub async fn get_kind_stats(
+    pool: &SqlitePool,
+    since: Option<&str>,
+    include_abandoned: bool,
+    exclude_batches: bool,
+    include_benchmarks: bool,
+) -> Result<KindReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            kind,
+            COUNT(*) as requests,
+            SUM(CASE WHEN is_error = 1 THEN 1 ELSE 0 END) as errors,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens,
+            COALESCE(SUM(total_tokens), 0) as total_tokens,
+            COALESCE(AVG(CAST(input_tokens AS REAL)), 0.0) as avg_input_tokens,
+            COALESCE(AVG(CAST(output_tokens AS REAL)), 0.0) as avg_output_tokens,
+            COALESCE(AVG(CAST(duration_ms AS REAL)), 0.0) as avg_duration_ms,
+            ROUND(AVG(CASE WHEN is_error = 0 THEN tokens_per_second END), 2)
+                as avg_tokens_per_second
+        FROM requests
+        WHERE (?1 IS NULL OR start_time >= ?1)
+          AND (?2 OR termination IS NOT ?3) AND (NOT ?4 OR batch_id IS NULL)
+          AND (?5 OR benchmark_id IS NULL)
+        GROUP BY kind
+        ORDER BY requests DESC
+        "#,
+    )
+    .bind(since)
+    .bind(include_abandoned)
+    .bind(TERMINATION_ABANDONED)
+    .bind(exclude_batches)
+    .bind(include_benchmarks)
+    .fetch_all(pool)
+    .await?;
+
+    let mut kinds = Vec::new();
+    for row in rows {
+        kinds.push(KindStats {
+            kind: row.try_get("kind")?,
+            requests: row.try_get("requests")?,
+            errors: row.try_get("errors")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+            total_tokens: row.try_get("total_tokens")?,
+            avg_input_tokens: row.try_get("avg_input_tokens")?,
+            avg_output_tokens: row.try_get("avg_output_tokens")?,
+            avg_duration_ms: row.try_get("avg_duration_ms")?,
+            avg_tokens_per_second: row.try_get("avg_tokens_per_second")?,
+        });
+    }
+
+    Ok(KindReport {
+        since: since.map(str::to_string),
+        kinds,
+    })
+}
@@ -0,0 +1,44 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// A model name as it has appeared in the request log.
+#[derive(Debug, Serialize)]
+pub struct KnownModel {
+    pub model: String,
+    pub first_seen: String,
+    pub last_seen: String,
+    pub requests: i64,
+    pub errors: i64,
+}
+
+/// Every model name ever recorded, whether or not any of its requests succeeded, most
+/// recently seen first.
+pub async fn get_known_models(pool: &SqlitePool) -> Result<Vec<KnownModel>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            model,
+            MIN(start_time) as first_seen,
+            MAX(start_time) as last_seen,
+            COUNT(*) as requests,
+            COALESCE(SUM(is_error), 0) as errors
+        FROM requests
+        GROUP BY model
+        ORDER BY last_seen DESC, model
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(KnownModel {
+                model: row.try_get("model")?,
+                first_seen: row.try_get("first_seen")?,
+                last_seen: row.try_get("last_seen")?,
+                requests: row.try_get("requests")?,
+                errors: row.try_get("errors")?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,147 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// Most recent full-reprocess samples the prompt-eval baseline is learned from
+const BASELINE_SAMPLE_SIZE: i64 = 50;
+
+/// The prompt of the previous successful request by the same client to the same model,
+/// if it started at or after `session_start`.
+pub async fn get_previous_session_prompt(
+    pool: &SqlitePool,
+    client_id: &str,
+    model: &str,
+    session_start: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT prompt_text
+        FROM request_rows
+        WHERE client_id = ? AND model = ? AND start_time >= ? AND is_error = 0
+        ORDER BY id DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(client_id)
+    .bind(model)
+    .bind(session_start)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Prompt tokens per second of the model's recent requests that shared fewer than
+/// `max_prefix_tokens` with their session's previous prompt, i.e. had little to reuse.
+pub async fn get_full_reprocess_rates(
+    pool: &SqlitePool,
+    model: &str,
+    max_prefix_tokens: i64,
+) -> Result<Vec<f64>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT input_tokens * 1000.0 / prompt_eval_ms
+        FROM requests
+        WHERE model = ? AND kv_prefix_tokens < ? AND prompt_eval_ms > 0 AND input_tokens > 0
+        ORDER BY id DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(model)
+    .bind(max_prefix_tokens)
+    .bind(BASELINE_SAMPLE_SIZE)
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(Debug, Serialize)]
+pub struct KvCacheRate {
+    /// Requests the heuristic could classify
+    pub classified: i64,
+    pub likely_hits: i64,
+    pub hit_rate: Option<f64>,
+    pub avg_prefix_tokens: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelKvCache {
+    pub model: String,
+    #[serde(flatten)]
+    pub rate: KvCacheRate,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientKvCache {
+    pub client: String,
+    #[serde(flatten)]
+    pub rate: KvCacheRate,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KvCacheReport {
+    pub since: Option<String>,
+    pub heuristic: &'static str,
+    pub by_model: Vec<ModelKvCache>,
+    pub by_client: Vec<ClientKvCache>,
+}
+
+/// Likely KV-cache hit rates grouped by `group_by`, a column of `requests`.
+async fn hit_rates(
+    pool: &SqlitePool,
+    group_by: &str,
+    since: Option<&str>,
+) -> Result<Vec<(String, KvCacheRate)>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT
+            COALESCE({group_by}, 'unknown') as grouping,
+            COUNT(*) as classified,
+            COALESCE(SUM(CASE WHEN kv_cache_class = 'likely_cache_hit' THEN 1 ELSE 0 END), 0)
+                as likely_hits,
+            AVG(kv_prefix_tokens) as avg_prefix_tokens
+        FROM requests
+        WHERE kv_cache_class IS NOT NULL AND (?1 IS NULL OR start_time >= ?1)
+        GROUP BY grouping
+        ORDER BY classified DESC
+        "#
+    ))
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            let classified: i64 = row.try_get("classified")?;
+            let likely_hits: i64 = row.try_get("likely_hits")?;
+            Ok((
+                row.try_get("grouping")?,
+                KvCacheRate {
+                    classified,
+                    likely_hits,
+                    hit_rate: (classified > 0).then(|| likely_hits as f64 / classified as f64),
+                    avg_prefix_tokens: row.try_get("avg_prefix_tokens")?,
+                },
+            ))
+        })
+        .collect()
+}
+
+pub async fn get_kv_cache_stats(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<KvCacheReport, sqlx::Error> {
+    let by_model = hit_rates(pool, "model", since)
+        .await?
+        .into_iter()
+        .map(|(model, rate)| ModelKvCache { model, rate })
+        .collect();
+    let by_client = hit_rates(pool, "client_id", since)
+        .await?
+        .into_iter()
+        .map(|(client, rate)| ClientKvCache { client, rate })
+        .collect();
+
+    Ok(KvCacheReport {
+        since: since.map(|s| s.to_string()),
+        heuristic: crate::kv_cache::HEURISTIC,
+        by_model,
+        by_client,
+    })
+}
@@ -0,0 +1,134 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use super::models::TERMINATION_ABANDONED;
+
+/// Usage in one prompt language.
+#[derive(Debug, Serialize)]
+pub struct LanguageUsage {
+    /// ISO 639-3 code, or `unknown` for low-confidence detections
+    pub language: String,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub avg_confidence: f64,
+    /// Requests answered in a different (known) language than they were asked in
+    pub output_language_mismatches: i64,
+}
+
+/// Usage of one model in one prompt language.
+#[derive(Debug, Serialize)]
+pub struct ModelLanguageUsage {
+    pub model: String,
+    pub language: String,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LanguageReport {
+    pub since: Option<String>,
+    /// Successful requests with no detected language (stored before detection existed,
+    /// detection compiled out, or no text to examine)
+    pub undetected_requests: i64,
+    /// Most requests first
+    pub languages: Vec<LanguageUsage>,
+    /// Per model, most requests first
+    pub by_model: Vec<ModelLanguageUsage>,
+}
+
+/// Successful requests since `since` grouped by prompt language, and by model and language.
+pub async fn get_language_report(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<LanguageReport, sqlx::Error> {
+    let languages: Vec<(String, i64, i64, i64, f64, i64)> = sqlx::query_as(
+        r#"
+        SELECT
+            prompt_language,
+            COUNT(*) as requests,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens,
+            ROUND(COALESCE(AVG(prompt_language_confidence), 0), 2) as avg_confidence,
+            SUM(CASE
+                WHEN output_language IS NOT NULL AND output_language != 'unknown'
+                    AND prompt_language != 'unknown' AND output_language != prompt_language
+                THEN 1 ELSE 0 END) as mismatches
+        FROM requests
+        WHERE is_error = 0 AND termination IS NOT ?2 AND prompt_language IS NOT NULL
+            AND (?1 IS NULL OR start_time >= ?1)
+        GROUP BY prompt_language
+        ORDER BY requests DESC, prompt_language
+        "#,
+    )
+    .bind(since)
+    .bind(TERMINATION_ABANDONED)
+    .fetch_all(pool)
+    .await?;
+
+    let by_model: Vec<(String, String, i64, i64, i64)> = sqlx::query_as(
+        r#"
+        SELECT
+            model,
+            prompt_language,
+            COUNT(*) as requests,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens
+        FROM requests
+        WHERE is_error = 0 AND termination IS NOT ?2 AND prompt_language IS NOT NULL
+            AND (?1 IS NULL OR start_time >= ?1)
+        GROUP BY model, prompt_language
+        ORDER BY model, requests DESC, prompt_language
+        "#,
+    )
+    .bind(since)
+    .bind(TERMINATION_ABANDONED)
+    .fetch_all(pool)
+    .await?;
+
+    let (undetected_requests,): (i64,) = sqlx::query_as(
+        r#"
+        SELECT COUNT(*)
+        FROM requests
+        WHERE is_error = 0 AND termination IS NOT ?2 AND prompt_language IS NULL
+            AND (?1 IS NULL OR start_time >= ?1)
+        "#,
+    )
+    .bind(since)
+    .bind(TERMINATION_ABANDONED)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(LanguageReport {
+        since: since.map(|s| s.to_string()),
+        undetected_requests,
+        languages: languages
+            .into_iter()
+            .map(
+                |(language, requests, input_tokens, output_tokens, avg_confidence, mismatches)| {
+                    LanguageUsage {
+                        language,
+                        requests,
+                        input_tokens,
+                        output_tokens,
+                        avg_confidence,
+                        output_language_mismatches: mismatches,
+                    }
+                },
+            )
+            .collect(),
+        by_model: by_model
+            .into_iter()
+            .map(
+                |(model, language, requests, input_tokens, output_tokens)| ModelLanguageUsage {
+                    model,
+                    language,
+                    requests,
+                    input_tokens,
+                    output_tokens,
+                },
+            )
+            .collect(),
+    })
+}
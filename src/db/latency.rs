@@ -0,0 +1,120 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// Durations of each model's latest `per_model` successful requests, oldest first.
+pub async fn get_recent_durations(
+    pool: &SqlitePool,
+    per_model: usize,
+) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT model, duration_ms
+        FROM (
+            SELECT id, model, duration_ms,
+                ROW_NUMBER() OVER (PARTITION BY model ORDER BY id DESC) as recency
+            FROM requests
+            WHERE is_error = 0
+        )
+        WHERE recency <= ?
+        ORDER BY id
+        "#,
+    )
+    .bind(per_model as i64)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| Ok((row.try_get("model")?, row.try_get("duration_ms")?)))
+        .collect()
+}
+
+/// Requests selected for a latency trend.
+#[derive(Debug)]
+pub struct TrendFilter<'a> {
+    pub model: Option<&'a str>,
+    pub session_id: Option<&'a str>,
+    /// RFC 3339 lower bound on `start_time`
+    pub since: Option<&'a str>,
+}
+
+/// `window` consecutive requests averaged together.
+#[derive(Debug, Serialize)]
+pub struct TrendWindow {
+    /// Position of the window among all of them, 0 for the oldest
+    pub window: i64,
+    pub first_id: i64,
+    pub last_id: i64,
+    /// `window`, except in a latest window that isn't full yet
+    pub requests: i64,
+    pub avg_duration_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LatencyTrend {
+    pub window: i64,
+    pub model: Option<String>,
+    pub session_id: Option<String>,
+    pub since: Option<String>,
+    /// Oldest first
+    pub windows: Vec<TrendWindow>,
+}
+
+/// Average duration over consecutive groups of `window` successful requests in id order,
+/// the latest `limit` groups. Benchmark runs and warm-up probes are left out.
+pub async fn get_latency_trend(
+    pool: &SqlitePool,
+    filter: &TrendFilter<'_>,
+    window: i64,
+    limit: i64,
+) -> Result<LatencyTrend, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        WITH ordered AS (
+            SELECT
+                id,
+                duration_ms,
+                (ROW_NUMBER() OVER (ORDER BY id) - 1) / ?4 as window_index
+            FROM requests
+            WHERE is_error = 0 AND benchmark_id IS NULL AND is_probe = 0
+              AND (?1 IS NULL OR model = ?1) AND (?2 IS NULL OR session_id = ?2)
+              AND (?3 IS NULL OR start_time >= ?3)
+        )
+        SELECT
+            window_index,
+            MIN(id) as first_id,
+            MAX(id) as last_id,
+            COUNT(*) as requests,
+            AVG(CAST(duration_ms AS REAL)) as avg_duration_ms
+        FROM ordered
+        GROUP BY window_index
+        ORDER BY window_index DESC
+        LIMIT ?5
+        "#,
+    )
+    .bind(filter.model)
+    .bind(filter.session_id)
+    .bind(filter.since)
+    .bind(window)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    let mut windows = Vec::new();
+    for row in rows.iter().rev() {
+        windows.push(TrendWindow {
+            window: row.try_get("window_index")?,
+            first_id: row.try_get("first_id")?,
+            last_id: row.try_get("last_id")?,
+            requests: row.try_get("requests")?,
+            avg_duration_ms: row.try_get("avg_duration_ms")?,
+        });
+    }
+
+    Ok(LatencyTrend {
+        window,
+        model: filter.model.map(str::to_string),
+        session_id: filter.session_id.map(str::to_string),
+        since: filter.since.map(str::to_string),
+        windows,
+    })
+}
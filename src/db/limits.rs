@@ -0,0 +1,115 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+#[derive(Debug, Serialize)]
+pub struct LimitTriggers {
+    pub limit: String,
+    /// Requests on which the limit fired at least once
+    pub requests: i64,
+    pub triggers: i64,
+    pub bytes_truncated: i64,
+    pub events_dropped: i64,
+    pub last_triggered: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LimitReport {
+    pub since: Option<String>,
+    pub limits: Vec<LimitTriggers>,
+}
+
+/// How often each protective limit fired, from the `limits_hit` column.
+pub async fn get_limit_triggers(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<LimitReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            json_extract(hit.value, '$.limit') as limit_name,
+            COUNT(DISTINCT r.id) as requests,
+            COALESCE(SUM(json_extract(hit.value, '$.triggers')), 0) as triggers,
+            COALESCE(SUM(json_extract(hit.value, '$.bytes_truncated')), 0) as bytes_truncated,
+            COALESCE(SUM(json_extract(hit.value, '$.events_dropped')), 0) as events_dropped,
+            MAX(r.start_time) as last_triggered
+        FROM requests r, json_each(r.limits_hit) hit
+        WHERE r.limits_hit IS NOT NULL AND (?1 IS NULL OR r.start_time >= ?1)
+        GROUP BY limit_name
+        ORDER BY triggers DESC, limit_name
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let limits = rows
+        .iter()
+        .map(|row| {
+            Ok(LimitTriggers {
+                limit: row.try_get("limit_name")?,
+                requests: row.try_get("requests")?,
+                triggers: row.try_get("triggers")?,
+                bytes_truncated: row.try_get("bytes_truncated")?,
+                events_dropped: row.try_get("events_dropped")?,
+                last_triggered: row.try_get("last_triggered")?,
+            })
+        })
+        .collect::<Result<_, sqlx::Error>>()?;
+
+    Ok(LimitReport {
+        since: since.map(|s| s.to_string()),
+        limits,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use crate::db::{insert_request, RequestRecord};
+    use crate::limits::{LIMIT_BUFFER_CAP, LIMIT_DEADLINE_TIMEOUT};
+    use chrono::DateTime;
+
+    async fn insert(pool: &SqlitePool, start: &str, hits: &[(&str, i64, i64)]) {
+        let start = DateTime::parse_from_rfc3339(start).unwrap().to_utc();
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            start,
+            "hi".to_string(),
+        );
+        for &(limit, bytes, events) in hits {
+            record.limits_hit.record(limit, bytes, events);
+        }
+        insert_request(pool, &record).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn triggers_are_summed_per_limit() {
+        let pool = memory_pool().await;
+        insert(&pool, "2026-01-01T00:00:00Z", &[(LIMIT_BUFFER_CAP, 10, 0)]).await;
+        insert(
+            &pool,
+            "2026-01-02T00:00:00Z",
+            &[(LIMIT_BUFFER_CAP, 5, 0), (LIMIT_BUFFER_CAP, 5, 0), (LIMIT_DEADLINE_TIMEOUT, 0, 1)],
+        )
+        .await;
+        insert(&pool, "2026-01-03T00:00:00Z", &[]).await;
+
+        let report = get_limit_triggers(&pool, None).await.unwrap();
+        let buffer = &report.limits[0];
+        assert_eq!(buffer.limit, LIMIT_BUFFER_CAP);
+        assert_eq!(buffer.requests, 2);
+        assert_eq!(buffer.triggers, 3);
+        assert_eq!(buffer.bytes_truncated, 20);
+        assert_eq!(buffer.last_triggered.as_deref(), Some("2026-01-02T00:00:00+00:00"));
+        let deadline = &report.limits[1];
+        assert_eq!(deadline.limit, LIMIT_DEADLINE_TIMEOUT);
+        assert_eq!((deadline.requests, deadline.events_dropped), (1, 1));
+        assert_eq!(report.limits.len(), 2);
+
+        let report = get_limit_triggers(&pool, Some("2026-01-02T00:00:00+00:00")).await.unwrap();
+        assert_eq!(report.limits[0].requests, 1);
+        assert_eq!(report.limits[0].bytes_truncated, 10);
+    }
+}
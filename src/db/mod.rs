@@ -1,6 +1,116 @@
+pub mod abandoned;
+pub mod agent_overhead;
+pub mod availability;
+pub mod batches;
+pub mod benchmarks;
+pub mod backfill;
+pub mod blobs;
+pub mod cache;
+pub mod canary;
+pub mod chargeback;
+pub mod clients;
+pub mod context_fit;
+pub mod context_utilization;
+pub mod costs;
+pub mod counters;
+pub mod determinism;
+pub mod duplicates;
+pub mod energy;
+pub mod errors;
+pub mod estimation_gap;
+pub mod events;
+pub mod incidents;
+pub mod export;
+pub mod finish_reasons;
+pub mod guardrails;
+pub mod heatmap;
+pub mod histogram;
+pub mod jobs;
+pub mod kinds;
+pub mod known_models;
+pub mod kv_cache;
+pub mod languages;
+pub mod latency;
+pub mod limits;
+pub mod model_usage;
 pub mod models;
+pub mod params;
+pub mod persist_lag;
+pub mod prompt_quality;
+pub mod prompt_versions;
+pub mod rate;
+pub mod reloads;
+pub mod retention;
+pub mod retries;
+pub mod rollups;
+pub mod sdk;
+pub mod search;
+pub mod sessions;
+pub mod status_codes;
+pub mod stops;
+pub mod storage;
+pub mod streaming;
+pub mod timeseries;
+pub mod top_prompts;
+pub mod tree;
+pub mod truncation;
+pub mod turns;
+pub mod usage_rollups;
+pub mod utilization;
+pub mod webhooks;
 
+#[cfg(test)]
+pub mod testing;
+
+pub use agent_overhead::get_agent_overhead;
+pub use cache::get_cache_opportunities;
+pub use chargeback::get_chargeback;
+pub use clients::get_client_stats;
+pub use context_fit::get_context_fit;
+pub use context_utilization::get_context_utilization;
+pub use costs::get_cost_report;
+pub use determinism::get_determinism;
+pub use duplicates::get_duplicates;
+pub use energy::get_energy_estimate;
+pub use errors::get_error_report;
+pub use estimation_gap::get_estimation_gap;
+pub use events::record_event;
+pub use export::{stream_requests, ExportFilter, StoredRequest};
+pub use finish_reasons::get_finish_reasons;
+pub use guardrails::get_guardrail_report;
+pub use heatmap::get_heatmap;
+pub use histogram::get_histogram;
+pub use kinds::get_kind_stats;
+pub use known_models::get_known_models;
+pub use kv_cache::get_kv_cache_stats;
+pub use languages::get_language_report;
+pub use latency::{get_latency_trend, get_recent_durations};
+pub use limits::get_limit_triggers;
+pub use model_usage::{get_model_usage, ModelUsage};
 pub use models::{
-    get_model_stats, get_recent_requests, get_summary_stats, init_db, insert_request,
+    get_daily_report, get_endpoint_stats, get_model_stats, get_recent_requests, get_request,
+    get_request_by_id, get_request_by_response_id, get_summary_stats, init_db, insert_request,
     RequestRecord,
 };
+pub use params::get_param_stats;
+pub use persist_lag::get_persist_lag;
+pub use prompt_quality::get_prompt_quality;
+pub use prompt_versions::{get_prompt_version_stats, set_prompt_version_label};
+pub use rate::get_rate_report;
+pub use reloads::get_reload_report;
+pub use retention::{delete_requests_before, simulate_prune};
+pub use retries::{find_retry_origin, get_retry_stats};
+pub use rollups::get_glance;
+pub use search::search_requests;
+pub use sessions::{get_session, get_sessions};
+pub use status_codes::get_status_codes;
+pub use stops::get_stop_report;
+pub use streaming::get_streaming_report;
+pub use sdk::get_sdk_stats;
+pub use timeseries::get_timeseries;
+pub use top_prompts::get_top_prompts;
+pub use tree::{get_request_tree, request_exists};
+pub use truncation::get_truncation;
+pub use turns::get_turn_latency;
+pub use usage_rollups::{get_rollups, rebuild as rebuild_rollups};
+pub use utilization::get_utilization;
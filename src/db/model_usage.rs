@@ -0,0 +1,65 @@
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+
+/// Idle gap after which a model's next request is assumed to have paid for a reload
+const COLD_START_GAP_MINUTES: i64 = 30;
+
+#[derive(Debug, Clone, Default)]
+pub struct ModelUsage {
+    /// Start time of the most recent successful, non-abandoned request
+    pub last_used: Option<String>,
+    /// Average time to response headers for requests after an idle gap, standing in
+    /// for how long the model takes to load
+    pub reload_ms: Option<f64>,
+    pub cold_starts: i64,
+}
+
+/// Recency and reload cost of every model that has served a real request.
+pub async fn get_model_usage(
+    pool: &SqlitePool,
+) -> Result<HashMap<String, ModelUsage>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        WITH served AS (
+            SELECT
+                model,
+                start_time,
+                time_to_headers_ms,
+                LAG(start_time) OVER (PARTITION BY model ORDER BY start_time) as previous_start
+            FROM requests
+            WHERE is_error = 0 AND termination IS NOT 'abandoned'
+        )
+        SELECT
+            model,
+            MAX(start_time) as last_used,
+            AVG(CASE WHEN is_cold THEN time_to_headers_ms END) as reload_ms,
+            COALESCE(SUM(CASE WHEN is_cold AND time_to_headers_ms IS NOT NULL THEN 1 ELSE 0 END), 0)
+                as cold_starts
+        FROM (
+            SELECT
+                *,
+                previous_start IS NULL
+                    OR (julianday(start_time) - julianday(previous_start)) * 1440 > ?
+                    as is_cold
+            FROM served
+        )
+        GROUP BY model
+        "#,
+    )
+    .bind(COLD_START_GAP_MINUTES)
+    .fetch_all(pool)
+    .await?;
+
+    let mut usage = HashMap::new();
+    for row in rows {
+        usage.insert(
+            row.try_get("model")?,
+            ModelUsage {
+                last_used: row.try_get("last_used")?,
+                reload_ms: row.try_get("reload_ms")?,
+                cold_starts: row.try_get("cold_starts")?,
+            },
+        );
+    }
+    Ok(usage)
+}
@@ -1,6 +1,56 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{Row, SqlitePool};
+use serde_json::Value;
+use sqlx::query::{Query, QueryScalar};
+use sqlx::sqlite::{SqliteArguments, SqliteRow};
+use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool};
+use std::collections::HashMap;
+use std::time::Instant;
+use uuid::Uuid;
+
+use super::abandoned::{AbandonedStats, get_abandoned_stats};
+use super::blobs::{self, content_hash};
+use super::chargeback::round_cost;
+use super::energy::EnergyEstimate;
+use super::export::StoredRequest;
+use super::kinds;
+use super::prompt_versions;
+use super::retries::get_retry_stats;
+use super::rollups::{self, percent_change};
+use super::search;
+use super::turns;
+use super::usage_rollups::{self, PERIOD_WEEK};
+use super::utilization;
+use crate::counters::MinuteCount;
+use crate::limits::LimitTracker;
+use crate::proxy::prompt_check::{self, PromptWarning};
+use super::truncation::{TruncatingClient, get_most_truncating_client};
+
+/// Wall-clock and monotonic elapsed times further apart than this are reported as skew
+const CLOCK_SKEW_THRESHOLD_MS: i64 = 1000;
+
+/// The request ran to completion, whatever the upstream status
+pub const TERMINATION_COMPLETED: &str = "completed";
+/// The proxy or upstream failed the request
+pub const TERMINATION_ERROR: &str = "error";
+/// The client hung up while a streamed response was being forwarded
+pub const TERMINATION_CLIENT_DISCONNECTED: &str = "client_disconnected";
+/// The client hung up before any response byte was forwarded; nothing was generated for it
+pub const TERMINATION_ABANDONED: &str = "abandoned";
+
+/// The status was LM Studio's, passed through to the client
+pub const STATUS_SOURCE_UPSTREAM: &str = "upstream";
+/// The proxy answered with its own status, e.g. a 502 when LM Studio was unreachable
+pub const STATUS_SOURCE_PROXY: &str = "proxy";
+
+/// The client's request body could not be read, or the buffers had no room for it
+pub const FAILURE_BODY_READ: &str = "body_read";
+/// The request could not be sent to LM Studio or got no answer from it
+pub const FAILURE_FORWARD: &str = "forward";
+/// LM Studio answered with an error status, or its response body could not be read
+pub const FAILURE_UPSTREAM_ERROR: &str = "upstream_error";
+/// LM Studio answered with a success status but a body the proxy could not parse
+pub const FAILURE_PARSE: &str = "parse";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RequestRecord {
@@ -19,6 +69,132 @@ pub struct RequestRecord {
     pub error_message: Option<String>,
     pub http_status: i32,
     pub was_streamed: bool,
+    pub sdk_name: Option<String>,
+    pub sdk_version: Option<String>,
+    pub user_agent: Option<String>,
+    pub client_ip: Option<String>,
+    pub client_id: Option<String>,
+    pub max_tokens: Option<i64>,
+    pub finish_reason: Option<String>,
+    pub proxy_request_id: Option<String>,
+    pub parent_id: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub retry_of: Option<String>,
+    pub retry_source: Option<String>,
+    pub deadline_ms: Option<i64>,
+    pub deadline_status: Option<String>,
+    pub deadline_remaining_forward_ms: Option<i64>,
+    pub deadline_remaining_response_ms: Option<i64>,
+    pub deadline_remaining_end_ms: Option<i64>,
+    pub termination: Option<String>,
+    pub queue_wait_ms: Option<i64>,
+    pub time_to_headers_ms: Option<i64>,
+    /// Upstream reported no usage, so the token counts are estimates
+    pub tokens_estimated: bool,
+    pub estimated_input_tokens: Option<i64>,
+    pub estimated_output_tokens: Option<i64>,
+    pub tokenizer: Option<String>,
+    pub estimated_energy_wh: Option<f64>,
+    /// Wall-clock elapsed minus monotonic elapsed, when they disagree significantly
+    pub clock_skew_ms: Option<i64>,
+    /// Protective limits that dropped or truncated data for this request
+    pub limits_hit: LimitTracker,
+    /// Time from forwarding to the first generated token, i.e. prompt processing
+    pub prompt_eval_ms: Option<i64>,
+    /// Estimated prompt tokens shared with the previous prompt in the same session
+    pub kv_prefix_tokens: Option<i64>,
+    /// The model's learned prompt-eval rate the classification was judged against
+    pub kv_baseline_tps: Option<f64>,
+    /// Heuristic KV-cache verdict: `likely_cache_hit` or `likely_full_reprocess`
+    pub kv_cache_class: Option<String>,
+    /// Billing namespace assigned from the client address
+    pub namespace: Option<String>,
+    /// Prices applied when the request was logged, in dollars per million tokens
+    pub input_price_per_m: Option<f64>,
+    pub output_price_per_m: Option<f64>,
+    /// Shape problems found in the request's messages
+    pub prompt_warnings: Vec<PromptWarning>,
+    /// Place in an agent tool loop: `initial`, `tool_followup` or `final`
+    pub agent_step: Option<String>,
+    /// Tokens of tool results and of user messages sent since the last assistant message
+    pub tool_result_tokens: Option<i64>,
+    pub new_user_tokens: Option<i64>,
+    /// Batch this request ran in, if it wasn't interactive
+    pub batch_id: Option<String>,
+    /// Incident in progress when the request arrived
+    pub incident_id: Option<String>,
+    /// Headers and raw response kept during an incident
+    pub incident_capture: Option<Value>,
+    /// Sampling seed the client asked for
+    pub seed: Option<i64>,
+    /// Other sampling parameters of a seeded request, as sent
+    pub sampling_params: Option<Value>,
+    pub system_fingerprint: Option<String>,
+    /// First request of the logical turn this one belongs to, assigned when stored
+    pub turn_id: Option<String>,
+    /// Time the user waited for the whole turn; only set on the turn's latest request
+    pub turn_latency_ms: Option<i64>,
+    /// Sampling parameters the guardrails clamped or filled in, with the client's values
+    pub param_adjustments: Option<Value>,
+    /// The request's `stop` sequences as a JSON list, with their count and total length
+    pub stop_sequences: Option<Value>,
+    pub stop_count: Option<i64>,
+    pub stop_chars: Option<i64>,
+    /// Best guess whether a `stop` finish came from one of the custom stop sequences
+    /// rather than the model's own end of sequence; NULL when it doesn't apply
+    pub stopped_by_custom_stop: Option<bool>,
+    /// Output tokens over the request's duration; NULL without output or duration
+    pub tokens_per_second: Option<f64>,
+    /// Milliseconds from the request finishing to its row being inserted
+    pub persist_lag_ms: Option<i64>,
+    /// Detected language of the new user content and of the output, with confidence
+    pub prompt_language: Option<String>,
+    pub prompt_language_confidence: Option<f64>,
+    pub output_language: Option<String>,
+    pub output_language_confidence: Option<f64>,
+    /// Benchmark this request was replayed for
+    pub benchmark_id: Option<String>,
+    /// Who produced `http_status`: [`STATUS_SOURCE_UPSTREAM`] or [`STATUS_SOURCE_PROXY`]
+    pub status_source: Option<String>,
+    /// Hash of the model and sampling parameters, the rest of a response cache's key
+    pub params_hash: Option<String>,
+    /// Upstream the request was sent to, `primary` unless a canary took it
+    pub upstream: Option<String>,
+    /// Fingerprint of the system prompt, see [`crate::proxy::prompt_version`]
+    pub prompt_version: Option<String>,
+    /// Time to the first content of a stream, from `start_time`
+    pub ttft_ms: Option<i64>,
+    /// What lenient body parsing had to work around, see [`crate::proxy::body_parse`]
+    pub body_parse_warning: Option<String>,
+    /// Stream data lines that weren't valid JSON, see [`crate::proxy::sse`]
+    pub stream_parse_errors: Option<i64>,
+    /// The first few of those lines
+    pub stream_parse_samples: Option<Value>,
+    /// A client library's warm-up request, see [`crate::proxy::probe`]
+    pub is_probe: bool,
+    /// Conversation named by the client's session header
+    pub session_id: Option<String>,
+    /// Hash of the whitespace-normalized prompt, see [`crate::proxy::prompt_hash`]
+    pub normalized_prompt_hash: Option<String>,
+    /// `chat`, `completion`, `embedding` or `other`, see [`super::kinds::kind_of`]
+    pub kind: String,
+    /// Where a failed request failed, one of the `FAILURE_*` stages; `None` for successes
+    /// and for requests the proxy refused itself
+    pub failure_stage: Option<String>,
+    /// The model's context window in tokens, from `MODEL_CONTEXT` when the request came in
+    pub context_length: Option<i64>,
+    /// Sampling parameters as forwarded, see [`crate::proxy::sampling`]; `None` when the
+    /// request left them out
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub presence_penalty: Option<f64>,
+    pub frequency_penalty: Option<f64>,
+    /// Monotonic start of the request, the source of `duration_ms`
+    #[serde(skip)]
+    pub started_at: Option<Instant>,
+    /// Monotonic time the request finished, the start of `persist_lag_ms`
+    #[serde(skip)]
+    pub completed_at: Option<Instant>,
 }
 
 impl RequestRecord {
@@ -29,6 +205,7 @@ impl RequestRecord {
         prompt: String,
     ) -> Self {
         Self {
+            kind: kinds::kind_of(&endpoint).to_string(),
             endpoint,
             model,
             start_time: start_time.to_rfc3339(),
@@ -44,6 +221,83 @@ impl RequestRecord {
             error_message: None,
             http_status: 200,
             was_streamed: false,
+            sdk_name: None,
+            sdk_version: None,
+            user_agent: None,
+            client_ip: None,
+            client_id: None,
+            max_tokens: None,
+            finish_reason: None,
+            proxy_request_id: Some(Uuid::new_v4().to_string()),
+            parent_id: None,
+            idempotency_key: None,
+            retry_of: None,
+            retry_source: None,
+            deadline_ms: None,
+            deadline_status: None,
+            deadline_remaining_forward_ms: None,
+            deadline_remaining_response_ms: None,
+            deadline_remaining_end_ms: None,
+            termination: None,
+            queue_wait_ms: None,
+            time_to_headers_ms: None,
+            tokens_estimated: false,
+            estimated_input_tokens: None,
+            estimated_output_tokens: None,
+            tokenizer: None,
+            estimated_energy_wh: None,
+            clock_skew_ms: None,
+            limits_hit: LimitTracker::default(),
+            prompt_eval_ms: None,
+            kv_prefix_tokens: None,
+            kv_baseline_tps: None,
+            kv_cache_class: None,
+            namespace: None,
+            input_price_per_m: None,
+            output_price_per_m: None,
+            prompt_warnings: Vec::new(),
+            agent_step: None,
+            tool_result_tokens: None,
+            new_user_tokens: None,
+            batch_id: None,
+            incident_id: None,
+            incident_capture: None,
+            seed: None,
+            sampling_params: None,
+            system_fingerprint: None,
+            turn_id: None,
+            turn_latency_ms: None,
+            param_adjustments: None,
+            stop_sequences: None,
+            stop_count: None,
+            stop_chars: None,
+            stopped_by_custom_stop: None,
+            tokens_per_second: None,
+            persist_lag_ms: None,
+            prompt_language: None,
+            prompt_language_confidence: None,
+            output_language: None,
+            output_language_confidence: None,
+            benchmark_id: None,
+            status_source: None,
+            params_hash: None,
+            upstream: None,
+            prompt_version: None,
+            ttft_ms: None,
+            body_parse_warning: None,
+            stream_parse_errors: None,
+            stream_parse_samples: None,
+            is_probe: false,
+            session_id: None,
+            normalized_prompt_hash: None,
+            failure_stage: None,
+            context_length: None,
+            temperature: None,
+            top_p: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            started_at: Some(Instant::now()),
+            completed_at: None,
         }
     }
 
@@ -56,54 +310,415 @@ impl RequestRecord {
         http_status: i32,
         was_streamed: bool,
     ) {
-        self.end_time = end_time.to_rfc3339();
         self.output = output;
         self.input_tokens = input_tokens;
         self.output_tokens = output_tokens;
         self.total_tokens = input_tokens + output_tokens;
         self.http_status = http_status;
+        self.status_source = Some(STATUS_SOURCE_UPSTREAM.to_string());
         self.was_streamed = was_streamed;
+        self.termination = Some(TERMINATION_COMPLETED.to_string());
+        self.finish_timing(end_time);
+        self.measure_throughput();
+    }
+
+    /// Sets `tokens_per_second` from the output tokens and duration. Requests that took
+    /// under a millisecond or produced nothing get none rather than a division by zero.
+    pub fn measure_throughput(&mut self) {
+        self.tokens_per_second = (self.duration_ms > 0 && self.output_tokens > 0).then(|| {
+            let rate = self.output_tokens as f64 / (self.duration_ms as f64 / 1000.0);
+            (rate * 100.0).round() / 100.0
+        });
+    }
+
+    /// Sets the end time and measures the duration on the monotonic clock.
+    ///
+    /// Wall-clock jumps (an NTP sync, say) during the request would otherwise turn into
+    /// wrong or negative durations. When the wall-clock delta disagrees with the
+    /// monotonic one by more than [`CLOCK_SKEW_THRESHOLD_MS`], the difference is kept
+    /// in `clock_skew_ms`.
+    fn finish_timing(&mut self, end_time: DateTime<Utc>) {
+        self.end_time = end_time.to_rfc3339();
+        self.completed_at = Some(Instant::now());
+
+        let wall_ms = DateTime::parse_from_rfc3339(&self.start_time)
+            .ok()
+            .map(|start| end_time.timestamp_millis() - start.timestamp_millis());
+
+        let Some(started_at) = self.started_at else {
+            // Not measured in this process; the wall clock is all there is
+            self.duration_ms = wall_ms.unwrap_or(0).max(0);
+            return;
+        };
 
-        // Calculate duration
-        if let (Ok(start), Ok(end)) = (
-            DateTime::parse_from_rfc3339(&self.start_time),
-            DateTime::parse_from_rfc3339(&self.end_time),
-        ) {
-            self.duration_ms = (end.timestamp_millis() - start.timestamp_millis()).max(0);
+        self.duration_ms = started_at.elapsed().as_millis() as i64;
+        if let Some(wall_ms) = wall_ms {
+            let skew = wall_ms - self.duration_ms;
+            if skew.abs() > CLOCK_SKEW_THRESHOLD_MS {
+                tracing::warn!(
+                    "Wall clock moved {} ms relative to the monotonic clock during request {}",
+                    skew,
+                    self.proxy_request_id.as_deref().unwrap_or_default()
+                );
+                self.clock_skew_ms = Some(skew);
+            }
         }
     }
 
+    /// Starts a fresh attempt at the same logical request, linked back to the original.
+    pub fn retry(&self, start_time: DateTime<Utc>, source: &str) -> Self {
+        let mut attempt = Self::new(
+            self.endpoint.clone(),
+            self.model.clone(),
+            start_time,
+            self.prompt.clone(),
+        );
+        attempt.sdk_name = self.sdk_name.clone();
+        attempt.sdk_version = self.sdk_version.clone();
+        attempt.user_agent = self.user_agent.clone();
+        attempt.client_ip = self.client_ip.clone();
+        attempt.client_id = self.client_id.clone();
+        attempt.namespace = self.namespace.clone();
+        attempt.batch_id = self.batch_id.clone();
+        attempt.benchmark_id = self.benchmark_id.clone();
+        attempt.incident_id = self.incident_id.clone();
+        attempt.incident_capture = self.incident_capture.clone();
+        attempt.seed = self.seed;
+        attempt.sampling_params = self.sampling_params.clone();
+        attempt.params_hash = self.params_hash.clone();
+        attempt.upstream = self.upstream.clone();
+        attempt.prompt_version = self.prompt_version.clone();
+        attempt.is_probe = self.is_probe;
+        attempt.session_id = self.session_id.clone();
+        attempt.normalized_prompt_hash = self.normalized_prompt_hash.clone();
+        attempt.body_parse_warning = self.body_parse_warning.clone();
+        attempt.param_adjustments = self.param_adjustments.clone();
+        attempt.stop_sequences = self.stop_sequences.clone();
+        attempt.stop_count = self.stop_count;
+        attempt.stop_chars = self.stop_chars;
+        attempt.max_tokens = self.max_tokens;
+        attempt.context_length = self.context_length;
+        attempt.temperature = self.temperature;
+        attempt.top_p = self.top_p;
+        attempt.presence_penalty = self.presence_penalty;
+        attempt.frequency_penalty = self.frequency_penalty;
+        attempt.prompt_warnings = self.prompt_warnings.clone();
+        attempt.parent_id = self.parent_id.clone();
+        attempt.idempotency_key = self.idempotency_key.clone();
+        attempt.retry_of = self
+            .retry_of
+            .clone()
+            .or_else(|| self.proxy_request_id.clone());
+        attempt.retry_source = Some(source.to_string());
+        attempt.deadline_ms = self.deadline_ms;
+        attempt
+    }
+
+    /// Rebuilds a record from a row of the `request_rows` view.
+    pub fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            endpoint: row.try_get("endpoint")?,
+            model: row.try_get("model")?,
+            start_time: row.try_get("start_time")?,
+            end_time: row.try_get("end_time")?,
+            duration_ms: row.try_get("duration_ms")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+            total_tokens: row.try_get("total_tokens")?,
+            prompt: row.try_get("prompt_text")?,
+            output: row.try_get("output_text")?,
+            request_id: row.try_get("request_id")?,
+            is_error: row.try_get("is_error")?,
+            error_message: row.try_get("error_message")?,
+            http_status: row.try_get("http_status")?,
+            was_streamed: row.try_get("was_streamed")?,
+            sdk_name: row.try_get("sdk_name")?,
+            sdk_version: row.try_get("sdk_version")?,
+            user_agent: row.try_get("user_agent")?,
+            client_ip: row.try_get("client_ip")?,
+            client_id: row.try_get("client_id")?,
+            max_tokens: row.try_get("max_tokens")?,
+            finish_reason: row.try_get("finish_reason")?,
+            proxy_request_id: row.try_get("proxy_request_id")?,
+            parent_id: row.try_get("parent_id")?,
+            idempotency_key: row.try_get("idempotency_key")?,
+            retry_of: row.try_get("retry_of")?,
+            retry_source: row.try_get("retry_source")?,
+            deadline_ms: row.try_get("deadline_ms")?,
+            deadline_status: row.try_get("deadline_status")?,
+            deadline_remaining_forward_ms: row.try_get("deadline_remaining_forward_ms")?,
+            deadline_remaining_response_ms: row.try_get("deadline_remaining_response_ms")?,
+            deadline_remaining_end_ms: row.try_get("deadline_remaining_end_ms")?,
+            termination: row.try_get("termination")?,
+            queue_wait_ms: row.try_get("queue_wait_ms")?,
+            time_to_headers_ms: row.try_get("time_to_headers_ms")?,
+            tokens_estimated: row.try_get("tokens_estimated")?,
+            estimated_input_tokens: row.try_get("estimated_input_tokens")?,
+            estimated_output_tokens: row.try_get("estimated_output_tokens")?,
+            tokenizer: row.try_get("tokenizer")?,
+            estimated_energy_wh: row.try_get("estimated_energy_wh")?,
+            clock_skew_ms: row.try_get("clock_skew_ms")?,
+            limits_hit: LimitTracker::from_json(
+                row.try_get::<Option<String>, _>("limits_hit")?.as_deref(),
+            ),
+            prompt_eval_ms: row.try_get("prompt_eval_ms")?,
+            kv_prefix_tokens: row.try_get("kv_prefix_tokens")?,
+            kv_baseline_tps: row.try_get("kv_baseline_tps")?,
+            kv_cache_class: row.try_get("kv_cache_class")?,
+            namespace: row.try_get("namespace")?,
+            input_price_per_m: row.try_get("input_price_per_m")?,
+            output_price_per_m: row.try_get("output_price_per_m")?,
+            prompt_warnings: prompt_check::from_json(
+                row.try_get::<Option<String>, _>("prompt_warnings")?.as_deref(),
+            ),
+            agent_step: row.try_get("agent_step")?,
+            tool_result_tokens: row.try_get("tool_result_tokens")?,
+            new_user_tokens: row.try_get("new_user_tokens")?,
+            batch_id: row.try_get("batch_id")?,
+            incident_id: row.try_get("incident_id")?,
+            incident_capture: row
+                .try_get::<Option<String>, _>("incident_capture")?
+                .and_then(|capture| serde_json::from_str(&capture).ok()),
+            seed: row.try_get("seed")?,
+            sampling_params: row
+                .try_get::<Option<String>, _>("sampling_params")?
+                .and_then(|params| serde_json::from_str(&params).ok()),
+            system_fingerprint: row.try_get("system_fingerprint")?,
+            turn_id: row.try_get("turn_id")?,
+            turn_latency_ms: row.try_get("turn_latency_ms")?,
+            param_adjustments: row
+                .try_get::<Option<String>, _>("param_adjustments")?
+                .and_then(|adjustments| serde_json::from_str(&adjustments).ok()),
+            stop_sequences: row
+                .try_get::<Option<String>, _>("stop_sequences")?
+                .and_then(|stops| serde_json::from_str(&stops).ok()),
+            stop_count: row.try_get("stop_count")?,
+            stop_chars: row.try_get("stop_chars")?,
+            stopped_by_custom_stop: row.try_get("stopped_by_custom_stop")?,
+            tokens_per_second: row.try_get("tokens_per_second")?,
+            persist_lag_ms: row.try_get("persist_lag_ms")?,
+            prompt_language: row.try_get("prompt_language")?,
+            prompt_language_confidence: row.try_get("prompt_language_confidence")?,
+            output_language: row.try_get("output_language")?,
+            output_language_confidence: row.try_get("output_language_confidence")?,
+            benchmark_id: row.try_get("benchmark_id")?,
+            status_source: row.try_get("status_source")?,
+            params_hash: row.try_get("params_hash")?,
+            upstream: row.try_get("upstream")?,
+            prompt_version: row.try_get("prompt_version")?,
+            ttft_ms: row.try_get("ttft_ms")?,
+            body_parse_warning: row.try_get("body_parse_warning")?,
+            stream_parse_errors: row.try_get("stream_parse_errors")?,
+            stream_parse_samples: row
+                .try_get::<Option<String>, _>("stream_parse_samples")?
+                .and_then(|samples| serde_json::from_str(&samples).ok()),
+            is_probe: row.try_get("is_probe")?,
+            session_id: row.try_get("session_id")?,
+            normalized_prompt_hash: row.try_get("normalized_prompt_hash")?,
+            kind: row.try_get("kind")?,
+            failure_stage: row.try_get("failure_stage")?,
+            context_length: row.try_get("context_length")?,
+            temperature: row.try_get("temperature")?,
+            top_p: row.try_get("top_p")?,
+            presence_penalty: row.try_get("presence_penalty")?,
+            frequency_penalty: row.try_get("frequency_penalty")?,
+            started_at: None,
+            completed_at: None,
+        })
+    }
+
+    /// Fails the request with a status the proxy produced itself.
     pub fn set_error(&mut self, end_time: DateTime<Utc>, error_message: String, http_status: i32) {
-        self.end_time = end_time.to_rfc3339();
         self.is_error = true;
         self.error_message = Some(error_message);
         self.http_status = http_status;
+        self.status_source = Some(STATUS_SOURCE_PROXY.to_string());
+        self.termination = Some(TERMINATION_ERROR.to_string());
+        self.finish_timing(end_time);
+    }
 
-        // Calculate duration
-        if let (Ok(start), Ok(end)) = (
-            DateTime::parse_from_rfc3339(&self.start_time),
-            DateTime::parse_from_rfc3339(&self.end_time),
-        ) {
-            self.duration_ms = (end.timestamp_millis() - start.timestamp_millis()).max(0);
-        }
+    /// Fails the request at the `upstream_error` stage with the status LM Studio answered with.
+    pub fn set_upstream_error(
+        &mut self,
+        end_time: DateTime<Utc>,
+        error_message: String,
+        http_status: i32,
+    ) {
+        self.set_error(end_time, error_message, http_status);
+        self.status_source = Some(STATUS_SOURCE_UPSTREAM.to_string());
+        self.failure_stage = Some(FAILURE_UPSTREAM_ERROR.to_string());
     }
 }
 
+/// Columns added to `requests` after the original schema. `schema.sql`
+/// creates them for new databases; older databases get them via `ALTER TABLE`.
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("sdk_name", "TEXT"),
+    ("sdk_version", "TEXT"),
+    ("user_agent", "TEXT"),
+    ("client_ip", "TEXT"),
+    ("client_id", "TEXT"),
+    ("max_tokens", "INTEGER"),
+    ("finish_reason", "TEXT"),
+    ("proxy_request_id", "TEXT"),
+    ("parent_id", "TEXT"),
+    ("idempotency_key", "TEXT"),
+    ("retry_of", "TEXT"),
+    ("retry_source", "TEXT"),
+    ("prompt_hash", "TEXT"),
+    ("output_hash", "TEXT"),
+    ("deadline_ms", "INTEGER"),
+    ("deadline_status", "TEXT"),
+    ("deadline_remaining_forward_ms", "INTEGER"),
+    ("deadline_remaining_response_ms", "INTEGER"),
+    ("deadline_remaining_end_ms", "INTEGER"),
+    ("termination", "TEXT"),
+    ("queue_wait_ms", "INTEGER"),
+    ("time_to_headers_ms", "INTEGER"),
+    ("tokens_estimated", "BOOLEAN DEFAULT 0"),
+    ("estimated_input_tokens", "INTEGER"),
+    ("estimated_output_tokens", "INTEGER"),
+    ("tokenizer", "TEXT"),
+    ("estimated_energy_wh", "REAL"),
+    ("clock_skew_ms", "INTEGER"),
+    ("limits_hit", "TEXT"),
+    ("prompt_eval_ms", "INTEGER"),
+    ("kv_prefix_tokens", "INTEGER"),
+    ("kv_baseline_tps", "REAL"),
+    ("kv_cache_class", "TEXT"),
+    ("namespace", "TEXT"),
+    ("input_price_per_m", "REAL"),
+    ("output_price_per_m", "REAL"),
+    ("prompt_warnings", "TEXT"),
+    ("agent_step", "TEXT"),
+    ("tool_result_tokens", "INTEGER"),
+    ("new_user_tokens", "INTEGER"),
+    ("batch_id", "TEXT"),
+    ("incident_id", "TEXT"),
+    ("incident_capture", "TEXT"),
+    ("seed", "INTEGER"),
+    ("sampling_params", "TEXT"),
+    ("system_fingerprint", "TEXT"),
+    ("turn_id", "TEXT"),
+    ("turn_latency_ms", "INTEGER"),
+    ("param_adjustments", "TEXT"),
+    ("stop_sequences", "TEXT"),
+    ("stop_count", "INTEGER"),
+    ("stop_chars", "INTEGER"),
+    ("stopped_by_custom_stop", "INTEGER"),
+    ("tokens_per_second", "REAL"),
+    ("persist_lag_ms", "INTEGER"),
+    ("prompt_language", "TEXT"),
+    ("prompt_language_confidence", "REAL"),
+    ("output_language", "TEXT"),
+    ("output_language_confidence", "REAL"),
+    ("benchmark_id", "TEXT"),
+    ("status_source", "TEXT"),
+    ("params_hash", "TEXT"),
+    ("upstream", "TEXT"),
+    ("prompt_version", "TEXT"),
+    ("ttft_ms", "INTEGER"),
+    ("body_parse_warning", "TEXT"),
+    ("stream_parse_errors", "INTEGER"),
+    ("stream_parse_samples", "TEXT"),
+    ("is_probe", "INTEGER NOT NULL DEFAULT 0"),
+    ("session_id", "TEXT"),
+    ("normalized_prompt_hash", "TEXT"),
+    ("kind", "TEXT"),
+    ("failure_stage", "TEXT"),
+    ("context_length", "INTEGER"),
+    ("temperature", "REAL"),
+    ("top_p", "REAL"),
+    ("presence_penalty", "REAL"),
+    ("frequency_penalty", "REAL"),
+];
+
 pub async fn init_db(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    // Upgrade an existing table first so indexes in schema.sql can reference new columns
+    add_missing_columns(pool).await?;
+
     let schema = include_str!("schema.sql");
     sqlx::raw_sql(schema).execute(pool).await?;
+    rollups::backfill_if_empty(pool).await?;
+    usage_rollups::backfill_if_empty(pool).await?;
+    utilization::backfill_if_empty(pool).await?;
+    search::backfill_index(pool).await?;
+    kinds::backfill_kinds(pool).await?;
+    Ok(())
+}
+
+async fn add_missing_columns(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let existing: Vec<String> =
+        sqlx::query_scalar("SELECT name FROM pragma_table_info('requests')")
+            .fetch_all(pool)
+            .await?;
+
+    // Fresh database - schema.sql will create the full table
+    if existing.is_empty() {
+        return Ok(());
+    }
+
+    for (name, definition) in ADDED_COLUMNS {
+        if !existing.iter().any(|column| column == name) {
+            tracing::info!("Adding column requests.{}", name);
+            sqlx::query(&format!(
+                "ALTER TABLE requests ADD COLUMN {} {}",
+                name, definition
+            ))
+            .execute(pool)
+            .await?;
+        }
+    }
+
     Ok(())
 }
 
+/// Inserts a request, storing its prompt and output as deduplicated blobs.
 pub async fn insert_request(pool: &SqlitePool, record: &RequestRecord) -> Result<i64, sqlx::Error> {
+    let prompt_hash = content_hash(&record.prompt);
+    let output_hash = content_hash(&record.output);
+
+    let mut tx = pool.begin().await?;
+    blobs::store_blob(&mut tx, &prompt_hash, &record.prompt).await?;
+    blobs::store_blob(&mut tx, &output_hash, &record.output).await?;
+    // After the first write, so the transaction already holds the write lock
+    let turn = turns::assign_turn(&mut tx, record).await?;
+    // Measured once the write lock is held, so waiting on other writers counts
+    let persist_lag_ms = record
+        .completed_at
+        .map(|completed_at| completed_at.elapsed().as_millis() as i64);
+
     let result = sqlx::query(
         r#"
         INSERT INTO requests (
             endpoint, model, start_time, end_time, duration_ms,
             input_tokens, output_tokens, total_tokens,
             prompt, output, request_id, is_error, error_message,
-            http_status, was_streamed
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            http_status, was_streamed, sdk_name, sdk_version, user_agent,
+            client_ip, client_id, max_tokens, finish_reason, proxy_request_id,
+            parent_id, idempotency_key, retry_of, retry_source,
+            prompt_hash, output_hash, deadline_ms, deadline_status,
+            deadline_remaining_forward_ms, deadline_remaining_response_ms,
+            deadline_remaining_end_ms, termination, queue_wait_ms, time_to_headers_ms,
+            tokens_estimated, estimated_input_tokens, estimated_output_tokens, tokenizer,
+            estimated_energy_wh, clock_skew_ms, limits_hit, prompt_eval_ms,
+            kv_prefix_tokens, kv_baseline_tps, kv_cache_class, namespace,
+            input_price_per_m, output_price_per_m, prompt_warnings, agent_step,
+            tool_result_tokens, new_user_tokens, batch_id, incident_id, incident_capture,
+            seed, sampling_params, system_fingerprint, turn_id, turn_latency_ms,
+            param_adjustments, stop_sequences, stop_count, stop_chars, stopped_by_custom_stop,
+            tokens_per_second, persist_lag_ms, prompt_language, prompt_language_confidence,
+            output_language, output_language_confidence, benchmark_id, status_source,
+            params_hash, upstream, prompt_version, ttft_ms, body_parse_warning,
+            stream_parse_errors, stream_parse_samples, is_probe, session_id,
+            normalized_prompt_hash, kind, failure_stage, context_length, temperature, top_p,
+            presence_penalty, frequency_penalty
+        ) VALUES (
+            ?, ?, ?, ?, ?, ?, ?, ?, '', '', ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+            ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+            ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+            ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
+        )
         "#,
     )
     .bind(&record.endpoint)
@@ -114,21 +729,110 @@ pub async fn insert_request(pool: &SqlitePool, record: &RequestRecord) -> Result
     .bind(record.input_tokens)
     .bind(record.output_tokens)
     .bind(record.total_tokens)
-    .bind(&record.prompt)
-    .bind(&record.output)
     .bind(&record.request_id)
     .bind(record.is_error)
     .bind(&record.error_message)
     .bind(record.http_status)
     .bind(record.was_streamed)
-    .execute(pool)
+    .bind(&record.sdk_name)
+    .bind(&record.sdk_version)
+    .bind(&record.user_agent)
+    .bind(&record.client_ip)
+    .bind(&record.client_id)
+    .bind(record.max_tokens)
+    .bind(&record.finish_reason)
+    .bind(&record.proxy_request_id)
+    .bind(&record.parent_id)
+    .bind(&record.idempotency_key)
+    .bind(&record.retry_of)
+    .bind(&record.retry_source)
+    .bind(&prompt_hash)
+    .bind(&output_hash)
+    .bind(record.deadline_ms)
+    .bind(&record.deadline_status)
+    .bind(record.deadline_remaining_forward_ms)
+    .bind(record.deadline_remaining_response_ms)
+    .bind(record.deadline_remaining_end_ms)
+    .bind(&record.termination)
+    .bind(record.queue_wait_ms)
+    .bind(record.time_to_headers_ms)
+    .bind(record.tokens_estimated)
+    .bind(record.estimated_input_tokens)
+    .bind(record.estimated_output_tokens)
+    .bind(&record.tokenizer)
+    .bind(record.estimated_energy_wh)
+    .bind(record.clock_skew_ms)
+    .bind(record.limits_hit.to_json())
+    .bind(record.prompt_eval_ms)
+    .bind(record.kv_prefix_tokens)
+    .bind(record.kv_baseline_tps)
+    .bind(&record.kv_cache_class)
+    .bind(&record.namespace)
+    .bind(record.input_price_per_m)
+    .bind(record.output_price_per_m)
+    .bind(prompt_check::to_json(&record.prompt_warnings))
+    .bind(&record.agent_step)
+    .bind(record.tool_result_tokens)
+    .bind(record.new_user_tokens)
+    .bind(&record.batch_id)
+    .bind(&record.incident_id)
+    .bind(record.incident_capture.as_ref().map(Value::to_string))
+    .bind(record.seed)
+    .bind(record.sampling_params.as_ref().map(Value::to_string))
+    .bind(&record.system_fingerprint)
+    .bind(turn.as_ref().map(|turn| turn.turn_id.as_str()))
+    .bind(turn.as_ref().map(|turn| turn.turn_latency_ms))
+    .bind(record.param_adjustments.as_ref().map(Value::to_string))
+    .bind(record.stop_sequences.as_ref().map(Value::to_string))
+    .bind(record.stop_count)
+    .bind(record.stop_chars)
+    .bind(record.stopped_by_custom_stop)
+    .bind(record.tokens_per_second)
+    .bind(persist_lag_ms)
+    .bind(&record.prompt_language)
+    .bind(record.prompt_language_confidence)
+    .bind(&record.output_language)
+    .bind(record.output_language_confidence)
+    .bind(&record.benchmark_id)
+    .bind(&record.status_source)
+    .bind(&record.params_hash)
+    .bind(&record.upstream)
+    .bind(&record.prompt_version)
+    .bind(record.ttft_ms)
+    .bind(&record.body_parse_warning)
+    .bind(record.stream_parse_errors)
+    .bind(record.stream_parse_samples.as_ref().map(Value::to_string))
+    .bind(record.is_probe)
+    .bind(&record.session_id)
+    .bind(&record.normalized_prompt_hash)
+    .bind(&record.kind)
+    .bind(&record.failure_stage)
+    .bind(record.context_length)
+    .bind(record.temperature)
+    .bind(record.top_p)
+    .bind(record.presence_penalty)
+    .bind(record.frequency_penalty)
+    .execute(&mut *tx)
     .await?;
+    rollups::add_request(&mut tx, record).await?;
+    usage_rollups::add_request(&mut tx, record).await?;
+    utilization::add_request(&mut tx, result.last_insert_rowid(), record).await?;
+    prompt_versions::register(&mut tx, record).await?;
+    tx.commit().await?;
 
     Ok(result.last_insert_rowid())
 }
 
 #[derive(Debug, Serialize)]
 pub struct SummaryStats {
+    /// Bounds of the window summarized, only present when a period was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// Whether closed weeks were read from the usage rollups. Their totals then also count
+    /// pruned requests, which the `detail` figures, ranked over stored requests, don't
+    pub totals_from_rollups: bool,
     pub total_requests: i64,
     pub successful_requests: i64,
     pub failed_requests: i64,
@@ -138,28 +842,270 @@ pub struct SummaryStats {
     pub avg_input_tokens: f64,
     pub avg_output_tokens: f64,
     pub avg_duration_ms: f64,
+    /// Output tokens per second, over requests with a measured rate
+    pub avg_tokens_per_second: f64,
+    pub max_tokens_per_second: f64,
+    /// Time to first token over successful streamed requests; `None` when there are none
+    pub avg_ttft_ms: Option<f64>,
+    /// Equivalent API cost of successful requests at their stored rates; `None` when none
+    /// were logged with a price
+    pub estimated_cost: Option<f64>,
+    /// Figures that scan the stored requests, only computed when asked for
+    #[serde(flatten)]
+    pub detail: Option<SummaryDetail>,
+    /// Energy and CO2 estimates, only present when power draw is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub energy_estimate: Option<EnergyEstimate>,
+    /// Live counts for the last hour, only available from the running server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_hour: Option<MinuteCount>,
+    /// The preceding window of equal length, only present when asked to compare
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comparison: Option<Box<SummaryComparison>>,
 }
 
-pub async fn get_summary_stats(pool: &SqlitePool) -> Result<SummaryStats, sqlx::Error> {
-    let row = sqlx::query(
-        r#"
-        SELECT
-            COUNT(*) as total_requests,
-            SUM(CASE WHEN is_error = 0 THEN 1 ELSE 0 END) as successful_requests,
-            SUM(CASE WHEN is_error = 1 THEN 1 ELSE 0 END) as failed_requests,
-            COALESCE(SUM(input_tokens), 0) as total_input_tokens,
-            COALESCE(SUM(output_tokens), 0) as total_output_tokens,
-            COALESCE(SUM(total_tokens), 0) as total_tokens,
-            COALESCE(AVG(CAST(input_tokens AS REAL)), 0.0) as avg_input_tokens,
-            COALESCE(AVG(CAST(output_tokens AS REAL)), 0.0) as avg_output_tokens,
-            COALESCE(AVG(CAST(duration_ms AS REAL)), 0.0) as avg_duration_ms
-        FROM requests
-        "#
-    )
-    .fetch_one(pool)
-    .await?;
+/// The parts of a summary read from the stored requests rather than the rollups. They
+/// cover only requests still stored, so next to rollup totals they can describe a
+/// shorter span.
+#[derive(Debug, Serialize)]
+pub struct SummaryDetail {
+    /// Start of the oldest and newest stored request the filters select, so a pruned or
+    /// young database isn't mistaken for a full history; `None` when none is stored
+    pub data_from: Option<String>,
+    pub data_to: Option<String>,
+    /// Start of the oldest closed rollup week counted in the totals that began before
+    /// `data_from`, when pruned requests still count there; `None` otherwise
+    pub rollup_from: Option<String>,
+    /// Stored requests the filters select
+    pub total_rows: i64,
+    /// Duration percentiles (nearest rank); 0 when there are none
+    pub p50_duration_ms: i64,
+    pub p90_duration_ms: i64,
+    pub p95_duration_ms: i64,
+    pub p99_duration_ms: i64,
+    pub p95_ttft_ms: Option<i64>,
+    pub most_truncating_client: Option<TruncatingClient>,
+    /// Tokens spent on attempts that were later retried
+    pub retry_overhead_tokens: i64,
+    pub abandoned_before_first_token: AbandonedStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SummaryComparison {
+    pub previous: SummaryStats,
+    pub change_pct: SummaryChange,
+}
+
+/// Percentage change from the previous window, to one decimal; `0` where both windows are
+/// zero and `None` where only the previous one is
+#[derive(Debug, Serialize)]
+pub struct SummaryChange {
+    pub requests: Option<f64>,
+    pub input_tokens: Option<f64>,
+    pub output_tokens: Option<f64>,
+    pub total_tokens: Option<f64>,
+    pub avg_duration_ms: Option<f64>,
+}
+
+impl SummaryComparison {
+    pub fn new(current: &SummaryStats, previous: SummaryStats) -> Self {
+        // An empty window averages to 0, which isn't a duration worth comparing
+        let avg_duration_ms = if current.total_requests > 0 {
+            percent_change(previous.avg_duration_ms, current.avg_duration_ms)
+        } else {
+            None
+        };
+        let change_pct = SummaryChange {
+            requests: percent_change(
+                previous.total_requests as f64,
+                current.total_requests as f64,
+            ),
+            input_tokens: percent_change(
+                previous.total_input_tokens as f64,
+                current.total_input_tokens as f64,
+            ),
+            output_tokens: percent_change(
+                previous.total_output_tokens as f64,
+                current.total_output_tokens as f64,
+            ),
+            total_tokens: percent_change(previous.total_tokens as f64, current.total_tokens as f64),
+            avg_duration_ms,
+        };
+        SummaryComparison {
+            previous,
+            change_pct,
+        }
+    }
+}
+
+/// Which requests a summary covers.
+#[derive(Debug, Default)]
+pub struct SummaryFilter<'a> {
+    /// Abandoned requests generated nothing, so they are left out unless set
+    pub include_abandoned: bool,
+    pub exclude_batches: bool,
+    pub include_benchmarks: bool,
+    /// Warm-up probes would drag down the averages, so they are left out unless set
+    pub include_probes: bool,
+    /// Every model when empty
+    pub models: &'a [String],
+    /// RFC 3339 bounds on `start_time`, `to` exclusive
+    pub from: Option<&'a str>,
+    pub to: Option<&'a str>,
+}
+
+impl<'a> SummaryFilter<'a> {
+    /// Binds `?1` to `?8` of [`SUMMARY_CONDITION`].
+    fn bind<'q, O>(
+        &self,
+        query: QueryScalar<'q, Sqlite, O, SqliteArguments<'q>>,
+    ) -> QueryScalar<'q, Sqlite, O, SqliteArguments<'q>>
+    where
+        'a: 'q,
+    {
+        let models = (!self.models.is_empty()).then(|| serde_json::json!(self.models).to_string());
+        query
+            .bind(self.include_abandoned)
+            .bind(TERMINATION_ABANDONED)
+            .bind(self.exclude_batches)
+            .bind(self.include_benchmarks)
+            .bind(models)
+            .bind(self.from)
+            .bind(self.to)
+            .bind(self.include_probes)
+    }
+
+    /// [`SummaryFilter::bind`] for queries returning rows.
+    fn bind_query<'q>(
+        &self,
+        query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    ) -> Query<'q, Sqlite, SqliteArguments<'q>>
+    where
+        'a: 'q,
+    {
+        let models = (!self.models.is_empty()).then(|| serde_json::json!(self.models).to_string());
+        query
+            .bind(self.include_abandoned)
+            .bind(TERMINATION_ABANDONED)
+            .bind(self.exclude_batches)
+            .bind(self.include_benchmarks)
+            .bind(models)
+            .bind(self.from)
+            .bind(self.to)
+            .bind(self.include_probes)
+    }
+
+    /// Whether the filter selects what the usage rollups count, so they can stand in for
+    /// the closed weeks.
+    fn matches_rollups(&self) -> bool {
+        !self.include_abandoned
+            && !self.exclude_batches
+            && !self.include_benchmarks
+            && !self.include_probes
+            && self.from.is_none()
+            && self.to.is_none()
+    }
+}
+
+/// Summarizes the requests `filter` selects. When the filter allows, closed weeks come
+/// from the usage rollups and so include pruned requests, and only this week's rows are
+/// read. With `detail`, the percentiles and other figures ranked or counted over every
+/// stored request are added.
+pub async fn get_summary_stats(
+    pool: &SqlitePool,
+    filter: &SummaryFilter<'_>,
+    detail: bool,
+) -> Result<SummaryStats, sqlx::Error> {
+    let SummaryFilter {
+        include_abandoned,
+        exclude_batches,
+        include_benchmarks,
+        include_probes,
+        models,
+        from,
+        to,
+    } = *filter;
+    let models = (!models.is_empty()).then(|| serde_json::json!(models).to_string());
+
+    let row = if filter.matches_rollups() {
+        // Closed weeks come from their rollups, so only this week's rows are scanned
+        sqlx::query(&format!(
+            r#"
+            SELECT
+                COALESCE(SUM(requests), 0) as total_requests,
+                COALESCE(SUM(CASE WHEN is_error = 0 THEN requests END), 0) as successful_requests,
+                COALESCE(SUM(CASE WHEN is_error = 1 THEN requests END), 0) as failed_requests,
+                COALESCE(SUM(input_tokens), 0) as total_input_tokens,
+                COALESCE(SUM(output_tokens), 0) as total_output_tokens,
+                COALESCE(SUM(total_tokens), 0) as total_tokens,
+                COALESCE(CAST(SUM(input_tokens) AS REAL) / SUM(requests), 0.0) as avg_input_tokens,
+                COALESCE(CAST(SUM(output_tokens) AS REAL) / SUM(requests), 0.0)
+                    as avg_output_tokens,
+                COALESCE(CAST(SUM(duration_sum) AS REAL) / SUM(requests), 0.0) as avg_duration_ms,
+                COALESCE(ROUND(SUM(tps_sum) / SUM(tps_count), 2), 0.0) as avg_tokens_per_second,
+                COALESCE(MAX(tps_max), 0.0) as max_tokens_per_second,
+                ROUND(CAST(SUM(CASE WHEN is_error = 0 THEN ttft_sum END) AS REAL)
+                    / SUM(CASE WHEN is_error = 0 THEN ttft_count END), 1) as avg_ttft_ms,
+                SUM(CASE WHEN is_error = 0 THEN cost END) as estimated_cost
+            FROM ({})
+            WHERE ?3 IS NULL OR model IN (SELECT value FROM json_each(?3))
+            "#,
+            usage_rollups::closed_weeks_and_current()
+        ))
+        .bind(PERIOD_WEEK)
+        .bind(TERMINATION_ABANDONED)
+        .bind(&models)
+        .fetch_one(pool)
+        .await?
+    } else {
+        sqlx::query(
+            r#"
+            SELECT
+                COUNT(*) as total_requests,
+                COALESCE(SUM(CASE WHEN is_error = 0 THEN 1 ELSE 0 END), 0) as successful_requests,
+                COALESCE(SUM(CASE WHEN is_error = 1 THEN 1 ELSE 0 END), 0) as failed_requests,
+                COALESCE(SUM(input_tokens), 0) as total_input_tokens,
+                COALESCE(SUM(output_tokens), 0) as total_output_tokens,
+                COALESCE(SUM(total_tokens), 0) as total_tokens,
+                COALESCE(AVG(CAST(input_tokens AS REAL)), 0.0) as avg_input_tokens,
+                COALESCE(AVG(CAST(output_tokens AS REAL)), 0.0) as avg_output_tokens,
+                COALESCE(AVG(CAST(duration_ms AS REAL)), 0.0) as avg_duration_ms,
+                COALESCE(ROUND(AVG(tokens_per_second), 2), 0.0) as avg_tokens_per_second,
+                COALESCE(MAX(tokens_per_second), 0.0) as max_tokens_per_second,
+                ROUND(AVG(CASE WHEN is_error = 0 THEN ttft_ms END), 1) as avg_ttft_ms,
+                SUM(CASE WHEN is_error = 0
+                    THEN (input_tokens * input_price_per_m + output_tokens * output_price_per_m) / 1e6
+                    END) as estimated_cost
+            FROM requests
+            WHERE (?1 OR termination IS NOT ?2) AND (NOT ?3 OR batch_id IS NULL)
+              AND (?4 OR benchmark_id IS NULL)
+              AND (?5 IS NULL OR model IN (SELECT value FROM json_each(?5)))
+              AND (?6 IS NULL OR start_time >= ?6) AND (?7 IS NULL OR start_time < ?7)
+              AND (?8 OR is_probe = 0)
+            "#
+        )
+        .bind(include_abandoned)
+        .bind(TERMINATION_ABANDONED)
+        .bind(exclude_batches)
+        .bind(include_benchmarks)
+        .bind(&models)
+        .bind(from)
+        .bind(to)
+        .bind(include_probes)
+        .fetch_one(pool)
+        .await?
+    };
+
+    let detail = if detail {
+        Some(get_summary_detail(pool, filter).await?)
+    } else {
+        None
+    };
 
     Ok(SummaryStats {
+        from: from.map(|s| s.to_string()),
+        to: to.map(|s| s.to_string()),
+        totals_from_rollups: filter.matches_rollups(),
         total_requests: row.try_get("total_requests")?,
         successful_requests: row.try_get("successful_requests")?,
         failed_requests: row.try_get("failed_requests")?,
@@ -169,9 +1115,135 @@ pub async fn get_summary_stats(pool: &SqlitePool) -> Result<SummaryStats, sqlx::
         avg_input_tokens: row.try_get("avg_input_tokens")?,
         avg_output_tokens: row.try_get("avg_output_tokens")?,
         avg_duration_ms: row.try_get("avg_duration_ms")?,
+        avg_tokens_per_second: row.try_get("avg_tokens_per_second")?,
+        max_tokens_per_second: row.try_get("max_tokens_per_second")?,
+        avg_ttft_ms: row.try_get("avg_ttft_ms")?,
+        estimated_cost: row
+            .try_get::<Option<f64>, _>("estimated_cost")?
+            .map(round_cost),
+        detail,
+        energy_estimate: None,
+        last_hour: None,
+        comparison: None,
+    })
+}
+
+/// The summary figures that need every stored request `filter` selects.
+async fn get_summary_detail(
+    pool: &SqlitePool,
+    filter: &SummaryFilter<'_>,
+) -> Result<SummaryDetail, sqlx::Error> {
+    let (from, to) = (filter.from, filter.to);
+    let models = (!filter.models.is_empty()).then(|| serde_json::json!(filter.models).to_string());
+    let coverage_sql = format!(
+        "SELECT MIN(start_time) as data_from, MAX(start_time) as data_to, COUNT(*) as total_rows
+         FROM requests WHERE {}",
+        SUMMARY_CONDITION
+    );
+    let coverage = filter.bind_query(sqlx::query(&coverage_sql)).fetch_one(pool).await?;
+    let data_from: Option<String> = coverage.try_get("data_from")?;
+    let mut rollup_from = None;
+    if filter.matches_rollups() {
+        // Rollups outlive pruned requests, so these totals can reach back to closed weeks
+        // no stored request falls in
+        rollup_from = sqlx::query_scalar(
+            r#"
+            SELECT MIN(period_start) || 'T00:00:00+00:00' FROM usage_rollups
+            WHERE period_type = ?1 AND period_start < date('now', '-6 days', 'weekday 1')
+              AND (?2 IS NULL OR period_start < date(substr(?2, 1, 10), '-6 days', 'weekday 1'))
+              AND (?3 IS NULL OR model IN (SELECT value FROM json_each(?3)))
+            "#,
+        )
+        .bind(PERIOD_WEEK)
+        .bind(&data_from)
+        .bind(&models)
+        .fetch_one(pool)
+        .await?;
+    }
+
+    // SQLite has no percentile function, so each one is read at its rank
+    let durations = ranked_percentiles(pool, filter, "duration_ms", "1", &[0.5, 0.9, 0.95, 0.99])
+        .await?
+        .unwrap_or_else(|| vec![0; 4]);
+    let p95_ttft_ms = ranked_percentiles(pool, filter, "ttft_ms", "is_error = 0", &[0.95])
+        .await?
+        .map(|ttfts| ttfts[0]);
+
+    Ok(SummaryDetail {
+        data_from,
+        data_to: coverage.try_get("data_to")?,
+        rollup_from,
+        total_rows: coverage.try_get("total_rows")?,
+        p50_duration_ms: durations[0],
+        p90_duration_ms: durations[1],
+        p95_duration_ms: durations[2],
+        p99_duration_ms: durations[3],
+        p95_ttft_ms,
+        most_truncating_client: get_most_truncating_client(pool, from, to, models.as_deref())
+            .await?,
+        retry_overhead_tokens: get_retry_stats(pool, from, to, models.as_deref())
+            .await?
+            .overhead_tokens,
+        abandoned_before_first_token: get_abandoned_stats(pool, from, to, models.as_deref())
+            .await?,
     })
 }
 
+/// Requests a summary selects from the table, with the binds [`SummaryFilter::bind`] adds.
+const SUMMARY_CONDITION: &str = r#"
+    (?1 OR termination IS NOT ?2) AND (NOT ?3 OR batch_id IS NULL)
+    AND (?4 OR benchmark_id IS NULL)
+    AND (?5 IS NULL OR model IN (SELECT value FROM json_each(?5)))
+    AND (?6 IS NULL OR start_time >= ?6) AND (?7 IS NULL OR start_time < ?7)
+    AND (?8 OR is_probe = 0)
+"#;
+
+/// Nearest-rank percentiles of `column` over the requests `filter` selects that also
+/// match `condition`; `None` when none of them has the column set. Each one is read as a
+/// single row at its rank, walking the column's index, so the rows are never fetched.
+async fn ranked_percentiles(
+    pool: &SqlitePool,
+    filter: &SummaryFilter<'_>,
+    column: &str,
+    condition: &str,
+    quantiles: &[f64],
+) -> Result<Option<Vec<i64>>, sqlx::Error> {
+    let selected = format!(
+        "FROM requests WHERE {} IS NOT NULL AND {} AND {}",
+        column, condition, SUMMARY_CONDITION
+    );
+    let count_sql = format!("SELECT COUNT(*) {}", selected);
+    let count: i64 = filter
+        .bind(sqlx::query_scalar(&count_sql))
+        .fetch_one(pool)
+        .await?;
+    if count == 0 {
+        return Ok(None);
+    }
+
+    let rank_sql = format!("SELECT {} {} ORDER BY {} LIMIT 1 OFFSET ?9", column, selected, column);
+    let mut values = Vec::with_capacity(quantiles.len());
+    for quantile in quantiles {
+        let rank = ((count as f64) * quantile).ceil() as i64;
+        let value: i64 = filter
+            .bind(sqlx::query_scalar(&rank_sql))
+            .bind(rank.clamp(1, count) - 1)
+            .fetch_one(pool)
+            .await?;
+        values.push(value);
+    }
+    Ok(Some(values))
+}
+
+/// Nearest-rank percentile of sorted durations, 0 when there are none.
+pub fn duration_percentile(sorted: &[i64], quantile: f64) -> i64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((sorted.len() as f64) * quantile).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
 #[derive(Debug, Serialize)]
 pub struct ModelStats {
     pub model: String,
@@ -180,91 +1252,706 @@ pub struct ModelStats {
     pub output_tokens: i64,
     pub total_tokens: i64,
     pub avg_tokens_per_request: f64,
+    pub avg_tokens_per_second: f64,
+    pub max_tokens_per_second: f64,
+    /// Time to first token over streamed requests; `None` when the model had none
+    pub avg_ttft_ms: Option<f64>,
+    pub p95_ttft_ms: Option<i64>,
+    /// `None` when none of the model's requests were logged with a price
+    pub estimated_cost: Option<f64>,
+}
+
+pub async fn get_model_stats(
+    pool: &SqlitePool,
+    exclude_batches: bool,
+    include_benchmarks: bool,
+    include_probes: bool,
+) -> Result<Vec<ModelStats>, sqlx::Error> {
+    let rows = if !exclude_batches && !include_benchmarks && !include_probes {
+        // Closed weeks come from their rollups, so only this week's rows are scanned
+        sqlx::query(&format!(
+            r#"
+            SELECT
+                model,
+                SUM(requests) as requests,
+                SUM(input_tokens) as input_tokens,
+                SUM(output_tokens) as output_tokens,
+                SUM(total_tokens) as total_tokens,
+                CAST(SUM(total_tokens) AS REAL) / SUM(requests) as avg_tokens_per_request,
+                COALESCE(ROUND(SUM(tps_sum) / SUM(tps_count), 2), 0.0) as avg_tokens_per_second,
+                COALESCE(MAX(tps_max), 0.0) as max_tokens_per_second,
+                ROUND(CAST(SUM(ttft_sum) AS REAL) / SUM(ttft_count), 1) as avg_ttft_ms,
+                SUM(cost) as estimated_cost
+            FROM ({})
+            WHERE is_error = 0
+            GROUP BY model
+            ORDER BY requests DESC
+            "#,
+            usage_rollups::closed_weeks_and_current()
+        ))
+        .bind(PERIOD_WEEK)
+        .bind(TERMINATION_ABANDONED)
+        .fetch_all(pool)
+        .await?
+    } else {
+        sqlx::query(
+            r#"
+            SELECT
+                model,
+                COUNT(*) as requests,
+                COALESCE(SUM(input_tokens), 0) as input_tokens,
+                COALESCE(SUM(output_tokens), 0) as output_tokens,
+                COALESCE(SUM(total_tokens), 0) as total_tokens,
+                COALESCE(AVG(CAST(total_tokens AS REAL)), 0.0) as avg_tokens_per_request,
+                COALESCE(ROUND(AVG(tokens_per_second), 2), 0.0) as avg_tokens_per_second,
+                COALESCE(MAX(tokens_per_second), 0.0) as max_tokens_per_second,
+                ROUND(AVG(ttft_ms), 1) as avg_ttft_ms,
+                SUM((input_tokens * input_price_per_m + output_tokens * output_price_per_m) / 1e6)
+                    as estimated_cost
+            FROM requests
+            WHERE is_error = 0 AND (NOT ?1 OR batch_id IS NULL) AND (?2 OR benchmark_id IS NULL)
+              AND (?3 OR is_probe = 0)
+            GROUP BY model
+            ORDER BY requests DESC
+            "#
+        )
+        .bind(exclude_batches)
+        .bind(include_benchmarks)
+        .bind(include_probes)
+        .fetch_all(pool)
+        .await?
+    };
+
+    let ttft_rows = sqlx::query(
+        r#"
+        SELECT model, ttft_ms
+        FROM requests
+        WHERE is_error = 0 AND ttft_ms IS NOT NULL
+          AND (NOT ?1 OR batch_id IS NULL) AND (?2 OR benchmark_id IS NULL)
+          AND (?3 OR is_probe = 0)
+        ORDER BY ttft_ms
+        "#,
+    )
+    .bind(exclude_batches)
+    .bind(include_benchmarks)
+    .bind(include_probes)
+    .fetch_all(pool)
+    .await?;
+    let mut ttfts: HashMap<String, Vec<i64>> = HashMap::new();
+    for row in ttft_rows {
+        ttfts
+            .entry(row.try_get("model")?)
+            .or_default()
+            .push(row.try_get("ttft_ms")?);
+    }
+
+    let mut stats = Vec::new();
+    for row in rows {
+        let model: String = row.try_get("model")?;
+        let p95_ttft_ms = ttfts
+            .get(&model)
+            .map(|ttfts| duration_percentile(ttfts, 0.95));
+        stats.push(ModelStats {
+            model,
+            requests: row.try_get("requests")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+            total_tokens: row.try_get("total_tokens")?,
+            avg_tokens_per_request: row.try_get("avg_tokens_per_request")?,
+            avg_tokens_per_second: row.try_get("avg_tokens_per_second")?,
+            max_tokens_per_second: row.try_get("max_tokens_per_second")?,
+            avg_ttft_ms: row.try_get("avg_ttft_ms")?,
+            p95_ttft_ms,
+            estimated_cost: row
+                .try_get::<Option<f64>, _>("estimated_cost")?
+                .map(round_cost),
+        });
+    }
+
+    Ok(stats)
 }
 
-pub async fn get_model_stats(pool: &SqlitePool) -> Result<Vec<ModelStats>, sqlx::Error> {
+/// One day's usage, overall or for one model.
+#[derive(Debug, Default, Serialize)]
+pub struct DailyUsage {
+    pub requests: i64,
+    pub errors: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub total_tokens: i64,
+    pub avg_duration_ms: f64,
+    /// Equivalent API cost of successful requests at their stored rates; `None` when none
+    /// were logged with a price
+    pub estimated_cost: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DailyModelUsage {
+    pub model: String,
+    #[serde(flatten)]
+    pub usage: DailyUsage,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DailyReport {
+    /// The UTC day covered, as `YYYY-MM-DD`
+    pub date: String,
+    pub totals: DailyUsage,
+    /// Most requests first
+    pub models: Vec<DailyModelUsage>,
+}
+
+/// Usage on one UTC day, per model and overall, leaving out abandoned requests and
+/// benchmark runs like the summary does. The totals are added up from the per-model rows,
+/// so the report takes a single query.
+pub async fn get_daily_report(
+    pool: &SqlitePool,
+    date: NaiveDate,
+) -> Result<DailyReport, sqlx::Error> {
+    let start = date.and_time(NaiveTime::MIN).and_utc();
+    let end = start + chrono::Duration::days(1);
     let rows = sqlx::query(
         r#"
         SELECT
             model,
             COUNT(*) as requests,
+            COALESCE(SUM(is_error), 0) as errors,
             COALESCE(SUM(input_tokens), 0) as input_tokens,
             COALESCE(SUM(output_tokens), 0) as output_tokens,
             COALESCE(SUM(total_tokens), 0) as total_tokens,
-            COALESCE(AVG(CAST(total_tokens AS REAL)), 0.0) as avg_tokens_per_request
+            COALESCE(SUM(duration_ms), 0) as total_duration_ms,
+            SUM(CASE WHEN is_error = 0
+                THEN (input_tokens * input_price_per_m + output_tokens * output_price_per_m) / 1e6
+                END) as estimated_cost
         FROM requests
-        WHERE is_error = 0
+        WHERE start_time >= ?1 AND start_time < ?2 AND termination IS NOT ?3
+          AND benchmark_id IS NULL
         GROUP BY model
-        ORDER BY requests DESC
+        ORDER BY requests DESC, model
         "#
     )
+    .bind(start.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .bind(TERMINATION_ABANDONED)
     .fetch_all(pool)
     .await?;
 
-    let mut stats = Vec::new();
+    let mut totals = DailyUsage::default();
+    let mut total_duration_ms = 0;
+    let mut models = Vec::new();
     for row in rows {
-        stats.push(ModelStats {
-            model: row.try_get("model")?,
-            requests: row.try_get("requests")?,
+        let requests: i64 = row.try_get("requests")?;
+        let duration_ms: i64 = row.try_get("total_duration_ms")?;
+        let usage = DailyUsage {
+            requests,
+            errors: row.try_get("errors")?,
             input_tokens: row.try_get("input_tokens")?,
             output_tokens: row.try_get("output_tokens")?,
             total_tokens: row.try_get("total_tokens")?,
-            avg_tokens_per_request: row.try_get("avg_tokens_per_request")?,
+            avg_duration_ms: duration_ms as f64 / requests as f64,
+            estimated_cost: row.try_get("estimated_cost")?,
+        };
+
+        totals.requests += usage.requests;
+        totals.errors += usage.errors;
+        totals.input_tokens += usage.input_tokens;
+        totals.output_tokens += usage.output_tokens;
+        totals.total_tokens += usage.total_tokens;
+        total_duration_ms += duration_ms;
+        if let Some(cost) = usage.estimated_cost {
+            totals.estimated_cost = Some(totals.estimated_cost.unwrap_or(0.0) + cost);
+        }
+        models.push(DailyModelUsage {
+            model: row.try_get("model")?,
+            usage: DailyUsage {
+                estimated_cost: usage.estimated_cost.map(round_cost),
+                ..usage
+            },
         });
     }
+    if totals.requests > 0 {
+        totals.avg_duration_ms = total_duration_ms as f64 / totals.requests as f64;
+    }
+    totals.estimated_cost = totals.estimated_cost.map(round_cost);
 
-    Ok(stats)
+    Ok(DailyReport {
+        date: date.format("%Y-%m-%d").to_string(),
+        totals,
+        models,
+    })
 }
 
 #[derive(Debug, Serialize)]
-pub struct RecentRequest {
-    pub id: i64,
+pub struct EndpointStats {
     pub endpoint: String,
-    pub model: String,
-    pub start_time: String,
-    pub duration_ms: i64,
-    pub input_tokens: i64,
-    pub output_tokens: i64,
-    pub is_error: bool,
+    pub requests: i64,
+    pub errors: i64,
+    pub total_tokens: i64,
+    pub avg_duration_ms: f64,
 }
 
-pub async fn get_recent_requests(
+/// Usage grouped by `/v1` endpoint, with the same filters as the summary plus an
+/// optional `since` cutoff.
+pub async fn get_endpoint_stats(
     pool: &SqlitePool,
-    limit: i64,
-) -> Result<Vec<RecentRequest>, sqlx::Error> {
+    since: Option<&str>,
+    include_abandoned: bool,
+    exclude_batches: bool,
+    include_benchmarks: bool,
+) -> Result<Vec<EndpointStats>, sqlx::Error> {
     let rows = sqlx::query(
         r#"
         SELECT
-            id,
             endpoint,
-            model,
-            start_time,
-            duration_ms,
-            input_tokens,
-            output_tokens,
-            is_error
+            COUNT(*) as requests,
+            SUM(CASE WHEN is_error = 1 THEN 1 ELSE 0 END) as errors,
+            COALESCE(SUM(total_tokens), 0) as total_tokens,
+            COALESCE(AVG(CAST(duration_ms AS REAL)), 0.0) as avg_duration_ms
         FROM requests
-        ORDER BY id DESC
-        LIMIT ?
+        WHERE (?1 IS NULL OR start_time >= ?1)
+          AND (?2 OR termination IS NOT ?3) AND (NOT ?4 OR batch_id IS NULL)
+          AND (?5 OR benchmark_id IS NULL)
+        GROUP BY endpoint
+        ORDER BY requests DESC
         "#
     )
-    .bind(limit)
+    .bind(since)
+    .bind(include_abandoned)
+    .bind(TERMINATION_ABANDONED)
+    .bind(exclude_batches)
+    .bind(include_benchmarks)
     .fetch_all(pool)
     .await?;
 
-    let mut requests = Vec::new();
+    let mut stats = Vec::new();
     for row in rows {
-        requests.push(RecentRequest {
+        stats.push(EndpointStats {
+            endpoint: row.try_get("endpoint")?,
+            requests: row.try_get("requests")?,
+            errors: row.try_get("errors")?,
+            total_tokens: row.try_get("total_tokens")?,
+            avg_duration_ms: row.try_get("avg_duration_ms")?,
+        });
+    }
+
+    Ok(stats)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentRequest {
+    /// Row id; `None` for requests only held in memory because they were never stored
+    pub id: Option<i64>,
+    pub proxy_request_id: Option<String>,
+    /// Response id from LM Studio, e.g. `chatcmpl-...`
+    pub request_id: Option<String>,
+    pub endpoint: String,
+    pub model: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub duration_ms: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub total_tokens: i64,
+    pub is_error: bool,
+    pub http_status: i32,
+    pub was_streamed: bool,
+}
+
+impl RecentRequest {
+    /// Reads the summary columns of a `requests` row, as selected by [`RECENT_COLUMNS`].
+    pub fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
             id: row.try_get("id")?,
+            proxy_request_id: row.try_get("proxy_request_id")?,
+            request_id: row.try_get("request_id")?,
             endpoint: row.try_get("endpoint")?,
             model: row.try_get("model")?,
             start_time: row.try_get("start_time")?,
+            end_time: row.try_get("end_time")?,
             duration_ms: row.try_get("duration_ms")?,
             input_tokens: row.try_get("input_tokens")?,
             output_tokens: row.try_get("output_tokens")?,
+            total_tokens: row.try_get("total_tokens")?,
             is_error: row.try_get("is_error")?,
-        });
+            http_status: row.try_get("http_status")?,
+            was_streamed: row.try_get("was_streamed")?,
+        })
     }
+}
+
+/// Columns of `requests` that make up a [`RecentRequest`]
+pub const RECENT_COLUMNS: &str = "id, proxy_request_id, request_id, endpoint, model, start_time, \
+    end_time, duration_ms, input_tokens, output_tokens, total_tokens, is_error, http_status, \
+    was_streamed";
 
-    Ok(requests)
+/// Which requests `/stats/recent` lists; every field left unset matches everything.
+#[derive(Debug, Default)]
+pub struct RecentFilter {
+    /// Only requests with a smaller row id, for paging
+    pub before_id: Option<i64>,
+    pub model: Option<String>,
+    pub endpoint: Option<String>,
+    pub errors_only: bool,
+}
+
+impl RecentFilter {
+    /// Same test as the database query, for requests held in memory.
+    pub fn matches(&self, request: &RecentRequest) -> bool {
+        self.before_id
+            .is_none_or(|before| request.id.is_some_and(|id| id < before))
+            && self
+                .model
+                .as_ref()
+                .is_none_or(|model| &request.model == model)
+            && self
+                .endpoint
+                .as_ref()
+                .is_none_or(|endpoint| &request.endpoint == endpoint)
+            && (!self.errors_only || request.is_error)
+    }
+}
+
+/// Loads one request, including its prompt and output, by proxy request id.
+pub async fn get_request(
+    pool: &SqlitePool,
+    proxy_request_id: &str,
+) -> Result<Option<StoredRequest>, sqlx::Error> {
+    let row = sqlx::query("SELECT * FROM request_rows WHERE proxy_request_id = ?")
+        .bind(proxy_request_id)
+        .fetch_optional(pool)
+        .await?;
+
+    row.as_ref().map(stored_request).transpose()
+}
+
+/// A stored request by its row id, the `id` listed by `/stats/recent`.
+pub async fn get_request_by_id(
+    pool: &SqlitePool,
+    id: i64,
+) -> Result<Option<StoredRequest>, sqlx::Error> {
+    let row = sqlx::query("SELECT * FROM request_rows WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+    row.as_ref().map(stored_request).transpose()
+}
+
+/// A stored request by the id LM Studio gave its response (`chatcmpl-...`), the newest
+/// if several share it.
+pub async fn get_request_by_response_id(
+    pool: &SqlitePool,
+    response_id: &str,
+) -> Result<Option<StoredRequest>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT * FROM request_rows WHERE request_id = ? ORDER BY id DESC LIMIT 1",
+    )
+    .bind(response_id)
+    .fetch_optional(pool)
+    .await?;
+
+    row.as_ref().map(stored_request).transpose()
+}
+
+fn stored_request(row: &SqliteRow) -> Result<StoredRequest, sqlx::Error> {
+    Ok(StoredRequest {
+        id: row.try_get("id")?,
+        record: RequestRecord::from_row(row)?,
+    })
+}
+
+/// Up to `limit` requests, newest first, optionally only those older than `before_id`.
+pub async fn get_recent_requests(
+    pool: &SqlitePool,
+    limit: i64,
+    filter: &RecentFilter,
+) -> Result<Vec<RecentRequest>, sqlx::Error> {
+    let mut query = QueryBuilder::<Sqlite>::new(format!(
+        "SELECT {} FROM requests WHERE 1 = 1",
+        RECENT_COLUMNS
+    ));
+    if let Some(before_id) = filter.before_id {
+        query.push(" AND id < ").push_bind(before_id);
+    }
+    if let Some(model) = &filter.model {
+        query.push(" AND model = ").push_bind(model);
+    }
+    if let Some(endpoint) = &filter.endpoint {
+        query.push(" AND endpoint = ").push_bind(endpoint);
+    }
+    if filter.errors_only {
+        query.push(" AND is_error = 1");
+    }
+    query.push(" ORDER BY id DESC LIMIT ").push_bind(limit);
+    let rows = query.build().fetch_all(pool).await?;
+
+    rows.iter().map(RecentRequest::from_row).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+
+    async fn insert(pool: &SqlitePool, model: &str, duration_ms: i64, ttft_ms: Option<i64>) {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            model.to_string(),
+            Utc::now(),
+            "hi".to_string(),
+        );
+        record.duration_ms = duration_ms;
+        record.ttft_ms = ttft_ms;
+        insert_request(pool, &record).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ranked_percentiles_match_sorting_every_row() {
+        let pool = memory_pool().await;
+        let mut durations = Vec::new();
+        for i in 0..37 {
+            let duration = (i * 7919) % 1000;
+            durations.push(duration);
+            let model = if i % 3 == 0 { "b" } else { "a" };
+            insert(&pool, model, duration, (i % 2 == 0).then_some(i)).await;
+        }
+        durations.sort();
+
+        let summary = get_summary_stats(&pool, &SummaryFilter::default(), true).await.unwrap();
+        let summary = summary.detail.unwrap();
+        assert_eq!(summary.p50_duration_ms, duration_percentile(&durations, 0.5));
+        assert_eq!(summary.p90_duration_ms, duration_percentile(&durations, 0.9));
+        assert_eq!(summary.p99_duration_ms, duration_percentile(&durations, 0.99));
+        let ttfts: Vec<i64> = (0..37).step_by(2).collect();
+        assert_eq!(summary.p95_ttft_ms, Some(duration_percentile(&ttfts, 0.95)));
+    }
+
+    #[tokio::test]
+    async fn empty_selection_has_zero_percentiles() {
+        let pool = memory_pool().await;
+        insert(&pool, "a", 500, None).await;
+        let models = ["missing".to_string()];
+        let filter = SummaryFilter {
+            models: &models,
+            ..Default::default()
+        };
+        let summary = get_summary_stats(&pool, &filter, true).await.unwrap();
+        let summary = summary.detail.unwrap();
+        assert_eq!(summary.p50_duration_ms, 0);
+        assert_eq!(summary.p95_ttft_ms, None);
+    }
+
+    #[tokio::test]
+    async fn coverage_follows_the_filter() {
+        let pool = memory_pool().await;
+        insert(&pool, "a", 100, None).await;
+        insert(&pool, "a", 200, None).await;
+        insert(&pool, "b", 300, None).await;
+
+        let models = ["b".to_string()];
+        let filter = SummaryFilter {
+            models: &models,
+            ..Default::default()
+        };
+        let summary = get_summary_stats(&pool, &filter, true).await.unwrap();
+        assert_eq!(summary.total_requests, 1);
+        let summary = summary.detail.unwrap();
+        assert_eq!(summary.total_rows, 1);
+
+        let all = get_summary_stats(&pool, &SummaryFilter::default(), true).await.unwrap();
+        let all = all.detail.unwrap();
+        assert_eq!(all.total_rows, 3);
+        assert!(summary.data_from > all.data_from);
+    }
+
+    #[tokio::test]
+    async fn pruned_weeks_are_reported_apart_from_the_stored_range() {
+        let pool = memory_pool().await;
+        let old = Utc::now() - chrono::Duration::days(30);
+        let endpoint = "/v1/chat/completions".to_string();
+        let mut record = RequestRecord::new(endpoint, "a".to_string(), old, "hi".to_string());
+        record.duration_ms = 100;
+        insert_request(&pool, &record).await.unwrap();
+        insert(&pool, "a", 100, None).await;
+        sqlx::query("DELETE FROM requests WHERE start_time < ?")
+            .bind((old + chrono::Duration::seconds(1)).to_rfc3339())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let summary = get_summary_stats(&pool, &SummaryFilter::default(), true).await.unwrap();
+        assert_eq!(summary.total_requests, 2);
+        let detail = summary.detail.unwrap();
+        assert_eq!(detail.total_rows, 1);
+        // Both bounds read the same way, the stored range apart from the rollups' reach
+        let weekday = chrono::Datelike::weekday(&old).num_days_from_monday();
+        let monday = old.date_naive() - chrono::Duration::days(weekday as i64);
+        assert_eq!(detail.rollup_from, Some(format!("{}T00:00:00+00:00", monday)));
+        for bound in [&detail.data_from, &detail.rollup_from] {
+            let bound = bound.as_deref().unwrap();
+            DateTime::parse_from_rfc3339(bound).unwrap_or_else(|_| panic!("{}", bound));
+        }
+        assert!(detail.data_from > detail.rollup_from);
+
+        // Without pruned weeks there is nothing to report
+        let pool = memory_pool().await;
+        insert(&pool, "a", 100, None).await;
+        let summary = get_summary_stats(&pool, &SummaryFilter::default(), true).await.unwrap();
+        assert_eq!(summary.detail.unwrap().rollup_from, None);
+    }
+
+    #[tokio::test]
+    async fn detail_is_only_read_when_asked_for() {
+        let pool = memory_pool().await;
+        insert(&pool, "a", 100, Some(20)).await;
+
+        let summary = get_summary_stats(&pool, &SummaryFilter::default(), false).await.unwrap();
+        assert!(summary.totals_from_rollups);
+        assert_eq!(summary.total_requests, 1);
+        assert!(summary.detail.is_none());
+        let json = serde_json::to_value(&summary).unwrap();
+        for field in ["total_rows", "data_from", "p95_duration_ms", "retry_overhead_tokens"] {
+            assert!(json.get(field).is_none(), "{} in {}", field, json);
+        }
+
+        // Asked for, the detail sits among the other fields
+        let models = ["a".to_string()];
+        let filter = SummaryFilter {
+            models: &models,
+            include_probes: true,
+            ..Default::default()
+        };
+        let summary = get_summary_stats(&pool, &filter, true).await.unwrap();
+        assert!(!summary.totals_from_rollups);
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["total_rows"], 1);
+        assert_eq!(json["p95_duration_ms"], 100);
+        assert_eq!(json["p95_ttft_ms"], 20);
+        assert_eq!(json["abandoned_before_first_token"]["total"], 0);
+    }
+
+    /// A record that started at `start` on the wall clock, `elapsed` ago on the monotonic one.
+    fn started(start: DateTime<Utc>, elapsed: std::time::Duration) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            start,
+            "hi".to_string(),
+        );
+        record.started_at = Some(Instant::now() - elapsed);
+        record
+    }
+
+    fn midnight_minus_one() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2026-03-01T23:59:59Z").unwrap().to_utc()
+    }
+
+    #[test]
+    fn forward_jump_keeps_the_monotonic_duration() {
+        let start = midnight_minus_one();
+        let mut record = started(start, std::time::Duration::from_secs(2));
+        let end = start + chrono::Duration::hours(1);
+        record.complete(end, "out".to_string(), 1, 10, 200, false);
+
+        assert!((2000..3000).contains(&record.duration_ms), "{}", record.duration_ms);
+        let skew = record.clock_skew_ms.unwrap();
+        assert_eq!(skew, 3_600_000 - record.duration_ms);
+        assert_eq!(record.end_time, end.to_rfc3339());
+        assert!(record.tokens_per_second.unwrap() > 3.0);
+    }
+
+    #[test]
+    fn backward_jump_never_goes_negative() {
+        let start = midnight_minus_one();
+        let mut record = started(start, std::time::Duration::from_secs(2));
+        record.set_error(start - chrono::Duration::minutes(10), "boom".to_string(), 500);
+
+        assert!((2000..3000).contains(&record.duration_ms), "{}", record.duration_ms);
+        assert_eq!(record.clock_skew_ms, Some(-600_000 - record.duration_ms));
+    }
+
+    #[test]
+    fn small_disagreement_is_not_skew() {
+        let start = Utc::now();
+        let mut record = started(start, std::time::Duration::from_millis(500));
+        let end = start + chrono::Duration::milliseconds(900);
+        record.complete(end, String::new(), 0, 0, 200, false);
+        assert_eq!(record.clock_skew_ms, None);
+    }
+
+    #[test]
+    fn without_a_monotonic_start_the_wall_clock_is_clamped() {
+        let start = midnight_minus_one();
+        let mut record = started(start, std::time::Duration::ZERO);
+        record.started_at = None;
+        record.set_error(start - chrono::Duration::seconds(5), "boom".to_string(), 500);
+        assert_eq!(record.duration_ms, 0);
+        assert_eq!(record.clock_skew_ms, None);
+
+        record.set_error(start + chrono::Duration::seconds(5), "boom".to_string(), 500);
+        assert_eq!(record.duration_ms, 5000);
+    }
+
+    #[tokio::test]
+    async fn skewed_requests_stay_in_their_start_day() {
+        let pool = memory_pool().await;
+        let start = midnight_minus_one();
+        let mut record = started(start, std::time::Duration::from_secs(2));
+        let end = start + chrono::Duration::hours(3);
+        record.complete(end, "out".to_string(), 1, 1, 200, false);
+        insert_request(&pool, &record).await.unwrap();
+
+        let day = get_daily_report(&pool, start.date_naive()).await.unwrap();
+        assert_eq!(day.totals.requests, 1);
+        assert!(day.totals.avg_duration_ms < 3000.0, "{}", day.totals.avg_duration_ms);
+        let next = start.date_naive().succ_opt().unwrap();
+        let next_day = get_daily_report(&pool, next).await.unwrap();
+        assert_eq!(next_day.totals.requests, 0);
+
+        let stored: Option<i64> = sqlx::query_scalar("SELECT clock_skew_ms FROM requests")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, record.clock_skew_ms);
+    }
+
+    #[tokio::test]
+    async fn probes_are_left_out_of_averages_unless_included() {
+        let pool = memory_pool().await;
+        insert(&pool, "a", 1000, None).await;
+        insert(&pool, "a", 3000, None).await;
+        for _ in 0..2 {
+            let mut probe = RequestRecord::new(
+                "/v1/chat/completions".to_string(),
+                "a".to_string(),
+                Utc::now(),
+                "hi".to_string(),
+            );
+            probe.duration_ms = 10;
+            probe.is_probe = true;
+            insert_request(&pool, &probe).await.unwrap();
+        }
+
+        let summary = get_summary_stats(&pool, &SummaryFilter::default(), true).await.unwrap();
+        assert_eq!(summary.total_requests, 2);
+        assert_eq!(summary.avg_duration_ms, 2000.0);
+        let filter = SummaryFilter {
+            include_probes: true,
+            ..Default::default()
+        };
+        let summary = get_summary_stats(&pool, &filter, true).await.unwrap();
+        assert_eq!(summary.total_requests, 4);
+        assert_eq!(summary.avg_duration_ms, 1005.0);
+
+        let models = get_model_stats(&pool, false, false, false).await.unwrap();
+        assert_eq!(models[0].requests, 2);
+        let models = get_model_stats(&pool, false, false, true).await.unwrap();
+        assert_eq!(models[0].requests, 4);
+
+        let stored: Vec<bool> = sqlx::query_scalar("SELECT is_probe FROM requests ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, [false, false, true, true]);
+    }
 }
@@ -0,0 +1,106 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool, sqlite::SqliteRow};
+
+use super::kinds::{KIND_CHAT, KIND_COMPLETION};
+
+/// Columns of the sampling parameters reported, in response order
+const PARAM_COLUMNS: [&str; 5] = [
+    "temperature",
+    "top_p",
+    "max_tokens",
+    "presence_penalty",
+    "frequency_penalty",
+];
+
+/// The values one sampling parameter was sent with.
+#[derive(Debug, Serialize)]
+pub struct ParamStats {
+    /// Requests that sent the parameter
+    pub sent: i64,
+    /// Requests that left it out, taking LM Studio's default
+    pub omitted: i64,
+    /// `None` when no request sent it
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub avg: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelParams {
+    pub model: String,
+    pub requests: i64,
+    pub temperature: ParamStats,
+    pub top_p: ParamStats,
+    pub max_tokens: ParamStats,
+    pub presence_penalty: ParamStats,
+    pub frequency_penalty: ParamStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParamReport {
+    pub since: Option<String>,
+    /// Busiest first
+    pub models: Vec<ModelParams>,
+}
+
+/// Sampling parameters per model over chat and completion requests from `since` onward.
+pub async fn get_param_stats(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<ParamReport, sqlx::Error> {
+    let aggregates: Vec<String> = PARAM_COLUMNS
+        .iter()
+        .map(|column| {
+            format!(
+                "COUNT({column}) as {column}_sent, MIN(CAST({column} AS REAL)) as {column}_min, \
+                 MAX(CAST({column} AS REAL)) as {column}_max, AVG({column}) as {column}_avg"
+            )
+        })
+        .collect();
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT model, COUNT(*) as requests, {}
+        FROM requests
+        WHERE kind IN (?2, ?3) AND (?1 IS NULL OR start_time >= ?1)
+        GROUP BY model
+        ORDER BY requests DESC, model
+        "#,
+        aggregates.join(", ")
+    ))
+    .bind(since)
+    .bind(KIND_CHAT)
+    .bind(KIND_COMPLETION)
+    .fetch_all(pool)
+    .await?;
+
+    let mut models = Vec::new();
+    for row in rows {
+        let requests: i64 = row.try_get("requests")?;
+        models.push(ModelParams {
+            model: row.try_get("model")?,
+            requests,
+            temperature: param_from_row(&row, "temperature", requests)?,
+            top_p: param_from_row(&row, "top_p", requests)?,
+            max_tokens: param_from_row(&row, "max_tokens", requests)?,
+            presence_penalty: param_from_row(&row, "presence_penalty", requests)?,
+            frequency_penalty: param_from_row(&row, "frequency_penalty", requests)?,
+        });
+    }
+
+    Ok(ParamReport {
+        since: since.map(str::to_string),
+        models,
+    })
+}
+
+fn param_from_row(row: &SqliteRow, column: &str, requests: i64) -> Result<ParamStats, sqlx::Error> {
+    let sent: i64 = row.try_get(format!("{column}_sent").as_str())?;
+    let value = |suffix: &str| row.try_get::<Option<f64>, _>(format!("{column}_{suffix}").as_str());
+    Ok(ParamStats {
+        sent,
+        omitted: requests - sent,
+        min: value("min")?,
+        max: value("max")?,
+        avg: value("avg")?.map(|avg| (avg * 1000.0).round() / 1000.0),
+    })
+}
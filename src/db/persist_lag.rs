@@ -0,0 +1,183 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+
+use super::models::duration_percentile;
+
+/// Distribution of persistence lag over a set of requests.
+#[derive(Debug, Default, Serialize)]
+pub struct LagStats {
+    pub requests: usize,
+    pub avg_ms: f64,
+    pub p50_ms: i64,
+    pub p95_ms: i64,
+    pub p99_ms: i64,
+    pub max_ms: i64,
+    /// Requests that took longer than `PERSIST_LAG_ALERT_MS` to become queryable
+    pub over_alert: usize,
+}
+
+impl LagStats {
+    fn from_sorted(lags: &[i64], alert_ms: i64) -> Self {
+        if lags.is_empty() {
+            return Self::default();
+        }
+        let avg = lags.iter().sum::<i64>() as f64 / lags.len() as f64;
+        Self {
+            requests: lags.len(),
+            avg_ms: (avg * 10.0).round() / 10.0,
+            p50_ms: duration_percentile(lags, 0.5),
+            p95_ms: duration_percentile(lags, 0.95),
+            p99_ms: duration_percentile(lags, 0.99),
+            max_ms: lags[lags.len() - 1],
+            over_alert: lags.iter().filter(|&&lag| lag > alert_ms).count(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HourlyLag {
+    /// Start of the UTC hour the requests finished in
+    pub hour: String,
+    #[serde(flatten)]
+    pub lag: LagStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PersistLagReport {
+    pub since: Option<String>,
+    pub alert_ms: i64,
+    pub totals: LagStats,
+    /// Oldest hour first, only hours with requests
+    pub by_hour: Vec<HourlyLag>,
+}
+
+/// Time from completion to insert of requests that finished since `since`.
+pub async fn get_persist_lag(
+    pool: &SqlitePool,
+    since: Option<&str>,
+    alert_ms: i64,
+) -> Result<PersistLagReport, sqlx::Error> {
+    let rows: Vec<(String, i64)> = sqlx::query_as(
+        r#"
+        SELECT strftime('%Y-%m-%dT%H:00:00Z', end_time) as hour, persist_lag_ms
+        FROM requests
+        WHERE persist_lag_ms IS NOT NULL AND (?1 IS NULL OR end_time >= ?1)
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut all = Vec::with_capacity(rows.len());
+    let mut hours: BTreeMap<String, Vec<i64>> = BTreeMap::new();
+    for (hour, lag) in rows {
+        all.push(lag);
+        hours.entry(hour).or_default().push(lag);
+    }
+    all.sort_unstable();
+
+    Ok(PersistLagReport {
+        since: since.map(|s| s.to_string()),
+        alert_ms,
+        totals: LagStats::from_sorted(&all, alert_ms),
+        by_hour: hours
+            .into_iter()
+            .map(|(hour, mut lags)| {
+                lags.sort_unstable();
+                HourlyLag {
+                    hour,
+                    lag: LagStats::from_sorted(&lags, alert_ms),
+                }
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use crate::db::{RequestRecord, insert_request};
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use std::time::Instant;
+
+    /// A request that finished `lag_ms` before it is inserted.
+    fn finished(end_time: DateTime<Utc>, lag_ms: u64) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            end_time,
+            "Tell me a story".to_string(),
+        );
+        record.end_time = end_time.to_rfc3339();
+        record.completed_at = Instant::now().checked_sub(std::time::Duration::from_millis(lag_ms));
+        record
+    }
+
+    async fn stored_lag(pool: &SqlitePool, record: &RequestRecord) -> Option<i64> {
+        sqlx::query_scalar("SELECT persist_lag_ms FROM requests WHERE proxy_request_id = ?")
+            .bind(&record.proxy_request_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn lag_runs_from_completion_to_insert() {
+        let pool = memory_pool().await;
+        let slow = finished(Utc::now(), 1_500);
+        insert_request(&pool, &slow).await.unwrap();
+        let mut untimed = finished(Utc::now(), 0);
+        untimed.completed_at = None;
+        insert_request(&pool, &untimed).await.unwrap();
+
+        let lag = stored_lag(&pool, &slow).await.unwrap();
+        assert!((1_500..2_500).contains(&lag), "{}", lag);
+        // Rows written without a completion time, e.g. imported ones, have no lag
+        assert_eq!(stored_lag(&pool, &untimed).await, None);
+    }
+
+    #[tokio::test]
+    async fn report_splits_by_hour_and_counts_alerts() {
+        let pool = memory_pool().await;
+        let ten = Utc.with_ymd_and_hms(2026, 5, 1, 10, 15, 0).unwrap();
+        let eleven = ten + Duration::hours(1);
+        for (end_time, lag_ms) in [(ten, 0), (ten, 200), (ten, 6_000), (eleven, 100)] {
+            insert_request(&pool, &finished(end_time, lag_ms)).await.unwrap();
+        }
+
+        let report = get_persist_lag(&pool, None, 5_000).await.unwrap();
+
+        assert_eq!(report.alert_ms, 5_000);
+        assert_eq!(report.totals.requests, 4);
+        assert_eq!(report.totals.over_alert, 1);
+        assert!(report.totals.max_ms >= 6_000, "{:?}", report.totals);
+        let hours: Vec<_> = report
+            .by_hour
+            .iter()
+            .map(|hour| (hour.hour.as_str(), hour.lag.requests, hour.lag.over_alert))
+            .collect();
+        assert_eq!(
+            hours,
+            [("2026-05-01T10:00:00Z", 3, 1), ("2026-05-01T11:00:00Z", 1, 0)]
+        );
+
+        let since = (eleven - Duration::minutes(1)).to_rfc3339();
+        let recent = get_persist_lag(&pool, Some(&since), 5_000).await.unwrap();
+        assert_eq!(recent.totals.requests, 1);
+        assert_eq!(recent.by_hour.len(), 1);
+    }
+
+    #[test]
+    fn stats_of_sorted_lags() {
+        let stats = LagStats::from_sorted(&[10, 20, 30, 40, 1_000], 100);
+        assert_eq!(stats.requests, 5);
+        assert_eq!(stats.avg_ms, 220.0);
+        assert_eq!(stats.p50_ms, 30);
+        assert_eq!(stats.p95_ms, 1_000);
+        assert_eq!(stats.max_ms, 1_000);
+        assert_eq!(stats.over_alert, 1);
+        assert_eq!(LagStats::from_sorted(&[], 100).requests, 0);
+    }
+}
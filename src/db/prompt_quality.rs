@@ -0,0 +1,133 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+#[derive(Debug, Serialize)]
+pub struct WarningCount {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Requests with at least one warning of this type
+    pub requests: i64,
+    pub occurrences: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientPromptQuality {
+    pub client_id: Option<String>,
+    pub requests: i64,
+    pub requests_with_warnings: i64,
+    pub warnings: Vec<WarningCount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromptQualityReport {
+    pub since: Option<String>,
+    /// Chat requests, the only ones whose messages are checked
+    pub checked_requests: i64,
+    pub requests_with_warnings: i64,
+    pub by_type: Vec<WarningCount>,
+    pub by_client: Vec<ClientPromptQuality>,
+}
+
+/// Message-shape warnings by type and by client, from the `prompt_warnings` column.
+pub async fn get_prompt_quality(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<PromptQualityReport, sqlx::Error> {
+    let totals = sqlx::query(
+        r#"
+        SELECT
+            COUNT(*) as checked_requests,
+            COALESCE(SUM(CASE WHEN prompt_warnings IS NOT NULL THEN 1 ELSE 0 END), 0)
+                as requests_with_warnings
+        FROM requests
+        WHERE endpoint LIKE '%/chat/completions' AND (?1 IS NULL OR start_time >= ?1)
+        "#,
+    )
+    .bind(since)
+    .fetch_one(pool)
+    .await?;
+
+    let type_rows = sqlx::query(
+        r#"
+        SELECT
+            r.client_id,
+            json_extract(w.value, '$.type') as kind,
+            COUNT(DISTINCT r.id) as requests,
+            COUNT(*) as occurrences
+        FROM requests r, json_each(r.prompt_warnings) w
+        WHERE r.prompt_warnings IS NOT NULL AND (?1 IS NULL OR r.start_time >= ?1)
+        GROUP BY r.client_id, kind
+        ORDER BY occurrences DESC, kind
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let client_rows = sqlx::query(
+        r#"
+        SELECT
+            client_id,
+            COUNT(*) as requests,
+            SUM(CASE WHEN prompt_warnings IS NOT NULL THEN 1 ELSE 0 END) as requests_with_warnings
+        FROM requests
+        WHERE endpoint LIKE '%/chat/completions' AND (?1 IS NULL OR start_time >= ?1)
+        GROUP BY client_id
+        HAVING requests_with_warnings > 0
+        ORDER BY requests_with_warnings DESC, client_id
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut by_type: Vec<WarningCount> = Vec::new();
+    let mut by_client = client_rows
+        .iter()
+        .map(|row| {
+            Ok(ClientPromptQuality {
+                client_id: row.try_get("client_id")?,
+                requests: row.try_get("requests")?,
+                requests_with_warnings: row.try_get("requests_with_warnings")?,
+                warnings: Vec::new(),
+            })
+        })
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+    for row in &type_rows {
+        let client_id: Option<String> = row.try_get("client_id")?;
+        let count = WarningCount {
+            kind: row.try_get("kind")?,
+            requests: row.try_get("requests")?,
+            occurrences: row.try_get("occurrences")?,
+        };
+
+        // A request belongs to one client, so per-client request counts add up
+        match by_type.iter_mut().find(|total| total.kind == count.kind) {
+            Some(total) => {
+                total.requests += count.requests;
+                total.occurrences += count.occurrences;
+            }
+            None => by_type.push(WarningCount {
+                kind: count.kind.clone(),
+                requests: count.requests,
+                occurrences: count.occurrences,
+            }),
+        }
+        if let Some(client) = by_client
+            .iter_mut()
+            .find(|client| client.client_id == client_id)
+        {
+            client.warnings.push(count);
+        }
+    }
+    by_type.sort_by(|a, b| b.occurrences.cmp(&a.occurrences).then(a.kind.cmp(&b.kind)));
+
+    Ok(PromptQualityReport {
+        since: since.map(|s| s.to_string()),
+        checked_requests: totals.try_get("checked_requests")?,
+        requests_with_warnings: totals.try_get("requests_with_warnings")?,
+        by_type,
+        by_client,
+    })
+}
@@ -0,0 +1,143 @@
+use serde::Serialize;
+use sqlx::{Row, SqliteConnection, SqlitePool};
+
+use super::models::{RequestRecord, TERMINATION_ABANDONED};
+
+/// Reported for requests without a system message
+pub const NO_PROMPT_VERSION: &str = "none";
+
+/// A system prompt fingerprint and its label.
+#[derive(Debug, Serialize)]
+pub struct PromptVersion {
+    pub hash: String,
+    pub first_seen: String,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromptVersionStats {
+    /// Fingerprint, or `none` for requests without a system message
+    pub prompt_version: String,
+    pub label: Option<String>,
+    pub first_seen: Option<String>,
+    pub last_seen: String,
+    pub requests: i64,
+    pub errors: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    /// Share of successful requests that stopped with finish_reason "length"
+    pub truncation_rate: Option<f64>,
+    /// Over successful requests
+    pub avg_output_tokens: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromptVersionReport {
+    pub since: Option<String>,
+    /// Newest version first, `none` last
+    pub versions: Vec<PromptVersionStats>,
+}
+
+/// Remembers the first time a request's system prompt fingerprint was seen.
+pub async fn register(
+    conn: &mut SqliteConnection,
+    record: &RequestRecord,
+) -> Result<(), sqlx::Error> {
+    let Some(hash) = &record.prompt_version else {
+        return Ok(());
+    };
+    sqlx::query(
+        r#"
+        INSERT INTO prompt_versions (hash, first_seen) VALUES (?, ?)
+        ON CONFLICT (hash) DO UPDATE SET first_seen = MIN(first_seen, excluded.first_seen)
+        "#,
+    )
+    .bind(hash)
+    .bind(&record.start_time)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Sets or clears a version's label; `None` if the fingerprint has never been seen.
+pub async fn set_prompt_version_label(
+    pool: &SqlitePool,
+    hash: &str,
+    label: Option<&str>,
+) -> Result<Option<PromptVersion>, sqlx::Error> {
+    let row = sqlx::query(
+        "UPDATE prompt_versions SET label = ? WHERE hash = ? RETURNING hash, first_seen, label",
+    )
+    .bind(label)
+    .bind(hash)
+    .fetch_optional(pool)
+    .await?;
+
+    row.map(|row| {
+        Ok(PromptVersion {
+            hash: row.try_get("hash")?,
+            first_seen: row.try_get("first_seen")?,
+            label: row.try_get("label")?,
+        })
+    })
+    .transpose()
+}
+
+/// Usage and output quality per system prompt version since `since`, leaving out
+/// abandoned requests and benchmark runs.
+pub async fn get_prompt_version_stats(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<PromptVersionReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            COALESCE(r.prompt_version, ?1) as prompt_version,
+            v.label,
+            v.first_seen,
+            MAX(r.start_time) as last_seen,
+            COUNT(*) as requests,
+            COALESCE(SUM(r.is_error), 0) as errors,
+            COALESCE(SUM(r.input_tokens), 0) as input_tokens,
+            COALESCE(SUM(r.output_tokens), 0) as output_tokens,
+            COALESCE(SUM(r.is_error = 0), 0) as successful,
+            COALESCE(SUM(r.is_error = 0 AND r.finish_reason = 'length'), 0) as length_hits,
+            AVG(CASE WHEN r.is_error = 0 THEN r.output_tokens END) as avg_output_tokens
+        FROM requests r
+        LEFT JOIN prompt_versions v ON v.hash = r.prompt_version
+        WHERE r.benchmark_id IS NULL
+          AND (r.termination IS NULL OR r.termination != ?2)
+          AND (?3 IS NULL OR r.start_time >= ?3)
+        GROUP BY r.prompt_version
+        ORDER BY r.prompt_version IS NULL, v.first_seen DESC
+        "#,
+    )
+    .bind(NO_PROMPT_VERSION)
+    .bind(TERMINATION_ABANDONED)
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut versions = Vec::new();
+    for row in rows {
+        let successful: i64 = row.try_get("successful")?;
+        let length_hits: i64 = row.try_get("length_hits")?;
+        versions.push(PromptVersionStats {
+            prompt_version: row.try_get("prompt_version")?,
+            label: row.try_get("label")?,
+            first_seen: row.try_get("first_seen")?,
+            last_seen: row.try_get("last_seen")?,
+            requests: row.try_get("requests")?,
+            errors: row.try_get("errors")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+            truncation_rate: (successful > 0).then(|| length_hits as f64 / successful as f64),
+            avg_output_tokens: row.try_get("avg_output_tokens")?,
+        });
+    }
+
+    Ok(PromptVersionReport {
+        since: since.map(|s| s.to_string()),
+        versions,
+    })
+}
@@ -0,0 +1,77 @@
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// Windows `/stats/rate` reports, by label and length in minutes
+pub const RATE_WINDOWS: &[(&str, i64)] = &[("5m", 5), ("1h", 60), ("24h", 24 * 60)];
+
+/// Load over one trailing window.
+#[derive(Debug, Serialize)]
+pub struct WindowRate {
+    pub window: &'static str,
+    pub requests: i64,
+    pub output_tokens: i64,
+    pub requests_per_minute: f64,
+    pub output_tokens_per_minute: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RateReport {
+    pub as_of: String,
+    /// Shortest window first
+    pub windows: Vec<WindowRate>,
+}
+
+/// Requests and output tokens per minute over each of [`RATE_WINDOWS`], counting every
+/// request that started within the window, failed ones included.
+///
+/// The cutoffs are formatted like the stored `start_time` values (`to_rfc3339`), which
+/// keeps the plain string comparison in SQL chronological.
+pub async fn get_rate_report(pool: &SqlitePool) -> Result<RateReport, sqlx::Error> {
+    let now = Utc::now();
+    let cutoffs: Vec<String> = RATE_WINDOWS
+        .iter()
+        .map(|(_, minutes)| (now - Duration::minutes(*minutes)).to_rfc3339())
+        .collect();
+
+    let row = sqlx::query(
+        r#"
+        SELECT
+            COALESCE(SUM(start_time >= ?1), 0) as requests_0,
+            COALESCE(SUM(CASE WHEN start_time >= ?1 THEN output_tokens END), 0) as output_0,
+            COALESCE(SUM(start_time >= ?2), 0) as requests_1,
+            COALESCE(SUM(CASE WHEN start_time >= ?2 THEN output_tokens END), 0) as output_1,
+            COUNT(*) as requests_2,
+            COALESCE(SUM(output_tokens), 0) as output_2
+        FROM requests
+        WHERE start_time >= ?3
+        "#,
+    )
+    .bind(&cutoffs[0])
+    .bind(&cutoffs[1])
+    .bind(&cutoffs[2])
+    .fetch_one(pool)
+    .await?;
+
+    let mut windows = Vec::new();
+    for (i, (window, minutes)) in RATE_WINDOWS.iter().enumerate() {
+        let requests: i64 = row.try_get(format!("requests_{i}").as_str())?;
+        let output_tokens: i64 = row.try_get(format!("output_{i}").as_str())?;
+        windows.push(WindowRate {
+            window,
+            requests,
+            output_tokens,
+            requests_per_minute: round_rate(requests as f64 / *minutes as f64),
+            output_tokens_per_minute: round_rate(output_tokens as f64 / *minutes as f64),
+        });
+    }
+
+    Ok(RateReport {
+        as_of: now.to_rfc3339(),
+        windows,
+    })
+}
+
+fn round_rate(rate: f64) -> f64 {
+    (rate * 1000.0).round() / 1000.0
+}
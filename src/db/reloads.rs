@@ -0,0 +1,77 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use crate::reloads::RELOAD_EVENT;
+
+/// Inferred idle unloads of one model and the latency they cost.
+#[derive(Debug, Serialize)]
+pub struct ModelReloads {
+    pub model: String,
+    pub reloads: i64,
+    pub total_extra_latency_ms: i64,
+    pub avg_extra_latency_ms: f64,
+    pub avg_gap_ms: f64,
+    /// Shortest idle gap that was followed by a reload; a keep-warm ping needs to come
+    /// more often than this
+    pub min_gap_ms: i64,
+    pub last_reload: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReloadReport {
+    pub since: Option<String>,
+    pub total_reloads: i64,
+    pub total_extra_latency_ms: i64,
+    /// Most latency lost first
+    pub models: Vec<ModelReloads>,
+}
+
+/// Reload events recorded since `since`, per model.
+pub async fn get_reload_report(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<ReloadReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            json_extract(detail, '$.model') as model,
+            COUNT(*) as reloads,
+            COALESCE(SUM(json_extract(detail, '$.extra_latency_ms')), 0) as total_extra_latency_ms,
+            COALESCE(AVG(json_extract(detail, '$.extra_latency_ms')), 0.0) as avg_extra_latency_ms,
+            COALESCE(AVG(json_extract(detail, '$.gap_ms')), 0.0) as avg_gap_ms,
+            COALESCE(MIN(json_extract(detail, '$.gap_ms')), 0) as min_gap_ms,
+            MAX(time) as last_reload
+        FROM proxy_events
+        WHERE kind = ?1 AND (?2 IS NULL OR time >= ?2)
+        GROUP BY model
+        ORDER BY total_extra_latency_ms DESC, model
+        "#,
+    )
+    .bind(RELOAD_EVENT)
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut models = Vec::new();
+    for row in rows {
+        models.push(ModelReloads {
+            model: row.try_get("model")?,
+            reloads: row.try_get("reloads")?,
+            total_extra_latency_ms: row.try_get("total_extra_latency_ms")?,
+            avg_extra_latency_ms: row.try_get("avg_extra_latency_ms")?,
+            avg_gap_ms: row.try_get("avg_gap_ms")?,
+            min_gap_ms: row.try_get("min_gap_ms")?,
+            last_reload: row.try_get("last_reload")?,
+        });
+    }
+
+    Ok(ReloadReport {
+        since: since.map(|s| s.to_string()),
+        total_reloads: models.iter().map(|model| model.reloads).sum(),
+        total_extra_latency_ms: models
+            .iter()
+            .map(|model| model.total_extra_latency_ms)
+            .sum(),
+        models,
+    })
+}
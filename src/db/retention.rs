@@ -0,0 +1,314 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// Rows a prune removes: requests started before `?1` (an RFC 3339 timestamp). Shared by
+/// the pruner and the simulation behind its dry run, so they always agree on what goes.
+const PRUNE_MATCH: &str = "start_time < ?1";
+
+/// Requests of one model that a prune would remove.
+#[derive(Debug, Serialize)]
+pub struct PrunedModel {
+    pub model: String,
+    pub requests: i64,
+    pub oldest: String,
+    pub newest: String,
+}
+
+/// What a prune would remove and leave behind, without deleting anything.
+#[derive(Debug, Serialize)]
+pub struct RetentionSimulation {
+    /// Requests started before this are removed
+    pub cutoff: String,
+    pub requests: i64,
+    /// Start of the oldest and newest removed request
+    pub oldest: Option<String>,
+    pub newest: Option<String>,
+    /// Requests that remain
+    pub remaining_requests: i64,
+    /// Prompt and output text of the removed requests, in bytes
+    pub text_bytes: i64,
+    /// Of which actually freed: inline text plus blobs no remaining request shares
+    pub freed_text_bytes: i64,
+    /// Most requests first
+    pub models: Vec<PrunedModel>,
+    pub database_bytes: i64,
+    /// Rough size after the prune and a `VACUUM`: the average row size outside of blobs
+    /// is taken off per removed request, along with the freed blobs
+    pub projected_database_bytes: i64,
+}
+
+/// Deletes requests that started before `cutoff`, returning how many were removed.
+pub async fn delete_requests_before(pool: &SqlitePool, cutoff: &str) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(&format!("DELETE FROM requests WHERE {PRUNE_MATCH}"))
+        .bind(cutoff)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Reports what [`delete_requests_before`] would remove for `cutoff`, without deleting.
+pub async fn simulate_prune(
+    pool: &SqlitePool,
+    cutoff: &str,
+) -> Result<RetentionSimulation, sqlx::Error> {
+    let totals = sqlx::query(&format!(
+        r#"
+        SELECT
+            COUNT(*) as requests,
+            MIN(start_time) as oldest,
+            MAX(start_time) as newest,
+            COALESCE(SUM(length(CAST(prompt_text AS BLOB)) + length(CAST(output_text AS BLOB))), 0)
+                as text_bytes,
+            COALESCE(SUM(length(CAST(prompt AS BLOB)) + length(CAST(output AS BLOB))), 0)
+                as inline_bytes
+        FROM request_rows
+        WHERE {PRUNE_MATCH}
+        "#
+    ))
+    .bind(cutoff)
+    .fetch_one(pool)
+    .await?;
+
+    // A blob goes once every reference to it is among the removed rows
+    let freed_blob_bytes: i64 = sqlx::query_scalar(&format!(
+        r#"
+        WITH refs AS (
+            SELECT prompt_hash as hash FROM requests WHERE {PRUNE_MATCH}
+            UNION ALL
+            SELECT output_hash FROM requests WHERE {PRUNE_MATCH}
+        )
+        SELECT COALESCE(SUM(length(CAST(b.content AS BLOB))), 0)
+        FROM (SELECT hash, COUNT(*) as refs FROM refs WHERE hash IS NOT NULL GROUP BY hash) r
+        JOIN blobs b ON b.hash = r.hash
+        WHERE b.refcount <= r.refs
+        "#
+    ))
+    .bind(cutoff)
+    .fetch_one(pool)
+    .await?;
+
+    let models = sqlx::query(&format!(
+        r#"
+        SELECT model, COUNT(*) as requests, MIN(start_time) as oldest, MAX(start_time) as newest
+        FROM requests
+        WHERE {PRUNE_MATCH}
+        GROUP BY model
+        ORDER BY requests DESC, model
+        "#
+    ))
+    .bind(cutoff)
+    .fetch_all(pool)
+    .await?
+    .iter()
+    .map(|row| {
+        Ok(PrunedModel {
+            model: row.try_get("model")?,
+            requests: row.try_get("requests")?,
+            oldest: row.try_get("oldest")?,
+            newest: row.try_get("newest")?,
+        })
+    })
+    .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+    let (database_bytes, free_bytes): (i64, i64) = sqlx::query_as(
+        r#"
+        SELECT page_count * page_size, freelist_count * page_size
+        FROM pragma_page_count(), pragma_page_size(), pragma_freelist_count()
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+    let (all_requests, all_blob_bytes): (i64, i64) = sqlx::query_as(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM requests),
+            (SELECT COALESCE(SUM(length(CAST(content AS BLOB))), 0) FROM blobs)
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let requests: i64 = totals.try_get("requests")?;
+    let inline_bytes: i64 = totals.try_get("inline_bytes")?;
+    let used_bytes = database_bytes - free_bytes;
+    let avg_row_bytes = if all_requests > 0 {
+        (used_bytes - all_blob_bytes).max(0) / all_requests
+    } else {
+        0
+    };
+
+    Ok(RetentionSimulation {
+        cutoff: cutoff.to_string(),
+        requests,
+        oldest: totals.try_get("oldest")?,
+        newest: totals.try_get("newest")?,
+        remaining_requests: all_requests - requests,
+        text_bytes: totals.try_get("text_bytes")?,
+        freed_text_bytes: inline_bytes + freed_blob_bytes,
+        models,
+        database_bytes,
+        projected_database_bytes: (used_bytes - requests * avg_row_bytes - freed_blob_bytes).max(0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use crate::db::{RequestRecord, insert_request};
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn start(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 1, day, 0, 0, 0).unwrap()
+    }
+
+    async fn insert(pool: &SqlitePool, day: u32, model: &str, prompt: &str, output: &str) {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            model.to_string(),
+            start(day),
+            prompt.to_string(),
+        );
+        record.output = output.to_string();
+        insert_request(pool, &record).await.unwrap();
+    }
+
+    /// Inserts a row the way they were written before blob storage.
+    async fn insert_inline(pool: &SqlitePool, day: u32, prompt: &str, output: &str) {
+        sqlx::query(
+            r#"
+            INSERT INTO requests (
+                endpoint, model, start_time, end_time, duration_ms,
+                input_tokens, output_tokens, total_tokens, prompt, output, http_status
+            )
+            VALUES ('/v1/chat/completions', 'legacy', ?1, ?1, 1, 0, 0, 0, ?2, ?3, 200)
+            "#,
+        )
+        .bind(start(day).to_rfc3339())
+        .bind(prompt)
+        .bind(output)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    /// Shared and unique blobs on both sides of day 10, inline rows, and a row exactly
+    /// at the cutoff.
+    async fn seed(pool: &SqlitePool) {
+        let system = "You are a helpful assistant. ".repeat(20);
+        insert(pool, 1, "a", &system, "first answer").await;
+        insert(pool, 2, "a", "only old", "only old").await;
+        insert(pool, 3, "b", "old and new", "old answer é").await;
+        insert(pool, 4, "b", &system, "second answer").await;
+        insert_inline(pool, 5, "inline prompt", "inline output").await;
+        insert(pool, 10, "a", &system, "at the cutoff").await;
+        insert(pool, 12, "b", "old and new", "new answer").await;
+        insert_inline(pool, 13, "kept inline", "kept").await;
+    }
+
+    async fn scalar(pool: &SqlitePool, sql: &str) -> i64 {
+        sqlx::query_scalar(sql).fetch_one(pool).await.unwrap()
+    }
+
+    async fn text_bytes(pool: &SqlitePool) -> i64 {
+        scalar(
+            pool,
+            r#"
+            SELECT
+                (SELECT COALESCE(SUM(length(CAST(content AS BLOB))), 0) FROM blobs)
+                + (SELECT COALESCE(SUM(length(CAST(prompt AS BLOB))
+                    + length(CAST(output AS BLOB))), 0) FROM requests)
+            "#,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn simulation_matches_what_the_prune_deletes() {
+        let pool = memory_pool().await;
+        seed(&pool).await;
+        let cutoff = start(10).to_rfc3339();
+
+        let simulation = simulate_prune(&pool, &cutoff).await.unwrap();
+        let models_before: Vec<(String, i64)> =
+            sqlx::query_as("SELECT model, COUNT(*) FROM requests GROUP BY model ORDER BY model")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        let text_before = text_bytes(&pool).await;
+        let removed_text: i64 = scalar(
+            &pool,
+            &format!(
+                "SELECT SUM(length(CAST(prompt_text AS BLOB)) + length(CAST(output_text AS BLOB)))
+                 FROM request_rows WHERE start_time < '{}'",
+                cutoff
+            ),
+        )
+        .await;
+
+        let deleted = delete_requests_before(&pool, &cutoff).await.unwrap();
+
+        assert_eq!(simulation.requests, deleted as i64);
+        assert_eq!(simulation.requests, 5);
+        let remaining = scalar(&pool, "SELECT COUNT(*) FROM requests").await;
+        assert_eq!(simulation.remaining_requests, remaining);
+        assert_eq!(simulation.oldest, Some(start(1).to_rfc3339()));
+        assert_eq!(simulation.newest, Some(start(5).to_rfc3339()));
+        assert_eq!(simulation.text_bytes, removed_text);
+        // Text still shared with a remaining row stays
+        assert_eq!(simulation.freed_text_bytes, text_before - text_bytes(&pool).await);
+        assert!(simulation.freed_text_bytes < simulation.text_bytes);
+
+        let models_after: Vec<(String, i64)> =
+            sqlx::query_as("SELECT model, COUNT(*) FROM requests GROUP BY model")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        let mut removed: Vec<(String, i64)> = models_before
+            .into_iter()
+            .map(|(model, before)| {
+                let after = models_after.iter().find(|(m, _)| *m == model).map_or(0, |m| m.1);
+                (model, before - after)
+            })
+            .filter(|(_, removed)| *removed > 0)
+            .collect();
+        removed.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(&y.0)));
+        let simulated: Vec<_> = simulation
+            .models
+            .iter()
+            .map(|model| (model.model.clone(), model.requests))
+            .collect();
+        assert_eq!(simulated, removed);
+    }
+
+    #[tokio::test]
+    async fn nothing_to_prune() {
+        let pool = memory_pool().await;
+        seed(&pool).await;
+
+        let simulation = simulate_prune(&pool, &start(1).to_rfc3339()).await.unwrap();
+
+        assert_eq!(simulation.requests, 0);
+        assert_eq!(simulation.remaining_requests, 8);
+        assert_eq!((simulation.oldest, simulation.newest), (None, None));
+        assert_eq!((simulation.text_bytes, simulation.freed_text_bytes), (0, 0));
+        assert!(simulation.models.is_empty());
+        assert!(simulation.projected_database_bytes <= simulation.database_bytes);
+        assert_eq!(delete_requests_before(&pool, &start(1).to_rfc3339()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn later_cutoffs_project_smaller_databases() {
+        let pool = memory_pool().await;
+        seed(&pool).await;
+
+        let mut projections = Vec::new();
+        for day in [1, 4, 11, 20] {
+            let simulation = simulate_prune(&pool, &start(day).to_rfc3339()).await.unwrap();
+            projections.push(simulation.projected_database_bytes);
+        }
+        assert!(projections.windows(2).all(|pair| pair[0] >= pair[1]), "{:?}", projections);
+        assert!(projections[0] > projections[3], "{:?}", projections);
+    }
+}
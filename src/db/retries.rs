@@ -0,0 +1,258 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Default, Serialize)]
+pub struct RetrySourceStats {
+    pub source: String,
+    /// Retry attempts issued by this source
+    pub attempts: i64,
+    /// Tokens spent on attempts that this source's retries superseded
+    pub overhead_tokens: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RetryStats {
+    pub since: Option<String>,
+    /// Logical requests that needed at least one retry
+    pub retried_requests: i64,
+    pub retry_attempts: i64,
+    /// Tokens consumed by attempts that were not the final one in their chain
+    pub overhead_tokens: i64,
+    /// Share of retried requests whose final attempt succeeded
+    pub success_after_retry_rate: Option<f64>,
+    pub by_source: Vec<RetrySourceStats>,
+}
+
+/// Finds the logical original for a client retry carrying `idempotency_key`.
+pub async fn find_retry_origin(
+    pool: &SqlitePool,
+    idempotency_key: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT COALESCE(retry_of, proxy_request_id)
+        FROM requests
+        WHERE idempotency_key = ?
+        ORDER BY start_time, id
+        LIMIT 1
+        "#,
+    )
+    .bind(idempotency_key)
+    .fetch_optional(pool)
+    .await
+    .map(Option::flatten)
+}
+
+/// Reports how much work was repeated by retries.
+///
+/// Attempts are grouped into chains by their logical original. Every attempt except
+/// the latest in a chain counts as overhead, attributed to the source of the retry
+/// that replaced it.
+pub async fn get_retry_stats(
+    pool: &SqlitePool,
+    since: Option<&str>,
+    to: Option<&str>,
+    models: Option<&str>,
+) -> Result<RetryStats, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        WITH attempts AS (
+            SELECT
+                COALESCE(r.retry_of, r.proxy_request_id) as chain_id,
+                r.id,
+                r.start_time,
+                r.total_tokens,
+                r.is_error,
+                r.retry_source
+            FROM requests r
+            WHERE (?1 IS NULL OR r.start_time >= ?1) AND (?2 IS NULL OR r.start_time < ?2)
+              AND (?3 IS NULL OR r.model IN (SELECT value FROM json_each(?3)))
+              AND (
+                r.retry_of IS NOT NULL
+                OR EXISTS (SELECT 1 FROM requests x WHERE x.retry_of = r.proxy_request_id)
+              )
+        )
+        SELECT
+            chain_id,
+            retry_source,
+            total_tokens,
+            is_error,
+            LEAD(retry_source) OVER chain as superseded_by,
+            ROW_NUMBER() OVER chain = COUNT(*) OVER (PARTITION BY chain_id) as is_final
+        FROM attempts
+        WINDOW chain AS (PARTITION BY chain_id ORDER BY start_time, id)
+        "#,
+    )
+    .bind(since)
+    .bind(to)
+    .bind(models)
+    .fetch_all(pool)
+    .await?;
+
+    let mut chains: HashMap<String, bool> = HashMap::new();
+    let mut by_source: BTreeMap<String, RetrySourceStats> = BTreeMap::new();
+    let mut retry_attempts = 0;
+    let mut overhead_tokens = 0;
+
+    for row in rows {
+        let chain_id: String = row.try_get("chain_id")?;
+        let retry_source: Option<String> = row.try_get("retry_source")?;
+        let superseded_by: Option<String> = row.try_get("superseded_by")?;
+        let total_tokens: i64 = row.try_get("total_tokens")?;
+        let is_error: bool = row.try_get("is_error")?;
+        let is_final: bool = row.try_get("is_final")?;
+
+        if let Some(source) = retry_source {
+            retry_attempts += 1;
+            let entry = by_source.entry(source.clone()).or_default();
+            entry.source = source;
+            entry.attempts += 1;
+        }
+
+        if is_final {
+            chains.insert(chain_id, !is_error);
+        } else {
+            overhead_tokens += total_tokens;
+            let source = superseded_by.unwrap_or_else(|| "unknown".to_string());
+            let entry = by_source.entry(source.clone()).or_default();
+            entry.source = source;
+            entry.overhead_tokens += total_tokens;
+        }
+    }
+
+    let retried_requests = chains.len() as i64;
+    let succeeded = chains.values().filter(|&&ok| ok).count() as i64;
+
+    Ok(RetryStats {
+        since: since.map(|s| s.to_string()),
+        retried_requests,
+        retry_attempts,
+        overhead_tokens,
+        success_after_retry_rate: (retried_requests > 0)
+            .then(|| succeeded as f64 / retried_requests as f64),
+        by_source: by_source.into_values().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use crate::db::{insert_request, RequestRecord};
+    use chrono::{DateTime, Duration, Utc};
+
+    fn attempt(start_time: DateTime<Utc>, total_tokens: i64) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            start_time,
+            "hi".to_string(),
+        );
+        record.total_tokens = total_tokens;
+        record
+    }
+
+    /// Links `record` the way the handler does for a repeated Idempotency-Key.
+    async fn as_client_retry(pool: &SqlitePool, record: &mut RequestRecord, key: &str) {
+        if let Some(original) = find_retry_origin(pool, key).await.unwrap() {
+            record.retry_of = Some(original);
+            record.retry_source = Some("client".to_string());
+        }
+        record.idempotency_key = Some(key.to_string());
+    }
+
+    #[tokio::test]
+    async fn client_retries_link_to_the_first_attempt() {
+        let pool = memory_pool().await;
+        let t0 = Utc::now() - Duration::minutes(5);
+
+        let mut first = attempt(t0, 10);
+        as_client_retry(&pool, &mut first, "key-1").await;
+        assert_eq!(first.retry_of, None);
+        insert_request(&pool, &first).await.unwrap();
+
+        let mut second = attempt(t0 + Duration::seconds(1), 10);
+        as_client_retry(&pool, &mut second, "key-1").await;
+        insert_request(&pool, &second).await.unwrap();
+
+        let mut third = attempt(t0 + Duration::seconds(2), 10);
+        as_client_retry(&pool, &mut third, "key-1").await;
+
+        assert_eq!(second.retry_of, first.proxy_request_id);
+        assert_eq!(third.retry_of, first.proxy_request_id);
+        assert_eq!(third.retry_source.as_deref(), Some("client"));
+        assert_eq!(find_retry_origin(&pool, "other").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn proxy_retries_keep_the_logical_original() {
+        let t0 = Utc::now();
+        let first = attempt(t0, 0);
+        let second = first.retry(t0 + Duration::seconds(1), "proxy-auto");
+        let third = second.retry(t0 + Duration::seconds(2), "proxy-auto");
+
+        assert_eq!(second.retry_of, first.proxy_request_id);
+        assert_eq!(third.retry_of, first.proxy_request_id);
+        assert_eq!(third.retry_source.as_deref(), Some("proxy-auto"));
+        assert_ne!(third.proxy_request_id, second.proxy_request_id);
+    }
+
+    #[tokio::test]
+    async fn overhead_is_charged_to_the_superseding_source() {
+        let pool = memory_pool().await;
+        let t0 = Utc::now() - Duration::minutes(5);
+
+        // A client retry whose own upstream attempt was retried by the proxy
+        let mut first = attempt(t0, 100);
+        first.set_error(t0 + Duration::milliseconds(10), "boom".to_string(), 500);
+        as_client_retry(&pool, &mut first, "key").await;
+        insert_request(&pool, &first).await.unwrap();
+
+        let mut second = attempt(t0 + Duration::seconds(1), 40);
+        as_client_retry(&pool, &mut second, "key").await;
+        second.set_error(t0 + Duration::seconds(2), "refused".to_string(), 502);
+        insert_request(&pool, &second).await.unwrap();
+
+        let third = second.retry(t0 + Duration::seconds(3), "proxy-auto");
+        insert_request(&pool, &third).await.unwrap();
+
+        // An unrelated request with no retries
+        insert_request(&pool, &attempt(t0, 1000)).await.unwrap();
+
+        let stats = get_retry_stats(&pool, None, None, None).await.unwrap();
+        assert_eq!(stats.retried_requests, 1);
+        assert_eq!(stats.retry_attempts, 2);
+        assert_eq!(stats.overhead_tokens, 140);
+        assert_eq!(stats.success_after_retry_rate, Some(1.0));
+
+        let sources: Vec<_> = stats
+            .by_source
+            .iter()
+            .map(|s| (s.source.as_str(), s.attempts, s.overhead_tokens))
+            .collect();
+        assert_eq!(sources, [("client", 1, 100), ("proxy-auto", 1, 40)]);
+    }
+
+    #[tokio::test]
+    async fn failed_final_attempt_is_not_a_success() {
+        let pool = memory_pool().await;
+        let t0 = Utc::now() - Duration::minutes(5);
+
+        let mut first = attempt(t0, 5);
+        first.set_error(t0, "refused".to_string(), 502);
+        insert_request(&pool, &first).await.unwrap();
+        let mut second = first.retry(t0 + Duration::seconds(1), "proxy-auto");
+        second.set_error(t0 + Duration::seconds(1), "refused".to_string(), 502);
+        insert_request(&pool, &second).await.unwrap();
+
+        let stats = get_retry_stats(&pool, None, None, None).await.unwrap();
+        assert_eq!(stats.overhead_tokens, 5);
+        assert_eq!(stats.success_after_retry_rate, Some(0.0));
+
+        let later = (t0 + Duration::minutes(1)).to_rfc3339();
+        let stats = get_retry_stats(&pool, Some(&later), None, None).await.unwrap();
+        assert_eq!(stats.retried_requests, 0);
+        assert_eq!(stats.success_after_retry_rate, None);
+    }
+}
@@ -0,0 +1,303 @@
+use chrono::{Duration, NaiveDate};
+use serde::Serialize;
+use sqlx::{Row, SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+
+use super::chargeback::round_cost;
+use super::models::{RequestRecord, TERMINATION_ABANDONED};
+
+/// Longest sparkline served, in days
+pub const MAX_SPARKLINE_DAYS: i64 = 31;
+
+/// One day's totals across all models.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct DayTotals {
+    pub requests: i64,
+    pub errors: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cost: f64,
+}
+
+impl DayTotals {
+    pub fn tokens(&self) -> i64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+/// Percent change of the sparkline window over the window just before it. `0` when both
+/// are zero, `None` when only the earlier one is.
+#[derive(Debug, Serialize)]
+pub struct PercentChange {
+    pub requests: Option<f64>,
+    pub tokens: Option<f64>,
+    pub cost: Option<f64>,
+}
+
+/// Daily values, oldest first, ending with today. Days without traffic are zeros.
+#[derive(Debug, Serialize)]
+pub struct Sparkline {
+    pub start: String,
+    pub requests: Vec<i64>,
+    /// Input plus output tokens
+    pub tokens: Vec<i64>,
+    pub cost: Vec<f64>,
+    pub change: PercentChange,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Glance {
+    /// Current UTC date
+    pub day: String,
+    pub today: DayTotals,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparkline: Option<Sparkline>,
+}
+
+/// Adds a request to its day's rollup, inside the transaction that inserts it.
+pub async fn add_request(
+    conn: &mut SqliteConnection,
+    record: &RequestRecord,
+) -> Result<(), sqlx::Error> {
+    if record.termination.as_deref() == Some(TERMINATION_ABANDONED) {
+        return Ok(());
+    }
+
+    let cost = if record.is_error {
+        0.0
+    } else {
+        record.input_tokens as f64 * record.input_price_per_m.unwrap_or(0.0) / 1e6
+            + record.output_tokens as f64 * record.output_price_per_m.unwrap_or(0.0) / 1e6
+    };
+    sqlx::query(
+        r#"
+        INSERT INTO daily_rollups (day, model, requests, errors, input_tokens, output_tokens, cost)
+        VALUES (substr(?, 1, 10), ?, 1, ?, ?, ?, ?)
+        ON CONFLICT (day, model) DO UPDATE SET
+            requests = requests + 1,
+            errors = errors + excluded.errors,
+            input_tokens = input_tokens + excluded.input_tokens,
+            output_tokens = output_tokens + excluded.output_tokens,
+            cost = cost + excluded.cost
+        "#,
+    )
+    .bind(&record.start_time)
+    .bind(&record.model)
+    .bind(record.is_error as i64)
+    .bind(record.input_tokens)
+    .bind(record.output_tokens)
+    .bind(cost)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Fills an empty rollup table from the requests already stored, so databases from
+/// before rollups existed start with their history.
+pub async fn backfill_if_empty(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let populated: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM daily_rollups)")
+        .fetch_one(pool)
+        .await?;
+    if populated {
+        return Ok(());
+    }
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO daily_rollups (day, model, requests, errors, input_tokens, output_tokens, cost)
+        SELECT
+            substr(start_time, 1, 10),
+            model,
+            COUNT(*),
+            SUM(is_error),
+            COALESCE(SUM(input_tokens), 0),
+            COALESCE(SUM(output_tokens), 0),
+            COALESCE(SUM(CASE WHEN is_error = 0 THEN
+                (input_tokens * COALESCE(input_price_per_m, 0)
+                 + output_tokens * COALESCE(output_price_per_m, 0)) / 1e6
+            END), 0)
+        FROM requests
+        WHERE termination IS NOT ?
+        GROUP BY 1, 2
+        "#,
+    )
+    .bind(TERMINATION_ABANDONED)
+    .execute(pool)
+    .await?;
+    if result.rows_affected() > 0 {
+        tracing::info!("Built {} daily rollup rows", result.rows_affected());
+    }
+
+    Ok(())
+}
+
+/// Today's totals, plus a sparkline of the last `days` days when asked for one.
+pub async fn get_glance(
+    pool: &SqlitePool,
+    today: NaiveDate,
+    days: Option<i64>,
+) -> Result<Glance, sqlx::Error> {
+    // The window before the sparkline is read too, as the baseline of its change
+    let span = days.unwrap_or(1);
+    let first = today - Duration::days(2 * span - 1);
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            day,
+            SUM(requests) as requests,
+            SUM(errors) as errors,
+            SUM(input_tokens) as input_tokens,
+            SUM(output_tokens) as output_tokens,
+            SUM(cost) as cost
+        FROM daily_rollups
+        WHERE day >= ?
+        GROUP BY day
+        "#,
+    )
+    .bind(first.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    let mut by_day = HashMap::new();
+    for row in &rows {
+        by_day.insert(
+            row.try_get::<String, _>("day")?,
+            DayTotals {
+                requests: row.try_get("requests")?,
+                errors: row.try_get("errors")?,
+                input_tokens: row.try_get("input_tokens")?,
+                output_tokens: row.try_get("output_tokens")?,
+                cost: row.try_get("cost")?,
+            },
+        );
+    }
+    let totals: Vec<DayTotals> = (0..2 * span)
+        .map(|offset| {
+            let day = first + Duration::days(offset);
+            by_day.get(&day.to_string()).copied().unwrap_or_default()
+        })
+        .collect();
+
+    let mut today_totals = totals.last().copied().unwrap_or_default();
+    today_totals.cost = round_cost(today_totals.cost);
+    let sparkline = days.map(|days| {
+        let (previous, current) = totals.split_at(days as usize);
+        Sparkline {
+            start: (today - Duration::days(days - 1)).to_string(),
+            requests: current.iter().map(|day| day.requests).collect(),
+            tokens: current.iter().map(DayTotals::tokens).collect(),
+            cost: current.iter().map(|day| round_cost(day.cost)).collect(),
+            change: PercentChange {
+                requests: percent_change(
+                    sum(previous, |day| day.requests as f64),
+                    sum(current, |day| day.requests as f64),
+                ),
+                tokens: percent_change(
+                    sum(previous, |day| day.tokens() as f64),
+                    sum(current, |day| day.tokens() as f64),
+                ),
+                cost: percent_change(sum(previous, |day| day.cost), sum(current, |day| day.cost)),
+            },
+        }
+    });
+
+    Ok(Glance {
+        day: today.to_string(),
+        today: today_totals,
+        sparkline,
+    })
+}
+
+/// Totals across all models from `first` through today, read from the rollups alone.
+pub async fn get_totals_since(
+    pool: &SqlitePool,
+    first: NaiveDate,
+) -> Result<DayTotals, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            COALESCE(SUM(requests), 0) as requests,
+            COALESCE(SUM(errors), 0) as errors,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens,
+            COALESCE(SUM(cost), 0.0) as cost
+        FROM daily_rollups
+        WHERE day >= ?
+        "#,
+    )
+    .bind(first.to_string())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(DayTotals {
+        requests: row.try_get("requests")?,
+        errors: row.try_get("errors")?,
+        input_tokens: row.try_get("input_tokens")?,
+        output_tokens: row.try_get("output_tokens")?,
+        cost: round_cost(row.try_get("cost")?),
+    })
+}
+
+fn sum(days: &[DayTotals], value: impl Fn(&DayTotals) -> f64) -> f64 {
+    days.iter().map(value).sum()
+}
+
+/// Change from `baseline` to `current` in percent, to one decimal. `0` when both are zero,
+/// `None` when only `baseline` is.
+pub(crate) fn percent_change(baseline: f64, current: f64) -> Option<f64> {
+    if baseline == 0.0 {
+        return (current == 0.0).then_some(0.0);
+    }
+    Some(((current - baseline) / baseline * 1000.0).round() / 10.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use crate::db::{RequestRecord, insert_request};
+    use chrono::{TimeZone, Utc};
+
+    async fn insert(pool: &SqlitePool, day: u32, tokens: (i64, i64), is_error: bool) {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            Utc.with_ymd_and_hms(2026, 3, day, 12, 0, 0).unwrap(),
+            "hi".to_string(),
+        );
+        (record.input_tokens, record.output_tokens) = tokens;
+        record.is_error = is_error;
+        record.input_price_per_m = Some(2.0);
+        record.output_price_per_m = Some(10.0);
+        insert_request(pool, &record).await.unwrap();
+    }
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 3, day).unwrap()
+    }
+
+    #[tokio::test]
+    async fn totals_since_cover_the_days_from_the_first_on() {
+        let pool = memory_pool().await;
+        insert(&pool, 1, (1_000_000, 100_000), false).await;
+        insert(&pool, 14, (500_000, 50_000), false).await;
+        insert(&pool, 15, (2_000_000, 0), false).await;
+        // Errors count as requests and tokens but cost nothing
+        insert(&pool, 15, (10, 10), true).await;
+
+        let all = get_totals_since(&pool, day(1)).await.unwrap();
+        assert_eq!(all.requests, 4);
+        assert_eq!(all.errors, 1);
+        assert_eq!(all.tokens(), 3_650_020);
+        assert_eq!(all.cost, 2.0 + 1.0 + 1.0 + 0.5 + 4.0);
+
+        let from_14th = get_totals_since(&pool, day(14)).await.unwrap();
+        assert_eq!(from_14th.requests, 3);
+        assert_eq!(from_14th.tokens(), 2_550_020);
+        assert_eq!(from_14th.cost, 5.5);
+
+        let later = get_totals_since(&pool, day(16)).await.unwrap();
+        assert_eq!((later.requests, later.tokens(), later.cost), (0, 0, 0.0));
+    }
+}
@@ -15,11 +15,14 @@ CREATE TABLE IF NOT EXISTS requests (
     output_tokens INTEGER NOT NULL,
     total_tokens INTEGER NOT NULL,
 
-    -- Content
+    -- Content (legacy inline text; new rows store it in blobs and leave these empty)
     prompt TEXT NOT NULL,
     output TEXT NOT NULL,
+    prompt_hash TEXT,
+    output_hash TEXT,
 
     -- Request metadata
+    -- The id LM Studio gave its response (`chatcmpl-...`), streamed or not
     request_id TEXT,
 
     -- Error tracking
@@ -30,6 +33,192 @@ CREATE TABLE IF NOT EXISTS requests (
     -- Streaming metadata
     was_streamed BOOLEAN DEFAULT 0,
 
+    -- Client SDK fingerprint parsed from the User-Agent header
+    sdk_name TEXT,
+    sdk_version TEXT,
+    user_agent TEXT,
+
+    -- Client identity (peer address plus User-Agent)
+    client_ip TEXT,
+    client_id TEXT,
+
+    -- Requested generation ceiling, if the client sent one
+    max_tokens INTEGER,
+
+    -- Why generation stopped (stop, length, tool_calls, ...)
+    finish_reason TEXT,
+
+    -- Proxy-issued request id and the id of the request that spawned this one
+    proxy_request_id TEXT,
+    parent_id TEXT,
+
+    -- Retry linkage: the logical original this attempt repeats, and what retried it
+    idempotency_key TEXT,
+    retry_of TEXT,
+    retry_source TEXT,
+
+    -- Client deadline budget, its outcome (rejected, timed_out, met, missed), and the
+    -- milliseconds left when forwarded, when upstream responded, and when finished
+    deadline_ms INTEGER,
+    deadline_status TEXT,
+    deadline_remaining_forward_ms INTEGER,
+    deadline_remaining_response_ms INTEGER,
+    deadline_remaining_end_ms INTEGER,
+
+    -- How the request ended (completed, error, client_disconnected, abandoned), the time
+    -- spent before forwarding upstream, and the time from forwarding to response headers
+    termination TEXT,
+    queue_wait_ms INTEGER,
+    time_to_headers_ms INTEGER,
+
+    -- Local token estimates and the tokenizer that made them; tokens_estimated means
+    -- upstream reported no usage and the token counts above are these estimates
+    tokens_estimated BOOLEAN DEFAULT 0,
+    estimated_input_tokens INTEGER,
+    estimated_output_tokens INTEGER,
+    tokenizer TEXT,
+
+    -- Estimated energy for the request: configured power draw times its duration
+    estimated_energy_wh REAL,
+
+    -- Wall-clock minus monotonic elapsed time, set when the system clock jumped mid-request
+    clock_skew_ms INTEGER,
+
+    -- JSON list of protective limits that dropped or truncated data, NULL when none fired
+    limits_hit TEXT,
+
+    -- Prompt processing time and the KV-cache reuse heuristic built on it
+    prompt_eval_ms INTEGER,
+    kv_prefix_tokens INTEGER,
+    kv_baseline_tps REAL,
+    kv_cache_class TEXT,
+
+    -- Billing namespace and the per-million-token prices applied at insert time
+    namespace TEXT,
+    input_price_per_m REAL,
+    output_price_per_m REAL,
+
+    -- JSON list of message-shape problems ({"type", "message"}), NULL when there were none
+    prompt_warnings TEXT,
+
+    -- Agent tool-loop step and the new tool-result / user tokens in the prompt
+    agent_step TEXT,
+    tool_result_tokens INTEGER,
+    new_user_tokens INTEGER,
+
+    -- Batch the request was submitted in, NULL for interactive traffic
+    batch_id TEXT,
+
+    -- Incident in progress when the request arrived, and the extra detail captured for it
+    -- (JSON: headers and raw response), both NULL outside incidents
+    incident_id TEXT,
+    incident_capture TEXT,
+
+    -- Requested sampling seed, the sampling parameters sent with it (JSON, only kept for
+    -- seeded requests) and the backend fingerprint from the response
+    seed INTEGER,
+    sampling_params TEXT,
+    system_fingerprint TEXT,
+
+    -- Logical turn (proxy request id of its first request) and, on the turn's latest
+    -- request only, the time from the turn's first arrival to this request finishing
+    turn_id TEXT,
+    turn_latency_ms INTEGER,
+
+    -- Sampling parameters changed by SAMPLING_GUARDRAILS (JSON list of {param, action,
+    -- original, applied}), NULL when nothing was changed
+    param_adjustments TEXT,
+
+    -- The request's `stop` sequences (JSON list), how many there were and their total
+    -- length in characters
+    stop_sequences TEXT,
+    stop_count INTEGER,
+    stop_chars INTEGER,
+    -- 1 when a `stop` finish came from a custom stop sequence, 0 when it looks like the
+    -- model's own end of sequence; NULL without stop sequences or a `stop` finish
+    stopped_by_custom_stop INTEGER,
+
+    -- Output tokens per second of duration; NULL without output or under a millisecond
+    tokens_per_second REAL,
+
+    -- Milliseconds from the request finishing to its row being inserted, including time
+    -- spent queued in the write spool; NULL for rows written by another process
+    persist_lag_ms INTEGER,
+
+    -- ISO 639-3 language of the new user content and of the output, with the detector's
+    -- confidence; 'unknown' below the threshold. NULL when detection is compiled out or
+    -- for rows written before it existed
+    prompt_language TEXT,
+    prompt_language_confidence REAL,
+    output_language TEXT,
+    output_language_confidence REAL,
+
+    -- Benchmark run the request replayed a stored prompt for, NULL for real traffic
+    benchmark_id TEXT,
+
+    -- Who produced http_status: 'upstream' (LM Studio's, passed through) or 'proxy'
+    -- (the proxy's own error response); NULL for requests logged before it was recorded
+    status_source TEXT,
+
+    -- Hash of the model and the sampling parameters sent; with prompt_hash it identifies
+    -- requests an exact-match response cache would treat as the same
+    params_hash TEXT,
+
+    -- Upstream the request was routed to: 'primary' (LM_STUDIO_URL) or a canary's name;
+    -- NULL for requests logged before canary routing existed, which all went to primary
+    upstream TEXT,
+
+    -- Fingerprint of the normalized system prompt, a key into prompt_versions; NULL when
+    -- the request had no system message
+    prompt_version TEXT,
+
+    -- Milliseconds from start_time to the first non-empty content delta of a stream;
+    -- NULL for non-streamed requests and streams that produced nothing
+    ttft_ms INTEGER,
+
+    -- What the proxy had to look past to parse the body, such as a byte order mark,
+    -- trailing commas or data after the first JSON object; NULL for clean JSON bodies
+    body_parse_warning TEXT,
+
+    -- Data lines of a stream that weren't valid JSON, so their content is missing from
+    -- output; NULL for non-streamed requests
+    stream_parse_errors INTEGER,
+    -- JSON array of the first few such lines, each cut to 512 bytes
+    stream_parse_samples TEXT,
+
+    -- 1 for a warm-up "ping" a client library sent to check the server answers, judged
+    -- by the PROBE_* settings when the request arrived; left out of latency and length
+    -- statistics unless asked for
+    is_probe INTEGER NOT NULL DEFAULT 0,
+
+    -- Conversation the client says the request belongs to, from the SESSION_HEADER
+    -- header; NULL without one
+    session_id TEXT,
+
+    -- SHA-256 of the prompt with its whitespace collapsed, so requests that differ only
+    -- in formatting count as the same prompt; NULL for bodies without messages or prompt
+    normalized_prompt_hash TEXT,
+
+    -- `chat`, `completion`, `embedding` or `other`, from the endpoint path
+    kind TEXT,
+
+    -- Where a failed request failed: `body_read` (the client's body), `forward` (sending
+    -- it to LM Studio), `upstream_error` (LM Studio's error status or unreadable body) or
+    -- `parse` (an unparseable success body); NULL for successes, proxy refusals and rows
+    -- logged before stages were recorded
+    failure_stage TEXT,
+
+    -- The model's context window in tokens as configured in `MODEL_CONTEXT` when the
+    -- request came in; NULL for models without one
+    context_length INTEGER,
+
+    -- Sampling parameters the request was forwarded with, read from numbers or numeric
+    -- strings; NULL when left out
+    temperature REAL,
+    top_p REAL,
+    presence_penalty REAL,
+    frequency_penalty REAL,
+
     created_at TEXT DEFAULT CURRENT_TIMESTAMP
 );
 
@@ -38,3 +227,281 @@ CREATE INDEX IF NOT EXISTS idx_model ON requests(model);
 CREATE INDEX IF NOT EXISTS idx_endpoint ON requests(endpoint);
 CREATE INDEX IF NOT EXISTS idx_start_time ON requests(start_time);
 CREATE INDEX IF NOT EXISTS idx_is_error ON requests(is_error);
+CREATE INDEX IF NOT EXISTS idx_sdk ON requests(sdk_name, sdk_version);
+CREATE INDEX IF NOT EXISTS idx_client_id ON requests(client_id);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_proxy_request_id ON requests(proxy_request_id);
+CREATE INDEX IF NOT EXISTS idx_request_id ON requests(request_id);
+CREATE INDEX IF NOT EXISTS idx_parent_id ON requests(parent_id);
+CREATE INDEX IF NOT EXISTS idx_idempotency_key ON requests(idempotency_key);
+CREATE INDEX IF NOT EXISTS idx_retry_of ON requests(retry_of);
+CREATE INDEX IF NOT EXISTS idx_prompt_hash ON requests(prompt_hash);
+CREATE INDEX IF NOT EXISTS idx_namespace ON requests(namespace, start_time);
+CREATE INDEX IF NOT EXISTS idx_batch_id ON requests(batch_id);
+CREATE INDEX IF NOT EXISTS idx_benchmark_id ON requests(benchmark_id);
+CREATE INDEX IF NOT EXISTS idx_incident_id ON requests(incident_id);
+CREATE INDEX IF NOT EXISTS idx_seed ON requests(seed, model, prompt_hash);
+CREATE INDEX IF NOT EXISTS idx_turn_id ON requests(turn_id);
+CREATE INDEX IF NOT EXISTS idx_cache_key ON requests(prompt_hash, params_hash, start_time);
+CREATE INDEX IF NOT EXISTS idx_prompt_version ON requests(prompt_version, start_time);
+CREATE INDEX IF NOT EXISTS idx_session_id ON requests(session_id, start_time);
+CREATE INDEX IF NOT EXISTS idx_normalized_prompt_hash ON requests(normalized_prompt_hash, start_time);
+CREATE INDEX IF NOT EXISTS idx_duration_ms ON requests(duration_ms);
+CREATE INDEX IF NOT EXISTS idx_ttft_ms ON requests(ttft_ms);
+
+-- Rolling per-minute counters, persisted so short-window stats survive restarts
+CREATE TABLE IF NOT EXISTS minute_counters (
+    minute TEXT NOT NULL,
+    model TEXT NOT NULL,
+    requests INTEGER NOT NULL,
+    input_tokens INTEGER NOT NULL,
+    output_tokens INTEGER NOT NULL,
+    flushed_at TEXT NOT NULL,
+    PRIMARY KEY (minute, model)
+);
+
+-- Per-day totals by model (UTC start date), updated with every insert so dashboards
+-- don't scan the requests table. Abandoned requests are left out, as in /stats/summary.
+-- Rows outlive the requests they count when those are deleted by retention.
+CREATE TABLE IF NOT EXISTS daily_rollups (
+    day TEXT NOT NULL,
+    model TEXT NOT NULL,
+    requests INTEGER NOT NULL,
+    errors INTEGER NOT NULL,
+    input_tokens INTEGER NOT NULL,
+    output_tokens INTEGER NOT NULL,
+    -- Dollars at the rates stored on each successful request; unpriced ones add nothing
+    cost REAL NOT NULL,
+    PRIMARY KEY (day, model)
+);
+
+-- Wall-clock time per day and model (UTC) with at least one request running, counting
+-- overlapping requests once. Updated with every insert; a request spanning midnight adds
+-- to both days. Abandoned requests are left out.
+CREATE TABLE IF NOT EXISTS daily_busy (
+    day TEXT NOT NULL,
+    model TEXT NOT NULL,
+    busy_ms INTEGER NOT NULL,
+    PRIMARY KEY (day, model)
+);
+
+-- Usage per UTC week (starting Monday) and month by model, split by success, updated with
+-- every insert. Counts what /stats/summary counts by default: no abandoned requests,
+-- benchmarks or probes. Sums rather than averages, so periods add up exactly.
+CREATE TABLE IF NOT EXISTS usage_rollups (
+    -- `week` or `month`
+    period_type TEXT NOT NULL,
+    -- UTC date the period starts on
+    period_start TEXT NOT NULL,
+    model TEXT NOT NULL,
+    is_error INTEGER NOT NULL,
+    requests INTEGER NOT NULL,
+    input_tokens INTEGER NOT NULL,
+    output_tokens INTEGER NOT NULL,
+    total_tokens INTEGER NOT NULL,
+    duration_sum INTEGER NOT NULL,
+    -- Sum and count of the requests with a tokens_per_second, and its highest value
+    tps_sum REAL NOT NULL,
+    tps_count INTEGER NOT NULL,
+    tps_max REAL,
+    ttft_sum INTEGER NOT NULL,
+    ttft_count INTEGER NOT NULL,
+    -- Dollars over the requests logged with both prices; NULL when none were
+    cost REAL,
+    PRIMARY KEY (period_type, period_start, model, is_error)
+);
+
+-- Periods of extra capture, started by an error spike or by hand
+CREATE TABLE IF NOT EXISTS incidents (
+    id TEXT PRIMARY KEY,
+    -- `error_rate` or `manual`
+    reason TEXT NOT NULL,
+    started_at TEXT NOT NULL,
+    -- Moved later when an incident is started again while it is active
+    ends_at TEXT NOT NULL
+);
+
+-- Actions the proxy took on its own, such as unloading an idle model
+CREATE TABLE IF NOT EXISTS proxy_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    detail TEXT NOT NULL
+);
+
+-- Upstream availability from the status prober: each row is an unbroken stretch of
+-- checks with the same answer, 'up' or 'down'. Time between rows is unknown
+CREATE TABLE IF NOT EXISTS availability_spans (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    state TEXT NOT NULL,
+    started_at TEXT NOT NULL,
+    last_seen_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_availability_spans_last_seen ON availability_spans(last_seen_at);
+
+-- Background maintenance jobs (backfills, migrations) and their progress
+CREATE TABLE IF NOT EXISTS maintenance_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    status TEXT NOT NULL,
+    processed INTEGER NOT NULL,
+    total INTEGER NOT NULL,
+    error TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    finished_at TEXT
+);
+
+-- JSONL batches submitted to /v1/batches and processed in the background
+CREATE TABLE IF NOT EXISTS batches (
+    id TEXT PRIMARY KEY,
+    status TEXT NOT NULL,
+    client_ip TEXT,
+    user_agent TEXT,
+    error TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    finished_at TEXT
+);
+
+-- One line of a batch; `response` is the upstream body once the line has run
+CREATE TABLE IF NOT EXISTS batch_items (
+    batch_id TEXT NOT NULL,
+    line INTEGER NOT NULL,
+    custom_id TEXT,
+    url TEXT NOT NULL,
+    body TEXT NOT NULL,
+    status TEXT NOT NULL,
+    http_status INTEGER,
+    response TEXT,
+    proxy_request_id TEXT,
+    finished_at TEXT,
+    PRIMARY KEY (batch_id, line)
+);
+CREATE INDEX IF NOT EXISTS idx_batch_items_status ON batch_items(batch_id, status);
+
+-- Benchmarks replaying stored prompts against several models; `spec` is the request
+-- that started it, as JSON
+CREATE TABLE IF NOT EXISTS benchmarks (
+    id TEXT PRIMARY KEY,
+    status TEXT NOT NULL,
+    spec TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    finished_at TEXT
+);
+
+-- One prompt sent to one model, with the request body built when the benchmark started
+CREATE TABLE IF NOT EXISTS benchmark_runs (
+    benchmark_id TEXT NOT NULL,
+    run INTEGER NOT NULL,
+    source_request_id INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    body TEXT NOT NULL,
+    status TEXT NOT NULL,
+    http_status INTEGER,
+    proxy_request_id TEXT,
+    finished_at TEXT,
+    PRIMARY KEY (benchmark_id, run)
+);
+CREATE INDEX IF NOT EXISTS idx_benchmark_runs_status ON benchmark_runs(benchmark_id, status);
+
+-- Webhook subscriptions added through /admin/webhooks
+CREATE TABLE IF NOT EXISTS webhooks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT,
+    -- Predicate over the stored request, see src/webhooks/filter.rs
+    filter TEXT NOT NULL,
+    url TEXT NOT NULL,
+    -- HMAC-SHA256 key for the signature header; NULL sends deliveries unsigned
+    secret TEXT,
+    -- `record` or `summary`
+    payload TEXT NOT NULL,
+    max_per_minute INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+-- The latest deliveries per webhook; `request_id` is the row id in requests
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook_id INTEGER NOT NULL,
+    request_id INTEGER NOT NULL,
+    proxy_request_id TEXT,
+    time TEXT NOT NULL,
+    -- `delivered`, `failed` or `rate_limited`
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    http_status INTEGER,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries ON webhook_deliveries(webhook_id, id);
+
+-- System prompts seen, by fingerprint, with a label that can be set by hand
+CREATE TABLE IF NOT EXISTS prompt_versions (
+    hash TEXT PRIMARY KEY,
+    -- start_time of the first request that used it
+    first_seen TEXT NOT NULL,
+    label TEXT
+);
+
+-- Prompt and output text, stored once per distinct SHA-256
+CREATE TABLE IF NOT EXISTS blobs (
+    hash TEXT PRIMARY KEY,
+    content TEXT NOT NULL,
+    refcount INTEGER NOT NULL DEFAULT 0
+);
+
+-- Reference counts follow the requests table, so every delete path releases blobs
+CREATE TRIGGER IF NOT EXISTS blobs_ref_insert AFTER INSERT ON requests
+BEGIN
+    UPDATE blobs SET refcount = refcount + 1 WHERE hash = NEW.prompt_hash;
+    UPDATE blobs SET refcount = refcount + 1 WHERE hash = NEW.output_hash;
+END;
+
+CREATE TRIGGER IF NOT EXISTS blobs_ref_update AFTER UPDATE OF prompt_hash, output_hash ON requests
+BEGIN
+    UPDATE blobs SET refcount = refcount + 1 WHERE hash = NEW.prompt_hash;
+    UPDATE blobs SET refcount = refcount + 1 WHERE hash = NEW.output_hash;
+    UPDATE blobs SET refcount = refcount - 1 WHERE hash = OLD.prompt_hash;
+    UPDATE blobs SET refcount = refcount - 1 WHERE hash = OLD.output_hash;
+    DELETE FROM blobs WHERE hash IN (OLD.prompt_hash, OLD.output_hash) AND refcount <= 0;
+END;
+
+CREATE TRIGGER IF NOT EXISTS blobs_ref_delete AFTER DELETE ON requests
+BEGIN
+    UPDATE blobs SET refcount = refcount - 1 WHERE hash = OLD.prompt_hash;
+    UPDATE blobs SET refcount = refcount - 1 WHERE hash = OLD.output_hash;
+    DELETE FROM blobs WHERE hash IN (OLD.prompt_hash, OLD.output_hash) AND refcount <= 0;
+END;
+
+-- Full-text index over each request's prompt and output, keyed by request id, for
+-- /stats/search. Kept in step with requests by the triggers below; blobs are stored
+-- before the row that references them, so the text can be looked up on insert.
+CREATE VIRTUAL TABLE IF NOT EXISTS request_search USING fts5(prompt, output);
+
+CREATE TRIGGER IF NOT EXISTS request_search_insert AFTER INSERT ON requests
+BEGIN
+    INSERT INTO request_search (rowid, prompt, output) VALUES (
+        NEW.id,
+        COALESCE((SELECT content FROM blobs WHERE hash = NEW.prompt_hash), NEW.prompt),
+        COALESCE((SELECT content FROM blobs WHERE hash = NEW.output_hash), NEW.output)
+    );
+END;
+
+CREATE TRIGGER IF NOT EXISTS request_search_delete AFTER DELETE ON requests
+BEGIN
+    DELETE FROM request_search WHERE rowid = OLD.id;
+END;
+
+-- Full request rows with prompt and output text joined back in. Rows written before
+-- blob storage keep their text inline. Recreated on startup so `r.*` picks up new columns.
+DROP VIEW IF EXISTS request_rows;
+CREATE VIEW request_rows AS
+SELECT
+    r.*,
+    COALESCE(pb.content, r.prompt) as prompt_text,
+    COALESCE(ob.content, r.output) as output_text
+FROM requests r
+LEFT JOIN blobs pb ON pb.hash = r.prompt_hash
+LEFT JOIN blobs ob ON ob.hash = r.output_hash;
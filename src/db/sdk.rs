@@ -0,0 +1,108 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+#[derive(Debug, Serialize)]
+pub struct SdkStats {
+    pub sdk_name: String,
+    pub sdk_version: Option<String>,
+    /// Raw User-Agent, only populated for agents that fell into "other"
+    pub user_agent: Option<String>,
+    pub requests: i64,
+    pub failed_requests: i64,
+    /// Warm-up pings, see [`crate::proxy::probe`]
+    pub probe_requests: i64,
+    pub total_tokens: i64,
+    pub avg_duration_ms: f64,
+    pub known_bad: bool,
+}
+
+/// Requests per client library. Probes are counted but left out of the average duration
+/// unless `include_probes` is set.
+pub async fn get_sdk_stats(
+    pool: &SqlitePool,
+    include_probes: bool,
+) -> Result<Vec<SdkStats>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            COALESCE(sdk_name, 'unknown') as sdk_name,
+            sdk_version,
+            CASE WHEN sdk_name = 'other' THEN user_agent END as raw_user_agent,
+            COUNT(*) as requests,
+            SUM(CASE WHEN is_error = 1 THEN 1 ELSE 0 END) as failed_requests,
+            SUM(is_probe) as probe_requests,
+            COALESCE(SUM(total_tokens), 0) as total_tokens,
+            COALESCE(AVG(CASE WHEN ?1 OR is_probe = 0 THEN CAST(duration_ms AS REAL) END), 0.0)
+                as avg_duration_ms
+        FROM requests
+        GROUP BY sdk_name, sdk_version, raw_user_agent
+        ORDER BY requests DESC
+        "#,
+    )
+    .bind(include_probes)
+    .fetch_all(pool)
+    .await?;
+
+    let mut stats = Vec::new();
+    for row in rows {
+        stats.push(SdkStats {
+            sdk_name: row.try_get("sdk_name")?,
+            sdk_version: row.try_get("sdk_version")?,
+            user_agent: row.try_get("raw_user_agent")?,
+            requests: row.try_get("requests")?,
+            failed_requests: row.try_get("failed_requests")?,
+            probe_requests: row.try_get("probe_requests")?,
+            total_tokens: row.try_get("total_tokens")?,
+            avg_duration_ms: row.try_get("avg_duration_ms")?,
+            known_bad: false,
+        });
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use crate::db::{RequestRecord, insert_request};
+    use chrono::Utc;
+
+    async fn insert(pool: &SqlitePool, sdk: &str, duration_ms: i64, is_probe: bool) {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            Utc::now(),
+            "hi".to_string(),
+        );
+        record.sdk_name = Some(sdk.to_string());
+        record.duration_ms = duration_ms;
+        record.is_probe = is_probe;
+        insert_request(pool, &record).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn probes_are_counted_per_client_library() {
+        let pool = memory_pool().await;
+        insert(&pool, "openai-python", 2000, false).await;
+        insert(&pool, "openai-python", 4000, false).await;
+        insert(&pool, "openai-python", 30, true).await;
+        for _ in 0..4 {
+            insert(&pool, "curl", 20, true).await;
+        }
+
+        let stats = get_sdk_stats(&pool, false).await.unwrap();
+        let counts: Vec<_> = stats
+            .iter()
+            .map(|sdk| (sdk.sdk_name.as_str(), sdk.requests, sdk.probe_requests))
+            .collect();
+        assert_eq!(counts, [("curl", 4, 4), ("openai-python", 3, 1)]);
+        // A library sending nothing but probes has no duration to average
+        assert_eq!(stats[0].avg_duration_ms, 0.0);
+        assert_eq!(stats[1].avg_duration_ms, 3000.0);
+
+        let stats = get_sdk_stats(&pool, true).await.unwrap();
+        assert_eq!(stats[0].avg_duration_ms, 20.0);
+        assert_eq!(stats[1].avg_duration_ms, 2010.0);
+    }
+}
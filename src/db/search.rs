@@ -0,0 +1,100 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use super::models::RecentRequest;
+
+/// Marks the matched terms in a snippet
+const HIGHLIGHT_START: &str = "<mark>";
+const HIGHLIGHT_END: &str = "</mark>";
+/// Tokens of context kept around the match
+const SNIPPET_TOKENS: i64 = 16;
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub request: RecentRequest,
+    /// The best-matching stretch of the prompt or output, with matches wrapped in `<mark>`
+    pub snippet: String,
+}
+
+/// Indexes requests stored before the search index existed, or while it was missing rows.
+pub async fn backfill_index(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO request_search (rowid, prompt, output)
+        SELECT id, prompt_text, output_text
+        FROM request_rows
+        WHERE id > (SELECT COALESCE(MAX(rowid), 0) FROM request_search)
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    if result.rows_affected() > 0 {
+        tracing::info!("Indexed {} requests for search", result.rows_affected());
+    }
+
+    Ok(())
+}
+
+/// Turns free text into an FTS5 query that matches rows containing every word, so
+/// punctuation and FTS operators in the text are searched for rather than interpreted.
+fn match_expression(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Up to `limit` requests whose prompt or output contains every word of `text`, newest
+/// first, optionally only those older than `before_id`.
+pub async fn search_requests(
+    pool: &SqlitePool,
+    text: &str,
+    limit: i64,
+    before_id: Option<i64>,
+) -> Result<Vec<SearchHit>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            r.id,
+            r.proxy_request_id,
+            r.request_id,
+            r.endpoint,
+            r.model,
+            r.start_time,
+            r.end_time,
+            r.duration_ms,
+            r.input_tokens,
+            r.output_tokens,
+            r.total_tokens,
+            r.is_error,
+            r.http_status,
+            r.was_streamed,
+            snippet(request_search, -1, ?2, ?3, '…', ?4) as snippet
+        FROM request_search
+        JOIN requests r ON r.id = request_search.rowid
+        WHERE request_search MATCH ?1
+          AND (?5 IS NULL OR r.id < ?5)
+        ORDER BY r.id DESC
+        LIMIT ?6
+        "#,
+    )
+    .bind(match_expression(text))
+    .bind(HIGHLIGHT_START)
+    .bind(HIGHLIGHT_END)
+    .bind(SNIPPET_TOKENS)
+    .bind(before_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    let mut hits = Vec::new();
+    for row in rows {
+        hits.push(SearchHit {
+            request: RecentRequest::from_row(&row)?,
+            snippet: row.try_get("snippet")?,
+        });
+    }
+
+    Ok(hits)
+}
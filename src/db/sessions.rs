@@ -0,0 +1,254 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+
+use super::models::{RECENT_COLUMNS, RecentRequest};
+use super::tree::{NODE_COLUMNS, RequestNode, nest, node_from_row};
+
+#[derive(Debug, Serialize)]
+pub struct SessionStats {
+    pub session_id: String,
+    pub requests: i64,
+    pub errors: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub total_tokens: i64,
+    pub first_request: String,
+    pub last_request: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionReport {
+    pub since: Option<String>,
+    /// Most recently active first
+    pub sessions: Vec<SessionStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Session {
+    /// Totals, shaped like a listing entry
+    pub session: SessionStats,
+    /// Oldest first
+    pub requests: Vec<RecentRequest>,
+    /// The same requests grouped by turn, oldest first
+    pub turns: Vec<SessionTurn>,
+}
+
+/// One turn of a session, its requests nested into trees by `X-Proxy-Parent-Id`.
+#[derive(Debug, Serialize)]
+pub struct SessionTurn {
+    /// `proxy_request_id` of the turn's first request; `None` for a request stored without
+    /// one, which is a turn of its own
+    pub turn_id: Option<String>,
+    /// Oldest root first. A request whose parent is in another turn or session is a root
+    pub trees: Vec<RequestNode>,
+}
+
+/// Usage per client-supplied session, counting requests from `since` onward. Requests
+/// without a session are left out.
+pub async fn get_sessions(
+    pool: &SqlitePool,
+    since: Option<&str>,
+    limit: i64,
+) -> Result<SessionReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            session_id,
+            COUNT(*) as requests,
+            SUM(CASE WHEN is_error = 1 THEN 1 ELSE 0 END) as errors,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens,
+            COALESCE(SUM(total_tokens), 0) as total_tokens,
+            MIN(start_time) as first_request,
+            MAX(start_time) as last_request
+        FROM requests
+        WHERE session_id IS NOT NULL AND (?1 IS NULL OR start_time >= ?1)
+        GROUP BY session_id
+        ORDER BY last_request DESC
+        LIMIT ?2
+        "#,
+    )
+    .bind(since)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    let mut sessions = Vec::new();
+    for row in rows {
+        sessions.push(SessionStats {
+            session_id: row.try_get("session_id")?,
+            requests: row.try_get("requests")?,
+            errors: row.try_get("errors")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+            total_tokens: row.try_get("total_tokens")?,
+            first_request: row.try_get("first_request")?,
+            last_request: row.try_get("last_request")?,
+        });
+    }
+
+    Ok(SessionReport {
+        since: since.map(str::to_string),
+        sessions,
+    })
+}
+
+/// Every request of one session in the order they started, or `None` when no request
+/// carried that session id.
+pub async fn get_session(
+    pool: &SqlitePool,
+    session_id: &str,
+) -> Result<Option<Session>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM requests WHERE session_id = ? ORDER BY start_time, id",
+        RECENT_COLUMNS
+    ))
+    .bind(session_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut stats = SessionStats {
+        session_id: session_id.to_string(),
+        requests: 0,
+        errors: 0,
+        input_tokens: 0,
+        output_tokens: 0,
+        total_tokens: 0,
+        first_request: String::new(),
+        last_request: String::new(),
+    };
+    let mut requests = Vec::new();
+    for row in rows {
+        let request = RecentRequest::from_row(&row)?;
+        stats.requests += 1;
+        stats.errors += request.is_error as i64;
+        stats.input_tokens += request.input_tokens;
+        stats.output_tokens += request.output_tokens;
+        stats.total_tokens += request.total_tokens;
+        requests.push(request);
+    }
+
+    let (Some(first), Some(last)) = (requests.first(), requests.last()) else {
+        return Ok(None);
+    };
+    stats.first_request = first.start_time.clone();
+    stats.last_request = last.start_time.clone();
+    Ok(Some(Session {
+        session: stats,
+        requests,
+        turns: get_session_turns(pool, session_id).await?,
+    }))
+}
+
+/// A session's requests grouped by turn in the order each turn started, nested into trees
+/// within it.
+async fn get_session_turns(
+    pool: &SqlitePool,
+    session_id: &str,
+) -> Result<Vec<SessionTurn>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT {}, r.turn_id FROM requests r WHERE r.session_id = ? ORDER BY r.start_time, r.id",
+        NODE_COLUMNS
+    ))
+    .bind(session_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut turns: Vec<(Option<String>, Vec<RequestNode>)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for row in &rows {
+        let turn_id: Option<String> = row.try_get("turn_id")?;
+        let node = node_from_row(row)?;
+        match turn_id.as_ref().and_then(|id| positions.get(id)) {
+            Some(&position) => turns[position].1.push(node),
+            None => {
+                if let Some(id) = &turn_id {
+                    positions.insert(id.clone(), turns.len());
+                }
+                turns.push((turn_id, vec![node]));
+            }
+        }
+    }
+    Ok(turns
+        .into_iter()
+        .map(|(turn_id, nodes)| SessionTurn {
+            turn_id,
+            trees: nest(nodes, None),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::{RequestRecord, insert_request};
+    use crate::db::testing::memory_pool;
+    use chrono::{Duration, TimeZone, Utc};
+
+    async fn insert(pool: &SqlitePool, session: &str, id: &str, parent: Option<&str>, secs: i64) {
+        let start = Utc.with_ymd_and_hms(2026, 3, 2, 9, 0, 0).unwrap() + Duration::seconds(secs);
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            start,
+            "hi".to_string(),
+        );
+        record.proxy_request_id = Some(id.to_string());
+        record.parent_id = parent.map(str::to_string);
+        record.session_id = Some(session.to_string());
+        record.duration_ms = 100;
+        record.input_tokens = 10;
+        insert_request(pool, &record).await.unwrap();
+    }
+
+    /// Each turn's id and its trees as `id(children...)`.
+    fn outline(session: &Session) -> Vec<(Option<&str>, Vec<String>)> {
+        fn tree(node: &RequestNode) -> String {
+            if node.children.is_empty() {
+                return node.proxy_request_id.clone();
+            }
+            let children: Vec<String> = node.children.iter().map(tree).collect();
+            format!("{}({})", node.proxy_request_id, children.join(" "))
+        }
+        session
+            .turns
+            .iter()
+            .map(|turn| (turn.turn_id.as_deref(), turn.trees.iter().map(tree).collect()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn session_nests_request_trees_within_turns() {
+        let pool = memory_pool().await;
+        insert(&pool, "other", "elsewhere", None, 0).await;
+        insert(&pool, "s", "t1", None, 10).await;
+        insert(&pool, "s", "t1-tool", Some("t1"), 20).await;
+        insert(&pool, "s", "t1-answer", Some("t1-tool"), 30).await;
+        insert(&pool, "s", "t1-side", Some("t1"), 35).await;
+        insert(&pool, "s", "t2", None, 600).await;
+        // Its parent is stored, but in another session
+        insert(&pool, "s", "t2-borrowed", Some("elsewhere"), 610).await;
+        insert(&pool, "s", "legacy", None, 900).await;
+        sqlx::query("UPDATE requests SET turn_id = NULL WHERE proxy_request_id = 'legacy'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let session = get_session(&pool, "s").await.unwrap().unwrap();
+        assert_eq!(session.requests.len(), 7);
+        assert_eq!(
+            outline(&session),
+            [
+                (Some("t1"), vec!["t1(t1-tool(t1-answer) t1-side)".to_string()]),
+                (Some("t2"), vec!["t2".to_string()]),
+                (Some("elsewhere"), vec!["t2-borrowed".to_string()]),
+                (None, vec!["legacy".to_string()]),
+            ]
+        );
+        let t1 = &session.turns[0].trees[0];
+        assert_eq!(t1.cumulative.requests, 4);
+        assert_eq!(t1.cumulative.input_tokens, 40);
+        assert!(!session.turns[2].trees[0].parent_missing);
+    }
+}
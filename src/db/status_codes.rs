@@ -0,0 +1,119 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::BTreeMap;
+
+use super::models::STATUS_SOURCE_PROXY;
+use super::timeseries::Bucket;
+
+/// Requests answered with one HTTP status.
+#[derive(Debug, Serialize)]
+pub struct StatusCount {
+    pub http_status: i32,
+    pub requests: i64,
+    /// Of those, statuses the proxy returned itself, such as a 502 when LM Studio
+    /// couldn't be reached
+    pub proxy_generated: i64,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BucketStatus {
+    pub http_status: i32,
+    pub requests: i64,
+    pub proxy_generated: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusBucket {
+    pub bucket_start: String,
+    /// Lowest status first
+    pub statuses: Vec<BucketStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusCodeReport {
+    pub since: Option<String>,
+    pub bucket: Option<Bucket>,
+    /// Lowest status first
+    pub statuses: Vec<StatusCount>,
+    /// Oldest first, only with a bucket; buckets without requests are left out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series: Option<Vec<StatusBucket>>,
+}
+
+/// Requests per HTTP status from `since` onward, and per status in each bucket when
+/// one is given. Failed requests the proxy answered itself are included, so periods
+/// when LM Studio was down show up as 502s.
+pub async fn get_status_codes(
+    pool: &SqlitePool,
+    since: Option<&str>,
+    bucket: Option<Bucket>,
+) -> Result<StatusCodeReport, sqlx::Error> {
+    // One query serves both forms; without a bucket every row falls in the same one
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT
+            {} as bucket_start,
+            http_status,
+            COUNT(*) as requests,
+            SUM(CASE WHEN status_source = ?2 THEN 1 ELSE 0 END) as proxy_generated,
+            MIN(start_time) as first_seen,
+            MAX(start_time) as last_seen
+        FROM requests
+        WHERE (?1 IS NULL OR start_time >= ?1)
+        GROUP BY bucket_start, http_status
+        ORDER BY bucket_start, http_status
+        "#,
+        bucket.map_or("NULL", Bucket::sql)
+    ))
+    .bind(since)
+    .bind(STATUS_SOURCE_PROXY)
+    .fetch_all(pool)
+    .await?;
+
+    let mut totals: BTreeMap<i32, StatusCount> = BTreeMap::new();
+    let mut series: Vec<StatusBucket> = Vec::new();
+    for row in rows {
+        let http_status: i32 = row.try_get("http_status")?;
+        let requests: i64 = row.try_get("requests")?;
+        let proxy_generated: i64 = row.try_get("proxy_generated")?;
+        let first_seen: String = row.try_get("first_seen")?;
+        let last_seen: String = row.try_get("last_seen")?;
+
+        let total = totals.entry(http_status).or_insert_with(|| StatusCount {
+            http_status,
+            requests: 0,
+            proxy_generated: 0,
+            first_seen: first_seen.clone(),
+            last_seen: last_seen.clone(),
+        });
+        total.requests += requests;
+        total.proxy_generated += proxy_generated;
+        total.first_seen = total.first_seen.clone().min(first_seen);
+        total.last_seen = total.last_seen.clone().max(last_seen);
+
+        if let Some(bucket_start) = row.try_get::<Option<String>, _>("bucket_start")? {
+            if series.last().is_none_or(|last| last.bucket_start != bucket_start) {
+                series.push(StatusBucket {
+                    bucket_start,
+                    statuses: Vec::new(),
+                });
+            }
+            if let Some(last) = series.last_mut() {
+                last.statuses.push(BucketStatus {
+                    http_status,
+                    requests,
+                    proxy_generated,
+                });
+            }
+        }
+    }
+
+    Ok(StatusCodeReport {
+        since: since.map(str::to_string),
+        bucket,
+        statuses: totals.into_values().collect(),
+        series: bucket.map(|_| series),
+    })
+}
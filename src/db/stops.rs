@@ -0,0 +1,149 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use super::models::TERMINATION_ABANDONED;
+
+/// Custom stop sequence usage of one client (or of all requests).
+#[derive(Debug, Serialize)]
+pub struct StopStats {
+    /// Successful requests
+    pub requests: i64,
+    pub requests_with_stops: i64,
+    pub avg_stop_count: f64,
+    /// Average total length of a request's stop sequences, in characters
+    pub avg_stop_chars: f64,
+    /// Requests with stop sequences that finished with `stop`
+    pub stop_finishes: i64,
+    /// Of those, the ones ended by a custom stop sequence rather than end of sequence
+    pub custom_stop_triggered: i64,
+    /// `custom_stop_triggered` over `requests_with_stops`
+    pub trigger_rate: f64,
+    pub avg_output_tokens_with_stops: Option<f64>,
+    pub avg_output_tokens_without_stops: Option<f64>,
+    /// With minus without; negative when stop sequences go with shorter answers
+    pub output_tokens_difference: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientStopStats {
+    pub client_id: Option<String>,
+    #[serde(flatten)]
+    pub stats: StopStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StopReport {
+    pub since: Option<String>,
+    pub totals: StopStats,
+    /// Most custom stops triggered first
+    pub by_client: Vec<ClientStopStats>,
+}
+
+/// How often custom stop sequences end successful requests since `since`, per client.
+pub async fn get_stop_report(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<StopReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            client_id,
+            COUNT(*) as requests,
+            COUNT(stop_count) as requests_with_stops,
+            COALESCE(SUM(stop_count), 0) as stop_count,
+            COALESCE(SUM(stop_chars), 0) as stop_chars,
+            SUM(CASE WHEN stop_count IS NOT NULL AND finish_reason = 'stop' THEN 1 ELSE 0 END)
+                as stop_finishes,
+            SUM(CASE WHEN stopped_by_custom_stop = 1 THEN 1 ELSE 0 END) as custom_stop_triggered,
+            SUM(CASE WHEN stop_count IS NOT NULL THEN output_tokens ELSE 0 END) as output_with,
+            SUM(CASE WHEN stop_count IS NULL THEN output_tokens ELSE 0 END) as output_without
+        FROM requests
+        WHERE is_error = 0 AND termination IS NOT ?2 AND (?1 IS NULL OR start_time >= ?1)
+        GROUP BY client_id
+        "#,
+    )
+    .bind(since)
+    .bind(TERMINATION_ABANDONED)
+    .fetch_all(pool)
+    .await?;
+
+    let mut totals = Sums::default();
+    let mut by_client = Vec::new();
+    for row in &rows {
+        let sums = Sums {
+            requests: row.try_get("requests")?,
+            requests_with_stops: row.try_get("requests_with_stops")?,
+            stop_count: row.try_get("stop_count")?,
+            stop_chars: row.try_get("stop_chars")?,
+            stop_finishes: row.try_get("stop_finishes")?,
+            custom_stop_triggered: row.try_get("custom_stop_triggered")?,
+            output_with: row.try_get("output_with")?,
+            output_without: row.try_get("output_without")?,
+        };
+        totals.add(&sums);
+        by_client.push(ClientStopStats {
+            client_id: row.try_get("client_id")?,
+            stats: sums.finish(),
+        });
+    }
+    by_client.sort_by_key(|client| std::cmp::Reverse(client.stats.custom_stop_triggered));
+
+    Ok(StopReport {
+        since: since.map(|s| s.to_string()),
+        totals: totals.finish(),
+        by_client,
+    })
+}
+
+#[derive(Default)]
+struct Sums {
+    requests: i64,
+    requests_with_stops: i64,
+    stop_count: i64,
+    stop_chars: i64,
+    stop_finishes: i64,
+    custom_stop_triggered: i64,
+    output_with: i64,
+    output_without: i64,
+}
+
+impl Sums {
+    fn add(&mut self, other: &Sums) {
+        self.requests += other.requests;
+        self.requests_with_stops += other.requests_with_stops;
+        self.stop_count += other.stop_count;
+        self.stop_chars += other.stop_chars;
+        self.stop_finishes += other.stop_finishes;
+        self.custom_stop_triggered += other.custom_stop_triggered;
+        self.output_with += other.output_with;
+        self.output_without += other.output_without;
+    }
+
+    fn finish(&self) -> StopStats {
+        let without = self.requests - self.requests_with_stops;
+        let average = |sum: f64, count: i64| (count > 0).then(|| round(sum / count as f64));
+        let with_stops = average(self.output_with as f64, self.requests_with_stops);
+        let without_stops = average(self.output_without as f64, without);
+        StopStats {
+            requests: self.requests,
+            requests_with_stops: self.requests_with_stops,
+            avg_stop_count: average(self.stop_count as f64, self.requests_with_stops)
+                .unwrap_or(0.0),
+            avg_stop_chars: average(self.stop_chars as f64, self.requests_with_stops)
+                .unwrap_or(0.0),
+            stop_finishes: self.stop_finishes,
+            custom_stop_triggered: self.custom_stop_triggered,
+            trigger_rate: average(self.custom_stop_triggered as f64, self.requests_with_stops)
+                .unwrap_or(0.0),
+            avg_output_tokens_with_stops: with_stops,
+            avg_output_tokens_without_stops: without_stops,
+            output_tokens_difference: with_stops
+                .zip(without_stops)
+                .map(|(with, without)| round(with - without)),
+        }
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
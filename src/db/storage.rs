@@ -0,0 +1,373 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::path::Path;
+
+/// What a file in the data directory is, judged by its name against the database's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilePurpose {
+    Database,
+    /// Write-ahead log, folded back into the database by a checkpoint
+    Wal,
+    /// WAL index shared between connections
+    SharedMemory,
+    /// Rollback journal, left from a database not in WAL mode
+    Journal,
+    Backup,
+    /// SQLite temporary file or another `.tmp` file
+    Temporary,
+    Unknown,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DataFile {
+    pub name: String,
+    pub bytes: u64,
+    pub purpose: FilePurpose,
+    /// Belongs to the database this proxy has open; other WAL, shared-memory and journal
+    /// files are left from databases that have since been moved or renamed
+    pub in_use: bool,
+    pub modified: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DataDirectory {
+    pub path: String,
+    pub total_bytes: u64,
+    /// Largest first
+    pub files: Vec<DataFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckpointReport {
+    pub wal_bytes_before: u64,
+    pub wal_bytes_after: u64,
+    pub reclaimed_bytes: u64,
+    /// A reader or writer kept the checkpoint from finishing; the WAL is only truncated
+    /// once no connection still needs it
+    pub busy: bool,
+}
+
+fn classify(name: &str, database: &str) -> (FilePurpose, bool) {
+    let suffixed = |suffix: &str| name.strip_suffix(suffix).map(|base| base == database);
+    if name == database {
+        return (FilePurpose::Database, true);
+    }
+    for (suffix, purpose) in [
+        ("-wal", FilePurpose::Wal),
+        ("-shm", FilePurpose::SharedMemory),
+        ("-journal", FilePurpose::Journal),
+    ] {
+        if let Some(in_use) = suffixed(suffix) {
+            return (purpose, in_use);
+        }
+    }
+    let lower = name.to_ascii_lowercase();
+    if lower.contains("backup")
+        || [".bak", ".backup", ".old"]
+            .iter()
+            .any(|s| lower.ends_with(s))
+    {
+        (FilePurpose::Backup, false)
+    } else if lower.starts_with("etilqs_") || lower.ends_with(".tmp") {
+        (FilePurpose::Temporary, false)
+    } else {
+        (FilePurpose::Unknown, false)
+    }
+}
+
+/// Every file in the directory holding the database at `database_path`, with what each
+/// one is for. Subdirectories are skipped. Nothing is modified.
+pub async fn get_data_directory(database_path: &str) -> Result<DataDirectory, sqlx::Error> {
+    let database_path = Path::new(database_path);
+    let database = database_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let directory = match database_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+
+    let mut files = Vec::new();
+    let mut entries = tokio::fs::read_dir(&directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let (purpose, in_use) = classify(&name, &database);
+        files.push(DataFile {
+            name,
+            bytes: metadata.len(),
+            purpose,
+            in_use,
+            modified: metadata
+                .modified()
+                .ok()
+                .map(|time| DateTime::<Utc>::from(time).to_rfc3339()),
+        });
+    }
+    files.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+
+    Ok(DataDirectory {
+        path: directory.to_string_lossy().into_owned(),
+        total_bytes: files.iter().map(|file| file.bytes).sum(),
+        files,
+    })
+}
+
+/// Size of the database's WAL file, 0 when there is none.
+pub async fn wal_size(database_path: &str) -> Result<u64, sqlx::Error> {
+    match tokio::fs::metadata(format!("{}-wal", database_path)).await {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Copies every WAL frame into the database and truncates the WAL to zero bytes,
+/// measuring the file before and after.
+pub async fn checkpoint_wal(
+    pool: &SqlitePool,
+    database_path: &str,
+) -> Result<CheckpointReport, sqlx::Error> {
+    let wal_bytes_before = wal_size(database_path).await?;
+    // A truncated WAL reports zero frames, so only the busy flag says anything here
+    let (busy, _, _): (i64, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+        .fetch_one(pool)
+        .await?;
+    let wal_bytes_after = wal_size(database_path).await?;
+
+    Ok(CheckpointReport {
+        wal_bytes_before,
+        wal_bytes_after,
+        reclaimed_bytes: wal_bytes_before.saturating_sub(wal_bytes_after),
+        busy: busy != 0,
+    })
+}
+
+/// Checkpoints the WAL if it is larger than `limit_bytes`, returning the report when it did.
+pub async fn checkpoint_if_larger(
+    pool: &SqlitePool,
+    database_path: &str,
+    limit_bytes: u64,
+) -> Result<Option<CheckpointReport>, sqlx::Error> {
+    if wal_size(database_path).await? <= limit_bytes {
+        return Ok(None);
+    }
+    checkpoint_wal(pool, database_path).await.map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+    use sqlx::{ConnectOptions, Connection};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    /// A fresh directory for one test, removed when dropped.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("storage-test-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Scratch(path)
+        }
+
+        fn database(&self) -> String {
+            self.0.join("metrics.db").to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn options(database: &str) -> SqliteConnectOptions {
+        SqliteConnectOptions::from_str(&format!("sqlite:{}?mode=rwc", database))
+            .unwrap()
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_millis(100))
+    }
+
+    /// A WAL-mode pool on `database` with a table of `rows` 10 kB blobs.
+    async fn filled_pool(database: &str, rows: i64) -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options(database))
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE filler (data BLOB)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        write(&pool, rows).await;
+        pool
+    }
+
+    async fn write(pool: &SqlitePool, rows: i64) {
+        for _ in 0..rows {
+            sqlx::query("INSERT INTO filler VALUES (randomblob(10000))")
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+    }
+
+    fn on_disk(path: &str) -> u64 {
+        std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0)
+    }
+
+    #[test]
+    fn files_are_classified_by_name() {
+        let cases = [
+            ("metrics.db", FilePurpose::Database, true),
+            ("metrics.db-wal", FilePurpose::Wal, true),
+            ("metrics.db-shm", FilePurpose::SharedMemory, true),
+            ("metrics.db-journal", FilePurpose::Journal, true),
+            // Left from a database under another name
+            ("old.db-wal", FilePurpose::Wal, false),
+            ("old.db-shm", FilePurpose::SharedMemory, false),
+            ("old.db-journal", FilePurpose::Journal, false),
+            ("metrics.db.bak", FilePurpose::Backup, false),
+            ("metrics-backup-2026-01-01.db", FilePurpose::Backup, false),
+            ("metrics.db.OLD", FilePurpose::Backup, false),
+            ("etilqs_3f2a1b", FilePurpose::Temporary, false),
+            ("export.tmp", FilePurpose::Temporary, false),
+            // Another database is just a file here
+            ("old.db", FilePurpose::Unknown, false),
+            ("notes.txt", FilePurpose::Unknown, false),
+            ("metrics.db-wal.1", FilePurpose::Unknown, false),
+        ];
+        for (name, purpose, in_use) in cases {
+            assert_eq!(classify(name, "metrics.db"), (purpose, in_use), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_inventory_lists_every_file_with_its_size() {
+        let scratch = Scratch::new("inventory");
+        let database = scratch.database();
+        let pool = filled_pool(&database, 20).await;
+        for (name, bytes) in [("old.db-wal", 3_000), ("metrics.db.bak", 2_000), ("notes", 1)] {
+            std::fs::write(scratch.0.join(name), vec![0; bytes]).unwrap();
+        }
+        std::fs::create_dir(scratch.0.join("exports")).unwrap();
+
+        let directory = get_data_directory(&database).await.unwrap();
+        assert_eq!(directory.path, scratch.0.to_string_lossy());
+        let files: Vec<_> = directory
+            .files
+            .iter()
+            .map(|file| (file.name.as_str(), file.purpose, file.in_use))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("metrics.db-wal", FilePurpose::Wal, true),
+                ("metrics.db-shm", FilePurpose::SharedMemory, true),
+                ("metrics.db", FilePurpose::Database, true),
+                ("old.db-wal", FilePurpose::Wal, false),
+                ("metrics.db.bak", FilePurpose::Backup, false),
+                ("notes", FilePurpose::Unknown, false),
+            ]
+        );
+        for file in &directory.files {
+            let path = scratch.0.join(&file.name);
+            assert_eq!(file.bytes, on_disk(&path.to_string_lossy()), "{}", file.name);
+            assert!(file.modified.is_some(), "{}", file.name);
+        }
+        let total: u64 = directory.files.iter().map(|file| file.bytes).sum();
+        assert_eq!(directory.total_bytes, total);
+        pool.close().await;
+    }
+
+    #[tokio::test]
+    async fn a_checkpoint_truncates_the_wal_and_reports_the_shrinkage() {
+        let scratch = Scratch::new("checkpoint");
+        let database = scratch.database();
+        let wal = format!("{}-wal", database);
+        let pool = filled_pool(&database, 100).await;
+        let grown = on_disk(&wal);
+        assert!(grown > 1_000_000, "{}", grown);
+        assert_eq!(wal_size(&database).await.unwrap(), grown);
+
+        let report = checkpoint_wal(&pool, &database).await.unwrap();
+        assert_eq!(report.wal_bytes_before, grown);
+        assert_eq!(report.wal_bytes_after, 0);
+        assert_eq!(on_disk(&wal), 0);
+        assert_eq!(report.reclaimed_bytes, grown);
+        assert!(!report.busy);
+
+        // The rows are all in the database file now
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM filler")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 100);
+        assert!(on_disk(&database) > 1_000_000);
+        pool.close().await;
+    }
+
+    #[tokio::test]
+    async fn an_open_reader_keeps_the_wal_from_being_truncated() {
+        let scratch = Scratch::new("busy");
+        let database = scratch.database();
+        let pool = filled_pool(&database, 10).await;
+        let mut reader = options(&database).connect().await.unwrap();
+        let mut snapshot = reader.begin().await.unwrap();
+        let _: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM filler")
+            .fetch_one(&mut *snapshot)
+            .await
+            .unwrap();
+        write(&pool, 10).await;
+
+        let report = checkpoint_wal(&pool, &database).await.unwrap();
+        assert!(report.busy);
+        assert!(report.wal_bytes_after > 0);
+        assert_eq!(
+            report.reclaimed_bytes,
+            report.wal_bytes_before - report.wal_bytes_after
+        );
+
+        snapshot.rollback().await.unwrap();
+        let report = checkpoint_wal(&pool, &database).await.unwrap();
+        assert!(!report.busy);
+        assert_eq!(report.wal_bytes_after, 0);
+        pool.close().await;
+    }
+
+    #[tokio::test]
+    async fn the_scheduled_check_only_checkpoints_a_wal_past_the_limit() {
+        let scratch = Scratch::new("limit");
+        let database = scratch.database();
+        let pool = filled_pool(&database, 30).await;
+        let grown = wal_size(&database).await.unwrap();
+
+        assert!(checkpoint_if_larger(&pool, &database, grown).await.unwrap().is_none());
+        assert_eq!(wal_size(&database).await.unwrap(), grown);
+
+        let report = checkpoint_if_larger(&pool, &database, grown - 1).await.unwrap();
+        assert_eq!(report.unwrap().reclaimed_bytes, grown);
+        assert_eq!(wal_size(&database).await.unwrap(), 0);
+        // Nothing left to do
+        assert!(checkpoint_if_larger(&pool, &database, 0).await.unwrap().is_none());
+        pool.close().await;
+    }
+
+    #[tokio::test]
+    async fn a_missing_wal_is_empty() {
+        let scratch = Scratch::new("missing");
+        assert_eq!(wal_size(&scratch.database()).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,99 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// Successful requests delivered one way, streamed or not.
+#[derive(Debug, Default, Serialize)]
+pub struct DeliveryStats {
+    pub requests: i64,
+    pub avg_duration_ms: f64,
+    pub avg_input_tokens: f64,
+    pub avg_output_tokens: f64,
+    pub avg_total_tokens: f64,
+    /// Averaged over the requests upstream reported usage for, leaving estimates out;
+    /// `None` when it reported none
+    pub avg_reported_total_tokens: Option<f64>,
+    /// Requests whose response carried no usage, so their token counts are local estimates
+    /// (or 0 for requests logged before estimates existed). For streams this means the
+    /// client didn't ask for `stream_options.include_usage`.
+    pub missing_usage_requests: i64,
+    /// Share of `requests` with missing usage; `None` without requests
+    pub missing_usage_rate: Option<f64>,
+    /// Tokens on those requests, which come from estimates rather than LM Studio
+    pub estimated_tokens: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StreamingReport {
+    pub since: Option<String>,
+    pub streamed: DeliveryStats,
+    pub non_streamed: DeliveryStats,
+}
+
+/// Compares successful streamed and non-streamed requests since `since`, leaving out
+/// benchmark runs as `/stats/summary` does.
+pub async fn get_streaming_report(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<StreamingReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        WITH delivered AS (
+            SELECT
+                was_streamed,
+                duration_ms,
+                input_tokens,
+                output_tokens,
+                total_tokens,
+                (tokens_estimated = 1 OR total_tokens = 0) as missing_usage
+            FROM requests
+            WHERE is_error = 0 AND benchmark_id IS NULL AND (?1 IS NULL OR start_time >= ?1)
+        )
+        SELECT
+            was_streamed,
+            COUNT(*) as requests,
+            COALESCE(AVG(CAST(duration_ms AS REAL)), 0.0) as avg_duration_ms,
+            COALESCE(AVG(CAST(input_tokens AS REAL)), 0.0) as avg_input_tokens,
+            COALESCE(AVG(CAST(output_tokens AS REAL)), 0.0) as avg_output_tokens,
+            COALESCE(AVG(CAST(total_tokens AS REAL)), 0.0) as avg_total_tokens,
+            AVG(CASE WHEN NOT missing_usage THEN CAST(total_tokens AS REAL) END)
+                as avg_reported_total_tokens,
+            COALESCE(SUM(missing_usage), 0) as missing_usage_requests,
+            COALESCE(SUM(CASE WHEN missing_usage THEN total_tokens ELSE 0 END), 0)
+                as estimated_tokens
+        FROM delivered
+        GROUP BY was_streamed
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut report = StreamingReport {
+        since: since.map(|s| s.to_string()),
+        streamed: DeliveryStats::default(),
+        non_streamed: DeliveryStats::default(),
+    };
+    for row in rows {
+        let requests: i64 = row.try_get("requests")?;
+        let missing_usage_requests: i64 = row.try_get("missing_usage_requests")?;
+        let stats = DeliveryStats {
+            requests,
+            avg_duration_ms: row.try_get("avg_duration_ms")?,
+            avg_input_tokens: row.try_get("avg_input_tokens")?,
+            avg_output_tokens: row.try_get("avg_output_tokens")?,
+            avg_total_tokens: row.try_get("avg_total_tokens")?,
+            avg_reported_total_tokens: row.try_get("avg_reported_total_tokens")?,
+            missing_usage_requests,
+            missing_usage_rate: (requests > 0)
+                .then(|| missing_usage_requests as f64 / requests as f64),
+            estimated_tokens: row.try_get("estimated_tokens")?,
+        };
+        if row.try_get::<bool, _>("was_streamed")? {
+            report.streamed = stats;
+        } else {
+            report.non_streamed = stats;
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,16 @@
+//! Databases for tests.
+
+use sqlx::SqlitePool;
+use sqlx::sqlite::SqlitePoolOptions;
+
+/// A fresh in-memory database with the full schema. One connection, since each
+/// in-memory connection would otherwise see its own empty database.
+pub async fn memory_pool() -> SqlitePool {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("in-memory database");
+    super::init_db(&pool).await.expect("schema");
+    pool
+}
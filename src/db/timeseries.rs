@@ -0,0 +1,154 @@
+use chrono::{DateTime, Datelike, Duration, DurationRound, Utc};
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+
+use super::models::TERMINATION_ABANDONED;
+
+/// Most buckets one series may span
+pub const MAX_BUCKETS: i64 = 1000;
+
+/// Width of a series bucket. Buckets are aligned in UTC: hours on the hour, days at
+/// midnight, weeks at midnight on Monday.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Hour,
+    Day,
+    Week,
+}
+
+impl Bucket {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "hour" => Some(Self::Hour),
+            "day" => Some(Self::Day),
+            "week" => Some(Self::Week),
+            _ => None,
+        }
+    }
+
+    pub fn width(self) -> Duration {
+        match self {
+            Self::Hour => Duration::hours(1),
+            Self::Day => Duration::days(1),
+            Self::Week => Duration::weeks(1),
+        }
+    }
+
+    /// Start of the bucket holding `time`.
+    pub fn align(self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let day = time.duration_trunc(Duration::days(1)).unwrap_or(time);
+        match self {
+            Self::Hour => time.duration_trunc(Duration::hours(1)).unwrap_or(time),
+            Self::Day => day,
+            Self::Week => day - Duration::days(day.weekday().num_days_from_monday().into()),
+        }
+    }
+
+    /// SQLite expression for the bucket start of `start_time`, in the same format as
+    /// [`bucket_key`]
+    pub fn sql(self) -> &'static str {
+        match self {
+            Self::Hour => "strftime('%Y-%m-%dT%H:00:00Z', start_time)",
+            Self::Day => "strftime('%Y-%m-%dT00:00:00Z', start_time)",
+            Self::Week => "strftime('%Y-%m-%dT00:00:00Z', start_time, '-6 days', 'weekday 1')",
+        }
+    }
+}
+
+fn bucket_key(start: DateTime<Utc>) -> String {
+    start.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesPoint {
+    pub bucket_start: String,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub errors: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimeSeries {
+    pub bucket: Bucket,
+    /// Start of the first bucket
+    pub from: String,
+    /// End of the last bucket (exclusive)
+    pub to: String,
+    /// Every bucket in the range, oldest first; buckets without traffic are zeros
+    pub points: Vec<SeriesPoint>,
+}
+
+/// Usage per bucket over `[from, to)`, both already aligned to the bucket width.
+pub async fn get_timeseries(
+    pool: &SqlitePool,
+    bucket: Bucket,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    include_abandoned: bool,
+    exclude_batches: bool,
+    include_benchmarks: bool,
+) -> Result<TimeSeries, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT
+            {} as bucket_start,
+            COUNT(*) as requests,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens,
+            SUM(CASE WHEN is_error = 1 THEN 1 ELSE 0 END) as errors
+        FROM requests
+        WHERE start_time >= ?1 AND start_time < ?2
+          AND (?3 OR termination IS NOT ?4) AND (NOT ?5 OR batch_id IS NULL)
+          AND (?6 OR benchmark_id IS NULL)
+        GROUP BY bucket_start
+        "#,
+        bucket.sql()
+    ))
+    .bind(from.to_rfc3339())
+    .bind(to.to_rfc3339())
+    .bind(include_abandoned)
+    .bind(TERMINATION_ABANDONED)
+    .bind(exclude_batches)
+    .bind(include_benchmarks)
+    .fetch_all(pool)
+    .await?;
+
+    let mut found = HashMap::new();
+    for row in &rows {
+        let start: String = row.try_get("bucket_start")?;
+        found.insert(
+            start.clone(),
+            SeriesPoint {
+                bucket_start: start,
+                requests: row.try_get("requests")?,
+                input_tokens: row.try_get("input_tokens")?,
+                output_tokens: row.try_get("output_tokens")?,
+                errors: row.try_get("errors")?,
+            },
+        );
+    }
+
+    let mut points = Vec::new();
+    let mut start = from;
+    while start < to {
+        let key = bucket_key(start);
+        points.push(found.remove(&key).unwrap_or(SeriesPoint {
+            bucket_start: key,
+            requests: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+            errors: 0,
+        }));
+        start += bucket.width();
+    }
+
+    Ok(TimeSeries {
+        bucket,
+        from: bucket_key(from),
+        to: bucket_key(to),
+        points,
+    })
+}
@@ -0,0 +1,95 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+/// Characters of a prompt kept as its sample, and as its grouping key when it has no hash
+pub const MAX_SAMPLE_CHARS: i64 = 200;
+
+/// Requests that sent the same prompt, with what they cost together.
+#[derive(Debug, Serialize)]
+pub struct TopPrompt {
+    /// Blob key of the prompt; `None` for rows not yet moved to blob storage, which are
+    /// grouped by their first 200 characters instead
+    pub prompt_hash: Option<String>,
+    pub occurrences: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub total_tokens: i64,
+    pub first_seen: String,
+    pub last_seen: String,
+    /// The prompt cut to [`MAX_SAMPLE_CHARS`] characters
+    pub sample: String,
+    pub truncated: bool,
+    /// The earliest occurrence, to look the full prompt up with `/stats/request/{id}`
+    pub sample_request_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopPromptReport {
+    pub since: Option<String>,
+    /// Most total tokens first
+    pub prompts: Vec<TopPrompt>,
+}
+
+/// The prompts that spent the most tokens from `since` onward. Warm-up probes and
+/// benchmark runs are left out.
+pub async fn get_top_prompts(
+    pool: &SqlitePool,
+    since: Option<&str>,
+    limit: i64,
+) -> Result<TopPromptReport, sqlx::Error> {
+    // substr counts characters rather than bytes, so samples never split one
+    let rows = sqlx::query(
+        r#"
+        WITH grouped AS (
+            SELECT
+                prompt_hash,
+                COUNT(*) as occurrences,
+                COALESCE(SUM(input_tokens), 0) as input_tokens,
+                COALESCE(SUM(output_tokens), 0) as output_tokens,
+                COALESCE(SUM(total_tokens), 0) as total_tokens,
+                MIN(start_time) as first_seen,
+                MAX(start_time) as last_seen,
+                MIN(id) as first_id
+            FROM requests
+            WHERE is_probe = 0 AND benchmark_id IS NULL AND (?1 IS NULL OR start_time >= ?1)
+            GROUP BY COALESCE(prompt_hash, substr(prompt, 1, ?3))
+            ORDER BY total_tokens DESC, occurrences DESC
+            LIMIT ?2
+        )
+        SELECT
+            g.*,
+            r.proxy_request_id,
+            substr(r.prompt_text, 1, ?3) as sample,
+            length(r.prompt_text) > ?3 as truncated
+        FROM grouped g
+        JOIN request_rows r ON r.id = g.first_id
+        ORDER BY g.total_tokens DESC, g.occurrences DESC
+        "#,
+    )
+    .bind(since)
+    .bind(limit)
+    .bind(MAX_SAMPLE_CHARS)
+    .fetch_all(pool)
+    .await?;
+
+    let mut prompts = Vec::new();
+    for row in rows {
+        prompts.push(TopPrompt {
+            prompt_hash: row.try_get("prompt_hash")?,
+            occurrences: row.try_get("occurrences")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+            total_tokens: row.try_get("total_tokens")?,
+            first_seen: row.try_get("first_seen")?,
+            last_seen: row.try_get("last_seen")?,
+            sample: row.try_get::<Option<String>, _>("sample")?.unwrap_or_default(),
+            truncated: row.try_get::<Option<bool>, _>("truncated")?.unwrap_or(false),
+            sample_request_id: row.try_get("proxy_request_id")?,
+        });
+    }
+
+    Ok(TopPromptReport {
+        since: since.map(str::to_string),
+        prompts,
+    })
+}
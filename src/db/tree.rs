@@ -0,0 +1,346 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use sqlx::sqlite::SqliteRow;
+use std::collections::{HashMap, HashSet};
+
+/// Deepest descendant level followed when walking a request tree
+const MAX_TREE_DEPTH: i64 = 64;
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct TreeRollup {
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub total_tokens: i64,
+    pub duration_ms: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RequestNode {
+    pub id: i64,
+    pub proxy_request_id: String,
+    pub parent_id: Option<String>,
+    /// The node names a parent that isn't in the database, so it is treated as a root
+    pub parent_missing: bool,
+    pub endpoint: String,
+    pub model: String,
+    pub start_time: String,
+    pub duration_ms: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub total_tokens: i64,
+    pub is_error: bool,
+    /// Totals for this node and all of its descendants
+    pub cumulative: TreeRollup,
+    pub children: Vec<RequestNode>,
+}
+
+impl RequestNode {
+    fn rollup(&mut self) -> TreeRollup {
+        let mut total = TreeRollup {
+            requests: 1,
+            input_tokens: self.input_tokens,
+            output_tokens: self.output_tokens,
+            total_tokens: self.total_tokens,
+            duration_ms: self.duration_ms,
+        };
+
+        for child in &mut self.children {
+            let child_total = child.rollup();
+            total.requests += child_total.requests;
+            total.input_tokens += child_total.input_tokens;
+            total.output_tokens += child_total.output_tokens;
+            total.total_tokens += child_total.total_tokens;
+            total.duration_ms += child_total.duration_ms;
+        }
+
+        self.cumulative = total;
+        total
+    }
+}
+
+/// Loads the request with `proxy_request_id` and every request descended from it.
+///
+/// Returns `None` when the root request doesn't exist.
+pub async fn get_request_tree(
+    pool: &SqlitePool,
+    proxy_request_id: &str,
+) -> Result<Option<RequestNode>, sqlx::Error> {
+    // UNION (not UNION ALL) plus the depth guard keeps a corrupted cycle from looping
+    let rows = sqlx::query(&format!(
+        r#"
+        WITH RECURSIVE tree(proxy_request_id, depth) AS (
+            SELECT proxy_request_id, 0 FROM requests WHERE proxy_request_id = ?1
+            UNION
+            SELECT r.proxy_request_id, t.depth + 1
+            FROM requests r
+            JOIN tree t ON r.parent_id = t.proxy_request_id
+            WHERE t.depth < ?2 AND r.proxy_request_id != r.parent_id
+        )
+        SELECT {}
+        FROM requests r
+        JOIN (SELECT DISTINCT proxy_request_id FROM tree) t
+            ON r.proxy_request_id = t.proxy_request_id
+        ORDER BY r.start_time, r.id
+        "#,
+        NODE_COLUMNS
+    ))
+    .bind(proxy_request_id)
+    .bind(MAX_TREE_DEPTH)
+    .fetch_all(pool)
+    .await?;
+
+    let nodes = rows.iter().map(node_from_row).collect::<Result<Vec<_>, _>>()?;
+    let mut forest = nest(nodes, Some(proxy_request_id)).into_iter();
+    let Some(mut root) = forest.next() else {
+        return Ok(None);
+    };
+    // Only descendants were loaded, so anything else at the top is cut off from the root
+    // by a cycle; it's kept under the root like a child whose parent went missing
+    for mut orphan in forest {
+        orphan.parent_missing = true;
+        root.children.push(orphan);
+    }
+    root.rollup();
+    Ok(Some(root))
+}
+
+/// Columns [`node_from_row`] reads, from `requests r`.
+pub(super) const NODE_COLUMNS: &str = r#"
+    r.id,
+    r.proxy_request_id,
+    r.parent_id,
+    r.parent_id IS NOT NULL AND NOT EXISTS (
+        SELECT 1 FROM requests p WHERE p.proxy_request_id = r.parent_id
+    ) as parent_missing,
+    r.endpoint,
+    r.model,
+    r.start_time,
+    r.duration_ms,
+    r.input_tokens,
+    r.output_tokens,
+    r.total_tokens,
+    r.is_error
+"#;
+
+pub(super) fn node_from_row(row: &SqliteRow) -> Result<RequestNode, sqlx::Error> {
+    Ok(RequestNode {
+        id: row.try_get("id")?,
+        proxy_request_id: row.try_get("proxy_request_id")?,
+        parent_id: row.try_get("parent_id")?,
+        parent_missing: row.try_get("parent_missing")?,
+        endpoint: row.try_get("endpoint")?,
+        model: row.try_get("model")?,
+        start_time: row.try_get("start_time")?,
+        duration_ms: row.try_get("duration_ms")?,
+        input_tokens: row.try_get("input_tokens")?,
+        output_tokens: row.try_get("output_tokens")?,
+        total_tokens: row.try_get("total_tokens")?,
+        is_error: row.try_get("is_error")?,
+        cumulative: TreeRollup::default(),
+        children: Vec::new(),
+    })
+}
+
+/// Nests `nodes`, given oldest first, under their parents and fills in the rollups. A
+/// node whose parent isn't among them is a root, and so is the node with id `root`
+/// whatever its parent. Roots come first in their given order, followed by any nodes
+/// reachable from none of them, which only a cycle of parents leaves behind.
+///
+/// Parents are found by id rather than by position, since a streamed parent, or a clock
+/// step, can put a child's start before its parent's.
+pub(super) fn nest(nodes: Vec<RequestNode>, root: Option<&str>) -> Vec<RequestNode> {
+    let ids: HashSet<String> = nodes.iter().map(|n| n.proxy_request_id.clone()).collect();
+    let mut order = Vec::with_capacity(nodes.len());
+    let mut roots = Vec::new();
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    let mut by_id = HashMap::with_capacity(nodes.len());
+    for node in nodes {
+        let id = node.proxy_request_id.clone();
+        let parent = node
+            .parent_id
+            .as_ref()
+            .filter(|parent| ids.contains(*parent) && root != Some(id.as_str()));
+        match parent {
+            Some(parent) => children.entry(parent.clone()).or_default().push(id.clone()),
+            None => roots.push(id.clone()),
+        }
+        order.push(id.clone());
+        by_id.insert(id, node);
+    }
+
+    let mut forest: Vec<RequestNode> = roots
+        .iter()
+        .filter_map(|id| attach(id, &mut by_id, &mut children))
+        .collect();
+    for id in &order {
+        if let Some(node) = attach(id, &mut by_id, &mut children) {
+            forest.push(node);
+        }
+    }
+    for node in &mut forest {
+        node.rollup();
+    }
+    forest
+}
+
+/// Takes `id` out of `nodes` with its descendants in place; `None` once it's been taken.
+fn attach(
+    id: &str,
+    nodes: &mut HashMap<String, RequestNode>,
+    children: &mut HashMap<String, Vec<String>>,
+) -> Option<RequestNode> {
+    let mut node = nodes.remove(id)?;
+    for child in children.remove(id).unwrap_or_default() {
+        node.children.extend(attach(&child, nodes, children));
+    }
+    Some(node)
+}
+
+/// Checks whether a request with the given proxy request id has been recorded.
+pub async fn request_exists(pool: &SqlitePool, proxy_request_id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM requests WHERE proxy_request_id = ?)")
+        .bind(proxy_request_id)
+        .fetch_one(pool)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::{RequestRecord, insert_request};
+    use crate::db::testing::memory_pool;
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 2, 9, 0, 0).unwrap() + Duration::seconds(secs)
+    }
+
+    async fn insert(pool: &SqlitePool, id: &str, parent: Option<&str>, start: i64, tokens: i64) {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            at(start),
+            "hi".to_string(),
+        );
+        record.proxy_request_id = Some(id.to_string());
+        record.parent_id = parent.map(str::to_string);
+        record.duration_ms = 100;
+        record.input_tokens = tokens;
+        record.total_tokens = tokens;
+        insert_request(pool, &record).await.unwrap();
+    }
+
+    /// `(id, depth, parent_missing)` of every node, depth first.
+    fn outline(node: &RequestNode, depth: usize, out: &mut Vec<(String, usize, bool)>) {
+        out.push((node.proxy_request_id.clone(), depth, node.parent_missing));
+        for child in &node.children {
+            outline(child, depth + 1, out);
+        }
+    }
+
+    async fn tree(pool: &SqlitePool, root: &str) -> (RequestNode, Vec<(String, usize, bool)>) {
+        let tree = get_request_tree(pool, root).await.unwrap().unwrap();
+        let mut nodes = Vec::new();
+        outline(&tree, 0, &mut nodes);
+        (tree, nodes)
+    }
+
+    fn node(id: &str, depth: usize) -> (String, usize, bool) {
+        (id.to_string(), depth, false)
+    }
+
+    #[tokio::test]
+    async fn descendants_nest_with_cumulative_totals() {
+        let pool = memory_pool().await;
+        insert(&pool, "root", None, 0, 10).await;
+        insert(&pool, "a", Some("root"), 10, 20).await;
+        insert(&pool, "a1", Some("a"), 20, 30).await;
+        insert(&pool, "b", Some("root"), 30, 40).await;
+        insert(&pool, "other", None, 40, 50).await;
+
+        let (root, nodes) = tree(&pool, "root").await;
+        assert_eq!(nodes, [node("root", 0), node("a", 1), node("a1", 2), node("b", 1)]);
+        assert_eq!(root.cumulative.requests, 4);
+        assert_eq!(root.cumulative.input_tokens, 100);
+        assert_eq!(root.cumulative.duration_ms, 400);
+        assert_eq!(root.children[0].cumulative.input_tokens, 50);
+
+        // Any node can be asked for as the root of its own subtree
+        let (a, nodes) = tree(&pool, "a").await;
+        assert_eq!(nodes, [node("a", 0), node("a1", 1)]);
+        assert_eq!(a.cumulative.requests, 2);
+        assert!(get_request_tree(&pool, "missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn children_starting_before_their_parent_stay_attached() {
+        let pool = memory_pool().await;
+        insert(&pool, "root", None, 0, 1).await;
+        // The clock stepped back: the grandchild starts before both its ancestors
+        insert(&pool, "grandchild", Some("child"), -30, 1).await;
+        insert(&pool, "child", Some("root"), 0, 1).await;
+        insert(&pool, "sibling", Some("root"), -10, 1).await;
+
+        let (root, nodes) = tree(&pool, "root").await;
+        assert_eq!(
+            nodes,
+            [node("root", 0), node("sibling", 1), node("child", 1), node("grandchild", 2)]
+        );
+        assert_eq!(root.cumulative.requests, 4);
+    }
+
+    #[tokio::test]
+    async fn unknown_parents_are_flagged_and_cycles_kept_under_the_root() {
+        let pool = memory_pool().await;
+        insert(&pool, "orphan", Some("never-stored"), 0, 1).await;
+        insert(&pool, "child", Some("orphan"), 10, 1).await;
+        let (_, nodes) = tree(&pool, "orphan").await;
+        assert_eq!(nodes, [("orphan".to_string(), 0, true), node("child", 1)]);
+
+        // Written by hand: x and y name each other
+        insert(&pool, "x", Some("y"), 20, 1).await;
+        insert(&pool, "y", Some("x"), 30, 1).await;
+        insert(&pool, "z", Some("y"), 40, 1).await;
+        let (root, nodes) = tree(&pool, "x").await;
+        assert_eq!(nodes, [node("x", 0), node("y", 1), node("z", 2)]);
+        assert_eq!(root.cumulative.requests, 3);
+    }
+
+    #[test]
+    fn nest_keeps_every_node_whatever_the_order() {
+        let node = |id: &str, parent: Option<&str>| RequestNode {
+            id: 0,
+            proxy_request_id: id.to_string(),
+            parent_id: parent.map(str::to_string),
+            parent_missing: false,
+            endpoint: String::new(),
+            model: String::new(),
+            start_time: String::new(),
+            duration_ms: 1,
+            input_tokens: 0,
+            output_tokens: 0,
+            total_tokens: 0,
+            is_error: false,
+            cumulative: TreeRollup::default(),
+            children: Vec::new(),
+        };
+        let forest = nest(
+            vec![
+                node("c", Some("b")),
+                node("b", Some("a")),
+                node("outside", Some("elsewhere")),
+                node("a", None),
+                // A cycle with no way in from a root
+                node("p", Some("q")),
+                node("q", Some("p")),
+            ],
+            None,
+        );
+        let tops: Vec<(&str, i64)> = forest
+            .iter()
+            .map(|n| (n.proxy_request_id.as_str(), n.cumulative.requests))
+            .collect();
+        assert_eq!(tops, [("outside", 1), ("a", 3), ("p", 2)]);
+    }
+}
@@ -0,0 +1,269 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Inclusive upper bounds of the `output_tokens / max_tokens` buckets
+const USAGE_BUCKETS: [f64; 4] = [0.25, 0.5, 0.75, 0.9];
+
+/// Clients need at least this many capped requests to be highlighted in the summary
+const MIN_HIGHLIGHT_REQUESTS: i64 = 5;
+
+#[derive(Debug, Serialize)]
+pub struct UsageBucket {
+    /// Upper bound of the ratio bucket; `None` for the open-ended last bucket
+    pub le: Option<f64>,
+    pub count: i64,
+}
+
+/// Requests that relied on the server's default generation limit.
+#[derive(Debug, Serialize)]
+pub struct ServerDefaultTruncation {
+    pub requests: i64,
+    pub length_hits: i64,
+    pub truncation_rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Truncation {
+    pub requests: i64,
+    /// Requests that sent an explicit max_tokens ceiling
+    pub capped_requests: i64,
+    /// Capped requests that stopped with finish_reason "length"
+    pub length_hits: i64,
+    pub truncation_rate: Option<f64>,
+    /// Mean of output_tokens / max_tokens over capped requests
+    pub avg_ceiling_usage: Option<f64>,
+    pub ceiling_usage_distribution: Vec<UsageBucket>,
+    pub server_default: ServerDefaultTruncation,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientTruncation {
+    pub client: String,
+    #[serde(flatten)]
+    pub truncation: Truncation,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelTruncation {
+    pub model: String,
+    #[serde(flatten)]
+    pub truncation: Truncation,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TruncationReport {
+    pub since: Option<String>,
+    pub overall: Truncation,
+    pub by_client: Vec<ClientTruncation>,
+    pub by_model: Vec<ModelTruncation>,
+}
+
+/// Headline figure for the client whose capped requests hit the limit most often.
+#[derive(Debug, Serialize)]
+pub struct TruncatingClient {
+    pub client: String,
+    pub capped_requests: i64,
+    pub length_hits: i64,
+    pub truncation_rate: f64,
+}
+
+#[derive(Default)]
+struct TruncationTally {
+    capped: i64,
+    length_hits: i64,
+    usage_sum: f64,
+    usage_count: i64,
+    buckets: [i64; USAGE_BUCKETS.len() + 1],
+    default_requests: i64,
+    default_length_hits: i64,
+}
+
+struct TruncationRow {
+    max_tokens: Option<i64>,
+    hit_length: bool,
+    usage_bucket: Option<usize>,
+    usage_sum: f64,
+    requests: i64,
+}
+
+impl TruncationTally {
+    fn add(&mut self, row: &TruncationRow) {
+        let hits = if row.hit_length { row.requests } else { 0 };
+
+        if row.max_tokens.is_none() {
+            self.default_requests += row.requests;
+            self.default_length_hits += hits;
+            return;
+        }
+
+        self.capped += row.requests;
+        self.length_hits += hits;
+        if let Some(bucket) = row.usage_bucket {
+            self.buckets[bucket.min(USAGE_BUCKETS.len())] += row.requests;
+            self.usage_sum += row.usage_sum;
+            self.usage_count += row.requests;
+        }
+    }
+
+    fn finish(self) -> Truncation {
+        let ceiling_usage_distribution = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(index, &count)| UsageBucket {
+                le: USAGE_BUCKETS.get(index).copied(),
+                count,
+            })
+            .collect();
+
+        Truncation {
+            requests: self.capped + self.default_requests,
+            capped_requests: self.capped,
+            length_hits: self.length_hits,
+            truncation_rate: ratio(self.length_hits, self.capped),
+            avg_ceiling_usage: (self.usage_count > 0)
+                .then(|| self.usage_sum / self.usage_count as f64),
+            ceiling_usage_distribution,
+            server_default: ServerDefaultTruncation {
+                requests: self.default_requests,
+                length_hits: self.default_length_hits,
+                truncation_rate: ratio(self.default_length_hits, self.default_requests),
+            },
+        }
+    }
+}
+
+fn ratio(part: i64, whole: i64) -> Option<f64> {
+    (whole > 0).then(|| part as f64 / whole as f64)
+}
+
+/// Reports how often requests stop at their max_tokens ceiling, per client and model.
+///
+/// Requests without an explicit max_tokens are kept apart under `server_default`
+/// so the server's own limit doesn't skew the ceiling ratios.
+pub async fn get_truncation(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<TruncationReport, sqlx::Error> {
+    truncation_for(pool, since, None, None).await
+}
+
+/// [`get_truncation`] up to `to` (exclusive), over the models in `models`, a JSON
+/// array; every model when `None`.
+async fn truncation_for(
+    pool: &SqlitePool,
+    since: Option<&str>,
+    to: Option<&str>,
+    models: Option<&str>,
+) -> Result<TruncationReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            model,
+            COALESCE(client_id, 'unknown') as client,
+            max_tokens,
+            COALESCE(finish_reason = 'length', 0) as hit_length,
+            CASE
+                WHEN max_tokens IS NULL OR max_tokens <= 0 THEN NULL
+                WHEN output_tokens * 4 <= max_tokens THEN 0
+                WHEN output_tokens * 2 <= max_tokens THEN 1
+                WHEN output_tokens * 4 <= max_tokens * 3 THEN 2
+                WHEN output_tokens * 10 <= max_tokens * 9 THEN 3
+                ELSE 4
+            END as usage_bucket,
+            COALESCE(SUM(CAST(output_tokens AS REAL) / NULLIF(max_tokens, 0)), 0.0) as usage_sum,
+            COUNT(*) as requests
+        FROM requests
+        WHERE is_error = 0 AND (?1 IS NULL OR start_time >= ?1)
+          AND (?2 IS NULL OR start_time < ?2)
+          AND (?3 IS NULL OR model IN (SELECT value FROM json_each(?3)))
+        GROUP BY model, client, max_tokens, hit_length, usage_bucket
+        "#,
+    )
+    .bind(since)
+    .bind(to)
+    .bind(models)
+    .fetch_all(pool)
+    .await?;
+
+    let mut overall = TruncationTally::default();
+    let mut by_client: HashMap<String, TruncationTally> = HashMap::new();
+    let mut by_model: HashMap<String, TruncationTally> = HashMap::new();
+
+    for row in rows {
+        let model: String = row.try_get("model")?;
+        let client: String = row.try_get("client")?;
+        let usage_bucket: Option<i64> = row.try_get("usage_bucket")?;
+        let entry = TruncationRow {
+            max_tokens: row.try_get("max_tokens")?,
+            hit_length: row.try_get("hit_length")?,
+            usage_bucket: usage_bucket.map(|b| b as usize),
+            usage_sum: row.try_get("usage_sum")?,
+            requests: row.try_get("requests")?,
+        };
+
+        overall.add(&entry);
+        by_client.entry(client).or_default().add(&entry);
+        by_model.entry(model).or_default().add(&entry);
+    }
+
+    let mut by_client: Vec<ClientTruncation> = by_client
+        .into_iter()
+        .map(|(client, tally)| ClientTruncation {
+            client,
+            truncation: tally.finish(),
+        })
+        .collect();
+    by_client.sort_by_key(|entry| Reverse(entry.truncation.length_hits));
+
+    let mut by_model: Vec<ModelTruncation> = by_model
+        .into_iter()
+        .map(|(model, tally)| ModelTruncation {
+            model,
+            truncation: tally.finish(),
+        })
+        .collect();
+    by_model.sort_by_key(|entry| Reverse(entry.truncation.length_hits));
+
+    Ok(TruncationReport {
+        since: since.map(|s| s.to_string()),
+        overall: overall.finish(),
+        by_client,
+        by_model,
+    })
+}
+
+/// Finds the client with the highest truncation rate among those with enough capped
+/// requests between `from` and `to` (exclusive) to the models in `models`, a JSON
+/// array; every model when `None`.
+pub async fn get_most_truncating_client(
+    pool: &SqlitePool,
+    from: Option<&str>,
+    to: Option<&str>,
+    models: Option<&str>,
+) -> Result<Option<TruncatingClient>, sqlx::Error> {
+    let report = truncation_for(pool, from, to, models).await?;
+
+    Ok(report
+        .by_client
+        .into_iter()
+        .filter(|entry| {
+            entry.truncation.capped_requests >= MIN_HIGHLIGHT_REQUESTS
+                && entry.truncation.length_hits > 0
+        })
+        .filter_map(|entry| {
+            Some(TruncatingClient {
+                truncation_rate: entry.truncation.truncation_rate?,
+                capped_requests: entry.truncation.capped_requests,
+                length_hits: entry.truncation.length_hits,
+                client: entry.client,
+            })
+        })
+        .max_by(|a, b| {
+            a.truncation_rate
+                .total_cmp(&b.truncation_rate)
+                .then(a.length_hits.cmp(&b.length_hits))
+        }))
+}
@@ -0,0 +1,416 @@
+use chrono::DateTime;
+use serde::Serialize;
+use sqlx::{Row, SqliteConnection, SqlitePool};
+use std::collections::BTreeMap;
+
+use super::models::RequestRecord;
+
+/// A request from the same client starting within this long of the previous one
+/// finishing continues its turn, when no parent or retry link says otherwise
+pub const TURN_GAP_MS: i64 = 3000;
+
+/// Where a request falls in its logical turn, worked out as it is stored.
+pub struct TurnAssignment {
+    /// Proxy request id of the turn's first request
+    pub turn_id: String,
+    /// Wall-clock time from the turn's first arrival to this request's completion
+    pub turn_latency_ms: i64,
+}
+
+/// Assigns a request to a turn, inside the transaction that inserts it.
+///
+/// Turn boundaries, in order:
+/// - a retry continues the turn of the attempt it retries, and a request with a stored
+///   parent continues its parent's turn, so a whole request tree is one turn;
+/// - otherwise a request from the same client starting within [`TURN_GAP_MS`] of the
+///   client's latest request ending continues that request's turn (tool loops whose
+///   client sends no linkage);
+/// - otherwise it starts a new turn.
+///
+/// The latency is kept only on the latest request of the turn, so earlier rows of the
+/// turn are cleared.
+pub async fn assign_turn(
+    conn: &mut SqliteConnection,
+    record: &RequestRecord,
+) -> Result<Option<TurnAssignment>, sqlx::Error> {
+    let Some(proxy_request_id) = record.proxy_request_id.as_deref() else {
+        return Ok(None);
+    };
+
+    let mut turn_id = None;
+    for linked in [&record.retry_of, &record.parent_id].into_iter().flatten() {
+        turn_id = sqlx::query_scalar(
+            "SELECT COALESCE(turn_id, proxy_request_id) FROM requests WHERE proxy_request_id = ?",
+        )
+        .bind(linked)
+        .fetch_optional(&mut *conn)
+        .await?;
+        if turn_id.is_some() {
+            break;
+        }
+    }
+
+    if turn_id.is_none()
+        && let Some(client_id) = &record.client_id
+        && let Ok(start) = DateTime::parse_from_rfc3339(&record.start_time)
+    {
+        let cutoff = (start - chrono::Duration::milliseconds(TURN_GAP_MS)).to_rfc3339();
+        turn_id = sqlx::query_scalar(
+            r#"
+            SELECT COALESCE(turn_id, proxy_request_id)
+            FROM requests
+            WHERE client_id = ? AND proxy_request_id IS NOT NULL
+              AND end_time >= ? AND start_time <= ?
+            ORDER BY end_time DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(client_id)
+        .bind(&cutoff)
+        .bind(&record.start_time)
+        .fetch_optional(&mut *conn)
+        .await?;
+    }
+
+    let Some(turn_id) = turn_id else {
+        return Ok(Some(TurnAssignment {
+            turn_id: proxy_request_id.to_string(),
+            turn_latency_ms: record.duration_ms,
+        }));
+    };
+
+    let turn_start: Option<String> = sqlx::query_scalar(
+        "SELECT MIN(start_time) FROM requests WHERE turn_id = ?1 OR proxy_request_id = ?1",
+    )
+    .bind(&turn_id)
+    .fetch_one(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"
+        UPDATE requests SET turn_latency_ms = NULL
+        WHERE (turn_id = ?1 OR proxy_request_id = ?1) AND turn_latency_ms IS NOT NULL
+        "#,
+    )
+    .bind(&turn_id)
+    .execute(&mut *conn)
+    .await?;
+
+    let elapsed = turn_start
+        .as_deref()
+        .and_then(|start| DateTime::parse_from_rfc3339(start).ok())
+        .zip(DateTime::parse_from_rfc3339(&record.end_time).ok())
+        .map(|(start, end)| (end - start).num_milliseconds());
+    Ok(Some(TurnAssignment {
+        turn_id,
+        turn_latency_ms: elapsed.unwrap_or(0).max(record.duration_ms),
+    }))
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TurnLatency {
+    pub turns: usize,
+    /// Turns made of more than one proxied call (tool rounds, retries)
+    pub multi_call_turns: usize,
+    pub avg_calls_per_turn: f64,
+    pub avg_ms: f64,
+    pub p50_ms: i64,
+    pub p95_ms: i64,
+    pub max_ms: i64,
+    /// Average duration of the final call alone, what per-request latency shows
+    pub avg_final_call_ms: f64,
+    #[serde(skip)]
+    latencies: Vec<i64>,
+    #[serde(skip)]
+    calls: i64,
+    #[serde(skip)]
+    final_call_ms: i64,
+}
+
+impl TurnLatency {
+    fn add(&mut self, latency_ms: i64, calls: i64, final_call_ms: i64) {
+        self.latencies.push(latency_ms);
+        self.calls += calls;
+        self.final_call_ms += final_call_ms;
+        if calls > 1 {
+            self.multi_call_turns += 1;
+        }
+    }
+
+    fn finish(mut self) -> Self {
+        self.turns = self.latencies.len();
+        if self.turns == 0 {
+            return self;
+        }
+        let turns = self.turns as f64;
+        self.latencies.sort_unstable();
+        self.avg_calls_per_turn = round(self.calls as f64 / turns);
+        self.avg_ms = round(self.latencies.iter().sum::<i64>() as f64 / turns);
+        self.avg_final_call_ms = round(self.final_call_ms as f64 / turns);
+        self.p50_ms = percentile(&self.latencies, 0.5);
+        self.p95_ms = percentile(&self.latencies, 0.95);
+        self.max_ms = self.latencies[self.turns - 1];
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelTurnLatency {
+    pub model: String,
+    #[serde(flatten)]
+    pub latency: TurnLatency,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientTurnLatency {
+    pub client_id: Option<String>,
+    #[serde(flatten)]
+    pub latency: TurnLatency,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TurnLatencyReport {
+    pub since: Option<String>,
+    pub gap_ms: i64,
+    pub totals: TurnLatency,
+    /// By the model of each turn's final call
+    pub by_model: Vec<ModelTurnLatency>,
+    pub by_client: Vec<ClientTurnLatency>,
+}
+
+/// Turn latencies of turns that finished since `since`, slowest p95 first.
+pub async fn get_turn_latency(
+    pool: &SqlitePool,
+    since: Option<&str>,
+) -> Result<TurnLatencyReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            r.model,
+            r.client_id,
+            r.turn_latency_ms,
+            r.duration_ms,
+            (SELECT COUNT(*) FROM requests c WHERE c.turn_id = r.turn_id) as calls
+        FROM requests r
+        WHERE r.turn_latency_ms IS NOT NULL AND (?1 IS NULL OR r.end_time >= ?1)
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    let mut totals = TurnLatency::default();
+    let mut by_model: BTreeMap<String, TurnLatency> = BTreeMap::new();
+    let mut by_client: BTreeMap<Option<String>, TurnLatency> = BTreeMap::new();
+    for row in &rows {
+        let latency: i64 = row.try_get("turn_latency_ms")?;
+        let calls: i64 = row.try_get("calls")?;
+        let duration: i64 = row.try_get("duration_ms")?;
+        totals.add(latency, calls, duration);
+        by_model
+            .entry(row.try_get("model")?)
+            .or_default()
+            .add(latency, calls, duration);
+        by_client
+            .entry(row.try_get("client_id")?)
+            .or_default()
+            .add(latency, calls, duration);
+    }
+
+    let mut by_model: Vec<_> = by_model
+        .into_iter()
+        .map(|(model, latency)| ModelTurnLatency {
+            model,
+            latency: latency.finish(),
+        })
+        .collect();
+    by_model.sort_by_key(|model| std::cmp::Reverse(model.latency.p95_ms));
+    let mut by_client: Vec<_> = by_client
+        .into_iter()
+        .map(|(client_id, latency)| ClientTurnLatency {
+            client_id,
+            latency: latency.finish(),
+        })
+        .collect();
+    by_client.sort_by_key(|client| std::cmp::Reverse(client.latency.p95_ms));
+
+    Ok(TurnLatencyReport {
+        since: since.map(|s| s.to_string()),
+        gap_ms: TURN_GAP_MS,
+        totals: totals.finish(),
+        by_model,
+        by_client,
+    })
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[i64], quantile: f64) -> i64 {
+    let rank = ((sorted.len() as f64) * quantile).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn round(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::insert_request;
+    use crate::db::testing::memory_pool;
+    use chrono::{Duration, Utc};
+
+    /// A call from `client_id` starting `start_ms` after `t0` and lasting `duration_ms`.
+    fn call(t0: DateTime<Utc>, client_id: &str, start_ms: i64, duration_ms: i64) -> RequestRecord {
+        let start = t0 + Duration::milliseconds(start_ms);
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            start,
+            "Tell me a story".to_string(),
+        );
+        record.client_id = Some(client_id.to_string());
+        record.duration_ms = duration_ms;
+        record.end_time = (start + Duration::milliseconds(duration_ms)).to_rfc3339();
+        record
+    }
+
+    /// `(turn id, turn latency)` stored for each of `records`.
+    async fn turns_of(pool: &SqlitePool, records: &[&RequestRecord]) -> Vec<(String, Option<i64>)> {
+        let mut turns = Vec::new();
+        for record in records {
+            let row = sqlx::query(
+                "SELECT turn_id, turn_latency_ms FROM requests WHERE proxy_request_id = ?",
+            )
+            .bind(&record.proxy_request_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+            turns.push((row.get("turn_id"), row.get("turn_latency_ms")));
+        }
+        turns
+    }
+
+    fn id(record: &RequestRecord) -> String {
+        record.proxy_request_id.clone().unwrap()
+    }
+
+    #[tokio::test]
+    async fn single_call_turn_is_its_own_duration() {
+        let pool = memory_pool().await;
+        let t0 = Utc::now() - Duration::hours(1);
+        let only = call(t0, "a", 0, 800);
+        insert_request(&pool, &only).await.unwrap();
+
+        assert_eq!(turns_of(&pool, &[&only]).await, [(id(&only), Some(800))]);
+    }
+
+    #[tokio::test]
+    async fn tool_loop_tree_is_one_turn_timed_on_its_last_call() {
+        let pool = memory_pool().await;
+        let t0 = Utc::now() - Duration::hours(1);
+        // The tool runs for 10 s between calls, longer than the gap, but the links hold
+        let ask = call(t0, "a", 0, 1_000);
+        let mut tool_result = call(t0, "a", 11_000, 2_000);
+        tool_result.parent_id = ask.proxy_request_id.clone();
+        let mut answer = call(t0, "a", 24_000, 1_500);
+        answer.parent_id = tool_result.proxy_request_id.clone();
+        for record in [&ask, &tool_result, &answer] {
+            insert_request(&pool, record).await.unwrap();
+        }
+
+        assert_eq!(
+            turns_of(&pool, &[&ask, &tool_result, &answer]).await,
+            [(id(&ask), None), (id(&ask), None), (id(&ask), Some(25_500))]
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_continue_the_turn_of_the_attempt_they_retry() {
+        let pool = memory_pool().await;
+        let t0 = Utc::now() - Duration::hours(1);
+        let mut failed = call(t0, "a", 0, 500);
+        failed.is_error = true;
+        insert_request(&pool, &failed).await.unwrap();
+        let start = DateTime::parse_from_rfc3339(&failed.start_time).unwrap().to_utc();
+        let mut retry = failed.retry(start + Duration::seconds(20), "client");
+        retry.is_error = false;
+        retry.duration_ms = 1_000;
+        retry.end_time = (start + Duration::seconds(21)).to_rfc3339();
+        insert_request(&pool, &retry).await.unwrap();
+
+        assert_eq!(
+            turns_of(&pool, &[&failed, &retry]).await,
+            [(id(&failed), None), (id(&failed), Some(21_000))]
+        );
+    }
+
+    #[tokio::test]
+    async fn unlinked_calls_join_a_turn_only_within_the_gap() {
+        let pool = memory_pool().await;
+        let t0 = Utc::now() - Duration::hours(1);
+        let first = call(t0, "a", 0, 1_000);
+        let follow_up = call(t0, "a", 1_000 + TURN_GAP_MS, 1_000);
+        let other_client = call(t0, "b", 2_000 + TURN_GAP_MS, 500);
+        let after_pause = call(t0, "a", 3_000 + 2 * TURN_GAP_MS, 700);
+        for record in [&first, &follow_up, &other_client, &after_pause] {
+            insert_request(&pool, record).await.unwrap();
+        }
+
+        assert_eq!(
+            turns_of(&pool, &[&first, &follow_up, &other_client, &after_pause]).await,
+            [
+                (id(&first), None),
+                (id(&first), Some(2_000 + TURN_GAP_MS)),
+                (id(&other_client), Some(500)),
+                (id(&after_pause), Some(700)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn report_aggregates_turns_by_model_and_client() {
+        let pool = memory_pool().await;
+        let t0 = Utc::now() - Duration::hours(1);
+        let ask = call(t0, "agent", 0, 1_000);
+        let mut answer = call(t0, "agent", 10_000, 2_000);
+        answer.parent_id = ask.proxy_request_id.clone();
+        answer.model = "big".to_string();
+        let plain = call(t0, "chat", 0, 400);
+        for record in [&ask, &answer, &plain] {
+            insert_request(&pool, record).await.unwrap();
+        }
+
+        let report = get_turn_latency(&pool, None).await.unwrap();
+
+        assert_eq!(report.gap_ms, TURN_GAP_MS);
+        assert_eq!(report.totals.turns, 2);
+        assert_eq!(report.totals.multi_call_turns, 1);
+        assert_eq!(report.totals.avg_calls_per_turn, 1.5);
+        assert_eq!(report.totals.max_ms, 12_000);
+        assert_eq!(report.totals.p50_ms, 400);
+        assert_eq!(report.totals.avg_final_call_ms, 1_200.0);
+        let models: Vec<_> = report
+            .by_model
+            .iter()
+            .map(|model| (model.model.as_str(), model.latency.p95_ms))
+            .collect();
+        assert_eq!(models, [("big", 12_000), ("m", 400)]);
+        let clients: Vec<_> = report
+            .by_client
+            .iter()
+            .map(|client| (client.client_id.as_deref(), client.latency.turns))
+            .collect();
+        assert_eq!(clients, [(Some("agent"), 1), (Some("chat"), 1)]);
+
+        let since = Utc::now().to_rfc3339();
+        assert_eq!(get_turn_latency(&pool, Some(&since)).await.unwrap().totals.turns, 0);
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted = [100, 200, 300, 400];
+        assert_eq!(percentile(&sorted, 0.5), 200);
+        assert_eq!(percentile(&sorted, 0.95), 400);
+        assert_eq!(percentile(&[7], 0.0), 7);
+    }
+}
@@ -0,0 +1,260 @@
+//! Weekly and monthly usage per model, kept so all-time summaries don't scan every
+//! request.
+//!
+//! Rows are split by success, and hold plain sums and counts rather than averages, so
+//! closed weeks can be added to the raw rows of the current week and still give the exact
+//! figures a full scan would. Only what `/stats/summary` counts by default goes in:
+//! abandoned requests, benchmarks and warm-up probes are left out. Like the daily rollups,
+//! rows outlive the requests a prune deletes; a rebuild makes them match the table again.
+
+use serde::Serialize;
+use sqlx::{Row, SqliteConnection, SqlitePool};
+
+use super::models::{RequestRecord, TERMINATION_ABANDONED};
+
+pub const PERIOD_WEEK: &str = "week";
+pub const PERIOD_MONTH: &str = "month";
+
+/// Requests the rollups count. `?2` binds [`TERMINATION_ABANDONED`].
+const COUNTED: &str = "termination IS NOT ?2 AND benchmark_id IS NULL AND is_probe = 0";
+
+/// The sums each rollup row holds, in column order, over a group of requests
+const SUMS: &str = r#"
+    COUNT(*) as requests,
+    COALESCE(SUM(input_tokens), 0) as input_tokens,
+    COALESCE(SUM(output_tokens), 0) as output_tokens,
+    COALESCE(SUM(total_tokens), 0) as total_tokens,
+    COALESCE(SUM(duration_ms), 0) as duration_sum,
+    COALESCE(SUM(tokens_per_second), 0.0) as tps_sum,
+    COUNT(tokens_per_second) as tps_count,
+    MAX(tokens_per_second) as tps_max,
+    COALESCE(SUM(ttft_ms), 0) as ttft_sum,
+    COUNT(ttft_ms) as ttft_count,
+    SUM((input_tokens * input_price_per_m + output_tokens * output_price_per_m) / 1e6) as cost
+"#;
+
+/// Start of the period of type `?1` that the RFC 3339 time `time` falls in: the Monday of
+/// its UTC week or the first of its month.
+fn period_start(time: &str) -> String {
+    format!(
+        "CASE ?1 WHEN '{PERIOD_WEEK}' THEN date(substr({time}, 1, 10), '-6 days', 'weekday 1') \
+         ELSE date(substr({time}, 1, 10), 'start of month') END"
+    )
+}
+
+/// Rows to sum up again for figures over every counted request: the weekly rollups of
+/// closed weeks, plus the same sums over the requests of the current week. One row per
+/// model and success in each part. `?1` binds [`PERIOD_WEEK`] and `?2`
+/// [`TERMINATION_ABANDONED`].
+pub fn closed_weeks_and_current() -> String {
+    format!(
+        r#"
+        SELECT model, is_error, requests, input_tokens, output_tokens, total_tokens,
+               duration_sum, tps_sum, tps_count, tps_max, ttft_sum, ttft_count, cost
+        FROM usage_rollups
+        WHERE period_type = ?1 AND period_start < date('now', '-6 days', 'weekday 1')
+        UNION ALL
+        SELECT model, is_error, {SUMS}
+        FROM requests
+        WHERE start_time >= date('now', '-6 days', 'weekday 1') AND {COUNTED}
+        GROUP BY model, is_error
+        "#
+    )
+}
+
+/// Usage of one model over one week or month.
+#[derive(Debug, Serialize)]
+pub struct UsageRollup {
+    /// UTC date the period starts on
+    pub period_start: String,
+    pub model: String,
+    pub requests: i64,
+    pub errors: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub duration_sum: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageRollupReport {
+    pub period_type: &'static str,
+    /// Newest period first, most requests first within it
+    pub rollups: Vec<UsageRollup>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RebuildReport {
+    pub weekly_rows: u64,
+    pub monthly_rows: u64,
+}
+
+/// Adds a request to its week and month, inside the transaction that inserts it.
+pub async fn add_request(
+    conn: &mut SqliteConnection,
+    record: &RequestRecord,
+) -> Result<(), sqlx::Error> {
+    if record.termination.as_deref() == Some(TERMINATION_ABANDONED)
+        || record.benchmark_id.is_some()
+        || record.is_probe
+    {
+        return Ok(());
+    }
+
+    let cost = match (record.input_price_per_m, record.output_price_per_m) {
+        (Some(input), Some(output)) => {
+            Some((record.input_tokens as f64 * input + record.output_tokens as f64 * output) / 1e6)
+        }
+        _ => None,
+    };
+    for period_type in [PERIOD_WEEK, PERIOD_MONTH] {
+        sqlx::query(&format!(
+            r#"
+            INSERT INTO usage_rollups (
+                period_type, period_start, model, is_error, requests, input_tokens,
+                output_tokens, total_tokens, duration_sum, tps_sum, tps_count, tps_max,
+                ttft_sum, ttft_count, cost
+            )
+            VALUES (?1, {}, ?3, ?4, 1, ?5, ?6, ?7, ?8, COALESCE(?9, 0.0), ?9 IS NOT NULL, ?9,
+                    COALESCE(?10, 0), ?10 IS NOT NULL, ?11)
+            ON CONFLICT (period_type, period_start, model, is_error) DO UPDATE SET
+                requests = requests + 1,
+                input_tokens = input_tokens + excluded.input_tokens,
+                output_tokens = output_tokens + excluded.output_tokens,
+                total_tokens = total_tokens + excluded.total_tokens,
+                duration_sum = duration_sum + excluded.duration_sum,
+                tps_sum = tps_sum + excluded.tps_sum,
+                tps_count = tps_count + excluded.tps_count,
+                tps_max = COALESCE(MAX(tps_max, excluded.tps_max), tps_max, excluded.tps_max),
+                ttft_sum = ttft_sum + excluded.ttft_sum,
+                ttft_count = ttft_count + excluded.ttft_count,
+                cost = CASE WHEN excluded.cost IS NULL THEN cost
+                    ELSE COALESCE(cost, 0.0) + excluded.cost END
+            "#,
+            period_start("?2")
+        ))
+        .bind(period_type)
+        .bind(&record.start_time)
+        .bind(&record.model)
+        .bind(record.is_error)
+        .bind(record.input_tokens)
+        .bind(record.output_tokens)
+        .bind(record.total_tokens)
+        .bind(record.duration_ms)
+        .bind(record.tokens_per_second)
+        .bind(record.ttft_ms)
+        .bind(cost)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Fills an empty rollup table from the requests already stored, so databases from
+/// before rollups existed start with their history.
+pub async fn backfill_if_empty(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let populated: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM usage_rollups)")
+        .fetch_one(pool)
+        .await?;
+    if populated {
+        return Ok(());
+    }
+
+    let report = rebuild(pool).await?;
+    if report.weekly_rows + report.monthly_rows > 0 {
+        tracing::info!(
+            "Built {} weekly and {} monthly usage rollup rows",
+            report.weekly_rows,
+            report.monthly_rows
+        );
+    }
+    Ok(())
+}
+
+/// Replaces every rollup with sums over the requests stored now, in one transaction.
+pub async fn rebuild(pool: &SqlitePool) -> Result<RebuildReport, sqlx::Error> {
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+    sqlx::query("DELETE FROM usage_rollups")
+        .execute(&mut *tx)
+        .await?;
+
+    let mut rows = Vec::new();
+    for period_type in [PERIOD_WEEK, PERIOD_MONTH] {
+        let result = sqlx::query(&format!(
+            r#"
+            INSERT INTO usage_rollups (
+                period_type, period_start, model, is_error, requests, input_tokens,
+                output_tokens, total_tokens, duration_sum, tps_sum, tps_count, tps_max,
+                ttft_sum, ttft_count, cost
+            )
+            SELECT ?1, {}, model, is_error, {SUMS}
+            FROM requests
+            WHERE {COUNTED}
+            GROUP BY 2, model, is_error
+            "#,
+            period_start("start_time")
+        ))
+        .bind(period_type)
+        .bind(TERMINATION_ABANDONED)
+        .execute(&mut *tx)
+        .await?;
+        rows.push(result.rows_affected());
+    }
+    tx.commit().await?;
+
+    Ok(RebuildReport {
+        weekly_rows: rows[0],
+        monthly_rows: rows[1],
+    })
+}
+
+/// Usage per model over the latest `limit` periods of `period_type`.
+pub async fn get_rollups(
+    pool: &SqlitePool,
+    period_type: &'static str,
+    limit: i64,
+) -> Result<UsageRollupReport, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            period_start,
+            model,
+            SUM(requests) as requests,
+            SUM(CASE WHEN is_error = 1 THEN requests ELSE 0 END) as errors,
+            SUM(input_tokens) as input_tokens,
+            SUM(output_tokens) as output_tokens,
+            SUM(duration_sum) as duration_sum
+        FROM usage_rollups
+        WHERE period_type = ?1 AND period_start IN (
+            SELECT DISTINCT period_start FROM usage_rollups
+            WHERE period_type = ?1
+            ORDER BY period_start DESC
+            LIMIT ?2
+        )
+        GROUP BY period_start, model
+        ORDER BY period_start DESC, requests DESC
+        "#,
+    )
+    .bind(period_type)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    let mut rollups = Vec::new();
+    for row in rows {
+        rollups.push(UsageRollup {
+            period_start: row.try_get("period_start")?,
+            model: row.try_get("model")?,
+            requests: row.try_get("requests")?,
+            errors: row.try_get("errors")?,
+            input_tokens: row.try_get("input_tokens")?,
+            output_tokens: row.try_get("output_tokens")?,
+            duration_sum: row.try_get("duration_sum")?,
+        });
+    }
+
+    Ok(UsageRollupReport {
+        period_type,
+        rollups,
+    })
+}
@@ -0,0 +1,416 @@
+use chrono::{DateTime, Duration, NaiveDate};
+use serde::Serialize;
+use sqlx::{Row, SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+
+use super::models::{RequestRecord, TERMINATION_ABANDONED};
+use crate::intervals::{self, DAY_MS, Interval};
+
+/// How far before a request's start earlier requests are looked for overlaps; requests
+/// running longer than this may count some time twice
+const OVERLAP_LOOKBACK: Duration = Duration::hours(24);
+
+#[derive(Debug, Serialize)]
+pub struct DayUtilization {
+    pub day: String,
+    pub busy_ms: i64,
+    pub busy_hours: f64,
+    /// Busy time as a percentage of the 24-hour day
+    pub utilization_pct: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelUtilization {
+    pub model: String,
+    pub busy_ms: i64,
+    pub busy_hours: f64,
+    /// Busy time as a percentage of every hour in the window
+    pub utilization_pct: f64,
+    /// Days with any busy time, oldest first
+    pub daily: Vec<DayUtilization>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Utilization {
+    /// First and last UTC day of the window; the last is today
+    pub from: String,
+    pub to: String,
+    pub days: i64,
+    /// Busiest first
+    pub models: Vec<ModelUtilization>,
+}
+
+/// The wall-clock span of a request, from its start for its measured duration.
+fn interval_of(start_time: &str, duration_ms: i64) -> Option<Interval> {
+    let start = DateTime::parse_from_rfc3339(start_time)
+        .ok()?
+        .timestamp_millis();
+    (duration_ms > 0).then(|| (start, start + duration_ms))
+}
+
+/// Adds the time a request kept its model busy that no stored request of the same model
+/// already covered, inside the transaction that inserts it. The request's own row is
+/// written first, so concurrent inserts see each other.
+pub async fn add_request(
+    conn: &mut SqliteConnection,
+    id: i64,
+    record: &RequestRecord,
+) -> Result<(), sqlx::Error> {
+    if record.termination.as_deref() == Some(TERMINATION_ABANDONED) {
+        return Ok(());
+    }
+    let Some(interval) = interval_of(&record.start_time, record.duration_ms) else {
+        return Ok(());
+    };
+    let (Some(lookback), Some(end)) = (
+        DateTime::from_timestamp_millis(interval.0).map(|start| start - OVERLAP_LOOKBACK),
+        DateTime::from_timestamp_millis(interval.1),
+    ) else {
+        return Ok(());
+    };
+
+    let rows = sqlx::query(
+        r#"
+        SELECT start_time, duration_ms
+        FROM requests
+        WHERE model = ? AND id != ? AND start_time >= ? AND start_time < ?
+          AND duration_ms > 0 AND termination IS NOT ?
+        "#,
+    )
+    .bind(&record.model)
+    .bind(id)
+    .bind(lookback.to_rfc3339())
+    .bind(end.to_rfc3339())
+    .bind(TERMINATION_ABANDONED)
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut covered = Vec::with_capacity(rows.len());
+    for row in rows {
+        let start_time: String = row.try_get("start_time")?;
+        covered.extend(interval_of(&start_time, row.try_get("duration_ms")?));
+    }
+
+    for part in intervals::uncovered(interval, covered) {
+        for (day, busy_ms) in intervals::split_by_day(part) {
+            add_busy_ms(&mut *conn, &day.to_string(), &record.model, busy_ms).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn add_busy_ms(
+    conn: &mut SqliteConnection,
+    day: &str,
+    model: &str,
+    busy_ms: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO daily_busy (day, model, busy_ms) VALUES (?, ?, ?)
+        ON CONFLICT (day, model) DO UPDATE SET busy_ms = busy_ms + excluded.busy_ms
+        "#,
+    )
+    .bind(day)
+    .bind(model)
+    .bind(busy_ms)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Fills an empty busy-time table from the requests already stored, merging each model's
+/// requests in one pass.
+pub async fn backfill_if_empty(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let populated: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM daily_busy)")
+        .fetch_one(pool)
+        .await?;
+    if populated {
+        return Ok(());
+    }
+
+    let rows = sqlx::query(
+        r#"
+        SELECT model, start_time, duration_ms
+        FROM requests
+        WHERE duration_ms > 0 AND termination IS NOT ?
+        "#,
+    )
+    .bind(TERMINATION_ABANDONED)
+    .fetch_all(pool)
+    .await?;
+    let mut by_model: HashMap<String, Vec<Interval>> = HashMap::new();
+    for row in rows {
+        let start_time: String = row.try_get("start_time")?;
+        if let Some(interval) = interval_of(&start_time, row.try_get("duration_ms")?) {
+            by_model
+                .entry(row.try_get("model")?)
+                .or_default()
+                .push(interval);
+        }
+    }
+
+    let mut busy: HashMap<(String, String), i64> = HashMap::new();
+    for (model, requests) in by_model {
+        for interval in intervals::merge(requests) {
+            for (day, busy_ms) in intervals::split_by_day(interval) {
+                *busy.entry((day.to_string(), model.clone())).or_default() += busy_ms;
+            }
+        }
+    }
+    if busy.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    for ((day, model), busy_ms) in &busy {
+        add_busy_ms(&mut tx, day, model, *busy_ms).await?;
+    }
+    tx.commit().await?;
+    tracing::info!("Built {} daily busy-time rows", busy.len());
+
+    Ok(())
+}
+
+fn hours(busy_ms: i64) -> f64 {
+    (busy_ms as f64 / 3_600_000.0 * 100.0).round() / 100.0
+}
+
+fn percent_of(busy_ms: i64, total_ms: i64) -> f64 {
+    (busy_ms as f64 / total_ms as f64 * 1000.0).round() / 10.0
+}
+
+/// Busy time per model and day from `first` through `today`.
+pub async fn get_utilization(
+    pool: &SqlitePool,
+    first: NaiveDate,
+    today: NaiveDate,
+) -> Result<Utilization, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT day, model, busy_ms
+        FROM daily_busy
+        WHERE day >= ? AND day <= ? AND busy_ms > 0
+        ORDER BY day
+        "#,
+    )
+    .bind(first.to_string())
+    .bind(today.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    let mut by_model: HashMap<String, (i64, Vec<DayUtilization>)> = HashMap::new();
+    for row in rows {
+        let busy_ms: i64 = row.try_get("busy_ms")?;
+        let (total, daily) = by_model.entry(row.try_get("model")?).or_default();
+        *total += busy_ms;
+        daily.push(DayUtilization {
+            day: row.try_get("day")?,
+            busy_ms,
+            busy_hours: hours(busy_ms),
+            utilization_pct: percent_of(busy_ms, DAY_MS),
+        });
+    }
+
+    let days = (today - first).num_days() + 1;
+    let mut models: Vec<(String, i64, Vec<DayUtilization>)> = by_model
+        .into_iter()
+        .map(|(model, (total, daily))| (model, total, daily))
+        .collect();
+    models.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    Ok(Utilization {
+        from: first.to_string(),
+        to: today.to_string(),
+        days,
+        models: models
+            .into_iter()
+            .map(|(model, busy_ms, daily)| ModelUtilization {
+                model,
+                busy_ms,
+                busy_hours: hours(busy_ms),
+                utilization_pct: percent_of(busy_ms, days * DAY_MS),
+                daily,
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use crate::db::{insert_request, models::TERMINATION_COMPLETED};
+    use chrono::{TimeZone, Utc};
+
+    const MINUTE: i64 = 60_000;
+
+    fn request(model: &str, start: DateTime<Utc>, duration_ms: i64) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            model.to_string(),
+            start,
+            "Hi".to_string(),
+        );
+        record.duration_ms = duration_ms;
+        record.termination = Some(TERMINATION_COMPLETED.to_string());
+        record
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, day, hour, minute, 0).unwrap()
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 3, day).unwrap()
+    }
+
+    async fn busy(pool: &SqlitePool) -> Vec<(String, String, i64)> {
+        sqlx::query_as("SELECT day, model, busy_ms FROM daily_busy ORDER BY day, model")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    /// The table as a fresh backfill from the stored requests would build it.
+    async fn rebuilt(pool: &SqlitePool) -> Vec<(String, String, i64)> {
+        sqlx::query("DELETE FROM daily_busy")
+            .execute(pool)
+            .await
+            .unwrap();
+        backfill_if_empty(pool).await.unwrap();
+        busy(pool).await
+    }
+
+    #[tokio::test]
+    async fn overlapping_requests_of_a_model_count_once() {
+        let pool = memory_pool().await;
+        for record in [
+            request("a", at(14, 10, 0), 10 * MINUTE),
+            // Overlaps the first by five minutes
+            request("a", at(14, 10, 5), 15 * MINUTE),
+            // Nested inside both
+            request("a", at(14, 10, 6), MINUTE),
+            // Starts exactly where the previous run ended
+            request("a", at(14, 10, 20), 5 * MINUTE),
+            // Stored after a later request but starting before it
+            request("a", at(14, 9, 50), 15 * MINUTE),
+            // Another model's time is its own
+            request("b", at(14, 10, 0), 30 * MINUTE),
+        ] {
+            insert_request(&pool, &record).await.unwrap();
+        }
+
+        let expected = vec![
+            ("2026-03-14".to_string(), "a".to_string(), 35 * MINUTE),
+            ("2026-03-14".to_string(), "b".to_string(), 30 * MINUTE),
+        ];
+        assert_eq!(busy(&pool).await, expected);
+        assert_eq!(rebuilt(&pool).await, expected);
+    }
+
+    #[tokio::test]
+    async fn requests_spanning_midnight_are_split_between_days() {
+        let pool = memory_pool().await;
+        insert_request(&pool, &request("a", at(14, 23, 30), 60 * MINUTE))
+            .await
+            .unwrap();
+        // Covered by the first on the 15th, new on the 14th
+        insert_request(&pool, &request("a", at(14, 23, 0), 45 * MINUTE))
+            .await
+            .unwrap();
+
+        let expected = vec![
+            ("2026-03-14".to_string(), "a".to_string(), 60 * MINUTE),
+            ("2026-03-15".to_string(), "a".to_string(), 30 * MINUTE),
+        ];
+        assert_eq!(busy(&pool).await, expected);
+        assert_eq!(rebuilt(&pool).await, expected);
+    }
+
+    #[tokio::test]
+    async fn abandoned_and_unmeasured_requests_add_nothing() {
+        let pool = memory_pool().await;
+        let mut abandoned = request("a", at(14, 10, 0), 60 * MINUTE);
+        abandoned.termination = Some(TERMINATION_ABANDONED.to_string());
+        insert_request(&pool, &abandoned).await.unwrap();
+        insert_request(&pool, &request("a", at(14, 12, 0), 0))
+            .await
+            .unwrap();
+        assert!(busy(&pool).await.is_empty());
+
+        // Nor does an abandoned request hide the time of one it overlaps
+        insert_request(&pool, &request("a", at(14, 10, 30), 10 * MINUTE))
+            .await
+            .unwrap();
+        let expected = vec![("2026-03-14".to_string(), "a".to_string(), 10 * MINUTE)];
+        assert_eq!(busy(&pool).await, expected);
+        assert_eq!(rebuilt(&pool).await, expected);
+    }
+
+    #[tokio::test]
+    async fn incremental_totals_match_a_backfill_in_any_order() {
+        let pool = memory_pool().await;
+        // A fixed pseudo-random sequence, so failures reproduce
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut next = |below: i64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % below as u64) as i64
+        };
+        // Bursts of requests around midnight between the 14th and the 15th
+        for _ in 0..60 {
+            let model = ["a", "b"][next(2) as usize];
+            let start = at(14, 22, 0) + Duration::seconds(next(4 * 3600));
+            let record = request(model, start, next(20 * MINUTE) + 1);
+            insert_request(&pool, &record).await.unwrap();
+        }
+
+        let incremental = busy(&pool).await;
+        assert!(incremental.iter().any(|(day, ..)| day == "2026-03-15"));
+        assert_eq!(incremental, rebuilt(&pool).await);
+    }
+
+    #[tokio::test]
+    async fn utilization_is_reported_per_model_busiest_first() {
+        let pool = memory_pool().await;
+        for record in [
+            request("small", at(14, 9, 0), 36 * MINUTE),
+            request("large", at(14, 0, 0), 6 * 60 * MINUTE),
+            request("large", at(15, 12, 0), 60 * MINUTE),
+            // Before the window
+            request("small", at(10, 9, 0), 600 * MINUTE),
+        ] {
+            insert_request(&pool, &record).await.unwrap();
+        }
+
+        let report = get_utilization(&pool, date(14), date(15)).await.unwrap();
+        assert_eq!((report.from.as_str(), report.to.as_str()), ("2026-03-14", "2026-03-15"));
+        assert_eq!(report.days, 2);
+        let models: Vec<_> = report.models.iter().map(|m| m.model.as_str()).collect();
+        assert_eq!(models, ["large", "small"]);
+
+        let large = &report.models[0];
+        assert_eq!(large.busy_ms, 7 * 60 * MINUTE);
+        assert_eq!(large.busy_hours, 7.0);
+        // 7 of 48 hours
+        assert_eq!(large.utilization_pct, 14.6);
+        let days: Vec<_> = large
+            .daily
+            .iter()
+            .map(|d| (d.day.as_str(), d.busy_hours, d.utilization_pct))
+            .collect();
+        assert_eq!(days, [("2026-03-14", 6.0, 25.0), ("2026-03-15", 1.0, 4.2)]);
+
+        let small = &report.models[1];
+        assert_eq!(small.busy_hours, 0.6);
+        // 36 minutes of 48 hours
+        assert_eq!(small.utilization_pct, 1.3);
+        assert_eq!(small.daily.len(), 1);
+
+        let empty = get_utilization(&pool, date(1), date(9)).await.unwrap();
+        assert_eq!(empty.days, 9);
+        assert!(empty.models.is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+use serde::Serialize;
+use sqlx::{Row, SqlitePool, sqlite::SqliteRow};
+
+/// The subscriber answered with a 2xx status
+pub const DELIVERY_DELIVERED: &str = "delivered";
+/// Every attempt failed
+pub const DELIVERY_FAILED: &str = "failed";
+/// Not sent because the subscription was over its `max_per_minute`
+pub const DELIVERY_RATE_LIMITED: &str = "rate_limited";
+
+/// Deliveries kept per webhook; older ones are pruned as new ones are recorded
+const DELIVERY_HISTORY: i64 = 500;
+
+/// A subscription as submitted, once its filter and URL have been checked.
+#[derive(Debug, Clone)]
+pub struct NewWebhook {
+    pub name: Option<String>,
+    pub filter: String,
+    pub url: String,
+    pub secret: Option<String>,
+    pub payload: String,
+    pub max_per_minute: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub name: Option<String>,
+    pub filter: String,
+    pub url: String,
+    /// Never sent back once stored
+    #[serde(skip)]
+    pub secret: Option<String>,
+    /// Whether deliveries carry a signature
+    pub signed: bool,
+    /// `record` or `summary`
+    pub payload: String,
+    pub max_per_minute: i64,
+    pub created_at: String,
+}
+
+impl Webhook {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        let secret: Option<String> = row.try_get("secret")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            filter: row.try_get("filter")?,
+            url: row.try_get("url")?,
+            signed: secret.is_some(),
+            secret,
+            payload: row.try_get("payload")?,
+            max_per_minute: row.try_get("max_per_minute")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+/// How a delivery attempt ended up.
+#[derive(Debug)]
+pub struct DeliveryOutcome {
+    pub status: &'static str,
+    pub attempts: i64,
+    pub http_status: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// One request's delivery to one webhook.
+#[derive(Debug, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    /// Row id of the request in `requests`
+    pub request_id: i64,
+    pub proxy_request_id: Option<String>,
+    /// When the delivery settled
+    pub time: String,
+    pub status: String,
+    pub attempts: i64,
+    /// Status of the last response, if there was one
+    pub http_status: Option<i64>,
+    pub error: Option<String>,
+}
+
+pub async fn list_webhooks(pool: &SqlitePool) -> Result<Vec<Webhook>, sqlx::Error> {
+    let rows = sqlx::query("SELECT * FROM webhooks ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(Webhook::from_row).collect()
+}
+
+pub async fn get_webhook(pool: &SqlitePool, id: i64) -> Result<Option<Webhook>, sqlx::Error> {
+    let row = sqlx::query("SELECT * FROM webhooks WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    row.as_ref().map(Webhook::from_row).transpose()
+}
+
+pub async fn insert_webhook(
+    pool: &SqlitePool,
+    webhook: &NewWebhook,
+) -> Result<Webhook, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        INSERT INTO webhooks (name, filter, url, secret, payload, max_per_minute, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        RETURNING *
+        "#,
+    )
+    .bind(&webhook.name)
+    .bind(&webhook.filter)
+    .bind(&webhook.url)
+    .bind(&webhook.secret)
+    .bind(&webhook.payload)
+    .bind(webhook.max_per_minute)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .fetch_one(pool)
+    .await?;
+    Webhook::from_row(&row)
+}
+
+/// Removes a webhook and its delivery history; `None` if there was no such webhook.
+pub async fn delete_webhook(pool: &SqlitePool, id: i64) -> Result<Option<Webhook>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let row = sqlx::query("DELETE FROM webhooks WHERE id = ? RETURNING *")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM webhook_deliveries WHERE webhook_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    row.as_ref().map(Webhook::from_row).transpose()
+}
+
+/// Stores how a delivery went and prunes the webhook's history to the newest entries.
+pub async fn record_delivery(
+    pool: &SqlitePool,
+    webhook_id: i64,
+    request_id: i64,
+    proxy_request_id: Option<&str>,
+    outcome: &DeliveryOutcome,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        INSERT INTO webhook_deliveries (
+            webhook_id, request_id, proxy_request_id, time, status, attempts, http_status, error
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(request_id)
+    .bind(proxy_request_id)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(outcome.status)
+    .bind(outcome.attempts)
+    .bind(outcome.http_status)
+    .bind(&outcome.error)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        DELETE FROM webhook_deliveries
+        WHERE webhook_id = ?1 AND id <= (
+            SELECT id FROM webhook_deliveries WHERE webhook_id = ?1
+            ORDER BY id DESC LIMIT 1 OFFSET ?2
+        )
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(DELIVERY_HISTORY)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// A webhook's most recent deliveries, newest first.
+pub async fn list_deliveries(
+    pool: &SqlitePool,
+    webhook_id: i64,
+    limit: i64,
+) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, webhook_id, request_id, proxy_request_id, time, status, attempts,
+               http_status, error
+        FROM webhook_deliveries
+        WHERE webhook_id = ?
+        ORDER BY id DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(webhook_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(WebhookDelivery {
+                id: row.try_get("id")?,
+                webhook_id: row.try_get("webhook_id")?,
+                request_id: row.try_get("request_id")?,
+                proxy_request_id: row.try_get("proxy_request_id")?,
+                time: row.try_get("time")?,
+                status: row.try_get("status")?,
+                attempts: row.try_get("attempts")?,
+                http_status: row.try_get("http_status")?,
+                error: row.try_get("error")?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+
+    fn webhook(name: &str) -> NewWebhook {
+        NewWebhook {
+            name: Some(name.to_string()),
+            filter: "is_error".to_string(),
+            url: "http://127.0.0.1:9/hook".to_string(),
+            secret: Some("s3cret".to_string()),
+            payload: "record".to_string(),
+            max_per_minute: 60,
+        }
+    }
+
+    fn delivered(http_status: i64) -> DeliveryOutcome {
+        DeliveryOutcome {
+            status: DELIVERY_DELIVERED,
+            attempts: 1,
+            http_status: Some(http_status),
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn webhooks_round_trip_without_showing_the_secret() {
+        let pool = memory_pool().await;
+        let signed = insert_webhook(&pool, &webhook("signed")).await.unwrap();
+        let unsigned = insert_webhook(
+            &pool,
+            &NewWebhook {
+                secret: None,
+                ..webhook("unsigned")
+            },
+        )
+        .await
+        .unwrap();
+        assert!(signed.signed && !unsigned.signed);
+        assert_eq!(signed.secret.as_deref(), Some("s3cret"));
+
+        let listed = list_webhooks(&pool).await.unwrap();
+        let names: Vec<_> = listed.iter().map(|w| w.name.as_deref().unwrap()).collect();
+        assert_eq!(names, ["signed", "unsigned"]);
+        let json = serde_json::to_value(&listed[0]).unwrap();
+        assert!(json.get("secret").is_none());
+        assert_eq!(json["signed"], true);
+
+        let fetched = get_webhook(&pool, signed.id).await.unwrap().unwrap();
+        assert_eq!(fetched.secret.as_deref(), Some("s3cret"));
+        assert!(get_webhook(&pool, 999).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn history_keeps_the_newest_deliveries_per_webhook() {
+        let pool = memory_pool().await;
+        let busy = insert_webhook(&pool, &webhook("busy")).await.unwrap();
+        let quiet = insert_webhook(&pool, &webhook("quiet")).await.unwrap();
+        record_delivery(&pool, quiet.id, 1, Some("q-1"), &delivered(204))
+            .await
+            .unwrap();
+        for request_id in 1..=DELIVERY_HISTORY + 3 {
+            record_delivery(&pool, busy.id, request_id, None, &delivered(200))
+                .await
+                .unwrap();
+        }
+
+        let kept = list_deliveries(&pool, busy.id, 1000).await.unwrap();
+        assert_eq!(kept.len() as i64, DELIVERY_HISTORY);
+        assert_eq!(kept[0].request_id, DELIVERY_HISTORY + 3);
+        assert_eq!(kept.last().unwrap().request_id, 4);
+        let newest = list_deliveries(&pool, busy.id, 2).await.unwrap();
+        let ids: Vec<i64> = newest.iter().map(|d| d.request_id).collect();
+        assert_eq!(ids, [DELIVERY_HISTORY + 3, DELIVERY_HISTORY + 2]);
+
+        let quiet_history = list_deliveries(&pool, quiet.id, 10).await.unwrap();
+        assert_eq!(quiet_history.len(), 1);
+        assert_eq!(quiet_history[0].proxy_request_id.as_deref(), Some("q-1"));
+        assert_eq!(quiet_history[0].http_status, Some(204));
+        assert_eq!(quiet_history[0].status, DELIVERY_DELIVERED);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_webhook_drops_its_history() {
+        let pool = memory_pool().await;
+        let gone = insert_webhook(&pool, &webhook("gone")).await.unwrap();
+        let kept = insert_webhook(&pool, &webhook("kept")).await.unwrap();
+        let failed = DeliveryOutcome {
+            status: DELIVERY_FAILED,
+            attempts: 4,
+            http_status: None,
+            error: Some("connection refused".to_string()),
+        };
+        record_delivery(&pool, gone.id, 1, None, &failed).await.unwrap();
+        record_delivery(&pool, kept.id, 1, None, &delivered(200))
+            .await
+            .unwrap();
+
+        let deleted = delete_webhook(&pool, gone.id).await.unwrap().unwrap();
+        assert_eq!(deleted.name.as_deref(), Some("gone"));
+        assert!(delete_webhook(&pool, gone.id).await.unwrap().is_none());
+        assert!(list_deliveries(&pool, gone.id, 10).await.unwrap().is_empty());
+        assert_eq!(list_deliveries(&pool, kept.id, 10).await.unwrap().len(), 1);
+        assert_eq!(list_webhooks(&pool).await.unwrap().len(), 1);
+    }
+}
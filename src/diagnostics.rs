@@ -0,0 +1,378 @@
+//! Counters for the proxy's own lost or delayed bookkeeping.
+//!
+//! Each counter is bumped where the loss happens: a request that could not be written to
+//! the database, a counter flush or event that failed, a log line the subscriber could
+//! not write, a stream that had to wait for a slow client, or a stream whose write was
+//! slow enough to be handed to the spool. Finished requests waiting for their row are
+//! tracked too, for the age of the oldest one, and so are connections hyper rejected
+//! before any request reached a handler. Deliveries to the export sink are counted here
+//! as well, and the snapshot carries the buffer tally from [`crate::buffers`].
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::buffers::{BufferBudget, BufferSnapshot};
+
+/// Latest rejected connections kept for `/stats/self`
+const MAX_RECENT_REJECTIONS: usize = 50;
+
+pub struct SelfDiagnostics {
+    started_at: DateTime<Utc>,
+    requests_not_stored: AtomicU64,
+    log_writes_in_flight: AtomicU64,
+    log_writes_high_water: AtomicU64,
+    stream_backpressure_waits: AtomicU64,
+    counter_flush_failures: AtomicU64,
+    event_record_failures: AtomicU64,
+    trace_write_failures: AtomicU64,
+    stream_writes_spooled: AtomicU64,
+    spool_pending: AtomicU64,
+    spool_overflows: AtomicU64,
+    sink_sent: AtomicU64,
+    sink_retries: AtomicU64,
+    sink_dropped: AtomicU64,
+    sink_dead_lettered: AtomicU64,
+    sink_pending: AtomicU64,
+    /// Completion time of each finished request not written yet, by ticket
+    pending_persists: Mutex<HashMap<u64, Instant>>,
+    next_persist_ticket: AtomicU64,
+    rejections: Mutex<Rejections>,
+}
+
+#[derive(Default)]
+struct Rejections {
+    by_kind: BTreeMap<&'static str, u64>,
+    recent: VecDeque<RejectedConnection>,
+}
+
+/// A connection that failed before a request reached a handler, e.g. on a malformed
+/// request line or oversized headers.
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedConnection {
+    pub time: String,
+    pub peer: String,
+    /// `parse_error`, `too_large`, `header_timeout` or `incomplete_request`
+    pub kind: &'static str,
+    /// Method and path from whatever part of the request line arrived
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiagnosticsSnapshot {
+    pub started_at: String,
+    /// Finished requests whose row could not be inserted (still counted in memory)
+    pub requests_not_stored: u64,
+    /// Finished requests still being annotated and written
+    pub log_writes_in_flight: u64,
+    /// Most log writes in flight at once since startup
+    pub log_writes_high_water: u64,
+    /// Stream chunks that waited because the client's buffer was full
+    pub stream_backpressure_waits: u64,
+    /// Failed writes of the rolling per-minute counters
+    pub counter_flush_failures: u64,
+    /// Failed writes to `proxy_events` that nothing else reported
+    pub event_record_failures: u64,
+    /// Log lines the tracing subscriber could not write
+    pub trace_write_failures: u64,
+    /// Streamed requests whose insert outlasted `STREAM_WRITE_TIMEOUT_MS` and went to
+    /// the write spool
+    pub stream_writes_spooled: u64,
+    /// Spooled requests not written yet
+    pub spool_pending: u64,
+    /// Requests dropped because the spool already held `SPOOL_CAPACITY`
+    pub spool_overflows: u64,
+    /// Age of the oldest finished request whose row isn't written yet; 0 when none
+    pub oldest_unpersisted_ms: u64,
+    /// Connections rejected before reaching a handler, in total and by kind
+    pub connection_rejections: u64,
+    pub connection_rejections_by_kind: BTreeMap<&'static str, u64>,
+    /// Newest first
+    pub recent_rejections: Vec<RejectedConnection>,
+    /// Records delivered to `EXPORT_SINK_URL`
+    pub export_sink_sent: u64,
+    /// Failed batch deliveries that were tried again
+    pub export_sink_retries: u64,
+    /// Records lost because the sink buffer was full or the sink stayed down without a
+    /// dead-letter file
+    pub export_sink_dropped: u64,
+    /// Records written to `EXPORT_SINK_DEAD_LETTER_FILE` instead of the sink
+    pub export_sink_dead_lettered: u64,
+    /// Records buffered or being delivered
+    pub export_sink_pending: u64,
+    /// Memory held in request and response buffers
+    pub buffers: BufferSnapshot,
+}
+
+impl SelfDiagnostics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            started_at: Utc::now(),
+            requests_not_stored: AtomicU64::new(0),
+            log_writes_in_flight: AtomicU64::new(0),
+            log_writes_high_water: AtomicU64::new(0),
+            stream_backpressure_waits: AtomicU64::new(0),
+            counter_flush_failures: AtomicU64::new(0),
+            event_record_failures: AtomicU64::new(0),
+            trace_write_failures: AtomicU64::new(0),
+            stream_writes_spooled: AtomicU64::new(0),
+            spool_pending: AtomicU64::new(0),
+            spool_overflows: AtomicU64::new(0),
+            sink_sent: AtomicU64::new(0),
+            sink_retries: AtomicU64::new(0),
+            sink_dropped: AtomicU64::new(0),
+            sink_dead_lettered: AtomicU64::new(0),
+            sink_pending: AtomicU64::new(0),
+            pending_persists: Mutex::new(HashMap::new()),
+            next_persist_ticket: AtomicU64::new(0),
+            rejections: Mutex::new(Rejections::default()),
+        })
+    }
+
+    /// Counts and logs a connection hyper gave up on before any handler ran.
+    pub fn connection_rejected(
+        &self,
+        peer: SocketAddr,
+        kind: &'static str,
+        request_line: (Option<String>, Option<String>),
+        error: String,
+    ) {
+        let (method, path) = request_line;
+        tracing::info!(
+            "Rejected connection from {} ({}): {} {} {}",
+            peer,
+            kind,
+            method.as_deref().unwrap_or("-"),
+            path.as_deref().unwrap_or("-"),
+            error
+        );
+
+        let mut rejections = self.rejections.lock().unwrap_or_else(|e| e.into_inner());
+        *rejections.by_kind.entry(kind).or_default() += 1;
+        if rejections.recent.len() == MAX_RECENT_REJECTIONS {
+            rejections.recent.pop_front();
+        }
+        rejections.recent.push_back(RejectedConnection {
+            time: Utc::now().to_rfc3339(),
+            peer: peer.to_string(),
+            kind,
+            method,
+            path,
+            error,
+        });
+    }
+
+    pub fn request_not_stored(&self) {
+        self.requests_not_stored.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stream_backpressure_wait(&self) {
+        self.stream_backpressure_waits
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counter_flush_failed(&self) {
+        self.counter_flush_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn event_record_failed(&self) {
+        self.event_record_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn spooled(&self) {
+        self.stream_writes_spooled.fetch_add(1, Ordering::Relaxed);
+        self.spool_pending.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn spool_written(&self) {
+        self.spool_pending.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn spool_overflowed(&self) {
+        self.spool_overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn spool_pending(&self) -> u64 {
+        self.spool_pending.load(Ordering::Relaxed)
+    }
+
+    pub fn sink_queued(&self) {
+        self.sink_pending.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sink_retried(&self) {
+        self.sink_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Settles `records` queued records as sent, dead-lettered or dropped.
+    pub fn sink_settled(&self, records: u64, outcome: SinkOutcome) {
+        let counter = match outcome {
+            SinkOutcome::Sent => &self.sink_sent,
+            SinkOutcome::DeadLettered => &self.sink_dead_lettered,
+            SinkOutcome::Dropped => &self.sink_dropped,
+        };
+        counter.fetch_add(records, Ordering::Relaxed);
+        self.sink_pending.fetch_sub(records, Ordering::Relaxed);
+    }
+
+    pub fn sink_pending(&self) -> u64 {
+        self.sink_pending.load(Ordering::Relaxed)
+    }
+
+    /// Tracks a finished request until its row is written or given up on. The guard
+    /// travels with the record, into the spool if it goes there.
+    pub fn pending_persist(self: &Arc<Self>, completed_at: Option<Instant>) -> PendingPersist {
+        let ticket = self.next_persist_ticket.fetch_add(1, Ordering::Relaxed);
+        self.pending()
+            .insert(ticket, completed_at.unwrap_or_else(Instant::now));
+        PendingPersist {
+            diagnostics: self.clone(),
+            ticket,
+        }
+    }
+
+    /// Age of the oldest finished request still waiting for its row.
+    pub fn oldest_unpersisted_ms(&self) -> u64 {
+        self.pending()
+            .values()
+            .min()
+            .map_or(0, |oldest| oldest.elapsed().as_millis() as u64)
+    }
+
+    fn pending(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Instant>> {
+        self.pending_persists
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Tracks one request's log write until the guard is dropped.
+    pub fn log_write(&self) -> LogWriteGuard<'_> {
+        let in_flight = self.log_writes_in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.log_writes_high_water
+            .fetch_max(in_flight, Ordering::Relaxed);
+        LogWriteGuard(self)
+    }
+
+    /// Writer for the tracing subscriber that counts the lines it fails to write.
+    pub fn trace_writer(self: &Arc<Self>) -> impl Fn() -> TraceWriter + use<> {
+        let diagnostics = self.clone();
+        move || TraceWriter(diagnostics.clone())
+    }
+
+    pub fn snapshot(&self, buffers: &BufferBudget) -> DiagnosticsSnapshot {
+        let rejections = self.rejections.lock().unwrap_or_else(|e| e.into_inner());
+        DiagnosticsSnapshot {
+            started_at: self.started_at.to_rfc3339(),
+            requests_not_stored: self.requests_not_stored.load(Ordering::Relaxed),
+            log_writes_in_flight: self.log_writes_in_flight.load(Ordering::Relaxed),
+            log_writes_high_water: self.log_writes_high_water.load(Ordering::Relaxed),
+            stream_backpressure_waits: self.stream_backpressure_waits.load(Ordering::Relaxed),
+            counter_flush_failures: self.counter_flush_failures.load(Ordering::Relaxed),
+            event_record_failures: self.event_record_failures.load(Ordering::Relaxed),
+            trace_write_failures: self.trace_write_failures.load(Ordering::Relaxed),
+            stream_writes_spooled: self.stream_writes_spooled.load(Ordering::Relaxed),
+            spool_pending: self.spool_pending(),
+            spool_overflows: self.spool_overflows.load(Ordering::Relaxed),
+            oldest_unpersisted_ms: self.oldest_unpersisted_ms(),
+            connection_rejections: rejections.by_kind.values().sum(),
+            connection_rejections_by_kind: rejections.by_kind.clone(),
+            recent_rejections: rejections.recent.iter().rev().cloned().collect(),
+            export_sink_sent: self.sink_sent.load(Ordering::Relaxed),
+            export_sink_retries: self.sink_retries.load(Ordering::Relaxed),
+            export_sink_dropped: self.sink_dropped.load(Ordering::Relaxed),
+            export_sink_dead_lettered: self.sink_dead_lettered.load(Ordering::Relaxed),
+            export_sink_pending: self.sink_pending(),
+            buffers: buffers.snapshot(),
+        }
+    }
+}
+
+/// What became of records the export sink had queued.
+#[derive(Debug, Clone, Copy)]
+pub enum SinkOutcome {
+    Sent,
+    DeadLettered,
+    Dropped,
+}
+
+/// A finished request whose row isn't written yet; see [`SelfDiagnostics::pending_persist`].
+pub struct PendingPersist {
+    diagnostics: Arc<SelfDiagnostics>,
+    ticket: u64,
+}
+
+impl Drop for PendingPersist {
+    fn drop(&mut self) {
+        self.diagnostics.pending().remove(&self.ticket);
+    }
+}
+
+pub struct LogWriteGuard<'a>(&'a SelfDiagnostics);
+
+impl Drop for LogWriteGuard<'_> {
+    fn drop(&mut self) {
+        self.0.log_writes_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Stdout, counting failed writes instead of losing them silently.
+pub struct TraceWriter(Arc<SelfDiagnostics>);
+
+impl Write for TraceWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stdout().write(buf).inspect_err(|_| {
+            self.0.trace_write_failures.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        io::stdout().write_all(buf).inspect_err(|_| {
+            self.0.trace_write_failures.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn finished_ago(ms: u64) -> Option<Instant> {
+        Instant::now().checked_sub(Duration::from_millis(ms))
+    }
+
+    #[test]
+    fn unpersisted_gauge_follows_the_oldest_pending_record() {
+        let diagnostics = SelfDiagnostics::new();
+        assert_eq!(diagnostics.oldest_unpersisted_ms(), 0);
+
+        let older = diagnostics.pending_persist(finished_ago(800));
+        let newer = diagnostics.pending_persist(finished_ago(100));
+        assert!(diagnostics.oldest_unpersisted_ms() >= 800);
+
+        drop(older);
+        let age = diagnostics.oldest_unpersisted_ms();
+        assert!((100..800).contains(&age), "{}", age);
+
+        drop(newer);
+        assert_eq!(diagnostics.oldest_unpersisted_ms(), 0);
+    }
+
+    #[test]
+    fn records_without_a_completion_time_count_from_now() {
+        let diagnostics = SelfDiagnostics::new();
+        let _pending = diagnostics.pending_persist(None);
+        assert!(diagnostics.oldest_unpersisted_ms() < 100);
+    }
+}
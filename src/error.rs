@@ -6,14 +6,13 @@ use axum::{
 use serde_json::json;
 use thiserror::Error;
 
+use crate::proxy::schema_check::{self, Violation};
+
 #[derive(Error, Debug)]
 pub enum ProxyError {
     #[error("LM Studio connection error: {0}")]
     LmStudioConnection(String),
 
-    #[error("Invalid response from LM Studio: {0}")]
-    InvalidResponse(String),
-
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
@@ -25,32 +24,75 @@ pub enum ProxyError {
 
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
+    #[error("Deadline exceeded: {0}")]
+    DeadlineExceeded(String),
+
+    #[error("Proxy overloaded: {0}")]
+    Overloaded(String),
+
+    #[error("Request does not match the OpenAI schema: {}", schema_check::summary(.0))]
+    SchemaViolation(Vec<Violation>),
+}
+
+impl ProxyError {
+    /// The status the client is answered with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProxyError::LmStudioConnection(_) => StatusCode::BAD_GATEWAY,
+            ProxyError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ProxyError::Http(_) => StatusCode::BAD_GATEWAY,
+            ProxyError::Json(_) => StatusCode::BAD_GATEWAY,
+            ProxyError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ProxyError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ProxyError::DeadlineExceeded(_) => StatusCode::GATEWAY_TIMEOUT,
+            ProxyError::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyError::SchemaViolation(_) => StatusCode::BAD_REQUEST,
+        }
+    }
 }
 
 impl IntoResponse for ProxyError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            ProxyError::LmStudioConnection(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
-            ProxyError::InvalidResponse(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
+        let status = self.status();
+        // Clients get the violations as a list too, rather than only in the message
+        if let ProxyError::SchemaViolation(violations) = &self {
+            let error = json!({
+                "message": self.to_string(),
+                "type": "proxy_error",
+                "violations": violations,
+            });
+            return (status, Json(json!({ "error": error }))).into_response();
+        }
+        let error_message = match self {
             ProxyError::Database(_) => {
                 tracing::error!("Database error: {}", self);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal server error".to_string(),
-                )
+                "Internal server error".to_string()
             }
-            ProxyError::Http(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
-            ProxyError::Json(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
-            ProxyError::Io(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
+            _ => self.to_string(),
         };
 
-        let body = Json(json!({
-            "error": {
-                "message": error_message,
-                "type": "proxy_error",
-            }
-        }));
+        error_response(status, error_message, "proxy_error", None)
+    }
+}
 
-        (status, body).into_response()
+/// The JSON error shape shared by the proxy and the stats API.
+pub fn error_response(
+    status: StatusCode,
+    message: String,
+    error_type: &str,
+    request_id: Option<&str>,
+) -> Response {
+    let mut error = json!({
+        "message": message,
+        "type": error_type,
+    });
+    if let Some(request_id) = request_id {
+        error["request_id"] = json!(request_id);
     }
+
+    (status, Json(json!({ "error": error }))).into_response()
 }
@@ -0,0 +1,152 @@
+use chrono::Utc;
+use serde_json::Value;
+
+use crate::db::batches::{ITEM_FAILED, ItemResult};
+use crate::db::chargeback::Chargeback;
+use crate::db::{RequestRecord, StoredRequest};
+
+/// Rows are sent to a downloading client once this much output has built up
+pub const EXPORT_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Every exported field name, in export order.
+pub fn csv_fields() -> Vec<String> {
+    let template = StoredRequest {
+        id: 0,
+        record: RequestRecord::new(String::new(), String::new(), Utc::now(), String::new()),
+    };
+
+    match serde_json::to_value(&template) {
+        Ok(Value::Object(fields)) => fields.keys().cloned().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// CSV header naming every exported field, in the same order as `csv_line`.
+pub fn csv_header() -> String {
+    csv_header_for(&csv_fields())
+}
+
+/// CSV header naming just `fields`, for `csv_line_for`.
+pub fn csv_header_for(fields: &[String]) -> String {
+    fields
+        .iter()
+        .map(|field| csv_escape(field))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Formats one stored request as a CSV line (without the trailing newline).
+pub fn csv_line(request: &StoredRequest) -> String {
+    match serde_json::to_value(request) {
+        Ok(Value::Object(fields)) => fields
+            .values()
+            .map(csv_value)
+            .collect::<Vec<_>>()
+            .join(","),
+        _ => String::new(),
+    }
+}
+
+/// Formats just `fields` of one stored request, in that order.
+pub fn csv_line_for(request: &StoredRequest, fields: &[String]) -> String {
+    let Ok(Value::Object(values)) = serde_json::to_value(request) else {
+        return String::new();
+    };
+    fields
+        .iter()
+        .map(|field| values.get(field).map(csv_value).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Renders a chargeback report as CSV: one row per line item, then a total row.
+pub fn chargeback_csv(report: &Chargeback) -> String {
+    let amount = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+
+    let mut csv = String::from(
+        "namespace,period_start,period_end,model,input_price_per_m,output_price_per_m,\
+         requests,input_tokens,output_tokens,input_cost,output_cost,total\n",
+    );
+    let prefix = format!(
+        "{},{},{}",
+        csv_escape(&report.namespace),
+        csv_escape(&report.period_start),
+        csv_escape(&report.period_end)
+    );
+    for line in &report.lines {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{}\n",
+            prefix,
+            csv_escape(&line.model),
+            amount(line.input_price_per_m),
+            amount(line.output_price_per_m),
+            line.requests,
+            line.input_tokens,
+            line.output_tokens,
+            amount(line.input_cost),
+            amount(line.output_cost),
+            amount(line.total)
+        ));
+    }
+    csv.push_str(&format!(
+        "{},TOTAL,,,{},{},{},,,{}\n",
+        prefix,
+        report.total_requests,
+        report.total_input_tokens,
+        report.total_output_tokens,
+        report.grand_total
+    ));
+    csv
+}
+
+/// Renders a batch's results as JSONL, one line per request that ran, in the OpenAI
+/// batch output shape.
+pub fn batch_results_jsonl(batch_id: &str, results: &[ItemResult]) -> String {
+    let mut jsonl = String::new();
+    for result in results {
+        let body = result
+            .response
+            .as_deref()
+            .map(|body| {
+                serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.to_string()))
+            })
+            .unwrap_or(Value::Null);
+        let error = if result.status == ITEM_FAILED {
+            body.get("error").cloned().unwrap_or_else(|| body.clone())
+        } else {
+            Value::Null
+        };
+        let line = serde_json::json!({
+            "id": format!("{}-{}", batch_id, result.line),
+            "custom_id": result.custom_id,
+            "response": {
+                "status_code": result.http_status,
+                "request_id": result.proxy_request_id,
+                "body": body,
+            },
+            "error": error,
+        });
+        jsonl.push_str(&line.to_string());
+        jsonl.push('\n');
+    }
+    jsonl
+}
+
+fn csv_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => csv_escape(s),
+        // Nested values are written as JSON, which has commas and quotes of its own
+        Value::Array(_) | Value::Object(_) => csv_escape(&value.to_string()),
+        other => other.to_string(),
+    }
+}
+
+/// Quotes a field when it contains a delimiter, quote or line break.
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
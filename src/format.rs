@@ -0,0 +1,116 @@
+//! Short human-readable numbers, for the badge endpoint and the `stats` command.
+
+/// Suffixes for successive powers of 1000
+const SI_SUFFIXES: &[&str] = &["", "k", "M", "G", "T", "P"];
+
+/// A count with an SI suffix: `950`, `3.2k`, `12.4M`, `310M`. One decimal is kept
+/// below 100 of a unit and dropped when it is zero.
+pub fn si(value: f64) -> String {
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    let sign = if value < 0.0 { "-" } else { "" };
+    let mut scaled = value.abs();
+    let mut unit = 0;
+    while scaled >= 1000.0 && unit < SI_SUFFIXES.len() - 1 {
+        scaled /= 1000.0;
+        unit += 1;
+    }
+    let mut text = short_decimal(scaled);
+    // Rounding can carry into the next unit, e.g. 999_960 would read "1000k"
+    if text == "1000" && unit < SI_SUFFIXES.len() - 1 {
+        unit += 1;
+        text = "1".to_string();
+    }
+    format!("{}{}{}", sign, text, SI_SUFFIXES[unit])
+}
+
+/// A dollar amount: cents below $1000, `$1.2k` and up above, and `<$0.01` for small
+/// amounts that would otherwise read as zero.
+pub fn currency(value: f64) -> String {
+    if value > 0.0 && value < 0.005 {
+        return "<$0.01".to_string();
+    }
+    if value.abs() >= 1000.0 {
+        return format!("${}", si(value));
+    }
+    format!("${:.2}", value)
+}
+
+/// One decimal below 100, none from there, without a trailing `.0`.
+fn short_decimal(value: f64) -> String {
+    let text = if value < 100.0 {
+        format!("{:.1}", value)
+    } else {
+        format!("{:.0}", value)
+    };
+    match text.strip_suffix(".0") {
+        Some(whole) => whole.to_string(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn si_suffixes() {
+        let cases = [
+            (0.0, "0"),
+            (7.0, "7"),
+            (950.0, "950"),
+            (999.0, "999"),
+            (1_000.0, "1k"),
+            (3_200.0, "3.2k"),
+            (3_249.0, "3.2k"),
+            (3_251.0, "3.3k"),
+            (99_940.0, "99.9k"),
+            (99_960.0, "100k"),
+            (310_000.0, "310k"),
+            (12_400_000.0, "12.4M"),
+            (310_000_000.0, "310M"),
+            (2_500_000_000.0, "2.5G"),
+            (1.5e15, "1.5P"),
+            // Past the last suffix the number just grows
+            (2.0e18, "2000P"),
+            (0.25, "0.2"),
+            (-3_200.0, "-3.2k"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(si(value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn rounding_carries_into_the_next_unit() {
+        assert_eq!(si(999_960.0), "1M");
+        assert_eq!(si(999_499.0), "999k");
+        assert_eq!(si(999_999_999.0), "1G");
+        assert_eq!(si(-999_960.0), "-1M");
+    }
+
+    #[test]
+    fn non_finite_values_pass_through() {
+        assert_eq!(si(f64::INFINITY), "inf");
+        assert_eq!(si(f64::NAN), "NaN");
+    }
+
+    #[test]
+    fn currency_amounts() {
+        let cases = [
+            (0.0, "$0.00"),
+            (0.001, "<$0.01"),
+            (0.005, "$0.01"),
+            (0.42, "$0.42"),
+            (12.346, "$12.35"),
+            (999.99, "$999.99"),
+            (1_000.0, "$1k"),
+            (1_234.0, "$1.2k"),
+            (2_500_000.0, "$2.5M"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(currency(value), expected, "{}", value);
+        }
+    }
+}
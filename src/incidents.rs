@@ -0,0 +1,230 @@
+//! Incident mode: a time-boxed stretch of extra capture, started when errors spike or by
+//! an admin.
+//!
+//! While an incident runs, every new request is tagged with its id and keeps its request
+//! headers plus the raw upstream response (every streamed chunk as sent) in
+//! `incident_capture`. Capture stops by itself when the incident ends; nothing needs a
+//! restart, and an incident still running at shutdown resumes on startup.
+
+use axum::http::HeaderMap;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use sqlx::SqlitePool;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::config::IncidentConfig;
+use crate::db::incidents as store;
+
+pub const REASON_ERROR_RATE: &str = "error_rate";
+pub const REASON_MANUAL: &str = "manual";
+
+/// Most response bytes kept per request
+const CAPTURE_LIMIT_BYTES: usize = 64 * 1024;
+
+/// Headers never written to the capture
+const REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveIncident {
+    pub id: String,
+    #[serde(serialize_with = "serialize_rfc3339")]
+    pub ends_at: DateTime<Utc>,
+}
+
+fn serialize_rfc3339<S: serde::Serializer>(
+    time: &DateTime<Utc>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&time.to_rfc3339())
+}
+
+pub struct Incidents {
+    config: IncidentConfig,
+    active: Mutex<Option<ActiveIncident>>,
+    /// Outcomes of the latest requests, `true` for errors
+    outcomes: Mutex<VecDeque<bool>>,
+}
+
+impl Incidents {
+    /// Picks up an incident that was still running when the process stopped.
+    pub async fn restore(
+        db: &SqlitePool,
+        config: IncidentConfig,
+    ) -> Result<Arc<Self>, sqlx::Error> {
+        let active = store::get_active_incident(db, &Utc::now().to_rfc3339())
+            .await?
+            .and_then(|(id, ends_at)| {
+                let ends_at = DateTime::parse_from_rfc3339(&ends_at).ok()?.to_utc();
+                tracing::warn!("Incident {} still running until {}", id, ends_at);
+                Some(ActiveIncident { id, ends_at })
+            });
+
+        Ok(Arc::new(Self {
+            config,
+            active: Mutex::new(active),
+            outcomes: Mutex::new(VecDeque::new()),
+        }))
+    }
+
+    /// Id of the incident in progress.
+    pub fn current(&self) -> Option<String> {
+        self.active().map(|incident| incident.id)
+    }
+
+    /// The incident in progress, ending it once its time is up.
+    pub fn active(&self) -> Option<ActiveIncident> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        if active
+            .as_ref()
+            .is_some_and(|incident| incident.ends_at <= Utc::now())
+            && let Some(incident) = active.take()
+        {
+            tracing::info!("Incident {} ended, capture back to normal", incident.id);
+        }
+        active.clone()
+    }
+
+    /// Starts an incident for `duration`, or pushes back the end of the one in progress.
+    pub async fn start(
+        &self,
+        db: &SqlitePool,
+        reason: &str,
+        duration: Duration,
+    ) -> Result<ActiveIncident, sqlx::Error> {
+        let now = Utc::now();
+        let ends_at = now + duration;
+        let (incident, extended) = {
+            let current = self.current();
+            let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+            let incident = ActiveIncident {
+                id: current
+                    .clone()
+                    .unwrap_or_else(|| format!("inc_{}", uuid::Uuid::new_v4().simple())),
+                ends_at: active
+                    .as_ref()
+                    .map_or(ends_at, |incident| incident.ends_at.max(ends_at)),
+            };
+            *active = Some(incident.clone());
+            (incident, current.is_some())
+        };
+
+        if extended {
+            store::extend_incident(db, &incident.id, &incident.ends_at.to_rfc3339()).await?;
+            tracing::info!(
+                "Incident {} extended until {}",
+                incident.id,
+                incident.ends_at
+            );
+        } else {
+            store::create_incident(
+                db,
+                &incident.id,
+                reason,
+                &now.to_rfc3339(),
+                &incident.ends_at.to_rfc3339(),
+            )
+            .await?;
+            tracing::warn!(
+                "Incident {} started ({}), capturing extra detail until {}",
+                incident.id,
+                reason,
+                incident.ends_at
+            );
+        }
+        Ok(incident)
+    }
+
+    /// Counts a finished request, starting an incident when the latest window of requests
+    /// fails at `INCIDENT_ERROR_RATE` or more.
+    pub async fn observe(&self, db: &SqlitePool, is_error: bool) -> Result<(), sqlx::Error> {
+        let Some(threshold) = self.config.error_rate else {
+            return Ok(());
+        };
+
+        let spiking = {
+            let mut outcomes = self.outcomes.lock().unwrap_or_else(|e| e.into_inner());
+            outcomes.push_back(is_error);
+            while outcomes.len() > self.config.window {
+                outcomes.pop_front();
+            }
+            let errors = outcomes.iter().filter(|&&error| error).count();
+            let spiking = outcomes.len() == self.config.window
+                && errors as f64 >= threshold * self.config.window as f64;
+            if spiking {
+                // Start over, so the next incident needs a fresh spike
+                outcomes.clear();
+            }
+            spiking
+        };
+
+        if spiking && self.current().is_none() {
+            self.start(
+                db,
+                REASON_ERROR_RATE,
+                Duration::minutes(self.config.duration_minutes),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Starts a request's capture with its headers.
+pub fn capture_request(headers: &HeaderMap) -> Value {
+    json!({ "request_headers": headers_json(headers) })
+}
+
+/// Adds the upstream response to a capture, cut off at the capture limit. `chunks` is the
+/// number of stream chunks the body was made of, if it was streamed.
+pub fn capture_response(
+    capture: &mut Option<Value>,
+    headers: &HeaderMap,
+    body: &str,
+    chunks: Option<usize>,
+) {
+    let Some(Value::Object(capture)) = capture else {
+        return;
+    };
+    let kept = &body[..floor_char_boundary(body, CAPTURE_LIMIT_BYTES)];
+    let truncated = kept.len() < body.len();
+    capture.insert("response_headers".to_string(), headers_json(headers));
+    capture.insert("response_body".to_string(), Value::String(kept.to_string()));
+    capture.insert("response_truncated".to_string(), Value::Bool(truncated));
+    if let Some(chunks) = chunks {
+        capture.insert("response_chunks".to_string(), json!(chunks));
+    }
+}
+
+/// Appends a streamed chunk to a raw-response buffer, up to just past the capture limit.
+pub fn append_capped(buffer: &mut String, chunk: &str) {
+    if buffer.len() <= CAPTURE_LIMIT_BYTES {
+        buffer.push_str(chunk);
+    }
+}
+
+fn headers_json(headers: &HeaderMap) -> Value {
+    let mut map = Map::new();
+    for (name, value) in headers {
+        let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+            "[redacted]".to_string()
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).to_string()
+        };
+        map.insert(name.to_string(), Value::String(value));
+    }
+    Value::Object(map)
+}
+
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    (0..=index.min(text.len()))
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or(0)
+}
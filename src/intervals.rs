@@ -0,0 +1,206 @@
+//! Overlap-merging of time intervals, so concurrent requests to a model count their shared
+//! wall-clock time once. Intervals are half-open `[start, end)` in milliseconds since the
+//! Unix epoch.
+
+use chrono::{DateTime, NaiveDate};
+
+/// Milliseconds in a UTC day
+pub const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+pub type Interval = (i64, i64);
+
+/// Sorts `intervals` and joins those that overlap or touch, dropping empty ones. Nested
+/// intervals disappear into the one containing them.
+pub fn merge(mut intervals: Vec<Interval>) -> Vec<Interval> {
+    intervals.retain(|(start, end)| start < end);
+    intervals.sort_unstable();
+    let mut merged: Vec<Interval> = Vec::with_capacity(intervals.len());
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// The parts of `interval` that none of `covered` overlaps, in order.
+pub fn uncovered(interval: Interval, covered: Vec<Interval>) -> Vec<Interval> {
+    let (mut start, end) = interval;
+    let mut parts = Vec::new();
+    for (covered_start, covered_end) in merge(covered) {
+        if start >= end || covered_start >= end {
+            break;
+        }
+        if covered_end <= start {
+            continue;
+        }
+        if covered_start > start {
+            parts.push((start, covered_start));
+        }
+        start = covered_end;
+    }
+    if start < end {
+        parts.push((start, end));
+    }
+    parts
+}
+
+/// Splits an interval at UTC midnights into the milliseconds it spends in each day.
+pub fn split_by_day(interval: Interval) -> Vec<(NaiveDate, i64)> {
+    let (mut start, end) = interval;
+    let mut days = Vec::new();
+    while start < end {
+        let midnight = (start.div_euclid(DAY_MS) + 1) * DAY_MS;
+        let stop = end.min(midnight);
+        if let Some(time) = DateTime::from_timestamp_millis(start) {
+            days.push((time.date_naive(), stop - start));
+        }
+        start = stop;
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(intervals: &[Interval]) -> i64 {
+        intervals.iter().map(|(start, end)| end - start).sum()
+    }
+
+    fn day(text: &str) -> NaiveDate {
+        text.parse().unwrap()
+    }
+
+    /// Milliseconds since the epoch of `hour` o'clock on 2026-03-14, UTC.
+    fn at(hour: i64) -> i64 {
+        let midnight = day("2026-03-14").and_hms_opt(0, 0, 0).unwrap().and_utc();
+        midnight.timestamp_millis() + hour * 3_600_000
+    }
+
+    #[test]
+    fn merge_joins_overlapping_nested_and_touching_intervals() {
+        let cases: [(Vec<Interval>, Vec<Interval>); 9] = [
+            (vec![], vec![]),
+            (vec![(0, 10)], vec![(0, 10)]),
+            // Overlapping, in either order
+            (vec![(5, 15), (0, 10)], vec![(0, 15)]),
+            // Nested inside a longer one
+            (vec![(0, 100), (10, 20), (30, 40)], vec![(0, 100)]),
+            // Exactly adjacent: the second starts where the first ends
+            (vec![(0, 10), (10, 20)], vec![(0, 20)]),
+            // A gap of one millisecond keeps them apart
+            (vec![(0, 10), (11, 20)], vec![(0, 10), (11, 20)]),
+            // Duplicates and a chain bridged by a later interval
+            (vec![(0, 10), (0, 10), (20, 30), (8, 22)], vec![(0, 30)]),
+            // Empty and inverted intervals count for nothing
+            (vec![(5, 5), (9, 3), (20, 30)], vec![(20, 30)]),
+            // Shared start, different ends
+            (vec![(0, 5), (0, 50), (0, 20)], vec![(0, 50)]),
+        ];
+        for (intervals, expected) in cases {
+            assert_eq!(merge(intervals.clone()), expected, "{:?}", intervals);
+        }
+    }
+
+    #[test]
+    fn uncovered_leaves_the_gaps() {
+        let cases: [(Interval, Vec<Interval>, Vec<Interval>); 10] = [
+            ((0, 100), vec![], vec![(0, 100)]),
+            ((0, 100), vec![(0, 100)], vec![]),
+            ((0, 100), vec![(-50, 500)], vec![]),
+            // A hole in the middle
+            ((0, 100), vec![(40, 60)], vec![(0, 40), (60, 100)]),
+            // Covered at both edges, exactly adjacent outside
+            ((0, 100), vec![(-10, 0), (100, 110)], vec![(0, 100)]),
+            ((0, 100), vec![(-10, 20), (90, 110)], vec![(20, 90)]),
+            // Unsorted, overlapping cover
+            ((0, 100), vec![(50, 70), (10, 30), (25, 55)], vec![(0, 10), (70, 100)]),
+            // Cover touching from inside
+            ((0, 100), vec![(0, 10), (90, 100)], vec![(10, 90)]),
+            // Entirely before or after
+            ((0, 100), vec![(-30, -20), (200, 300)], vec![(0, 100)]),
+            ((50, 50), vec![], vec![]),
+        ];
+        for (interval, covered, expected) in cases {
+            assert_eq!(
+                uncovered(interval, covered.clone()),
+                expected,
+                "{:?} minus {:?}",
+                interval,
+                covered
+            );
+        }
+    }
+
+    #[test]
+    fn adding_uncovered_parts_in_any_order_totals_the_merged_time() {
+        // A fixed pseudo-random sequence, so failures reproduce
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = |below: i64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % below as u64) as i64
+        };
+        for _ in 0..200 {
+            let count = 1 + next(12);
+            let requests: Vec<Interval> = (0..count)
+                .map(|_| {
+                    let start = next(1_000);
+                    (start, start + next(200))
+                })
+                .collect();
+
+            let mut seen = Vec::new();
+            let mut busy = 0;
+            for &request in &requests {
+                busy += total(&uncovered(request, seen.clone()));
+                seen.push(request);
+            }
+            assert_eq!(busy, total(&merge(requests.clone())), "{:?}", requests);
+        }
+    }
+
+    #[test]
+    fn split_by_day_within_one_day() {
+        assert_eq!(split_by_day((at(9), at(17))), [(day("2026-03-14"), 8 * 3_600_000)]);
+        // Ending exactly at midnight stays in the day
+        assert_eq!(split_by_day((at(23), at(24))), [(day("2026-03-14"), 3_600_000)]);
+        // Starting exactly at midnight belongs to the new day
+        assert_eq!(split_by_day((at(24), at(25))), [(day("2026-03-15"), 3_600_000)]);
+        assert!(split_by_day((at(5), at(5))).is_empty());
+    }
+
+    #[test]
+    fn split_by_day_across_midnight() {
+        let before = 90_000;
+        let after = 30_000;
+        assert_eq!(
+            split_by_day((at(24) - before, at(24) + after)),
+            [(day("2026-03-14"), before), (day("2026-03-15"), after)]
+        );
+
+        // Two midnights, with a whole day between them
+        let days = split_by_day((at(22), at(24 + 24 + 1)));
+        assert_eq!(
+            days,
+            [
+                (day("2026-03-14"), 2 * 3_600_000),
+                (day("2026-03-15"), DAY_MS),
+                (day("2026-03-16"), 3_600_000),
+            ]
+        );
+        let spent: i64 = days.iter().map(|(_, ms)| ms).sum();
+        assert_eq!(spent, at(49) - at(22));
+    }
+
+    #[test]
+    fn split_by_day_before_the_epoch() {
+        assert_eq!(
+            split_by_day((-1_000, 1_000)),
+            [(day("1969-12-31"), 1_000), (day("1970-01-01"), 1_000)]
+        );
+    }
+}
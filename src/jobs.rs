@@ -0,0 +1,365 @@
+//! Long-running database maintenance (backfills, migrations) as pausable background jobs.
+//!
+//! A job works through its rows in chunks, one short transaction per chunk, so proxy
+//! writes and stats reads interleave with it instead of waiting minutes for one big
+//! transaction. Every chunk leaves each row either fully converted or untouched, and
+//! jobs select their work by what is still unconverted, so a cancelled or interrupted
+//! job is resumed by starting the same kind again. Anything that should only happen
+//! once the whole table is converted runs when the job completes.
+
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+use crate::db::jobs::{
+    self as store, JOB_CANCELLED, JOB_COMPLETED, JOB_FAILED, JOB_PAUSED, JOB_RUNNING,
+};
+use crate::db::{backfill, blobs};
+use crate::tokenizer::Tokenizers;
+
+/// Rows converted per transaction
+const CHUNK_SIZE: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    /// Moves inline prompt/output text of old rows into deduplicated blobs
+    BlobDedup,
+    /// Adds token estimates to successful requests logged before they were recorded
+    TokenBackfill,
+}
+
+impl JobKind {
+    pub const ALL: [JobKind; 2] = [JobKind::BlobDedup, JobKind::TokenBackfill];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            JobKind::BlobDedup => "blob_dedup",
+            JobKind::TokenBackfill => "token_backfill",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Run,
+    Pause,
+    Cancel,
+}
+
+/// Starts jobs and relays pause/resume/cancel to the ones running in this process.
+pub struct Jobs {
+    db: SqlitePool,
+    tokenizers: Arc<Tokenizers>,
+    /// Job id -> (kind, control channel) for jobs that haven't finished
+    running: Mutex<HashMap<i64, (JobKind, watch::Sender<Control>)>>,
+}
+
+impl Jobs {
+    pub fn new(db: SqlitePool, tokenizers: Arc<Tokenizers>) -> Arc<Self> {
+        Arc::new(Self {
+            db,
+            tokenizers,
+            running: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Starts a job of `kind` unless one is already running, returning its id.
+    pub async fn start(self: &Arc<Self>, kind: JobKind) -> Result<Option<i64>, sqlx::Error> {
+        if self.lock().values().any(|(running, _)| *running == kind) {
+            return Ok(None);
+        }
+
+        let total = match kind {
+            JobKind::BlobDedup => blobs::count_undeduplicated(&self.db).await?,
+            JobKind::TokenBackfill => backfill::count_unestimated(&self.db).await?,
+        };
+        let id = store::create_job(&self.db, kind.name(), total).await?;
+        let (tx, rx) = watch::channel(Control::Run);
+        self.lock().insert(id, (kind, tx));
+        tracing::info!("Started {} job {} for {} rows", kind.name(), id, total);
+
+        let jobs = self.clone();
+        tokio::spawn(async move { jobs.run(id, kind, rx).await });
+        Ok(Some(id))
+    }
+
+    /// Sends `control` to a running job; `false` if it isn't running in this process.
+    pub fn control(&self, id: i64, control: Control) -> bool {
+        match self.lock().get(&id) {
+            Some((_, tx)) => tx.send(control).is_ok(),
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<i64, (JobKind, watch::Sender<Control>)>> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn run(self: Arc<Self>, id: i64, kind: JobKind, mut control: watch::Receiver<Control>) {
+        let result = self.drive(id, kind, &mut control).await;
+        self.lock().remove(&id);
+
+        let finished = match &result {
+            Ok(status) => {
+                tracing::info!("{} job {} {}", kind.name(), id, status);
+                store::finish_job(&self.db, id, status, None).await
+            }
+            Err(e) => {
+                tracing::error!("{} job {} failed: {}", kind.name(), id, e);
+                store::finish_job(&self.db, id, JOB_FAILED, Some(&e.to_string())).await
+            }
+        };
+        if let Err(e) = finished {
+            tracing::error!("Failed to record the end of job {}: {}", id, e);
+        }
+    }
+
+    /// Processes chunks until the work runs out or the job is cancelled, returning the
+    /// final status.
+    async fn drive(
+        &self,
+        id: i64,
+        kind: JobKind,
+        control: &mut watch::Receiver<Control>,
+    ) -> Result<&'static str, sqlx::Error> {
+        let mut processed = 0;
+        loop {
+            let requested = *control.borrow_and_update();
+            match requested {
+                Control::Cancel => return Ok(JOB_CANCELLED),
+                Control::Pause => {
+                    store::update_job_progress(&self.db, id, JOB_PAUSED, processed).await?;
+                    if control.changed().await.is_err() {
+                        return Ok(JOB_CANCELLED);
+                    }
+                    continue;
+                }
+                Control::Run => {}
+            }
+
+            let converted = match kind {
+                JobKind::BlobDedup => blobs::deduplicate_batch(&self.db, CHUNK_SIZE).await?,
+                JobKind::TokenBackfill => self.backfill_tokens().await?,
+            } as i64;
+            if converted == 0 {
+                if kind == JobKind::BlobDedup && processed > 0 {
+                    blobs::reclaim_space(&self.db).await?;
+                }
+                return Ok(JOB_COMPLETED);
+            }
+
+            processed += converted;
+            store::update_job_progress(&self.db, id, JOB_RUNNING, processed).await?;
+            // Let queued proxy writes take the lock between chunks
+            tokio::task::yield_now().await;
+        }
+    }
+
+    async fn backfill_tokens(&self) -> Result<u64, sqlx::Error> {
+        let requests = backfill::get_unestimated(&self.db, CHUNK_SIZE).await?;
+        let tokenizers = self.tokenizers.clone();
+        // Tokenizing a chunk is CPU-bound, so keep it off the async workers
+        let estimates = tokio::task::spawn_blocking(move || {
+            requests
+                .iter()
+                .map(|request| {
+                    let (input, output, tokenizer) =
+                        tokenizers.estimate(&request.model, &request.prompt, &request.output);
+                    (request.id, input, output, tokenizer)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .map_err(|e| sqlx::Error::Protocol(format!("token estimation panicked: {}", e)))?;
+
+        backfill::store_token_estimates(&self.db, &estimates).await?;
+        Ok(estimates.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TokenizerRule;
+    use crate::db::jobs::MaintenanceJob;
+    use crate::db::testing::memory_pool;
+    use std::time::Duration;
+
+    /// Chunks the seeded work takes, enough to stop a job partway through
+    const CHUNKS: i64 = 4;
+
+    /// Inserts rows the way they were written before blobs and token estimates.
+    async fn seed_legacy_rows(pool: &SqlitePool, count: i64) {
+        sqlx::query(
+            r#"
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?)
+            INSERT INTO requests (
+                endpoint, model, start_time, end_time, duration_ms,
+                input_tokens, output_tokens, total_tokens, prompt, output, http_status
+            )
+            SELECT '/v1/chat/completions', 'm', '2026-01-01T00:00:00+00:00',
+                '2026-01-01T00:00:01+00:00', 1000, 0, 0, 0,
+                'shared prompt ' || (i % 7), 'output ' || i, 200
+            FROM n
+            "#,
+        )
+        .bind(count)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    fn jobs(pool: &SqlitePool) -> Arc<Jobs> {
+        let rules = [TokenizerRule {
+            pattern: "*".to_string(),
+            source: "chars/4".to_string(),
+        }];
+        Jobs::new(pool.clone(), Arc::new(Tokenizers::load(&rules)))
+    }
+
+    async fn job(pool: &SqlitePool, id: i64) -> MaintenanceJob {
+        store::get_job(pool, id).await.unwrap().unwrap()
+    }
+
+    async fn wait_until(pool: &SqlitePool, id: i64, done: impl Fn(&MaintenanceJob) -> bool) {
+        for _ in 0..10_000 {
+            if done(&job(pool, id).await) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        panic!("job {} never got there: {:?}", id, job(pool, id).await);
+    }
+
+    async fn finished(pool: &SqlitePool, id: i64) -> MaintenanceJob {
+        wait_until(pool, id, |job| job.finished_at.is_some()).await;
+        job(pool, id).await
+    }
+
+    /// Starts a job and cancels it once its first chunk is committed.
+    async fn cancel_midway(pool: &SqlitePool, jobs: &Arc<Jobs>, kind: JobKind) -> MaintenanceJob {
+        let id = jobs.start(kind).await.unwrap().unwrap();
+        assert_eq!(jobs.start(kind).await.unwrap(), None, "one job of a kind at a time");
+        wait_until(pool, id, |job| job.processed > 0).await;
+        assert!(jobs.control(id, Control::Cancel));
+
+        let job = finished(pool, id).await;
+        assert_eq!(job.status, JOB_CANCELLED);
+        assert!(job.processed > 0 && job.processed < job.total, "{:?}", job);
+        assert!(!jobs.control(id, Control::Run));
+        job
+    }
+
+    async fn scalar(pool: &SqlitePool, sql: &str) -> i64 {
+        sqlx::query_scalar(sql).fetch_one(pool).await.unwrap()
+    }
+
+    /// Every row is either fully moved to blobs or untouched, reads back its original
+    /// text, and every blob's reference count matches the rows pointing at it.
+    async fn assert_dedup_consistent(pool: &SqlitePool) {
+        let half_converted = scalar(
+            pool,
+            r#"
+            SELECT COUNT(*) FROM requests
+            WHERE (prompt_hash IS NULL) != (output_hash IS NULL)
+               OR (prompt_hash IS NOT NULL AND (prompt != '' OR output != ''))
+               OR (prompt_hash IS NULL AND prompt = '')
+            "#,
+        )
+        .await;
+        assert_eq!(half_converted, 0);
+
+        let wrong_text = scalar(
+            pool,
+            r#"
+            SELECT COUNT(*) FROM request_rows
+            WHERE prompt_text != 'shared prompt ' || (id % 7) OR output_text != 'output ' || id
+            "#,
+        )
+        .await;
+        assert_eq!(wrong_text, 0);
+
+        let wrong_refcount = scalar(
+            pool,
+            r#"
+            SELECT COUNT(*) FROM blobs b
+            WHERE refcount != (SELECT COUNT(*) FROM requests WHERE prompt_hash = b.hash)
+                + (SELECT COUNT(*) FROM requests WHERE output_hash = b.hash)
+            "#,
+        )
+        .await;
+        assert_eq!(wrong_refcount, 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_dedup_is_consistent_and_resumable() {
+        let pool = memory_pool().await;
+        let total = CHUNK_SIZE * CHUNKS;
+        seed_legacy_rows(&pool, total).await;
+        let jobs = jobs(&pool);
+
+        let cancelled = cancel_midway(&pool, &jobs, JobKind::BlobDedup).await;
+        assert_eq!(
+            blobs::count_undeduplicated(&pool).await.unwrap(),
+            total - cancelled.processed
+        );
+        assert_dedup_consistent(&pool).await;
+
+        let id = jobs.start(JobKind::BlobDedup).await.unwrap().unwrap();
+        let resumed = finished(&pool, id).await;
+        assert_eq!(resumed.status, JOB_COMPLETED);
+        assert_eq!(resumed.total, total - cancelled.processed);
+        assert_eq!(resumed.processed, resumed.total);
+        assert_eq!(blobs::count_undeduplicated(&pool).await.unwrap(), 0);
+        assert_dedup_consistent(&pool).await;
+        // Seven distinct prompts shared by every row, plus one output each
+        assert_eq!(scalar(&pool, "SELECT COUNT(*) FROM blobs").await, 7 + total);
+    }
+
+    #[tokio::test]
+    async fn cancelled_backfill_is_consistent_and_resumable() {
+        let pool = memory_pool().await;
+        let total = CHUNK_SIZE * CHUNKS;
+        seed_legacy_rows(&pool, total).await;
+        let jobs = jobs(&pool);
+
+        let cancelled = cancel_midway(&pool, &jobs, JobKind::TokenBackfill).await;
+        let estimated = "SELECT COUNT(*) FROM requests WHERE tokenizer IS NOT NULL \
+            AND estimated_input_tokens IS NOT NULL AND estimated_output_tokens IS NOT NULL";
+        assert_eq!(scalar(&pool, estimated).await, cancelled.processed);
+        assert_eq!(
+            backfill::count_unestimated(&pool).await.unwrap(),
+            total - cancelled.processed
+        );
+
+        let id = jobs.start(JobKind::TokenBackfill).await.unwrap().unwrap();
+        assert_eq!(finished(&pool, id).await.status, JOB_COMPLETED);
+        assert_eq!(scalar(&pool, estimated).await, total);
+        // Estimates don't stand in for the recorded counts
+        assert_eq!(scalar(&pool, "SELECT SUM(input_tokens) FROM requests").await, 0);
+    }
+
+    #[tokio::test]
+    async fn paused_job_waits_for_resume() {
+        let pool = memory_pool().await;
+        seed_legacy_rows(&pool, CHUNK_SIZE * 2).await;
+        let jobs = jobs(&pool);
+
+        let id = jobs.start(JobKind::BlobDedup).await.unwrap().unwrap();
+        assert!(jobs.control(id, Control::Pause));
+        wait_until(&pool, id, |job| job.status == JOB_PAUSED).await;
+        let paused = job(&pool, id).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(job(&pool, id).await.processed, paused.processed);
+
+        assert!(jobs.control(id, Control::Run));
+        let done = finished(&pool, id).await;
+        assert_eq!(done.status, JOB_COMPLETED);
+        assert_eq!(done.processed, CHUNK_SIZE * 2);
+    }
+}
@@ -0,0 +1,242 @@
+//! Heuristic detection of LM Studio reusing its KV cache for a repeated prompt prefix.
+//!
+//! Nothing upstream reports cache hits, so this infers them from timing: when a request
+//! shares a long prefix with the previous prompt in its session, prompt processing should
+//! only take as long as the new tokens need. The expected rate comes from the model's
+//! recent requests with little to reuse.
+
+use chrono::{DateTime, Duration};
+use sqlx::SqlitePool;
+
+use crate::db::RequestRecord;
+
+/// Description returned with the stats so nobody mistakes the verdicts for measurements
+pub const HEURISTIC: &str = "Heuristic: a request is a likely cache hit when its prompt \
+    processing time is closer to what only the tokens after the prefix shared with the \
+    session's previous prompt would need than to reprocessing the whole prompt, at the \
+    model's median prompt-eval rate over recent requests with no meaningful prefix to reuse.";
+
+pub const LIKELY_CACHE_HIT: &str = "likely_cache_hit";
+pub const LIKELY_FULL_REPROCESS: &str = "likely_full_reprocess";
+
+/// Requests by the same client and model this close together form a session
+const SESSION_GAP_MINUTES: i64 = 30;
+
+/// Shared prefixes shorter than this save too little time to tell apart from noise
+const MIN_PREFIX_TOKENS: i64 = 32;
+
+/// Fewer full-reprocess samples than this are too noisy to learn a baseline from
+const MIN_BASELINE_SAMPLES: usize = 5;
+
+/// Median prompt tokens per second over full-reprocess samples, once there are enough.
+pub fn baseline_tps(mut rates: Vec<f64>) -> Option<f64> {
+    rates.retain(|rate| rate.is_finite() && *rate > 0.0);
+    if rates.len() < MIN_BASELINE_SAMPLES {
+        return None;
+    }
+
+    rates.sort_by(f64::total_cmp);
+    let mid = rates.len() / 2;
+    Some(if rates.len().is_multiple_of(2) {
+        (rates[mid - 1] + rates[mid]) / 2.0
+    } else {
+        rates[mid]
+    })
+}
+
+/// Prompt tokens estimated to be shared with `previous`, scaling the shared character
+/// prefix by the prompt's token count.
+pub fn shared_prefix_tokens(prompt: &str, previous: &str, input_tokens: i64) -> i64 {
+    let shared = prompt
+        .chars()
+        .zip(previous.chars())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let total = prompt.chars().count();
+    if total == 0 {
+        return 0;
+    }
+    (input_tokens as f64 * shared as f64 / total as f64).round() as i64
+}
+
+/// Classifies prompt processing time against the two outcomes the baseline predicts,
+/// splitting the difference between them.
+pub fn classify(
+    prompt_eval_ms: i64,
+    input_tokens: i64,
+    prefix_tokens: i64,
+    baseline_tps: f64,
+) -> &'static str {
+    let full_ms = input_tokens as f64 * 1000.0 / baseline_tps;
+    let cached_ms = (input_tokens - prefix_tokens).max(0) as f64 * 1000.0 / baseline_tps;
+    if (prompt_eval_ms as f64) <= (full_ms + cached_ms) / 2.0 {
+        LIKELY_CACHE_HIT
+    } else {
+        LIKELY_FULL_REPROCESS
+    }
+}
+
+/// Records the shared prefix and, when it is long enough and a baseline exists, the
+/// cache verdict. Requests with too short a prefix become baseline samples.
+pub async fn annotate(pool: &SqlitePool, record: &mut RequestRecord) -> Result<(), sqlx::Error> {
+    let (Some(prompt_eval_ms), Some(client_id)) = (record.prompt_eval_ms, &record.client_id) else {
+        return Ok(());
+    };
+    if record.is_error || record.input_tokens <= 0 {
+        return Ok(());
+    }
+    let Ok(start) = DateTime::parse_from_rfc3339(&record.start_time) else {
+        return Ok(());
+    };
+    let session_start = (start - Duration::minutes(SESSION_GAP_MINUTES)).to_rfc3339();
+
+    let previous = crate::db::kv_cache::get_previous_session_prompt(
+        pool,
+        client_id,
+        &record.model,
+        &session_start,
+    )
+    .await?;
+    let prefix_tokens = previous
+        .map(|previous| shared_prefix_tokens(&record.prompt, &previous, record.input_tokens))
+        .unwrap_or(0);
+    record.kv_prefix_tokens = Some(prefix_tokens);
+    if prefix_tokens < MIN_PREFIX_TOKENS {
+        return Ok(());
+    }
+
+    let rates =
+        crate::db::kv_cache::get_full_reprocess_rates(pool, &record.model, MIN_PREFIX_TOKENS)
+            .await?;
+    if let Some(tps) = baseline_tps(rates) {
+        record.kv_baseline_tps = Some(tps);
+        record.kv_cache_class =
+            Some(classify(prompt_eval_ms, record.input_tokens, prefix_tokens, tps).to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::insert_request;
+    use crate::db::testing::memory_pool;
+    use chrono::Utc;
+
+    #[test]
+    fn baseline_needs_enough_samples() {
+        assert_eq!(baseline_tps(vec![100.0; MIN_BASELINE_SAMPLES - 1]), None);
+        assert_eq!(baseline_tps(vec![100.0; MIN_BASELINE_SAMPLES]), Some(100.0));
+    }
+
+    #[test]
+    fn baseline_is_the_median_of_usable_rates() {
+        assert_eq!(baseline_tps(vec![5.0, 1.0, 4.0, 2.0, 3.0]), Some(3.0));
+        assert_eq!(baseline_tps(vec![6.0, 1.0, 4.0, 2.0, 3.0, 5.0]), Some(3.5));
+        // One wild sample doesn't move it
+        assert_eq!(baseline_tps(vec![1.0, 2.0, 3.0, 4.0, 1e9]), Some(3.0));
+        let unusable = vec![f64::NAN, f64::INFINITY, 0.0, -1.0, 2.0, 2.0, 2.0, 2.0];
+        assert_eq!(baseline_tps(unusable), None);
+    }
+
+    #[test]
+    fn prefix_tokens_scale_with_the_shared_characters() {
+        assert_eq!(shared_prefix_tokens("abcdefgh", "abcdXXXX", 100), 50);
+        assert_eq!(shared_prefix_tokens("abcd", "abcdefgh", 10), 10);
+        assert_eq!(shared_prefix_tokens("abcd", "xbcd", 10), 0);
+        assert_eq!(shared_prefix_tokens("", "abcd", 10), 0);
+    }
+
+    #[test]
+    fn classification_splits_the_difference() {
+        // 1000 tokens at 100 tok/s: 10 s in full, 2 s with 800 of them cached
+        assert_eq!(classify(2_000, 1000, 800, 100.0), LIKELY_CACHE_HIT);
+        assert_eq!(classify(6_000, 1000, 800, 100.0), LIKELY_CACHE_HIT);
+        assert_eq!(classify(6_001, 1000, 800, 100.0), LIKELY_FULL_REPROCESS);
+        assert_eq!(classify(10_000, 1000, 800, 100.0), LIKELY_FULL_REPROCESS);
+        // A prefix longer than the estimate can't make the cached time negative
+        assert_eq!(classify(5_000, 1000, 2000, 100.0), LIKELY_CACHE_HIT);
+    }
+
+    fn request(prompt: &str, input_tokens: i64, prompt_eval_ms: i64) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            Utc::now(),
+            prompt.to_string(),
+        );
+        record.client_id = Some("client".to_string());
+        record.input_tokens = input_tokens;
+        record.prompt_eval_ms = Some(prompt_eval_ms);
+        record
+    }
+
+    /// Annotates and stores a request, as the handler does.
+    async fn send(pool: &SqlitePool, mut record: RequestRecord) -> RequestRecord {
+        annotate(pool, &mut record).await.unwrap();
+        insert_request(pool, &record).await.unwrap();
+        record
+    }
+
+    #[tokio::test]
+    async fn session_follow_ups_are_classified_against_the_learned_baseline() {
+        let pool = memory_pool().await;
+        let history = "x".repeat(4000);
+
+        // Unrelated prompts: no prefix, so they become baseline samples at 100 tok/s
+        for i in 0..MIN_BASELINE_SAMPLES {
+            let sample = send(&pool, request(&format!("{} {}", i, history), 1000, 10_000)).await;
+            assert_eq!(sample.kv_prefix_tokens, Some(0));
+            assert_eq!(sample.kv_cache_class, None);
+        }
+
+        // Same history plus a new turn, processed in about the time the new turn needs
+        let base = send(&pool, request(&format!("base {}", history), 1000, 10_000)).await;
+        assert_eq!(base.kv_cache_class, None);
+        let follow_up = format!("{}{}", base.prompt, "y".repeat(1000));
+        let hit = send(&pool, request(&follow_up, 1250, 3_000)).await;
+        assert_eq!(hit.kv_prefix_tokens, Some(1000));
+        assert_eq!(hit.kv_baseline_tps, Some(100.0));
+        assert_eq!(hit.kv_cache_class.as_deref(), Some(LIKELY_CACHE_HIT));
+
+        let again = format!("{}{}", follow_up, "z".repeat(1000));
+        let miss = send(&pool, request(&again, 1500, 15_000)).await;
+        assert_eq!(miss.kv_cache_class.as_deref(), Some(LIKELY_FULL_REPROCESS));
+
+        let report = crate::db::get_kv_cache_stats(&pool, None).await.unwrap();
+        assert_eq!(report.by_model[0].rate.classified, 2);
+        assert_eq!(report.by_model[0].rate.likely_hits, 1);
+        assert_eq!(report.by_model[0].rate.hit_rate, Some(0.5));
+        assert_eq!(report.by_client[0].client, "client");
+    }
+
+    #[tokio::test]
+    async fn baseline_follows_new_samples() {
+        let pool = memory_pool().await;
+        for i in 0..MIN_BASELINE_SAMPLES {
+            send(&pool, request(&format!("{} slow", i), 1000, 10_000)).await;
+        }
+        // The model got faster: more recent samples at 500 tok/s outnumber the old ones
+        for i in 0..MIN_BASELINE_SAMPLES + 1 {
+            send(&pool, request(&format!("{} fast", i), 1000, 2_000)).await;
+        }
+        let rates = crate::db::kv_cache::get_full_reprocess_rates(&pool, "m", MIN_PREFIX_TOKENS)
+            .await
+            .unwrap();
+        assert_eq!(baseline_tps(rates), Some(500.0));
+    }
+
+    #[tokio::test]
+    async fn requests_without_timing_or_client_are_left_alone() {
+        let pool = memory_pool().await;
+        let mut record = request("prompt", 10, 100);
+        record.client_id = None;
+        annotate(&pool, &mut record).await.unwrap();
+        assert_eq!(record.kv_prefix_tokens, None);
+
+        let mut record = request("prompt", 10, 100);
+        record.prompt_eval_ms = None;
+        annotate(&pool, &mut record).await.unwrap();
+        assert_eq!(record.kv_prefix_tokens, None);
+    }
+}
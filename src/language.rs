@@ -0,0 +1,83 @@
+//! Language of prompts and outputs, for usage split by language.
+//!
+//! Only compiled in with the `language-detection` feature; without it the language
+//! columns are left NULL. The prompt's language is taken from the user messages of the
+//! new turn (see [`crate::agent::new_turn`]), or the whole prompt when it isn't a
+//! `messages` array. Codes are ISO 639-3 (`eng`, `spa`, ...). A detection below
+//! [`MIN_CONFIDENCE`] is stored as [`UNKNOWN`] with its confidence, while a request with
+//! no stored text gets no language at all.
+
+use crate::db::RequestRecord;
+
+/// Language recorded when the detector isn't confident enough to name one
+pub const UNKNOWN: &str = "unknown";
+
+/// Detections less confident than this are recorded as [`UNKNOWN`]
+pub const MIN_CONFIDENCE: f64 = 0.5;
+
+/// Only the start of long texts is examined, so huge prompts don't slow down logging
+pub const MAX_DETECT_BYTES: usize = 4096;
+
+/// Sets the prompt and output languages of a successful request.
+pub fn annotate(record: &mut RequestRecord) {
+    if record.is_error {
+        return;
+    }
+    let prompt = match serde_json::from_str::<Vec<serde_json::Value>>(&record.prompt) {
+        Ok(messages) => crate::agent::new_turn(&messages).user_content.join("\n"),
+        Err(_) => record.prompt.clone(),
+    };
+    if let Some((language, confidence)) = detect(&prompt) {
+        record.prompt_language = Some(language);
+        record.prompt_language_confidence = Some(confidence);
+    }
+    if let Some((language, confidence)) = detect(&record.output) {
+        record.output_language = Some(language);
+        record.output_language_confidence = Some(confidence);
+    }
+}
+
+/// Language code and confidence of `text`, or `None` when there is nothing to examine.
+fn detect(text: &str) -> Option<(String, f64)> {
+    let text = head(text.trim(), MAX_DETECT_BYTES);
+    if text.is_empty() {
+        return None;
+    }
+    let (code, confidence) = imp::detect(text)?;
+    let confidence = (confidence * 100.0).round() / 100.0;
+    if confidence < MIN_CONFIDENCE {
+        return Some((UNKNOWN.to_string(), confidence));
+    }
+    Some((code.to_string(), confidence))
+}
+
+/// At most `max` bytes from the start of `text`, cut on a character boundary.
+fn head(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+#[cfg(feature = "language-detection")]
+mod imp {
+    pub fn detect(text: &str) -> Option<(&'static str, f64)> {
+        // Text without letters (code, numbers) detects as nothing; call it unknown
+        Some(
+            whatlang::detect(text).map_or((super::UNKNOWN, 0.0), |info| {
+                (info.lang().code(), info.confidence())
+            }),
+        )
+    }
+}
+
+#[cfg(not(feature = "language-detection"))]
+mod imp {
+    pub fn detect(_text: &str) -> Option<(&'static str, f64)> {
+        None
+    }
+}
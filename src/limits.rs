@@ -0,0 +1,136 @@
+//! Accounting for data the proxy deliberately drops or truncates.
+//!
+//! Every protective limit reports through a request's [`LimitTracker`] instead of logging
+//! on its own, so `limits_hit` on the stored request shows exactly which limits fired.
+
+use serde::{Deserialize, Serialize};
+
+/// The request was refused up front because its deadline couldn't be met
+pub const LIMIT_DEADLINE_REJECTED: &str = "deadline_rejected";
+/// The upstream call was cut off when the client's deadline ran out
+pub const LIMIT_DEADLINE_TIMEOUT: &str = "deadline_timeout";
+/// A stream's text stopped being kept because buffers held `MAX_BUFFERED_BYTES`
+pub const LIMIT_BUFFER_CAP: &str = "buffer_cap";
+
+/// One limit that fired, with how much it discarded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LimitHit {
+    pub limit: String,
+    /// Times the limit fired during the request
+    pub triggers: i64,
+    #[serde(default)]
+    pub bytes_truncated: i64,
+    #[serde(default)]
+    pub events_dropped: i64,
+}
+
+/// The limits that fired during one request, one entry per limit.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LimitTracker {
+    hits: Vec<LimitHit>,
+}
+
+impl LimitTracker {
+    /// Records that `limit` discarded `events` without truncating anything.
+    pub fn dropped(&mut self, limit: &str, events: i64) {
+        self.record(limit, 0, events);
+    }
+
+    /// Records one firing of `limit` and what it cut: bytes of a body or buffer,
+    /// and/or whole events (chunks, requests, samples).
+    pub fn record(&mut self, limit: &str, bytes: i64, events: i64) {
+        match self.hits.iter_mut().find(|hit| hit.limit == limit) {
+            Some(hit) => {
+                hit.triggers += 1;
+                hit.bytes_truncated += bytes;
+                hit.events_dropped += events;
+            }
+            None => self.hits.push(LimitHit {
+                limit: limit.to_string(),
+                triggers: 1,
+                bytes_truncated: bytes,
+                events_dropped: events,
+            }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hits.is_empty()
+    }
+
+    /// JSON for the `limits_hit` column; `None` when nothing fired.
+    pub fn to_json(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        serde_json::to_string(&self.hits).ok()
+    }
+
+    /// Reads back a stored `limits_hit` column, treating anything unreadable as empty.
+    pub fn from_json(json: Option<&str>) -> Self {
+        json.and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_firings_accumulate_per_limit() {
+        let mut tracker = LimitTracker::default();
+        tracker.record(LIMIT_BUFFER_CAP, 100, 0);
+        tracker.dropped(LIMIT_DEADLINE_TIMEOUT, 1);
+        tracker.record(LIMIT_BUFFER_CAP, 50, 2);
+
+        assert_eq!(
+            tracker.hits,
+            [
+                LimitHit {
+                    limit: LIMIT_BUFFER_CAP.to_string(),
+                    triggers: 2,
+                    bytes_truncated: 150,
+                    events_dropped: 2,
+                },
+                LimitHit {
+                    limit: LIMIT_DEADLINE_TIMEOUT.to_string(),
+                    triggers: 1,
+                    bytes_truncated: 0,
+                    events_dropped: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn nothing_fired_stores_null() {
+        let tracker = LimitTracker::default();
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.to_json(), None);
+        assert_eq!(LimitTracker::from_json(None), tracker);
+    }
+
+    #[test]
+    fn column_round_trips() {
+        let mut tracker = LimitTracker::default();
+        tracker.record(LIMIT_BUFFER_CAP, 7, 0);
+        let json = tracker.to_json().unwrap();
+        assert_eq!(
+            json,
+            r#"[{"limit":"buffer_cap","triggers":1,"bytes_truncated":7,"events_dropped":0}]"#
+        );
+        assert_eq!(LimitTracker::from_json(Some(&json)), tracker);
+    }
+
+    #[test]
+    fn unreadable_column_reads_as_empty() {
+        assert!(LimitTracker::from_json(Some("not json")).is_empty());
+        assert!(LimitTracker::from_json(Some(r#"{"limit":"x"}"#)).is_empty());
+        // Magnitudes written before they were tracked default to zero
+        let old = LimitTracker::from_json(Some(r#"[{"limit":"x","triggers":3}]"#));
+        assert_eq!(old.hits[0].bytes_truncated, 0);
+        assert_eq!(old.hits[0].triggers, 3);
+    }
+}
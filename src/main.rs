@@ -1,26 +1,79 @@
+mod agent;
+mod batches;
+mod benchmarks;
+mod buffers;
+mod canary;
+mod cli;
 mod config;
+mod counters;
 mod db;
+mod diagnostics;
 mod error;
+mod export;
+mod format;
+mod incidents;
+mod intervals;
+mod jobs;
+mod kv_cache;
+mod language;
+mod limits;
 mod proxy;
+mod recent;
+mod reloads;
+mod server;
+mod sink;
+mod spool;
 mod stats;
+mod status;
+mod systemd;
+mod tokenizer;
+mod verify;
+mod webhooks;
 
 use axum::{
     Router,
-    routing::{any, get},
+    routing::{any, delete, get, patch, post},
 };
-use sqlx::sqlite::SqlitePoolOptions;
+use clap::Parser;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use stats::auth::{Access, TaggedRoutes};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::str::FromStr;
 use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+/// How often the rolling per-minute counters are written to the database
+const COUNTER_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// How often the WAL size is compared with `WAL_CHECKPOINT_MB`
+const WAL_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// How long shutdown waits for spooled request writes
+const SPOOL_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long shutdown waits for the export sink to deliver what it holds
+const SINK_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // Initialize tracing
+async fn main() -> anyhow::Result<ExitCode> {
+    // Offline subcommands work on the database file directly and never start the server
+    let cli = cli::Cli::parse();
+    if let Some(command) = cli.command {
+        return cli::run(command).await;
+    }
+
+    // Initialize tracing, counting log lines that fail to write
+    let diagnostics = diagnostics::SelfDiagnostics::new();
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "lms_metrics_proxy=info,tower_http=info".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_writer(diagnostics.trace_writer()))
         .init();
 
     // Load configuration
@@ -33,17 +86,17 @@ async fn main() -> anyhow::Result<()> {
 
     // Initialize database
     // Parse the database URL to extract the file path and ensure parent directory exists
-    let db_path = config
-        .database_url
-        .strip_prefix("sqlite:")
-        .unwrap_or(&config.database_url);
+    let db_path = config.database_path();
     if let Some(parent) = std::path::Path::new(db_path).parent() {
         std::fs::create_dir_all(parent)?;
     }
 
+    // WAL lets stats queries read while a request insert or maintenance job writes
+    let options = SqliteConnectOptions::from_str(&format!("{}?mode=rwc", config.database_url))?
+        .journal_mode(SqliteJournalMode::Wal);
     let db = SqlitePoolOptions::new()
         .max_connections(5)
-        .connect(&format!("{}?mode=rwc", config.database_url))
+        .connect_with(options)
         .await?;
 
     db::init_db(&db).await?;
@@ -52,30 +105,367 @@ async fn main() -> anyhow::Result<()> {
     // Create HTTP client
     let client = proxy::create_client();
 
+    // Restore rolling counters from the last run
+    let counters = Arc::new(counters::MinuteCounters::restore(&db).await?);
+
+    // Deadline checks start from the latencies of the last run
+    let latency = proxy::deadline::RecentLatency::restore(&db).await?;
+
+    let tokenizers = Arc::new(tokenizer::Tokenizers::load(&config.tokenizers));
+
+    // Jobs don't survive a restart; move any rows left from older versions into blobs
+    let interrupted = db::jobs::interrupt_unfinished_jobs(&db).await?;
+    if interrupted > 0 {
+        tracing::warn!("{} maintenance job(s) were interrupted by the last shutdown", interrupted);
+    }
+    let jobs = jobs::Jobs::new(db.clone(), tokenizers.clone());
+    if db::blobs::count_undeduplicated(&db).await? > 0 {
+        jobs.start(jobs::JobKind::BlobDedup).await?;
+    }
+
+    let verifier = verify::Verifier::new(db.clone(), counters.clone(), diagnostics.clone());
+    let nightly_verification = verifier.spawn_nightly();
+
+    // Incident mode carries over a restart until its end time
+    let incidents = incidents::Incidents::restore(&db, config.incident.clone()).await?;
+
+    // Stored requests are shipped to the export sink, if one is configured
+    let sink = sink::ExportSink::start(&config.export_sink, client.clone(), diagnostics.clone())?;
+
+    // Webhook subscriptions added at runtime are stored and picked up again here
+    let webhooks = webhooks::Webhooks::start(db.clone(), client.clone()).await?;
+
+    // Slow stream writes are finished in the background
+    let recent = Arc::new(recent::RecentRing::new(config.recent_ring_size));
+    let spool = spool::Spool::start(
+        db.clone(),
+        recent.clone(),
+        sink.clone(),
+        webhooks.clone(),
+        diagnostics.clone(),
+        config.spool_capacity,
+    );
+
+    // Canary upstreams take their configured share of sessions from the start
+    let canary = canary::CanaryRouter::new(&config.lm_studio_url, &config.canary)?;
+    for (name, share) in canary.weights() {
+        if name != canary::PRIMARY_UPSTREAM {
+            tracing::info!("Canary upstream {} takes {}% of sessions", name, share);
+        }
+    }
+
     // Create shared state
     let state = Arc::new(proxy::AppState {
         config: config.clone(),
-        db,
+        db: db.clone(),
         client,
+        counters: counters.clone(),
+        in_flight: proxy::deadline::InFlight::default(),
+        latency,
+        active: proxy::active::ActiveRequests::default(),
+        tokenizers,
+        jobs,
+        recent,
+        verifier,
+        batches: Arc::new(batches::Batches::default()),
+        benchmarks: Arc::new(benchmarks::Benchmarks::default()),
+        reloads: Arc::new(reloads::ReloadDetector::default()),
+        canary: Arc::new(canary),
+        diagnostics: diagnostics.clone(),
+        buffers: buffers::BufferBudget::new(config.max_buffered_bytes),
+        incidents,
+        spool: spool.clone(),
+        sink: sink.clone(),
+        webhooks,
+    });
+
+    // Finish batches cut short by the last shutdown
+    let resumed = state.batches.resume(state.clone()).await?;
+    if resumed > 0 {
+        tracing::info!("Resuming {} unfinished batch(es)", resumed);
+    }
+    let resumed = state.benchmarks.resume(state.clone()).await?;
+    if resumed > 0 {
+        tracing::info!("Resuming {} unfinished benchmark(s)", resumed);
+    }
+
+    // Periodically persist the rolling counters
+    let flusher = {
+        let counters = counters.clone();
+        let db = db.clone();
+        let diagnostics = diagnostics.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(COUNTER_FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = counters.flush(&db).await {
+                    diagnostics.counter_flush_failed();
+                    tracing::error!("Failed to persist rolling counters: {}", e);
+                }
+            }
+        })
+    };
+
+    // Truncate the WAL when it has grown past the configured size
+    let wal_checkpointer = config.wal_checkpoint_bytes.map(|limit| {
+        let db = db.clone();
+        let db_path = config.database_path().to_string();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WAL_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                match db::storage::checkpoint_if_larger(&db, &db_path, limit).await {
+                    Ok(Some(report)) => tracing::info!(
+                        "WAL checkpoint: {} -> {} bytes",
+                        report.wal_bytes_before,
+                        report.wal_bytes_after
+                    ),
+                    Ok(None) => {}
+                    Err(e) => tracing::error!("Failed to checkpoint the WAL: {}", e),
+                }
+            }
+        })
+    });
+
+    // Check the upstream for the status page
+    let status_prober = config.status_page.then(|| {
+        status::spawn_prober(
+            db.clone(),
+            state.client.clone(),
+            config.lm_studio_url.clone(),
+            config.status_probe_interval_secs,
+        )
+    });
+
+    // Stop every listener together on Ctrl+C / SIGTERM
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        systemd::notify_stopping();
+        let _ = shutdown_tx.send(());
     });
 
     // Build router
-    let app = Router::new()
+    let proxy_routes = Router::new()
         // Health check
         .route("/health", get(stats::health_check))
-        // Statistics endpoints
-        .route("/stats/summary", get(stats::get_summary))
-        .route("/stats/by-model", get(stats::get_by_model))
-        .route("/stats/recent", get(stats::get_recent))
-        // Proxy endpoints - catch all /v1/* routes with any HTTP method
-        .route("/v1/{*path}", any(proxy::proxy_handler))
-        .with_state(state);
+        // Proxy endpoints - catch all /v1/* routes with any HTTP method; the handler
+        // decides which ones it serves locally
+        .route("/v1/{*path}", any(proxy::proxy_handler));
+    // The status page is public wherever it is enabled
+    let proxy_routes = if config.status_page {
+        proxy_routes.route("/status", get(stats::get_status))
+    } else {
+        proxy_routes
+    };
+
+    // Sockets passed by systemd socket activation take the place of binding ourselves
+    let mut activated = systemd::activated_listeners()?;
+    let proxy_addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+    let proxy_listener = bind(&mut activated, proxy_addr, "Proxy server").await?;
+
+    let watchdog = systemd::spawn_watchdog(db.clone());
+
+    match config.admin_port {
+        None => {
+            let app = proxy_routes
+                .merge(stats_routes().into_router(&config, false))
+                .with_state(state);
+            systemd::notify_ready();
+            server::serve(app, proxy_listener, shutdown_rx, diagnostics.clone()).await?;
+        }
+        Some(admin_port) => {
+            // Keep stats off the proxy port, except the aggregates viewer tokens may read
+            // and a public badge
+            let proxy_routes = if config.viewer_tokens.is_empty() && !config.badge.public {
+                proxy_routes
+            } else {
+                proxy_routes.merge(stats_routes().into_router(&config, true))
+            };
+            let app = proxy_routes.with_state(state.clone());
+            let mut admin = Router::new().route("/health", get(stats::health_check));
+            if config.status_page {
+                admin = admin.route("/status", get(stats::get_status));
+            }
+            let admin = admin
+                .merge(stats_routes().into_router(&config, false))
+                .with_state(state);
+            let admin_addr = SocketAddr::new(config.admin_bind_addr, admin_port);
+            let admin_listener = bind(&mut activated, admin_addr, "Admin server").await?;
+
+            systemd::notify_ready();
+            tokio::try_join!(
+                server::serve(app, proxy_listener, shutdown_rx.clone(), diagnostics.clone()),
+                server::serve(admin, admin_listener, shutdown_rx, diagnostics.clone()),
+            )?;
+        }
+    }
 
-    // Start server
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", config.port)).await?;
-    tracing::info!("Proxy server listening on 0.0.0.0:{}", config.port);
+    // Servers have drained; write what is still spooled and the final counter state
+    spool.drain(SPOOL_DRAIN_TIMEOUT).await;
+    sink.drain(SINK_DRAIN_TIMEOUT).await;
+    if let Some(watchdog) = watchdog {
+        watchdog.abort();
+    }
+    flusher.abort();
+    if let Some(status_prober) = status_prober {
+        status_prober.abort();
+    }
+    if let Some(wal_checkpointer) = wal_checkpointer {
+        wal_checkpointer.abort();
+    }
+    nightly_verification.abort();
+    if let Err(e) = counters.flush(&db).await {
+        diagnostics.counter_flush_failed();
+        tracing::error!("Failed to persist rolling counters: {}", e);
+    }
 
-    axum::serve(listener, app).await?;
+    Ok(ExitCode::SUCCESS)
+}
 
-    Ok(())
+/// Every stats route, tagged with who may call it.
+fn stats_routes() -> TaggedRoutes<Arc<proxy::AppState>> {
+    TaggedRoutes::default()
+        .route("/stats/summary", Access::Viewer, get(stats::get_summary))
+        .route("/stats/by-model", Access::Viewer, get(stats::get_by_model))
+        .route("/stats/models", Access::Full, get(stats::get_models))
+        .route("/stats/by-endpoint", Access::Full, get(stats::get_by_endpoint))
+        .route("/stats/by-kind", Access::Full, get(stats::get_by_kind))
+        .route("/stats/daily", Access::Full, get(stats::get_daily))
+        .route("/stats/badge", Access::Public, get(stats::get_badge))
+        .route("/stats/glance", Access::Full, get(stats::get_glance))
+        .route("/stats/timeseries", Access::Viewer, get(stats::get_timeseries))
+        .route("/stats/status-codes", Access::Full, get(stats::get_status_codes))
+        .route("/stats/heatmap", Access::Viewer, get(stats::get_heatmap))
+        .route("/stats/histogram", Access::Full, get(stats::get_histogram))
+        .route("/stats/utilization", Access::Full, get(stats::get_utilization))
+        .route("/stats/streaming", Access::Full, get(stats::get_streaming))
+        .route("/stats/estimation-gap", Access::Full, get(stats::get_estimation_gap))
+        .route("/stats/params", Access::Full, get(stats::get_params))
+        .route("/stats/rate", Access::Viewer, get(stats::get_rate))
+        .route("/stats/latency-trend", Access::Full, get(stats::get_latency_trend))
+        .route("/stats/rollups", Access::Full, get(stats::get_rollups))
+        .route("/stats/rollup/rebuild", Access::Admin, post(stats::rebuild_rollups))
+        .route("/stats/canary", Access::Full, get(stats::get_canary))
+        .route("/stats/by-language", Access::Full, get(stats::get_by_language))
+        .route("/stats/costs", Access::Full, get(stats::get_costs))
+        .route("/stats/by-sdk", Access::Full, get(stats::get_by_sdk))
+        .route("/stats/context-fit", Access::Full, get(stats::get_context_fit))
+        .route(
+            "/stats/context-utilization",
+            Access::Full,
+            get(stats::get_context_utilization),
+        )
+        .route("/stats/truncation", Access::Full, get(stats::get_truncation))
+        .route("/stats/finish-reasons", Access::Full, get(stats::get_finish_reasons))
+        .route("/stats/by-prompt-version", Access::Full, get(stats::get_by_prompt_version))
+        .route("/stats/determinism", Access::Full, get(stats::get_determinism))
+        .route("/stats/guardrails", Access::Full, get(stats::get_guardrails))
+        .route("/stats/stops", Access::Full, get(stats::get_stops))
+        .route("/stats/export.csv", Access::Full, get(stats::export_csv))
+        .route("/stats/export.jsonl", Access::Full, get(stats::export_jsonl))
+        .route("/stats/errors", Access::Full, get(stats::get_errors))
+        .route("/stats/retries", Access::Full, get(stats::get_retries))
+        .route("/stats/limits/triggers", Access::Full, get(stats::get_limit_triggers))
+        .route("/stats/agent-overhead", Access::Full, get(stats::get_agent_overhead))
+        .route("/stats/turn-latency", Access::Full, get(stats::get_turn_latency))
+        .route("/stats/prompt-quality", Access::Full, get(stats::get_prompt_quality))
+        .route("/stats/recent", Access::Full, get(stats::get_recent))
+        .route("/stats/active", Access::Full, get(stats::get_active))
+        .route("/stats/search", Access::Full, get(stats::search_requests))
+        .route("/stats/self", Access::Full, get(stats::get_self_diagnostics))
+        .route("/metrics", Access::Full, get(stats::get_metrics))
+        .route("/stats/persistence-lag", Access::Full, get(stats::get_persistence_lag))
+        .route("/stats/advisor/unload", Access::Full, get(stats::get_unload_advice))
+        .route("/stats/reloads", Access::Full, get(stats::get_reloads))
+        .route(
+            "/stats/cache-opportunities",
+            Access::Full,
+            get(stats::get_cache_opportunities),
+        )
+        .route("/stats/duplicates", Access::Full, get(stats::get_duplicates))
+        .route("/stats/top-prompts", Access::Full, get(stats::get_top_prompts))
+        .route("/stats/kv-cache", Access::Full, get(stats::get_kv_cache))
+        .route("/stats/chargeback", Access::Full, get(stats::get_chargeback))
+        .route("/stats/request/{id}", Access::Full, get(stats::get_request))
+        .route("/stats/request/{id}/tree", Access::Full, get(stats::get_request_tree))
+        .route("/stats/by-client", Access::Full, get(stats::get_by_client))
+        .route("/stats/sessions", Access::Full, get(stats::get_sessions))
+        .route("/stats/sessions/{id}", Access::Full, get(stats::get_session))
+        .route(
+            "/stats/requests/by-request-id/{id}",
+            Access::Full,
+            get(stats::get_request_by_response_id),
+        )
+        .route("/admin/verify", Access::Admin, post(stats::verify_counters))
+        .route("/admin/retention/simulate", Access::Admin, get(stats::simulate_retention))
+        .route("/admin/db", Access::Admin, get(stats::get_data_directory))
+        .route("/admin/db/checkpoint", Access::Admin, post(stats::checkpoint_database))
+        .route("/admin/batches/{id}", Access::Admin, get(stats::get_batch))
+        .route("/admin/batches/{id}/results", Access::Admin, get(stats::get_batch_results))
+        .route("/admin/batches/{id}/cancel", Access::Admin, post(stats::cancel_batch))
+        .route("/admin/benchmark", Access::Admin, post(stats::start_benchmark))
+        .route("/admin/benchmark/{id}", Access::Admin, get(stats::get_benchmark))
+        .route("/admin/benchmark/{id}/cancel", Access::Admin, post(stats::cancel_benchmark))
+        .route("/admin/routing/weights", Access::Admin, post(stats::set_routing_weights))
+        .route(
+            "/admin/prompt-versions/{hash}",
+            Access::Admin,
+            patch(stats::set_prompt_version_label),
+        )
+        .route(
+            "/admin/webhooks",
+            Access::Admin,
+            get(stats::list_webhooks).post(stats::create_webhook),
+        )
+        .route("/admin/webhooks/{id}", Access::Admin, delete(stats::delete_webhook))
+        .route(
+            "/admin/webhooks/{id}/deliveries",
+            Access::Admin,
+            get(stats::get_webhook_deliveries),
+        )
+        .route("/admin/incidents", Access::Admin, get(stats::list_incidents))
+        .route("/admin/incident/start", Access::Admin, post(stats::start_incident))
+        .route("/admin/jobs", Access::Admin, get(stats::list_jobs).post(stats::start_job))
+        .route("/admin/jobs/{id}", Access::Admin, get(stats::get_job))
+        .route("/admin/jobs/{id}/{action}", Access::Admin, post(stats::control_job))
+}
+
+/// Takes the next socket-activated listener, or binds `addr` when there is none.
+async fn bind(
+    activated: &mut VecDeque<std::net::TcpListener>,
+    addr: SocketAddr,
+    name: &str,
+) -> anyhow::Result<TcpListener> {
+    let listener = match activated.pop_front() {
+        Some(listener) => TcpListener::from_std(listener)?,
+        None => TcpListener::bind(addr).await?,
+    };
+    tracing::info!("{} listening on {}", name, listener.local_addr()?);
+    Ok(listener)
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutdown signal received, finishing in-flight requests");
 }
@@ -0,0 +1,134 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+
+use crate::db::RequestRecord;
+
+/// A tracked request the proxy is still working on, as shown by `/stats/active`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveRequest {
+    pub proxy_request_id: Option<String>,
+    pub model: String,
+    pub endpoint: String,
+    pub start_time: String,
+    pub elapsed_ms: i64,
+    pub streaming: bool,
+    /// Upstream the request went to; `None` until it is forwarded
+    pub upstream: Option<String>,
+    /// Bytes of the response relayed to the client so far; streams only
+    pub bytes_streamed: u64,
+    /// Estimated output tokens so far, a few chunks behind the stream; streams only
+    pub tokens_streamed: i64,
+}
+
+struct Entry {
+    start_time: String,
+    received_at: Instant,
+    streaming: bool,
+    model: String,
+    endpoint: String,
+    /// Changed by retries, which continue as a new request, and by routing
+    identity: Mutex<(Option<String>, Option<String>)>,
+    bytes_streamed: AtomicU64,
+    tokens_streamed: AtomicI64,
+}
+
+type Entries = Arc<Mutex<HashMap<u64, Arc<Entry>>>>;
+
+/// Tracked requests from arrival until their row is written (or handed to the spool).
+/// Clones share the same registry.
+#[derive(Clone, Default)]
+pub struct ActiveRequests {
+    next_key: Arc<AtomicU64>,
+    entries: Entries,
+}
+
+impl ActiveRequests {
+    /// Lists `record` as active until the returned guard is dropped.
+    pub fn register(&self, record: &RequestRecord, streaming: bool) -> ActiveGuard {
+        let key = self.next_key.fetch_add(1, Ordering::Relaxed);
+        let entry = Arc::new(Entry {
+            start_time: record.start_time.clone(),
+            received_at: Instant::now(),
+            streaming,
+            model: record.model.clone(),
+            endpoint: record.endpoint.clone(),
+            identity: Mutex::new((record.proxy_request_id.clone(), record.upstream.clone())),
+            bytes_streamed: AtomicU64::new(0),
+            tokens_streamed: AtomicI64::new(0),
+        });
+        lock(&self.entries).insert(key, entry.clone());
+        ActiveGuard {
+            entries: self.entries.clone(),
+            key,
+            entry,
+        }
+    }
+
+    /// Every active request, oldest first.
+    pub fn snapshot(&self) -> Vec<ActiveRequest> {
+        let entries: Vec<Arc<Entry>> = lock(&self.entries).values().cloned().collect();
+        let mut requests: Vec<ActiveRequest> = entries
+            .iter()
+            .map(|entry| {
+                let (proxy_request_id, upstream) = entry
+                    .identity
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone();
+                ActiveRequest {
+                    proxy_request_id,
+                    model: entry.model.clone(),
+                    endpoint: entry.endpoint.clone(),
+                    start_time: entry.start_time.clone(),
+                    elapsed_ms: entry.received_at.elapsed().as_millis() as i64,
+                    streaming: entry.streaming,
+                    upstream,
+                    bytes_streamed: entry.bytes_streamed.load(Ordering::Relaxed),
+                    tokens_streamed: entry.tokens_streamed.load(Ordering::Relaxed),
+                }
+            })
+            .collect();
+        requests.sort_by_key(|request| std::cmp::Reverse(request.elapsed_ms));
+        requests
+    }
+}
+
+fn lock(entries: &Entries) -> std::sync::MutexGuard<'_, HashMap<u64, Arc<Entry>>> {
+    entries.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Keeps a request listed as active; dropping it, on any path, removes the entry.
+pub struct ActiveGuard {
+    entries: Entries,
+    key: u64,
+    entry: Arc<Entry>,
+}
+
+impl ActiveGuard {
+    /// Follows the request to its latest attempt and upstream.
+    pub fn track(&self, record: &RequestRecord) {
+        let mut identity = self
+            .entry
+            .identity
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *identity = (record.proxy_request_id.clone(), record.upstream.clone());
+    }
+
+    /// Adds a relayed stream chunk and the output token count so far.
+    pub fn streamed(&self, bytes: usize, tokens: i64) {
+        self.entry
+            .bytes_streamed
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.entry.tokens_streamed.store(tokens, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        lock(&self.entries).remove(&self.key);
+    }
+}
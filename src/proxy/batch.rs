@@ -0,0 +1,98 @@
+use axum::{
+    Json,
+    extract::{ConnectInfo, Request, State},
+};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::db::batches::{self as store, Batch, NewBatchItem};
+use crate::error::ProxyError;
+use crate::proxy::AppState;
+
+/// `POST /v1/batches`: stores a JSONL body of requests and starts running it.
+///
+/// Each line is either an OpenAI batch line (`{"custom_id", "url", "body"}`) or a bare
+/// request body, which goes to `/v1/chat/completions` (or `/v1/completions` when it has
+/// a `prompt` instead of `messages`).
+pub async fn submit_batch(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+) -> Result<Json<Batch>, ProxyError> {
+    let (parts, body) = req.into_parts();
+    let body_bytes = body
+        .collect()
+        .await
+        .map_err(|e| ProxyError::Http(e.to_string()))?
+        .to_bytes();
+    let items = parse_jsonl(&String::from_utf8_lossy(&body_bytes))?;
+
+    let id = format!("batch_{}", uuid::Uuid::new_v4().simple());
+    let user_agent = parts
+        .headers
+        .get(hyper::header::USER_AGENT)
+        .and_then(|v| v.to_str().ok());
+    let client_ip = crate::proxy::client_ip::resolve(&state.config, peer, &parts.headers);
+    store::create_batch(&state.db, &id, &client_ip.to_string(), user_agent, &items).await?;
+    tracing::info!("Accepted batch {} with {} lines", id, items.len());
+
+    state.batches.start(state.clone(), id.clone());
+    let batch = store::get_batch(&state.db, &id)
+        .await?
+        .ok_or(ProxyError::Database(sqlx::Error::RowNotFound))?;
+    Ok(Json(batch))
+}
+
+fn parse_jsonl(body: &str) -> Result<Vec<NewBatchItem>, ProxyError> {
+    let mut items = Vec::new();
+    for (index, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid =
+            |reason: &str| ProxyError::BadRequest(format!("line {}: {}", index + 1, reason));
+
+        let Ok(Value::Object(mut entry)) = serde_json::from_str::<Value>(line) else {
+            return Err(invalid("expected a JSON object"));
+        };
+        let item = match entry.remove("body") {
+            Some(body @ Value::Object(_)) => NewBatchItem {
+                custom_id: entry
+                    .get("custom_id")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                url: entry
+                    .get("url")
+                    .and_then(Value::as_str)
+                    .unwrap_or("/v1/chat/completions")
+                    .to_string(),
+                body: body.to_string(),
+            },
+            Some(_) => return Err(invalid("body must be a JSON object")),
+            None => NewBatchItem {
+                custom_id: None,
+                url: if entry.contains_key("messages") {
+                    "/v1/chat/completions"
+                } else {
+                    "/v1/completions"
+                }
+                .to_string(),
+                body: Value::Object(entry).to_string(),
+            },
+        };
+        if !item.url.starts_with("/v1/") || item.url == "/v1/batches" {
+            return Err(invalid(
+                "url must be a /v1/ endpoint other than /v1/batches",
+            ));
+        }
+        items.push(item);
+    }
+
+    if items.is_empty() {
+        return Err(ProxyError::BadRequest("batch has no requests".to_string()));
+    }
+    Ok(items)
+}
@@ -0,0 +1,223 @@
+//! Lenient parsing of request bodies from clients that don't quite send JSON.
+//!
+//! Some clients prefix the body with a byte order mark, leave trailing commas, or
+//! concatenate several objects in one POST. LM Studio reads the first object and ignores
+//! the rest, so the proxy does the same: it takes the first object it can recover, forwards
+//! that, and records in `body_parse_warning` what it had to look past.
+
+use serde_json::{Deserializer, Value};
+
+const BOM: char = '\u{feff}';
+
+/// A request body as the proxy understood it.
+pub struct ParsedBody {
+    /// The first JSON value in the body, if one could be recovered
+    pub value: Option<Value>,
+    /// The body to forward: the recovered object on its own when anything had to be
+    /// dropped or repaired, the original otherwise
+    pub body: String,
+    /// What was wrong with the body, for `body_parse_warning`
+    pub warning: Option<String>,
+}
+
+pub fn parse(body: String) -> ParsedBody {
+    let mut problems = Vec::new();
+    let text = match body.strip_prefix(BOM) {
+        Some(rest) => {
+            problems.push("byte order mark removed".to_string());
+            rest
+        }
+        None => body.as_str(),
+    };
+
+    let (value, rest, repaired) = match first_value(text) {
+        Ok((value, rest)) => (value, rest.to_string(), None),
+        Err(error) => {
+            let without_commas = strip_trailing_commas(text);
+            match first_value(&without_commas) {
+                Ok((value, rest)) if without_commas.len() != text.len() => {
+                    let rest = rest.to_string();
+                    (value, rest, Some("trailing commas removed"))
+                }
+                _ => {
+                    problems.push(format!("not valid JSON: {}", error));
+                    return ParsedBody {
+                        value: None,
+                        warning: Some(problems.join("; ")),
+                        body,
+                    };
+                }
+            }
+        }
+    };
+    problems.extend(repaired.map(str::to_string));
+    if let Some(trailing) = describe_trailing(&rest) {
+        problems.push(trailing);
+    }
+    if !value.is_object() {
+        problems.push(format!("body is a JSON {}, not an object", kind(&value)));
+    }
+
+    if problems.is_empty() {
+        return ParsedBody {
+            value: Some(value),
+            body,
+            warning: None,
+        };
+    }
+    let body = if value.is_object() {
+        value.to_string()
+    } else {
+        body
+    };
+    ParsedBody {
+        value: Some(value),
+        body,
+        warning: Some(problems.join("; ")),
+    }
+}
+
+/// The first JSON value in `text` and whatever follows it.
+fn first_value(text: &str) -> Result<(Value, &str), serde_json::Error> {
+    let mut values = Deserializer::from_str(text).into_iter::<Value>();
+    match values.next() {
+        Some(Ok(value)) => Ok((value, &text[values.byte_offset()..])),
+        Some(Err(error)) => Err(error),
+        None => Err(serde_json::from_str::<Value>(text).unwrap_err()),
+    }
+}
+
+/// Describes what follows the first value: further JSON values, or anything else.
+fn describe_trailing(rest: &str) -> Option<String> {
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    let extra = Deserializer::from_str(rest)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<_>, _>>();
+    Some(match extra {
+        Ok(values) => format!(
+            "{} JSON values in body, only the first was used",
+            values.len() + 1
+        ),
+        Err(_) => format!("{} bytes of trailing data ignored", rest.len()),
+    })
+}
+
+/// Removes commas that directly precede a closing `}` or `]`, leaving strings alone.
+fn strip_trailing_commas(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, &c) in chars.iter().enumerate() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' {
+            let next = chars[i + 1..].iter().find(|c| !c.is_whitespace());
+            if matches!(next, Some('}' | ']')) {
+                continue;
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(body, model read, forwarded body, warning)`; a warning ending in `…` is matched
+    /// as a prefix
+    type Case = (&'static str, Option<&'static str>, &'static str, Option<&'static str>);
+
+    #[test]
+    fn bodies_are_recovered_or_rejected() {
+        let cases: &[Case] = &[
+            (r#"{"model":"a"}"#, Some("a"), r#"{"model":"a"}"#, None),
+            (
+                r#"{"model":"a","stream":true}{"model":"b"}"#,
+                Some("a"),
+                r#"{"model":"a","stream":true}"#,
+                Some("2 JSON values in body, only the first was used"),
+            ),
+            (
+                "{\"model\":\"a\"}\n{\"model\":\"b\"}\n{\"model\":\"c\"}\n",
+                Some("a"),
+                r#"{"model":"a"}"#,
+                Some("3 JSON values in body, only the first was used"),
+            ),
+            (
+                r#"{"model":"a"} xyz"#,
+                Some("a"),
+                r#"{"model":"a"}"#,
+                Some("3 bytes of trailing data ignored"),
+            ),
+            (
+                r#"{"model":"a","messages":[{"role":"user","content":"x, ]"},],}"#,
+                Some("a"),
+                r#"{"model":"a","messages":[{"role":"user","content":"x, ]"}]}"#,
+                Some("trailing commas removed"),
+            ),
+            (
+                "\u{feff}{\"model\":\"a\",\"stream\":true}",
+                Some("a"),
+                r#"{"model":"a","stream":true}"#,
+                Some("byte order mark removed"),
+            ),
+            (
+                "\u{feff}{\"model\":\"a\",}{\"model\":\"b\"}",
+                Some("a"),
+                r#"{"model":"a"}"#,
+                Some(
+                    "byte order mark removed; trailing commas removed; \
+                     2 JSON values in body, only the first was used",
+                ),
+            ),
+            // Still rejected: forwarded untouched for LM Studio to answer
+            (r#"{"model": "#, None, r#"{"model": "#, Some("not valid JSON: …")),
+            ("not json", None, "not json", Some("not valid JSON: …")),
+            ("", None, "", Some("not valid JSON: …")),
+            ("[1, 2]", None, "[1, 2]", Some("body is a JSON array, not an object")),
+        ];
+        for &(body, model, forwarded, warning) in cases {
+            let parsed = parse(body.to_string());
+            assert_eq!(parsed.body, forwarded, "{:?}", body);
+            match (warning, &parsed.warning) {
+                (Some(expected), Some(got)) if expected.ends_with('…') => {
+                    let prefix = expected.trim_end_matches('…');
+                    assert!(got.starts_with(prefix), "{:?}: {}", body, got);
+                }
+                _ => assert_eq!(parsed.warning.as_deref(), warning, "{:?}", body),
+            }
+            let read = parsed.value.as_ref().and_then(|v| v.get("model")?.as_str());
+            assert_eq!(read, model, "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn commas_inside_strings_are_kept() {
+        assert_eq!(strip_trailing_commas(r#"{"a":"x,}","b":[1,],}"#), r#"{"a":"x,}","b":[1]}"#);
+        assert_eq!(strip_trailing_commas(r#"{"a":"\",}",}"#), r#"{"a":"\",}"}"#);
+    }
+}
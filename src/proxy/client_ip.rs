@@ -0,0 +1,67 @@
+//! The address a request is attributed to: the connection's peer, or, behind a trusted
+//! reverse proxy, the client it reports in `X-Forwarded-For`.
+
+use axum::http::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::config::Config;
+
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// The client address of a request arriving from `peer`. With `TRUST_FORWARDED_FOR` set,
+/// the last entry in `X-Forwarded-For` wins: the one the reverse proxy in front appended,
+/// which a client cannot forge by sending the header itself. Only that entry is trusted,
+/// so when it isn't a bare address the peer is used instead of an earlier entry.
+pub fn resolve(config: &Config, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    if !config.trust_forwarded_for {
+        return peer.ip();
+    }
+    last_forwarded_for(headers).unwrap_or_else(|| peer.ip())
+}
+
+/// The last `X-Forwarded-For` entry, if it is an address.
+fn last_forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .next_back()?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(FORWARDED_FOR_HEADER, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn takes_the_entry_the_reverse_proxy_appended() {
+        let resolved = last_forwarded_for(&headers(&["1.1.1.1, 10.0.0.7"]));
+        assert_eq!(resolved, Some("10.0.0.7".parse().unwrap()));
+        let resolved = last_forwarded_for(&headers(&["1.1.1.1", "::1"]));
+        assert_eq!(resolved, Some("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn unparseable_last_entry_never_falls_back_to_a_forged_one() {
+        for last in ["10.0.0.7:5123", "unknown", "garbage", ""] {
+            let value = format!("1.1.1.1, {}", last);
+            assert_eq!(last_forwarded_for(&headers(&[&value])), None, "{}", last);
+        }
+        assert_eq!(last_forwarded_for(&headers(&["1.1.1.1", "unknown"])), None);
+        assert_eq!(last_forwarded_for(&HeaderMap::new()), None);
+    }
+}
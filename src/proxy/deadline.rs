@@ -0,0 +1,278 @@
+use axum::http::{HeaderMap, HeaderValue};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::db::RequestRecord;
+use crate::error::ProxyError;
+
+/// Request header carrying the client's total time budget in milliseconds
+pub const DEADLINE_HEADER: &str = "x-proxy-deadline-ms";
+
+/// Alternative request header carrying the client's timeout in seconds
+pub const REQUEST_TIMEOUT_HEADER: &str = "request-timeout";
+
+/// Which header the client expressed its deadline in, so it is propagated the same way
+#[derive(Debug, Clone, Copy)]
+enum DeadlineSource {
+    DeadlineMs,
+    RequestTimeout,
+}
+
+/// A client-supplied deadline, measured from when the proxy received the request.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    /// Total budget the client asked for
+    pub budget_ms: i64,
+    expires_at: Instant,
+    source: DeadlineSource,
+}
+
+impl Deadline {
+    /// Reads `X-Proxy-Deadline-Ms`, falling back to `Request-Timeout` (seconds).
+    pub fn from_headers(
+        headers: &HeaderMap,
+        received_at: Instant,
+    ) -> Result<Option<Self>, ProxyError> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+
+        let (budget_ms, source) = if let Some(value) = header(DEADLINE_HEADER) {
+            let ms = value.parse::<u64>().map_err(|_| {
+                ProxyError::BadRequest(format!("Invalid X-Proxy-Deadline-Ms value: {}", value))
+            })?;
+            (ms, DeadlineSource::DeadlineMs)
+        } else if let Some(value) = header(REQUEST_TIMEOUT_HEADER) {
+            let seconds = value
+                .parse::<f64>()
+                .ok()
+                .filter(|s| s.is_finite() && *s >= 0.0)
+                .ok_or_else(|| {
+                    ProxyError::BadRequest(format!("Invalid Request-Timeout value: {}", value))
+                })?;
+            ((seconds * 1000.0) as u64, DeadlineSource::RequestTimeout)
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            budget_ms: budget_ms as i64,
+            expires_at: received_at + Duration::from_millis(budget_ms),
+            source,
+        }))
+    }
+
+    /// Milliseconds left before the client gives up; negative once it has passed.
+    pub fn remaining_ms(&self) -> i64 {
+        let now = Instant::now();
+        if now <= self.expires_at {
+            (self.expires_at - now).as_millis() as i64
+        } else {
+            -((now - self.expires_at).as_millis() as i64)
+        }
+    }
+
+    /// Replaces the client's deadline header with the budget left for the upstream call.
+    pub fn propagate(&self, headers: &mut HeaderMap, upstream_budget: Duration) {
+        headers.remove(DEADLINE_HEADER);
+        headers.remove(REQUEST_TIMEOUT_HEADER);
+
+        let (name, value) = match self.source {
+            DeadlineSource::DeadlineMs => {
+                (DEADLINE_HEADER, upstream_budget.as_millis().to_string())
+            }
+            DeadlineSource::RequestTimeout => (
+                REQUEST_TIMEOUT_HEADER,
+                format!("{:.3}", upstream_budget.as_secs_f64()),
+            ),
+        };
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// Most recent successful requests considered when estimating a model's latency
+const LATENCY_SAMPLE_SIZE: usize = 100;
+
+/// Fewer samples than this are too noisy to reject a request on
+const MIN_LATENCY_SAMPLES: usize = 10;
+
+/// The p95 duration of each model's recent successful requests, kept up to date as
+/// requests are logged so the deadline check never waits on the database. Clones share
+/// the same windows.
+#[derive(Clone, Default)]
+pub struct RecentLatency {
+    by_model: Arc<Mutex<HashMap<String, LatencyWindow>>>,
+}
+
+#[derive(Default)]
+struct LatencyWindow {
+    /// Oldest first, at most `LATENCY_SAMPLE_SIZE`
+    durations: VecDeque<i64>,
+    p95_ms: Option<i64>,
+}
+
+impl LatencyWindow {
+    fn push(&mut self, duration_ms: i64) {
+        if self.durations.len() == LATENCY_SAMPLE_SIZE {
+            self.durations.pop_front();
+        }
+        self.durations.push_back(duration_ms);
+    }
+
+    fn update_p95(&mut self) {
+        if self.durations.len() < MIN_LATENCY_SAMPLES {
+            self.p95_ms = None;
+            return;
+        }
+        let mut sorted: Vec<i64> = self.durations.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = ((sorted.len() as f64) * 0.95).ceil() as usize;
+        self.p95_ms = Some(sorted[rank.clamp(1, sorted.len()) - 1]);
+    }
+}
+
+impl RecentLatency {
+    /// Windows filled from the stored requests, so estimates survive a restart.
+    pub async fn restore(pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let durations = crate::db::get_recent_durations(pool, LATENCY_SAMPLE_SIZE).await?;
+        let mut by_model: HashMap<String, LatencyWindow> = HashMap::new();
+        for (model, duration_ms) in durations {
+            by_model.entry(model).or_default().push(duration_ms);
+        }
+        by_model.values_mut().for_each(LatencyWindow::update_p95);
+        Ok(RecentLatency {
+            by_model: Arc::new(Mutex::new(by_model)),
+        })
+    }
+
+    /// Adds a finished request to its model's window; failed requests don't count.
+    pub fn record(&self, record: &RequestRecord) {
+        if record.is_error {
+            return;
+        }
+        let mut by_model = self.by_model.lock().unwrap_or_else(|e| e.into_inner());
+        let window = by_model.entry(record.model.clone()).or_default();
+        window.push(record.duration_ms);
+        window.update_p95();
+    }
+
+    /// p95 duration of the model's recent successful requests, if there is enough history.
+    pub fn p95_ms(&self, model: &str) -> Option<i64> {
+        let by_model = self.by_model.lock().unwrap_or_else(|e| e.into_inner());
+        by_model.get(model).and_then(|window| window.p95_ms)
+    }
+}
+
+/// Requests currently being forwarded upstream, per model. Clones share the same counts.
+#[derive(Clone, Default)]
+pub struct InFlight {
+    by_model: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl InFlight {
+    /// Requests for `model` already in progress.
+    pub fn depth(&self, model: &str) -> usize {
+        let by_model = self.by_model.lock().unwrap_or_else(|e| e.into_inner());
+        by_model.get(model).copied().unwrap_or(0)
+    }
+
+    /// Counts a request as in flight until the returned guard is dropped.
+    pub fn enter(&self, model: &str) -> InFlightGuard {
+        let mut by_model = self.by_model.lock().unwrap_or_else(|e| e.into_inner());
+        *by_model.entry(model.to_string()).or_default() += 1;
+        InFlightGuard {
+            by_model: self.by_model.clone(),
+            model: model.to_string(),
+        }
+    }
+}
+
+pub struct InFlightGuard {
+    by_model: Arc<Mutex<HashMap<String, usize>>>,
+    model: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut by_model = self.by_model.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = by_model.get_mut(&self.model) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                by_model.remove(&self.model);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::insert_request;
+    use crate::db::testing::memory_pool;
+    use chrono::Utc;
+
+    fn finished(model: &str, duration_ms: i64, is_error: bool) -> RequestRecord {
+        let endpoint = "/v1/chat/completions".to_string();
+        let mut record = RequestRecord::new(endpoint, model.to_string(), Utc::now(), "hi".into());
+        record.duration_ms = duration_ms;
+        record.is_error = is_error;
+        record
+    }
+
+    #[test]
+    fn p95_rolls_over_the_latest_successful_requests() {
+        let latency = RecentLatency::default();
+        for duration in 1..MIN_LATENCY_SAMPLES as i64 {
+            latency.record(&finished("m", duration * 10, false));
+        }
+        assert_eq!(latency.p95_ms("m"), None);
+        latency.record(&finished("m", 100, false));
+        assert_eq!(latency.p95_ms("m"), Some(100));
+
+        // Failures and other models leave the window alone
+        latency.record(&finished("m", 90_000, true));
+        latency.record(&finished("other", 90_000, false));
+        assert_eq!(latency.p95_ms("m"), Some(100));
+        assert_eq!(latency.p95_ms("other"), None);
+
+        // A full window of slow requests pushes the fast ones out
+        for _ in 0..LATENCY_SAMPLE_SIZE {
+            latency.record(&finished("m", 5_000, false));
+        }
+        assert_eq!(latency.p95_ms("m"), Some(5_000));
+        for duration in 1..=LATENCY_SAMPLE_SIZE as i64 {
+            latency.record(&finished("m", duration, false));
+        }
+        assert_eq!(latency.p95_ms("m"), Some(95));
+    }
+
+    #[tokio::test]
+    async fn restore_reads_each_models_latest_requests() {
+        let pool = memory_pool().await;
+        for _ in 0..LATENCY_SAMPLE_SIZE {
+            insert_request(&pool, &finished("a", 9_000, false)).await.unwrap();
+        }
+        for duration in 1..=LATENCY_SAMPLE_SIZE as i64 {
+            insert_request(&pool, &finished("a", duration, false)).await.unwrap();
+            insert_request(&pool, &finished("a", 50_000, true)).await.unwrap();
+        }
+        for _ in 0..MIN_LATENCY_SAMPLES - 1 {
+            insert_request(&pool, &finished("b", 200, false)).await.unwrap();
+        }
+
+        let latency = RecentLatency::restore(&pool).await.unwrap();
+        assert_eq!(latency.p95_ms("a"), Some(95));
+        assert_eq!(latency.p95_ms("b"), None);
+        latency.record(&finished("b", 200, false));
+        assert_eq!(latency.p95_ms("b"), Some(200));
+    }
+}
@@ -0,0 +1,239 @@
+//! Per-model guardrails on sampling parameters (`SAMPLING_GUARDRAILS`).
+//!
+//! Values outside a model's allowed range are clamped to it, and a configured default is
+//! filled in when the client leaves the parameter out. The forwarded body is edited in
+//! place: only the adjusted value's text changes, so every other field reaches LM Studio
+//! byte for byte as the client sent it.
+
+use serde::Serialize;
+use serde_json::{Number, Value};
+use std::ops::Range;
+
+use crate::config::{GuardrailRule, model_pattern_matches};
+
+/// Response header listing the adjustments, when `ADJUSTED_PARAMS_HEADER` is on
+pub const ADJUSTED_PARAMS_HEADER: &str = "x-proxy-adjusted-params";
+
+pub const ACTION_CLAMPED: &str = "clamped";
+pub const ACTION_DEFAULTED: &str = "defaulted";
+
+/// One parameter the proxy changed before forwarding.
+#[derive(Debug, Clone, Serialize)]
+pub struct Adjustment {
+    pub param: String,
+    /// `clamped` or `defaulted`
+    pub action: &'static str,
+    /// What the client sent, `None` when it left the parameter out
+    pub original: Option<f64>,
+    pub applied: f64,
+}
+
+/// Applies the rules for `model` to a JSON request body, returning the body to forward
+/// and what was changed. Bodies that aren't JSON objects are left alone.
+pub fn apply(rules: &[GuardrailRule], model: &str, body: String) -> (String, Vec<Adjustment>) {
+    if !rules
+        .iter()
+        .any(|rule| model_pattern_matches(&rule.pattern, model))
+    {
+        return (body, Vec::new());
+    }
+    let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(&body) else {
+        return (body, Vec::new());
+    };
+
+    let mut forwarded = body;
+    let mut adjustments = Vec::new();
+    let mut seen: Vec<&str> = Vec::new();
+    for rule in rules {
+        // First matching rule per parameter wins
+        if seen.contains(&rule.param.as_str()) || !model_pattern_matches(&rule.pattern, model) {
+            continue;
+        }
+        seen.push(&rule.param);
+
+        let adjustment = match fields.get(&rule.param) {
+            Some(Value::Number(value)) => {
+                let Some(original) = value.as_f64() else {
+                    continue;
+                };
+                let applied = original.clamp(rule.min, rule.max);
+                if applied == original {
+                    continue;
+                }
+                let Some(span) = top_level_value(&forwarded, &rule.param) else {
+                    continue;
+                };
+                forwarded.replace_range(span, &number_text(applied));
+                Adjustment {
+                    param: rule.param.clone(),
+                    action: ACTION_CLAMPED,
+                    original: Some(original),
+                    applied,
+                }
+            }
+            None | Some(Value::Null) => {
+                let Some(default) = rule.default else {
+                    continue;
+                };
+                match top_level_value(&forwarded, &rule.param) {
+                    Some(span) => forwarded.replace_range(span, &number_text(default)),
+                    None => insert_field(
+                        &mut forwarded,
+                        &format!("\"{}\":{}", rule.param, number_text(default)),
+                    ),
+                }
+                Adjustment {
+                    param: rule.param.clone(),
+                    action: ACTION_DEFAULTED,
+                    original: None,
+                    applied: default,
+                }
+            }
+            // Not a number; LM Studio will reject it on its own
+            Some(_) => continue,
+        };
+        tracing::info!(
+            "Adjusted {} for {}: {} -> {}",
+            adjustment.param,
+            model,
+            adjustment
+                .original
+                .map_or_else(|| "unset".to_string(), number_text),
+            number_text(adjustment.applied)
+        );
+        adjustments.push(adjustment);
+    }
+    (forwarded, adjustments)
+}
+
+/// `X-Proxy-Adjusted-Params` value, e.g. `temperature:1.8->1.0, top_p:unset->0.9`.
+pub fn header_value(adjustments: &[Adjustment]) -> String {
+    adjustments
+        .iter()
+        .map(|adjustment| {
+            format!(
+                "{}:{}->{}",
+                adjustment.param,
+                adjustment
+                    .original
+                    .map_or_else(|| "unset".to_string(), number_text),
+                number_text(adjustment.applied)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Adjustments as kept in `param_adjustments`, `None` when there were none.
+pub fn to_value(adjustments: &[Adjustment]) -> Option<Value> {
+    if adjustments.is_empty() {
+        return None;
+    }
+    serde_json::to_value(adjustments).ok()
+}
+
+fn number_text(value: f64) -> String {
+    Number::from_f64(value).map_or_else(|| value.to_string(), |number| number.to_string())
+}
+
+/// Inserts `field` as the first member of the top-level object.
+fn insert_field(body: &mut String, field: &str) {
+    let Some(open) = body.find('{') else {
+        return;
+    };
+    let empty = body[open + 1..].trim_start().starts_with('}');
+    let separator = if empty { "" } else { "," };
+    body.insert_str(open + 1, &format!("{}{}", field, separator));
+}
+
+/// Byte range of the value of `key` in the top-level object. With duplicate keys this is
+/// the last one, the one JSON parsers use.
+fn top_level_value(body: &str, key: &str) -> Option<Range<usize>> {
+    let bytes = body.as_bytes();
+    let mut i = skip_whitespace(bytes, 0);
+    if bytes.get(i) != Some(&b'{') {
+        return None;
+    }
+    i += 1;
+
+    let mut found = None;
+    loop {
+        i = skip_whitespace(bytes, i);
+        if bytes.get(i) != Some(&b'"') {
+            return found;
+        }
+        let key_end = skip_string(bytes, i)?;
+        let name: String = serde_json::from_str(&body[i..key_end]).ok()?;
+        i = skip_whitespace(bytes, key_end);
+        if bytes.get(i) != Some(&b':') {
+            return None;
+        }
+        let value_start = skip_whitespace(bytes, i + 1);
+        let value_end = skip_value(bytes, value_start)?;
+        if name == key {
+            found = Some(value_start..value_end);
+        }
+        i = skip_whitespace(bytes, value_end);
+        match bytes.get(i) {
+            Some(b',') => i += 1,
+            _ => return found,
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+        i += 1;
+    }
+    i
+}
+
+/// End of the string starting at the quote at `start`.
+fn skip_string(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    loop {
+        match bytes.get(i)? {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+}
+
+/// End of the value starting at `start`.
+fn skip_value(bytes: &[u8], start: usize) -> Option<usize> {
+    match bytes.get(start)? {
+        b'"' => skip_string(bytes, start),
+        b'{' | b'[' => {
+            let mut depth = 0usize;
+            let mut i = start;
+            loop {
+                match bytes.get(i)? {
+                    b'"' => {
+                        i = skip_string(bytes, i)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+        _ => {
+            let mut i = start;
+            while bytes
+                .get(i)
+                .is_some_and(|b| !matches!(b, b',' | b'}' | b']') && !b.is_ascii_whitespace())
+            {
+                i += 1;
+            }
+            Some(i)
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use axum::{
     body::Body,
-    extract::{Request, State},
-    http::HeaderMap,
+    extract::{ConnectInfo, Request, State},
+    http::HeaderValue,
     response::Response,
 };
 use bytes::Bytes;
@@ -10,43 +10,175 @@ use http_body_util::BodyExt;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::SqlitePool;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::Instant;
 use tokio_stream::StreamExt;
 
+use crate::batches::{BatchTag, Batches};
+use crate::benchmarks::{BenchmarkTag, Benchmarks};
+use crate::buffers::{BufferBudget, BufferGuard};
+use crate::canary::CanaryRouter;
 use crate::config::Config;
+use crate::counters::MinuteCounters;
 use crate::db::RequestRecord;
+use crate::db::blobs::content_hash;
+use crate::db::models::{
+    FAILURE_BODY_READ, FAILURE_FORWARD, FAILURE_PARSE, FAILURE_UPSTREAM_ERROR,
+    TERMINATION_ABANDONED, TERMINATION_CLIENT_DISCONNECTED,
+};
+use crate::diagnostics::SelfDiagnostics;
 use crate::error::ProxyError;
+use crate::incidents::{self, Incidents};
+use crate::jobs::Jobs;
+use crate::limits::{LIMIT_BUFFER_CAP, LIMIT_DEADLINE_REJECTED, LIMIT_DEADLINE_TIMEOUT};
+use crate::proxy::body_parse;
 use crate::proxy::client::HttpClient;
+use crate::proxy::client_ip;
+use crate::proxy::active::{ActiveGuard, ActiveRequests};
+use crate::proxy::deadline::{Deadline, InFlight, InFlightGuard, RecentLatency};
+use crate::proxy::guardrails;
+use crate::proxy::pacing::{PACE_HEADER, StreamPace};
+use crate::proxy::prompt_check::PromptWarning;
+use crate::proxy::schema_check::{self, Violation};
+use crate::proxy::{probe, prompt_check, prompt_hash, prompt_version};
+use crate::proxy::routes::{self, Dispatch};
+use crate::proxy::sampling;
+use crate::proxy::stops;
+use crate::proxy::sdk::{SdkFingerprint, fingerprint};
+use crate::proxy::sse;
+use crate::proxy::stream_stats::{self, APPEND_STATS_HEADER, AppendStats};
+use crate::recent::RecentRing;
+use crate::reloads::{RELOAD_EVENT, ReloadDetector};
+use crate::sink::ExportSink;
+use crate::spool::Spool;
+use crate::tokenizer::{RunningCount, Tokenizers};
+use crate::webhooks::Webhooks;
+use crate::verify::Verifier;
+
+/// Request header naming the proxy request id of the request that spawned this one
+const PARENT_ID_HEADER: &str = "x-proxy-parent-id";
+
+/// Response header carrying the proxy-issued id of a tracked request
+pub const REQUEST_ID_HEADER: &str = "x-proxy-request-id";
+
+/// Set to any value to get the request's prompt warnings back in `PROMPT_WARNINGS_HEADER`
+const DEBUG_HEADER: &str = "x-proxy-debug";
+const PROMPT_WARNINGS_HEADER: &str = "x-proxy-prompt-warnings";
+/// Set to any value to refuse the request if its body doesn't match the OpenAI schema
+const STRICT_SCHEMA_HEADER: &str = "x-proxy-strict-schema";
+
+/// Request header clients reuse when retrying the same logical request
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Status logged for requests the client gave up on (nginx's "client closed request")
+const CLIENT_CLOSED_REQUEST: i32 = 499;
+
+/// Longest partial SSE line kept once a stream is past the buffer cap; anything longer
+/// is dropped rather than held outside the tally
+const MAX_UNBUFFERED_LINE_BYTES: usize = 1024 * 1024;
+
+/// Delay before each proxy-initiated upstream retry, multiplied by the attempt number
+const UPSTREAM_RETRY_BACKOFF: std::time::Duration = std::time::Duration::from_millis(250);
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Config,
     pub db: SqlitePool,
     pub client: HttpClient,
+    pub counters: Arc<MinuteCounters>,
+    pub in_flight: InFlight,
+    pub latency: RecentLatency,
+    pub active: ActiveRequests,
+    pub tokenizers: Arc<Tokenizers>,
+    pub jobs: Arc<Jobs>,
+    pub recent: Arc<RecentRing>,
+    pub verifier: Arc<Verifier>,
+    pub batches: Arc<Batches>,
+    pub benchmarks: Arc<Benchmarks>,
+    pub reloads: Arc<ReloadDetector>,
+    pub canary: Arc<CanaryRouter>,
+    pub diagnostics: Arc<SelfDiagnostics>,
+    pub buffers: Arc<BufferBudget>,
+    pub incidents: Arc<Incidents>,
+    pub spool: Arc<Spool>,
+    pub sink: Arc<ExportSink>,
+    pub webhooks: Arc<Webhooks>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ChatRequest {
     model: Option<String>,
     messages: Option<Vec<Value>>,
+    #[serde(default, deserialize_with = "lenient_prompt")]
     prompt: Option<String>,
     stream: Option<bool>,
+    #[serde(default, deserialize_with = "sampling::lenient_count")]
+    max_tokens: Option<i64>,
+    #[serde(default, deserialize_with = "sampling::lenient_count")]
+    seed: Option<i64>,
+    stop: Option<Value>,
 }
 
+/// Deserializes a completions `prompt`: a string as is, and a batch of strings or
+/// token ids as its JSON text, the way `messages` are recorded. Anything else is `None`.
+fn lenient_prompt<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::String(prompt)) => Some(prompt),
+        Some(prompt @ Value::Array(_)) => Some(prompt.to_string()),
+        _ => None,
+    })
+}
+
+/// Request fields besides the seed that change what a seeded generation produces
+const SAMPLING_PARAMS: &[&str] = &[
+    "temperature",
+    "top_p",
+    "top_k",
+    "min_p",
+    "repeat_penalty",
+    "frequency_penalty",
+    "presence_penalty",
+    "max_tokens",
+    "stop",
+    "response_format",
+];
+
+/// Request fields besides the prompt and sampling parameters that a cached response
+/// would have to match
+const CACHE_KEY_FIELDS: &[&str] = &["tools", "tool_choice"];
+
 #[derive(Debug, Deserialize)]
 struct ChatResponse {
     id: Option<String>,
-    model: Option<String>,
+    system_fingerprint: Option<String>,
+    /// Absent from embeddings responses
+    #[serde(default)]
     choices: Vec<Choice>,
     usage: Option<Usage>,
+    stats: Option<UpstreamStats>,
+}
+
+/// Generation stats LM Studio adds to responses from its native API
+#[derive(Debug, Deserialize)]
+struct UpstreamStats {
+    /// Seconds
+    time_to_first_token: Option<f64>,
+    /// `eosFound`, `stopStringFound`, ...
+    stop_reason: Option<Value>,
 }
 
 #[derive(Debug, Deserialize)]
 struct Choice {
     message: Option<Message>,
     text: Option<String>,
-    delta: Option<Delta>,
     finish_reason: Option<String>,
+    /// The matched stop sequence, from backends that report it
+    stop_reason: Option<Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -54,11 +186,6 @@ struct Message {
     content: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Delta {
-    content: Option<String>,
-}
-
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 struct Usage {
     prompt_tokens: Option<i64>,
@@ -66,36 +193,101 @@ struct Usage {
     total_tokens: Option<i64>,
 }
 
+impl Usage {
+    /// `prompt_tokens`, or what the total leaves after the completion tokens.
+    fn input_tokens(&self) -> Option<i64> {
+        self.prompt_tokens
+            .or_else(|| Some(self.total_tokens? - self.completion_tokens?))
+    }
+
+    /// `completion_tokens`, or what the total leaves after the prompt tokens. Embeddings
+    /// report only prompt and total tokens, which makes this 0 rather than unknown.
+    fn output_tokens(&self) -> Option<i64> {
+        self.completion_tokens
+            .or_else(|| Some(self.total_tokens? - self.prompt_tokens?))
+    }
+}
+
 pub async fn proxy_handler(
     State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     req: Request,
 ) -> Result<Response, ProxyError> {
+    let dispatch = routes::resolve(&state.config.forward_paths, req.method(), req.uri().path());
+    if let Dispatch::Local(local) = dispatch {
+        return local.serve(state, peer, req).await;
+    }
+
     let start_time = Utc::now();
+    let received_at = Instant::now();
     let endpoint = req.uri().path().to_string();
     let method = req.method().clone();
 
-    // Extract the request body
-    let (parts, body) = req.into_parts();
-    let body_bytes = body
-        .collect()
+    // Extract the request body, refusing it when buffers already hold all they may
+    let (mut parts, body) = req.into_parts();
+    let declared = parts
+        .headers
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let read = state
+        .buffers
+        .read_body(body, declared)
         .await
-        .map_err(|e| ProxyError::Http(e.to_string()))?
-        .to_bytes();
+        .map_err(|e| ProxyError::Http(e.to_string()))
+        .and_then(|read| {
+            read.ok_or_else(|| {
+                tracing::warn!("Refusing {} {}: buffer memory cap reached", method, endpoint);
+                ProxyError::Overloaded(
+                    "buffered request data is at MAX_BUFFERED_BYTES".to_string(),
+                )
+            })
+        });
+    let (body_bytes, mut buffered) = match read {
+        Ok(read) => read,
+        Err(error) => {
+            return Err(
+                reject_untracked(&state, &parts, peer, start_time, FAILURE_BODY_READ, error).await,
+            );
+        }
+    };
 
     let body_str = String::from_utf8_lossy(&body_bytes).to_string();
+    drop(body_bytes);
+    buffered.resize(body_str.len() as u64);
 
     // For GET requests or other methods without a body, just proxy through without tracking
     // Only track POST requests that create completions/chat completions
     if method != "POST" || body_str.is_empty() {
-        return simple_proxy(state, parts, body_str, method).await;
+        return simple_proxy(state, parts, body_str, method, peer, start_time).await;
     }
 
-    // Parse the request to check if it's streaming
-    let chat_req: ChatRequest = serde_json::from_str(&body_str).unwrap_or(ChatRequest {
+    // Parse the request to check if it's streaming, recovering the first object from
+    // bodies that aren't clean JSON
+    let parsed = body_parse::parse(body_str);
+    let body_str = parsed.body;
+    if parsed.warning.is_some() {
+        tracing::warn!(
+            "Request body for {} needed lenient parsing: {}",
+            endpoint,
+            parsed.warning.as_deref().unwrap_or_default()
+        );
+        // What's forwarded may be the recovered object rather than the original bytes
+        parts.headers.remove(hyper::header::CONTENT_LENGTH);
+    }
+    let chat_req = parsed
+        .value
+        .as_ref()
+        .and_then(|value| ChatRequest::deserialize(value).ok());
+    let chat_req = chat_req.unwrap_or(ChatRequest {
         model: None,
         messages: None,
         prompt: None,
         stream: Some(false),
+        max_tokens: None,
+        seed: None,
+        stop: None,
     });
 
     let model = chat_req
@@ -104,6 +296,15 @@ pub async fn proxy_handler(
         .unwrap_or_else(|| "unknown".to_string());
     let is_streaming = chat_req.stream.unwrap_or(false);
 
+    // Hold sampling parameters to the model's guardrails; the edited body is what gets
+    // forwarded and recorded
+    let (body_str, param_adjustments) =
+        guardrails::apply(&state.config.guardrails, &model, body_str);
+    if !param_adjustments.is_empty() {
+        // The body's length may have changed; hyper sets it again from the body
+        parts.headers.remove(hyper::header::CONTENT_LENGTH);
+    }
+
     // Extract prompt from messages or prompt field
     let prompt_str = if let Some(messages) = &chat_req.messages {
         serde_json::to_string(messages).unwrap_or_default()
@@ -115,70 +316,731 @@ pub async fn proxy_handler(
 
     // Create request record
     let mut record = RequestRecord::new(endpoint.clone(), model.clone(), start_time, prompt_str);
+    // The record keeps its own copy of the prompt until it is written
+    buffered.resize((body_str.len() + record.prompt.len()) as u64);
+    // Listed in /stats/active until its row is written; the guard goes on every exit path
+    let active = state.active.register(&record, is_streaming);
 
-    // Reconstruct the request
-    let mut hyper_req = hyper::Request::builder()
-        .method(parts.method.clone())
-        .uri(parts.uri.clone())
-        .body(body_str.clone())
-        .map_err(|e| ProxyError::Http(e.to_string()))?;
+    // Fingerprint the client SDK from its User-Agent
+    let user_agent = parts
+        .headers
+        .get(hyper::header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let sdk = user_agent
+        .as_deref()
+        .map(fingerprint)
+        .unwrap_or_else(SdkFingerprint::other);
+    let known_bad_sdk = state
+        .config
+        .known_bad_sdks
+        .iter()
+        .any(|pattern| sdk.matches(pattern));
+    if known_bad_sdk {
+        tracing::warn!(
+            "Request from known-problematic SDK {} (User-Agent: {})",
+            sdk.label(),
+            user_agent.as_deref().unwrap_or("")
+        );
+    }
+    record.sdk_name = Some(sdk.name.clone());
+    record.sdk_version = sdk.version.clone();
 
-    // Copy headers
-    *hyper_req.headers_mut() = parts.headers.clone();
+    // A client is identified by its address together with the agent it runs
+    let client_ip = client_ip::resolve(&state.config, peer, &parts.headers).to_string();
+    record.client_id = Some(format!(
+        "{} {}",
+        client_ip,
+        user_agent.as_deref().unwrap_or("-")
+    ));
+    record.namespace = state.config.pricing.namespace_for(&client_ip).map(str::to_string);
+    record.client_ip = Some(client_ip);
+    record.user_agent = user_agent;
+    record.body_parse_warning = parsed.warning.clone();
+    record.session_id = parts
+        .headers
+        .get(&state.config.session_header)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    record.max_tokens = chat_req.max_tokens;
+    record.context_length = state.config.context_for(&model);
+    record.is_probe = probe::is_probe(
+        &state.config.probes,
+        chat_req.messages.as_deref(),
+        chat_req.prompt.as_deref(),
+        chat_req.max_tokens,
+    );
+    record.normalized_prompt_hash =
+        prompt_hash::normalized_hash(chat_req.messages.as_deref(), chat_req.prompt.as_deref());
+    record.seed = chat_req.seed;
+    record.param_adjustments = guardrails::to_value(&param_adjustments);
+    if record.seed.is_some() {
+        record.sampling_params = sampling_params(&body_str);
+    }
+    record.params_hash = params_hash(&model, &body_str);
+    let settings = sampling::parse(&body_str);
+    record.temperature = settings.temperature;
+    record.top_p = settings.top_p;
+    record.presence_penalty = settings.presence_penalty;
+    record.frequency_penalty = settings.frequency_penalty;
+    let stop_sequences = stops::parse(chat_req.stop.as_ref());
+    if !stop_sequences.is_empty() {
+        record.stop_count = Some(stop_sequences.len() as i64);
+        record.stop_chars = Some(stop_sequences.iter().map(|stop| stop.chars().count() as i64).sum());
+        record.stop_sequences = serde_json::to_value(&stop_sequences).ok();
+    }
+    record.batch_id = parts.extensions.get::<BatchTag>().map(|tag| tag.0.clone());
+    record.benchmark_id = parts.extensions.get::<BenchmarkTag>().map(|tag| tag.0.clone());
+    record.incident_id = state.incidents.current();
+    if record.incident_id.is_some() {
+        record.incident_capture = Some(incidents::capture_request(&parts.headers));
+    }
+    if let Some(messages) = &chat_req.messages {
+        record.prompt_warnings =
+            prompt_check::check_messages(messages, state.config.prompt_warn_message_chars);
+        record.prompt_version =
+            prompt_version::fingerprint(messages, &state.config.prompt_version_normalization);
+    }
 
-    // Forward request to LM Studio
-    let lm_response = crate::proxy::client::forward_request(
-        &state.client,
-        hyper_req,
-        &state.config.lm_studio_url,
+    // Hold the body to the OpenAI schema when asked to: refuse it in strict mode, record
+    // the violations alongside the prompt warnings otherwise
+    let strict_schema = state.config.schema_validation == "strict"
+        || parts.headers.contains_key(STRICT_SCHEMA_HEADER);
+    if strict_schema || state.config.schema_validation == "warn" {
+        let violations = schema_check::validate(&endpoint, parsed.value.as_ref());
+        if strict_schema {
+            // A body the proxy had to repair is one the real API would refuse outright
+            let repaired = parsed
+                .value
+                .as_ref()
+                .is_some_and(Value::is_object)
+                .then_some(parsed.warning)
+                .flatten();
+            let violations: Vec<Violation> = repaired
+                .map(|warning| Violation::new("$".to_string(), warning))
+                .into_iter()
+                .chain(violations)
+                .collect();
+            if !violations.is_empty() {
+                let error = ProxyError::SchemaViolation(violations);
+                return Err(reject(&state, &mut record, error).await);
+            }
+        } else {
+            record
+                .prompt_warnings
+                .extend(violations.into_iter().map(PromptWarning::from));
+        }
+    }
+    let adjusted_params = (state.config.adjusted_params_header && !param_adjustments.is_empty())
+        .then(|| guardrails::header_value(&param_adjustments));
+    let prompt_warnings = parts
+        .headers
+        .contains_key(DEBUG_HEADER)
+        .then(|| prompt_check::header_value(&record.prompt_warnings))
+        .filter(|warnings| !warnings.is_empty());
+
+    // Link this request to the earlier request that spawned it, if the client says so
+    let proxy_request_id = record.proxy_request_id.clone().unwrap_or_default();
+    if let Some(parent_id) = parts
+        .headers
+        .get(PARENT_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+    {
+        // The request's own id is minted here, so the named parent can't be the request or
+        // one of its descendants. A failed lookup treats the parent as unknown rather than
+        // refusing the request
+        let exists = crate::db::request_exists(&state.db, &parent_id)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Failed to look up parent request {}: {}", parent_id, e);
+                false
+            });
+        if !exists {
+            tracing::warn!(
+                "Request {} names unknown parent {}; it will be treated as a root",
+                proxy_request_id,
+                parent_id
+            );
+        }
+        record.parent_id = Some(parent_id);
+    }
+
+    // A repeated Idempotency-Key marks a client retry of an earlier request
+    if let Some(key) = parts
+        .headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+    {
+        let origin = crate::db::find_retry_origin(&state.db, &key)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Failed to look up the origin of retry key {}: {}", key, e);
+                None
+            });
+        if let Some(original) = origin {
+            record.retry_of = Some(original);
+            record.retry_source = Some("client".to_string());
+        }
+        record.idempotency_key = Some(key);
+    }
+
+    // Streams can be metered out to the client at a steady pace when enabled
+    if is_streaming {
+        let pace = StreamPace::from_headers(&parts.headers, &state.config);
+        if let Some(pace) = reject_on_error(&state, &mut record, pace).await? {
+            parts.extensions.insert(pace);
+        }
+        let append_stats =
+            stream_stats::requested(&parts.headers, state.config.stream_stats_event);
+        if reject_on_error(&state, &mut record, append_stats).await? {
+            parts.extensions.insert(AppendStats);
+        }
+    }
+
+    // Fail fast when the client's deadline can't be met by the model's recent p95,
+    // given the requests for the same model already ahead of this one
+    let deadline = Deadline::from_headers(&parts.headers, received_at);
+    let deadline = reject_on_error(&state, &mut record, deadline).await?;
+    if let Some(deadline) = &deadline {
+        record.deadline_ms = Some(deadline.budget_ms);
+        let available = deadline.remaining_ms() - state.config.deadline_overhead_ms as i64;
+        // Without a p95 only the remaining budget itself can reject the request
+        let estimate = state.latency.p95_ms(&model).map(|p95| p95 * (state.in_flight.depth(&model) as i64 + 1));
+
+        let reason = if available <= 0 {
+            Some(format!(
+                "deadline of {} ms leaves no time for the upstream call",
+                deadline.budget_ms
+            ))
+        } else {
+            estimate.filter(|&estimate| estimate > available).map(|estimate| {
+                format!(
+                    "expected completion in {} ms exceeds the {} ms remaining",
+                    estimate, available
+                )
+            })
+        };
+
+        if let Some(reason) = reason {
+            tracing::info!("Rejecting request {}: {}", proxy_request_id, reason);
+            record.set_error(Utc::now(), reason.clone(), 504);
+            record.deadline_status = Some("rejected".to_string());
+            record.limits_hit.dropped(LIMIT_DEADLINE_REJECTED, 1);
+            settle_deadline(&mut record, Some(deadline));
+            log_request(&state, &mut record).await;
+            return Err(ProxyError::DeadlineExceeded(reason));
+        }
+    }
+
+    // Hyper drops this future if the client hangs up before the response starts, so
+    // the guard logs the request as abandoned unless forwarding finishes first
+    let mut abandon = AbandonGuard::new(state.clone(), &record, received_at, deadline);
+    let result = forward_tracked(
+        state,
+        &parts,
+        body_str,
+        record,
+        is_streaming,
+        &mut abandon,
+        RequestHold {
+            active,
+            _buffered: buffered,
+        },
     )
     .await;
+    abandon.disarm();
+    let mut response = result?;
+
+    // Let the client know its SDK version is on the known-bad list
+    if known_bad_sdk
+        && let Ok(value) =
+            HeaderValue::from_str(&format!("known-problematic client SDK {}", sdk.label()))
+    {
+        response.headers_mut().insert("x-proxy-warning", value);
+    }
+    if let Some(value) = adjusted_params.and_then(|params| HeaderValue::from_str(&params).ok()) {
+        response
+            .headers_mut()
+            .insert(guardrails::ADJUSTED_PARAMS_HEADER, value);
+    }
+    if let Some(value) = prompt_warnings.and_then(|warnings| HeaderValue::from_str(&warnings).ok()) {
+        response.headers_mut().insert(PROMPT_WARNINGS_HEADER, value);
+    }
+
+    Ok(response)
+}
+
+/// Forwards a tracked request upstream, retrying if configured, and logs the outcome.
+async fn forward_tracked(
+    state: Arc<AppState>,
+    parts: &axum::http::request::Parts,
+    body_str: String,
+    mut record: RequestRecord,
+    is_streaming: bool,
+    abandon: &mut AbandonGuard,
+    hold: RequestHold,
+) -> Result<Response, ProxyError> {
+    let in_flight = state.in_flight.enter(&record.model);
+    let deadline = abandon.deadline;
+
+    // Keep a conversation on one upstream: its session id decides, or the client's identity
+    let session = record
+        .session_id
+        .as_deref()
+        .or(record.client_id.as_deref())
+        .unwrap_or_default();
+    let upstream = state.canary.route(session);
+    let upstream_url = upstream.url.clone();
+    record.upstream = Some(upstream.name.clone());
+    hold.active.track(&record);
+
+    // Forward request to LM Studio, retrying connection failures if configured
+    let mut attempt = 0;
+    let mut attempt_started = abandon.received_at;
+    let lm_response = loop {
+        // Reconstruct the request
+        let built = hyper::Request::builder()
+            .method(parts.method.clone())
+            .uri(parts.uri.clone())
+            .body(body_str.clone())
+            .map_err(|e| ProxyError::Http(e.to_string()));
+        if built.is_err() {
+            record.failure_stage = Some(FAILURE_FORWARD.to_string());
+        }
+        let mut hyper_req = reject_on_error(&state, &mut record, built).await?;
+
+        // Copy headers, minus the proxy's own linkage and debug headers
+        *hyper_req.headers_mut() = parts.headers.clone();
+        hyper_req.headers_mut().remove(PARENT_ID_HEADER);
+        hyper_req.headers_mut().remove(DEBUG_HEADER);
+        hyper_req.headers_mut().remove(PACE_HEADER);
+        hyper_req.headers_mut().remove(APPEND_STATS_HEADER);
+        hyper_req.headers_mut().remove(STRICT_SCHEMA_HEADER);
 
-    match lm_response {
+        // Give upstream whatever is left of the deadline, minus the proxy's own margin
+        let upstream_budget = deadline.as_ref().map(|deadline| {
+            let remaining = deadline.remaining_ms();
+            record.deadline_remaining_forward_ms = Some(remaining);
+            let budget = Duration::from_millis(
+                (remaining - state.config.deadline_overhead_ms as i64).max(0) as u64,
+            );
+            deadline.propagate(hyper_req.headers_mut(), budget);
+            budget
+        });
+
+        record.queue_wait_ms = Some(attempt_started.elapsed().as_millis() as i64);
+        let forwarded_at = Instant::now();
+        abandon.forwarded(&record, forwarded_at);
+
+        let forward =
+            crate::proxy::client::forward_request(&state.client, hyper_req, &upstream_url);
+        let result = match upstream_budget {
+            Some(budget) => tokio::time::timeout(budget, forward).await.unwrap_or_else(|_| {
+                Err(ProxyError::DeadlineExceeded(format!(
+                    "LM Studio did not respond within the remaining {} ms",
+                    budget.as_millis()
+                )))
+            }),
+            None => forward.await,
+        };
+        record.time_to_headers_ms = Some(forwarded_at.elapsed().as_millis() as i64);
+
+        match result {
+            Err(e)
+                if attempt < state.config.upstream_retries
+                    && !matches!(e, ProxyError::DeadlineExceeded(_)) =>
+            {
+                attempt += 1;
+                tracing::warn!(
+                    "Upstream attempt {} for {} failed, retrying: {}",
+                    attempt,
+                    record.proxy_request_id.as_deref().unwrap_or_default(),
+                    e
+                );
+
+                // Record the failed attempt, then continue as a linked retry
+                let end_time = Utc::now();
+                record.set_error(end_time, e.to_string(), 502);
+                record.failure_stage = Some(FAILURE_FORWARD.to_string());
+                settle_deadline(&mut record, deadline.as_ref());
+                log_request(&state, &mut record).await;
+                record = record.retry(end_time, "proxy-auto");
+                attempt_started = Instant::now();
+                abandon.track(&record);
+                hold.active.track(&record);
+
+                tokio::time::sleep(UPSTREAM_RETRY_BACKOFF * attempt).await;
+            }
+            result => break result,
+        }
+    };
+    let proxy_request_id = record.proxy_request_id.clone().unwrap_or_default();
+
+    let mut response = match lm_response {
         Ok(response) => {
             let status = response.status();
-            let headers = response.headers().clone();
+            record.deadline_remaining_response_ms = deadline.as_ref().map(|d| d.remaining_ms());
+            abandon.track(&record);
 
             if is_streaming && status.is_success() {
                 // Handle streaming response
-                handle_streaming_response(state, record, response, headers).await
+                let options = StreamOptions {
+                    pace: parts.extensions.get::<StreamPace>().copied(),
+                    append_stats: parts.extensions.get::<AppendStats>().is_some(),
+                };
+                handle_streaming_response(
+                    state,
+                    record,
+                    response,
+                    deadline,
+                    in_flight,
+                    hold,
+                    options,
+                )
+                .await?
             } else {
                 // Handle non-streaming response
-                handle_non_streaming_response(state, record, response).await
+                handle_non_streaming_response(state, record, response, deadline).await?
             }
         }
         Err(e) => {
             // Log error to database
             let end_time = Utc::now();
-            record.set_error(end_time, e.to_string(), 502);
+            let http_status = match e {
+                ProxyError::DeadlineExceeded(_) => {
+                    record.deadline_status = Some("timed_out".to_string());
+                    record.limits_hit.dropped(LIMIT_DEADLINE_TIMEOUT, 1);
+                    504
+                }
+                _ => 502,
+            };
+            record.set_error(end_time, e.to_string(), http_status);
+            record.failure_stage = Some(FAILURE_FORWARD.to_string());
+            settle_deadline(&mut record, deadline.as_ref());
+            log_request(&state, &mut record).await;
 
-            if let Err(db_err) = crate::db::insert_request(&state.db, &record).await {
-                tracing::error!("Failed to log error to database: {}", db_err);
-            }
+            return Err(e);
+        }
+    };
 
-            Err(e)
+    // Hand the client the id it can pass as X-Proxy-Parent-Id on follow-up requests
+    if let Ok(value) = HeaderValue::from_str(&proxy_request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    Ok(response)
+}
+
+/// Logs a tracked request that the proxy fails before LM Studio answers, with the status
+/// `error` will be answered with, and hands the error back to be returned.
+async fn reject(state: &AppState, record: &mut RequestRecord, error: ProxyError) -> ProxyError {
+    record.set_error(Utc::now(), error.to_string(), error.status().as_u16() as i32);
+    log_request(state, record).await;
+    error
+}
+
+/// Logs a request that fails before it is tracked, so it still leaves a row, and hands the
+/// error back to be returned. Nothing is known of it beyond its endpoint and client.
+async fn reject_untracked(
+    state: &AppState,
+    parts: &axum::http::request::Parts,
+    peer: SocketAddr,
+    start_time: chrono::DateTime<Utc>,
+    stage: &str,
+    error: ProxyError,
+) -> ProxyError {
+    let mut record = RequestRecord::new(
+        parts.uri.path().to_string(),
+        "unknown".to_string(),
+        start_time,
+        String::new(),
+    );
+    let user_agent = parts
+        .headers
+        .get(hyper::header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let client_ip = client_ip::resolve(&state.config, peer, &parts.headers).to_string();
+    record.client_id = Some(format!(
+        "{} {}",
+        client_ip,
+        user_agent.as_deref().unwrap_or("-")
+    ));
+    record.client_ip = Some(client_ip);
+    record.user_agent = user_agent;
+    record.failure_stage = Some(stage.to_string());
+    reject(state, &mut record, error).await
+}
+
+/// Passes `result` through, logging the request via [`reject`] when it is an error.
+async fn reject_on_error<T, E: Into<ProxyError>>(
+    state: &AppState,
+    record: &mut RequestRecord,
+    result: Result<T, E>,
+) -> Result<T, ProxyError> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(error) => Err(reject(state, record, error.into()).await),
+    }
+}
+
+/// Logs a tracked request as abandoned if it is dropped while still armed.
+///
+/// Axum drops the handler future when the client disconnects, so a guard that is still
+/// armed on drop means the client gave up before any response byte was forwarded.
+struct AbandonGuard {
+    state: Arc<AppState>,
+    /// Latest snapshot of the request; `None` once disarmed
+    record: Option<RequestRecord>,
+    received_at: Instant,
+    forwarded_at: Option<Instant>,
+    deadline: Option<Deadline>,
+}
+
+impl AbandonGuard {
+    fn new(
+        state: Arc<AppState>,
+        record: &RequestRecord,
+        received_at: Instant,
+        deadline: Option<Deadline>,
+    ) -> Self {
+        Self {
+            state,
+            record: Some(record.clone()),
+            received_at,
+            forwarded_at: None,
+            deadline,
+        }
+    }
+
+    /// Updates the snapshot logged if the client hangs up.
+    fn track(&mut self, record: &RequestRecord) {
+        self.record = Some(record.clone());
+    }
+
+    /// Updates the snapshot as the attempt is sent upstream.
+    fn forwarded(&mut self, record: &RequestRecord, at: Instant) {
+        self.track(record);
+        self.forwarded_at = Some(at);
+    }
+
+    fn disarm(&mut self) {
+        self.record = None;
+    }
+}
+
+impl Drop for AbandonGuard {
+    fn drop(&mut self) {
+        let Some(mut record) = self.record.take() else {
+            return;
+        };
+
+        // Keep whatever waits had already been measured and close off the one in progress
+        if record.queue_wait_ms.is_none() {
+            record.queue_wait_ms = Some(self.received_at.elapsed().as_millis() as i64);
+        } else if record.time_to_headers_ms.is_none()
+            && let Some(forwarded_at) = self.forwarded_at
+        {
+            record.time_to_headers_ms = Some(forwarded_at.elapsed().as_millis() as i64);
+        }
+
+        record.set_error(
+            Utc::now(),
+            "Client disconnected before the response started".to_string(),
+            CLIENT_CLOSED_REQUEST,
+        );
+        record.termination = Some(TERMINATION_ABANDONED.to_string());
+        settle_deadline(&mut record, self.deadline.as_ref());
+        tracing::info!(
+            "Client abandoned request {} before the first response byte",
+            record.proxy_request_id.as_deref().unwrap_or_default()
+        );
+
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            log_request(&state, &mut record).await;
+        });
+    }
+}
+
+/// The sampling parameters present in a request body, in a fixed order so equal settings
+/// compare equal.
+fn sampling_params(body: &str) -> Option<Value> {
+    let Ok(Value::Object(body)) = serde_json::from_str::<Value>(body) else {
+        return None;
+    };
+    let params: serde_json::Map<String, Value> = SAMPLING_PARAMS
+        .iter()
+        .filter_map(|key| body.get(*key).map(|value| (key.to_string(), value.clone())))
+        .collect();
+    Some(Value::Object(params))
+}
+
+/// Hash of everything besides the prompt that decides a response: the model, the sampling
+/// parameters and the tools offered.
+fn params_hash(model: &str, body: &str) -> Option<String> {
+    let Ok(Value::Object(body)) = serde_json::from_str::<Value>(body) else {
+        return None;
+    };
+    let key: Vec<Value> = SAMPLING_PARAMS
+        .iter()
+        .chain(CACHE_KEY_FIELDS)
+        .map(|field| body.get(*field).cloned().unwrap_or(Value::Null))
+        .collect();
+    Some(content_hash(&format!("{}\n{}", model, Value::Array(key))))
+}
+
+/// Stop sequences recorded for the request.
+fn stop_sequences_of(record: &RequestRecord) -> Vec<String> {
+    stops::parse(record.stop_sequences.as_ref())
+}
+
+/// Records how much of the client's deadline was left when the request finished.
+fn settle_deadline(record: &mut RequestRecord, deadline: Option<&Deadline>) {
+    if let Some(deadline) = deadline {
+        let remaining = deadline.remaining_ms();
+        record.deadline_remaining_end_ms = Some(remaining);
+        if record.deadline_status.is_none() {
+            let status = if remaining >= 0 { "met" } else { "missed" };
+            record.deadline_status = Some(status.to_string());
+        }
+    }
+}
+
+/// Counts a finished request and writes it to the database, logging (not returning) failures.
+async fn log_request(state: &AppState, record: &mut RequestRecord) {
+    let _write = state.diagnostics.log_write();
+    let _pending = state.diagnostics.pending_persist(record.completed_at);
+    annotate_for_log(state, record, None).await;
+
+    let id = match crate::db::insert_request(&state.db, record).await {
+        Ok(id) => {
+            state.sink.push(record, id);
+            state.webhooks.notify(record, id);
+            Some(id)
+        }
+        Err(e) => {
+            state.diagnostics.request_not_stored();
+            tracing::error!("Failed to log request to database: {}", e);
+            None
+        }
+    };
+    state.recent.record(record, id);
+}
+
+/// Logs a finished stream without letting a slow database hold its task open: once
+/// `STREAM_WRITE_TIMEOUT_MS` has passed, the record goes to the write spool and the task
+/// ends, releasing its buffers.
+async fn log_streamed_request(state: &AppState, mut record: RequestRecord) {
+    let _write = state.diagnostics.log_write();
+    let pending = state.diagnostics.pending_persist(record.completed_at);
+    let deadline = Instant::now() + Duration::from_millis(state.config.stream_write_timeout_ms);
+    annotate_for_log(state, &mut record, Some(deadline)).await;
+
+    match tokio::time::timeout_at(deadline, crate::db::insert_request(&state.db, &record)).await {
+        Ok(Ok(id)) => {
+            state.sink.push(&record, id);
+            state.webhooks.notify(&record, id);
+            state.recent.record(&record, Some(id));
+        }
+        Ok(Err(e)) => {
+            state.diagnostics.request_not_stored();
+            tracing::error!("Failed to log request to database: {}", e);
+            state.recent.record(&record, None);
+        }
+        Err(_) => {
+            tracing::warn!(
+                "Writing request {} is slow, handing it to the spool",
+                record.proxy_request_id.as_deref().unwrap_or_default()
+            );
+            state.spool.hand_off(record, pending);
+        }
+    }
+}
+
+/// Fills in the estimates, prices and counters a request is logged with. With a
+/// `deadline`, the KV-cache lookup is skipped if the database hasn't answered by then.
+async fn annotate_for_log(state: &AppState, record: &mut RequestRecord, deadline: Option<Instant>) {
+    state.tokenizers.annotate(record);
+    crate::agent::annotate(&state.tokenizers, record);
+    crate::language::annotate(record);
+    let classify = crate::kv_cache::annotate(&state.db, record);
+    let classified = match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, classify)
+            .await
+            .unwrap_or(Ok(())),
+        None => classify.await,
+    };
+    if let Err(e) = classified {
+        tracing::warn!("Failed to classify KV-cache reuse: {}", e);
+    }
+    if !record.is_error {
+        record.estimated_energy_wh =
+            state.config.energy.estimate_wh(&record.model, record.duration_ms);
+
+        // Keep the rate in force now, so later price changes don't rewrite past charges
+        if let Some(price) = state
+            .config
+            .pricing
+            .price_for(record.namespace.as_deref(), &record.model)
+        {
+            record.input_price_per_m = Some(price.input_per_m);
+            record.output_price_per_m = Some(price.output_per_m);
         }
     }
+    if let Some(reload) = state.reloads.observe(&state.config, record) {
+        tracing::info!(
+            "Request {} to {} likely waited for a reload: {} ms after {} s idle",
+            record.proxy_request_id.as_deref().unwrap_or_default(),
+            record.model,
+            reload.prompt_eval_ms,
+            reload.gap_ms / 1000
+        );
+        let detail = serde_json::to_value(&reload).unwrap_or_default();
+        if let Err(e) = crate::db::record_event(&state.db, RELOAD_EVENT, &detail).await {
+            tracing::warn!("Failed to record a model reload: {}", e);
+        }
+    }
+    state.counters.record(record);
+    state.latency.record(record);
+    let failed = record.is_error && record.termination.as_deref() != Some(TERMINATION_ABANDONED);
+    if let Err(e) = state.incidents.observe(&state.db, failed).await {
+        tracing::error!("Failed to start an incident: {}", e);
+    }
 }
 
 async fn handle_non_streaming_response(
     state: Arc<AppState>,
     mut record: RequestRecord,
     response: hyper::Response<hyper::body::Incoming>,
+    deadline: Option<Deadline>,
 ) -> Result<Response, ProxyError> {
     let status = response.status();
     let headers = response.headers().clone();
 
-    // Collect the response body
-    let body_bytes = response
+    // Collect the response body; LM Studio's status is lost if it can't be read
+    let collected = response
         .into_body()
         .collect()
         .await
-        .map_err(|e| ProxyError::Http(e.to_string()))?
-        .to_bytes();
+        .map_err(|e| ProxyError::Http(e.to_string()));
+    if collected.is_err() {
+        record.failure_stage = Some(FAILURE_UPSTREAM_ERROR.to_string());
+        settle_deadline(&mut record, deadline.as_ref());
+    }
+    let body_bytes = reject_on_error(&state, &mut record, collected).await?.to_bytes();
 
     let body_str = String::from_utf8_lossy(&body_bytes).to_string();
+    // Already read, so counted even past the cap; the bytes and their text live until the end
+    let mut response_buffer = state.buffers.guard();
+    response_buffer.resize((body_bytes.len() + body_str.len()) as u64);
     let end_time = Utc::now();
+    incidents::capture_response(&mut record.incident_capture, &headers, &body_str, None);
 
     // Parse the response to extract token usage
     if status.is_success() {
@@ -187,12 +1049,12 @@ async fn handle_non_streaming_response(
             let input_tokens = chat_response
                 .usage
                 .as_ref()
-                .and_then(|u| u.prompt_tokens)
+                .and_then(Usage::input_tokens)
                 .unwrap_or(0);
             let output_tokens = chat_response
                 .usage
                 .as_ref()
-                .and_then(|u| u.completion_tokens)
+                .and_then(Usage::output_tokens)
                 .unwrap_or(0);
 
             record.complete(
@@ -207,17 +1069,39 @@ async fn handle_non_streaming_response(
             if let Some(id) = chat_response.id {
                 record.request_id = Some(id);
             }
+            record.system_fingerprint = chat_response.system_fingerprint;
+            record.tokens_estimated = chat_response.usage.is_none();
+            record.prompt_eval_ms = chat_response
+                .stats
+                .as_ref()
+                .and_then(|stats| stats.time_to_first_token)
+                .map(|seconds| (seconds * 1000.0).round() as i64);
+            let first_choice = chat_response.choices.first();
+            record.finish_reason = first_choice.and_then(|c| c.finish_reason.clone());
+            let stop_reason = first_choice
+                .and_then(|c| c.stop_reason.as_ref())
+                .or_else(|| chat_response.stats.as_ref().and_then(|stats| stats.stop_reason.as_ref()));
+            record.stopped_by_custom_stop = stops::stopped_by_custom_stop(
+                &stop_sequences_of(&record),
+                record.finish_reason.as_deref(),
+                &record.output,
+                stop_reason,
+            );
         } else {
-            record.set_error(end_time, "Failed to parse response".to_string(), status.as_u16() as i32);
+            record.set_upstream_error(
+                end_time,
+                "Failed to parse response".to_string(),
+                status.as_u16() as i32,
+            );
+            record.failure_stage = Some(FAILURE_PARSE.to_string());
         }
     } else {
-        record.set_error(end_time, body_str.clone(), status.as_u16() as i32);
+        record.set_upstream_error(end_time, body_str.clone(), status.as_u16() as i32);
     }
 
     // Log to database (don't fail if this errors)
-    if let Err(e) = crate::db::insert_request(&state.db, &record).await {
-        tracing::error!("Failed to log request to database: {}", e);
-    }
+    settle_deadline(&mut record, deadline.as_ref());
+    log_request(&state, &mut record).await;
 
     // Build and return response
     let mut response_builder = Response::builder().status(status);
@@ -225,28 +1109,75 @@ async fn handle_non_streaming_response(
         response_builder = response_builder.header(key, value);
     }
 
-    Ok(response_builder
+    response_builder
         .body(Body::from(body_bytes))
-        .map_err(|e| ProxyError::Http(e.to_string()))?)
+        .map_err(|e| ProxyError::Http(e.to_string()))
+}
+
+/// What a tracked request keeps until its record is written: its entry in
+/// `/stats/active` and its share of the buffer tally.
+struct RequestHold {
+    active: ActiveGuard,
+    /// Only held, released on drop
+    _buffered: BufferGuard,
+}
+
+/// What the client asked of a streamed response beyond passing it through.
+#[derive(Debug, Clone, Copy)]
+struct StreamOptions {
+    pace: Option<StreamPace>,
+    /// Close the stream with a `proxy_stats` event, see [`stream_stats`]
+    append_stats: bool,
 }
 
 async fn handle_streaming_response(
     state: Arc<AppState>,
     mut record: RequestRecord,
     response: hyper::Response<hyper::body::Incoming>,
-    headers: HeaderMap,
+    deadline: Option<Deadline>,
+    in_flight: InFlightGuard,
+    hold: RequestHold,
+    options: StreamOptions,
 ) -> Result<Response, ProxyError> {
     let status = response.status();
+    let headers = response.headers().clone();
 
-    // Create a channel for streaming to client
-    let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, std::io::Error>>(100);
+    // Create a channel for streaming to client, behind a pacer if one was asked for
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, std::io::Error>>(100);
+    let tx = match options.pace {
+        Some(pace) => pace.spawn(tx, state.config.stream_pacing_buffer_bytes),
+        None => tx,
+    };
 
     // Spawn a task to process the stream
     let state_clone = state.clone();
+    // During an incident, keep the raw stream as it was sent
+    let mut raw_stream = record
+        .incident_capture
+        .is_some()
+        .then(|| (String::new(), 0, headers.clone()));
     tokio::spawn(async move {
         let mut buffer = String::new();
         let mut last_usage: Option<Usage> = None;
         let mut request_id: Option<String> = None;
+        let mut system_fingerprint: Option<String> = None;
+        let mut finish_reason: Option<String> = None;
+        let mut stop_reason: Option<Value> = None;
+        let mut client_disconnected = false;
+        let mut first_token_ms: Option<i64> = None;
+        // Text after the last complete line, waiting for the rest of it
+        let mut pending = Vec::new();
+        let mut parse_failures = sse::ParseFailures::default();
+        // Whether what the client got so far ends with a complete event
+        let mut at_event_boundary = true;
+        // Output, partial lines and raw capture held for the record, while the cap allows.
+        // Past it, lines are still parsed for usage, finish reason and id.
+        let mut stream_buffer = state_clone.buffers.guard();
+        let mut buffering = true;
+        let mut unbuffered_bytes = 0;
+        // Counted as it arrives, in case the usage chunk never does
+        let mut output_count =
+            RunningCount::new(state_clone.tokenizers.for_model(&record.model));
 
         let body_stream = response.into_body();
         let mut frame_stream = http_body_util::BodyStream::new(body_stream);
@@ -257,14 +1188,54 @@ async fn handle_streaming_response(
                     if let Ok(data) = frame.into_data() {
                         let chunk = String::from_utf8_lossy(&data).to_string();
 
-                        // Forward to client immediately
-                        if tx.send(Ok(chunk.clone())).await.is_err() {
+                        // Forward the bytes as received, noting when a slow reader makes us wait
+                        let sent = match tx.try_send(Ok(data.clone())) {
+                            Ok(()) => true,
+                            Err(TrySendError::Full(chunk)) => {
+                                state_clone.diagnostics.stream_backpressure_wait();
+                                tx.send(chunk).await.is_ok()
+                            }
+                            Err(TrySendError::Closed(_)) => false,
+                        };
+                        if !sent {
                             tracing::warn!("Client disconnected during streaming");
+                            client_disconnected = true;
                             break;
                         }
+                        hold.active.streamed(chunk.len(), output_count.settled());
+
+                        // Room for the chunk in each buffer it may land in; once there
+                        // isn't, the rest of the stream is parsed but its text not kept
+                        if buffering {
+                            let raw_len = raw_stream.as_ref().map_or(0, |(raw, _, _)| raw.len());
+                            let copies = if raw_stream.is_some() { 3 } else { 2 };
+                            let held = pending.len() + buffer.len() + raw_len;
+                            buffering =
+                                stream_buffer.try_resize((held + chunk.len() * copies) as u64);
+                            if !buffering {
+                                tracing::warn!(
+                                    "Buffer memory cap reached, no longer recording the stream of {}",
+                                    record.proxy_request_id.as_deref().unwrap_or_default()
+                                );
+                            }
+                        }
+                        if !buffering {
+                            unbuffered_bytes += chunk.len() as i64;
+                        } else if let Some((raw, chunks, _)) = &mut raw_stream {
+                            incidents::append_capped(raw, &chunk);
+                            *chunks += 1;
+                        }
 
-                        // Parse SSE chunks
-                        for line in chunk.lines() {
+                        // Parse SSE lines once they are complete
+                        pending.extend_from_slice(&data);
+                        if !buffering && pending.len() > MAX_UNBUFFERED_LINE_BYTES {
+                            pending.clear();
+                            at_event_boundary = false;
+                        }
+                        let lines = sse::take_lines(&mut pending);
+                        at_event_boundary = pending.is_empty()
+                            && lines.last().map_or(at_event_boundary, String::is_empty);
+                        for line in lines {
                             if let Some(json_str) = line.strip_prefix("data: ") {
                                 if json_str == "[DONE]" {
                                     continue;
@@ -275,27 +1246,69 @@ async fn handle_streaming_response(
                                     if let Some(id) = chunk_data.get("id").and_then(|v| v.as_str()) {
                                         request_id = Some(id.to_string());
                                     }
+                                    if let Some(fingerprint) = chunk_data
+                                        .get("system_fingerprint")
+                                        .and_then(|v| v.as_str())
+                                    {
+                                        system_fingerprint = Some(fingerprint.to_string());
+                                    }
 
                                     // Extract content delta
-                                    if let Some(choices) = chunk_data.get("choices").and_then(|v| v.as_array()) {
-                                        if let Some(choice) = choices.first() {
-                                            if let Some(delta) = choice.get("delta") {
-                                                if let Some(content) = delta.get("content").and_then(|v| v.as_str()) {
-                                                    buffer.push_str(content);
-                                                }
-                                            }
+                                    if let Some(choices) = chunk_data.get("choices").and_then(|v| v.as_array())
+                                        && let Some(choice) = choices.first()
+                                        && let Some(delta) = choice.get("delta")
+                                    {
+                                        let content = delta.get("content").and_then(|v| v.as_str());
+                                        // The opening chunk usually carries only the role
+                                        let has_tool_calls = delta
+                                            .get("tool_calls")
+                                            .and_then(|v| v.as_array())
+                                            .is_some_and(|calls| !calls.is_empty());
+                                        if first_token_ms.is_none()
+                                            && (content.is_some_and(|c| !c.is_empty()) || has_tool_calls)
+                                        {
+                                            first_token_ms = record
+                                                .started_at
+                                                .map(|at| at.elapsed().as_millis() as i64);
+                                        }
+                                        if let Some(content) = content
+                                            && buffering
+                                        {
+                                            buffer.push_str(content);
+                                            output_count.observe(&buffer);
                                         }
                                     }
 
+                                    // Extract finish reason (set on the final content chunk)
+                                    if let Some(reason) = chunk_data
+                                        .pointer("/choices/0/finish_reason")
+                                        .and_then(|v| v.as_str())
+                                    {
+                                        finish_reason = Some(reason.to_string());
+                                    }
+                                    if let Some(reason) = chunk_data
+                                        .pointer("/choices/0/stop_reason")
+                                        .or_else(|| chunk_data.pointer("/stats/stop_reason"))
+                                        .filter(|reason| !reason.is_null())
+                                    {
+                                        stop_reason = Some(reason.clone());
+                                    }
+
                                     // Extract usage (usually in last chunk)
-                                    if let Some(usage) = chunk_data.get("usage") {
-                                        if let Ok(usage_data) = serde_json::from_value::<Usage>(usage.clone()) {
-                                            last_usage = Some(usage_data);
-                                        }
+                                    if let Some(usage) = chunk_data.get("usage")
+                                        && let Ok(usage_data) = serde_json::from_value::<Usage>(usage.clone())
+                                    {
+                                        last_usage = Some(usage_data);
                                     }
+                                } else {
+                                    parse_failures.record(&line);
                                 }
                             }
                         }
+                        if buffering {
+                            let raw_len = raw_stream.as_ref().map_or(0, |(raw, _, _)| raw.len());
+                            stream_buffer.resize((pending.len() + buffer.len() + raw_len) as u64);
+                        }
                     }
                 }
                 Err(e) => {
@@ -305,10 +1318,38 @@ async fn handle_streaming_response(
             }
         }
 
+        // A data line the upstream never finished is as lost as one that didn't parse
+        let unfinished = String::from_utf8_lossy(&pending);
+        if !client_disconnected
+            && let Some(json_str) = unfinished.trim_end().strip_prefix("data: ")
+            && json_str != "[DONE]"
+            && serde_json::from_str::<Value>(json_str).is_err()
+        {
+            parse_failures.record(unfinished.trim_end());
+        }
+        if parse_failures.count() > 0 {
+            tracing::warn!(
+                "{} stream chunk(s) from {} could not be parsed",
+                parse_failures.count(),
+                record.model
+            );
+        }
+        parse_failures.apply(&mut record);
+        if unbuffered_bytes > 0 {
+            record.limits_hit.record(LIMIT_BUFFER_CAP, unbuffered_bytes, 0);
+        }
+
         // Stream complete - log to database
         let end_time = Utc::now();
-        let input_tokens = last_usage.as_ref().and_then(|u| u.prompt_tokens).unwrap_or(0);
-        let output_tokens = last_usage.as_ref().and_then(|u| u.completion_tokens).unwrap_or(0);
+        let input_tokens = last_usage.as_ref().and_then(Usage::input_tokens).unwrap_or(0);
+        let output_tokens = last_usage.as_ref().and_then(Usage::output_tokens).unwrap_or(0);
+        record.stopped_by_custom_stop = stops::stopped_by_custom_stop(
+            &stop_sequences_of(&record),
+            finish_reason.as_deref(),
+            &buffer,
+            stop_reason.as_ref(),
+        );
+        record.estimated_output_tokens = Some(output_count.total(&buffer));
 
         record.complete(
             end_time,
@@ -322,10 +1363,41 @@ async fn handle_streaming_response(
         if let Some(id) = request_id {
             record.request_id = Some(id);
         }
+        record.system_fingerprint = system_fingerprint;
+        record.tokens_estimated = last_usage.is_none();
+        record.ttft_ms = first_token_ms;
+        // Time to the first token, less the wait before the request went upstream
+        record.prompt_eval_ms = first_token_ms
+            .map(|ms| ms - record.queue_wait_ms.unwrap_or(0));
+        record.finish_reason = finish_reason;
+        if client_disconnected {
+            record.termination = Some(TERMINATION_CLIENT_DISCONNECTED.to_string());
+        }
+        if let Some((raw, chunks, upstream_headers)) = raw_stream {
+            incidents::capture_response(
+                &mut record.incident_capture,
+                &upstream_headers,
+                &raw,
+                Some(chunks),
+            );
+        }
+        settle_deadline(&mut record, deadline.as_ref());
+        drop(in_flight);
 
-        if let Err(e) = crate::db::insert_request(&state_clone.db, &record).await {
-            tracing::error!("Failed to log streaming request to database: {}", e);
+        // After every upstream chunk, [DONE] included, and kept out of the stored output
+        if options.append_stats && !client_disconnected {
+            let price = state_clone
+                .config
+                .pricing
+                .price_for(record.namespace.as_deref(), &record.model);
+            // Close an upstream event left unterminated so the two can't merge
+            let separator = if at_event_boundary { "" } else { "\n\n" };
+            let event = format!("{}{}", separator, stream_stats::event(&record, price));
+            let _ = tx.send(Ok(Bytes::from(event))).await;
         }
+
+        log_streamed_request(&state_clone, record).await;
+        drop((hold, stream_buffer));
     });
 
     // Convert receiver to SSE stream
@@ -346,13 +1418,11 @@ async fn handle_streaming_response(
     }
 
     // Convert stream to Body
-    let body = Body::from_stream(stream.map(|result| {
-        result.map(|s| Bytes::from(s))
-    }));
+    let body = Body::from_stream(stream);
 
-    Ok(response_builder
+    response_builder
         .body(body)
-        .map_err(|e| ProxyError::Http(e.to_string()))?)
+        .map_err(|e| ProxyError::Http(e.to_string()))
 }
 
 async fn simple_proxy(
@@ -360,35 +1430,66 @@ async fn simple_proxy(
     parts: axum::http::request::Parts,
     body_str: String,
     method: axum::http::Method,
+    peer: SocketAddr,
+    start_time: chrono::DateTime<Utc>,
 ) -> Result<Response, ProxyError> {
     // Reconstruct the request for simple proxying (GET, DELETE, etc.)
-    let mut hyper_req = hyper::Request::builder()
+    let built = hyper::Request::builder()
         .method(method)
         .uri(parts.uri.clone())
         .body(body_str)
-        .map_err(|e| ProxyError::Http(e.to_string()))?;
+        .map_err(|e| ProxyError::Http(e.to_string()));
+    let mut hyper_req = match built {
+        Ok(request) => request,
+        Err(error) => {
+            return Err(
+                reject_untracked(&state, &parts, peer, start_time, FAILURE_FORWARD, error).await,
+            );
+        }
+    };
 
     // Copy headers
     *hyper_req.headers_mut() = parts.headers.clone();
+    hyper_req.headers_mut().remove(PARENT_ID_HEADER);
+    hyper_req.headers_mut().remove(DEBUG_HEADER);
+    hyper_req.headers_mut().remove(PACE_HEADER);
+    hyper_req.headers_mut().remove(APPEND_STATS_HEADER);
+    hyper_req.headers_mut().remove(STRICT_SCHEMA_HEADER);
 
-    // Forward to LM Studio
-    let lm_response = crate::proxy::client::forward_request(
+    // Forward to LM Studio; only failures are logged, these requests aren't tracked
+    let forwarded = crate::proxy::client::forward_request(
         &state.client,
         hyper_req,
         &state.config.lm_studio_url,
     )
-    .await?;
+    .await;
+    let lm_response = match forwarded {
+        Ok(response) => response,
+        Err(error) => {
+            return Err(
+                reject_untracked(&state, &parts, peer, start_time, FAILURE_FORWARD, error).await,
+            );
+        }
+    };
 
     let status = lm_response.status();
     let headers = lm_response.headers().clone();
 
     // Collect response body
-    let body_bytes = lm_response
+    let collected = lm_response
         .into_body()
         .collect()
         .await
-        .map_err(|e| ProxyError::Http(e.to_string()))?
-        .to_bytes();
+        .map_err(|e| ProxyError::Http(e.to_string()));
+    let body_bytes = match collected {
+        Ok(collected) => collected.to_bytes(),
+        Err(error) => {
+            let stage = FAILURE_UPSTREAM_ERROR;
+            return Err(reject_untracked(&state, &parts, peer, start_time, stage, error).await);
+        }
+    };
+    let mut response_buffer = state.buffers.guard();
+    response_buffer.resize(body_bytes.len() as u64);
 
     // Build and return response
     let mut response_builder = Response::builder().status(status);
@@ -396,17 +1497,17 @@ async fn simple_proxy(
         response_builder = response_builder.header(key, value);
     }
 
-    Ok(response_builder
+    response_builder
         .body(Body::from(body_bytes))
-        .map_err(|e| ProxyError::Http(e.to_string()))?)
+        .map_err(|e| ProxyError::Http(e.to_string()))
 }
 
 fn extract_output(response: &ChatResponse) -> String {
     if let Some(first_choice) = response.choices.first() {
-        if let Some(message) = &first_choice.message {
-            if let Some(content) = &message.content {
-                return content.clone();
-            }
+        if let Some(message) = &first_choice.message
+            && let Some(content) = &message.content
+        {
+            return content.clone();
         }
         if let Some(text) = &first_choice.text {
             return text.clone();
@@ -414,3 +1515,39 @@ fn extract_output(response: &ChatResponse) -> String {
     }
     String::new()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn chat_request(body: Value) -> ChatRequest {
+        ChatRequest::deserialize(body).expect("lenient fields never fail the request")
+    }
+
+    #[test]
+    fn batched_prompt_is_kept_as_json_text() {
+        let req = chat_request(json!({"model": "m", "prompt": ["a", "b"], "seed": 7}));
+        assert_eq!(req.model.as_deref(), Some("m"));
+        assert_eq!(req.prompt.as_deref(), Some(r#"["a","b"]"#));
+        assert_eq!(req.seed, Some(7));
+    }
+
+    #[test]
+    fn string_seed_is_read_and_odd_values_dropped() {
+        let req = chat_request(json!({"model": "m", "prompt": "hi", "seed": "42"}));
+        assert_eq!(req.seed, Some(42));
+        assert_eq!(req.prompt.as_deref(), Some("hi"));
+
+        let req = chat_request(json!({"model": "m", "prompt": {"x": 1}, "seed": true}));
+        assert_eq!(req.model.as_deref(), Some("m"));
+        assert_eq!(req.prompt, None);
+        assert_eq!(req.seed, None);
+    }
+
+    #[test]
+    fn large_seed_survives_exactly() {
+        let req = chat_request(json!({"seed": 9_007_199_254_740_993_i64}));
+        assert_eq!(req.seed, Some(9_007_199_254_740_993));
+    }
+}
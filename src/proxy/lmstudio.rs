@@ -0,0 +1,108 @@
+use http_body_util::BodyExt;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use crate::error::ProxyError;
+use crate::proxy::client::{HttpClient, forward_request};
+
+/// A model as reported by LM Studio's native REST API.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpstreamModel {
+    pub id: String,
+    /// `loaded` or `not-loaded`
+    #[serde(default)]
+    pub state: Option<String>,
+    /// On-disk size, which approximates its memory footprint once loaded
+    #[serde(default)]
+    pub size_bytes: Option<i64>,
+}
+
+impl UpstreamModel {
+    pub fn is_loaded(&self) -> bool {
+        self.state.as_deref() == Some("loaded")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<UpstreamModel>,
+}
+
+async fn call<T: DeserializeOwned>(
+    client: &HttpClient,
+    lm_studio_url: &str,
+    method: hyper::Method,
+    path: &str,
+    body: String,
+) -> Result<T, ProxyError> {
+    let req = hyper::Request::builder()
+        .method(method)
+        .uri(path)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .map_err(|e| ProxyError::Http(e.to_string()))?;
+
+    let response = forward_request(client, req, lm_studio_url).await?;
+    let status = response.status();
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| ProxyError::Http(e.to_string()))?
+        .to_bytes();
+
+    if !status.is_success() {
+        return Err(ProxyError::Http(format!(
+            "LM Studio returned {} for {}: {}",
+            status,
+            path,
+            String::from_utf8_lossy(&bytes)
+        )));
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Lists every model LM Studio knows about, with its load state.
+pub async fn list_models(
+    client: &HttpClient,
+    lm_studio_url: &str,
+) -> Result<Vec<UpstreamModel>, ProxyError> {
+    let list: ModelList = call(
+        client,
+        lm_studio_url,
+        hyper::Method::GET,
+        "/api/v0/models",
+        String::new(),
+    )
+    .await?;
+    Ok(list.data)
+}
+
+/// Succeeds when LM Studio answers its OpenAI-compatible model list.
+pub async fn ping(client: &HttpClient, lm_studio_url: &str) -> Result<(), ProxyError> {
+    call::<serde_json::Value>(
+        client,
+        lm_studio_url,
+        hyper::Method::GET,
+        "/v1/models",
+        String::new(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Asks LM Studio to unload a loaded model.
+pub async fn unload_model(
+    client: &HttpClient,
+    lm_studio_url: &str,
+    model: &str,
+) -> Result<serde_json::Value, ProxyError> {
+    call(
+        client,
+        lm_studio_url,
+        hyper::Method::POST,
+        "/api/v1/models/unload",
+        serde_json::json!({ "instance_id": model }).to_string(),
+    )
+    .await
+}
@@ -1,5 +1,24 @@
+pub mod active;
+pub mod batch;
+pub mod body_parse;
 pub mod client;
+pub mod client_ip;
+pub mod deadline;
+pub mod guardrails;
 pub mod handler;
+pub mod lmstudio;
+pub mod pacing;
+pub mod probe;
+pub mod prompt_check;
+pub mod prompt_hash;
+pub mod prompt_version;
+pub mod routes;
+pub mod sampling;
+pub mod schema_check;
+pub mod sdk;
+pub mod sse;
+pub mod stops;
+pub mod stream_stats;
 
 pub use client::create_client;
 pub use handler::{proxy_handler, AppState};
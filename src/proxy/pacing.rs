@@ -0,0 +1,282 @@
+//! Optional pacing of streamed responses for consumers that choke on bursts, such as
+//! text-to-speech engines.
+//!
+//! The upstream stream is still read at full speed, so recorded output and timings are
+//! the real ones. Complete SSE events are queued and released to the client one per
+//! `1 / rate` seconds, treating each event as a token. Whatever is queued when upstream
+//! finishes is sent at once, as is anything past the buffer cap.
+
+use axum::http::HeaderMap;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::config::Config;
+use crate::error::ProxyError;
+
+/// Request header asking for a paced stream, in tokens (SSE events) per second
+pub const PACE_HEADER: &str = "x-proxy-pace-tokens-per-sec";
+
+/// Highest pace accepted; faster than this is no different from passthrough
+const MAX_TOKENS_PER_SEC: f64 = 1000.0;
+
+type Chunk = Result<Bytes, std::io::Error>;
+
+/// A requested pace. Set as a request extension once the header is validated.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamPace {
+    pub tokens_per_sec: f64,
+}
+
+impl StreamPace {
+    /// Reads `X-Proxy-Pace-Tokens-Per-Sec`, refusing it unless pacing is enabled.
+    pub fn from_headers(headers: &HeaderMap, config: &Config) -> Result<Option<Self>, ProxyError> {
+        let Some(value) = headers
+            .get(PACE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+        else {
+            return Ok(None);
+        };
+
+        if !config.stream_pacing {
+            return Err(ProxyError::BadRequest(
+                "X-Proxy-Pace-Tokens-Per-Sec requires STREAM_PACING=true".to_string(),
+            ));
+        }
+        let tokens_per_sec = value
+            .parse::<f64>()
+            .ok()
+            .filter(|rate| rate.is_finite() && *rate > 0.0 && *rate <= MAX_TOKENS_PER_SEC)
+            .ok_or_else(|| {
+                ProxyError::BadRequest(format!(
+                    "Invalid X-Proxy-Pace-Tokens-Per-Sec value: {}",
+                    value
+                ))
+            })?;
+        Ok(Some(Self { tokens_per_sec }))
+    }
+
+    /// Puts a pacer in front of `client`, returning the sender the stream reader writes to.
+    /// Dropping the client's receiver closes the returned sender too.
+    pub fn spawn(self, client: mpsc::Sender<Chunk>, buffer_bytes: usize) -> mpsc::Sender<Chunk> {
+        let (tx, rx) = mpsc::channel(100);
+        tokio::spawn(pace(rx, client, self.tokens_per_sec, buffer_bytes));
+        tx
+    }
+}
+
+async fn pace(
+    mut upstream: mpsc::Receiver<Chunk>,
+    client: mpsc::Sender<Chunk>,
+    tokens_per_sec: f64,
+    buffer_bytes: usize,
+) {
+    let interval = Duration::from_secs_f64(1.0 / tokens_per_sec);
+    let mut partial = Vec::new();
+    let mut queue: VecDeque<Bytes> = VecDeque::new();
+    let mut queued_bytes = 0;
+    let mut next_release = Instant::now();
+
+    loop {
+        tokio::select! {
+            chunk = upstream.recv() => match chunk {
+                Some(Ok(data)) => {
+                    partial.extend_from_slice(&data);
+                    while let Some(end) = partial.windows(2).position(|pair| pair == b"\n\n") {
+                        let event = Bytes::from(partial.drain(..end + 2).collect::<Vec<u8>>());
+                        queued_bytes += event.len();
+                        queue.push_back(event);
+                    }
+                    // Over the cap, fall behind the pace rather than buffer without bound
+                    while queued_bytes + partial.len() > buffer_bytes
+                        && let Some(event) = queue.pop_front()
+                    {
+                        queued_bytes -= event.len();
+                        if client.send(Ok(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                end => {
+                    // Upstream finished (or failed): flush everything at once
+                    queue.extend((!partial.is_empty()).then(|| Bytes::from(partial)));
+                    for event in queue {
+                        if client.send(Ok(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                    if let Some(Err(e)) = end {
+                        let _ = client.send(Err(e)).await;
+                    }
+                    return;
+                }
+            },
+            _ = tokio::time::sleep_until(next_release), if !queue.is_empty() => {
+                if let Some(event) = queue.pop_front() {
+                    queued_bytes -= event.len();
+                    if client.send(Ok(event)).await.is_err() {
+                        return;
+                    }
+                }
+                next_release = Instant::now() + interval;
+            }
+            // Stop pacing as soon as the client hangs up, so the reader notices
+            _ = client.closed() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: usize) -> String {
+        format!("data: {}\n\n", n)
+    }
+
+    /// Paces `chunks`, sent as fast as possible, and returns each event the client got
+    /// with when it arrived, measured from the start.
+    async fn paced(
+        chunks: Vec<String>,
+        tokens_per_sec: f64,
+        buffer_bytes: usize,
+    ) -> Vec<(String, Duration)> {
+        let (client, mut received) = mpsc::channel(100);
+        let tx = StreamPace { tokens_per_sec }.spawn(client, buffer_bytes);
+        let start = Instant::now();
+        tokio::spawn(async move {
+            for chunk in chunks {
+                tx.send(Ok(chunk.into())).await.unwrap();
+            }
+        });
+
+        let mut events = Vec::new();
+        while let Some(chunk) = received.recv().await {
+            events.push((text(chunk.unwrap()), start.elapsed()));
+        }
+        events
+    }
+
+    fn text(chunk: Bytes) -> String {
+        String::from_utf8(chunk.to_vec()).unwrap()
+    }
+
+    fn texts(events: &[(String, Duration)]) -> String {
+        events.iter().map(|(text, _)| text.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn events_are_released_in_order_at_the_pace() {
+        // Upstream stalls so the queue must be released one event at a time
+        let (client, mut received) = mpsc::channel(100);
+        let tx = StreamPace { tokens_per_sec: 20.0 }.spawn(client, 1 << 20);
+        let start = Instant::now();
+        let burst: String = (0..5).map(event).collect();
+        tx.send(Ok(burst.into())).await.unwrap();
+
+        let mut arrivals = Vec::new();
+        for n in 0..5 {
+            let chunk = received.recv().await.unwrap().unwrap();
+            assert_eq!(chunk, event(n));
+            arrivals.push(start.elapsed());
+        }
+        for pair in arrivals.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(40), "{:?}", arrivals);
+        }
+        drop(tx);
+        assert!(received.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn events_split_across_chunks_are_reassembled() {
+        let chunks = vec![
+            "data: {\"a\":".to_string(),
+            "1}\n".to_string(),
+            "\ndata: 2\n\nda".to_string(),
+            "ta: 3\n\n".to_string(),
+        ];
+        let events = paced(chunks, 1000.0, 1 << 20).await;
+
+        let received: Vec<_> = events.iter().map(|(text, _)| text.as_str()).collect();
+        assert_eq!(received, ["data: {\"a\":1}\n\n", "data: 2\n\n", "data: 3\n\n"]);
+    }
+
+    #[tokio::test]
+    async fn characters_split_across_chunks_pass_through_intact() {
+        let event = "data: {\"text\":\"caf\u{e9} \u{1f600}\"}\n\n".as_bytes();
+        let split = event.len() - 6;
+        let (client, mut received) = mpsc::channel(100);
+        let tx = StreamPace { tokens_per_sec: 1000.0 }.spawn(client, 1 << 20);
+        tx.send(Ok(Bytes::copy_from_slice(&event[..split]))).await.unwrap();
+        tx.send(Ok(Bytes::copy_from_slice(&event[split..]))).await.unwrap();
+        drop(tx);
+
+        assert_eq!(received.recv().await.unwrap().unwrap(), event);
+        assert!(received.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn end_of_stream_flushes_the_queue_at_once() {
+        let mut chunks: Vec<String> = (0..10).map(event).collect();
+        // A trailing partial event is still delivered
+        chunks.push("data: [DONE]".to_string());
+        let events = paced(chunks.clone(), 1.0, 1 << 20).await;
+
+        assert_eq!(texts(&events), chunks.concat());
+        assert!(events.last().unwrap().1 < Duration::from_millis(500), "{:?}", events);
+    }
+
+    #[tokio::test]
+    async fn buffer_cap_releases_events_early() {
+        let chunks: Vec<String> = (0..20).map(event).collect();
+        let cap = 3 * event(0).len();
+        let (client, mut received) = mpsc::channel(100);
+        let tx = StreamPace { tokens_per_sec: 1.0 }.spawn(client, cap);
+        for chunk in &chunks {
+            tx.send(Ok(chunk.clone().into())).await.unwrap();
+        }
+
+        // With upstream still open, only what fits under the cap is held back
+        let mut early = String::new();
+        while let Ok(Some(chunk)) =
+            tokio::time::timeout(Duration::from_millis(200), received.recv()).await
+        {
+            early.push_str(&text(chunk.unwrap()));
+        }
+        drop(tx);
+        let mut rest = String::new();
+        while let Some(chunk) = received.recv().await {
+            rest.push_str(&text(chunk.unwrap()));
+        }
+
+        assert!(!rest.is_empty() && rest.len() <= cap, "held back {:?}", rest);
+        assert_eq!(early + &rest, chunks.concat());
+    }
+
+    #[tokio::test]
+    async fn upstream_error_follows_the_flushed_events() {
+        let (client, mut received) = mpsc::channel(100);
+        let tx = StreamPace { tokens_per_sec: 1.0 }.spawn(client, 1 << 20);
+        tx.send(Ok((event(0) + &event(1)).into())).await.unwrap();
+        tx.send(Err(std::io::Error::other("reset"))).await.unwrap();
+
+        assert_eq!(received.recv().await.unwrap().unwrap(), event(0));
+        assert_eq!(received.recv().await.unwrap().unwrap(), event(1));
+        assert!(received.recv().await.unwrap().is_err());
+        assert!(received.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn client_hanging_up_closes_the_reader_side() {
+        let (client, received) = mpsc::channel(100);
+        let tx = StreamPace { tokens_per_sec: 1.0 }.spawn(client, 1 << 20);
+        drop(received);
+        tokio::time::timeout(Duration::from_secs(1), tx.closed())
+            .await
+            .expect("pacer stops once the client is gone");
+    }
+}
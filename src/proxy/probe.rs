@@ -0,0 +1,210 @@
+//! Recognizes the tiny warm-up completions client libraries and editor plugins send at
+//! startup to check that the server answers, so they can be kept out of latency and
+//! output length statistics. See [`ProbeConfig`] for the rule.
+
+use serde_json::Value;
+
+use crate::config::ProbeConfig;
+
+/// Whether a request with these `messages` (chat) or `prompt` (completions) and
+/// `max_tokens` is a warm-up probe. Requests carrying anything but text, such as images
+/// or tool calls, never are.
+pub fn is_probe(
+    config: &ProbeConfig,
+    messages: Option<&[Value]>,
+    prompt: Option<&str>,
+    max_tokens: Option<i64>,
+) -> bool {
+    if config.max_prompt_chars == 0 {
+        return false;
+    }
+    let text = match (messages, prompt) {
+        (Some(messages), _) => match messages_text(messages) {
+            Some(text) => text,
+            None => return false,
+        },
+        (None, Some(prompt)) => prompt.to_string(),
+        (None, None) => return false,
+    };
+    let text = normalize(&text);
+    if text.chars().count() > config.max_prompt_chars {
+        return false;
+    }
+    max_tokens.is_some_and(|max_tokens| max_tokens <= config.max_tokens)
+        || config.prompts.iter().any(|known| normalize(known) == text)
+}
+
+/// The text of every message joined by newlines; `None` if a message holds anything else.
+fn messages_text(messages: &[Value]) -> Option<String> {
+    let mut texts = Vec::new();
+    for message in messages {
+        let message = message.as_object()?;
+        if message.contains_key("tool_calls") || message.contains_key("function_call") {
+            return None;
+        }
+        match message.get("content") {
+            None | Some(Value::Null) => {}
+            Some(Value::String(text)) => texts.push(text.as_str()),
+            Some(Value::Array(parts)) => {
+                for part in parts {
+                    match part.get("type").and_then(Value::as_str) {
+                        Some("text") | None => texts.push(part.get("text")?.as_str()?),
+                        Some(_) => return None,
+                    }
+                }
+            }
+            Some(_) => return None,
+        }
+    }
+    Some(texts.join("\n"))
+}
+
+/// Lowercases and trims whitespace and punctuation, so `"Hi!"` matches `hi`.
+fn normalize(text: &str) -> String {
+    text.trim_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation())
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> ProbeConfig {
+        ProbeConfig {
+            max_prompt_chars: 32,
+            max_tokens: 5,
+            prompts: ["hi", "hello", "ping", "test", "say hello", "are you there"]
+                .map(str::to_string)
+                .to_vec(),
+        }
+    }
+
+    /// Chat bodies as client libraries send them, and whether each is a warm-up probe
+    fn chat_corpus() -> Vec<(Value, bool)> {
+        vec![
+            // Connection checks from editor plugins and SDK examples
+            (json!({"messages": [{"role": "user", "content": "Hi"}], "max_tokens": 1}), true),
+            (json!({"messages": [{"role": "user", "content": "hello"}]}), true),
+            (json!({"messages": [{"role": "user", "content": "Hello!"}]}), true),
+            (json!({"messages": [{"role": "user", "content": "  PING  "}]}), true),
+            (json!({"messages": [{"role": "user", "content": "Are you there?"}]}), true),
+            (json!({"messages": [{"role": "user", "content": "Say OK"}], "max_tokens": 5}), true),
+            (
+                json!({"messages": [
+                    {"role": "user", "content": [{"type": "text", "text": "test"}]}
+                ]}),
+                true,
+            ),
+            (
+                json!({"messages": [
+                    {"role": "system", "content": "Be brief."},
+                    {"role": "user", "content": "Reply with OK"}
+                ], "max_tokens": 2}),
+                true,
+            ),
+            // Short but real questions, without a tiny token limit
+            (json!({"messages": [{"role": "user", "content": "What is 2+2?"}]}), false),
+            (json!({"messages": [{"role": "user", "content": "hi there"}]}), false),
+            (
+                json!({"messages": [{"role": "user", "content": "Say OK"}], "max_tokens": 6}),
+                false,
+            ),
+            // A tiny limit on a prompt too long to be a ping
+            (
+                json!({"messages": [
+                    {"role": "user", "content": "Classify the sentiment of: I loved it"}
+                ], "max_tokens": 1}),
+                false,
+            ),
+            (
+                json!({"messages": [
+                    {"role": "system", "content": "You are a helpful assistant."},
+                    {"role": "user", "content": "hi"}
+                ]}),
+                false,
+            ),
+            // Images and tool calls are real work however short
+            (
+                json!({"messages": [{"role": "user", "content": [
+                    {"type": "text", "text": "hi"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,AA"}}
+                ]}], "max_tokens": 1}),
+                false,
+            ),
+            (
+                json!({"messages": [
+                    {"role": "user", "content": "hi"},
+                    {"role": "assistant", "content": null, "tool_calls": [{"id": "c1"}]}
+                ], "max_tokens": 1}),
+                false,
+            ),
+            (json!({"messages": [{"role": "user", "content": 42}], "max_tokens": 1}), false),
+            (json!({"messages": ["hi"], "max_tokens": 1}), false),
+        ]
+    }
+
+    fn classify(config: &ProbeConfig, body: &Value) -> bool {
+        let messages = body["messages"].as_array().map(Vec::as_slice);
+        is_probe(
+            config,
+            messages,
+            body["prompt"].as_str(),
+            body["max_tokens"].as_i64(),
+        )
+    }
+
+    #[test]
+    fn chat_requests_are_classified() {
+        let config = config();
+        for (body, expected) in chat_corpus() {
+            assert_eq!(classify(&config, &body), expected, "{}", body);
+        }
+    }
+
+    #[test]
+    fn completion_prompts_are_classified() {
+        let config = config();
+        let cases = [
+            (json!({"prompt": "ping"}), true),
+            (json!({"prompt": "Once upon a", "max_tokens": 1}), true),
+            (json!({"prompt": "Once upon a"}), false),
+            (json!({"prompt": "Once upon a time there was a small robot", "max_tokens": 1}), false),
+            (json!({"max_tokens": 1}), false),
+        ];
+        for (body, expected) in cases {
+            assert_eq!(classify(&config, &body), expected, "{}", body);
+        }
+    }
+
+    #[test]
+    fn a_zero_character_limit_turns_detection_off() {
+        let config = ProbeConfig {
+            max_prompt_chars: 0,
+            ..config()
+        };
+        for (body, _) in chat_corpus() {
+            assert!(!classify(&config, &body), "{}", body);
+        }
+    }
+
+    #[test]
+    fn known_prompts_still_need_to_fit_the_character_limit() {
+        let config = ProbeConfig {
+            max_prompt_chars: 4,
+            ..config()
+        };
+        let ping = json!({"messages": [{"role": "user", "content": "Ping!"}]});
+        assert!(classify(&config, &ping));
+        let greeting = json!({"messages": [{"role": "user", "content": "say hello"}]});
+        assert!(!classify(&config, &greeting));
+    }
+
+    #[test]
+    fn normalizing_trims_punctuation_and_case() {
+        assert_eq!(normalize("  Hi!! "), "hi");
+        assert_eq!(normalize("...Are you there?"), "are you there");
+        assert_eq!(normalize("¿Hola?"), "¿hola");
+        assert_eq!(normalize("!?"), "");
+    }
+}
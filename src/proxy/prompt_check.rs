@@ -0,0 +1,174 @@
+//! Diagnostics for chat message arrays that LM Studio accepts but that don't fit the
+//! shape chat templates expect. Requests are never changed or refused because of these.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const WARN_INVALID_MESSAGE: &str = "invalid_message";
+pub const WARN_MISSING_ROLE: &str = "missing_role";
+pub const WARN_UNKNOWN_ROLE: &str = "unknown_role";
+pub const WARN_EMPTY_CONTENT: &str = "empty_content";
+pub const WARN_MULTIPLE_SYSTEM: &str = "multiple_system";
+pub const WARN_SYSTEM_NOT_FIRST: &str = "system_not_first";
+pub const WARN_ALTERNATION: &str = "alternation";
+pub const WARN_OVERSIZED_MESSAGE: &str = "oversized_message";
+/// A schema violation, recorded when `SCHEMA_VALIDATION` is `warn`
+pub const WARN_SCHEMA: &str = "schema";
+
+pub const KNOWN_ROLES: &[&str] = &[
+    "system",
+    "developer",
+    "user",
+    "assistant",
+    "tool",
+    "function",
+];
+
+/// One problem found in the message array, or a schema violation anywhere in the body.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptWarning {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Index of the offending message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<usize>,
+    /// JSONPath of the offending value, for schema violations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+}
+
+impl PromptWarning {
+    fn new(kind: &str, message: usize) -> Self {
+        Self {
+            kind: kind.to_string(),
+            message: Some(message),
+            path: None,
+            problem: None,
+        }
+    }
+
+    pub fn schema(path: String, problem: String) -> Self {
+        Self {
+            kind: WARN_SCHEMA.to_string(),
+            message: None,
+            path: Some(path),
+            problem: Some(problem),
+        }
+    }
+}
+
+/// Checks a `messages` array, flagging single messages longer than `max_message_chars`.
+pub fn check_messages(messages: &[Value], max_message_chars: usize) -> Vec<PromptWarning> {
+    let mut warnings = Vec::new();
+    let mut seen_system = false;
+    let mut seen_other = false;
+    let mut previous_role: Option<&str> = None;
+
+    for (index, message) in messages.iter().enumerate() {
+        let Some(message) = message.as_object() else {
+            warnings.push(PromptWarning::new(WARN_INVALID_MESSAGE, index));
+            previous_role = None;
+            continue;
+        };
+
+        let role = message.get("role").and_then(Value::as_str);
+        match role {
+            None => warnings.push(PromptWarning::new(WARN_MISSING_ROLE, index)),
+            Some(role) if !KNOWN_ROLES.contains(&role) => {
+                warnings.push(PromptWarning::new(WARN_UNKNOWN_ROLE, index))
+            }
+            Some("system" | "developer") => {
+                if seen_system {
+                    warnings.push(PromptWarning::new(WARN_MULTIPLE_SYSTEM, index));
+                }
+                if seen_other {
+                    warnings.push(PromptWarning::new(WARN_SYSTEM_NOT_FIRST, index));
+                }
+                seen_system = true;
+            }
+            Some(_) => seen_other = true,
+        }
+
+        // Templates expect user and assistant turns to take turns
+        if let Some(role @ ("user" | "assistant")) = role
+            && previous_role == Some(role)
+        {
+            warnings.push(PromptWarning::new(WARN_ALTERNATION, index));
+        }
+        previous_role = role;
+
+        // An assistant turn that only calls tools legitimately has no content
+        let calls_tools =
+            message.contains_key("tool_calls") || message.contains_key("function_call");
+        let content = message.get("content").unwrap_or(&Value::Null);
+        if !calls_tools && is_empty_content(content) {
+            warnings.push(PromptWarning::new(WARN_EMPTY_CONTENT, index));
+        }
+        if text_chars(content) > max_message_chars {
+            warnings.push(PromptWarning::new(WARN_OVERSIZED_MESSAGE, index));
+        }
+    }
+
+    warnings
+}
+
+/// Formats warnings for the `X-Proxy-Prompt-Warnings` header as `type@index` entries, or
+/// `type@path` for schema violations.
+pub fn header_value(warnings: &[PromptWarning]) -> String {
+    warnings
+        .iter()
+        .map(|warning| match (&warning.path, warning.message) {
+            (Some(path), _) => format!("{}@{}", warning.kind, path),
+            (None, Some(message)) => format!("{}@{}", warning.kind, message),
+            (None, None) => warning.kind.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn to_json(warnings: &[PromptWarning]) -> Option<String> {
+    if warnings.is_empty() {
+        return None;
+    }
+    serde_json::to_string(warnings).ok()
+}
+
+pub fn from_json(json: Option<&str>) -> Vec<PromptWarning> {
+    json.and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+/// Content is empty when it has no text and no other parts. Multimodal parts (images,
+/// audio) count as content even without text.
+fn is_empty_content(content: &Value) -> bool {
+    match content {
+        Value::Null => true,
+        Value::String(text) => text.trim().is_empty(),
+        Value::Array(parts) => {
+            parts
+                .iter()
+                .all(|part| match part.get("type").and_then(Value::as_str) {
+                    Some("text") | None => part
+                        .get("text")
+                        .and_then(Value::as_str)
+                        .is_none_or(|text| text.trim().is_empty()),
+                    Some(_) => false,
+                })
+        }
+        _ => false,
+    }
+}
+
+fn text_chars(content: &Value) -> usize {
+    match content {
+        Value::String(text) => text.chars().count(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .map(|text| text.chars().count())
+            .sum(),
+        _ => 0,
+    }
+}
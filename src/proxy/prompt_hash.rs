@@ -0,0 +1,31 @@
+//! Hashes of the whole prompt with formatting normalized away, so `/stats/duplicates` can
+//! find clients sending the same prompt over and over.
+//!
+//! Unlike `prompt_hash`, the key of the stored prompt blob, this ignores how the JSON was
+//! laid out and how the text was wrapped: each message's role and text are taken with runs
+//! of whitespace collapsed to one space. Anything besides the text, such as images or tool
+//! calls, is left out.
+
+use serde_json::Value;
+
+use crate::db::blobs::content_hash;
+use crate::proxy::prompt_version::{collapse_whitespace, content_text};
+
+/// Hash of the normalized `messages`, or of `prompt` for completions; `None` when the body
+/// has neither or its text is empty.
+pub fn normalized_hash(messages: Option<&[Value]>, prompt: Option<&str>) -> Option<String> {
+    let text = match (messages, prompt) {
+        (Some(messages), _) => messages
+            .iter()
+            .map(|message| {
+                let role = message.get("role").and_then(Value::as_str).unwrap_or("");
+                let text = message.get("content").map(content_text).unwrap_or_default();
+                format!("{}: {}", role, collapse_whitespace(&text))
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        (None, Some(prompt)) => collapse_whitespace(prompt),
+        (None, None) => return None,
+    };
+    (!text.trim().is_empty()).then(|| content_hash(&text))
+}
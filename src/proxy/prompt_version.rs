@@ -0,0 +1,62 @@
+//! System prompt fingerprints, so usage can be compared across prompt revisions.
+//!
+//! The text of every `system` and `developer` message is normalized according to
+//! `PROMPT_VERSION_NORMALIZATION` and hashed. With the default `whitespace`, reflowing or
+//! re-indenting a prompt keeps its fingerprint while any change to the words gives a new
+//! one. Changing the setting changes every fingerprint from then on.
+
+use serde_json::Value;
+
+use crate::db::blobs::content_hash;
+
+/// Hex digits of the SHA-256 kept, enough to tell a person's prompt revisions apart
+const FINGERPRINT_LEN: usize = 16;
+
+/// Fingerprint of the system messages in `messages`, or `None` when there are none or
+/// they are empty after normalization.
+pub fn fingerprint(messages: &[Value], normalization: &str) -> Option<String> {
+    let system: Vec<String> = messages
+        .iter()
+        .filter(|message| {
+            matches!(
+                message.get("role").and_then(Value::as_str),
+                Some("system" | "developer")
+            )
+        })
+        .filter_map(|message| message.get("content").map(content_text))
+        .collect();
+
+    let text = normalize(&system.join("\n"), normalization);
+    if text.is_empty() {
+        return None;
+    }
+    let mut hash = content_hash(&text);
+    hash.truncate(FINGERPRINT_LEN);
+    Some(hash)
+}
+
+/// A message's text, from a plain string or the `text` of each content part.
+pub fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn normalize(text: &str, normalization: &str) -> String {
+    match normalization {
+        "exact" => text.to_string(),
+        "lowercase" => collapse_whitespace(text).to_lowercase(),
+        _ => collapse_whitespace(text),
+    }
+}
+
+/// Trims the text and turns every run of whitespace into a single space.
+pub fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,108 @@
+//! Which `/v1` requests the proxy answers itself and which go to LM Studio.
+//!
+//! The router sends every `/v1` path to the proxy handler, which resolves it here first,
+//! so local endpoints never compete with the catch-all for axum's route precedence.
+//! Anything without a local route is forwarded, and paths matching `FORWARD_PATHS` are
+//! forwarded even when they have one.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::Method,
+    response::{IntoResponse, Response},
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::config::model_pattern_matches;
+use crate::error::ProxyError;
+use crate::proxy::AppState;
+
+/// Endpoints served by the proxy itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Local {
+    SubmitBatch,
+}
+
+/// `(method, path, endpoint)`; paths match exactly
+const LOCAL_ROUTES: &[(Method, &str, Local)] = &[(Method::POST, "/v1/batches", Local::SubmitBatch)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dispatch {
+    Local(Local),
+    Forward,
+}
+
+/// Finds the local endpoint for the request, or forwarding when there is none or the
+/// path matches one of `forward_paths`.
+pub fn resolve(forward_paths: &[String], method: &Method, path: &str) -> Dispatch {
+    if forward_paths
+        .iter()
+        .any(|pattern| model_pattern_matches(pattern, path))
+    {
+        return Dispatch::Forward;
+    }
+
+    LOCAL_ROUTES
+        .iter()
+        .find(|(route_method, route_path, _)| route_method == method && *route_path == path)
+        .map_or(Dispatch::Forward, |(_, _, local)| Dispatch::Local(*local))
+}
+
+impl Local {
+    pub async fn serve(
+        self,
+        state: Arc<AppState>,
+        peer: SocketAddr,
+        req: Request,
+    ) -> Result<Response, ProxyError> {
+        match self {
+            Local::SubmitBatch => {
+                crate::proxy::batch::submit_batch(State(state), ConnectInfo(peer), req)
+                    .await
+                    .map(IntoResponse::into_response)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL_BATCH: Dispatch = Dispatch::Local(Local::SubmitBatch);
+
+    #[test]
+    fn precedence_table() {
+        let cases: &[(&[&str], Method, &str, Dispatch)] = &[
+            // Local routes match method and path exactly
+            (&[], Method::POST, "/v1/batches", LOCAL_BATCH),
+            (&[], Method::GET, "/v1/batches", Dispatch::Forward),
+            (&[], Method::POST, "/v1/batches/", Dispatch::Forward),
+            (&[], Method::POST, "/v1/batches/batch_1", Dispatch::Forward),
+            // Everything else goes upstream, known to LM Studio or not
+            (&[], Method::POST, "/v1/chat/completions", Dispatch::Forward),
+            (&[], Method::GET, "/v1/models", Dispatch::Forward),
+            (&[], Method::DELETE, "/v1/no/such/endpoint", Dispatch::Forward),
+            // Forced forwarding wins over a local route, by exact path or pattern
+            (&["/v1/batches"], Method::POST, "/v1/batches", Dispatch::Forward),
+            (&["/v1/batch*"], Method::POST, "/v1/batches", Dispatch::Forward),
+            (&["*"], Method::POST, "/v1/batches", Dispatch::Forward),
+            // and leaves local routes it doesn't match alone
+            (&["/v1/chat/*"], Method::POST, "/v1/batches", LOCAL_BATCH),
+            (&["/v1/batches/*"], Method::POST, "/v1/batches", LOCAL_BATCH),
+        ];
+
+        for (forward_paths, method, path, expected) in cases {
+            let forward_paths: Vec<String> =
+                forward_paths.iter().map(|pattern| pattern.to_string()).collect();
+            assert_eq!(
+                resolve(&forward_paths, method, path),
+                *expected,
+                "{} {} with FORWARD_PATHS={:?}",
+                method,
+                path,
+                forward_paths
+            );
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! The sampling parameters a request was forwarded with, for `/stats/params`.
+//!
+//! Some clients send numbers as strings (`"temperature": "0.7"`), which LM Studio
+//! accepts, so both forms are read. Anything else counts as leaving the parameter out.
+
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+/// Sampling parameters recorded in their own columns; `max_tokens` has one already
+#[derive(Debug, Default)]
+pub struct SamplingSettings {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub presence_penalty: Option<f64>,
+    pub frequency_penalty: Option<f64>,
+}
+
+/// Reads the parameters from a JSON request body; all `None` for bodies that aren't
+/// JSON objects.
+pub fn parse(body: &str) -> SamplingSettings {
+    let Ok(Value::Object(body)) = serde_json::from_str::<Value>(body) else {
+        return SamplingSettings::default();
+    };
+    let param = |key: &str| body.get(key).and_then(number);
+    SamplingSettings {
+        temperature: param("temperature"),
+        top_p: param("top_p"),
+        presence_penalty: param("presence_penalty"),
+        frequency_penalty: param("frequency_penalty"),
+    }
+}
+
+/// A finite number, or a string holding one.
+pub fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+    .filter(|number: &f64| number.is_finite())
+}
+
+/// Deserializes a whole number sent as a number or a string, such as `max_tokens` or
+/// `seed`, to `None` rather than failing on anything else, so one sloppy field doesn't
+/// lose the rest of the request.
+pub fn lenient_count<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    let value = Option::<Value>::deserialize(deserializer)?;
+    // Exact first, since seeds can be past what an f64 holds
+    let exact = match &value {
+        Some(Value::Number(number)) => number.as_i64(),
+        Some(Value::String(text)) => text.trim().parse().ok(),
+        _ => None,
+    };
+    Ok(exact.or_else(|| {
+        value
+            .as_ref()
+            .and_then(number)
+            .filter(|number| number.fract() == 0.0)
+            .map(|number| number as i64)
+    }))
+}
@@ -0,0 +1,570 @@
+//! Checks request bodies against the documented OpenAI request schemas, for client
+//! developers who want to hear about fields the real API would reject before LM Studio
+//! quietly accepts them. Only the shape is checked: unknown top-level fields, missing
+//! required fields, wrong JSON types and invalid message roles. Value ranges are left to
+//! the server.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::prompt_check::{KNOWN_ROLES, PromptWarning};
+
+/// One place where a body departs from the schema.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    /// JSONPath of the offending value, e.g. `$.messages[1].role`
+    pub path: String,
+    pub problem: String,
+}
+
+impl Violation {
+    pub fn new(path: String, problem: impl Into<String>) -> Self {
+        Self {
+            path,
+            problem: problem.into(),
+        }
+    }
+}
+
+impl From<Violation> for PromptWarning {
+    fn from(violation: Violation) -> Self {
+        PromptWarning::schema(violation.path, violation.problem)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Object,
+    Array,
+    StringOrArray,
+    StringOrObject,
+    OneOf(&'static [&'static str]),
+}
+
+impl Kind {
+    fn accepts(self, value: &Value) -> bool {
+        match self {
+            Kind::String => value.is_string(),
+            Kind::Number => value.is_number(),
+            Kind::Integer => value.is_i64() || value.is_u64(),
+            Kind::Boolean => value.is_boolean(),
+            Kind::Object => value.is_object(),
+            Kind::Array => value.is_array(),
+            Kind::StringOrArray => value.is_string() || value.is_array(),
+            Kind::StringOrObject => value.is_string() || value.is_object(),
+            Kind::OneOf(allowed) => value.as_str().is_some_and(|v| allowed.contains(&v)),
+        }
+    }
+
+    fn expected(self) -> String {
+        match self {
+            Kind::String => "a string".to_string(),
+            Kind::Number => "a number".to_string(),
+            Kind::Integer => "an integer".to_string(),
+            Kind::Boolean => "a boolean".to_string(),
+            Kind::Object => "an object".to_string(),
+            Kind::Array => "an array".to_string(),
+            Kind::StringOrArray => "a string or an array".to_string(),
+            Kind::StringOrObject => "a string or an object".to_string(),
+            Kind::OneOf(allowed) => format!("one of {}", allowed.join(", ")),
+        }
+    }
+}
+
+/// A field of a request object. Optional fields may also be `null`.
+struct Field {
+    name: &'static str,
+    kind: Kind,
+    required: bool,
+}
+
+const fn required(name: &'static str, kind: Kind) -> Field {
+    Field {
+        name,
+        kind,
+        required: true,
+    }
+}
+
+const fn optional(name: &'static str, kind: Kind) -> Field {
+    Field {
+        name,
+        kind,
+        required: false,
+    }
+}
+
+const CHAT_FIELDS: &[Field] = &[
+    required("model", Kind::String),
+    required("messages", Kind::Array),
+    optional("audio", Kind::Object),
+    optional("frequency_penalty", Kind::Number),
+    optional("function_call", Kind::StringOrObject),
+    optional("functions", Kind::Array),
+    optional("logit_bias", Kind::Object),
+    optional("logprobs", Kind::Boolean),
+    optional("max_completion_tokens", Kind::Integer),
+    optional("max_tokens", Kind::Integer),
+    optional("metadata", Kind::Object),
+    optional("modalities", Kind::Array),
+    optional("n", Kind::Integer),
+    optional("parallel_tool_calls", Kind::Boolean),
+    optional("prediction", Kind::Object),
+    optional("presence_penalty", Kind::Number),
+    optional("prompt_cache_key", Kind::String),
+    optional(
+        "reasoning_effort",
+        Kind::OneOf(&["minimal", "low", "medium", "high"]),
+    ),
+    optional("response_format", Kind::Object),
+    optional("safety_identifier", Kind::String),
+    optional("seed", Kind::Integer),
+    optional("service_tier", Kind::String),
+    optional("stop", Kind::StringOrArray),
+    optional("store", Kind::Boolean),
+    optional("stream", Kind::Boolean),
+    optional("stream_options", Kind::Object),
+    optional("temperature", Kind::Number),
+    optional("tool_choice", Kind::StringOrObject),
+    optional("tools", Kind::Array),
+    optional("top_logprobs", Kind::Integer),
+    optional("top_p", Kind::Number),
+    optional("user", Kind::String),
+    optional("verbosity", Kind::OneOf(&["low", "medium", "high"])),
+    optional("web_search_options", Kind::Object),
+];
+
+const COMPLETION_FIELDS: &[Field] = &[
+    required("model", Kind::String),
+    required("prompt", Kind::StringOrArray),
+    optional("best_of", Kind::Integer),
+    optional("echo", Kind::Boolean),
+    optional("frequency_penalty", Kind::Number),
+    optional("logit_bias", Kind::Object),
+    optional("logprobs", Kind::Integer),
+    optional("max_tokens", Kind::Integer),
+    optional("n", Kind::Integer),
+    optional("presence_penalty", Kind::Number),
+    optional("seed", Kind::Integer),
+    optional("stop", Kind::StringOrArray),
+    optional("stream", Kind::Boolean),
+    optional("stream_options", Kind::Object),
+    optional("suffix", Kind::String),
+    optional("temperature", Kind::Number),
+    optional("top_p", Kind::Number),
+    optional("user", Kind::String),
+];
+
+const EMBEDDING_FIELDS: &[Field] = &[
+    required("model", Kind::String),
+    required("input", Kind::StringOrArray),
+    optional("dimensions", Kind::Integer),
+    optional("encoding_format", Kind::OneOf(&["float", "base64"])),
+    optional("user", Kind::String),
+];
+
+const MESSAGE_FIELDS: &[Field] = &[
+    required("role", Kind::OneOf(KNOWN_ROLES)),
+    optional("content", Kind::StringOrArray),
+    optional("name", Kind::String),
+    optional("tool_calls", Kind::Array),
+    optional("tool_call_id", Kind::String),
+    optional("function_call", Kind::Object),
+    optional("refusal", Kind::String),
+    optional("audio", Kind::Object),
+];
+
+/// The request body of one endpoint.
+struct Schema {
+    fields: &'static [Field],
+    /// Check each entry of `messages` as a chat message
+    messages: bool,
+}
+
+/// The schema of the endpoint's request body, for the endpoints that have one here.
+fn schema_for(endpoint: &str) -> Option<Schema> {
+    let (fields, messages) = match endpoint.trim_end_matches('/') {
+        "/v1/chat/completions" => (CHAT_FIELDS, true),
+        "/v1/completions" => (COMPLETION_FIELDS, false),
+        "/v1/embeddings" => (EMBEDDING_FIELDS, false),
+        _ => return None,
+    };
+    Some(Schema { fields, messages })
+}
+
+/// Every violation in a request body for `endpoint`. `body` is `None` when the body wasn't
+/// JSON. Endpoints without a schema here have none.
+pub fn validate(endpoint: &str, body: Option<&Value>) -> Vec<Violation> {
+    let Some(schema) = schema_for(endpoint) else {
+        return Vec::new();
+    };
+    let Some(body) = body else {
+        return vec![Violation::new("$".to_string(), "body is not valid JSON")];
+    };
+    let Some(object) = body.as_object() else {
+        return vec![Violation::new(
+            "$".to_string(),
+            "body must be a JSON object",
+        )];
+    };
+
+    let mut violations = Vec::new();
+    check_object(object, schema.fields, "$", &mut violations);
+    if schema.messages
+        && let Some(messages) = object.get("messages").and_then(Value::as_array)
+    {
+        if messages.is_empty() {
+            violations.push(Violation::new(
+                "$.messages".to_string(),
+                "must contain at least one message",
+            ));
+        }
+        for (index, message) in messages.iter().enumerate() {
+            check_message(message, &format!("$.messages[{}]", index), &mut violations);
+        }
+    }
+    violations
+}
+
+/// Checks an object's fields against `fields`: unknown names, missing required ones and
+/// values of the wrong type.
+fn check_object(
+    object: &Map<String, Value>,
+    fields: &[Field],
+    path: &str,
+    violations: &mut Vec<Violation>,
+) {
+    for (name, value) in object {
+        let field_path = format!("{}.{}", path, name);
+        let Some(field) = fields.iter().find(|field| field.name == name) else {
+            violations.push(Violation::new(field_path, "unknown field"));
+            continue;
+        };
+        if value.is_null() && !field.required {
+            continue;
+        }
+        if !field.kind.accepts(value) {
+            let problem = match (field.kind, value.as_str()) {
+                (Kind::OneOf(_), Some(text)) => {
+                    format!("'{}' is not {}", text, field.kind.expected())
+                }
+                _ => format!(
+                    "expected {}, got {}",
+                    field.kind.expected(),
+                    describe(value)
+                ),
+            };
+            violations.push(Violation::new(field_path, problem));
+        }
+    }
+    for field in fields.iter().filter(|field| field.required) {
+        if !object.contains_key(field.name) {
+            violations.push(Violation::new(
+                format!("{}.{}", path, field.name),
+                "required field is missing",
+            ));
+        }
+    }
+}
+
+fn check_message(message: &Value, path: &str, violations: &mut Vec<Violation>) {
+    let Some(object) = message.as_object() else {
+        violations.push(Violation::new(
+            path.to_string(),
+            format!("expected an object, got {}", describe(message)),
+        ));
+        return;
+    };
+    check_object(object, MESSAGE_FIELDS, path, violations);
+
+    let role = object.get("role").and_then(Value::as_str);
+    // An assistant turn that only calls tools may leave out its content
+    let calls_tools = object.contains_key("tool_calls") || object.contains_key("function_call");
+    let needs_content = !(role == Some("assistant") && calls_tools);
+    if needs_content && object.get("content").is_none_or(Value::is_null) {
+        violations.push(Violation::new(
+            format!("{}.content", path),
+            "required field is missing",
+        ));
+    }
+    if role == Some("tool") && !object.contains_key("tool_call_id") {
+        violations.push(Violation::new(
+            format!("{}.tool_call_id", path),
+            "required for tool messages",
+        ));
+    }
+    if role == Some("function") && !object.contains_key("name") {
+        violations.push(Violation::new(
+            format!("{}.name", path),
+            "required for function messages",
+        ));
+    }
+
+    if let Some(parts) = object.get("content").and_then(Value::as_array) {
+        for (index, part) in parts.iter().enumerate() {
+            let part_path = format!("{}.content[{}]", path, index);
+            match part.get("type") {
+                Some(Value::String(kind)) if kind == "text" => {
+                    if !part.get("text").is_some_and(Value::is_string) {
+                        violations.push(Violation::new(
+                            format!("{}.text", part_path),
+                            "text parts need a string text",
+                        ));
+                    }
+                }
+                Some(Value::String(_)) => {}
+                _ if !part.is_object() => violations.push(Violation::new(
+                    part_path,
+                    format!("expected an object, got {}", describe(part)),
+                )),
+                _ => violations.push(Violation::new(
+                    format!("{}.type", part_path),
+                    "content parts need a string type",
+                )),
+            }
+        }
+    }
+}
+
+fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(number) if number.is_f64() => "a number",
+        Value::Number(_) => "an integer",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Formats violations for an error message as `path: problem` entries.
+pub fn summary(violations: &[Violation]) -> String {
+    violations
+        .iter()
+        .map(|violation| format!("{}: {}", violation.path, violation.problem))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const CHAT: &str = "/v1/chat/completions";
+    const COMPLETIONS: &str = "/v1/completions";
+    const EMBEDDINGS: &str = "/v1/embeddings";
+
+    /// An endpoint, a body for it and the `(path, problem)` pairs it should produce.
+    type Case = (&'static str, Value, &'static [(&'static str, &'static str)]);
+
+    #[test]
+    fn valid_bodies_pass() {
+        let corpus = [
+            (CHAT, json!({"model": "m", "messages": [{"role": "user", "content": "Hi"}]})),
+            (
+                "/v1/chat/completions/",
+                json!({"model": "m", "messages": [{"role": "user", "content": "Hi"}]}),
+            ),
+            (
+                CHAT,
+                json!({
+                    "model": "m",
+                    "messages": [
+                        {"role": "system", "content": "Be brief"},
+                        {"role": "developer", "content": "No emoji"},
+                        {"role": "user", "name": "ann", "content": [
+                            {"type": "text", "text": "What is this?"},
+                            {"type": "image_url", "image_url": {"url": "data:..."}}
+                        ]},
+                        {"role": "assistant", "content": null, "tool_calls": [{"id": "c1"}]},
+                        {"role": "tool", "tool_call_id": "c1", "content": "42"},
+                        {"role": "assistant", "function_call": {"name": "f"}},
+                        {"role": "function", "name": "f", "content": "ok"}
+                    ],
+                    "temperature": 0.2,
+                    "top_p": 1,
+                    "max_tokens": 100,
+                    "max_completion_tokens": null,
+                    "stop": ["\n"],
+                    "stream": true,
+                    "stream_options": {"include_usage": true},
+                    "tool_choice": "auto",
+                    "response_format": {"type": "json_object"},
+                    "reasoning_effort": "low",
+                    "seed": -1,
+                    "user": "u1"
+                }),
+            ),
+            (COMPLETIONS, json!({"model": "m", "prompt": "Once"})),
+            (
+                COMPLETIONS,
+                json!({"model": "m", "prompt": ["a", "b"], "echo": false, "logprobs": 2}),
+            ),
+            (EMBEDDINGS, json!({"model": "m", "input": "text"})),
+            (
+                EMBEDDINGS,
+                json!({"model": "m", "input": [[1, 2]], "encoding_format": "base64"}),
+            ),
+            // Endpoints without a schema accept anything
+            ("/v1/models", json!({"whatever": true})),
+            ("/api/v0/chat/completions", json!([])),
+        ];
+        for (endpoint, body) in corpus {
+            assert_eq!(validate(endpoint, Some(&body)), [], "{} {}", endpoint, body);
+        }
+        assert_eq!(validate("/v1/models", None), []);
+    }
+
+    #[test]
+    fn invalid_bodies_list_every_violation() {
+        let corpus: Vec<Case> = vec![
+            (
+                CHAT,
+                json!({}),
+                &[
+                    ("$.model", "required field is missing"),
+                    ("$.messages", "required field is missing"),
+                ],
+            ),
+            (
+                CHAT,
+                json!({"model": "m", "messages": [], "temprature": 0.5}),
+                &[
+                    ("$.temprature", "unknown field"),
+                    ("$.messages", "must contain at least one message"),
+                ],
+            ),
+            (
+                CHAT,
+                json!({
+                    "model": 7,
+                    "messages": [{"role": "user", "content": "Hi"}],
+                    "max_tokens": 1.5,
+                    "stream": "yes",
+                    "stop": 3,
+                    "reasoning_effort": "extreme",
+                    "verbosity": 2
+                }),
+                // In the body's own order
+                &[
+                    ("$.model", "expected a string, got an integer"),
+                    ("$.max_tokens", "expected an integer, got a number"),
+                    ("$.stream", "expected a boolean, got a string"),
+                    ("$.stop", "expected a string or an array, got an integer"),
+                    ("$.reasoning_effort", "'extreme' is not one of minimal, low, medium, high"),
+                    ("$.verbosity", "expected one of low, medium, high, got an integer"),
+                ],
+            ),
+            // A required field may not be null
+            (
+                CHAT,
+                json!({"model": null, "messages": [{"role": "user", "content": "Hi"}]}),
+                &[("$.model", "expected a string, got null")],
+            ),
+            (
+                CHAT,
+                json!({"model": "m", "messages": [
+                    "Hi",
+                    {"role": "robot", "content": "beep"},
+                    {"content": "who?"},
+                    {"role": "user"},
+                    {"role": "assistant", "content": null},
+                    {"role": "tool", "content": "42"},
+                    {"role": "function", "content": "ok"},
+                    {"role": "user", "content": "Hi", "mood": "calm"}
+                ]}),
+                &[
+                    ("$.messages[0]", "expected an object, got a string"),
+                    (
+                        "$.messages[1].role",
+                        "'robot' is not one of system, developer, user, assistant, tool, \
+                         function",
+                    ),
+                    ("$.messages[2].role", "required field is missing"),
+                    ("$.messages[3].content", "required field is missing"),
+                    ("$.messages[4].content", "required field is missing"),
+                    ("$.messages[5].tool_call_id", "required for tool messages"),
+                    ("$.messages[6].name", "required for function messages"),
+                    ("$.messages[7].mood", "unknown field"),
+                ],
+            ),
+            (
+                CHAT,
+                json!({"model": "m", "messages": [{"role": "user", "content": [
+                    {"type": "text", "text": "ok"},
+                    {"type": "text"},
+                    {"text": "untyped"},
+                    "loose",
+                    {"type": 1}
+                ]}]}),
+                &[
+                    ("$.messages[0].content[1].text", "text parts need a string text"),
+                    ("$.messages[0].content[2].type", "content parts need a string type"),
+                    ("$.messages[0].content[3]", "expected an object, got a string"),
+                    ("$.messages[0].content[4].type", "content parts need a string type"),
+                ],
+            ),
+            // A messages value of the wrong type isn't walked as messages
+            (
+                CHAT,
+                json!({"model": "m", "messages": "Hi"}),
+                &[("$.messages", "expected an array, got a string")],
+            ),
+            (
+                COMPLETIONS,
+                json!({"model": "m", "messages": [], "best_of": "2"}),
+                &[
+                    ("$.messages", "unknown field"),
+                    ("$.best_of", "expected an integer, got a string"),
+                    ("$.prompt", "required field is missing"),
+                ],
+            ),
+            (
+                EMBEDDINGS,
+                json!({"model": "m", "input": {"text": "a"}, "encoding_format": "int8"}),
+                &[
+                    ("$.input", "expected a string or an array, got an object"),
+                    ("$.encoding_format", "'int8' is not one of float, base64"),
+                ],
+            ),
+            (CHAT, json!([1, 2]), &[("$", "body must be a JSON object")]),
+            (EMBEDDINGS, json!("text"), &[("$", "body must be a JSON object")]),
+        ];
+        for (endpoint, body, expected) in corpus {
+            let found: Vec<(String, String)> = validate(endpoint, Some(&body))
+                .into_iter()
+                .map(|violation| (violation.path, violation.problem))
+                .collect();
+            let expected: Vec<(String, String)> = expected
+                .iter()
+                .map(|(path, problem)| (path.to_string(), problem.to_string()))
+                .collect();
+            assert_eq!(found, expected, "{} {}", endpoint, body);
+        }
+    }
+
+    #[test]
+    fn bodies_that_are_not_json_are_one_violation() {
+        assert_eq!(
+            validate(CHAT, None),
+            [Violation::new("$".to_string(), "body is not valid JSON")]
+        );
+    }
+
+    #[test]
+    fn summary_joins_paths_and_problems() {
+        let violations = validate(EMBEDDINGS, Some(&json!({"input": 1})));
+        assert_eq!(
+            summary(&violations),
+            "$.input: expected a string or an array, got an integer; \
+             $.model: required field is missing"
+        );
+        assert_eq!(summary(&[]), "");
+    }
+}
@@ -0,0 +1,167 @@
+/// Client library identified from a request's User-Agent header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SdkFingerprint {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl SdkFingerprint {
+    fn new(name: &str, version: Option<&str>) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.filter(|v| !v.is_empty()).map(|v| v.to_string()),
+        }
+    }
+
+    pub fn other() -> Self {
+        Self::new("other", None)
+    }
+
+    /// Checks this fingerprint against a `name` or `name/version` pattern.
+    /// A version pattern matches the exact version or any version nested
+    /// under it, so `openai-python/1.2` matches `1.2` and `1.2.3`.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let (name, version) = match pattern.split_once('/') {
+            Some((name, version)) => (name, Some(version)),
+            None => (pattern, None),
+        };
+
+        if !self.name.eq_ignore_ascii_case(name.trim()) {
+            return false;
+        }
+
+        match (version.map(str::trim), &self.version) {
+            (None, _) => true,
+            (Some(wanted), Some(actual)) => {
+                actual == wanted
+                    || actual
+                        .strip_prefix(wanted)
+                        .is_some_and(|rest| rest.starts_with('.'))
+            }
+            (Some(_), None) => false,
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{}/{}", self.name, version),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Parses a User-Agent string into a structured SDK fingerprint.
+///
+/// Wrapper libraries are checked before the OpenAI SDKs because they
+/// commonly append their own product token to the SDK's agent string.
+pub fn fingerprint(user_agent: &str) -> SdkFingerprint {
+    let tokens: Vec<&str> = user_agent.split_whitespace().collect();
+
+    for (index, token) in tokens.iter().enumerate() {
+        let (product, version) = split_product(token);
+        let product = product.to_ascii_lowercase();
+
+        if product == "litellm" {
+            return SdkFingerprint::new("litellm", version);
+        }
+        if product.starts_with("langchain") {
+            return SdkFingerprint::new("langchain", version.or_else(|| next_version(&tokens, index)));
+        }
+    }
+
+    for (index, token) in tokens.iter().enumerate() {
+        let (product, version) = split_product(token);
+
+        // The official SDKs send e.g. `OpenAI/Python 1.30.1` and `OpenAI/JS 4.47.1`,
+        // so the version lives in the following token. The Python SDK's async client
+        // names itself `AsyncOpenAI`.
+        if product.eq_ignore_ascii_case("openai") || product.eq_ignore_ascii_case("asyncopenai") {
+            match version.map(str::to_ascii_lowercase).as_deref() {
+                Some("python") => {
+                    return SdkFingerprint::new("openai-python", next_version(&tokens, index));
+                }
+                Some("js") | Some("node") => {
+                    return SdkFingerprint::new("openai-node", next_version(&tokens, index));
+                }
+                _ => {}
+            }
+        }
+        if product.eq_ignore_ascii_case("curl") {
+            return SdkFingerprint::new("curl", version);
+        }
+    }
+
+    SdkFingerprint::other()
+}
+
+fn split_product(token: &str) -> (&str, Option<&str>) {
+    match token.split_once('/') {
+        Some((product, version)) => (product, Some(version)),
+        None => (token, None),
+    }
+}
+
+fn next_version<'a>(tokens: &[&'a str], index: usize) -> Option<&'a str> {
+    tokens
+        .get(index + 1)
+        .copied()
+        .filter(|token| token.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// User-Agent strings as sent by the clients, and what they should be read as
+    const CORPUS: &[(&str, &str, Option<&str>)] = &[
+        ("OpenAI/Python 1.30.1", "openai-python", Some("1.30.1")),
+        ("AsyncOpenAI/Python 1.35.10", "openai-python", Some("1.35.10")),
+        ("OpenAI/JS 4.47.1", "openai-node", Some("4.47.1")),
+        ("OpenAI/Node 3.3.0", "openai-node", Some("3.3.0")),
+        ("curl/8.4.0", "curl", Some("8.4.0")),
+        ("curl/7.81.0", "curl", Some("7.81.0")),
+        ("litellm/1.40.2", "litellm", Some("1.40.2")),
+        ("OpenAI/Python 1.30.1 litellm/1.40.2", "litellm", Some("1.40.2")),
+        ("langchain-openai/0.1.8", "langchain", Some("0.1.8")),
+        ("OpenAI/JS 4.20.1 langchainjs 0.1.2", "langchain", Some("0.1.2")),
+        ("OpenAI/Python", "openai-python", None),
+        (
+            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 \
+             (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+            "other",
+            None,
+        ),
+        ("python-requests/2.31.0", "other", None),
+        ("python-httpx/0.27.0", "other", None),
+        ("Go-http-client/1.1", "other", None),
+        ("", "other", None),
+    ];
+
+    #[test]
+    fn fingerprints_real_user_agents() {
+        for &(user_agent, name, version) in CORPUS {
+            let found = fingerprint(user_agent);
+            assert_eq!(found.name, name, "{}", user_agent);
+            assert_eq!(found.version.as_deref(), version, "{}", user_agent);
+        }
+    }
+
+    #[test]
+    fn patterns_match_names_and_nested_versions() {
+        let sdk = fingerprint("OpenAI/Python 1.2.3");
+        assert!(sdk.matches("openai-python"));
+        assert!(sdk.matches("OpenAI-Python"));
+        assert!(sdk.matches("openai-python/1.2"));
+        assert!(sdk.matches("openai-python/1.2.3"));
+        assert!(!sdk.matches("openai-python/1.23"));
+        assert!(!sdk.matches("openai-python/1.2.3.4"));
+        assert!(!sdk.matches("openai-node"));
+        assert!(!fingerprint("OpenAI/Python").matches("openai-python/1"));
+    }
+
+    #[test]
+    fn labels_include_the_version_when_known() {
+        assert_eq!(fingerprint("curl/8.4.0").label(), "curl/8.4.0");
+        assert_eq!(fingerprint("Go-http-client/1.1").label(), "other");
+    }
+}
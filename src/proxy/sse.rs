@@ -0,0 +1,141 @@
+//! Line framing for upstream SSE streams, and the data lines that fail to parse.
+//!
+//! Network frames don't line up with SSE events, so a `data:` line can arrive in
+//! pieces. Lines are only parsed once complete; a line that still isn't valid JSON is
+//! counted and a few are kept on the record, since some upstream builds emit chunks with
+//! unescaped newlines inside strings and their content never reaches the stored output.
+
+use serde_json::json;
+
+use crate::db::RequestRecord;
+
+/// Failed lines kept per stream
+const MAX_SAMPLES: usize = 3;
+/// Longest sample kept, in bytes
+const MAX_SAMPLE_BYTES: usize = 512;
+
+/// Takes every complete line out of `pending`, leaving a trailing partial line behind.
+/// Bytes are only decoded once their line is complete, so a character split between
+/// frames comes through whole.
+pub fn take_lines(pending: &mut Vec<u8>) -> Vec<String> {
+    let Some(end) = pending.iter().rposition(|&byte| byte == b'\n') else {
+        return Vec::new();
+    };
+    let rest = pending.split_off(end + 1);
+    let complete = std::mem::replace(pending, rest);
+    String::from_utf8_lossy(&complete)
+        .lines()
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .collect()
+}
+
+/// Data lines of one stream that weren't valid JSON.
+#[derive(Debug, Default)]
+pub struct ParseFailures {
+    count: i64,
+    samples: Vec<String>,
+}
+
+impl ParseFailures {
+    pub fn count(&self) -> i64 {
+        self.count
+    }
+
+    pub fn record(&mut self, line: &str) {
+        self.count += 1;
+        if self.samples.len() < MAX_SAMPLES {
+            let end = (0..=line.len().min(MAX_SAMPLE_BYTES))
+                .rev()
+                .find(|&i| line.is_char_boundary(i))
+                .unwrap_or(0);
+            self.samples.push(line[..end].to_string());
+        }
+    }
+
+    /// Stores the count on the record, and the samples when there are any.
+    pub fn apply(self, record: &mut RequestRecord) {
+        record.stream_parse_errors = Some(self.count);
+        if !self.samples.is_empty() {
+            record.stream_parse_samples = Some(json!(self.samples));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(text: &str) -> Vec<u8> {
+        text.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn complete_lines_are_taken_and_the_partial_one_kept() {
+        let mut buffer = pending("data: 1\n\ndata: 2\r\ndata: {\"a\":");
+        assert_eq!(take_lines(&mut buffer), ["data: 1", "", "data: 2"]);
+        assert_eq!(buffer, b"data: {\"a\":");
+
+        assert!(take_lines(&mut buffer).is_empty());
+        buffer.extend_from_slice(b"1}\n\n");
+        assert_eq!(take_lines(&mut buffer), ["data: {\"a\":1}", ""]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn characters_split_between_frames_come_through_whole() {
+        let line = "data: {\"content\":\"caf\u{e9} \u{1f600}\"}\n".as_bytes();
+        let mut buffer = Vec::new();
+        let mut lines = Vec::new();
+        // One byte per frame splits both the two- and the four-byte character
+        for byte in line {
+            buffer.push(*byte);
+            lines.extend(take_lines(&mut buffer));
+        }
+        assert_eq!(lines, ["data: {\"content\":\"caf\u{e9} \u{1f600}\"}"]);
+    }
+
+    #[test]
+    fn failures_are_all_counted_and_a_few_sampled() {
+        let mut failures = ParseFailures::default();
+        for n in 0..5 {
+            failures.record(&format!("data: {{broken {}", n));
+        }
+        assert_eq!(failures.count(), 5);
+
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            chrono::Utc::now(),
+            String::new(),
+        );
+        failures.apply(&mut record);
+        assert_eq!(record.stream_parse_errors, Some(5));
+        assert_eq!(
+            record.stream_parse_samples,
+            Some(json!(["data: {broken 0", "data: {broken 1", "data: {broken 2"]))
+        );
+    }
+
+    #[test]
+    fn long_samples_are_cut_on_a_character_boundary() {
+        let line = format!("data: {}", "\u{20ac}".repeat(400));
+        let mut failures = ParseFailures::default();
+        failures.record(&line);
+        let sample = &failures.samples[0];
+        assert!(sample.len() <= MAX_SAMPLE_BYTES && sample.len() > MAX_SAMPLE_BYTES - 3);
+        assert!(line.starts_with(sample.as_str()));
+    }
+
+    #[test]
+    fn clean_streams_record_a_zero_count_and_no_samples() {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            chrono::Utc::now(),
+            String::new(),
+        );
+        ParseFailures::default().apply(&mut record);
+        assert_eq!(record.stream_parse_errors, Some(0));
+        assert_eq!(record.stream_parse_samples, None);
+    }
+}
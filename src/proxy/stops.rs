@@ -0,0 +1,50 @@
+//! Custom stop sequences (`stop`) and whether they ended a generation.
+//!
+//! Backends usually strip the matched stop sequence from the output, so a `stop` finish
+//! alone can't tell a custom stop from the model's own end of sequence. The backend's
+//! own hint is used when it sends one: LM Studio's `stats.stop_reason`
+//! (`stopStringFound` / `eosFound`), or a per-choice `stop_reason` naming the matched
+//! string. Without a hint, the raw output (the streamed deltas as received) is checked
+//! for ending in one of the sequences, which catches backends that leave it in.
+
+use serde_json::Value;
+
+/// The request's stop sequences, from a `stop` string or array of strings.
+pub fn parse(stop: Option<&Value>) -> Vec<String> {
+    match stop {
+        Some(Value::String(stop)) => vec![stop.clone()],
+        Some(Value::Array(stops)) => stops
+            .iter()
+            .filter_map(|stop| stop.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Best guess whether a finished generation was ended by one of `stops`. `None` when
+/// there were no stop sequences or the finish reason isn't `stop`.
+pub fn stopped_by_custom_stop(
+    stops: &[String],
+    finish_reason: Option<&str>,
+    output: &str,
+    stop_reason: Option<&Value>,
+) -> Option<bool> {
+    if stops.is_empty() || finish_reason != Some("stop") {
+        return None;
+    }
+    match stop_reason {
+        Some(Value::String(reason)) if reason == "stopStringFound" => return Some(true),
+        Some(Value::String(reason)) if reason == "eosFound" => return Some(false),
+        Some(Value::String(matched)) => return Some(stops.contains(matched)),
+        // A stop token id rather than one of the strings
+        Some(Value::Number(_)) => return Some(false),
+        _ => {}
+    }
+    // Ignore whitespace the backend may have trimmed after the sequence
+    let trimmed = output.trim_end();
+    Some(stops.iter().any(|stop| {
+        let stop_trimmed = stop.trim_end();
+        (!stop.is_empty() && output.ends_with(stop.as_str()))
+            || (!stop_trimmed.is_empty() && trimmed.ends_with(stop_trimmed))
+    }))
+}
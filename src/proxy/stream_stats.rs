@@ -0,0 +1,175 @@
+//! An opt-in closing event on streamed responses carrying the proxy's own measurements,
+//! for clients that want to show them without querying the stats API.
+//!
+//! The event is named `proxy_stats` and sent after everything upstream sent, `[DONE]`
+//! included. Clients stop at `[DONE]` or skip named events they don't know, so strict
+//! OpenAI clients never mistake it for a completion chunk. It is not part of the stored
+//! output or the raw stream an incident captures.
+
+use axum::http::HeaderMap;
+use serde_json::json;
+
+use crate::config::Price;
+use crate::db::RequestRecord;
+use crate::error::ProxyError;
+
+/// Request header asking for the closing stats event
+pub const APPEND_STATS_HEADER: &str = "x-proxy-append-stats";
+
+/// SSE event name of the closing stats event
+const EVENT_NAME: &str = "proxy_stats";
+
+/// Set as a request extension on streams that asked for the closing stats event.
+#[derive(Debug, Clone, Copy)]
+pub struct AppendStats;
+
+/// Reads `X-Proxy-Append-Stats`, refusing it unless `enabled` by `STREAM_STATS_EVENT`.
+pub fn requested(headers: &HeaderMap, enabled: bool) -> Result<bool, ProxyError> {
+    let Some(value) = headers
+        .get(APPEND_STATS_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+    else {
+        return Ok(false);
+    };
+
+    if !enabled {
+        return Err(ProxyError::BadRequest(
+            "X-Proxy-Append-Stats requires STREAM_STATS_EVENT=true".to_string(),
+        ));
+    }
+    match value {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(ProxyError::BadRequest(format!(
+            "Invalid X-Proxy-Append-Stats value: {}",
+            value
+        ))),
+    }
+}
+
+/// The SSE event for a completed stream. Output tokens fall back to the running estimate
+/// when upstream sent no usage, which `tokens_estimated` flags.
+pub fn event(record: &RequestRecord, price: Option<Price>) -> String {
+    let output_tokens = if record.tokens_estimated {
+        record.estimated_output_tokens.unwrap_or(record.output_tokens)
+    } else {
+        record.output_tokens
+    };
+    let estimated_cost = price.map(|price| {
+        (record.input_tokens as f64 * price.input_per_m + output_tokens as f64 * price.output_per_m)
+            / 1e6
+    });
+    let payload = json!({
+        "proxy_request_id": record.proxy_request_id,
+        "model": record.model,
+        "input_tokens": record.input_tokens,
+        "output_tokens": output_tokens,
+        "total_tokens": record.input_tokens + output_tokens,
+        "tokens_estimated": record.tokens_estimated,
+        "tokens_per_second": record.tokens_per_second,
+        "ttft_ms": record.ttft_ms,
+        "duration_ms": record.duration_ms,
+        "estimated_cost": estimated_cost,
+    });
+    format!("event: {}\ndata: {}\n\n", EVENT_NAME, payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::Value;
+
+    fn headers(value: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = value {
+            headers.insert(APPEND_STATS_HEADER, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn the_header_is_read_only_when_enabled() {
+        let cases = [
+            (None, true, Ok(false)),
+            (None, false, Ok(false)),
+            (Some(""), false, Ok(false)),
+            (Some("1"), true, Ok(true)),
+            (Some(" true "), true, Ok(true)),
+            (Some("0"), true, Ok(false)),
+            (Some("false"), true, Ok(false)),
+            (Some("yes"), true, Err("Invalid X-Proxy-Append-Stats value: yes")),
+            (Some("1"), false, Err("X-Proxy-Append-Stats requires STREAM_STATS_EVENT=true")),
+            (Some("0"), false, Err("X-Proxy-Append-Stats requires STREAM_STATS_EVENT=true")),
+        ];
+        for (value, enabled, expected) in cases {
+            let found = requested(&headers(value), enabled).map_err(|e| match e {
+                ProxyError::BadRequest(message) => message,
+                other => panic!("{:?}", other),
+            });
+            assert_eq!(found, expected.map_err(str::to_string), "{:?} {}", value, enabled);
+        }
+    }
+
+    fn completed() -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            Utc::now(),
+            "Hi".to_string(),
+        );
+        record.input_tokens = 1_000;
+        record.output_tokens = 500;
+        record.tokens_per_second = Some(25.0);
+        record.ttft_ms = Some(120);
+        record.duration_ms = 20_120;
+        record
+    }
+
+    /// The name and JSON payload of an event, which must be one complete SSE event.
+    fn parse(event: &str) -> (String, Value) {
+        let body = event.strip_suffix("\n\n").expect("ends the event");
+        let (name, data) = body.split_once('\n').expect("two lines");
+        let name = name.strip_prefix("event: ").expect("a named event");
+        let data = data.strip_prefix("data: ").expect("one data line");
+        (name.to_string(), serde_json::from_str(data).unwrap())
+    }
+
+    #[test]
+    fn the_event_carries_the_measurements_and_cost() {
+        let record = completed();
+        let price = Price {
+            input_per_m: 2.0,
+            output_per_m: 10.0,
+        };
+        let (name, payload) = parse(&event(&record, Some(price)));
+        assert_eq!(name, "proxy_stats");
+        assert_eq!(payload["proxy_request_id"], record.proxy_request_id.unwrap());
+        assert_eq!(payload["model"], "m");
+        assert_eq!(payload["input_tokens"], 1_000);
+        assert_eq!(payload["output_tokens"], 500);
+        assert_eq!(payload["total_tokens"], 1_500);
+        assert_eq!(payload["tokens_estimated"], false);
+        assert_eq!(payload["tokens_per_second"], 25.0);
+        assert_eq!(payload["ttft_ms"], 120);
+        assert_eq!(payload["duration_ms"], 20_120);
+        assert_eq!(payload["estimated_cost"], 0.007);
+    }
+
+    #[test]
+    fn unreported_usage_falls_back_to_the_estimate() {
+        let mut record = completed();
+        record.output_tokens = 0;
+        record.tokens_estimated = true;
+        record.estimated_output_tokens = Some(42);
+        record.ttft_ms = None;
+        let (_, payload) = parse(&event(&record, None));
+        assert_eq!(payload["output_tokens"], 42);
+        assert_eq!(payload["total_tokens"], 1_042);
+        assert_eq!(payload["tokens_estimated"], true);
+        assert_eq!(payload["ttft_ms"], Value::Null);
+        assert_eq!(payload["estimated_cost"], Value::Null);
+    }
+}
@@ -0,0 +1,184 @@
+//! Fixed-size in-memory copy of the latest completed requests.
+//!
+//! Filled in as requests finish, whether or not they reached the database, so recent
+//! traffic can still be shown while the database is slow or unavailable.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::db::RequestRecord;
+use crate::db::models::{RecentFilter, RecentRequest};
+
+pub struct RecentRing {
+    capacity: usize,
+    entries: Mutex<VecDeque<RecentRequest>>,
+}
+
+impl RecentRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Remembers a completed request; `id` is its row id if it was stored.
+    pub fn record(&self, record: &RequestRecord, id: Option<i64>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        // Build the summary before locking so writers only contend for the push
+        let summary = RecentRequest {
+            id,
+            proxy_request_id: record.proxy_request_id.clone(),
+            request_id: record.request_id.clone(),
+            endpoint: record.endpoint.clone(),
+            model: record.model.clone(),
+            start_time: record.start_time.clone(),
+            end_time: record.end_time.clone(),
+            duration_ms: record.duration_ms,
+            input_tokens: record.input_tokens,
+            output_tokens: record.output_tokens,
+            total_tokens: record.total_tokens,
+            is_error: record.is_error,
+            http_status: record.http_status,
+            was_streamed: record.was_streamed,
+        };
+
+        let mut entries = self.lock();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(summary);
+    }
+
+    /// Up to `limit` requests matching `filter`, newest first. With `before_id`, only
+    /// stored requests with a smaller id.
+    pub fn latest(&self, limit: usize, filter: &RecentFilter) -> Vec<RecentRequest> {
+        self.lock()
+            .iter()
+            .rev()
+            .filter(|request| filter.matches(request))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<RecentRequest>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::sync::Arc;
+
+    fn completed(model: &str, is_error: bool) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            model.to_string(),
+            Utc::now(),
+            "Tell me a story".to_string(),
+        );
+        record.is_error = is_error;
+        record
+    }
+
+    fn ids(requests: &[RecentRequest]) -> Vec<Option<i64>> {
+        requests.iter().map(|request| request.id).collect()
+    }
+
+    #[test]
+    fn keeps_the_newest_requests_up_to_capacity() {
+        let ring = RecentRing::new(3);
+        for id in 1..=5 {
+            ring.record(&completed("m", false), Some(id));
+        }
+
+        let all = RecentFilter::default();
+        assert_eq!(ids(&ring.latest(10, &all)), [Some(5), Some(4), Some(3)]);
+        assert_eq!(ids(&ring.latest(2, &all)), [Some(5), Some(4)]);
+    }
+
+    #[test]
+    fn unstored_requests_are_kept_but_skipped_when_paging() {
+        let ring = RecentRing::new(10);
+        ring.record(&completed("m", false), Some(1));
+        ring.record(&completed("m", false), Some(2));
+        ring.record(&completed("m", false), None);
+
+        assert_eq!(
+            ids(&ring.latest(10, &RecentFilter::default())),
+            [None, Some(2), Some(1)]
+        );
+        let page = RecentFilter {
+            before_id: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(ids(&ring.latest(10, &page)), [Some(1)]);
+    }
+
+    #[test]
+    fn filters_like_the_database_query() {
+        let ring = RecentRing::new(10);
+        ring.record(&completed("a", false), Some(1));
+        ring.record(&completed("b", true), Some(2));
+        ring.record(&completed("a", true), Some(3));
+
+        let model_a = RecentFilter {
+            model: Some("a".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&ring.latest(10, &model_a)), [Some(3), Some(1)]);
+        let errors = RecentFilter {
+            errors_only: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(&ring.latest(10, &errors)), [Some(3), Some(2)]);
+        let other_endpoint = RecentFilter {
+            endpoint: Some("/v1/embeddings".to_string()),
+            ..Default::default()
+        };
+        assert!(ring.latest(10, &other_endpoint).is_empty());
+    }
+
+    #[test]
+    fn zero_capacity_keeps_nothing() {
+        let ring = RecentRing::new(0);
+        ring.record(&completed("m", false), Some(1));
+        assert!(ring.latest(10, &RecentFilter::default()).is_empty());
+    }
+
+    #[test]
+    fn concurrent_writers_never_exceed_capacity() {
+        let ring = Arc::new(RecentRing::new(50));
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let ring = ring.clone();
+                std::thread::spawn(move || {
+                    for i in 0..200 {
+                        ring.record(&completed("m", false), Some(writer * 1000 + i));
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let latest = ring.latest(1000, &RecentFilter::default());
+        assert_eq!(latest.len(), 50);
+        // Each writer's own requests stay in the order it recorded them
+        for writer in 0..8 {
+            let own: Vec<i64> = latest
+                .iter()
+                .filter_map(|request| request.id)
+                .filter(|id| id / 1000 == writer)
+                .collect();
+            assert!(own.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", own);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Idle unloads inferred from request timings.
+//!
+//! LM Studio unloads a model once it has been idle for its TTL and loads it again for the
+//! next request, which pays the load time during prompt processing. The proxy can't see
+//! unloads directly, so it looks for their fingerprint instead: a request that follows a
+//! long gap in a model's traffic and whose `prompt_eval_ms` is far above that model's
+//! recent baseline. Each match is recorded as a `model_reload` event in `proxy_events`.
+//! Timings are kept in memory, so detection starts afresh after a restart.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::db::RequestRecord;
+
+/// `proxy_events` kind of an inferred unload/reload
+pub const RELOAD_EVENT: &str = "model_reload";
+
+/// Latest prompt-processing times per model that the baseline is the median of
+const BASELINE_SAMPLES: usize = 20;
+
+/// No reload is inferred for a model until it has this many baseline samples
+const MIN_BASELINE_SAMPLES: usize = 5;
+
+/// A request that most likely waited for its model to be loaded again.
+#[derive(Debug, Serialize)]
+pub struct InferredReload {
+    pub model: String,
+    pub proxy_request_id: Option<String>,
+    /// Time since the model's previous request finished
+    pub gap_ms: i64,
+    pub prompt_eval_ms: i64,
+    pub baseline_ms: i64,
+    /// Latency paid over the baseline
+    pub extra_latency_ms: i64,
+}
+
+/// Recent activity of one model.
+#[derive(Default)]
+struct ModelActivity {
+    last_end: Option<DateTime<Utc>>,
+    prompt_eval_ms: VecDeque<i64>,
+}
+
+impl ModelActivity {
+    fn baseline_ms(&self) -> Option<i64> {
+        if self.prompt_eval_ms.len() < MIN_BASELINE_SAMPLES {
+            return None;
+        }
+        let mut sorted: Vec<i64> = self.prompt_eval_ms.iter().copied().collect();
+        sorted.sort_unstable();
+        Some(sorted[sorted.len() / 2])
+    }
+}
+
+/// Tracks per-model gaps and prompt-processing times to spot reloads.
+#[derive(Default)]
+pub struct ReloadDetector {
+    models: Mutex<HashMap<String, ModelActivity>>,
+}
+
+impl ReloadDetector {
+    /// Notes a finished request, returning the reload it paid for, if it looks like one.
+    pub fn observe(&self, config: &Config, record: &RequestRecord) -> Option<InferredReload> {
+        if record.is_error {
+            return None;
+        }
+        let start = DateTime::parse_from_rfc3339(&record.start_time)
+            .ok()?
+            .to_utc();
+        let end = DateTime::parse_from_rfc3339(&record.end_time)
+            .ok()?
+            .to_utc();
+
+        let mut models = self.models.lock().unwrap_or_else(|e| e.into_inner());
+        let activity = models.entry(record.model.clone()).or_default();
+        let gap_ms = activity
+            .last_end
+            .map(|last_end| (start - last_end).num_milliseconds());
+        // Requests can finish out of order; the model was busy until the latest end
+        activity.last_end = activity.last_end.max(Some(end));
+
+        let prompt_eval_ms = record.prompt_eval_ms?;
+        let reload = match (gap_ms, activity.baseline_ms()) {
+            (Some(gap_ms), Some(baseline_ms))
+                if gap_ms >= config.reload_min_gap_secs as i64 * 1000
+                    && prompt_eval_ms as f64
+                        > baseline_ms.max(1) as f64 * config.reload_latency_multiple =>
+            {
+                Some(InferredReload {
+                    model: record.model.clone(),
+                    proxy_request_id: record.proxy_request_id.clone(),
+                    gap_ms,
+                    prompt_eval_ms,
+                    baseline_ms,
+                    extra_latency_ms: prompt_eval_ms - baseline_ms,
+                })
+            }
+            _ => None,
+        };
+
+        // Keep load times out of the baseline they are measured against
+        if reload.is_none() {
+            if activity.prompt_eval_ms.len() == BASELINE_SAMPLES {
+                activity.prompt_eval_ms.pop_front();
+            }
+            activity.prompt_eval_ms.push_back(prompt_eval_ms);
+        }
+        reload
+    }
+}
@@ -0,0 +1,304 @@
+//! The accept loop behind every listener.
+//!
+//! `axum::serve` drops connection errors, which hides requests hyper rejects before any
+//! handler runs: malformed request lines, oversized headers, clients that hang up halfway
+//! through a request. This loop serves connections the same way but reports those
+//! failures to [`SelfDiagnostics`], together with the method and path when enough of the
+//! request line arrived to read them.
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use hyper::Request;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::server::graceful::GracefulShutdown;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tower::Service;
+
+use crate::diagnostics::SelfDiagnostics;
+
+/// Bytes of each request's start kept to name a rejected request
+const MAX_HEAD_BYTES: usize = 512;
+
+/// Longest method or path reported for a rejected request
+const MAX_REQUEST_LINE_PART: usize = 200;
+
+/// Serves `app` on `listener` until `shutdown` fires, then waits for open connections.
+pub async fn serve(
+    app: Router,
+    listener: TcpListener,
+    mut shutdown: watch::Receiver<()>,
+    diagnostics: Arc<SelfDiagnostics>,
+) -> anyhow::Result<()> {
+    let graceful = GracefulShutdown::new();
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Usually out of file descriptors; give connections time to close
+                    tracing::error!("Failed to accept connection: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            _ = shutdown.changed() => break,
+        };
+
+        let head = Arc::new(RequestHead::default());
+        let io = TokioIo::new(RecordingStream {
+            inner: stream,
+            head: head.clone(),
+        });
+        let service = {
+            let app = app.clone();
+            let head = head.clone();
+            hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                let dispatched = head.dispatched();
+                request.extensions_mut().insert(ConnectInfo(peer));
+                let response = app.clone().call(request.map(Body::new));
+                async move {
+                    let response = response.await;
+                    drop(dispatched);
+                    response
+                }
+            })
+        };
+
+        let connection = Builder::new(TokioExecutor::new())
+            .serve_connection_with_upgrades(io, service)
+            .into_owned();
+        let connection = graceful.watch(connection);
+        let diagnostics = diagnostics.clone();
+        tokio::spawn(async move {
+            let Err(error) = connection.await else {
+                return;
+            };
+            let Some(error) = error.downcast_ref::<hyper::Error>() else {
+                return;
+            };
+            if let Some(kind) = rejection_kind(error, head.started()) {
+                diagnostics.connection_rejected(peer, kind, head.request_line(), error.to_string());
+            }
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// Classifies a connection error as a rejection before any handler ran, or `None` for
+/// failures after a request was handed over (such as the client leaving mid-response).
+/// `started` tells whether part of a request that never reached a handler was read.
+fn rejection_kind(error: &hyper::Error, started: bool) -> Option<&'static str> {
+    if error.is_parse_too_large() {
+        Some("too_large")
+    } else if error.is_parse() || error.is_parse_status() {
+        Some("parse_error")
+    } else if error.is_timeout() {
+        Some("header_timeout")
+    } else if error.is_incomplete_message() && started {
+        Some("incomplete_request")
+    } else {
+        None
+    }
+}
+
+/// The start of the request a connection is reading, and how many of its requests are
+/// with a handler.
+#[derive(Default)]
+struct RequestHead {
+    bytes: Mutex<Vec<u8>>,
+    in_flight: AtomicUsize,
+}
+
+impl RequestHead {
+    fn bytes(&self) -> std::sync::MutexGuard<'_, Vec<u8>> {
+        self.bytes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Keeps the first bytes read while no request is being handled, so bodies of
+    /// accepted requests are never recorded.
+    fn record(&self, read: &[u8]) {
+        if self.in_flight() > 0 {
+            return;
+        }
+        let mut bytes = self.bytes();
+        let room = MAX_HEAD_BYTES.saturating_sub(bytes.len());
+        bytes.extend_from_slice(&read[..read.len().min(room)]);
+    }
+
+    /// Starts a request the handler accepted; the next request's head starts afresh.
+    fn dispatched(self: &Arc<Self>) -> Dispatched {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        self.bytes().clear();
+        Dispatched(self.clone())
+    }
+
+    fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Whether bytes of a request arrived since the last one was dispatched. The
+    /// connection's error is only seen once it has ended, when nothing is in flight any
+    /// more, so this is what tells a client that left halfway through a request from one
+    /// that left after its response.
+    fn started(&self) -> bool {
+        !self.bytes().is_empty()
+    }
+
+    /// Method and path from the part of the request line that arrived.
+    fn request_line(&self) -> (Option<String>, Option<String>) {
+        let bytes = self.bytes();
+        let line = bytes.split(|&b| b == b'\n').next().unwrap_or_default();
+        let line = String::from_utf8_lossy(line);
+        let mut parts = line.trim_end_matches('\r').split(' ');
+        let method = parts
+            .next()
+            .filter(|method| {
+                !method.is_empty()
+                    && method.len() <= MAX_REQUEST_LINE_PART
+                    && method.bytes().all(|b| b.is_ascii_uppercase())
+            })
+            .map(str::to_string);
+        let path = parts.next().filter(|path| !path.is_empty()).map(|path| {
+            path.chars()
+                .take(MAX_REQUEST_LINE_PART)
+                .map(|c| if c.is_control() { '?' } else { c })
+                .collect()
+        });
+        (method, path)
+    }
+}
+
+/// A request with the handler; dropping it marks the response as produced.
+struct Dispatched(Arc<RequestHead>);
+
+impl Drop for Dispatched {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A client connection that records the start of each request it reads.
+struct RecordingStream {
+    inner: TcpStream,
+    head: Arc<RequestHead>,
+}
+
+impl AsyncRead for RecordingStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            self.head.record(&buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+impl AsyncWrite for RecordingStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(bytes: &[u8]) -> Arc<RequestHead> {
+        let head = Arc::new(RequestHead::default());
+        head.record(bytes);
+        head
+    }
+
+    #[test]
+    fn request_line_of_a_partial_head() {
+        let line = |bytes: &[u8]| head(bytes).request_line();
+        assert_eq!(
+            line(b"POST /v1/chat/completions HTTP/1.1\r\nHost: x\r\n"),
+            (Some("POST".to_string()), Some("/v1/chat/completions".to_string()))
+        );
+        assert_eq!(
+            line(b"GET /stats/sum"),
+            (Some("GET".to_string()), Some("/stats/sum".to_string()))
+        );
+        assert_eq!(line(b"GET"), (Some("GET".to_string()), None));
+        assert_eq!(line(b"\x16\x03\x01\x02\x00"), (None, None));
+        assert_eq!(line(b"get /x HTTP/1.1"), (None, Some("/x".to_string())));
+        assert_eq!(line(b""), (None, None));
+    }
+
+    #[test]
+    fn request_line_parts_are_bounded_and_printable() {
+        let mut bytes = b"GET /a\x07b".to_vec();
+        bytes.extend(std::iter::repeat_n(b'c', 1_000));
+        let (method, path) = head(&bytes).request_line();
+        assert_eq!(method.as_deref(), Some("GET"));
+        let path = path.unwrap();
+        assert!(path.starts_with("/a?bccc"), "{}", path);
+        assert_eq!(path.chars().count(), MAX_REQUEST_LINE_PART);
+
+        // The recorded head itself stops at MAX_HEAD_BYTES
+        let head = head(&[b'A'; 2 * MAX_HEAD_BYTES]);
+        head.record(b" /late");
+        assert_eq!(head.bytes().len(), MAX_HEAD_BYTES);
+    }
+
+    #[test]
+    fn heads_of_dispatched_requests_are_not_recorded() {
+        let head = head(b"POST /first HTTP/1.1\r\n\r\n");
+        let dispatched = head.dispatched();
+        head.record(b"{\"secret\":\"body\"}");
+        assert!(head.bytes().is_empty());
+        assert_eq!(head.in_flight(), 1);
+
+        drop(dispatched);
+        // Finished with a response and nothing new: no request was cut off
+        assert!(!head.started());
+        head.record(b"GET /second HTTP/1.1\r\n");
+        assert!(head.started());
+        assert_eq!(head.in_flight(), 0);
+        assert_eq!(head.request_line().1.as_deref(), Some("/second"));
+    }
+}
@@ -0,0 +1,593 @@
+//! Ships stored requests to an external HTTP endpoint, such as ClickHouse's HTTP
+//! interface or a log collector.
+//!
+//! Each request is queued right after its row is inserted, and a background task posts
+//! the queue in batches of `EXPORT_SINK_BATCH_SIZE`, or whatever arrived within
+//! `EXPORT_SINK_INTERVAL_MS`. Queueing never waits: when `EXPORT_SINK_BUFFER` records are
+//! already waiting, new ones are dropped and counted. A failed batch is retried with
+//! exponential backoff. Once the sink has been failing for `EXPORT_SINK_DEAD_LETTER_AFTER_SECS`,
+//! each batch gets one attempt and then goes to the dead-letter file (or is dropped
+//! without one), so the queue keeps moving until the sink is back.
+
+use http_body_util::BodyExt;
+use hyper::header::{CONTENT_TYPE, HeaderName, HeaderValue};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::config::SinkConfig;
+use crate::db::RequestRecord;
+use crate::diagnostics::{SelfDiagnostics, SinkOutcome};
+use crate::proxy::client::HttpClient;
+
+/// Record fields holding request or response content, left out unless bodies are enabled
+const BODY_FIELDS: &[&str] = &["prompt", "output", "incident_capture"];
+
+/// Wait before the first retry of a batch; doubled for each further one
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Longest a single delivery may take before it counts as failed
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct ExportSink {
+    /// `None` when no sink is configured
+    tx: Option<mpsc::Sender<Value>>,
+    include_bodies: bool,
+    diagnostics: Arc<SelfDiagnostics>,
+}
+
+impl ExportSink {
+    /// Starts the delivery task when `EXPORT_SINK_URL` is set.
+    pub fn start(
+        config: &SinkConfig,
+        client: HttpClient,
+        diagnostics: Arc<SelfDiagnostics>,
+    ) -> anyhow::Result<Arc<Self>> {
+        let Some(url) = &config.url else {
+            return Ok(Arc::new(Self {
+                tx: None,
+                include_bodies: false,
+                diagnostics,
+            }));
+        };
+
+        let uri: hyper::Uri = url
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid EXPORT_SINK_URL value: {}", e))?;
+        let headers = config
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                Ok((
+                    HeaderName::try_from(name.as_str())?,
+                    HeaderValue::try_from(value.as_str())?,
+                ))
+            })
+            .collect::<Result<Vec<_>, hyper::http::Error>>()
+            .map_err(|e| anyhow::anyhow!("Invalid EXPORT_SINK_HEADERS entry: {}", e))?;
+
+        let (tx, rx) = mpsc::channel(config.buffer.max(1));
+        let delivery = Delivery {
+            client,
+            uri,
+            headers,
+            json_array: config.format == "json",
+            batch_size: config.batch_size.max(1),
+            batch_interval: Duration::from_millis(config.batch_interval_ms),
+            dead_letter_file: config.dead_letter_file.clone(),
+            dead_letter_after: Duration::from_secs(config.dead_letter_after_secs),
+            failing_since: None,
+            diagnostics: diagnostics.clone(),
+        };
+        tokio::spawn(delivery.run(rx));
+        tracing::info!("Exporting stored requests to {}", url);
+
+        Ok(Arc::new(Self {
+            tx: Some(tx),
+            include_bodies: config.include_bodies,
+            diagnostics,
+        }))
+    }
+
+    /// Queues a stored request for export, dropping it if the buffer is full.
+    pub fn push(&self, record: &RequestRecord, id: i64) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let value = match export_value(record, id, self.include_bodies) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!("Failed to serialize request {} for export: {}", id, e);
+                return;
+            }
+        };
+
+        self.diagnostics.sink_queued();
+        if tx.try_send(value).is_err() {
+            self.diagnostics.sink_settled(1, SinkOutcome::Dropped);
+            tracing::warn!("Export sink buffer full, request {} not exported", id);
+        }
+    }
+
+    /// Waits up to `timeout` for queued records to be delivered, for shutdown.
+    pub async fn drain(&self, timeout: Duration) {
+        if self.tx.is_none() {
+            return;
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let pending = self.diagnostics.sink_pending();
+            if pending == 0 {
+                return;
+            }
+            if Instant::now() >= deadline {
+                tracing::warn!("{} request(s) not exported before shutdown", pending);
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+/// A stored request as exported, with its row id and, unless `include_bodies`, without
+/// the prompt and output.
+pub fn export_value(
+    record: &RequestRecord,
+    id: i64,
+    include_bodies: bool,
+) -> serde_json::Result<Value> {
+    let mut value = serde_json::to_value(record)?;
+    if let Value::Object(fields) = &mut value {
+        if !include_bodies {
+            for field in BODY_FIELDS {
+                fields.shift_remove(*field);
+            }
+        }
+        fields.insert("id".to_string(), Value::from(id));
+    }
+    Ok(value)
+}
+
+/// The delivery task's settings and state.
+struct Delivery {
+    client: HttpClient,
+    uri: hyper::Uri,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    json_array: bool,
+    batch_size: usize,
+    batch_interval: Duration,
+    dead_letter_file: Option<String>,
+    dead_letter_after: Duration,
+    /// First failure since the last successful delivery
+    failing_since: Option<Instant>,
+    diagnostics: Arc<SelfDiagnostics>,
+}
+
+impl Delivery {
+    async fn run(mut self, mut rx: mpsc::Receiver<Value>) {
+        while let Some(first) = rx.recv().await {
+            let mut batch = vec![first];
+            let flush_at = Instant::now() + self.batch_interval;
+            while batch.len() < self.batch_size {
+                match tokio::time::timeout_at(flush_at, rx.recv()).await {
+                    Ok(Some(value)) => batch.push(value),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+            self.deliver(batch).await;
+        }
+    }
+
+    /// Sends a batch, retrying until it gets through or the sink has been down too long.
+    async fn deliver(&mut self, batch: Vec<Value>) {
+        let records = batch.len() as u64;
+        let body = self.encode(&batch);
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let error = match self.send(body.clone()).await {
+                Ok(()) => {
+                    if self.failing_since.take().is_some() {
+                        tracing::info!("Export sink is accepting batches again");
+                    }
+                    self.diagnostics.sink_settled(records, SinkOutcome::Sent);
+                    return;
+                }
+                Err(error) => error,
+            };
+
+            let failing_since = *self.failing_since.get_or_insert_with(Instant::now);
+            if failing_since.elapsed() >= self.dead_letter_after {
+                self.dead_letter(&batch, &error).await;
+                return;
+            }
+            tracing::warn!(
+                "Export sink rejected a batch of {} record(s), retrying in {:?}: {}",
+                records,
+                backoff,
+                error
+            );
+            self.diagnostics.sink_retried();
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    fn encode(&self, batch: &[Value]) -> String {
+        if self.json_array {
+            Value::Array(batch.to_vec()).to_string()
+        } else {
+            ndjson(batch)
+        }
+    }
+
+    async fn send(&self, body: String) -> Result<(), String> {
+        let content_type = if self.json_array {
+            "application/json"
+        } else {
+            "application/x-ndjson"
+        };
+        let mut request = hyper::Request::post(self.uri.clone())
+            .header(CONTENT_TYPE, content_type)
+            .body(body)
+            .map_err(|e| e.to_string())?;
+        for (name, value) in &self.headers {
+            request.headers_mut().insert(name.clone(), value.clone());
+        }
+
+        let response = tokio::time::timeout(SEND_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| format!("no response within {:?}", SEND_TIMEOUT))?
+            .map_err(|e| e.to_string())?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map(|body| String::from_utf8_lossy(&body.to_bytes()).into_owned())
+            .unwrap_or_default();
+        Err(format!(
+            "{} {}",
+            status,
+            body.chars().take(200).collect::<String>()
+        ))
+    }
+
+    /// Appends a batch the sink wouldn't take to the dead-letter file, one record per
+    /// line, or drops it when there is no file.
+    async fn dead_letter(&self, batch: &[Value], error: &str) {
+        let records = batch.len() as u64;
+        let Some(path) = &self.dead_letter_file else {
+            tracing::error!(
+                "Export sink down, dropping a batch of {} record(s): {}",
+                records,
+                error
+            );
+            self.diagnostics.sink_settled(records, SinkOutcome::Dropped);
+            return;
+        };
+
+        let lines = ndjson(batch);
+        let written = async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            file.write_all(lines.as_bytes()).await
+        }
+        .await;
+
+        match written {
+            Ok(()) => {
+                tracing::warn!(
+                    "Export sink down, wrote a batch of {} record(s) to {}: {}",
+                    records,
+                    path,
+                    error
+                );
+                self.diagnostics
+                    .sink_settled(records, SinkOutcome::DeadLettered);
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Export sink down and dead-letter file {} unwritable, dropping {} record(s): {}",
+                    path,
+                    records,
+                    e
+                );
+                self.diagnostics.sink_settled(records, SinkOutcome::Dropped);
+            }
+        }
+    }
+}
+
+/// One JSON record per line.
+fn ndjson(batch: &[Value]) -> String {
+    let mut lines = String::new();
+    for value in batch {
+        lines.push_str(&value.to_string());
+        lines.push('\n');
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffers::BufferBudget;
+    use crate::diagnostics::DiagnosticsSnapshot;
+    use crate::proxy::client::create_client;
+    use axum::Router;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode};
+    use std::sync::Mutex;
+
+    /// A batch the mock sink received: its headers and body.
+    type Batch = (HeaderMap, String);
+
+    /// A local sink answering the `n`th batch with `status(n)` after `delay`.
+    #[derive(Clone)]
+    struct MockSink {
+        received: Arc<Mutex<Vec<Batch>>>,
+        status: fn(usize) -> StatusCode,
+        delay: Duration,
+    }
+
+    impl MockSink {
+        async fn start(status: fn(usize) -> StatusCode, delay: Duration) -> (Self, String) {
+            let sink = MockSink {
+                received: Arc::default(),
+                status,
+                delay,
+            };
+            let app = Router::new()
+                .route("/", axum::routing::post(receive))
+                .with_state(sink.clone());
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+            (sink, url)
+        }
+
+        fn batches(&self) -> Vec<Batch> {
+            self.received.lock().unwrap().clone()
+        }
+
+        /// Waits for `count` batches to arrive.
+        async fn wait_for(&self, count: usize) -> Vec<Batch> {
+            for _ in 0..200 {
+                if self.received.lock().unwrap().len() >= count {
+                    return self.batches();
+                }
+                tokio::time::sleep(Duration::from_millis(25)).await;
+            }
+            panic!("sink got {} of {} batches", self.batches().len(), count);
+        }
+    }
+
+    async fn receive(State(sink): State<MockSink>, headers: HeaderMap, body: String) -> StatusCode {
+        let n = {
+            let mut received = sink.received.lock().unwrap();
+            received.push((headers, body));
+            received.len() - 1
+        };
+        tokio::time::sleep(sink.delay).await;
+        (sink.status)(n)
+    }
+
+    fn config(url: &str) -> SinkConfig {
+        SinkConfig {
+            url: Some(url.to_string()),
+            headers: vec![("X-Token".to_string(), "secret".to_string())],
+            format: "ndjson".to_string(),
+            include_bodies: false,
+            batch_size: 3,
+            batch_interval_ms: 200,
+            buffer: 100,
+            dead_letter_file: None,
+            dead_letter_after_secs: 300,
+        }
+    }
+
+    fn record(n: i64) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            "m".to_string(),
+            chrono::Utc::now(),
+            format!("prompt {}", n),
+        );
+        record.output = format!("output {}", n);
+        record.input_tokens = n;
+        record
+    }
+
+    fn start(config: &SinkConfig) -> (Arc<ExportSink>, Arc<SelfDiagnostics>) {
+        let diagnostics = SelfDiagnostics::new();
+        let sink = ExportSink::start(config, create_client(), diagnostics.clone()).unwrap();
+        (sink, diagnostics)
+    }
+
+    fn snapshot(diagnostics: &SelfDiagnostics) -> DiagnosticsSnapshot {
+        diagnostics.snapshot(&BufferBudget::new(0))
+    }
+
+    fn ids(body: &str) -> Vec<i64> {
+        body.lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["id"].as_i64().unwrap())
+            .collect()
+    }
+
+    async fn settled(diagnostics: &SelfDiagnostics) {
+        for _ in 0..200 {
+            if diagnostics.sink_pending() == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+        panic!("{} record(s) still pending", diagnostics.sink_pending());
+    }
+
+    #[test]
+    fn bodies_are_left_out_unless_enabled() {
+        let value = export_value(&record(7), 42, false).unwrap();
+        assert_eq!(value["id"], 42);
+        assert_eq!(value["input_tokens"], 7);
+        assert_eq!(value["model"], "m");
+        for field in BODY_FIELDS {
+            assert!(value.get(*field).is_none(), "{}", field);
+        }
+
+        let value = export_value(&record(7), 42, true).unwrap();
+        assert_eq!(value["prompt"], "prompt 7");
+        assert_eq!(value["output"], "output 7");
+    }
+
+    #[tokio::test]
+    async fn full_batches_go_at_once_and_the_rest_after_the_interval() {
+        let (mock, url) = MockSink::start(|_| StatusCode::OK, Duration::ZERO).await;
+        let (sink, diagnostics) = start(&config(&url));
+
+        for id in 1..=7 {
+            sink.push(&record(id), id);
+        }
+        let batches = mock.wait_for(2).await;
+        assert_eq!(ids(&batches[0].1), [1, 2, 3]);
+        assert_eq!(ids(&batches[1].1), [4, 5, 6]);
+
+        // The last record waits out the interval on its own
+        let batches = mock.wait_for(3).await;
+        assert_eq!(ids(&batches[2].1), [7]);
+        settled(&diagnostics).await;
+        assert_eq!(mock.batches().len(), 3);
+
+        let (headers, _) = &batches[0];
+        assert_eq!(headers["content-type"], "application/x-ndjson");
+        assert_eq!(headers["x-token"], "secret");
+        let stats = snapshot(&diagnostics);
+        assert_eq!(stats.export_sink_sent, 7);
+        assert_eq!(stats.export_sink_retries, 0);
+        assert_eq!(stats.export_sink_pending, 0);
+    }
+
+    #[tokio::test]
+    async fn json_format_sends_an_array() {
+        let (mock, url) = MockSink::start(|_| StatusCode::OK, Duration::ZERO).await;
+        let mut config = config(&url);
+        config.format = "json".to_string();
+        config.include_bodies = true;
+        let (sink, _) = start(&config);
+
+        for id in 1..=3 {
+            sink.push(&record(id), id);
+        }
+        let (headers, body) = mock.wait_for(1).await.remove(0);
+        assert_eq!(headers["content-type"], "application/json");
+        let batch: Vec<Value> = serde_json::from_str(&body).unwrap();
+        let prompts: Vec<&str> = batch.iter().map(|v| v["prompt"].as_str().unwrap()).collect();
+        assert_eq!(prompts, ["prompt 1", "prompt 2", "prompt 3"]);
+    }
+
+    #[tokio::test]
+    async fn a_failed_batch_is_retried_until_accepted() {
+        let status = |n| match n {
+            0 => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::OK,
+        };
+        let (mock, url) = MockSink::start(status, Duration::ZERO).await;
+        let (sink, diagnostics) = start(&config(&url));
+
+        for id in 1..=3 {
+            sink.push(&record(id), id);
+        }
+        let batches = mock.wait_for(2).await;
+        assert_eq!(batches[0].1, batches[1].1);
+        settled(&diagnostics).await;
+
+        let stats = snapshot(&diagnostics);
+        assert_eq!(stats.export_sink_sent, 3);
+        assert_eq!(stats.export_sink_retries, 1);
+        assert_eq!(stats.export_sink_dropped, 0);
+        assert_eq!(stats.export_sink_dead_lettered, 0);
+    }
+
+    #[tokio::test]
+    async fn a_sink_down_too_long_sends_batches_to_the_dead_letter_file() {
+        let (mock, url) = MockSink::start(|_| StatusCode::BAD_GATEWAY, Duration::ZERO).await;
+        let path = std::env::temp_dir().join(format!("sink-test-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut config = config(&url);
+        config.dead_letter_file = Some(path.display().to_string());
+        config.dead_letter_after_secs = 0;
+        let (sink, diagnostics) = start(&config);
+
+        for id in 1..=4 {
+            sink.push(&record(id), id);
+        }
+        settled(&diagnostics).await;
+
+        // One attempt per batch, no retries
+        assert_eq!(mock.batches().len(), 2);
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ids(&written), [1, 2, 3, 4]);
+        let stats = snapshot(&diagnostics);
+        assert_eq!(stats.export_sink_dead_lettered, 4);
+        assert_eq!(stats.export_sink_retries, 0);
+        assert_eq!(stats.export_sink_sent, 0);
+    }
+
+    #[tokio::test]
+    async fn a_sink_down_without_a_dead_letter_file_drops_batches() {
+        let (_mock, url) = MockSink::start(|_| StatusCode::BAD_GATEWAY, Duration::ZERO).await;
+        let mut config = config(&url);
+        config.dead_letter_after_secs = 0;
+        let (sink, diagnostics) = start(&config);
+
+        for id in 1..=3 {
+            sink.push(&record(id), id);
+        }
+        settled(&diagnostics).await;
+        let stats = snapshot(&diagnostics);
+        assert_eq!(stats.export_sink_dropped, 3);
+        assert_eq!(stats.export_sink_sent, 0);
+    }
+
+    #[tokio::test]
+    async fn a_full_buffer_drops_new_records_without_waiting() {
+        let (mock, url) = MockSink::start(|_| StatusCode::OK, Duration::from_secs(30)).await;
+        let mut config = config(&url);
+        config.batch_size = 1;
+        config.buffer = 1;
+        let (sink, diagnostics) = start(&config);
+
+        // The first record is out for delivery, the second fills the buffer
+        sink.push(&record(1), 1);
+        mock.wait_for(1).await;
+        let started = Instant::now();
+        for id in 2..=4 {
+            sink.push(&record(id), id);
+        }
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        let stats = snapshot(&diagnostics);
+        assert_eq!(stats.export_sink_dropped, 2);
+        assert_eq!(stats.export_sink_pending, 2);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_queued_without_a_url() {
+        let mut config = config("");
+        config.url = None;
+        let (sink, diagnostics) = start(&config);
+        sink.push(&record(1), 1);
+        assert_eq!(diagnostics.sink_pending(), 0);
+        sink.drain(Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,98 @@
+//! Background writer for finished requests whose insert took too long.
+//!
+//! A stream's task hands its record over here once the write outlasts
+//! `STREAM_WRITE_TIMEOUT_MS`, so the task and its buffers go away as soon as the client
+//! is done. Records are written one at a time in arrival order. At most `SPOOL_CAPACITY`
+//! wait at once; past that they are dropped (and counted), keeping memory bounded while
+//! the disk is slow.
+
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::db::RequestRecord;
+use crate::diagnostics::{PendingPersist, SelfDiagnostics};
+use crate::recent::RecentRing;
+use crate::sink::ExportSink;
+use crate::webhooks::Webhooks;
+
+pub struct Spool {
+    tx: mpsc::Sender<(RequestRecord, PendingPersist)>,
+    recent: Arc<RecentRing>,
+    diagnostics: Arc<SelfDiagnostics>,
+}
+
+impl Spool {
+    /// Starts the writer task.
+    pub fn start(
+        db: SqlitePool,
+        recent: Arc<RecentRing>,
+        sink: Arc<ExportSink>,
+        webhooks: Arc<Webhooks>,
+        diagnostics: Arc<SelfDiagnostics>,
+        capacity: usize,
+    ) -> Arc<Self> {
+        let (tx, mut rx) = mpsc::channel::<(RequestRecord, PendingPersist)>(capacity.max(1));
+        let writer_recent = recent.clone();
+        let writer_diagnostics = diagnostics.clone();
+        tokio::spawn(async move {
+            while let Some((record, pending)) = rx.recv().await {
+                let id = match crate::db::insert_request(&db, &record).await {
+                    Ok(id) => {
+                        sink.push(&record, id);
+                        webhooks.notify(&record, id);
+                        Some(id)
+                    }
+                    Err(e) => {
+                        writer_diagnostics.request_not_stored();
+                        tracing::error!("Failed to log spooled request to database: {}", e);
+                        None
+                    }
+                };
+                drop(pending);
+                writer_recent.record(&record, id);
+                writer_diagnostics.spool_written();
+            }
+        });
+
+        Arc::new(Self {
+            tx,
+            recent,
+            diagnostics,
+        })
+    }
+
+    /// Queues a record for the writer. When the spool is full the record is dropped
+    /// from the database, though it is still in the counters and the recent ring.
+    pub fn hand_off(&self, record: RequestRecord, pending: PendingPersist) {
+        match self.tx.try_send((record, pending)) {
+            Ok(()) => self.diagnostics.spooled(),
+            Err(TrySendError::Full((record, _)) | TrySendError::Closed((record, _))) => {
+                self.diagnostics.spool_overflowed();
+                self.diagnostics.request_not_stored();
+                tracing::error!(
+                    "Write spool full, request {} not stored",
+                    record.proxy_request_id.as_deref().unwrap_or_default()
+                );
+                self.recent.record(&record, None);
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for queued records to be written, for shutdown.
+    pub async fn drain(&self, timeout: Duration) {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let pending = self.diagnostics.spool_pending();
+            if pending == 0 {
+                return;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                tracing::warn!("{} spooled request(s) not written before shutdown", pending);
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
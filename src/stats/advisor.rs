@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::db::ModelUsage;
+use crate::proxy::lmstudio::UpstreamModel;
+
+/// Idle days assumed for loaded models that have never served a request
+const NEVER_USED_DAYS: f64 = 30.0;
+
+/// VRAM assumed when LM Studio doesn't report a model's size
+const UNKNOWN_VRAM_GB: f64 = 1.0;
+
+const BYTES_PER_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// A loaded model with the inputs to its unload score, so the ranking can be checked.
+#[derive(Debug, Serialize)]
+pub struct UnloadCandidate {
+    pub model: String,
+    pub score: f64,
+    pub recommended: bool,
+    pub last_used: Option<String>,
+    pub days_idle: f64,
+    /// Size reported by LM Studio; null when unknown, and scored as 1 GB
+    pub vram_bytes: Option<i64>,
+    /// Average time to first response after an idle gap; null when never observed
+    pub reload_ms: Option<f64>,
+    pub cold_starts: i64,
+}
+
+/// How worthwhile unloading a model is: memory held for longer idle stretches scores
+/// higher, discounted by how long the model takes to load back.
+pub fn unload_score(days_idle: f64, vram_gb: f64, reload_seconds: f64) -> f64 {
+    days_idle.max(0.0) * vram_gb.max(0.0) / (1.0 + reload_seconds.max(0.0))
+}
+
+/// Scores every loaded model, highest score first.
+pub fn rank_loaded_models(
+    models: &[UpstreamModel],
+    usage: &HashMap<String, ModelUsage>,
+    threshold: f64,
+    now: DateTime<Utc>,
+) -> Vec<UnloadCandidate> {
+    let mut candidates: Vec<UnloadCandidate> = models
+        .iter()
+        .filter(|model| model.is_loaded())
+        .map(|model| {
+            let usage = usage.get(&model.id).cloned().unwrap_or_default();
+            let days_idle = usage
+                .last_used
+                .as_deref()
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| (now - time.with_timezone(&Utc)).num_seconds() as f64 / 86_400.0)
+                .unwrap_or(NEVER_USED_DAYS);
+            let vram_gb = model
+                .size_bytes
+                .map(|bytes| bytes as f64 / BYTES_PER_GB)
+                .unwrap_or(UNKNOWN_VRAM_GB);
+            let reload_seconds = usage.reload_ms.unwrap_or(0.0) / 1000.0;
+            let score = unload_score(days_idle, vram_gb, reload_seconds);
+
+            UnloadCandidate {
+                model: model.id.clone(),
+                score,
+                recommended: score >= threshold,
+                last_used: usage.last_used,
+                days_idle,
+                vram_bytes: model.size_bytes,
+                reload_ms: usage.reload_ms,
+                cold_starts: usage.cold_starts,
+            }
+        })
+        .collect();
+
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    const GB: i64 = 1024 * 1024 * 1024;
+
+    fn model(id: &str, state: &str, size_bytes: Option<i64>) -> UpstreamModel {
+        UpstreamModel {
+            id: id.to_string(),
+            state: Some(state.to_string()),
+            size_bytes,
+        }
+    }
+
+    fn used(now: DateTime<Utc>, days_ago: i64, reload_ms: Option<f64>) -> ModelUsage {
+        ModelUsage {
+            last_used: Some((now - Duration::days(days_ago)).to_rfc3339()),
+            reload_ms,
+            cold_starts: reload_ms.map_or(0, |_| 1),
+        }
+    }
+
+    #[test]
+    fn score_grows_with_idle_time_and_size() {
+        assert_eq!(unload_score(0.0, 8.0, 0.0), 0.0);
+        assert_eq!(unload_score(2.0, 8.0, 0.0), 16.0);
+        assert!(unload_score(4.0, 8.0, 0.0) > unload_score(2.0, 8.0, 0.0));
+        assert!(unload_score(2.0, 16.0, 0.0) > unload_score(2.0, 8.0, 0.0));
+    }
+
+    #[test]
+    fn slow_reloads_discount_the_score() {
+        assert_eq!(unload_score(2.0, 8.0, 1.0), 8.0);
+        assert!(unload_score(2.0, 8.0, 30.0) < unload_score(2.0, 8.0, 1.0));
+    }
+
+    #[test]
+    fn negative_inputs_are_clamped() {
+        // A last-used time in the future, from clock skew, means not idle at all
+        assert_eq!(unload_score(-1.0, 8.0, 0.0), 0.0);
+        assert_eq!(unload_score(1.0, -8.0, 0.0), 0.0);
+        assert_eq!(unload_score(1.0, 8.0, -0.5), 8.0);
+    }
+
+    #[test]
+    fn only_loaded_models_are_ranked_highest_first() {
+        let now = Utc::now();
+        let models = [
+            model("busy", "loaded", Some(8 * GB)),
+            model("idle", "loaded", Some(8 * GB)),
+            model("on-disk", "not-loaded", Some(40 * GB)),
+            model("huge", "loaded", Some(40 * GB)),
+        ];
+        let usage = HashMap::from([
+            ("busy".to_string(), used(now, 0, None)),
+            ("idle".to_string(), used(now, 3, None)),
+            ("huge".to_string(), used(now, 1, Some(60_000.0))),
+        ]);
+
+        let ranked = rank_loaded_models(&models, &usage, 10.0, now);
+        let order: Vec<_> = ranked.iter().map(|c| c.model.as_str()).collect();
+        assert_eq!(order, ["idle", "huge", "busy"]);
+
+        let idle = &ranked[0];
+        assert!((idle.days_idle - 3.0).abs() < 0.01);
+        assert!((idle.score - 24.0).abs() < 0.1);
+        assert!(idle.recommended);
+        assert_eq!(idle.vram_bytes, Some(8 * GB));
+        assert_eq!(ranked[1].reload_ms, Some(60_000.0));
+        assert_eq!(ranked[1].cold_starts, 1);
+        assert!(!ranked[1].recommended);
+        assert_eq!(ranked[2].score, 0.0);
+    }
+
+    #[test]
+    fn unknown_usage_and_size_fall_back_to_defaults() {
+        let now = Utc::now();
+        let models = [model("never", "loaded", None)];
+        let ranked = rank_loaded_models(&models, &HashMap::new(), 30.0, now);
+
+        assert_eq!(ranked.len(), 1);
+        assert_eq!(ranked[0].days_idle, NEVER_USED_DAYS);
+        assert_eq!(ranked[0].score, NEVER_USED_DAYS * UNKNOWN_VRAM_GB);
+        assert!(ranked[0].recommended, "the threshold is inclusive");
+        assert_eq!(ranked[0].last_used, None);
+    }
+}
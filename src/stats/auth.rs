@@ -0,0 +1,179 @@
+use axum::{
+    Router,
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, header::AUTHORIZATION},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::stats::error::StatsError;
+use crate::stats::index::{API_PREFIX, ApiIndex, IndexRoute};
+use crate::stats::shape;
+
+/// Checks the `Authorization: Bearer` header against `ADMIN_TOKEN`.
+///
+/// Admin actions are disabled outright when no token is configured.
+pub fn require_admin(config: &Config, headers: &HeaderMap) -> Result<(), StatsError> {
+    let Some(expected) = config.admin_token.as_deref() else {
+        return Err(StatsError::Forbidden(
+            "admin actions are disabled; set ADMIN_TOKEN to enable them".to_string(),
+        ));
+    };
+
+    match bearer_token(headers) {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(()),
+        _ => Err(StatsError::Unauthorized(
+            "missing or invalid admin bearer token".to_string(),
+        )),
+    }
+}
+
+/// Who may call a stats route. Every route is registered with one through
+/// [`TaggedRoutes::route`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    /// Aggregates without prompt or output text; `VIEWER_TOKENS` may read them
+    Viewer,
+    /// A viewer route that needs no token at all when `PUBLIC_BADGE` is set
+    Public,
+    /// Can return prompts, outputs or client detail; refused to viewer tokens
+    Full,
+    /// Changes state; refused to viewer tokens, and the handler requires `ADMIN_TOKEN`
+    Admin,
+}
+
+impl Access {
+    fn viewer_readable(self) -> bool {
+        matches!(self, Self::Viewer | Self::Public)
+    }
+}
+
+/// Stats routes together with the access each was registered with.
+pub struct TaggedRoutes<S> {
+    routes: Vec<(&'static str, Access, MethodRouter<S>)>,
+}
+
+impl<S> Default for TaggedRoutes<S> {
+    fn default() -> Self {
+        Self { routes: Vec::new() }
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> TaggedRoutes<S> {
+    pub fn route(
+        mut self,
+        path: &'static str,
+        access: Access,
+        method_router: MethodRouter<S>,
+    ) -> Self {
+        self.routes.push((path, access, method_router));
+        self
+    }
+
+    /// The router, with every route behind [`authorize`] and responses reshaped by
+    /// [`shape::shape`]. Each route is mounted under [`API_PREFIX`] and at its own path,
+    /// and the index of them at `/api/v1/`. With `viewer_only`, just the viewer and public
+    /// routes are mounted and callers must present an admin or viewer token, except for
+    /// public routes when `PUBLIC_BADGE` is set.
+    pub fn into_router(self, config: &Config, viewer_only: bool) -> Router<S> {
+        let mut router = Router::new();
+        let mut access = HashMap::new();
+        let mut index = Vec::new();
+        for (path, route_access, method_router) in self.routes {
+            if viewer_only && !route_access.viewer_readable() {
+                continue;
+            }
+            let versioned = format!("{API_PREFIX}{path}");
+            router = router
+                .route(path, method_router.clone())
+                .route(&versioned, method_router);
+            access.insert(path.to_string(), route_access);
+            access.insert(versioned.clone(), route_access);
+            index.push(IndexRoute {
+                path: versioned,
+                alias: path,
+                access: route_access,
+            });
+        }
+        let index = ApiIndex::new(index).into_handler();
+        for path in [API_PREFIX.to_string(), format!("{API_PREFIX}/")] {
+            router = router.route(&path, index.clone());
+            access.insert(path, Access::Viewer);
+        }
+
+        let permissions = Arc::new(Permissions {
+            access,
+            admin_token: config.admin_token.clone(),
+            viewer_tokens: config.viewer_tokens.clone(),
+            token_required: viewer_only,
+            public_badge: config.badge.public,
+        });
+        router
+            .layer(middleware::from_fn_with_state(permissions, authorize))
+            .layer(middleware::from_fn(shape::shape))
+    }
+}
+
+struct Permissions {
+    access: HashMap<String, Access>,
+    admin_token: Option<String>,
+    viewer_tokens: Vec<String>,
+    token_required: bool,
+    public_badge: bool,
+}
+
+/// Holds viewer tokens to the routes tagged [`Access::Viewer`] or [`Access::Public`]. A
+/// route missing from the table counts as not viewer-readable, so an untagged route fails
+/// closed.
+async fn authorize(
+    State(permissions): State<Arc<Permissions>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let access = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| permissions.access.get(path.as_str()))
+        .copied();
+    let token = bearer_token(request.headers());
+    let is_admin = token
+        .zip(permissions.admin_token.as_deref())
+        .is_some_and(|(token, expected)| constant_time_eq(token.as_bytes(), expected.as_bytes()));
+    let is_viewer = token.is_some_and(|token| {
+        permissions
+            .viewer_tokens
+            .iter()
+            .any(|expected| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+    });
+
+    if is_viewer && !is_admin && !access.is_some_and(Access::viewer_readable) {
+        return StatsError::Forbidden(
+            "viewer tokens may only read aggregate statistics".to_string(),
+        )
+        .into_response();
+    }
+    let public = permissions.public_badge && access == Some(Access::Public);
+    if permissions.token_required && !is_viewer && !is_admin && !public {
+        return StatsError::Unauthorized("missing or invalid viewer bearer token".to_string())
+            .into_response();
+    }
+    next.run(request).await
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,63 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::error::{ProxyError, error_response};
+
+/// Errors from the stats API. Database details are logged, never sent to the client.
+#[derive(Error, Debug)]
+pub enum StatsError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    /// A call to LM Studio made on behalf of a stats endpoint failed
+    #[error(transparent)]
+    Upstream(#[from] ProxyError),
+
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+}
+
+impl IntoResponse for StatsError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            StatsError::Database(_) | StatsError::Upstream(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            StatsError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            StatsError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            StatsError::Forbidden(_) => StatusCode::FORBIDDEN,
+            StatsError::NotFound(_) => StatusCode::NOT_FOUND,
+            StatsError::Conflict(_) => StatusCode::CONFLICT,
+        };
+
+        match self {
+            StatsError::Upstream(e) => e.into_response(),
+            StatsError::Database(e) => {
+                // Clients get an id to quote; the SQL error only goes to the log
+                let request_id = Uuid::new_v4().to_string();
+                tracing::error!("Stats query failed (request {}): {}", request_id, e);
+                error_response(
+                    status,
+                    "Internal server error".to_string(),
+                    "stats_error",
+                    Some(&request_id),
+                )
+            }
+            other => error_response(status, other.to_string(), "stats_error", None),
+        }
+    }
+}
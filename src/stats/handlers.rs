@@ -1,49 +1,2165 @@
 use axum::{
     Json,
-    extract::{Query, State},
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
 };
+use chrono::Datelike;
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use tokio_stream::StreamExt;
 
+use crate::canary::{CanaryReport, ROUTING_WEIGHTS_EVENT};
+use crate::config::BADGE_METRICS;
+use crate::db::agent_overhead::AgentOverheadReport;
+use crate::db::batches::{BATCH_CANCELLED, BATCH_COMPLETED, Batch};
+use crate::db::benchmarks::{BENCHMARK_QUEUED, BENCHMARK_RUNNING, BenchmarkReport, PromptFilter};
+use crate::db::cache::CacheOpportunityReport;
+use crate::db::clients::ClientReport;
+use crate::db::context_fit::ContextFitReport;
+use crate::db::context_utilization::ContextUtilizationReport;
+use crate::db::costs::CostReport;
+use crate::db::determinism::DeterminismReport;
+use crate::db::duplicates::DuplicateReport;
+use crate::db::errors::{ErrorFilter, ErrorReport};
+use crate::db::estimation_gap::EstimationGap;
+use crate::db::finish_reasons::FinishReasonReport;
+use crate::db::guardrails::GuardrailReport;
+use crate::db::heatmap::Heatmap;
+use crate::db::histogram::{Binning, Histogram, HistogramField, MAX_HISTOGRAM_BUCKETS};
+use crate::db::jobs::MaintenanceJob;
+use crate::db::kinds::KindReport;
+use crate::db::kv_cache::KvCacheReport;
+use crate::db::languages::LanguageReport;
+use crate::db::latency::{LatencyTrend, TrendFilter};
+use crate::db::limits::LimitReport;
+use crate::db::models::{
+    DailyReport, RecentFilter, RecentRequest, SummaryComparison, SummaryFilter, SummaryStats,
+};
+use crate::db::params::ParamReport;
+use crate::db::persist_lag::PersistLagReport;
+use crate::db::prompt_quality::PromptQualityReport;
+use crate::db::prompt_versions::{PromptVersion, PromptVersionReport};
+use crate::db::rate::RateReport;
+use crate::db::reloads::ReloadReport;
+use crate::db::retention::RetentionSimulation;
+use crate::db::retries::RetryStats;
+use crate::db::sessions::{Session, SessionReport};
+use crate::db::rollups::{Glance, MAX_SPARKLINE_DAYS, get_totals_since};
+use crate::db::status_codes::StatusCodeReport;
+use crate::db::stops::StopReport;
+use crate::db::storage::{self, CheckpointReport, DataDirectory};
+use crate::db::streaming::StreamingReport;
+use crate::db::timeseries::{Bucket, MAX_BUCKETS, TimeSeries};
+use crate::db::top_prompts::TopPromptReport;
+use crate::db::tree::RequestNode;
+use crate::db::truncation::TruncationReport;
+use crate::db::turns::TurnLatencyReport;
+use crate::db::usage_rollups::{PERIOD_MONTH, PERIOD_WEEK, RebuildReport, UsageRollupReport};
+use crate::db::utilization::Utilization;
+use crate::db::webhooks::{NewWebhook, Webhook};
+use crate::db::{ExportFilter, StoredRequest};
+use crate::diagnostics::DiagnosticsSnapshot;
+use crate::format;
+use crate::incidents::ActiveIncident;
+use crate::jobs::{Control, JobKind};
 use crate::proxy::AppState;
+use crate::proxy::lmstudio::{list_models, unload_model};
+use crate::proxy::sdk::SdkFingerprint;
+use crate::stats::advisor::rank_loaded_models;
+use crate::stats::auth::require_admin;
+use crate::stats::error::StatsError;
+use crate::stats::params::{
+    billing_period, list_param, parse_duration, parse_timestamp, since_cutoff, time_range,
+};
+use crate::stats::response::{
+    ActiveRequestsResponse, ApiResponse, Badge, EndpointStatsResponse, HealthResponse,
+    IncidentsResponse, JobsResponse, KnownModelsResponse, ModelStatsResponse,
+    RecentRequestsResponse, RoutingWeightsChange, SdkStatsResponse, SearchResponse, StatsResult,
+    UnloadAction, UnloadAdvice, WebhookDeliveriesResponse, WebhooksResponse,
+};
+use crate::verify::VerificationReport;
+use crate::webhooks;
+
+/// Badge color when no `BADGE_COLORS` threshold has been reached
+const DEFAULT_BADGE_COLOR: &str = "blue";
+
+/// Buckets in a time series when no start is given
+const DEFAULT_SERIES_BUCKETS: i64 = 30;
+
+/// Longest `q` accepted by `/stats/search`
+const MAX_SEARCH_CHARS: usize = 256;
+
+fn default_limit() -> i64 {
+    100
+}
 
 #[derive(Debug, Deserialize)]
-pub struct PaginationQuery {
+pub struct RecentQuery {
     #[serde(default = "default_limit")]
     limit: i64,
+    /// Only requests with a smaller id, from a previous page's `next_cursor`
+    before_id: Option<i64>,
+    model: Option<String>,
+    endpoint: Option<String>,
+    #[serde(default)]
+    errors_only: bool,
+    /// `database` (default) or `memory`
+    source: Option<String>,
 }
 
-fn default_limit() -> i64 {
-    100
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    q: Option<String>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+    /// Only requests with a smaller id, from a previous page's `next_cursor`
+    before_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionsQuery {
+    since: Option<String>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LatencyTrendQuery {
+    /// Requests averaged together in each window
+    #[serde(default = "default_trend_window")]
+    window: i64,
+    model: Option<String>,
+    session: Option<String>,
+    since: Option<String>,
+    /// Latest windows returned
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_trend_window() -> i64 {
+    20
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DuplicatesQuery {
+    since: Option<String>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TopPromptsQuery {
+    since: Option<String>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RollupQuery {
+    /// `week` (default) or `month`
+    period: Option<String>,
+    /// Latest periods returned
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SinceQuery {
+    since: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SummaryQuery {
+    #[serde(default)]
+    include_abandoned: bool,
+    /// Leave out requests submitted through `/v1/batches`
+    #[serde(default)]
+    exclude_batches: bool,
+    /// Count requests replayed by `/admin/benchmark`, left out by default
+    #[serde(default)]
+    include_benchmarks: bool,
+    /// Count client libraries' warm-up pings, left out by default
+    #[serde(default)]
+    include_probes: bool,
+    /// Only summarize this long a window ending now, e.g. `7d`
+    period: Option<String>,
+    /// Also summarize the window of equal length before `period`
+    #[serde(default)]
+    compare: bool,
+    /// Add the percentiles and other figures that scan every stored request
+    #[serde(default)]
+    detail: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ByModelQuery {
+    #[serde(default)]
+    exclude_batches: bool,
+    #[serde(default)]
+    include_benchmarks: bool,
+    #[serde(default)]
+    include_probes: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BySdkQuery {
+    /// Average probes' durations in too
+    #[serde(default)]
+    include_probes: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ByEndpointQuery {
+    /// Only count requests from this long ago onward, e.g. `24h`
+    since: Option<String>,
+    #[serde(default)]
+    include_abandoned: bool,
+    #[serde(default)]
+    exclude_batches: bool,
+    #[serde(default)]
+    include_benchmarks: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ByClientQuery {
+    since: Option<String>,
+    #[serde(default)]
+    include_abandoned: bool,
+    #[serde(default)]
+    exclude_batches: bool,
+    #[serde(default)]
+    include_benchmarks: bool,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DailyQuery {
+    /// UTC day as `YYYY-MM-DD`, today when omitted
+    date: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimeseriesQuery {
+    /// `hour`, `day` (default) or `week`
+    bucket: Option<String>,
+    /// Start of the range, widened to the start of its bucket
+    from: Option<String>,
+    /// End of the range, widened to the end of its bucket; defaults to now
+    to: Option<String>,
+    #[serde(default)]
+    include_abandoned: bool,
+    #[serde(default)]
+    exclude_batches: bool,
+    #[serde(default)]
+    include_benchmarks: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusCodesQuery {
+    since: Option<String>,
+    /// `hour`, `day` or `week` for a series per bucket as well
+    bucket: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    /// Only requests from this long ago onward, e.g. `7d`
+    since: Option<String>,
+    /// Start of the range, RFC 3339 or `YYYY-MM-DD`; takes precedence over `since`
+    from: Option<String>,
+    /// End of the range, exclusive
+    to: Option<String>,
+    model: Option<String>,
+    /// `false` to leave out failed requests
+    include_errors: Option<bool>,
+    /// CSV only: comma-separated columns to include, in that order; every column by default
+    fields: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnloadAdviceQuery {
+    #[serde(default)]
+    act: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChargebackQuery {
+    namespace: String,
+    #[serde(default = "default_period")]
+    period: String,
+    format: Option<String>,
+}
+
+/// Error messages `/stats/errors` returns unless told otherwise, and the most it returns
+const DEFAULT_RECENT_ERRORS: i64 = 10;
+const MAX_RECENT_ERRORS: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct CacheOpportunitiesQuery {
+    since: Option<String>,
+    /// Only report prompts repeated at least this often
+    min_occurrences: Option<i64>,
+    /// Clusters listed
+    limit: Option<usize>,
+}
+
+/// Clusters `/stats/cache-opportunities` lists unless told otherwise, and the most it lists
+const DEFAULT_CACHE_CLUSTERS: usize = 20;
+const MAX_CACHE_CLUSTERS: usize = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct RetentionQuery {
+    /// Keep requests from the last this many days
+    days: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ErrorsQuery {
+    since: Option<String>,
+    /// RFC 3339 or `YYYY-MM-DD`; overrides `since`
+    from: Option<String>,
+    to: Option<String>,
+    #[serde(default)]
+    include_abandoned: bool,
+    /// How many of the latest error messages to return
+    recent: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CostsQuery {
+    since: Option<String>,
+    /// RFC 3339 or `YYYY-MM-DD`; overrides `since`
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HeatmapQuery {
+    since: Option<String>,
+    /// RFC 3339 or `YYYY-MM-DD`; overrides `since`
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistogramQuery {
+    /// `input_tokens`, `output_tokens` or `duration_ms`
+    field: String,
+    /// Equal-width buckets of this size instead of the logarithmic ones
+    width: Option<i64>,
+    since: Option<String>,
+    /// RFC 3339 or `YYYY-MM-DD`; overrides `since`
+    from: Option<String>,
+    to: Option<String>,
+    #[serde(default)]
+    include_probes: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UtilizationQuery {
+    /// Days to report, ending with today
+    #[serde(default = "default_utilization_window")]
+    since: String,
+}
+
+fn default_utilization_window() -> String {
+    "30d".to_string()
 }
 
+fn default_period() -> String {
+    "month".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GlanceQuery {
+    /// Days of daily values to include, e.g. `14d`
+    sparkline: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BadgeQuery {
+    /// `tokens_month`, `requests_today` or `cost_month`
+    metric: Option<String>,
+    /// Only `shieldsio`, the default
+    style: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IncidentQuery {
+    /// How long to capture, e.g. `30m`; defaults to `INCIDENT_MINUTES`
+    duration: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyQuery {
+    since: Option<String>,
+    #[serde(default)]
+    repair: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartJobRequest {
+    kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoutingWeightsRequest {
+    /// Percent of sessions per canary; canaries left out keep their share
+    weights: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PromptVersionLabelRequest {
+    /// `null` or an empty string removes the label
+    label: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    name: Option<String>,
+    /// Predicate over the stored request, see `webhooks::filter`
+    filter: String,
+    url: String,
+    /// Key for the `X-Webhook-Signature` HMAC; deliveries are unsigned without one
+    secret: Option<String>,
+    /// `record` (default) or `summary`
+    payload: Option<String>,
+    /// Defaults to 60
+    max_per_minute: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookDeliveriesQuery {
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartBenchmarkRequest {
+    /// Models every sampled prompt is sent to
+    models: Vec<String>,
+    /// Stored prompts to sample at random
+    #[serde(default = "default_benchmark_sample")]
+    sample: i64,
+    /// Only prompts from this long ago onward; defaults to `30d`
+    since: Option<String>,
+    client_id: Option<String>,
+    /// Only prompts originally sent to this model
+    source_model: Option<String>,
+    /// Runs sent at the same time; defaults to and is capped at `BATCH_CONCURRENCY`
+    concurrency: Option<usize>,
+    /// Stop sending runs once their estimated cost reaches this many dollars
+    max_cost: Option<f64>,
+}
+
+fn default_benchmark_sample() -> i64 {
+    50
+}
+
+/// Most prompts one benchmark may sample
+const MAX_BENCHMARK_SAMPLE: i64 = 1000;
+
+/// Most models one benchmark may compare
+const MAX_BENCHMARK_MODELS: usize = 8;
+
+#[derive(Debug, Deserialize)]
+pub struct ContextFitQuery {
+    candidate_context: i64,
+    since: Option<String>,
+}
+
+/// Summary of every request, or of the models named by `model` (repeated or
+/// comma-separated). The live last-hour counts and energy estimate cover all traffic, so
+/// they are left out of a per-model summary.
 pub async fn get_summary(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<serde_json::Value>, crate::error::ProxyError> {
-    let stats = crate::db::get_summary_stats(&state.db).await?;
-    Ok(Json(json!(stats)))
+    Query(params): Query<SummaryQuery>,
+    Query(pairs): Query<Vec<(String, String)>>,
+) -> StatsResult<SummaryStats> {
+    let models = list_param(&pairs, "model");
+    let window = params
+        .period
+        .as_deref()
+        .map(|period| {
+            parse_duration(period)
+                .filter(|window| *window > chrono::Duration::zero())
+                .ok_or_else(|| {
+                    StatsError::BadRequest(format!(
+                        "Invalid period value '{}', expected e.g. 24h or 7d",
+                        period
+                    ))
+                })
+        })
+        .transpose()?;
+    if params.compare && window.is_none() {
+        return Err(StatsError::BadRequest(
+            "compare=true needs a period, e.g. period=7d".to_string(),
+        ));
+    }
+
+    let now = chrono::Utc::now();
+    let summarize = |from: Option<String>, to: Option<String>| {
+        let (state, models) = (&state, &models);
+        async move {
+            let filter = SummaryFilter {
+                include_abandoned: params.include_abandoned,
+                exclude_batches: params.exclude_batches,
+                include_benchmarks: params.include_benchmarks,
+                include_probes: params.include_probes,
+                models,
+                from: from.as_deref(),
+                to: to.as_deref(),
+            };
+            crate::db::get_summary_stats(&state.db, &filter, params.detail).await
+        }
+    };
+    let (from, to) = window
+        .map(|window| ((now - window).to_rfc3339(), now.to_rfc3339()))
+        .unzip();
+    let mut stats = summarize(from, to).await?;
+    if let Some(window) = window.filter(|_| params.compare) {
+        let previous = summarize(
+            Some((now - window - window).to_rfc3339()),
+            Some((now - window).to_rfc3339()),
+        )
+        .await?;
+        stats.comparison = Some(Box::new(SummaryComparison::new(&stats, previous)));
+    }
+    if !models.is_empty() {
+        return Ok(ApiResponse(stats));
+    }
+    stats.last_hour = Some(state.counters.totals_since(chrono::Duration::hours(1)));
+
+    let energy = &state.config.energy;
+    if energy.enabled() {
+        stats.energy_estimate = Some(
+            crate::db::get_energy_estimate(
+                &state.db,
+                energy.default_watts,
+                energy.grid_co2_g_per_kwh,
+            )
+            .await?,
+        );
+    }
+    Ok(ApiResponse(stats))
+}
+
+/// Today's totals and an optional daily sparkline, read from the rollup table only so
+/// status-bar widgets can poll it.
+pub async fn get_glance(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<GlanceQuery>,
+) -> StatsResult<Glance> {
+    let days = match params.sparkline.as_deref() {
+        None => None,
+        Some(value) => {
+            let days = parse_duration(value)
+                .filter(|window| *window == chrono::Duration::days(window.num_days()))
+                .map(|window| window.num_days())
+                .filter(|days| (1..=MAX_SPARKLINE_DAYS).contains(days))
+                .ok_or_else(|| {
+                    StatsError::BadRequest(format!(
+                        "Invalid sparkline value '{}', expected 1d to {}d",
+                        value, MAX_SPARKLINE_DAYS
+                    ))
+                })?;
+            Some(days)
+        }
+    };
+
+    let glance = crate::db::get_glance(&state.db, chrono::Utc::now().date_naive(), days).await?;
+    Ok(ApiResponse(glance))
+}
+
+/// One headline number in the shields.io endpoint format, read from the daily rollups.
+pub async fn get_badge(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<BadgeQuery>,
+) -> StatsResult<Badge> {
+    let style = params.style.as_deref().unwrap_or("shieldsio");
+    if style != "shieldsio" {
+        return Err(StatsError::BadRequest(format!(
+            "Invalid style '{}', expected shieldsio",
+            style
+        )));
+    }
+    let metric = params.metric.as_deref().unwrap_or_default();
+    let today = chrono::Utc::now().date_naive();
+    let month_start = today.with_day(1).unwrap_or(today);
+    let (label, value, message) = match metric {
+        "tokens_month" => {
+            let totals = get_totals_since(&state.db, month_start).await?;
+            let tokens = totals.tokens() as f64;
+            ("tokens this month", tokens, format::si(tokens))
+        }
+        "requests_today" => {
+            let totals = get_totals_since(&state.db, today).await?;
+            let requests = totals.requests as f64;
+            ("requests today", requests, format::si(requests))
+        }
+        "cost_month" => {
+            let totals = get_totals_since(&state.db, month_start).await?;
+            (
+                "cost this month",
+                totals.cost,
+                format::currency(totals.cost),
+            )
+        }
+        _ => {
+            return Err(StatsError::BadRequest(format!(
+                "Invalid metric '{}', expected one of {}",
+                metric,
+                BADGE_METRICS.join(", ")
+            )));
+        }
+    };
+
+    // The highest threshold the value has reached picks the color
+    let color = state
+        .config
+        .badge
+        .colors
+        .iter()
+        .filter(|rule| rule.metric == metric && value >= rule.threshold)
+        .max_by(|a, b| a.threshold.total_cmp(&b.threshold))
+        .map_or(DEFAULT_BADGE_COLOR, |rule| rule.color.as_str());
+    Ok(ApiResponse(Badge {
+        schema_version: 1,
+        label,
+        message,
+        color: color.to_string(),
+    }))
+}
+
+pub async fn get_self_diagnostics(
+    State(state): State<Arc<AppState>>,
+) -> StatsResult<DiagnosticsSnapshot> {
+    Ok(ApiResponse(state.diagnostics.snapshot(&state.buffers)))
+}
+
+/// The buffer tally in the Prometheus text format, for scrapers.
+pub async fn get_metrics(State(state): State<Arc<AppState>>) -> Response {
+    let body = state.buffers.snapshot().prometheus();
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
 }
 
 pub async fn get_by_model(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<serde_json::Value>, crate::error::ProxyError> {
-    let stats = crate::db::get_model_stats(&state.db).await?;
-    Ok(Json(json!({ "models": stats })))
+    Query(params): Query<ByModelQuery>,
+) -> StatsResult<ModelStatsResponse> {
+    let stats = crate::db::get_model_stats(
+        &state.db,
+        params.exclude_batches,
+        params.include_benchmarks,
+        params.include_probes,
+    )
+    .await?;
+    Ok(ApiResponse(ModelStatsResponse { models: stats }))
+}
+
+/// Every model name the proxy has recorded, including ones that only ever failed.
+pub async fn get_models(State(state): State<Arc<AppState>>) -> StatsResult<KnownModelsResponse> {
+    let models = crate::db::get_known_models(&state.db).await?;
+    Ok(ApiResponse(KnownModelsResponse { models }))
+}
+
+pub async fn get_by_endpoint(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ByEndpointQuery>,
+) -> StatsResult<EndpointStatsResponse> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let stats = crate::db::get_endpoint_stats(
+        &state.db,
+        since.as_deref(),
+        params.include_abandoned,
+        params.exclude_batches,
+        params.include_benchmarks,
+    )
+    .await?;
+    Ok(ApiResponse(EndpointStatsResponse { endpoints: stats }))
+}
+
+/// Usage per kind of request (chat, completion, embedding, other), so embeddings can be
+/// told apart from generation.
+pub async fn get_by_kind(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ByEndpointQuery>,
+) -> StatsResult<KindReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_kind_stats(
+        &state.db,
+        since.as_deref(),
+        params.include_abandoned,
+        params.exclude_batches,
+        params.include_benchmarks,
+    )
+    .await?;
+    Ok(ApiResponse(report))
+}
+
+/// Usage per client, so traffic from several people sharing the proxy can be told apart.
+pub async fn get_by_client(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ByClientQuery>,
+) -> StatsResult<ClientReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let limit = params.limit.clamp(1, 1000);
+    let report = crate::db::get_client_stats(
+        &state.db,
+        since.as_deref(),
+        params.include_abandoned,
+        params.exclude_batches,
+        params.include_benchmarks,
+        limit,
+    )
+    .await?;
+    Ok(ApiResponse(report))
+}
+
+/// One UTC day's totals with a row per model, like a small invoice.
+pub async fn get_daily(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DailyQuery>,
+) -> StatsResult<DailyReport> {
+    let date = match params.date.as_deref() {
+        Some(date) => chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+            StatsError::BadRequest(format!(
+                "Invalid date value '{}', expected YYYY-MM-DD",
+                date
+            ))
+        })?,
+        None => chrono::Utc::now().date_naive(),
+    };
+    let report = crate::db::get_daily_report(&state.db, date).await?;
+    Ok(ApiResponse(report))
+}
+
+/// Usage per hour, day or week, with every bucket in the range present. Without `from`,
+/// the series covers the last [`DEFAULT_SERIES_BUCKETS`] buckets.
+pub async fn get_timeseries(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TimeseriesQuery>,
+) -> StatsResult<TimeSeries> {
+    let bucket_name = params.bucket.as_deref().unwrap_or("day");
+    let bucket = Bucket::parse(bucket_name).ok_or_else(|| {
+        StatsError::BadRequest(format!(
+            "Invalid bucket '{}', expected hour, day or week",
+            bucket_name
+        ))
+    })?;
+
+    let to = match params.to.as_deref() {
+        Some(to) => parse_timestamp("to", to)?,
+        None => chrono::Utc::now(),
+    };
+    let aligned_to = bucket.align(to);
+    let to = if aligned_to == to {
+        to
+    } else {
+        aligned_to + bucket.width()
+    };
+    let from = match params.from.as_deref() {
+        Some(from) => bucket.align(parse_timestamp("from", from)?),
+        None => to - bucket.width() * DEFAULT_SERIES_BUCKETS as i32,
+    };
+
+    if from >= to {
+        return Err(StatsError::BadRequest("from must be before to".to_string()));
+    }
+    let buckets = (to - from).num_seconds() / bucket.width().num_seconds();
+    if buckets > MAX_BUCKETS {
+        return Err(StatsError::BadRequest(format!(
+            "Range spans {} buckets, at most {} are returned; use a wider bucket or a shorter range",
+            buckets, MAX_BUCKETS
+        )));
+    }
+
+    let series = crate::db::get_timeseries(
+        &state.db,
+        bucket,
+        from,
+        to,
+        params.include_abandoned,
+        params.exclude_batches,
+        params.include_benchmarks,
+    )
+    .await?;
+    Ok(ApiResponse(series))
+}
+
+/// Requests per HTTP status, optionally per time bucket, to see when errors started.
+pub async fn get_status_codes(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<StatusCodesQuery>,
+) -> StatsResult<StatusCodeReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let bucket = params
+        .bucket
+        .as_deref()
+        .map(|name| {
+            Bucket::parse(name).ok_or_else(|| {
+                StatsError::BadRequest(format!(
+                    "Invalid bucket '{}', expected hour, day or week",
+                    name
+                ))
+            })
+        })
+        .transpose()?;
+    let report = crate::db::get_status_codes(&state.db, since.as_deref(), bucket).await?;
+    Ok(ApiResponse(report))
+}
+
+/// Traffic per client library, with how many of its requests were warm-up probes.
+pub async fn get_by_sdk(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<BySdkQuery>,
+) -> StatsResult<SdkStatsResponse> {
+    let mut stats = crate::db::get_sdk_stats(&state.db, params.include_probes).await?;
+    for entry in &mut stats {
+        let fingerprint = SdkFingerprint {
+            name: entry.sdk_name.clone(),
+            version: entry.sdk_version.clone(),
+        };
+        entry.known_bad = state
+            .config
+            .known_bad_sdks
+            .iter()
+            .any(|pattern| fingerprint.matches(pattern));
+    }
+    Ok(ApiResponse(SdkStatsResponse { sdks: stats }))
+}
+
+pub async fn get_context_fit(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ContextFitQuery>,
+) -> StatsResult<ContextFitReport> {
+    if params.candidate_context <= 0 {
+        return Err(StatsError::BadRequest(
+            "candidate_context must be a positive token count".to_string(),
+        ));
+    }
+
+    let since = since_cutoff(params.since.as_deref())?;
+    let report =
+        crate::db::get_context_fit(&state.db, params.candidate_context, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+/// How close prompts come to filling each model's configured context window.
+pub async fn get_context_utilization(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<ContextUtilizationReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_context_utilization(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn get_truncation(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<TruncationReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_truncation(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+/// How successful requests finished, per model, to spot models that keep hitting `length`.
+pub async fn get_finish_reasons(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<FinishReasonReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_finish_reasons(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn get_determinism(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<DeterminismReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_determinism(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+/// One stored request by its proxy request id, or by its row id (the `id` listed by
+/// `/stats/recent`) when `{id}` is a number. Proxy request ids are UUIDs, so the two
+/// can't be confused.
+pub async fn get_request(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> StatsResult<StoredRequest> {
+    let request = match id.parse() {
+        Ok(row_id) => crate::db::get_request_by_id(&state.db, row_id).await?,
+        Err(_) => crate::db::get_request(&state.db, &id).await?,
+    };
+    let request = request.ok_or_else(|| StatsError::NotFound(format!("request {}", id)))?;
+    Ok(ApiResponse(request))
+}
+
+/// Looks a request up by the response id a client logged, e.g. `chatcmpl-...`.
+pub async fn get_request_by_response_id(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> StatsResult<StoredRequest> {
+    let request = crate::db::get_request_by_response_id(&state.db, &id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("request with response id {}", id)))?;
+    Ok(ApiResponse(request))
+}
+
+pub async fn get_request_tree(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> StatsResult<RequestNode> {
+    let tree = crate::db::get_request_tree(&state.db, &id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("request {}", id)))?;
+    Ok(ApiResponse(tree))
+}
+
+/// Usage per conversation, for requests that named one in the session header.
+pub async fn get_sessions(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SessionsQuery>,
+) -> StatsResult<SessionReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let limit = params.limit.clamp(1, 1000);
+    let report = crate::db::get_sessions(&state.db, since.as_deref(), limit).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn get_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> StatsResult<Session> {
+    let session = crate::db::get_session(&state.db, &id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("session {}", id)))?;
+    Ok(ApiResponse(session))
+}
+
+pub async fn get_kv_cache(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<KvCacheReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_kv_cache_stats(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+/// Failed requests split by who produced the status (LM Studio or the proxy) and its class.
+pub async fn get_errors(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ErrorsQuery>,
+) -> StatsResult<ErrorReport> {
+    let (from, to) = time_range(
+        params.since.as_deref(),
+        params.from.as_deref(),
+        params.to.as_deref(),
+    )?;
+    let filter = ErrorFilter {
+        from: from.as_deref(),
+        to: to.as_deref(),
+        include_abandoned: params.include_abandoned,
+        recent: params
+            .recent
+            .unwrap_or(DEFAULT_RECENT_ERRORS)
+            .clamp(0, MAX_RECENT_ERRORS),
+    };
+    let report = crate::db::get_error_report(&state.db, &filter).await?;
+    Ok(ApiResponse(report))
+}
+
+/// Equivalent API cost per model over a time range, at the rates stored on each request.
+pub async fn get_costs(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<CostsQuery>,
+) -> StatsResult<CostReport> {
+    let (from, to) = time_range(
+        params.since.as_deref(),
+        params.from.as_deref(),
+        params.to.as_deref(),
+    )?;
+    let report = crate::db::get_cost_report(&state.db, from.as_deref(), to.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+/// Hours per day each model spent with at least one request running, and that time as a
+/// share of the day.
+pub async fn get_utilization(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<UtilizationQuery>,
+) -> StatsResult<Utilization> {
+    let days = parse_duration(&params.since)
+        .filter(|window| *window == chrono::Duration::days(window.num_days()))
+        .map(|window| window.num_days())
+        .filter(|days| *days > 0)
+        .ok_or_else(|| {
+            StatsError::BadRequest(format!(
+                "Invalid since value '{}', expected whole days such as 7d or 30d",
+                params.since
+            ))
+        })?;
+    let today = chrono::Utc::now().date_naive();
+    let first = today - chrono::Duration::days(days - 1);
+    let utilization = crate::db::get_utilization(&state.db, first, today).await?;
+    Ok(ApiResponse(utilization))
+}
+
+/// Requests and tokens per day of the week and hour of the day, as full 7x24 grids.
+pub async fn get_heatmap(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HeatmapQuery>,
+) -> StatsResult<Heatmap> {
+    let (from, to) = time_range(
+        params.since.as_deref(),
+        params.from.as_deref(),
+        params.to.as_deref(),
+    )?;
+    let heatmap = crate::db::get_heatmap(&state.db, from.as_deref(), to.as_deref()).await?;
+    Ok(ApiResponse(heatmap))
+}
+
+/// How many successful requests fall into each size bucket of a token count or duration,
+/// for the models named by `model` (repeated or comma-separated) or all of them.
+pub async fn get_histogram(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HistogramQuery>,
+    Query(pairs): Query<Vec<(String, String)>>,
+) -> StatsResult<Histogram> {
+    let field = HistogramField::parse(&params.field).ok_or_else(|| {
+        StatsError::BadRequest(format!(
+            "Invalid field value '{}', expected input_tokens, output_tokens or duration_ms",
+            params.field
+        ))
+    })?;
+    let binning = match params.width {
+        None => Binning::Log,
+        Some(width) if width > 0 => Binning::Width(width),
+        Some(width) => {
+            return Err(StatsError::BadRequest(format!(
+                "Invalid width value {}, expected a positive number",
+                width
+            )));
+        }
+    };
+    let (from, to) = time_range(
+        params.since.as_deref(),
+        params.from.as_deref(),
+        params.to.as_deref(),
+    )?;
+    let models = list_param(&pairs, "model");
+
+    crate::db::get_histogram(
+        &state.db,
+        field,
+        binning,
+        from.as_deref(),
+        to.as_deref(),
+        &models,
+        params.include_probes,
+    )
+    .await?
+    .map(ApiResponse)
+    .map_err(|too_many| {
+        StatsError::BadRequest(format!(
+            "width={} would need {} buckets, at most {} are allowed; use a larger width",
+            params.width.unwrap_or_default(),
+            too_many.0,
+            MAX_HISTOGRAM_BUCKETS
+        ))
+    })
+}
+
+pub async fn get_chargeback(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ChargebackQuery>,
+) -> Result<Response, StatsError> {
+    let (start, end) = billing_period(&params.period)?;
+    let report = crate::db::get_chargeback(
+        &state.db,
+        &params.namespace,
+        &start.to_rfc3339(),
+        &end.to_rfc3339(),
+    )
+    .await?;
+
+    match params.format.as_deref() {
+        None | Some("json") => Ok(ApiResponse(report).into_response()),
+        Some("csv") => Ok((
+            [(header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+            crate::export::chargeback_csv(&report),
+        )
+            .into_response()),
+        Some(other) => Err(StatsError::BadRequest(format!(
+            "Invalid format '{}', expected json or csv",
+            other
+        ))),
+    }
+}
+
+/// Streams the matching requests as a CSV download, sent in chunks as rows are read.
+pub async fn export_csv(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ExportQuery>,
+) -> Result<Response, StatsError> {
+    let filter = export_filter(&params)?;
+    let all_fields = crate::export::csv_fields();
+    let fields = match params.fields.as_deref() {
+        None => all_fields,
+        Some(list) => {
+            let fields: Vec<String> = list
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(str::to_string)
+                .collect();
+            if fields.is_empty() {
+                return Err(StatsError::BadRequest(
+                    "fields must name at least one column".to_string(),
+                ));
+            }
+            if let Some(unknown) = fields.iter().find(|field| !all_fields.contains(field)) {
+                return Err(StatsError::BadRequest(format!(
+                    "Unknown field '{}'",
+                    unknown
+                )));
+            }
+            fields
+        }
+    };
+
+    let mut heading = crate::export::csv_header_for(&fields);
+    heading.push('\n');
+    Ok(export_download(
+        &state,
+        filter,
+        "csv",
+        "text/csv; charset=utf-8",
+        heading,
+        move |request| crate::export::csv_line_for(request, &fields),
+    ))
+}
+
+/// Streams the matching requests as JSON lines, one stored request (its row id and
+/// every serialized field) per line.
+pub async fn export_jsonl(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ExportQuery>,
+) -> Result<Response, StatsError> {
+    let filter = export_filter(&params)?;
+    Ok(export_download(
+        &state,
+        filter,
+        "jsonl",
+        "application/jsonl",
+        String::new(),
+        |request| serde_json::to_string(request).unwrap_or_default(),
+    ))
+}
+
+fn export_filter(params: &ExportQuery) -> Result<ExportFilter, StatsError> {
+    let (from, to) = time_range(
+        params.since.as_deref(),
+        params.from.as_deref(),
+        params.to.as_deref(),
+    )?;
+    Ok(ExportFilter {
+        from,
+        to,
+        model: params.model.clone(),
+        include_errors: params.include_errors.unwrap_or(true),
+    })
+}
+
+/// A file download of the requests matching `filter`, `heading` followed by one `line`
+/// per request. Rows are read on a separate task and sent in chunks, so the table is
+/// never held in memory.
+fn export_download(
+    state: &AppState,
+    filter: ExportFilter,
+    extension: &str,
+    content_type: &'static str,
+    heading: String,
+    line: impl Fn(&StoredRequest) -> String + Send + 'static,
+) -> Response {
+    let db = state.db.clone();
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, std::io::Error>>(4);
+    tokio::spawn(async move {
+        let mut chunk = heading;
+        let mut rows = crate::db::stream_requests(&db, &filter);
+        while let Some(request) = rows.next().await {
+            match request {
+                Ok(request) => {
+                    chunk.push_str(&line(&request));
+                    chunk.push('\n');
+                }
+                Err(e) => {
+                    // The headers are already out, so all that's left is to cut the body short
+                    tracing::error!("Export failed: {}", e);
+                    let _ = tx.send(Err(std::io::Error::other(e))).await;
+                    return;
+                }
+            }
+            if chunk.len() >= crate::export::EXPORT_CHUNK_BYTES
+                && tx.send(Ok(std::mem::take(&mut chunk))).await.is_err()
+            {
+                return; // The client went away
+            }
+        }
+        let _ = tx.send(Ok(chunk)).await;
+    });
+
+    let filename = format!(
+        "requests-{}.{}",
+        chrono::Utc::now().format("%Y%m%d-%H%M%S"),
+        extension
+    );
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+pub async fn get_retries(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<RetryStats> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let stats = crate::db::get_retry_stats(&state.db, since.as_deref(), None, None).await?;
+    Ok(ApiResponse(stats))
+}
+
+pub async fn get_unload_advice(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<UnloadAdviceQuery>,
+    headers: HeaderMap,
+) -> StatsResult<UnloadAdvice> {
+    if params.act {
+        require_admin(&state.config, &headers)?;
+    }
+
+    let models = list_models(&state.client, &state.config.lm_studio_url).await?;
+    let usage = crate::db::get_model_usage(&state.db).await?;
+    let threshold = state.config.unload_advisor_threshold;
+    let candidates = rank_loaded_models(&models, &usage, threshold, chrono::Utc::now());
+
+    let mut action = None;
+    if params.act
+        && let Some(top) = candidates.iter().find(|candidate| candidate.recommended)
+    {
+        let result = unload_model(&state.client, &state.config.lm_studio_url, &top.model).await;
+        let detail = json!({
+            "model": top.model,
+            "candidate": top,
+            "success": result.is_ok(),
+            "error": result.as_ref().err().map(|e| e.to_string()),
+        });
+        let event_id = crate::db::record_event(&state.db, "model_unload", &detail).await?;
+        result?;
+        tracing::info!(
+            "Unload advisor unloaded {} (score {:.2})",
+            top.model,
+            top.score
+        );
+        action = Some(UnloadAction {
+            unloaded: top.model.clone(),
+            event_id,
+        });
+    }
+
+    Ok(ApiResponse(UnloadAdvice {
+        threshold,
+        models: candidates,
+        action,
+    }))
+}
+
+pub async fn get_limit_triggers(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<LimitReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_limit_triggers(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn get_agent_overhead(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<AgentOverheadReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_agent_overhead(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn get_turn_latency(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<TurnLatencyReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_turn_latency(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn get_guardrails(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<GuardrailReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_guardrail_report(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn get_by_language(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<LanguageReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_language_report(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn get_stops(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<StopReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_stop_report(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn get_persistence_lag(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<PersistLagReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let alert_ms = state.config.persist_lag_alert_ms as i64;
+    let report = crate::db::get_persist_lag(&state.db, since.as_deref(), alert_ms).await?;
+    Ok(ApiResponse(report))
+}
+
+/// Repeated identical requests and what an exact-match response cache would have saved.
+pub async fn get_cache_opportunities(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<CacheOpportunitiesQuery>,
+) -> StatsResult<CacheOpportunityReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let min_occurrences = params.min_occurrences.unwrap_or(2);
+    if min_occurrences < 2 {
+        return Err(StatsError::BadRequest(
+            "min_occurrences must be at least 2".to_string(),
+        ));
+    }
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_CACHE_CLUSTERS)
+        .clamp(1, MAX_CACHE_CLUSTERS);
+    let report =
+        crate::db::get_cache_opportunities(&state.db, since.as_deref(), min_occurrences, limit)
+            .await?;
+    Ok(ApiResponse(report))
+}
+
+/// Average duration over consecutive groups of requests, to see latency drift over a
+/// long session.
+pub async fn get_latency_trend(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<LatencyTrendQuery>,
+) -> StatsResult<LatencyTrend> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let filter = TrendFilter {
+        model: params.model.as_deref(),
+        session_id: params.session.as_deref(),
+        since: since.as_deref(),
+    };
+    let window = params.window.clamp(1, 1000);
+    let limit = params.limit.clamp(1, 1000);
+    let trend = crate::db::get_latency_trend(&state.db, &filter, window, limit).await?;
+    Ok(ApiResponse(trend))
+}
+
+/// Prompts clients keep sending unchanged, with the tokens they cost.
+pub async fn get_duplicates(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DuplicatesQuery>,
+) -> StatsResult<DuplicateReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let limit = params.limit.clamp(1, 1000);
+    let report = crate::db::get_duplicates(&state.db, since.as_deref(), limit).await?;
+    Ok(ApiResponse(report))
+}
+
+/// The prompts that spent the most tokens, with a sample of each.
+pub async fn get_top_prompts(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TopPromptsQuery>,
+) -> StatsResult<TopPromptReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let limit = params.limit.clamp(1, 1000);
+    let report = crate::db::get_top_prompts(&state.db, since.as_deref(), limit).await?;
+    Ok(ApiResponse(report))
+}
+
+/// Usage per model for each of the latest weeks or months, from the usage rollups.
+pub async fn get_rollups(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RollupQuery>,
+) -> StatsResult<UsageRollupReport> {
+    let period_type = match params.period.as_deref() {
+        None | Some("week") => PERIOD_WEEK,
+        Some("month") => PERIOD_MONTH,
+        Some(other) => {
+            return Err(StatsError::BadRequest(format!(
+                "Invalid period value '{}', expected week or month",
+                other
+            )));
+        }
+    };
+    let limit = params.limit.clamp(1, 1000);
+    let report = crate::db::get_rollups(&state.db, period_type, limit).await?;
+    Ok(ApiResponse(report))
+}
+
+/// `POST /stats/rollup/rebuild`: regenerates the usage rollups from the stored requests,
+/// e.g. after an import or a retention prune.
+pub async fn rebuild_rollups(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> StatsResult<RebuildReport> {
+    require_admin(&state.config, &headers)?;
+    let report = crate::db::rebuild_rollups(&state.db).await?;
+    tracing::info!(
+        "Rebuilt usage rollups: {} weekly and {} monthly rows",
+        report.weekly_rows,
+        report.monthly_rows
+    );
+    Ok(ApiResponse(report))
+}
+
+/// Requests and output tokens per minute over the last 5 minutes, hour and day.
+pub async fn get_rate(State(state): State<Arc<AppState>>) -> StatsResult<RateReport> {
+    let report = crate::db::get_rate_report(&state.db).await?;
+    Ok(ApiResponse(report))
+}
+
+/// Each upstream's error rate and latency, with a verdict on every canary.
+pub async fn get_canary(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<CanaryReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let stats = crate::db::canary::get_upstream_stats(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(state.canary.report(
+        since,
+        stats,
+        &state.config.canary,
+    )))
+}
+
+/// `POST /admin/routing/weights`: changes canary shares on the fly. New sessions and
+/// sessions whose bucket moved switch upstream with their next request.
+pub async fn set_routing_weights(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<RoutingWeightsRequest>,
+) -> StatsResult<RoutingWeightsChange> {
+    require_admin(&state.config, &headers)?;
+    let changes: Vec<(String, f64)> = request.weights.into_iter().collect();
+    let previous = state
+        .canary
+        .set_weights(&changes)
+        .map_err(StatsError::BadRequest)?;
+    let weights = state.canary.weights();
+    tracing::info!(
+        "Routing weights changed from {:?} to {:?}",
+        previous,
+        weights
+    );
+
+    let detail = json!({ "previous": previous, "weights": weights });
+    let event_id = crate::db::record_event(&state.db, ROUTING_WEIGHTS_EVENT, &detail).await?;
+    Ok(ApiResponse(RoutingWeightsChange {
+        previous,
+        weights,
+        event_id,
+    }))
+}
+
+/// Usage, truncation and output length per system prompt version.
+pub async fn get_by_prompt_version(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<PromptVersionReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_prompt_version_stats(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+/// `PATCH /admin/prompt-versions/{hash}`: names a system prompt version for the reports.
+pub async fn set_prompt_version_label(
+    State(state): State<Arc<AppState>>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<PromptVersionLabelRequest>,
+) -> StatsResult<PromptVersion> {
+    require_admin(&state.config, &headers)?;
+    let label = request
+        .label
+        .as_deref()
+        .map(str::trim)
+        .filter(|label| !label.is_empty());
+    let version = crate::db::set_prompt_version_label(&state.db, &hash, label)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("prompt version {}", hash)))?;
+    Ok(ApiResponse(version))
+}
+
+/// Streamed and non-streamed requests side by side, with how many streams carried no usage.
+pub async fn get_streaming(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<StreamingReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_streaming_report(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+/// How many output tokens are missing from the totals or only estimated.
+pub async fn get_estimation_gap(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<EstimationGap> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_estimation_gap(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+/// The sampling parameters each model was sent, and how often each was left out.
+pub async fn get_params(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<ParamReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_param_stats(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+/// Inferred idle unloads per model and the latency they added.
+pub async fn get_reloads(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<ReloadReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_reload_report(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn get_prompt_quality(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SinceQuery>,
+) -> StatsResult<PromptQualityReport> {
+    let since = since_cutoff(params.since.as_deref())?;
+    let report = crate::db::get_prompt_quality(&state.db, since.as_deref()).await?;
+    Ok(ApiResponse(report))
+}
+
+/// Tracked requests the proxy is working on right now, from arrival until their row is
+/// written.
+pub async fn get_active(State(state): State<Arc<AppState>>) -> StatsResult<ActiveRequestsResponse> {
+    let requests = state.active.snapshot();
+    let mut by_model: BTreeMap<String, usize> = BTreeMap::new();
+    for request in &requests {
+        *by_model.entry(request.model.clone()).or_default() += 1;
+    }
+    Ok(ApiResponse(ActiveRequestsResponse {
+        count: requests.len(),
+        by_model,
+        requests,
+    }))
 }
 
 pub async fn get_recent(
     State(state): State<Arc<AppState>>,
-    Query(params): Query<PaginationQuery>,
-) -> Result<Json<serde_json::Value>, crate::error::ProxyError> {
-    let limit = params.limit.min(1000).max(1); // Cap at 1000
-    let requests = crate::db::get_recent_requests(&state.db, limit).await?;
-    Ok(Json(json!({ "requests": requests })))
+    Query(params): Query<RecentQuery>,
+) -> StatsResult<RecentRequestsResponse> {
+    let limit = params.limit.clamp(1, 1000); // Cap at 1000
+    let filter = RecentFilter {
+        before_id: params.before_id,
+        model: params.model,
+        endpoint: params.endpoint,
+        errors_only: params.errors_only,
+    };
+    let page = |source, fallback_reason, requests: Vec<RecentRequest>| {
+        // A full page may have more behind it; a short one is the end of the history
+        let next_cursor = if requests.len() as i64 == limit {
+            requests.iter().filter_map(|request| request.id).min()
+        } else {
+            None
+        };
+        ApiResponse(RecentRequestsResponse {
+            source,
+            fallback_reason,
+            next_cursor,
+            requests,
+        })
+    };
+    let from_memory = |fallback_reason| {
+        page(
+            "memory",
+            fallback_reason,
+            state.recent.latest(limit as usize, &filter),
+        )
+    };
+
+    match params.source.as_deref() {
+        None | Some("database") => {}
+        Some("memory") => return Ok(from_memory(None)),
+        Some(other) => {
+            return Err(StatsError::BadRequest(format!(
+                "Invalid source '{}', expected database or memory",
+                other
+            )));
+        }
+    }
+
+    // Keep answering from memory while the database is unavailable
+    match crate::db::get_recent_requests(&state.db, limit, &filter).await {
+        Ok(requests) => Ok(page("database", None, requests)),
+        Err(e) => {
+            tracing::warn!("Recent requests query failed, serving from memory: {}", e);
+            Ok(from_memory(Some("database query failed")))
+        }
+    }
 }
 
-pub async fn health_check() -> Json<serde_json::Value> {
-    Json(json!({
-        "status": "ok",
-        "service": "lms_metrics_proxy_proxy"
+pub async fn search_requests(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SearchQuery>,
+) -> StatsResult<SearchResponse> {
+    let query = params.q.as_deref().unwrap_or_default().trim();
+    if query.is_empty() {
+        return Err(StatsError::BadRequest("q must not be empty".to_string()));
+    }
+    if query.chars().count() > MAX_SEARCH_CHARS {
+        return Err(StatsError::BadRequest(format!(
+            "q is too long, at most {} characters",
+            MAX_SEARCH_CHARS
+        )));
+    }
+    let limit = params.limit.clamp(1, 1000);
+
+    let results = crate::db::search_requests(&state.db, query, limit, params.before_id).await?;
+    // A full page may have more behind it; a short one is the end of the matches
+    let next_cursor = if results.len() as i64 == limit {
+        results.iter().filter_map(|hit| hit.request.id).min()
+    } else {
+        None
+    };
+    Ok(ApiResponse(SearchResponse {
+        query: query.to_string(),
+        next_cursor,
+        results,
     }))
 }
+
+pub async fn list_jobs(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> StatsResult<JobsResponse> {
+    require_admin(&state.config, &headers)?;
+    let jobs = crate::db::jobs::list_jobs(&state.db).await?;
+    Ok(ApiResponse(JobsResponse { jobs }))
+}
+
+pub async fn get_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> StatsResult<MaintenanceJob> {
+    require_admin(&state.config, &headers)?;
+    let job = crate::db::jobs::get_job(&state.db, id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("job {}", id)))?;
+    Ok(ApiResponse(job))
+}
+
+pub async fn start_job(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<StartJobRequest>,
+) -> StatsResult<MaintenanceJob> {
+    require_admin(&state.config, &headers)?;
+    let kind = JobKind::from_name(&request.kind).ok_or_else(|| {
+        let known: Vec<_> = JobKind::ALL.iter().map(|kind| kind.name()).collect();
+        StatsError::BadRequest(format!(
+            "unknown job kind '{}', expected one of: {}",
+            request.kind,
+            known.join(", ")
+        ))
+    })?;
+
+    let id =
+        state.jobs.start(kind).await?.ok_or_else(|| {
+            StatsError::Conflict(format!("a {} job is already running", kind.name()))
+        })?;
+    let job = crate::db::jobs::get_job(&state.db, id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("job {}", id)))?;
+    Ok(ApiResponse(job))
+}
+
+/// `GET /admin/retention/simulate?days=30`: what pruning requests older than `days` would
+/// remove, without deleting anything.
+pub async fn simulate_retention(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RetentionQuery>,
+    headers: HeaderMap,
+) -> StatsResult<RetentionSimulation> {
+    require_admin(&state.config, &headers)?;
+    let window = chrono::Duration::try_days(params.days)
+        .filter(|_| params.days >= 0)
+        .ok_or_else(|| StatsError::BadRequest(format!("Invalid days value {}", params.days)))?;
+    let cutoff = chrono::Utc::now() - window;
+    let simulation = crate::db::simulate_prune(&state.db, &cutoff.to_rfc3339()).await?;
+    Ok(ApiResponse(simulation))
+}
+
+/// `GET /admin/db`: every file in the database's directory, with its size, purpose and
+/// last modification.
+pub async fn get_data_directory(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> StatsResult<DataDirectory> {
+    require_admin(&state.config, &headers)?;
+    let directory = storage::get_data_directory(state.config.database_path()).await?;
+    Ok(ApiResponse(directory))
+}
+
+/// `POST /admin/db/checkpoint`: folds the WAL into the database and truncates it.
+pub async fn checkpoint_database(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> StatsResult<CheckpointReport> {
+    require_admin(&state.config, &headers)?;
+    let report = storage::checkpoint_wal(&state.db, state.config.database_path()).await?;
+    tracing::info!(
+        "WAL checkpoint: {} -> {} bytes",
+        report.wal_bytes_before,
+        report.wal_bytes_after
+    );
+    Ok(ApiResponse(report))
+}
+
+/// `POST /admin/verify?since=6h&repair=true`
+pub async fn verify_counters(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<VerifyQuery>,
+    headers: HeaderMap,
+) -> StatsResult<VerificationReport> {
+    require_admin(&state.config, &headers)?;
+    let since = params
+        .since
+        .as_deref()
+        .map(|since| {
+            parse_duration(since).ok_or_else(|| {
+                StatsError::BadRequest(format!(
+                    "Invalid since value '{}', expected e.g. 6h or 1d",
+                    since
+                ))
+            })
+        })
+        .transpose()?;
+
+    let report = state.verifier.run(since, params.repair).await?;
+    Ok(ApiResponse(report))
+}
+
+pub async fn start_incident(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<IncidentQuery>,
+    headers: HeaderMap,
+) -> StatsResult<ActiveIncident> {
+    require_admin(&state.config, &headers)?;
+    let duration = match params.duration.as_deref() {
+        Some(duration) => parse_duration(duration)
+            .filter(|duration| *duration > chrono::Duration::zero())
+            .ok_or_else(|| {
+                StatsError::BadRequest(format!(
+                    "Invalid duration value '{}', expected e.g. 30m or 2h",
+                    duration
+                ))
+            })?,
+        None => chrono::Duration::minutes(state.config.incident.duration_minutes),
+    };
+
+    let incident = state
+        .incidents
+        .start(&state.db, crate::incidents::REASON_MANUAL, duration)
+        .await?;
+    Ok(ApiResponse(incident))
+}
+
+pub async fn list_webhooks(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> StatsResult<WebhooksResponse> {
+    require_admin(&state.config, &headers)?;
+    let webhooks = crate::db::webhooks::list_webhooks(&state.db).await?;
+    Ok(ApiResponse(WebhooksResponse { webhooks }))
+}
+
+/// `POST /admin/webhooks`: subscribes a URL to requests matching a filter, from the next
+/// stored request on.
+pub async fn create_webhook(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<CreateWebhookRequest>,
+) -> StatsResult<Webhook> {
+    require_admin(&state.config, &headers)?;
+    let checked = webhooks::check(NewWebhook {
+        name: request.name.filter(|name| !name.is_empty()),
+        filter: request.filter,
+        url: request.url,
+        secret: request.secret.filter(|secret| !secret.is_empty()),
+        payload: request
+            .payload
+            .unwrap_or_else(|| webhooks::PAYLOAD_RECORD.to_string()),
+        max_per_minute: request.max_per_minute.unwrap_or(60),
+    })
+    .map_err(StatsError::BadRequest)?;
+
+    let webhook = state.webhooks.add(checked).await?;
+    tracing::info!("Webhook {} added for {}", webhook.id, webhook.url);
+    Ok(ApiResponse(webhook))
+}
+
+pub async fn delete_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> StatsResult<Webhook> {
+    require_admin(&state.config, &headers)?;
+    let webhook = state
+        .webhooks
+        .remove(id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("webhook {}", id)))?;
+    tracing::info!("Webhook {} removed", id);
+    Ok(ApiResponse(webhook))
+}
+
+/// `GET /admin/webhooks/{id}/deliveries`: the webhook's latest deliveries, newest first.
+pub async fn get_webhook_deliveries(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<WebhookDeliveriesQuery>,
+    headers: HeaderMap,
+) -> StatsResult<WebhookDeliveriesResponse> {
+    require_admin(&state.config, &headers)?;
+    let webhook = crate::db::webhooks::get_webhook(&state.db, id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("webhook {}", id)))?;
+    let deliveries =
+        crate::db::webhooks::list_deliveries(&state.db, id, params.limit.clamp(1, 500)).await?;
+    Ok(ApiResponse(WebhookDeliveriesResponse {
+        webhook,
+        deliveries,
+    }))
+}
+
+pub async fn list_incidents(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> StatsResult<IncidentsResponse> {
+    require_admin(&state.config, &headers)?;
+    let incidents = crate::db::incidents::list_incidents(&state.db).await?;
+    Ok(ApiResponse(IncidentsResponse {
+        active: state.incidents.active(),
+        incidents,
+    }))
+}
+
+pub async fn get_batch(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> StatsResult<Batch> {
+    require_admin(&state.config, &headers)?;
+    let batch = crate::db::batches::get_batch(&state.db, &id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("batch {}", id)))?;
+    Ok(ApiResponse(batch))
+}
+
+/// Responses of every line that has run so far, as a JSONL download.
+pub async fn get_batch_results(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatsError> {
+    require_admin(&state.config, &headers)?;
+    if crate::db::batches::get_batch(&state.db, &id)
+        .await?
+        .is_none()
+    {
+        return Err(StatsError::NotFound(format!("batch {}", id)));
+    }
+
+    let results = crate::db::batches::get_item_results(&state.db, &id).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/jsonl".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.jsonl\"", id),
+            ),
+        ],
+        crate::export::batch_results_jsonl(&id, &results),
+    )
+        .into_response())
+}
+
+/// Stops a batch from starting more lines. Lines already sent finish and keep their results.
+pub async fn cancel_batch(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> StatsResult<Batch> {
+    require_admin(&state.config, &headers)?;
+    let batch = crate::db::batches::get_batch(&state.db, &id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("batch {}", id)))?;
+    if matches!(batch.status.as_str(), BATCH_COMPLETED | BATCH_CANCELLED) {
+        return Err(StatsError::Conflict(format!(
+            "batch {} is already {}",
+            id, batch.status
+        )));
+    }
+
+    if !state.batches.cancel(&id) {
+        // Not running in this process, so nothing else will touch its lines
+        crate::db::batches::cancel_pending_items(&state.db, &id).await?;
+        crate::db::batches::set_batch_status(&state.db, &id, BATCH_CANCELLED).await?;
+    }
+    Ok(ApiResponse(batch))
+}
+
+/// `POST /admin/benchmark`: samples stored prompts, stores a run of each against every
+/// model, and starts sending them in the background.
+pub async fn start_benchmark(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<StartBenchmarkRequest>,
+) -> StatsResult<BenchmarkReport> {
+    require_admin(&state.config, &headers)?;
+    let mut models = Vec::new();
+    for model in request.models {
+        let model = model.trim().to_string();
+        if !model.is_empty() && !models.contains(&model) {
+            models.push(model);
+        }
+    }
+    if models.is_empty() || models.len() > MAX_BENCHMARK_MODELS {
+        return Err(StatsError::BadRequest(format!(
+            "models must list between 1 and {} models",
+            MAX_BENCHMARK_MODELS
+        )));
+    }
+    if !(1..=MAX_BENCHMARK_SAMPLE).contains(&request.sample) {
+        return Err(StatsError::BadRequest(format!(
+            "sample must be between 1 and {}",
+            MAX_BENCHMARK_SAMPLE
+        )));
+    }
+    if request.max_cost.is_some_and(|cost| cost <= 0.0) {
+        return Err(StatsError::BadRequest(
+            "max_cost must be positive".to_string(),
+        ));
+    }
+    let since = request.since.as_deref().unwrap_or("30d");
+    let cutoff = since_cutoff(Some(since))?;
+    let limit = state.config.batch_concurrency.max(1);
+    let concurrency = request.concurrency.unwrap_or(limit).clamp(1, limit);
+
+    let prompts = crate::db::benchmarks::sample_prompts(
+        &state.db,
+        &PromptFilter {
+            since: cutoff.as_deref(),
+            client_id: request.client_id.as_deref(),
+            source_model: request.source_model.as_deref(),
+            sample: request.sample,
+        },
+    )
+    .await?;
+    if prompts.is_empty() {
+        return Err(StatsError::BadRequest(
+            "no stored chat prompts match the filter".to_string(),
+        ));
+    }
+
+    // Every model gets each prompt before the next one, so partial results compare evenly
+    let mut runs = Vec::new();
+    for prompt in &prompts {
+        for model in &models {
+            let mut body = serde_json::Map::new();
+            if let Some(Value::Object(params)) = &prompt.sampling_params {
+                body.extend(params.clone());
+            } else if let Some(max_tokens) = prompt.max_tokens {
+                body.insert("max_tokens".to_string(), json!(max_tokens));
+            }
+            body.insert("model".to_string(), json!(model));
+            body.insert("messages".to_string(), prompt.messages.clone());
+            body.insert("stream".to_string(), json!(true));
+            runs.push((prompt.id, model.clone(), Value::Object(body).to_string()));
+        }
+    }
+
+    let spec = json!({
+        "models": models,
+        "sample": request.sample,
+        "since": since,
+        "client_id": request.client_id,
+        "source_model": request.source_model,
+        "concurrency": concurrency,
+        "max_cost": request.max_cost,
+        "prompts": prompts.len(),
+    });
+    let id = format!("bench_{}", uuid::Uuid::new_v4().simple());
+    crate::db::benchmarks::create_benchmark(&state.db, &id, &spec, &runs).await?;
+    tracing::info!(
+        "Started benchmark {}: {} prompts against {} models",
+        id,
+        prompts.len(),
+        models.len()
+    );
+
+    state.benchmarks.start(state.clone(), id.clone());
+    let report = crate::db::benchmarks::get_report(&state.db, &id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("benchmark {}", id)))?;
+    Ok(ApiResponse(report))
+}
+
+/// Progress and per-model results so far, while running and after.
+pub async fn get_benchmark(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> StatsResult<BenchmarkReport> {
+    require_admin(&state.config, &headers)?;
+    let report = crate::db::benchmarks::get_report(&state.db, &id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("benchmark {}", id)))?;
+    Ok(ApiResponse(report))
+}
+
+/// Stops a benchmark from sending more runs. Runs in flight finish and are reported.
+pub async fn cancel_benchmark(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> StatsResult<BenchmarkReport> {
+    require_admin(&state.config, &headers)?;
+    let report = crate::db::benchmarks::get_report(&state.db, &id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("benchmark {}", id)))?;
+    let status = report.benchmark.status.as_str();
+    if !matches!(status, BENCHMARK_QUEUED | BENCHMARK_RUNNING) {
+        return Err(StatsError::Conflict(format!(
+            "benchmark {} is already {}",
+            id, status
+        )));
+    }
+
+    if !state.benchmarks.cancel(&id) {
+        // Not running in this process, so nothing else will touch its runs
+        crate::db::benchmarks::cancel_pending_runs(&state.db, &id).await?;
+        crate::db::benchmarks::set_benchmark_status(
+            &state.db,
+            &id,
+            crate::db::benchmarks::BENCHMARK_CANCELLED,
+        )
+        .await?;
+    }
+    Ok(ApiResponse(report))
+}
+
+/// `POST /admin/jobs/{id}/{pause|resume|cancel}`
+pub async fn control_job(
+    State(state): State<Arc<AppState>>,
+    Path((id, action)): Path<(i64, String)>,
+    headers: HeaderMap,
+) -> StatsResult<MaintenanceJob> {
+    require_admin(&state.config, &headers)?;
+    let control = match action.as_str() {
+        "pause" => Control::Pause,
+        "resume" => Control::Run,
+        "cancel" => Control::Cancel,
+        _ => {
+            return Err(StatsError::NotFound(format!(
+                "job action '{}', expected pause, resume or cancel",
+                action
+            )));
+        }
+    };
+
+    let job = crate::db::jobs::get_job(&state.db, id)
+        .await?
+        .ok_or_else(|| StatsError::NotFound(format!("job {}", id)))?;
+    if !state.jobs.control(id, control) {
+        return Err(StatsError::Conflict(format!(
+            "job {} is not running (status {})",
+            id, job.status
+        )));
+    }
+    Ok(ApiResponse(job))
+}
+
+/// `GET /status`: whether the upstream is up, its uptime and recent outages, as HTML for
+/// browsers and JSON otherwise. Served without a token when `STATUS_PAGE` is set, so it
+/// carries nothing about usage.
+pub async fn get_status(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, StatsError> {
+    let report = crate::status::report(
+        &state.db,
+        state.config.status_probe_interval_secs,
+        chrono::Utc::now(),
+    )
+    .await?;
+    let wants_html = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    if wants_html {
+        return Ok(axum::response::Html(crate::status::render_html(&report)).into_response());
+    }
+    Ok(ApiResponse(report).into_response())
+}
+
+pub async fn health_check(State(state): State<Arc<AppState>>) -> ApiResponse<HealthResponse> {
+    let oldest_unpersisted_ms = state.diagnostics.oldest_unpersisted_ms();
+    ApiResponse(HealthResponse {
+        status: "ok",
+        service: "lms_metrics_proxy_proxy",
+        last_verification: state.verifier.last(),
+        oldest_unpersisted_ms,
+        persistence_lagging: oldest_unpersisted_ms > state.config.persist_lag_alert_ms,
+    })
+}
@@ -0,0 +1,108 @@
+//! The versioned stats API. Every stats route is mounted under [`API_PREFIX`] as well as
+//! at its original path, and `/api/v1/` lists what is mounted for scripts to discover.
+
+use axum::routing::{MethodRouter, get};
+use serde::Serialize;
+
+use crate::stats::auth::Access;
+use crate::stats::response::ApiResponse;
+
+/// Prefix of the current API version; the unprefixed paths stay as aliases of it
+pub const API_PREFIX: &str = "/api/v1";
+
+/// The routes mounted on one listener.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiIndex {
+    /// The proxy's version, from `Cargo.toml`
+    pub version: &'static str,
+    pub prefix: &'static str,
+    /// In registration order
+    pub routes: Vec<IndexRoute>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexRoute {
+    pub path: String,
+    /// The same route without the version prefix
+    pub alias: &'static str,
+    pub access: Access,
+}
+
+impl ApiIndex {
+    pub fn new(routes: Vec<IndexRoute>) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            prefix: API_PREFIX,
+            routes,
+        }
+    }
+
+    /// A handler answering with this index.
+    pub fn into_handler<S: Clone + Send + Sync + 'static>(self) -> MethodRouter<S> {
+        get(move || {
+            let index = self.clone();
+            async move { ApiResponse(index) }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    fn routes() -> Vec<IndexRoute> {
+        vec![
+            IndexRoute {
+                path: format!("{API_PREFIX}/stats/summary"),
+                alias: "/stats/summary",
+                access: Access::Viewer,
+            },
+            IndexRoute {
+                path: format!("{API_PREFIX}/admin/jobs"),
+                alias: "/admin/jobs",
+                access: Access::Admin,
+            },
+        ]
+    }
+
+    #[test]
+    fn index_carries_the_crate_version_and_routes_in_order() {
+        let index = serde_json::to_value(ApiIndex::new(routes())).unwrap();
+        assert_eq!(
+            index,
+            json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "prefix": "/api/v1",
+                "routes": [
+                    {
+                        "path": "/api/v1/stats/summary",
+                        "alias": "/stats/summary",
+                        "access": "viewer"
+                    },
+                    { "path": "/api/v1/admin/jobs", "alias": "/admin/jobs", "access": "admin" }
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn handler_answers_every_call_with_the_index() {
+        let handler = ApiIndex::new(routes()).into_handler();
+        let router: Router = Router::new().route("/api/v1/", handler);
+        for _ in 0..2 {
+            let request = Request::get("/api/v1/").body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), 200);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["routes"][1]["alias"], "/admin/jobs");
+            assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        }
+    }
+}
@@ -1,3 +1,27 @@
+pub mod advisor;
+pub mod auth;
+pub mod error;
 pub mod handlers;
+pub mod index;
+pub mod params;
+pub mod response;
+pub mod shape;
 
-pub use handlers::{get_by_model, get_recent, get_summary, health_check};
+pub use handlers::{
+    cancel_batch, cancel_benchmark, checkpoint_database, control_job, create_webhook,
+    delete_webhook, export_csv, export_jsonl, get_active, get_agent_overhead, get_badge, get_batch,
+    get_batch_results, get_benchmark, get_by_client, get_by_endpoint, get_by_kind, get_by_language,
+    get_by_model, get_by_prompt_version, get_by_sdk, get_cache_opportunities, get_canary,
+    get_chargeback, get_context_fit, get_context_utilization, get_costs, get_daily,
+    get_data_directory, get_determinism, get_duplicates, get_errors, get_estimation_gap,
+    get_finish_reasons, get_glance, get_guardrails, get_heatmap, get_histogram, get_job,
+    get_kv_cache, get_latency_trend, get_limit_triggers, get_metrics, get_models, get_params,
+    get_persistence_lag, get_prompt_quality, get_rate, get_recent, get_reloads, get_request,
+    get_request_by_response_id, get_request_tree, get_retries, get_rollups,
+    get_self_diagnostics, get_session, get_sessions, get_status, get_status_codes, get_stops,
+    get_streaming, get_summary, get_timeseries, get_top_prompts, get_truncation, get_turn_latency,
+    get_unload_advice, get_utilization, get_webhook_deliveries, health_check, list_incidents,
+    list_jobs, list_webhooks, rebuild_rollups, search_requests, set_prompt_version_label,
+    set_routing_weights, simulate_retention, start_benchmark, start_incident, start_job,
+    verify_counters,
+};
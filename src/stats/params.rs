@@ -0,0 +1,117 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+
+use crate::stats::error::StatsError;
+
+/// Parses a compact duration such as `90s`, `15m`, `12h`, `30d` or `2w`.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount.parse().ok()?;
+
+    match unit {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => None,
+    }
+}
+
+/// Converts an optional `since` window into an RFC 3339 lower bound for `start_time`.
+pub fn since_cutoff(since: Option<&str>) -> Result<Option<String>, StatsError> {
+    let Some(since) = since else {
+        return Ok(None);
+    };
+
+    let window = parse_duration(since).ok_or_else(|| {
+        StatsError::BadRequest(format!(
+            "Invalid since value '{}', expected e.g. 24h or 30d",
+            since
+        ))
+    })?;
+
+    Ok(Some((Utc::now() - window).to_rfc3339()))
+}
+
+/// Parses an absolute time given as RFC 3339 or a `YYYY-MM-DD` date (midnight UTC).
+pub fn parse_timestamp(name: &str, value: &str) -> Result<DateTime<Utc>, StatsError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.to_utc())
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(chrono::NaiveTime::MIN).and_utc())
+        })
+        .ok_or_else(|| {
+            StatsError::BadRequest(format!(
+                "Invalid {} value '{}', expected RFC 3339 or YYYY-MM-DD",
+                name, value
+            ))
+        })
+}
+
+/// Resolves `from`/`to` timestamps into RFC 3339 bounds, falling back to a `since` window
+/// for the lower bound.
+pub fn time_range(
+    since: Option<&str>,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<(Option<String>, Option<String>), StatsError> {
+    let from = match from {
+        Some(from) => Some(parse_timestamp("from", from)?.to_rfc3339()),
+        None => since_cutoff(since)?,
+    };
+    let to = match to {
+        Some(to) => Some(parse_timestamp("to", to)?.to_rfc3339()),
+        None => None,
+    };
+    Ok((from, to))
+}
+
+/// Collects every value of a query parameter that may be repeated (`?model=a&model=b`) or
+/// comma-separated (`?model=a,b`), in order and without duplicates.
+pub fn list_param(pairs: &[(String, String)], name: &str) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+    for (key, value) in pairs {
+        if key != name {
+            continue;
+        }
+        for value in value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            if !values.iter().any(|seen| seen == value) {
+                values.push(value.to_string());
+            }
+        }
+    }
+    values
+}
+
+/// Resolves a billing period to its `[start, end)` bounds in UTC: `month` (the current
+/// calendar month), `previous-month`, or an explicit `YYYY-MM`.
+pub fn billing_period(period: &str) -> Result<(DateTime<Utc>, DateTime<Utc>), StatsError> {
+    let today = Utc::now().date_naive();
+    let this_month = NaiveDate::from_ymd_opt(today.year(), today.month(), 1);
+    let first = match period {
+        "month" => this_month,
+        "previous-month" => {
+            this_month.and_then(|month| month.checked_sub_months(chrono::Months::new(1)))
+        }
+        explicit => NaiveDate::parse_from_str(&format!("{}-01", explicit), "%Y-%m-%d").ok(),
+    }
+    .ok_or_else(|| {
+        StatsError::BadRequest(format!(
+            "Invalid period '{}', expected month, previous-month or YYYY-MM",
+            period
+        ))
+    })?;
+
+    let next = first
+        .checked_add_months(chrono::Months::new(1))
+        .ok_or_else(|| StatsError::BadRequest(format!("Invalid period '{}'", period)))?;
+    Ok((
+        first.and_time(chrono::NaiveTime::MIN).and_utc(),
+        next.and_time(chrono::NaiveTime::MIN).and_utc(),
+    ))
+}
@@ -0,0 +1,140 @@
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::stats::error::StatsError;
+use crate::stats::shape;
+
+/// A successful stats response. The body is `T` serialized as-is; anything every
+/// endpoint should carry (an envelope, cache headers) belongs here.
+#[derive(Debug)]
+pub struct ApiResponse<T>(pub T);
+
+impl<T: Serialize> IntoResponse for ApiResponse<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.0).into_response();
+        response.extensions_mut().insert(shape::FieldNames);
+        response
+    }
+}
+
+pub type StatsResult<T> = Result<ApiResponse<T>, StatsError>;
+
+#[derive(Debug, Serialize)]
+pub struct ModelStatsResponse {
+    pub models: Vec<crate::db::models::ModelStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KnownModelsResponse {
+    pub models: Vec<crate::db::known_models::KnownModel>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EndpointStatsResponse {
+    pub endpoints: Vec<crate::db::models::EndpointStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SdkStatsResponse {
+    pub sdks: Vec<crate::db::sdk::SdkStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActiveRequestsResponse {
+    pub count: usize,
+    /// Active requests per model
+    pub by_model: BTreeMap<String, usize>,
+    /// Longest-running first
+    pub requests: Vec<crate::proxy::active::ActiveRequest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecentRequestsResponse {
+    /// `database` or `memory`
+    pub source: &'static str,
+    /// Why memory was used when the database was asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_reason: Option<&'static str>,
+    /// Pass as `before_id` for the next (older) page; `null` on the last page
+    pub next_cursor: Option<i64>,
+    pub requests: Vec<crate::db::models::RecentRequest>,
+}
+
+/// A badge in the shields.io endpoint schema.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Badge {
+    pub schema_version: u8,
+    pub label: &'static str,
+    pub message: String,
+    pub color: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResponse {
+    pub query: String,
+    /// Pass as `before_id` for the next (older) page; `null` on the last page
+    pub next_cursor: Option<i64>,
+    pub results: Vec<crate::db::search::SearchHit>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnloadAction {
+    pub unloaded: String,
+    /// Row id of the `proxy_events` entry recording the unload
+    pub event_id: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnloadAdvice {
+    pub threshold: f64,
+    pub models: Vec<crate::stats::advisor::UnloadCandidate>,
+    pub action: Option<UnloadAction>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    pub status: &'static str,
+    pub service: &'static str,
+    /// Latest counter consistency check since startup, if one has run
+    pub last_verification: Option<crate::verify::VerificationSummary>,
+    /// Age of the oldest finished request whose row isn't written yet; 0 when none
+    pub oldest_unpersisted_ms: u64,
+    /// Whether `oldest_unpersisted_ms` is past `PERSIST_LAG_ALERT_MS`
+    pub persistence_lagging: bool,
+}
+
+/// Canary shares before and after a change through `POST /admin/routing/weights`.
+#[derive(Debug, Serialize)]
+pub struct RoutingWeightsChange {
+    pub previous: BTreeMap<String, f64>,
+    pub weights: BTreeMap<String, f64>,
+    /// Row id of the `proxy_events` entry recording the change
+    pub event_id: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IncidentsResponse {
+    pub active: Option<crate::incidents::ActiveIncident>,
+    pub incidents: Vec<crate::db::incidents::Incident>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhooksResponse {
+    pub webhooks: Vec<crate::db::webhooks::Webhook>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookDeliveriesResponse {
+    pub webhook: crate::db::webhooks::Webhook,
+    pub deliveries: Vec<crate::db::webhooks::WebhookDelivery>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobsResponse {
+    pub jobs: Vec<crate::db::jobs::MaintenanceJob>,
+}
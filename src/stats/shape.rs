@@ -0,0 +1,271 @@
+//! Response shaping shared by every stats route: `?case=camel` renames fields to
+//! camelCase for dashboards written to JavaScript conventions, and `?pretty=true`
+//! indents the body for reading with curl.
+//!
+//! Only the bodies of [`ApiResponse`](super::response::ApiResponse)s are renamed, after
+//! they are serialized. Map keys that are data (model names in `by_model`, upstream
+//! names in `weights`) and stored JSON such as `sampling_params` are left as they are.
+
+use axum::{
+    body::Body,
+    extract::{Query, Request},
+    http::{
+        StatusCode,
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body_util::BodyExt;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::stats::error::StatsError;
+
+/// Response extension marking a body whose keys are response field names, so
+/// `case=camel` may rename them. Set by `ApiResponse`; error bodies don't carry it.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldNames;
+
+/// Top-level fields holding maps keyed by model or upstream names. Their keys are kept;
+/// the values inside are renamed as usual.
+const KEYED_BY_DATA: &[&str] =
+    &["by_model", "previous", "weights", "connection_rejections_by_kind"];
+
+/// Fields holding JSON as a client sent it, left whole wherever they appear.
+const VERBATIM: &[&str] = &[
+    "incident_capture",
+    "messages",
+    "param_adjustments",
+    "sampling_params",
+    "spec",
+    "stop_sequences",
+    "stream_parse_samples",
+];
+
+#[derive(Debug, Deserialize)]
+struct ShapeQuery {
+    /// `snake` (default) or `camel`
+    case: Option<String>,
+    /// `true`/`1` or `false`/`0` (default)
+    pretty: Option<String>,
+}
+
+/// Reshapes JSON responses as the `case` and `pretty` query parameters ask. Requests
+/// without them pass through untouched.
+pub async fn shape(request: Request, next: Next) -> Response {
+    let params = match Query::<ShapeQuery>::try_from_uri(request.uri()) {
+        Ok(Query(params)) => params,
+        Err(e) => return StatsError::BadRequest(e.body_text()).into_response(),
+    };
+    let camel = match params.case.as_deref() {
+        None | Some("snake") => false,
+        Some("camel") => true,
+        Some(other) => {
+            return StatsError::BadRequest(format!(
+                "Invalid case value '{}', expected snake or camel",
+                other
+            ))
+            .into_response();
+        }
+    };
+    let pretty = match params.pretty.as_deref() {
+        None | Some("false") | Some("0") => false,
+        Some("true") | Some("1") => true,
+        Some(other) => {
+            return StatsError::BadRequest(format!(
+                "Invalid pretty value '{}', expected true or false",
+                other
+            ))
+            .into_response();
+        }
+    };
+    let response = next.run(request).await;
+    let camel = camel && response.extensions().get::<FieldNames>().is_some();
+    if !camel && !pretty {
+        return response;
+    }
+
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(collected) = body.collect().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let bytes = collected.to_bytes();
+    let Ok(mut value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    if camel {
+        value = camel_response(value);
+    }
+    let body = if pretty {
+        serde_json::to_vec_pretty(&value)
+    } else {
+        serde_json::to_vec(&value)
+    };
+    let Ok(body) = body else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// `body` with its field names in camelCase.
+fn camel_response(body: Value) -> Value {
+    let Value::Object(fields) = body else {
+        return camel_keys(body);
+    };
+    let fields = fields
+        .into_iter()
+        .map(|(key, value)| match value {
+            Value::Object(map) if KEYED_BY_DATA.contains(&key.as_str()) => {
+                let map = map.into_iter().map(|(name, value)| (name, camel_keys(value)));
+                (camel_case(&key), Value::Object(map.collect()))
+            }
+            value => camel_field(key, value),
+        })
+        .collect();
+    Value::Object(fields)
+}
+
+/// `value` with the keys of every object in it in camelCase, except inside
+/// [`VERBATIM`] fields.
+fn camel_keys(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => {
+            let fields: Map<String, Value> = fields
+                .into_iter()
+                .map(|(key, value)| camel_field(key, value))
+                .collect();
+            Value::Object(fields)
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(camel_keys).collect()),
+        value => value,
+    }
+}
+
+fn camel_field(key: String, value: Value) -> (String, Value) {
+    if VERBATIM.contains(&key.as_str()) {
+        (camel_case(&key), value)
+    } else {
+        (camel_case(&key), camel_keys(value))
+    }
+}
+
+/// `avg_ttft_ms` becomes `avgTtftMs`; names without underscores stay as they are.
+fn camel_case(key: &str) -> String {
+    let mut parts = key.split('_').filter(|part| !part.is_empty());
+    let mut camel = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Totals {
+        total_tokens: i64,
+    }
+
+    #[derive(Serialize)]
+    struct Report {
+        avg_ttft_ms: Option<f64>,
+        #[serde(flatten)]
+        totals: Totals,
+        by_model: BTreeMap<String, Totals>,
+        rows: Vec<Totals>,
+    }
+
+    #[test]
+    fn renames_fields_but_not_model_names() {
+        let report = Report {
+            avg_ttft_ms: Some(12.5),
+            totals: Totals { total_tokens: 3 },
+            by_model: BTreeMap::from([
+                ("foo_bar".to_string(), Totals { total_tokens: 1 }),
+                ("fooBar".to_string(), Totals { total_tokens: 2 }),
+            ]),
+            rows: vec![Totals { total_tokens: 4 }],
+        };
+        let value = camel_response(serde_json::to_value(&report).unwrap());
+        assert_eq!(
+            value,
+            json!({
+                "avgTtftMs": 12.5,
+                "totalTokens": 3,
+                "byModel": {
+                    "fooBar": { "totalTokens": 2 },
+                    "foo_bar": { "totalTokens": 1 },
+                },
+                "rows": [{ "totalTokens": 4 }],
+            })
+        );
+    }
+
+    #[test]
+    fn stored_json_keeps_its_keys_wherever_it_appears() {
+        let request = json!({
+            "proxy_request_id": "p1",
+            "sampling_params": { "top_p": 0.9, "repeat_penalty": { "last_n": 64 } },
+            "stop_sequences": [{ "stop_word": "END" }],
+            "incident_capture": { "request_headers": { "x_client_id": "a" } },
+        });
+        let value = camel_response(json!({ "requests": [request.clone()] }));
+        assert_eq!(
+            value,
+            json!({
+                "requests": [{
+                    "proxyRequestId": "p1",
+                    "samplingParams": { "top_p": 0.9, "repeat_penalty": { "last_n": 64 } },
+                    "stopSequences": [{ "stop_word": "END" }],
+                    "incidentCapture": { "request_headers": { "x_client_id": "a" } },
+                }],
+            })
+        );
+        assert_eq!(camel_response(request)["samplingParams"]["top_p"], 0.9);
+    }
+
+    #[test]
+    fn only_top_level_maps_are_keyed_by_data() {
+        let value = camel_response(json!({
+            "weights": { "canary_a": 10.0 },
+            "connection_rejections_by_kind": { "header_timeout": 2 },
+            "comparison": { "previous": { "total_requests": 1 } },
+            "by_model": [{ "model": "m_1", "total_requests": 1 }],
+        }));
+        assert_eq!(
+            value,
+            json!({
+                "weights": { "canary_a": 10.0 },
+                "connectionRejectionsByKind": { "header_timeout": 2 },
+                "comparison": { "previous": { "totalRequests": 1 } },
+                "byModel": [{ "model": "m_1", "totalRequests": 1 }],
+            })
+        );
+    }
+
+    #[test]
+    fn camel_case_joins_words() {
+        assert_eq!(camel_case("avg_ttft_ms"), "avgTtftMs");
+        assert_eq!(camel_case("requests"), "requests");
+        assert_eq!(camel_case("p95_ms"), "p95Ms");
+    }
+}
@@ -0,0 +1,284 @@
+//! The public status page: whether LM Studio answers, how often it did lately and its
+//! recent outages, for people who shouldn't see the usage statistics.
+//!
+//! A background prober asks the primary upstream for its model list every
+//! `STATUS_PROBE_INTERVAL_SECS` and stores the answers in `availability_spans`. The page
+//! deliberately carries nothing about requests, models or clients.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::db::availability::{self, Outage, STATE_DOWN, STATE_UNKNOWN, STATE_UP, Uptime};
+use crate::proxy::client::HttpClient;
+use crate::proxy::lmstudio;
+
+/// How long a check waits for LM Studio before counting it as down
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Outages listed on the page
+const MAX_OUTAGES: usize = 10;
+
+/// Longest window the page reports on
+const LONGEST_WINDOW_DAYS: i64 = 30;
+
+const PAGE_TEMPLATE: &str = include_str!("status.html");
+
+#[derive(Debug, Serialize)]
+pub struct Uptimes {
+    #[serde(rename = "24h")]
+    pub day: Uptime,
+    #[serde(rename = "7d")]
+    pub week: Uptime,
+    #[serde(rename = "30d")]
+    pub month: Uptime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    /// `up`, `down`, or `unknown` when the latest check is too old to go by
+    pub status: &'static str,
+    /// When the current state began
+    pub since: Option<String>,
+    pub last_checked: Option<String>,
+    pub uptime: Uptimes,
+    /// Newest first, from the last 30 days
+    pub outages: Vec<Outage>,
+}
+
+/// Checks later than this after the previous one don't continue its span: two missed
+/// checks plus the time a check may take.
+pub fn max_gap(interval_secs: u64) -> Duration {
+    Duration::seconds(interval_secs as i64 * 2 + PROBE_TIMEOUT.as_secs() as i64)
+}
+
+/// Checks the upstream every `interval_secs` for as long as the proxy runs.
+pub fn spawn_prober(
+    db: SqlitePool,
+    client: HttpClient,
+    lm_studio_url: String,
+    interval_secs: u64,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+            let check = lmstudio::ping(&client, &lm_studio_url);
+            let state = match tokio::time::timeout(PROBE_TIMEOUT, check).await {
+                Ok(Ok(())) => STATE_UP,
+                _ => STATE_DOWN,
+            };
+            match availability::record_check(&db, state, Utc::now(), max_gap(interval_secs)).await
+            {
+                Ok(Some(previous)) => {
+                    tracing::warn!("LM Studio is now {} (was {})", state, previous)
+                }
+                Ok(None) => {}
+                Err(e) => tracing::error!("Failed to record an availability check: {}", e),
+            }
+        }
+    })
+}
+
+/// The page's figures as of `now`.
+pub async fn report(
+    db: &SqlitePool,
+    interval_secs: u64,
+    now: DateTime<Utc>,
+) -> Result<StatusReport, sqlx::Error> {
+    let spans = availability::get_spans(db, now - Duration::days(LONGEST_WINDOW_DAYS)).await?;
+    let current = spans
+        .last()
+        .filter(|span| now - span.last_seen_at <= max_gap(interval_secs));
+    let status = match current.map(|span| span.state.as_str()) {
+        Some(STATE_UP) => STATE_UP,
+        Some(STATE_DOWN) => STATE_DOWN,
+        _ => STATE_UNKNOWN,
+    };
+    let window = |days: i64| availability::uptime(&spans, now - Duration::days(days), now);
+
+    let mut outages = availability::outages(&spans, current.is_some());
+    outages.truncate(MAX_OUTAGES);
+    Ok(StatusReport {
+        status,
+        since: current.map(|span| span.started_at.to_rfc3339()),
+        last_checked: spans.last().map(|span| span.last_seen_at.to_rfc3339()),
+        uptime: Uptimes {
+            day: window(1),
+            week: window(7),
+            month: window(LONGEST_WINDOW_DAYS),
+        },
+        outages,
+    })
+}
+
+/// The report as a small self-contained HTML page.
+pub fn render_html(report: &StatusReport) -> String {
+    let percent = |uptime: &Uptime| match uptime.uptime_percent {
+        Some(percent) => format!("{:.2}%", percent),
+        None => "no data".to_string(),
+    };
+    let outages = if report.outages.is_empty() {
+        "<li>None in the last 30 days</li>".to_string()
+    } else {
+        report
+            .outages
+            .iter()
+            .map(|outage| match &outage.ended_at {
+                Some(ended_at) => format!(
+                    "<li>{} to {} ({})</li>",
+                    outage.started_at,
+                    ended_at,
+                    duration_label(outage.duration_secs)
+                ),
+                None => format!("<li>Since {} (ongoing)</li>", outage.started_at),
+            })
+            .collect()
+    };
+
+    PAGE_TEMPLATE
+        .replace("{{status}}", report.status)
+        .replace("{{since}}", report.since.as_deref().unwrap_or("-"))
+        .replace("{{last_checked}}", report.last_checked.as_deref().unwrap_or("never"))
+        .replace("{{uptime_24h}}", &percent(&report.uptime.day))
+        .replace("{{uptime_7d}}", &percent(&report.uptime.week))
+        .replace("{{uptime_30d}}", &percent(&report.uptime.month))
+        .replace("{{outages}}", &outages)
+}
+
+fn duration_label(secs: i64) -> String {
+    match secs {
+        ..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use chrono::TimeZone;
+
+    const INTERVAL: u64 = 30;
+
+    fn t(secs: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 4, 1, 0, 0, 0).unwrap() + Duration::seconds(secs)
+    }
+
+    async fn checks(pool: &SqlitePool, state: &str, from: i64, to: i64) {
+        for secs in (from..=to).step_by(INTERVAL as usize) {
+            availability::record_check(pool, state, t(secs), max_gap(INTERVAL))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn the_report_follows_the_latest_check() {
+        let pool = memory_pool().await;
+        checks(&pool, STATE_UP, 0, 3600).await;
+        checks(&pool, STATE_DOWN, 3630, 3900).await;
+
+        let current = report(&pool, INTERVAL, t(3910)).await.unwrap();
+        assert_eq!(current.status, STATE_DOWN);
+        assert_eq!(current.since, Some(t(3600).to_rfc3339()));
+        assert_eq!(current.last_checked, Some(t(3900).to_rfc3339()));
+        assert_eq!(current.outages.len(), 1);
+        assert_eq!(current.outages[0].ended_at, None);
+        assert_eq!(current.uptime.day.up_secs, 3600);
+        assert_eq!(current.uptime.day.down_secs, 300);
+        assert_eq!(current.uptime.day.uptime_percent, Some(92.31));
+
+        // Without checks for longer than two intervals, nothing is known about now
+        let later = report(&pool, INTERVAL, t(3900) + max_gap(INTERVAL) + Duration::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(later.status, STATE_UNKNOWN);
+        assert_eq!(later.since, None);
+        assert_eq!(later.last_checked, Some(t(3900).to_rfc3339()));
+        assert!(later.outages[0].ended_at.is_some());
+        // and the silence isn't held against the upstream
+        assert_eq!(later.uptime.day.uptime_percent, Some(92.31));
+    }
+
+    #[tokio::test]
+    async fn windows_only_reach_back_their_own_length() {
+        let pool = memory_pool().await;
+        // Down for an hour ten days ago, up for the last hour
+        let day = 86_400;
+        checks(&pool, STATE_DOWN, 0, 3600).await;
+        checks(&pool, STATE_UP, 10 * day, 10 * day + 3600).await;
+
+        let report = report(&pool, INTERVAL, t(10 * day + 3600)).await.unwrap();
+        assert_eq!(report.status, STATE_UP);
+        assert_eq!(report.uptime.day.uptime_percent, Some(100.0));
+        assert_eq!(report.uptime.week.uptime_percent, Some(100.0));
+        assert_eq!(report.uptime.month.uptime_percent, Some(50.0));
+        assert_eq!(report.uptime.day.unknown_secs, day - 3600);
+        assert_eq!(report.outages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn an_empty_history_is_unknown() {
+        let pool = memory_pool().await;
+        let report = report(&pool, INTERVAL, t(0)).await.unwrap();
+        assert_eq!(report.status, STATE_UNKNOWN);
+        assert_eq!(report.last_checked, None);
+        assert_eq!(report.uptime.month.uptime_percent, None);
+        assert!(report.outages.is_empty());
+
+        let html = render_html(&report);
+        assert!(html.contains("The LLM is unknown"), "{}", html);
+        assert!(html.contains("last checked never"), "{}", html);
+        assert!(html.contains("None in the last 30 days"), "{}", html);
+        assert_eq!(html.matches("no data").count(), 3);
+    }
+
+    #[tokio::test]
+    async fn the_page_lists_outages_and_uptime() {
+        let pool = memory_pool().await;
+        checks(&pool, STATE_UP, 0, 600).await;
+        checks(&pool, STATE_DOWN, 630, 900).await;
+        checks(&pool, STATE_UP, 930, 4500).await;
+        checks(&pool, STATE_DOWN, 4530, 4560).await;
+
+        let report = report(&pool, INTERVAL, t(4560)).await.unwrap();
+        let html = render_html(&report);
+        assert!(html.contains(r#"<div class="status down">The LLM is down</div>"#), "{}", html);
+        assert!(html.contains(&format!("<li>Since {} (ongoing)</li>", t(4500).to_rfc3339())));
+        assert!(html.contains(&format!(
+            "<li>{} to {} (5m)</li>",
+            t(600).to_rfc3339(),
+            t(900).to_rfc3339()
+        )));
+        // 4200 of 4560 observed seconds up
+        assert!(html.contains("<td>92.11%</td>"), "{}", html);
+        assert!(!html.contains("{{"), "{}", html);
+
+        let json = serde_json::to_value(&report).unwrap();
+        let windows: Vec<&String> = json["uptime"].as_object().unwrap().keys().collect();
+        assert_eq!(windows, ["24h", "7d", "30d"]);
+    }
+
+    #[tokio::test]
+    async fn at_most_ten_outages_are_listed() {
+        let pool = memory_pool().await;
+        for i in 0..12 {
+            checks(&pool, STATE_UP, i * 120, i * 120 + 30).await;
+            checks(&pool, STATE_DOWN, i * 120 + 60, i * 120 + 90).await;
+        }
+        let report = report(&pool, INTERVAL, t(12 * 120)).await.unwrap();
+        assert_eq!(report.outages.len(), MAX_OUTAGES);
+        assert_eq!(report.outages[0].started_at, t(11 * 120 + 30).to_rfc3339());
+    }
+
+    #[test]
+    fn durations_are_labelled_in_the_largest_unit() {
+        let cases = [(0, "0s"), (59, "59s"), (60, "1m"), (3599, "59m"), (3600, "1h 0m")];
+        for (secs, label) in cases {
+            assert_eq!(duration_label(secs), label);
+        }
+        assert_eq!(duration_label(90_061), "25h 1m");
+    }
+}
@@ -0,0 +1,195 @@
+//! systemd integration: `Type=notify` readiness, watchdog pings and socket activation.
+//!
+//! Only compiled in with the `systemd` feature on Linux. Everything is a no-op unless
+//! systemd passed the matching environment (`NOTIFY_SOCKET`, `WATCHDOG_USEC`,
+//! `LISTEN_FDS`), so the same binary still runs normally outside a unit.
+
+pub use imp::*;
+
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+mod imp {
+    use sqlx::SqlitePool;
+    use std::collections::VecDeque;
+    use std::env;
+    use std::os::fd::{FromRawFd, RawFd};
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+    use std::time::Duration;
+
+    /// First file descriptor passed by socket activation (`SD_LISTEN_FDS_START`)
+    const LISTEN_FDS_START: RawFd = 3;
+
+    /// How long the watchdog's database probe may take before the process counts as wedged
+    const WATCHDOG_DB_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Sends a state string such as `READY=1` to the service manager.
+    ///
+    /// Returns `false` without doing anything when `NOTIFY_SOCKET` isn't set.
+    pub fn notify(state: &str) -> bool {
+        let Ok(path) = env::var("NOTIFY_SOCKET") else {
+            return false;
+        };
+
+        let result = (|| {
+            let addr = match path.strip_prefix('@') {
+                Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+                None => SocketAddr::from_pathname(&path)?,
+            };
+            let socket = UnixDatagram::unbound()?;
+            socket.send_to_addr(state.as_bytes(), &addr)
+        })();
+
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                tracing::warn!("Failed to notify systemd ({}): {}", state, e);
+                false
+            }
+        }
+    }
+
+    pub fn notify_ready() {
+        if notify("READY=1") {
+            tracing::info!("Notified systemd that the service is ready");
+        }
+    }
+
+    pub fn notify_stopping() {
+        notify("STOPPING=1");
+    }
+
+    /// Listening sockets handed over by systemd socket activation, in unit file order.
+    pub fn activated_listeners() -> anyhow::Result<VecDeque<std::net::TcpListener>> {
+        let for_us = env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_some_and(|pid| pid == std::process::id());
+        if !for_us {
+            return Ok(VecDeque::new());
+        }
+
+        let count: RawFd = env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0);
+
+        let mut listeners = VecDeque::new();
+        for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+            // SAFETY: systemd passes these descriptors to this process (LISTEN_PID matched),
+            // and nothing else in the process takes ownership of them.
+            let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            listener.set_nonblocking(true)?;
+            listeners.push_back(listener);
+        }
+        if !listeners.is_empty() {
+            tracing::info!("Using {} socket(s) passed by systemd", listeners.len());
+        }
+        Ok(listeners)
+    }
+
+    /// Interval between watchdog pings: half of `WATCHDOG_USEC`, as systemd recommends.
+    fn watchdog_interval() -> Option<Duration> {
+        if let Ok(pid) = env::var("WATCHDOG_PID")
+            && pid.parse::<u32>().ok() != Some(std::process::id())
+        {
+            return None;
+        }
+
+        env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|&usec| usec > 0)
+            .map(|usec| Duration::from_micros(usec / 2))
+    }
+
+    /// Pings the watchdog while the runtime and database stay responsive.
+    ///
+    /// The pings come from a task on the main runtime, so a stalled event loop misses them
+    /// too. If the database can't answer a trivial query, pinging stops for good and
+    /// systemd restarts the process once the watchdog timeout expires.
+    pub fn spawn_watchdog(db: SqlitePool) -> Option<tokio::task::JoinHandle<()>> {
+        let interval = watchdog_interval()?;
+        tracing::info!("systemd watchdog enabled, pinging every {:?}", interval);
+
+        Some(tokio::spawn(ping_while_responsive(db, interval, || {
+            notify("WATCHDOG=1");
+        })))
+    }
+
+    /// Calls `ping` every `interval` for as long as the database answers a trivial query.
+    async fn ping_while_responsive(db: SqlitePool, interval: Duration, ping: impl Fn()) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            let probe = sqlx::query_scalar::<_, i64>("SELECT 1").fetch_one(&db);
+            match tokio::time::timeout(WATCHDOG_DB_TIMEOUT, probe).await {
+                Ok(Ok(_)) => ping(),
+                Ok(Err(e)) => {
+                    tracing::error!("Database unresponsive, stopping watchdog pings: {}", e);
+                    break;
+                }
+                Err(_) => {
+                    tracing::error!(
+                        "Database probe timed out after {:?}, stopping watchdog pings",
+                        WATCHDOG_DB_TIMEOUT
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::db::testing::memory_pool;
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        #[tokio::test]
+        async fn pings_stop_once_the_database_is_gone() {
+            let pool = memory_pool().await;
+            let pings = Arc::new(AtomicU32::new(0));
+            let counted = pings.clone();
+            let watchdog = tokio::spawn(ping_while_responsive(
+                pool.clone(),
+                Duration::from_millis(10),
+                move || {
+                    counted.fetch_add(1, Ordering::Relaxed);
+                },
+            ));
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(pings.load(Ordering::Relaxed) >= 3);
+            assert!(!watchdog.is_finished());
+
+            pool.close().await;
+            tokio::time::timeout(Duration::from_secs(5), watchdog)
+                .await
+                .expect("watchdog stops pinging")
+                .unwrap();
+            let after_close = pings.load(Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(pings.load(Ordering::Relaxed), after_close);
+        }
+    }
+}
+
+#[cfg(not(all(feature = "systemd", target_os = "linux")))]
+mod imp {
+    use sqlx::SqlitePool;
+    use std::collections::VecDeque;
+
+    pub fn notify_ready() {}
+
+    pub fn notify_stopping() {}
+
+    pub fn activated_listeners() -> anyhow::Result<VecDeque<std::net::TcpListener>> {
+        Ok(VecDeque::new())
+    }
+
+    pub fn spawn_watchdog(_db: SqlitePool) -> Option<tokio::task::JoinHandle<()>> {
+        None
+    }
+}
@@ -0,0 +1,529 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tiktoken_rs::CoreBPE;
+
+use crate::config::{TokenizerRule, model_pattern_matches};
+use crate::db::RequestRecord;
+
+/// Tokenizer used for models that no rule matches
+const DEFAULT_SOURCE: &str = "cl100k";
+
+/// Source name of the characters-per-token heuristic
+const CHARS_HEURISTIC: &str = "chars/4";
+
+/// Tokens a chat template adds around each message, on top of its content
+const TOKENS_PER_MESSAGE: i64 = 4;
+
+/// Streamed chunks between updates of a [`RunningCount`]
+const RUNNING_COUNT_EVERY_CHUNKS: u32 = 16;
+
+/// Minimum time between updates of a [`RunningCount`], so a very fast stream spends its
+/// time forwarding chunks rather than tokenizing them
+const RUNNING_COUNT_MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Uncounted text a [`RunningCount`] settles even without a word boundary in it, for
+/// scripts written without spaces
+const RUNNING_COUNT_MAX_PENDING_BYTES: usize = 8 * 1024;
+
+enum Encoder {
+    Bpe(CoreBPE),
+    HuggingFace(Box<tokenizers::Tokenizer>),
+    Chars,
+}
+
+/// A loaded tokenizer together with the name recorded on the estimates it produces.
+pub struct Tokenizer {
+    pub name: String,
+    encoder: Encoder,
+}
+
+impl Tokenizer {
+    fn load(source: &str) -> anyhow::Result<Self> {
+        let encoder = match source {
+            "cl100k" => Encoder::Bpe(tiktoken_rs::cl100k_base()?),
+            "o200k" => Encoder::Bpe(tiktoken_rs::o200k_base()?),
+            CHARS_HEURISTIC => Encoder::Chars,
+            path => Encoder::HuggingFace(Box::new(
+                tokenizers::Tokenizer::from_file(path).map_err(|e| anyhow::anyhow!(e))?,
+            )),
+        };
+        let name = match encoder {
+            Encoder::HuggingFace(_) => format!("hf:{}", source),
+            _ => source.to_string(),
+        };
+        Ok(Self { name, encoder })
+    }
+
+    fn chars() -> Self {
+        Self {
+            name: CHARS_HEURISTIC.to_string(),
+            encoder: Encoder::Chars,
+        }
+    }
+
+    pub fn count(&self, text: &str) -> i64 {
+        match &self.encoder {
+            Encoder::Bpe(bpe) => bpe.encode_ordinary(text).len() as i64,
+            Encoder::HuggingFace(tokenizer) => match tokenizer.encode(text, false) {
+                Ok(encoding) => encoding.len() as i64,
+                Err(_) => chars_estimate(text),
+            },
+            Encoder::Chars => chars_estimate(text),
+        }
+    }
+
+    /// Counts a logged prompt, which is either serialized chat messages or plain text.
+    fn count_prompt(&self, prompt: &str) -> i64 {
+        let Ok(Value::Array(messages)) = serde_json::from_str::<Value>(prompt) else {
+            return self.count(prompt);
+        };
+
+        messages
+            .iter()
+            .map(|message| {
+                let content = match message.get("content") {
+                    Some(Value::String(text)) => self.count(text),
+                    Some(other) => self.count(&other.to_string()),
+                    None => 0,
+                };
+                content + TOKENS_PER_MESSAGE
+            })
+            .sum()
+    }
+}
+
+fn chars_estimate(text: &str) -> i64 {
+    (text.chars().count() as i64 + 3) / 4
+}
+
+/// Output token count of a stream, kept up to date as chunks arrive so a stream that
+/// ends without a usage chunk still has a count.
+///
+/// Each piece of text is tokenized once. An update counts the new text up to the start
+/// of its last run of whitespace and settles it; the rest is counted again with the next
+/// update. Tokenizers split words at that point, so the settled counts add up to what
+/// counting the whole output at once gives, short of rare tokens spanning the cut.
+pub struct RunningCount {
+    tokenizer: Arc<Tokenizer>,
+    settled_bytes: usize,
+    settled_tokens: i64,
+    /// Characters settled so far, for the characters-per-token heuristic, which has to
+    /// round the whole output at once to match a one-shot count
+    settled_chars: usize,
+    chunks_since_update: u32,
+    last_update: Option<Instant>,
+}
+
+impl RunningCount {
+    pub fn new(tokenizer: Arc<Tokenizer>) -> Self {
+        Self {
+            tokenizer,
+            settled_bytes: 0,
+            settled_tokens: 0,
+            settled_chars: 0,
+            chunks_since_update: 0,
+            last_update: None,
+        }
+    }
+
+    /// Notes that a chunk was appended to `output`, updating the count every few chunks.
+    pub fn observe(&mut self, output: &str) {
+        self.chunks_since_update += 1;
+        if self.chunks_since_update < RUNNING_COUNT_EVERY_CHUNKS
+            || self
+                .last_update
+                .is_some_and(|at| at.elapsed() < RUNNING_COUNT_MIN_INTERVAL)
+        {
+            return;
+        }
+        self.chunks_since_update = 0;
+        self.last_update = Some(Instant::now());
+
+        let pending = &output[self.settled_bytes..];
+        let cut = word_boundary(pending).or_else(|| {
+            (pending.len() > RUNNING_COUNT_MAX_PENDING_BYTES).then(|| {
+                let mut cut = pending.len();
+                while !pending.is_char_boundary(cut - 1) {
+                    cut -= 1;
+                }
+                cut - 1
+            })
+        });
+        if let Some(cut) = cut.filter(|&cut| cut > 0) {
+            self.settle(&pending[..cut]);
+        }
+    }
+
+    /// Tokens in `output`, the whole text the count has been observing.
+    pub fn total(&self, output: &str) -> i64 {
+        let pending = &output[self.settled_bytes..];
+        match self.tokenizer.encoder {
+            Encoder::Chars => ((self.settled_chars + pending.chars().count()) as i64 + 3) / 4,
+            _ => self.settled_tokens + self.tokenizer.count(pending),
+        }
+    }
+
+    /// Tokens counted so far, leaving out the few chunks since the last update.
+    pub fn settled(&self) -> i64 {
+        match self.tokenizer.encoder {
+            Encoder::Chars => (self.settled_chars as i64 + 3) / 4,
+            _ => self.settled_tokens,
+        }
+    }
+
+    fn settle(&mut self, text: &str) {
+        self.settled_bytes += text.len();
+        match self.tokenizer.encoder {
+            Encoder::Chars => self.settled_chars += text.chars().count(),
+            _ => self.settled_tokens += self.tokenizer.count(text),
+        }
+    }
+}
+
+/// Byte offset where the last run of whitespace in `text` that follows a letter or digit
+/// starts. Tokenizers join punctuation to the line breaks after it, so a run after
+/// punctuation is no place to cut.
+fn word_boundary(text: &str) -> Option<usize> {
+    let mut end = text.len();
+    loop {
+        let word = text[..end]
+            .trim_end_matches(|c: char| !c.is_whitespace())
+            .trim_end();
+        match word.chars().next_back() {
+            None => return None,
+            Some(c) if c.is_alphanumeric() => return Some(word.len()),
+            Some(_) => end = word.len(),
+        }
+    }
+}
+
+/// Per-model tokenizers for estimating token counts, loaded once at startup.
+///
+/// Rules are tried in configured order and the first whose pattern matches the model
+/// wins. Rules whose tokenizer failed to load are skipped, and models nothing matches
+/// use cl100k.
+pub struct Tokenizers {
+    rules: Vec<(String, Arc<Tokenizer>)>,
+    fallback: Arc<Tokenizer>,
+    /// Model name -> tokenizer it resolved to
+    resolved: Mutex<HashMap<String, Arc<Tokenizer>>>,
+}
+
+impl Tokenizers {
+    /// Loads every configured tokenizer, sharing one instance between rules with the
+    /// same source. Failures are logged and the rule is dropped.
+    pub fn load(rules: &[TokenizerRule]) -> Self {
+        let mut loaded: HashMap<String, Arc<Tokenizer>> = HashMap::new();
+        let mut load = |source: &str| -> Option<Arc<Tokenizer>> {
+            if let Some(tokenizer) = loaded.get(source) {
+                return Some(tokenizer.clone());
+            }
+            match Tokenizer::load(source) {
+                Ok(tokenizer) => {
+                    let tokenizer = Arc::new(tokenizer);
+                    loaded.insert(source.to_string(), tokenizer.clone());
+                    Some(tokenizer)
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to load tokenizer {}, skipping it: {}", source, e);
+                    None
+                }
+            }
+        };
+
+        let rules = rules
+            .iter()
+            .filter_map(|rule| load(&rule.source).map(|t| (rule.pattern.clone(), t)))
+            .collect();
+        let fallback = load(DEFAULT_SOURCE).unwrap_or_else(|| Arc::new(Tokenizer::chars()));
+
+        Self {
+            rules,
+            fallback,
+            resolved: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The tokenizer for `model`, resolved once per model name.
+    pub fn for_model(&self, model: &str) -> Arc<Tokenizer> {
+        let mut resolved = self.resolved.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(tokenizer) = resolved.get(model) {
+            return tokenizer.clone();
+        }
+
+        let tokenizer = self
+            .rules
+            .iter()
+            .find(|(pattern, _)| model_pattern_matches(pattern, model))
+            .map(|(_, tokenizer)| tokenizer.clone())
+            .unwrap_or_else(|| self.fallback.clone());
+        resolved.insert(model.to_string(), tokenizer.clone());
+        tokenizer
+    }
+
+    /// Estimated input and output tokens of a logged prompt and output, with the name of
+    /// the tokenizer that counted them.
+    pub fn estimate(&self, model: &str, prompt: &str, output: &str) -> (i64, i64, String) {
+        let tokenizer = self.for_model(model);
+        (
+            tokenizer.count_prompt(prompt),
+            tokenizer.count(output),
+            tokenizer.name.clone(),
+        )
+    }
+
+    /// Records estimated token counts and the tokenizer that produced them.
+    ///
+    /// Estimates are kept alongside exact usage so their accuracy can be checked; they
+    /// only replace the token counts when upstream reported none.
+    pub fn annotate(&self, record: &mut RequestRecord) {
+        if record.is_error {
+            return;
+        }
+
+        let tokenizer = self.for_model(&record.model);
+        let input = tokenizer.count_prompt(&record.prompt);
+        // Streams count their output while it arrives
+        let output = record
+            .estimated_output_tokens
+            .unwrap_or_else(|| tokenizer.count(&record.output));
+        record.estimated_input_tokens = Some(input);
+        record.estimated_output_tokens = Some(output);
+        record.tokenizer = Some(tokenizer.name.clone());
+
+        if record.tokens_estimated {
+            record.input_tokens = input;
+            record.output_tokens = output;
+            record.total_tokens = input + output;
+            record.measure_throughput();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, source: &str) -> TokenizerRule {
+        TokenizerRule {
+            pattern: pattern.to_string(),
+            source: source.to_string(),
+        }
+    }
+
+    /// A word-level tokenizer.json that splits on whitespace and punctuation.
+    fn word_level_file(dir: &std::path::Path) -> String {
+        let path = dir.join("tokenizer.json");
+        let json = serde_json::json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "[UNK]": 0, "hello": 1, "world": 2 },
+                "unk_token": "[UNK]"
+            }
+        });
+        std::fs::write(&path, json.to_string()).unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let tokenizers = Tokenizers::load(&[
+            rule("qwen*", "chars/4"),
+            rule("*", "o200k"),
+            rule("qwen2", "cl100k"),
+        ]);
+        assert_eq!(tokenizers.for_model("Qwen2").name, "chars/4");
+        assert_eq!(tokenizers.for_model("llama-3").name, "o200k");
+    }
+
+    #[test]
+    fn unmatched_models_use_cl100k() {
+        let tokenizers = Tokenizers::load(&[rule("qwen*", "chars/4")]);
+        assert_eq!(tokenizers.for_model("llama-3").name, "cl100k");
+        assert_eq!(Tokenizers::load(&[]).for_model("anything").name, "cl100k");
+    }
+
+    #[test]
+    fn rules_that_fail_to_load_are_skipped() {
+        let tokenizers = Tokenizers::load(&[
+            rule("llama*", "/nonexistent/tokenizer.json"),
+            rule("llama*", "chars/4"),
+            rule("mistral*", "/nonexistent/tokenizer.json"),
+        ]);
+        assert_eq!(tokenizers.for_model("llama-3").name, "chars/4");
+        assert_eq!(tokenizers.for_model("mistral-7b").name, "cl100k");
+    }
+
+    #[test]
+    fn tokenizers_are_shared_and_resolved_once() {
+        let tokenizers = Tokenizers::load(&[rule("a*", "o200k"), rule("b*", "o200k")]);
+        let a = tokenizers.for_model("a1");
+        assert!(Arc::ptr_eq(&a, &tokenizers.for_model("a1")));
+        assert!(Arc::ptr_eq(&a, &tokenizers.for_model("b1")));
+        assert!(!Arc::ptr_eq(&a, &tokenizers.for_model("c1")));
+        assert_eq!(tokenizers.resolved.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn huggingface_tokenizer_is_loaded_from_a_file() {
+        let dir = std::env::temp_dir().join(format!("tokenizer-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = word_level_file(&dir);
+        let tokenizers = Tokenizers::load(&[rule("local*", &path)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let tokenizer = tokenizers.for_model("local-model");
+        assert_eq!(tokenizer.name, format!("hf:{}", path));
+        assert_eq!(tokenizer.count("hello world, hello"), 4);
+    }
+
+    #[test]
+    fn chars_heuristic_rounds_up() {
+        let tokenizer = Tokenizer::chars();
+        assert_eq!(tokenizer.count(""), 0);
+        assert_eq!(tokenizer.count("abcd"), 1);
+        assert_eq!(tokenizer.count("abcde"), 2);
+        assert_eq!(tokenizer.count("ééééé"), 2);
+    }
+
+    #[test]
+    fn chat_prompts_count_each_message() {
+        let tokenizer = Tokenizer::chars();
+        let prompt = r#"[{"role":"system","content":"abcd"},{"role":"user","content":"abcdefgh"}]"#;
+        assert_eq!(tokenizer.count_prompt(prompt), 1 + 2 + 2 * TOKENS_PER_MESSAGE);
+        assert_eq!(tokenizer.count_prompt("abcdefgh"), 2);
+    }
+
+    #[test]
+    fn estimates_replace_counts_only_when_usage_was_missing() {
+        let tokenizers = Tokenizers::load(&[rule("*", "chars/4")]);
+        let mut record = RequestRecord::new(
+            "/v1/completions".to_string(),
+            "m".to_string(),
+            chrono::Utc::now(),
+            "abcdefgh".to_string(),
+        );
+        record.output = "abcd".to_string();
+        record.input_tokens = 10;
+        record.output_tokens = 5;
+        tokenizers.annotate(&mut record);
+        assert_eq!((record.input_tokens, record.output_tokens), (10, 5));
+        assert_eq!(record.estimated_input_tokens, Some(2));
+        assert_eq!(record.estimated_output_tokens, Some(1));
+        assert_eq!(record.tokenizer.as_deref(), Some("chars/4"));
+
+        record.estimated_output_tokens = None;
+        record.tokens_estimated = true;
+        tokenizers.annotate(&mut record);
+        assert_eq!((record.input_tokens, record.output_tokens), (2, 1));
+        assert_eq!(record.total_tokens, 3);
+    }
+
+    #[test]
+    fn running_count_matches_a_one_shot_count() {
+        let tokenizer = Arc::new(Tokenizer::load("cl100k").unwrap());
+        let mut count = RunningCount::new(tokenizer.clone());
+        let mut output = String::new();
+        for i in 0..200 {
+            output.push_str(&format!("word{} ", i));
+            count.observe(&output);
+        }
+        assert!(count.settled() <= count.total(&output));
+        assert_eq!(count.total(&output), tokenizer.count(&output));
+    }
+
+    /// Feeds `pieces` as streamed chunks, updating on every chunk, and checks the running
+    /// count against a one-shot count of the output so far every `check_every` chunks and
+    /// at the end.
+    fn stream_with_every_update(tokenizer: &Arc<Tokenizer>, pieces: &[&str], check_every: usize) {
+        let mut count = RunningCount::new(tokenizer.clone());
+        let mut output = String::new();
+        for (i, piece) in pieces.iter().enumerate() {
+            output.push_str(piece);
+            count.chunks_since_update = RUNNING_COUNT_EVERY_CHUNKS;
+            count.last_update = None;
+            count.observe(&output);
+            if i % check_every == 0 || i == pieces.len() - 1 {
+                assert!(count.settled() <= count.total(&output), "{:?}", output);
+                assert_eq!(count.total(&output), tokenizer.count(&output), "{:?}", output);
+            }
+        }
+    }
+
+    #[test]
+    fn running_count_matches_after_every_update() {
+        let pieces = [
+            "Once", " upon", " a time", ",\n\nthere", " was a ", "fn main() {\n    let x",
+            " = 42;\n}\n", "Café ", "naïve", " résumé — ", "東京", "は 大きい ", "🦀🦀 ",
+            "crab", "s!!!   ", "\t", "end.",
+        ];
+        for source in ["cl100k", "o200k", "chars/4"] {
+            let tokenizer = Arc::new(Tokenizer::load(source).unwrap());
+            stream_with_every_update(&tokenizer, &pieces, 1);
+        }
+    }
+
+    #[test]
+    fn running_count_matches_on_a_long_document() {
+        // The start of the README streamed in small chunks, like a model writing markdown
+        // and code
+        let mut pieces = Vec::new();
+        let mut rest = include_str!("../README.md");
+        while !rest.is_empty() && pieces.len() < 5_000 {
+            let mut cut = rest.len().min(7);
+            while !rest.is_char_boundary(cut) {
+                cut += 1;
+            }
+            pieces.push(&rest[..cut]);
+            rest = &rest[cut..];
+        }
+        for source in ["cl100k", "o200k", "chars/4"] {
+            let tokenizer = Arc::new(Tokenizer::load(source).unwrap());
+            stream_with_every_update(&tokenizer, &pieces, 1_000);
+        }
+    }
+
+    #[test]
+    fn running_count_settles_unspaced_text_at_a_char_boundary() {
+        let tokenizer = Arc::new(Tokenizer::chars());
+        let mut count = RunningCount::new(tokenizer.clone());
+        let mut output = String::new();
+        for _ in 0..RUNNING_COUNT_EVERY_CHUNKS {
+            output.push_str(&"東".repeat(1_000));
+            count.observe(&output);
+        }
+        // Nothing to cut at but the pending text is past the cap, so it is settled
+        // short of its last character
+        assert_eq!(count.settled_chars, 16 * 1_000 - 1);
+        assert_eq!(count.total(&output), tokenizer.count(&output));
+    }
+
+    #[test]
+    fn running_count_updates_are_rate_limited() {
+        let tokenizer = Arc::new(Tokenizer::chars());
+        let mut count = RunningCount::new(tokenizer.clone());
+        let mut output = String::new();
+        // A burst far faster than the minimum interval gets a single update
+        for _ in 0..10 * RUNNING_COUNT_EVERY_CHUNKS {
+            output.push_str("abcd ");
+            count.observe(&output);
+        }
+        assert_eq!(count.settled(), (RUNNING_COUNT_EVERY_CHUNKS as i64 * 5 - 1 + 3) / 4);
+        assert_eq!(count.total(&output), tokenizer.count(&output));
+
+        count.last_update = Instant::now().checked_sub(RUNNING_COUNT_MIN_INTERVAL);
+        output.push_str("abcd ");
+        count.observe(&output);
+        assert_eq!(count.settled_bytes, output.len() - 1);
+    }
+}
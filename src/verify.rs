@@ -0,0 +1,371 @@
+//! Consistency checks of the rolling per-minute counters against the raw requests.
+//!
+//! The counters are kept twice, in memory and in the `minute_counters` table, and both
+//! are derived from the requests table. A check recomputes each minute from the raw
+//! rows and reports every model/minute where either copy disagrees; a repair rewrites
+//! both copies from the recomputed values.
+
+use chrono::{Duration, NaiveTime, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+use crate::counters::{MinuteCount, MinuteCounters, RETENTION_MINUTES, minute_key};
+use crate::db::counters::{self, MinuteCounterRow};
+use crate::diagnostics::SelfDiagnostics;
+
+/// Minutes this recent are skipped: a request can be counted in memory a moment before
+/// its row is inserted
+const SETTLE_MINUTES: i64 = 2;
+
+/// Time of day (UTC) of the automatic check
+const NIGHTLY_AT: (u32, u32) = (0, 5);
+
+pub const SOURCE_MEMORY: &str = "memory";
+pub const SOURCE_TABLE: &str = "table";
+
+/// One model/minute where a copy of the counters disagrees with the raw rows.
+#[derive(Debug, Clone, Serialize)]
+pub struct Discrepancy {
+    pub minute: String,
+    pub model: String,
+    /// `memory` or `table`
+    pub source: &'static str,
+    /// Recomputed from the requests table
+    pub expected: MinuteCount,
+    pub actual: MinuteCount,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerificationSummary {
+    pub checked_at: String,
+    pub window_start: String,
+    pub window_end: String,
+    /// Model/minute buckets present in any of the three sources
+    pub buckets_checked: usize,
+    pub discrepancies: usize,
+    /// Sum of absolute request-count differences across all discrepancies
+    pub drift_requests: i64,
+    /// Sum of absolute input plus output token differences
+    pub drift_tokens: i64,
+    pub repaired: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerificationReport {
+    pub summary: VerificationSummary,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// Runs checks and remembers the latest result for `/health`.
+pub struct Verifier {
+    db: SqlitePool,
+    counters: Arc<MinuteCounters>,
+    diagnostics: Arc<SelfDiagnostics>,
+    last: Mutex<Option<VerificationSummary>>,
+}
+
+impl Verifier {
+    pub fn new(
+        db: SqlitePool,
+        counters: Arc<MinuteCounters>,
+        diagnostics: Arc<SelfDiagnostics>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            db,
+            counters,
+            diagnostics,
+            last: Mutex::new(None),
+        })
+    }
+
+    pub fn last(&self) -> Option<VerificationSummary> {
+        self.last.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Checks the minutes from `since` (at most the retained window) up to the settled
+    /// present, rewriting both copies from the raw rows when `repair` is set.
+    pub async fn run(
+        &self,
+        since: Option<Duration>,
+        repair: bool,
+    ) -> Result<VerificationReport, sqlx::Error> {
+        let now = Utc::now();
+        // Skip the oldest retained minute too, which trimming may remove mid-check
+        let oldest = now - Duration::minutes(RETENTION_MINUTES - 1);
+        let from = minute_key(since.map_or(oldest, |since| (now - since).max(oldest)));
+        let until = minute_key(now - Duration::minutes(SETTLE_MINUTES));
+
+        let raw: Vec<MinuteCounterRow> = counters::count_requests_by_minute(&self.db, &from)
+            .await?
+            .into_iter()
+            .filter(|row| row.minute < until)
+            .collect();
+        let expected = by_bucket(&raw);
+        let memory = by_bucket(&self.counters.minutes_between(&from, &until));
+        let table = by_bucket(&counters::get_minute_counters(&self.db, &from, &until).await?);
+
+        let buckets: BTreeSet<_> = expected
+            .keys()
+            .chain(memory.keys())
+            .chain(table.keys())
+            .collect();
+        let mut discrepancies = Vec::new();
+        for bucket in &buckets {
+            let want = expected.get(*bucket).copied().unwrap_or_default();
+            for (source, copy) in [(SOURCE_MEMORY, &memory), (SOURCE_TABLE, &table)] {
+                let actual = copy.get(*bucket).copied().unwrap_or_default();
+                if actual != want {
+                    discrepancies.push(Discrepancy {
+                        minute: bucket.0.clone(),
+                        model: bucket.1.clone(),
+                        source,
+                        expected: want,
+                        actual,
+                    });
+                }
+            }
+        }
+
+        let repaired = repair && !discrepancies.is_empty();
+        if repaired {
+            counters::replace_minute_counters(&self.db, &from, &until, &raw, &minute_key(now))
+                .await?;
+            self.counters.replace_between(&from, &until, &raw);
+        }
+
+        let summary = VerificationSummary {
+            checked_at: now.to_rfc3339(),
+            window_start: from,
+            window_end: until,
+            buckets_checked: buckets.len(),
+            discrepancies: discrepancies.len(),
+            drift_requests: discrepancies
+                .iter()
+                .map(|d| (d.actual.requests - d.expected.requests).abs())
+                .sum(),
+            drift_tokens: discrepancies
+                .iter()
+                .map(|d| {
+                    (d.actual.input_tokens - d.expected.input_tokens).abs()
+                        + (d.actual.output_tokens - d.expected.output_tokens).abs()
+                })
+                .sum(),
+            repaired,
+        };
+
+        if summary.discrepancies > 0 {
+            tracing::warn!(
+                "Counter verification found {} discrepancies ({} requests, {} tokens of drift){}",
+                summary.discrepancies,
+                summary.drift_requests,
+                summary.drift_tokens,
+                if repaired { ", repaired" } else { "" }
+            );
+        }
+        if let Ok(detail) = serde_json::to_value(&summary)
+            && let Err(e) = crate::db::record_event(&self.db, "counter_verification", &detail).await
+        {
+            self.diagnostics.event_record_failed();
+            tracing::error!("Failed to record counter verification: {}", e);
+        }
+        *self.last.lock().unwrap_or_else(|e| e.into_inner()) = Some(summary.clone());
+
+        Ok(VerificationReport {
+            summary,
+            discrepancies,
+        })
+    }
+
+    /// Checks the whole retained window once a night, reporting without repairing.
+    pub fn spawn_nightly(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let verifier = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(until_next_run()).await;
+                if let Err(e) = verifier.run(None, false).await {
+                    tracing::error!("Nightly counter verification failed: {}", e);
+                }
+            }
+        })
+    }
+}
+
+fn by_bucket(rows: &[MinuteCounterRow]) -> BTreeMap<(String, String), MinuteCount> {
+    rows.iter()
+        .map(|row| ((row.minute.clone(), row.model.clone()), row.count))
+        .collect()
+}
+
+fn until_next_run() -> std::time::Duration {
+    let now = Utc::now();
+    let at = NaiveTime::from_hms_opt(NIGHTLY_AT.0, NIGHTLY_AT.1, 0).unwrap_or_default();
+    let mut next = now.date_naive().and_time(at).and_utc();
+    if next <= now {
+        next += Duration::days(1);
+    }
+    (next - now).to_std().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use crate::db::{RequestRecord, insert_request};
+    use chrono::DateTime;
+
+    fn finished(
+        now: DateTime<Utc>,
+        minutes_ago: i64,
+        model: &str,
+        input_tokens: i64,
+    ) -> RequestRecord {
+        let end_time = now - Duration::minutes(minutes_ago);
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            model.to_string(),
+            end_time,
+            "Tell me a story".to_string(),
+        );
+        record.end_time = end_time.to_rfc3339();
+        record.input_tokens = input_tokens;
+        record.output_tokens = 5;
+        record
+    }
+
+    /// A verifier over consistent counters: each request stored, counted and flushed.
+    async fn consistent(records: &[RequestRecord]) -> (SqlitePool, Arc<Verifier>) {
+        let pool = memory_pool().await;
+        let counters = Arc::new(MinuteCounters::default());
+        for record in records {
+            insert_request(&pool, record).await.unwrap();
+            counters.record(record);
+        }
+        counters.flush(&pool).await.unwrap();
+        let verifier = Verifier::new(pool.clone(), counters, SelfDiagnostics::new());
+        (pool, verifier)
+    }
+
+    fn found(report: &VerificationReport) -> Vec<(&str, &str, i64, i64)> {
+        report
+            .discrepancies
+            .iter()
+            .map(|d| (d.model.as_str(), d.source, d.expected.requests, d.actual.requests))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn consistent_counters_pass() {
+        let now = Utc::now();
+        let (_pool, verifier) =
+            consistent(&[finished(now, 10, "a", 100), finished(now, 5, "b", 50)]).await;
+
+        let report = verifier.run(None, false).await.unwrap();
+
+        assert_eq!(report.summary.buckets_checked, 2);
+        assert_eq!(report.summary.discrepancies, 0);
+        assert!(!report.summary.repaired);
+    }
+
+    #[tokio::test]
+    async fn corrupted_rollup_row_is_detected_and_repaired() {
+        let now = Utc::now();
+        let (pool, verifier) =
+            consistent(&[finished(now, 10, "a", 100), finished(now, 5, "b", 50)]).await;
+        sqlx::query(
+            "UPDATE minute_counters SET requests = requests + 3, input_tokens = 999
+             WHERE model = 'a'",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        // A model the requests table never saw
+        sqlx::query(
+            "INSERT INTO minute_counters
+                 (minute, model, requests, input_tokens, output_tokens, flushed_at)
+             VALUES (?1, 'ghost', 2, 0, 0, ?1)",
+        )
+        .bind(minute_key(now - Duration::minutes(7)))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let report = verifier.run(None, false).await.unwrap();
+        assert_eq!(found(&report), [("a", SOURCE_TABLE, 1, 4), ("ghost", SOURCE_TABLE, 0, 2)]);
+        assert_eq!(report.summary.drift_requests, 5);
+        assert_eq!(report.summary.drift_tokens, 899);
+        assert!(!report.summary.repaired);
+        // Checking alone leaves the corruption in place
+        assert_eq!(verifier.run(None, false).await.unwrap().summary.discrepancies, 2);
+
+        let repair = verifier.run(None, true).await.unwrap();
+        assert_eq!(repair.summary.discrepancies, 2);
+        assert!(repair.summary.repaired);
+
+        let after = verifier.run(None, false).await.unwrap();
+        assert_eq!(after.summary.discrepancies, 0);
+        assert_eq!(after.summary.buckets_checked, 2);
+        let stored: i64 = sqlx::query_scalar("SELECT SUM(requests) FROM minute_counters")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, 2);
+    }
+
+    #[tokio::test]
+    async fn drifted_memory_counters_are_detected_and_repaired() {
+        let now = Utc::now();
+        let (_pool, verifier) = consistent(&[finished(now, 10, "a", 100)]).await;
+        // Counted twice in memory, as a double-counting bug would
+        verifier.counters.record(&finished(now, 10, "a", 100));
+
+        let report = verifier.run(None, true).await.unwrap();
+        assert_eq!(found(&report), [("a", SOURCE_MEMORY, 1, 2)]);
+        assert_eq!(report.summary.drift_tokens, 105);
+
+        let from = minute_key(now - Duration::minutes(20));
+        let memory = verifier.counters.minutes_between(&from, &minute_key(now));
+        assert_eq!(memory.len(), 1);
+        assert_eq!(memory[0].count.requests, 1);
+        assert_eq!(verifier.run(None, false).await.unwrap().summary.discrepancies, 0);
+    }
+
+    #[tokio::test]
+    async fn unsettled_and_out_of_window_minutes_are_skipped() {
+        let now = Utc::now();
+        let (_pool, verifier) =
+            consistent(&[finished(now, 30, "a", 100), finished(now, 10, "a", 100)]).await;
+        // In memory a moment before its row is written
+        verifier.counters.record(&finished(now, 0, "a", 100));
+        verifier.counters.record(&finished(now, 30, "a", 100));
+
+        let recent = verifier.run(Some(Duration::minutes(20)), false).await.unwrap();
+        assert_eq!(recent.summary.buckets_checked, 1);
+        assert_eq!(recent.summary.discrepancies, 0);
+
+        let everything = verifier.run(None, false).await.unwrap();
+        assert_eq!(found(&everything), [("a", SOURCE_MEMORY, 1, 2)]);
+    }
+
+    #[tokio::test]
+    async fn last_result_is_kept_and_recorded() {
+        let now = Utc::now();
+        let (pool, verifier) = consistent(&[finished(now, 10, "a", 100)]).await;
+        assert!(verifier.last().is_none());
+
+        verifier.counters.record(&finished(now, 10, "a", 100));
+        verifier.run(None, false).await.unwrap();
+
+        let last = verifier.last().unwrap();
+        assert_eq!(last.discrepancies, 1);
+        assert_eq!(last.drift_requests, 1);
+        let recorded: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM proxy_events WHERE kind = 'counter_verification'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(recorded, 1);
+    }
+}
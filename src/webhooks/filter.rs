@@ -0,0 +1,617 @@
+//! A small predicate language for picking out requests, used by webhook subscriptions.
+//!
+//! A filter compares fields of a stored request, as they appear in its JSON form, with
+//! literals, and combines the comparisons with `and`, `or`, `not` and parentheses:
+//!
+//! ```text
+//! model ~ "qwen*" and (is_error or output_tokens > 2000)
+//! ```
+//!
+//! The operators are `==`, `!=`, `<`, `<=`, `>`, `>=` and `~`, which matches a `*`
+//! wildcard pattern without regard to case, like the model patterns in the config.
+//! Literals are numbers, strings in single or double quotes, `true`, `false` and `null`.
+//! A field on its own holds unless it is missing, `null`, `false`, `0` or empty. Dotted
+//! names reach into JSON fields, as in `sampling_params.temperature`.
+//!
+//! Ordering only holds between two numbers or two strings, so `output_tokens > 100` is
+//! false for a request without a count rather than an error. A missing field equals
+//! `null` and nothing else.
+
+use serde_json::Value;
+use std::cmp::Ordering;
+
+use crate::config::model_pattern_matches;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Op(Op),
+    LParen,
+    RParen,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare {
+        field: Vec<String>,
+        op: Op,
+        value: Value,
+    },
+    Truthy(Vec<String>),
+}
+
+/// A parsed filter expression.
+#[derive(Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    /// Parses `text`, naming the character position of the first problem on failure.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: text.chars().count(),
+        };
+        if parser.tokens.is_empty() {
+            return Err("filter is empty".to_string());
+        }
+        let expr = parser.or()?;
+        if let Some((at, token)) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected {} at position {}", describe(token), at));
+        }
+        Ok(Self { expr })
+    }
+
+    /// Whether a request, in its JSON form, satisfies the filter.
+    pub fn matches(&self, record: &Value) -> bool {
+        evaluate(&self.expr, record)
+    }
+
+    /// Top-level field names the filter reads.
+    pub fn fields(&self) -> Vec<&str> {
+        let mut fields = Vec::new();
+        collect_fields(&self.expr, &mut fields);
+        fields
+    }
+}
+
+fn collect_fields<'a>(expr: &'a Expr, fields: &mut Vec<&'a str>) {
+    match expr {
+        Expr::Or(left, right) | Expr::And(left, right) => {
+            collect_fields(left, fields);
+            collect_fields(right, fields);
+        }
+        Expr::Not(inner) => collect_fields(inner, fields),
+        Expr::Compare { field, .. } | Expr::Truthy(field) => fields.push(&field[0]),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(name) => format!("'{}'", name),
+        Token::Str(text) => format!("string \"{}\"", text),
+        Token::Num(number) => format!("number {}", number),
+        Token::Op(_) => "operator".to_string(),
+        Token::LParen => "'('".to_string(),
+        Token::RParen => "')'".to_string(),
+    }
+}
+
+/// Splits the text into tokens, each with the character position it starts at.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let token = match c {
+            '(' => {
+                i += 1;
+                Token::LParen
+            }
+            ')' => {
+                i += 1;
+                Token::RParen
+            }
+            '"' | '\'' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(format!("unterminated string at position {}", start)),
+                        Some('\\') => {
+                            let Some(escaped) = chars.get(i + 1) else {
+                                return Err(format!("unterminated string at position {}", start));
+                            };
+                            value.push(*escaped);
+                            i += 2;
+                        }
+                        Some(quote) if *quote == c => {
+                            i += 1;
+                            break;
+                        }
+                        Some(other) => {
+                            value.push(*other);
+                            i += 1;
+                        }
+                    }
+                }
+                Token::Str(value)
+            }
+            '=' | '!' | '<' | '>' | '~' => {
+                let next = chars.get(i + 1).copied();
+                let (op, len) = match (c, next) {
+                    ('=', Some('=')) => (Op::Eq, 2),
+                    ('!', Some('=')) => (Op::Ne, 2),
+                    ('<', Some('=')) => (Op::Le, 2),
+                    ('>', Some('=')) => (Op::Ge, 2),
+                    ('<', _) => (Op::Lt, 1),
+                    ('>', _) => (Op::Gt, 1),
+                    ('~', _) => (Op::Match, 1),
+                    _ => {
+                        return Err(format!(
+                            "unknown operator at position {}, expected one of == != < <= > >= ~",
+                            start
+                        ));
+                    }
+                };
+                i += len;
+                Token::Op(op)
+            }
+            c if c.is_ascii_digit()
+                || (c == '-' && chars.get(i + 1).is_some_and(|next| next.is_ascii_digit())) =>
+            {
+                i += 1;
+                while chars
+                    .get(i)
+                    .is_some_and(|next| next.is_ascii_digit() || *next == '.')
+                {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                let number = number
+                    .parse()
+                    .map_err(|_| format!("invalid number {} at position {}", number, start))?;
+                Token::Num(number)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while chars
+                    .get(i)
+                    .is_some_and(|next| next.is_alphanumeric() || *next == '_' || *next == '.')
+                {
+                    i += 1;
+                }
+                Token::Ident(chars[start..i].iter().collect())
+            }
+            other => {
+                return Err(format!("unexpected '{}' at position {}", other, start));
+            }
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `or` > `and` > `not` > comparisons, loosest first.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Length of the text, reported when it ends too early
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|(at, _)| *at)
+            .unwrap_or(self.end)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, token)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    /// Consumes the keyword if it comes next.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(name)) if name.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.keyword("or") {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.not()?;
+        while self.keyword("and") {
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let at = self.position();
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                let at = self.position();
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(format!("expected ')' at position {}", at)),
+                }
+            }
+            Some(Token::Ident(name)) if !is_reserved(&name) => {
+                let field: Vec<String> = name.split('.').map(str::to_string).collect();
+                if field.iter().any(String::is_empty) {
+                    return Err(format!("invalid field name '{}' at position {}", name, at));
+                }
+                let Some(Token::Op(op)) = self.peek().cloned() else {
+                    return Ok(Expr::Truthy(field));
+                };
+                self.pos += 1;
+                let value = self.literal()?;
+                if op == Op::Match && !value.is_string() {
+                    return Err(format!("'~' needs a string pattern, at position {}", at));
+                }
+                Ok(Expr::Compare { field, op, value })
+            }
+            Some(token) => Err(format!(
+                "expected a field, 'not' or '(' at position {}, found {}",
+                at,
+                describe(&token)
+            )),
+            None => Err(format!("filter ends early at position {}", at)),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Value, String> {
+        let at = self.position();
+        match self.next() {
+            Some(Token::Str(text)) => Ok(Value::String(text)),
+            Some(Token::Num(number)) => Ok(Value::from(number)),
+            Some(Token::Ident(name)) if name.eq_ignore_ascii_case("true") => Ok(Value::Bool(true)),
+            Some(Token::Ident(name)) if name.eq_ignore_ascii_case("false") => {
+                Ok(Value::Bool(false))
+            }
+            Some(Token::Ident(name)) if name.eq_ignore_ascii_case("null") => Ok(Value::Null),
+            Some(token) => Err(format!(
+                "expected a value at position {}, found {}",
+                at,
+                describe(&token)
+            )),
+            None => Err(format!(
+                "filter ends early at position {}, expected a value",
+                at
+            )),
+        }
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    ["and", "or", "not", "true", "false", "null"]
+        .iter()
+        .any(|reserved| name.eq_ignore_ascii_case(reserved))
+}
+
+fn evaluate(expr: &Expr, record: &Value) -> bool {
+    match expr {
+        Expr::Or(left, right) => evaluate(left, record) || evaluate(right, record),
+        Expr::And(left, right) => evaluate(left, record) && evaluate(right, record),
+        Expr::Not(inner) => !evaluate(inner, record),
+        Expr::Truthy(field) => match lookup(record, field) {
+            None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+            Some(Value::Number(number)) => number.as_f64() != Some(0.0),
+            Some(Value::String(text)) => !text.is_empty(),
+            Some(Value::Array(items)) => !items.is_empty(),
+            Some(Value::Object(fields)) => !fields.is_empty(),
+            Some(Value::Bool(true)) => true,
+        },
+        Expr::Compare { field, op, value } => {
+            let actual = lookup(record, field).unwrap_or(&Value::Null);
+            match op {
+                Op::Eq => equal(actual, value),
+                Op::Ne => !equal(actual, value),
+                Op::Match => match (actual, value) {
+                    (Value::String(actual), Value::String(pattern)) => {
+                        model_pattern_matches(pattern, actual)
+                    }
+                    _ => false,
+                },
+                Op::Lt => order(actual, value) == Some(Ordering::Less),
+                Op::Le => matches!(order(actual, value), Some(Ordering::Less | Ordering::Equal)),
+                Op::Gt => order(actual, value) == Some(Ordering::Greater),
+                Op::Ge => matches!(
+                    order(actual, value),
+                    Some(Ordering::Greater | Ordering::Equal)
+                ),
+            }
+        }
+    }
+}
+
+fn lookup<'a>(record: &'a Value, field: &[String]) -> Option<&'a Value> {
+    field
+        .iter()
+        .try_fold(record, |value, name| value.as_object()?.get(name))
+}
+
+/// Numbers compare by value, so `max_tokens == 512` holds for a stored `512`.
+fn equal(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => actual == expected,
+    }
+}
+
+fn order(actual: &Value, expected: &Value) -> Option<Ordering> {
+    match (actual, expected) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record() -> Value {
+        json!({
+            "model": "Qwen2.5-7B-Instruct",
+            "is_error": false,
+            "output_tokens": 2500,
+            "input_tokens": 0,
+            "http_status": 200,
+            "client_id": null,
+            "finish_reason": "stop",
+            "error_message": "",
+            "max_tokens": 512,
+            "temperature": 0.7,
+            "sampling_params": { "temperature": 0.2, "top_p": 0.9 },
+            "stop_sequences": [],
+        })
+    }
+
+    fn holds(filter: &str) -> bool {
+        Filter::parse(filter)
+            .unwrap_or_else(|e| panic!("{}: {}", filter, e))
+            .matches(&record())
+    }
+
+    #[test]
+    fn comparisons() {
+        let cases = [
+            ("output_tokens > 2000", true),
+            ("output_tokens > 2500", false),
+            ("output_tokens >= 2500", true),
+            ("output_tokens < 2500", false),
+            ("output_tokens <= 2500", true),
+            ("output_tokens == 2500", true),
+            ("output_tokens == 2500.0", true),
+            ("output_tokens != 2500", false),
+            ("temperature < 1", true),
+            ("temperature > -1", true),
+            ("http_status == 200", true),
+            ("finish_reason == \"stop\"", true),
+            ("finish_reason == 'stop'", true),
+            ("finish_reason != \"length\"", true),
+            ("finish_reason == \"STOP\"", false),
+            ("finish_reason < \"t\"", true),
+            ("is_error == false", true),
+            ("is_error == true", false),
+            ("client_id == null", true),
+            ("client_id != null", false),
+        ];
+        for (filter, expected) in cases {
+            assert_eq!(holds(filter), expected, "{}", filter);
+        }
+    }
+
+    #[test]
+    fn ordering_only_holds_between_like_values() {
+        let cases = [
+            // A string and a number never order, either way round
+            ("finish_reason > 1", false),
+            ("finish_reason < 1", false),
+            ("output_tokens > \"1\"", false),
+            ("output_tokens < \"1\"", false),
+            ("output_tokens == \"2500\"", false),
+            // Null and missing fields order against nothing
+            ("client_id < 1", false),
+            ("client_id >= 0", false),
+            ("missing > 0", false),
+            ("missing <= 0", false),
+        ];
+        for (filter, expected) in cases {
+            assert_eq!(holds(filter), expected, "{}", filter);
+        }
+    }
+
+    #[test]
+    fn missing_fields_equal_null_and_nothing_else() {
+        assert!(holds("missing == null"));
+        assert!(!holds("missing != null"));
+        assert!(!holds("missing == 0"));
+        assert!(!holds("missing == \"\""));
+        assert!(holds("missing != 0"));
+        assert!(!holds("missing"));
+        assert!(!holds("missing ~ \"*\""));
+    }
+
+    #[test]
+    fn wildcard_match_ignores_case() {
+        let cases = [
+            ("model ~ \"qwen*\"", true),
+            ("model ~ \"*instruct\"", true),
+            ("model ~ \"*7B*\"", true),
+            ("model ~ \"qwen2.5-7b-instruct\"", true),
+            ("model ~ \"llama*\"", false),
+            ("model ~ \"qwen\"", false),
+            ("model ~ \"*\"", true),
+            // Only strings match a pattern
+            ("output_tokens ~ \"2*\"", false),
+        ];
+        for (filter, expected) in cases {
+            assert_eq!(holds(filter), expected, "{}", filter);
+        }
+    }
+
+    #[test]
+    fn a_bare_field_holds_unless_empty_or_false() {
+        let cases = [
+            ("model", true),
+            ("output_tokens", true),
+            ("sampling_params", true),
+            ("is_error", false),
+            ("input_tokens", false),
+            ("client_id", false),
+            ("error_message", false),
+            ("stop_sequences", false),
+            ("missing", false),
+        ];
+        for (filter, expected) in cases {
+            assert_eq!(holds(filter), expected, "{}", filter);
+        }
+    }
+
+    #[test]
+    fn dotted_names_reach_into_json_fields() {
+        assert!(holds("sampling_params.temperature == 0.2"));
+        assert!(holds("sampling_params.top_p > 0.5"));
+        assert!(!holds("sampling_params.seed"));
+        assert!(holds("sampling_params.seed == null"));
+        // Reaching into something that isn't an object finds nothing
+        assert!(holds("model.name == null"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or_and_not_tighter_than_both() {
+        let cases = [
+            ("is_error or output_tokens > 2000 and http_status == 200", true),
+            ("is_error and output_tokens > 2000 or http_status == 200", true),
+            ("is_error and (output_tokens > 2000 or http_status == 200)", false),
+            ("(is_error or output_tokens > 2000) and http_status == 500", false),
+            ("not is_error and http_status == 200", true),
+            ("not (is_error or http_status == 200)", false),
+            ("not is_error or is_error", true),
+            ("not not model", true),
+            ("NOT is_error AND model ~ 'QWEN*'", true),
+            ("((model))", true),
+            ("model ~ \"qwen*\" and (is_error or output_tokens > 2000)", true),
+        ];
+        for (filter, expected) in cases {
+            assert_eq!(holds(filter), expected, "{}", filter);
+        }
+    }
+
+    #[test]
+    fn strings_take_escapes_and_either_quote() {
+        let record = json!({ "error_message": "said \"no\" and it's final\\" });
+        let filter = Filter::parse(r#"error_message == "said \"no\" and it's final\\""#).unwrap();
+        assert!(filter.matches(&record));
+        let filter = Filter::parse(r#"error_message == 'said "no" and it\'s final\\'"#).unwrap();
+        assert!(filter.matches(&record));
+        let filter = Filter::parse("error_message ~ '*\"no\"*'").unwrap();
+        assert!(filter.matches(&record));
+    }
+
+    #[test]
+    fn fields_lists_the_top_level_names() {
+        let filter = Filter::parse(
+            "model ~ 'a*' and not (is_error or sampling_params.temperature > 1) or model",
+        )
+        .unwrap();
+        assert_eq!(filter.fields(), ["model", "is_error", "sampling_params", "model"]);
+    }
+
+    #[test]
+    fn errors_name_the_position() {
+        let cases = [
+            ("", "filter is empty"),
+            ("   ", "filter is empty"),
+            ("model ==", "filter ends early at position 8, expected a value"),
+            ("model == \"qwen", "unterminated string at position 9"),
+            ("model == 'qwen\\", "unterminated string at position 9"),
+            ("model = 'a'", "unknown operator at position 6, expected one of == != < <= > >= ~"),
+            ("!model", "unknown operator at position 0, expected one of == != < <= > >= ~"),
+            ("model ~ 5", "'~' needs a string pattern, at position 0"),
+            ("tokens > 1.2.3", "invalid number 1.2.3 at position 9"),
+            ("model == 'a' $", "unexpected '$' at position 13"),
+            ("model and", "filter ends early at position 9"),
+            ("model or or", "expected a field, 'not' or '(' at position 9, found 'or'"),
+            ("(model", "expected ')' at position 6"),
+            ("model)", "unexpected ')' at position 5"),
+            ("model 'a'", "unexpected string \"a\" at position 6"),
+            ("model == 1 2", "unexpected number 2 at position 11"),
+            ("== 1", "expected a field, 'not' or '(' at position 0, found operator"),
+            ("true", "expected a field, 'not' or '(' at position 0, found 'true'"),
+            ("model == other", "expected a value at position 9, found 'other'"),
+            ("model == ==", "expected a value at position 9, found operator"),
+            ("model. == 1", "invalid field name 'model.' at position 0"),
+            ("a..b", "invalid field name 'a..b' at position 0"),
+            ("-", "unexpected '-' at position 0"),
+            // Positions count characters, not bytes
+            ("model == 'é' @", "unexpected '@' at position 13"),
+        ];
+        for (filter, expected) in cases {
+            let error = Filter::parse(filter).expect_err(filter);
+            assert_eq!(error, expected, "{}", filter);
+        }
+    }
+
+    #[test]
+    fn literals_are_case_insensitive_keywords() {
+        assert!(holds("is_error == FALSE"));
+        assert!(holds("client_id == Null"));
+        assert!(holds("not is_error And True_field == null"));
+    }
+}
@@ -0,0 +1,709 @@
+//! Webhook subscriptions: POSTs a stored request to a URL when it matches a filter.
+//!
+//! Subscriptions are managed through `/admin/webhooks`, kept in the `webhooks` table and
+//! held compiled in memory. Each request is checked against them right after its row is
+//! inserted. Deliveries run in their own tasks, so a slow subscriber never holds up
+//! persistence. A delivery is retried with backoff on network errors, 429s and 5xx
+//! responses, and every outcome is recorded in `webhook_deliveries`. Matches beyond a
+//! subscription's `max_per_minute` are recorded as `rate_limited` and not sent.
+
+pub mod filter;
+
+use http_body_util::BodyExt;
+use hyper::header::{CONTENT_TYPE, HeaderValue};
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::db::RequestRecord;
+use crate::db::webhooks::{
+    DELIVERY_DELIVERED, DELIVERY_FAILED, DELIVERY_RATE_LIMITED, DeliveryOutcome, NewWebhook,
+    Webhook,
+};
+use crate::proxy::client::HttpClient;
+use crate::sink::export_value;
+use filter::Filter;
+
+/// The full stored request, without prompt and output
+pub const PAYLOAD_RECORD: &str = "record";
+/// Just the fields [`SUMMARY_FIELDS`] lists
+pub const PAYLOAD_SUMMARY: &str = "summary";
+
+/// `sha256=` and the hex HMAC-SHA256 of the body under the subscription's secret
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// Event name sent in every payload
+const EVENT_REQUEST_COMPLETED: &str = "request.completed";
+
+const SUMMARY_FIELDS: &[&str] = &[
+    "id",
+    "proxy_request_id",
+    "endpoint",
+    "model",
+    "start_time",
+    "duration_ms",
+    "http_status",
+    "is_error",
+    "error_message",
+    "input_tokens",
+    "output_tokens",
+    "finish_reason",
+    "client_id",
+];
+
+/// Tries per delivery, the first included
+const DELIVERY_ATTEMPTS: i64 = 4;
+/// Wait before the first retry; doubled for each further one
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Longest a single attempt may take before it counts as failed
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// A subscription with its filter parsed, ready to store.
+pub struct CheckedWebhook {
+    webhook: NewWebhook,
+    filter: Filter,
+    uri: hyper::Uri,
+}
+
+/// Checks a new subscription's filter, URL, payload and rate.
+pub fn check(webhook: NewWebhook) -> Result<CheckedWebhook, String> {
+    let (filter, uri) = compile(&webhook.filter, &webhook.url)?;
+    if webhook.payload != PAYLOAD_RECORD && webhook.payload != PAYLOAD_SUMMARY {
+        return Err(format!(
+            "unknown payload '{}', expected {} or {}",
+            webhook.payload, PAYLOAD_RECORD, PAYLOAD_SUMMARY
+        ));
+    }
+    if webhook.max_per_minute < 1 {
+        return Err("max_per_minute must be at least 1".to_string());
+    }
+    Ok(CheckedWebhook {
+        webhook,
+        filter,
+        uri,
+    })
+}
+
+fn compile(filter: &str, url: &str) -> Result<(Filter, hyper::Uri), String> {
+    let filter = Filter::parse(filter).map_err(|e| format!("invalid filter: {}", e))?;
+    let known = record_fields();
+    if let Some(unknown) = filter
+        .fields()
+        .into_iter()
+        .find(|field| !known.contains_key(*field))
+    {
+        return Err(format!("invalid filter: unknown field '{}'", unknown));
+    }
+
+    let uri: hyper::Uri = url.parse().map_err(|e| format!("invalid url: {}", e))?;
+    if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.host().is_none() {
+        return Err(format!("invalid url '{}', expected an http(s) URL", url));
+    }
+    Ok((filter, uri))
+}
+
+/// Fields a filter may name: those of a request as exported.
+fn record_fields() -> Map<String, Value> {
+    let record = RequestRecord::new(
+        String::new(),
+        String::new(),
+        chrono::Utc::now(),
+        String::new(),
+    );
+    match export_value(&record, 0, false) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    }
+}
+
+struct Subscription {
+    webhook: Webhook,
+    filter: Filter,
+    uri: hyper::Uri,
+    /// Start of the current minute and the deliveries made in it
+    window: Mutex<(Instant, i64)>,
+}
+
+impl Subscription {
+    fn new(webhook: Webhook, filter: Filter, uri: hyper::Uri) -> Self {
+        Self {
+            webhook,
+            filter,
+            uri,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Counts a delivery against the rate limit, or says it is over.
+    fn take_slot(&self) -> bool {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        if window.0.elapsed() >= RATE_WINDOW {
+            *window = (Instant::now(), 0);
+        }
+        if window.1 >= self.webhook.max_per_minute {
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+}
+
+pub struct Webhooks {
+    db: SqlitePool,
+    client: HttpClient,
+    subscriptions: RwLock<Vec<Arc<Subscription>>>,
+}
+
+impl Webhooks {
+    /// Loads the stored subscriptions.
+    pub async fn start(db: SqlitePool, client: HttpClient) -> anyhow::Result<Arc<Self>> {
+        let mut subscriptions = Vec::new();
+        for webhook in crate::db::webhooks::list_webhooks(&db).await? {
+            match compile(&webhook.filter, &webhook.url) {
+                Ok((filter, uri)) => {
+                    subscriptions.push(Arc::new(Subscription::new(webhook, filter, uri)))
+                }
+                Err(e) => tracing::warn!("Webhook {} disabled: {}", webhook.id, e),
+            }
+        }
+        if !subscriptions.is_empty() {
+            tracing::info!("{} webhook subscription(s) active", subscriptions.len());
+        }
+
+        Ok(Arc::new(Self {
+            db,
+            client,
+            subscriptions: RwLock::new(subscriptions),
+        }))
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Vec<Arc<Subscription>>> {
+        self.subscriptions.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Vec<Arc<Subscription>>> {
+        self.subscriptions
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stores a subscription and starts delivering to it.
+    pub async fn add(&self, checked: CheckedWebhook) -> Result<Webhook, sqlx::Error> {
+        let webhook = crate::db::webhooks::insert_webhook(&self.db, &checked.webhook).await?;
+        let subscription = Subscription::new(webhook.clone(), checked.filter, checked.uri);
+        self.write().push(Arc::new(subscription));
+        Ok(webhook)
+    }
+
+    /// Stops and removes a subscription; `None` if there was no such webhook.
+    pub async fn remove(&self, id: i64) -> Result<Option<Webhook>, sqlx::Error> {
+        self.write()
+            .retain(|subscription| subscription.webhook.id != id);
+        crate::db::webhooks::delete_webhook(&self.db, id).await
+    }
+
+    /// Checks a stored request against every subscription and starts the deliveries.
+    pub fn notify(&self, record: &RequestRecord, id: i64) {
+        let subscriptions = self.read().clone();
+        if subscriptions.is_empty() {
+            return;
+        }
+        let value = match export_value(record, id, false) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!("Failed to serialize request {} for webhooks: {}", id, e);
+                return;
+            }
+        };
+
+        for subscription in subscriptions {
+            if !subscription.filter.matches(&value) {
+                continue;
+            }
+            let delivery = Delivery {
+                db: self.db.clone(),
+                client: self.client.clone(),
+                request_id: id,
+                proxy_request_id: record.proxy_request_id.clone(),
+                body: payload(&subscription.webhook, &value),
+                subscription,
+            };
+            tokio::spawn(delivery.run());
+        }
+    }
+}
+
+/// The JSON sent to a subscription for one request.
+fn payload(webhook: &Webhook, record: &Value) -> String {
+    let request = if webhook.payload == PAYLOAD_SUMMARY {
+        let fields: Map<String, Value> = SUMMARY_FIELDS
+            .iter()
+            .filter_map(|field| Some((field.to_string(), record.get(*field)?.clone())))
+            .collect();
+        Value::Object(fields)
+    } else {
+        record.clone()
+    };
+    json!({
+        "event": EVENT_REQUEST_COMPLETED,
+        "webhook_id": webhook.id,
+        "webhook": webhook.name,
+        "request": request,
+    })
+    .to_string()
+}
+
+/// One request on its way to one subscription.
+struct Delivery {
+    db: SqlitePool,
+    client: HttpClient,
+    subscription: Arc<Subscription>,
+    request_id: i64,
+    proxy_request_id: Option<String>,
+    body: String,
+}
+
+impl Delivery {
+    async fn run(self) {
+        let webhook = &self.subscription.webhook;
+        let outcome = if self.subscription.take_slot() {
+            self.send_with_retries().await
+        } else {
+            DeliveryOutcome {
+                status: DELIVERY_RATE_LIMITED,
+                attempts: 0,
+                http_status: None,
+                error: None,
+            }
+        };
+        if outcome.status == DELIVERY_FAILED {
+            tracing::warn!(
+                "Webhook {} failed for request {} after {} attempt(s): {}",
+                webhook.id,
+                self.request_id,
+                outcome.attempts,
+                outcome.error.as_deref().unwrap_or_default()
+            );
+        }
+
+        if let Err(e) = crate::db::webhooks::record_delivery(
+            &self.db,
+            webhook.id,
+            self.request_id,
+            self.proxy_request_id.as_deref(),
+            &outcome,
+        )
+        .await
+        {
+            tracing::error!("Failed to record webhook {} delivery: {}", webhook.id, e);
+        }
+    }
+
+    async fn send_with_retries(&self) -> DeliveryOutcome {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let (http_status, error) = match self.send().await {
+                Ok(status) => {
+                    return DeliveryOutcome {
+                        status: DELIVERY_DELIVERED,
+                        attempts,
+                        http_status: Some(status),
+                        error: None,
+                    };
+                }
+                Err(failure) => failure,
+            };
+            // Other 4xx responses won't change on a retry
+            let retryable = http_status.is_none_or(|status| status == 429 || status >= 500);
+            if !retryable || attempts >= DELIVERY_ATTEMPTS {
+                return DeliveryOutcome {
+                    status: DELIVERY_FAILED,
+                    attempts,
+                    http_status,
+                    error: Some(error),
+                };
+            }
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    /// One attempt, giving the response status, or on failure the status if there was a
+    /// response and what went wrong.
+    async fn send(&self) -> Result<i64, (Option<i64>, String)> {
+        let mut request = hyper::Request::post(self.subscription.uri.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(self.body.clone())
+            .map_err(|e| (None, e.to_string()))?;
+        if let Some(secret) = &self.subscription.webhook.secret {
+            let signature = format!("sha256={}", hmac_sha256(secret, &self.body));
+            if let Ok(value) = HeaderValue::from_str(&signature) {
+                request.headers_mut().insert(SIGNATURE_HEADER, value);
+            }
+        }
+
+        let response = tokio::time::timeout(SEND_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| (None, format!("no response within {:?}", SEND_TIMEOUT)))?
+            .map_err(|e| (None, e.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(status.as_u16() as i64);
+        }
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map(|body| String::from_utf8_lossy(&body.to_bytes()).into_owned())
+            .unwrap_or_default();
+        Err((
+            Some(status.as_u16() as i64),
+            format!("{} {}", status, body.chars().take(200).collect::<String>()),
+        ))
+    }
+}
+
+/// HMAC-SHA256 (RFC 2104) of `body` keyed with `secret`, hex-encoded.
+fn hmac_sha256(secret: &str, body: &str) -> String {
+    const BLOCK_SIZE: usize = 64;
+    let mut key = secret.as_bytes().to_vec();
+    if key.len() > BLOCK_SIZE {
+        key = Sha256::digest(&key).to_vec();
+    }
+    key.resize(BLOCK_SIZE, 0);
+
+    let inner_pad: Vec<u8> = key.iter().map(|byte| byte ^ 0x36).collect();
+    let outer_pad: Vec<u8> = key.iter().map(|byte| byte ^ 0x5c).collect();
+    let inner = Sha256::new()
+        .chain_update(&inner_pad)
+        .chain_update(body.as_bytes())
+        .finalize();
+    let outer = Sha256::new()
+        .chain_update(&outer_pad)
+        .chain_update(inner)
+        .finalize();
+    format!("{:x}", outer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::memory_pool;
+    use crate::db::webhooks::list_deliveries;
+    use crate::proxy::client::create_client;
+    use axum::Router;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode};
+
+    /// A local subscriber answering the `n`th delivery with `status(n)`.
+    #[derive(Clone)]
+    struct Subscriber {
+        received: Arc<Mutex<Vec<(HeaderMap, String)>>>,
+        status: fn(usize) -> StatusCode,
+    }
+
+    impl Subscriber {
+        async fn start(status: fn(usize) -> StatusCode) -> (Self, String) {
+            let subscriber = Subscriber {
+                received: Arc::default(),
+                status,
+            };
+            let app = Router::new()
+                .route("/hook", axum::routing::post(receive))
+                .with_state(subscriber.clone());
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/hook", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+            (subscriber, url)
+        }
+
+        fn received(&self) -> Vec<(HeaderMap, String)> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    async fn receive(
+        State(subscriber): State<Subscriber>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        let mut received = subscriber.received.lock().unwrap();
+        received.push((headers, body));
+        (subscriber.status)(received.len() - 1)
+    }
+
+    fn new_webhook(filter: &str, url: &str) -> NewWebhook {
+        NewWebhook {
+            name: Some("alerts".to_string()),
+            filter: filter.to_string(),
+            url: url.to_string(),
+            secret: None,
+            payload: PAYLOAD_RECORD.to_string(),
+            max_per_minute: 60,
+        }
+    }
+
+    fn record(model: &str, output_tokens: i64) -> RequestRecord {
+        let mut record = RequestRecord::new(
+            "/v1/chat/completions".to_string(),
+            model.to_string(),
+            chrono::Utc::now(),
+            "secret prompt".to_string(),
+        );
+        record.output = "secret output".to_string();
+        record.output_tokens = output_tokens;
+        record.proxy_request_id = Some(format!("req-{}-{}", model, output_tokens));
+        record
+    }
+
+    /// Waits for the webhook to have `count` deliveries recorded, and returns them.
+    async fn deliveries(
+        pool: &SqlitePool,
+        webhook_id: i64,
+        count: usize,
+    ) -> Vec<crate::db::webhooks::WebhookDelivery> {
+        for _ in 0..400 {
+            let deliveries = list_deliveries(pool, webhook_id, 100).await.unwrap();
+            if deliveries.len() >= count {
+                return deliveries;
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+        panic!("webhook {} never got {} deliveries", webhook_id, count);
+    }
+
+    #[test]
+    fn hmac_matches_known_digests() {
+        assert_eq!(
+            hmac_sha256("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hmac_sha256("key", "The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        // Keys longer than a block are hashed first
+        assert_eq!(
+            hmac_sha256(&"k".repeat(100), "body"),
+            "a3306f78bc603683d9d9d98b9304be3e3e99497c42e660d7b00f6698b0df4e89"
+        );
+    }
+
+    #[test]
+    fn check_rejects_bad_subscriptions() {
+        let url = "http://hooks.local/in";
+        assert!(check(new_webhook("model ~ 'qwen*' and output_tokens > 10", url)).is_ok());
+        assert!(check(new_webhook("sampling_params.temperature > 1", url)).is_ok());
+
+        let cases = [
+            (new_webhook("model ==", url), "invalid filter: filter ends early"),
+            (new_webhook("modle == 'x'", url), "invalid filter: unknown field 'modle'"),
+            (new_webhook("prompt ~ '*x*'", url), "invalid filter: unknown field 'prompt'"),
+            (new_webhook("is_error", "not a url"), "invalid url"),
+            (new_webhook("is_error", "ftp://hooks.local/"), "invalid url 'ftp://hooks.local/'"),
+            (new_webhook("is_error", "/relative"), "invalid url '/relative'"),
+            (
+                NewWebhook {
+                    payload: "everything".to_string(),
+                    ..new_webhook("is_error", url)
+                },
+                "unknown payload 'everything', expected record or summary",
+            ),
+            (
+                NewWebhook {
+                    max_per_minute: 0,
+                    ..new_webhook("is_error", url)
+                },
+                "max_per_minute must be at least 1",
+            ),
+        ];
+        for (webhook, expected) in cases {
+            let filter = webhook.filter.clone();
+            let error = check(webhook).err().expect(&filter);
+            assert!(error.starts_with(expected), "{}: {}", filter, error);
+        }
+    }
+
+    #[test]
+    fn summary_payload_keeps_only_the_summary_fields() {
+        let mut webhook = Webhook {
+            id: 3,
+            name: Some("alerts".to_string()),
+            filter: "is_error".to_string(),
+            url: "http://hooks.local/in".to_string(),
+            secret: None,
+            signed: false,
+            payload: PAYLOAD_SUMMARY.to_string(),
+            max_per_minute: 60,
+            created_at: String::new(),
+        };
+        let value = export_value(&record("qwen", 12), 41, false).unwrap();
+
+        let summary: Value = serde_json::from_str(&payload(&webhook, &value)).unwrap();
+        assert_eq!(summary["event"], EVENT_REQUEST_COMPLETED);
+        assert_eq!(summary["webhook_id"], 3);
+        assert_eq!(summary["webhook"], "alerts");
+        let fields: Vec<&String> = summary["request"].as_object().unwrap().keys().collect();
+        assert_eq!(fields, SUMMARY_FIELDS);
+        assert_eq!(summary["request"]["id"], 41);
+        assert_eq!(summary["request"]["output_tokens"], 12);
+
+        webhook.payload = PAYLOAD_RECORD.to_string();
+        let full: Value = serde_json::from_str(&payload(&webhook, &value)).unwrap();
+        assert_eq!(full["request"], value);
+        assert!(full["request"].get("prompt").is_none());
+        assert!(full["request"].get("output").is_none());
+    }
+
+    #[tokio::test]
+    async fn rate_limit_counts_deliveries_per_minute() {
+        let pool = memory_pool().await;
+        let checked = check(NewWebhook {
+            max_per_minute: 2,
+            ..new_webhook("is_error", "http://hooks.local/in")
+        })
+        .unwrap();
+        let webhook = crate::db::webhooks::insert_webhook(&pool, &checked.webhook)
+            .await
+            .unwrap();
+        let subscription = Subscription::new(webhook, checked.filter, checked.uri);
+        assert!(subscription.take_slot());
+        assert!(subscription.take_slot());
+        assert!(!subscription.take_slot());
+
+        // A new window starts once the minute is up
+        subscription.window.lock().unwrap().0 = Instant::now() - RATE_WINDOW;
+        assert!(subscription.take_slot());
+        assert!(subscription.take_slot());
+        assert!(!subscription.take_slot());
+    }
+
+    #[tokio::test]
+    async fn matching_requests_are_signed_and_delivered() {
+        let pool = memory_pool().await;
+        let (subscriber, url) = Subscriber::start(|_| StatusCode::NO_CONTENT).await;
+        let webhooks = Webhooks::start(pool.clone(), create_client()).await.unwrap();
+        let webhook = webhooks
+            .add(
+                check(NewWebhook {
+                    secret: Some("s3cret".to_string()),
+                    ..new_webhook("model ~ 'qwen*' and output_tokens > 100", &url)
+                })
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        webhooks.notify(&record("llama", 500), 1);
+        webhooks.notify(&record("qwen-7b", 50), 2);
+        webhooks.notify(&record("Qwen-14b", 500), 3);
+        let history = deliveries(&pool, webhook.id, 1).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(list_deliveries(&pool, webhook.id, 100).await.unwrap().len(), 1);
+
+        assert_eq!(history[0].request_id, 3);
+        assert_eq!(history[0].proxy_request_id.as_deref(), Some("req-Qwen-14b-500"));
+        assert_eq!(history[0].status, DELIVERY_DELIVERED);
+        assert_eq!(history[0].attempts, 1);
+        assert_eq!(history[0].http_status, Some(204));
+
+        let (headers, body) = subscriber.received().remove(0);
+        assert_eq!(headers["content-type"], "application/json");
+        let expected = format!("sha256={}", hmac_sha256("s3cret", &body));
+        assert_eq!(headers[SIGNATURE_HEADER], expected.as_str());
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["request"]["id"], 3);
+        assert_eq!(body["request"]["model"], "Qwen-14b");
+        assert!(body["request"].get("prompt").is_none());
+
+        // Removing the subscription stops deliveries
+        webhooks.remove(webhook.id).await.unwrap().unwrap();
+        webhooks.notify(&record("qwen-7b", 500), 4);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(subscriber.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_and_client_errors_are_not() {
+        let pool = memory_pool().await;
+        let (_, flaky) = Subscriber::start(|n| match n {
+            0 => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::OK,
+        })
+        .await;
+        let (refusing, rejecting) = Subscriber::start(|_| StatusCode::BAD_REQUEST).await;
+        let webhooks = Webhooks::start(pool.clone(), create_client()).await.unwrap();
+        let retried = webhooks.add(check(new_webhook("model", &flaky)).unwrap()).await.unwrap();
+        let rejected = webhooks
+            .add(check(new_webhook("model", &rejecting)).unwrap())
+            .await
+            .unwrap();
+
+        webhooks.notify(&record("qwen", 1), 7);
+        let history = deliveries(&pool, retried.id, 1).await;
+        assert_eq!(history[0].status, DELIVERY_DELIVERED);
+        assert_eq!(history[0].attempts, 2);
+        assert_eq!(history[0].http_status, Some(200));
+
+        let history = deliveries(&pool, rejected.id, 1).await;
+        assert_eq!(history[0].status, DELIVERY_FAILED);
+        assert_eq!(history[0].attempts, 1);
+        assert_eq!(history[0].http_status, Some(400));
+        assert!(history[0].error.as_deref().unwrap().starts_with("400 Bad Request"));
+        assert_eq!(refusing.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn matches_over_the_rate_are_recorded_but_not_sent() {
+        let pool = memory_pool().await;
+        let (subscriber, url) = Subscriber::start(|_| StatusCode::OK).await;
+        let webhooks = Webhooks::start(pool.clone(), create_client()).await.unwrap();
+        let webhook = webhooks
+            .add(
+                check(NewWebhook {
+                    max_per_minute: 2,
+                    ..new_webhook("model", &url)
+                })
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        for id in 1..=5 {
+            webhooks.notify(&record("qwen", id), id);
+        }
+        let history = deliveries(&pool, webhook.id, 5).await;
+        let statuses: Vec<&str> = history.iter().map(|d| d.status.as_str()).collect();
+        assert_eq!(statuses.iter().filter(|s| **s == DELIVERY_DELIVERED).count(), 2);
+        assert_eq!(statuses.iter().filter(|s| **s == DELIVERY_RATE_LIMITED).count(), 3);
+        assert!(
+            history
+                .iter()
+                .filter(|d| d.status == DELIVERY_RATE_LIMITED)
+                .all(|d| d.attempts == 0 && d.http_status.is_none())
+        );
+        assert_eq!(subscriber.received().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stored_subscriptions_are_loaded_at_start() {
+        let pool = memory_pool().await;
+        let (subscriber, url) = Subscriber::start(|_| StatusCode::OK).await;
+        let checked = check(new_webhook("output_tokens >= 10", &url)).unwrap();
+        let webhook = crate::db::webhooks::insert_webhook(&pool, &checked.webhook)
+            .await
+            .unwrap();
+
+        let webhooks = Webhooks::start(pool.clone(), create_client()).await.unwrap();
+        webhooks.notify(&record("qwen", 10), 1);
+        deliveries(&pool, webhook.id, 1).await;
+        assert_eq!(subscriber.received().len(), 1);
+    }
+}
@@ -0,0 +1,83 @@
+//! `ADMIN_PORT`: stats and admin routes on their own listener, the proxy port left with
+//! `/health` and `/v1`.
+
+mod common;
+
+use common::{Server, free_port, request};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+const CHAT: &str = r#"{"model":"m","messages":[{"role":"user","content":"hi"}]}"#;
+
+const ADMIN: (&str, &str) = ("Authorization", "Bearer secret");
+
+/// Polls `/stats/summary` on `port` until the last hour holds `expected` requests.
+fn wait_for_last_hour(port: u16, expected: i64) -> Value {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let (status, body) = request(port, "GET", "/stats/summary", &[], "");
+        assert_eq!(status, 200, "{}", body);
+        let summary: Value = serde_json::from_str(&body).unwrap();
+        if summary["last_hour"]["requests"] == expected || Instant::now() > deadline {
+            return summary;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn without_admin_port_one_listener_serves_everything() {
+    let server = Server::start(&[("ADMIN_TOKEN", "secret".to_string())]);
+    assert_eq!(server.get("/health").0, 200);
+    assert_eq!(server.get("/stats/summary").0, 200);
+    assert_eq!(request(server.port, "GET", "/admin/db", &[ADMIN], "").0, 200);
+}
+
+#[test]
+fn admin_port_splits_the_routes() {
+    let admin_port = free_port();
+    let server = Server::start(&[
+        ("ADMIN_PORT", admin_port.to_string()),
+        ("ADMIN_BIND_ADDR", "127.0.0.1".to_string()),
+        ("ADMIN_TOKEN", "secret".to_string()),
+    ]);
+    server.wait_for(admin_port);
+
+    for path in ["/stats/summary", "/admin/db", "/metrics", "/api/v1/stats/summary"] {
+        let (status, _) = request(server.port, "GET", path, &[ADMIN], "");
+        assert_eq!(status, 404, "{} on the proxy port", path);
+        let (status, _) = request(admin_port, "GET", path, &[ADMIN], "");
+        assert_eq!(status, 200, "{} on the admin port", path);
+    }
+    assert_eq!(server.get("/health").0, 200);
+
+    let (status, _) = request(server.port, "POST", "/v1/chat/completions", &[], CHAT);
+    assert_eq!(status, 502, "forwarded to the dead upstream");
+    let (status, _) = request(admin_port, "POST", "/v1/chat/completions", &[], CHAT);
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn both_listeners_share_state() {
+    let admin_port = free_port();
+    let server = Server::start(&[("ADMIN_PORT", admin_port.to_string())]);
+    server.wait_for(admin_port);
+
+    request(server.port, "POST", "/v1/chat/completions", &[], CHAT);
+    let summary = wait_for_last_hour(admin_port, 1);
+    assert_eq!(summary["last_hour"]["requests"], 1, "{}", summary);
+}
+
+#[test]
+fn shutdown_stops_both_listeners() {
+    let admin_port = free_port();
+    let server = Server::start(&[("ADMIN_PORT", admin_port.to_string())]);
+    server.wait_for(admin_port);
+    let port = server.port;
+
+    let status = server.stop();
+    assert!(status.success(), "{:?}", status);
+    for port in [port, admin_port] {
+        assert!(std::net::TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! The versioned stats API: every route under `/api/v1` answers exactly like its
+//! unprefixed alias, and `/api/v1/` lists what the listener mounts.
+
+mod common;
+
+use common::{Server, Upstream, completion_body, eventually, free_port, request, respond_json};
+use serde_json::Value;
+
+const ADMIN: (&str, &str) = ("Authorization", "Bearer secret");
+const VIEWER: (&str, &str) = ("Authorization", "Bearer wall");
+const CHAT: &str = r#"{"model":"m","messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+/// A server with one stored request, so the stats routes have something to report.
+fn serve(extra_env: &[(&str, String)]) -> (Server, Upstream) {
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("Once upon a time", 5, 4))
+    });
+    let mut env = vec![
+        ("LM_STUDIO_URL", upstream.url()),
+        ("ADMIN_TOKEN", "secret".to_string()),
+        ("VIEWER_TOKENS", "wall".to_string()),
+    ];
+    env.extend(extra_env.iter().cloned());
+    let server = Server::start(&env);
+    (server, upstream)
+}
+
+fn index(port: u16, auth: (&str, &str)) -> Value {
+    let (status, body) = request(port, "GET", "/api/v1/", &[auth], "");
+    assert_eq!(status, 200, "{}", body);
+    serde_json::from_str(&body).unwrap()
+}
+
+/// `(alias, access)` of every route in the index.
+fn routes(index: &Value) -> Vec<(String, String)> {
+    index["routes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|route| {
+            let alias = route["alias"].as_str().unwrap();
+            assert_eq!(route["path"], format!("/api/v1{}", alias));
+            (alias.to_string(), route["access"].as_str().unwrap().to_string())
+        })
+        .collect()
+}
+
+/// The body with the time it was made left out, which moves between two calls.
+fn without_clock(body: &str) -> Value {
+    let mut body: Value = serde_json::from_str(body).unwrap_or(Value::String(body.to_string()));
+    if let Some(fields) = body.as_object_mut() {
+        fields.remove("as_of");
+    }
+    body
+}
+
+#[test]
+fn index_lists_every_route_with_the_crate_version() {
+    let (server, _upstream) = serve(&[]);
+    let listed = index(server.port, ADMIN);
+    assert_eq!(listed["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(listed["prefix"], "/api/v1");
+
+    let routes = routes(&listed);
+    assert!(routes.contains(&("/stats/summary".to_string(), "viewer".to_string())));
+    assert!(routes.contains(&("/stats/recent".to_string(), "full".to_string())));
+    assert!(routes.contains(&("/stats/badge".to_string(), "public".to_string())));
+    assert!(routes.iter().any(|(_, access)| access == "admin"));
+
+    // With or without the trailing slash, and for viewer tokens too
+    let (status, body) = request(server.port, "GET", "/api/v1", &[ADMIN], "");
+    assert_eq!(status, 200);
+    assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), listed);
+    assert_eq!(index(server.port, VIEWER), listed);
+}
+
+#[test]
+fn both_path_forms_return_identical_bodies() {
+    let (server, _upstream) = serve(&[]);
+    let (status, _) = request(server.port, "POST", "/v1/chat/completions", &[], CHAT);
+    assert_eq!(status, 200);
+    let stored = eventually("the request to be stored", || server.recent().into_iter().next());
+    let id = stored["proxy_request_id"].as_str().unwrap().to_string();
+
+    let mut compared = 0;
+    for (alias, access) in routes(&index(server.port, ADMIN)) {
+        if access == "admin" || alias.contains('{') {
+            continue;
+        }
+        let (versioned_status, versioned) =
+            request(server.port, "GET", &format!("/api/v1{}", alias), &[ADMIN], "");
+        let (alias_status, unversioned) = request(server.port, "GET", &alias, &[ADMIN], "");
+        assert_eq!(versioned_status, alias_status, "{}", alias);
+        assert_eq!(without_clock(&versioned), without_clock(&unversioned), "{}", alias);
+        compared += 1;
+    }
+    assert!(compared > 20, "only {} routes compared", compared);
+
+    // Path parameters, query options and errors come out the same as well
+    for path in [
+        format!("/stats/request/{}", id),
+        "/stats/request/missing".to_string(),
+        "/stats/summary?case=camel&pretty=true".to_string(),
+        "/stats/recent?limit=1&fields=model,output_tokens".to_string(),
+        "/stats/histogram".to_string(),
+        "/stats/utilization?since=nonsense".to_string(),
+    ] {
+        let versioned = request(server.port, "GET", &format!("/api/v1{}", path), &[ADMIN], "");
+        assert_eq!(versioned, request(server.port, "GET", &path, &[ADMIN], ""), "{}", path);
+    }
+}
+
+#[test]
+fn aliases_keep_the_access_of_their_versioned_route() {
+    let (server, _upstream) = serve(&[]);
+    for path in ["/stats/summary", "/stats/recent", "/admin/db"] {
+        let versioned = request(server.port, "GET", &format!("/api/v1{}", path), &[VIEWER], "");
+        let alias = request(server.port, "GET", path, &[VIEWER], "");
+        assert_eq!(versioned.0, alias.0, "{}", path);
+    }
+    assert_eq!(request(server.port, "GET", "/api/v1/stats/recent", &[VIEWER], "").0, 403);
+    assert_eq!(request(server.port, "GET", "/api/v1/stats/summary", &[VIEWER], "").0, 200);
+}
+
+#[test]
+fn proxy_port_index_lists_only_viewer_routes_under_admin_port() {
+    let admin_port = free_port();
+    let (server, _upstream) = serve(&[
+        ("ADMIN_PORT", admin_port.to_string()),
+        ("ADMIN_BIND_ADDR", "127.0.0.1".to_string()),
+    ]);
+    server.wait_for(admin_port);
+
+    let everything = routes(&index(admin_port, ADMIN));
+    let viewer = routes(&index(server.port, VIEWER));
+    assert!(!viewer.is_empty());
+    assert!(viewer.iter().all(|(_, access)| access == "viewer" || access == "public"));
+    let readable: Vec<_> = everything
+        .iter()
+        .filter(|(_, access)| access == "viewer" || access == "public")
+        .cloned()
+        .collect();
+    assert_eq!(viewer, readable);
+
+    // The proxy port still asks for a token, on the index as on the routes
+    assert_eq!(request(server.port, "GET", "/api/v1/", &[], "").0, 401);
+}
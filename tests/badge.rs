@@ -0,0 +1,124 @@
+//! `/stats/badge` in the shields.io endpoint format: values from the rollups, colors
+//! from `BADGE_COLORS`, and `PUBLIC_BADGE` opening just the badge to callers without a
+//! token.
+
+mod common;
+
+use chrono::{Datelike, Duration, Utc};
+use common::{
+    Server, TempDir, Upstream, completion_body, empty_database, free_port, request,
+    respond_json, seed_request,
+};
+use serde_json::{Value, json};
+
+const VIEWER: (&str, &str) = ("Authorization", "Bearer wall");
+
+fn badge(server: &Server, query: &str) -> (u16, Value) {
+    let (status, body) = server.get(&format!("/stats/badge?{}", query));
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn badges_read_this_month_and_today_from_the_rollups() {
+    let dir = TempDir::new();
+    let (_, pool) = empty_database(&dir).await;
+    let now = Utc::now();
+    let last_month = now.with_day(1).unwrap() - Duration::days(1);
+    for output_tokens in [100_000, 100_000, 200_000] {
+        seed_request(&pool, "m", now, 4_000_000, output_tokens).await;
+    }
+    seed_request(&pool, "m", last_month, 50_000_000, 0).await;
+    pool.close().await;
+
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("Hi", 5_000, 1_000))
+    });
+    let server = Server::start_in(
+        dir,
+        &[
+            ("LM_STUDIO_URL", upstream.url()),
+            ("MODEL_PRICING", "m:1000:2000".to_string()),
+            (
+                "BADGE_COLORS",
+                "tokens_month=1000000:yellow,tokens_month=10000000:red,requests_today=100:green"
+                    .to_string(),
+            ),
+        ],
+    );
+    let chat = r#"{"model":"m","messages":[{"role":"user","content":"Hi"}]}"#;
+    let (status, _) = request(server.port, "POST", "/v1/chat/completions", &[], chat);
+    assert_eq!(status, 200);
+
+    // Last month's 50M tokens are left out
+    let (status, tokens) = badge(&server, "metric=tokens_month");
+    assert_eq!(status, 200);
+    assert_eq!(
+        tokens,
+        json!({
+            "schemaVersion": 1,
+            "label": "tokens this month",
+            "message": "12.4M",
+            "color": "red",
+        })
+    );
+    let (_, requests) = badge(&server, "metric=requests_today&style=shieldsio");
+    assert_eq!(requests["label"], "requests today");
+    assert_eq!(requests["message"], "4");
+    assert_eq!(requests["color"], "blue");
+    // Only the proxied request was priced: 5k input at $1000/M and 1k output at $2000/M
+    let (_, cost) = badge(&server, "metric=cost_month");
+    assert_eq!(cost["label"], "cost this month");
+    assert_eq!(cost["message"], "$7.00");
+
+    for query in ["metric=tokens_year", "", "metric=tokens_month&style=flat"] {
+        let (status, error) = badge(&server, query);
+        assert_eq!(status, 400, "{}: {}", query, error);
+    }
+}
+
+#[test]
+fn public_badge_needs_no_token_while_other_stats_stay_locked() {
+    let admin_port = free_port();
+    let server = Server::start(&[
+        ("ADMIN_PORT", admin_port.to_string()),
+        ("ADMIN_BIND_ADDR", "127.0.0.1".to_string()),
+        ("PUBLIC_BADGE", "true".to_string()),
+    ]);
+
+    let (status, badge) = badge(&server, "metric=requests_today");
+    assert_eq!(status, 200);
+    assert_eq!(badge["message"], "0");
+    for path in ["/stats/summary", "/stats/daily", "/stats/recent"] {
+        assert_eq!(server.get(path).0, 401, "{}", path);
+    }
+    let admin = request(admin_port, "GET", "/stats/badge?metric=requests_today", &[], "");
+    assert_eq!(admin.0, 200);
+}
+
+#[test]
+fn without_public_badge_the_badge_needs_a_viewer_token() {
+    let admin_port = free_port();
+    let server = Server::start(&[
+        ("ADMIN_PORT", admin_port.to_string()),
+        ("ADMIN_BIND_ADDR", "127.0.0.1".to_string()),
+        ("VIEWER_TOKENS", "wall".to_string()),
+    ]);
+
+    let path = "/stats/badge?metric=tokens_month";
+    assert_eq!(server.get(path).0, 401);
+    assert_eq!(request(server.port, "GET", path, &[VIEWER], "").0, 200);
+}
+
+#[test]
+fn invalid_badge_colors_stop_startup() {
+    let dir = TempDir::new();
+    let output = common::proxy(&dir)
+        .env("PORT", free_port().to_string())
+        .env("DATABASE_URL", format!("sqlite:{}", dir.join("metrics.db").display()))
+        .env("BADGE_COLORS", "tokens_month=lots:red")
+        .output()
+        .expect("run server");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid BADGE_COLORS entry"), "{}", stderr);
+}
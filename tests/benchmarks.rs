@@ -0,0 +1,240 @@
+//! `/admin/benchmark` end to end: stored prompts replayed against each model, the
+//! report, cancellation, the cost budget and resuming after a restart.
+
+mod common;
+
+use common::{
+    Server, TempDir, Upstream, completion_body, delta_event, empty_database, end_chunks,
+    eventually, final_events, request, respond_json, send_chunk, start_event_stream,
+};
+use serde_json::{Value, json};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const ADMIN: (&str, &str) = ("Authorization", "Bearer secret");
+const JSON: (&str, &str) = ("Content-Type", "application/json");
+
+/// Streams every run, failing those for model `broken`, after `delay`; records the
+/// bodies it was sent.
+fn upstream(delay: Duration) -> (Upstream, Arc<Mutex<Vec<Value>>>) {
+    let bodies = Arc::new(Mutex::new(Vec::new()));
+    let seen = bodies.clone();
+    let upstream = Upstream::start(move |received, stream| {
+        let body: Value = serde_json::from_str(&received.body).unwrap_or(Value::Null);
+        seen.lock().unwrap().push(body.clone());
+        std::thread::sleep(delay);
+        if body["model"] == "broken" {
+            return respond_json(stream, 500, r#"{"error":"model crashed"}"#);
+        }
+        if body["stream"] != true {
+            return respond_json(stream, 200, &completion_body("The end.", 10, 3));
+        }
+        start_event_stream(stream)?;
+        send_chunk(stream, &delta_event("Once upon a time"))?;
+        send_chunk(stream, &final_events(5, 4))?;
+        end_chunks(stream)
+    });
+    (upstream, bodies)
+}
+
+fn serve(upstream: &Upstream, env: &[(&str, String)]) -> Server {
+    let mut env = env.to_vec();
+    env.push(("LM_STUDIO_URL", upstream.url()));
+    env.push(("ADMIN_TOKEN", "secret".to_string()));
+    Server::start(&env)
+}
+
+/// Logs `count` distinct chat prompts for benchmarks to sample.
+fn seed_prompts(server: &Server, count: usize) {
+    for i in 0..count {
+        let body = json!({
+            "model": "m",
+            "messages": [{ "role": "user", "content": format!("Tell me story number {}", i) }]
+        });
+        let (status, body) =
+            request(server.port, "POST", "/v1/chat/completions", &[], &body.to_string());
+        assert_eq!(status, 200, "{}", body);
+    }
+    eventually("the prompts to be stored", || {
+        (server.recent().len() == count).then_some(())
+    });
+}
+
+fn admin(server: &Server, method: &str, path: &str, body: &Value) -> (u16, Value) {
+    let (status, body) = request(server.port, method, path, &[ADMIN, JSON], &body.to_string());
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+fn start(server: &Server, spec: Value) -> String {
+    let (status, report) = admin(server, "POST", "/admin/benchmark", &spec);
+    assert_eq!(status, 200, "{}", report);
+    report["id"].as_str().unwrap().to_string()
+}
+
+/// Waits for the benchmark to end and returns its report.
+fn finished(server: &Server, id: &str) -> Value {
+    eventually("the benchmark to finish", || {
+        let (_, report) = admin(server, "GET", &format!("/admin/benchmark/{}", id), &Value::Null);
+        report["finished_at"].is_string().then_some(report)
+    })
+}
+
+fn summary_requests(server: &Server, query: &str) -> Value {
+    server.get_json(&format!("/stats/summary{}", query))["total_requests"].clone()
+}
+
+#[test]
+fn every_prompt_runs_against_every_model_and_stays_out_of_user_stats() {
+    let (upstream, bodies) = upstream(Duration::ZERO);
+    let server = serve(&upstream, &[]);
+    seed_prompts(&server, 2);
+
+    let id = start(&server, json!({ "models": ["a", "broken"], "sample": 10 }));
+    let report = finished(&server, &id);
+
+    assert_eq!(report["status"], "completed");
+    assert_eq!(
+        report["progress"],
+        json!({ "total": 4, "pending": 0, "succeeded": 2, "failed": 2, "cancelled": 0 })
+    );
+    let a = &report["models"][0];
+    assert_eq!((&a["model"], &a["runs"], &a["errors"]), (&json!("a"), &json!(2), &json!(0)));
+    assert_eq!(a["avg_output_tokens"], 4.0);
+    assert_eq!(a["output_tokens"], 8);
+    assert!(a["avg_ttft_ms"].is_number(), "{}", a);
+    assert!(a["avg_tokens_per_second"].is_number(), "{}", a);
+    let broken = &report["models"][1];
+    assert_eq!((&broken["errors"], &broken["error_rate"]), (&json!(2), &json!(1.0)));
+    assert_eq!(broken["avg_ttft_ms"], Value::Null);
+
+    // Each stored prompt went to each model, streamed
+    let runs: Vec<_> = bodies.lock().unwrap()[2..].to_vec();
+    assert_eq!(runs.len(), 4);
+    for run in &runs {
+        assert_eq!(run["stream"], true, "{}", run);
+        assert!(run["messages"][0]["content"].as_str().unwrap().starts_with("Tell me story"));
+    }
+    assert_eq!(runs.iter().filter(|run| run["model"] == "a").count(), 2);
+
+    assert_eq!(summary_requests(&server, ""), 2);
+    assert_eq!(summary_requests(&server, "?include_benchmarks=true"), 6);
+    // Runs are never sampled as prompts for a later benchmark
+    let id = start(&server, json!({ "models": ["a"], "sample": 10 }));
+    assert_eq!(finished(&server, &id)["progress"]["total"], 2);
+}
+
+#[test]
+fn invalid_specs_are_rejected() {
+    let (upstream, _) = upstream(Duration::ZERO);
+    let server = serve(&upstream, &[]);
+    for spec in [
+        json!({ "models": [] }),
+        json!({ "models": ["a"], "sample": 0 }),
+        json!({ "models": ["a"], "max_cost": 0 }),
+        // Nothing stored to replay yet
+        json!({ "models": ["a"] }),
+    ] {
+        let (status, body) = admin(&server, "POST", "/admin/benchmark", &spec);
+        assert_eq!(status, 400, "{}: {}", spec, body);
+    }
+    let spec = json!({ "models": ["a"] }).to_string();
+    let (status, _) = request(server.port, "POST", "/admin/benchmark", &[JSON], &spec);
+    assert_eq!(status, 401);
+    let (status, _) = admin(&server, "GET", "/admin/benchmark/missing", &Value::Null);
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn cancelling_stops_new_runs() {
+    let (upstream, _) = upstream(Duration::from_millis(300));
+    let server = serve(&upstream, &[]);
+    seed_prompts(&server, 5);
+
+    let id = start(&server, json!({ "models": ["a"], "concurrency": 1 }));
+    let cancel = format!("/admin/benchmark/{}/cancel", id);
+    let (status, body) = admin(&server, "POST", &cancel, &Value::Null);
+    assert_eq!(status, 200, "{}", body);
+    let report = finished(&server, &id);
+
+    assert_eq!(report["status"], "cancelled");
+    let progress = &report["progress"];
+    assert_eq!(progress["pending"], 0);
+    assert!(progress["cancelled"].as_i64().unwrap() >= 3, "{}", progress);
+    let (status, _) = admin(&server, "POST", &cancel, &Value::Null);
+    assert_eq!(status, 409);
+}
+
+#[test]
+fn budget_stops_the_benchmark_once_spent() {
+    let (upstream, _) = upstream(Duration::ZERO);
+    // Each run costs 5 * $1 + 4 * $1
+    let server = serve(&upstream, &[("MODEL_PRICING", "a:1000000:1000000".to_string())]);
+    seed_prompts(&server, 3);
+
+    let id = start(
+        &server,
+        json!({ "models": ["a"], "concurrency": 1, "max_cost": 10.0 }),
+    );
+    let report = finished(&server, &id);
+
+    assert_eq!(report["status"], "budget_exceeded");
+    assert_eq!(report["progress"]["succeeded"], 2);
+    assert_eq!(report["progress"]["cancelled"], 1);
+    assert_eq!(report["models"][0]["estimated_cost"], 18.0);
+}
+
+#[tokio::test]
+async fn unfinished_benchmarks_resume_at_startup() {
+    // A benchmark cut short by a restart: one run done, two still pending
+    let dir = TempDir::new();
+    let (_, pool) = empty_database(&dir).await;
+    let now = chrono::Utc::now().to_rfc3339();
+    sqlx::query(
+        "INSERT INTO benchmarks (id, status, spec, created_at, updated_at)
+         VALUES ('b1', 'running', '{\"models\":[\"a\"],\"concurrency\":1}', ?1, ?1)",
+    )
+    .bind(&now)
+    .execute(&pool)
+    .await
+    .unwrap();
+    for (run, status) in [(1, "succeeded"), (2, "pending"), (3, "pending")] {
+        let body = json!({
+            "model": "a",
+            "stream": true,
+            "messages": [{ "role": "user", "content": format!("Story {}", run) }]
+        });
+        sqlx::query(
+            "INSERT INTO benchmark_runs (benchmark_id, run, source_request_id, model, body, status)
+             VALUES ('b1', ?, ?, 'a', ?, ?)",
+        )
+        .bind(run)
+        .bind(run)
+        .bind(body.to_string())
+        .bind(status)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+    pool.close().await;
+
+    let (upstream, bodies) = upstream(Duration::ZERO);
+    let server = Server::start_in(
+        dir,
+        &[
+            ("LM_STUDIO_URL", upstream.url()),
+            ("ADMIN_TOKEN", "secret".to_string()),
+        ],
+    );
+    let report = finished(&server, "b1");
+
+    assert_eq!(report["status"], "completed");
+    assert_eq!(report["progress"]["succeeded"], 3);
+    // Only the pending runs were sent
+    let sent: Vec<_> = bodies
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|body| body["messages"][0]["content"].clone())
+        .collect();
+    assert_eq!(sent, [json!("Story 2"), json!("Story 3")]);
+}
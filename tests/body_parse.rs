@@ -0,0 +1,90 @@
+//! Request bodies that aren't quite JSON: the first recoverable object is forwarded and
+//! decides the model and streaming, and `body_parse_warning` records what was looked past.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{
+    Server, Upstream, completion_body, delta_event, end_chunks, eventually, final_events,
+    request, respond_json, send_chunk, start_event_stream,
+};
+use serde_json::Value;
+
+/// Streams when the forwarded body asks for it, keeping every body it received.
+fn upstream(bodies: Arc<Mutex<Vec<String>>>) -> Upstream {
+    Upstream::start(move |received, stream| {
+        bodies.lock().unwrap().push(received.body.clone());
+        let asked: Option<Value> = serde_json::from_str(&received.body).ok();
+        if asked.is_some_and(|body| body["stream"] == true) {
+            start_event_stream(stream)?;
+            send_chunk(stream, &delta_event("Hello"))?;
+            send_chunk(stream, &final_events(7, 1))?;
+            return end_chunks(stream);
+        }
+        respond_json(stream, 200, &completion_body("Hello", 7, 1))
+    })
+}
+
+#[test]
+fn malformed_bodies_are_forwarded_and_recorded_as_their_first_object() {
+    let bodies = Arc::new(Mutex::new(Vec::new()));
+    let upstream = upstream(bodies.clone());
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+
+    let messages = r#""messages":[{"role":"user","content":"Hi, there"}]"#;
+    let cases = [
+        (
+            format!(r#"{{"model":"a","stream":true,{messages}}}{{"model":"b"}}"#),
+            format!(r#"{{"model":"a","stream":true,{messages}}}"#),
+            Some("2 JSON values in body, only the first was used"),
+        ),
+        (
+            r#"{"model":"a","stream":true,"messages":[{"role":"user","content":"Hi, there"},],}"#
+                .to_string(),
+            format!(r#"{{"model":"a","stream":true,{messages}}}"#),
+            Some("trailing commas removed"),
+        ),
+        (
+            format!("\u{feff}{{\"model\":\"a\",{messages}}}"),
+            format!(r#"{{"model":"a",{messages}}}"#),
+            Some("byte order mark removed"),
+        ),
+        (
+            format!(r#"{{"model":"a",{messages}}}"#),
+            format!(r#"{{"model":"a",{messages}}}"#),
+            None,
+        ),
+        // Unrecoverable: forwarded untouched, the warning carrying serde's error
+        ("{not json".to_string(), "{not json".to_string(), Some("not valid JSON: ")),
+    ];
+
+    for (sent, forwarded, warning) in &cases {
+        let count = bodies.lock().unwrap().len();
+        let (status, answer) = request(server.port, "POST", "/v1/chat/completions", &[], sent);
+        assert_eq!(status, 200, "{}", sent);
+        assert!(answer.contains("Hello"), "{}", answer);
+        assert_eq!(&bodies.lock().unwrap()[count], forwarded, "forwarded for {}", sent);
+
+        let rows = eventually("the request to be stored", || {
+            let rows = server.recent();
+            (rows.len() == count + 1).then_some(rows)
+        });
+        let id = rows[0]["proxy_request_id"].as_str().unwrap();
+        let stored = server.get_json(&format!("/stats/request/{}", id));
+        let streamed = forwarded.contains(r#""stream":true"#);
+        assert_eq!(stored["was_streamed"], streamed, "{}", stored);
+        let recovered = !sent.starts_with("{not");
+        assert_eq!(stored["model"], if recovered { "a" } else { "unknown" }, "{}", stored);
+        if recovered {
+            assert_eq!(stored["output_tokens"], 1, "{}", stored);
+        }
+        match warning {
+            Some(warning) => {
+                let recorded = stored["body_parse_warning"].as_str().unwrap();
+                assert!(recorded.starts_with(warning), "{}", recorded);
+            }
+            None => assert_eq!(stored["body_parse_warning"], Value::Null),
+        }
+    }
+}
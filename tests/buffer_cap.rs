@@ -0,0 +1,156 @@
+//! `MAX_BUFFERED_BYTES`: concurrent large-bodied requests against a small cap, refused
+//! with 503 while the buffer tally in `/stats/self` and `/metrics` stays exact.
+
+mod common;
+
+use common::{Server, Upstream, completion_body, eventually, request, respond_json};
+use serde_json::Value;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+const CLIENTS: usize = 4;
+const PROMPT_BYTES: usize = 60_000;
+
+fn large_chat() -> String {
+    format!(
+        r#"{{"model":"m","messages":[{{"role":"user","content":"{}"}}]}}"#,
+        "x".repeat(PROMPT_BYTES)
+    )
+}
+
+fn buffers(server: &Server) -> Value {
+    server.get_json("/stats/self")["buffers"].clone()
+}
+
+/// An upstream slow enough for every client to arrive while the first is in flight,
+/// counting what reaches it.
+fn slow_upstream(forwarded: Arc<AtomicUsize>) -> Upstream {
+    Upstream::start(move |_, stream| {
+        forwarded.fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(1500));
+        respond_json(stream, 200, &completion_body("done", 5, 1))
+    })
+}
+
+#[test]
+fn concurrent_bodies_past_the_cap_are_refused_and_the_tally_settles() {
+    let forwarded = Arc::new(AtomicUsize::new(0));
+    let upstream = slow_upstream(forwarded.clone());
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("MAX_BUFFERED_BYTES", "100000".to_string()),
+    ]);
+
+    let port = server.port;
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            std::thread::spawn(move || {
+                request(port, "POST", "/v1/chat/completions", &[], &large_chat())
+            })
+        })
+        .collect();
+    let answers: Vec<(u16, String)> = clients.into_iter().map(|c| c.join().unwrap()).collect();
+
+    // One body fits under the cap; the rest arrive while it is held and are refused
+    let served: Vec<_> = answers.iter().filter(|(status, _)| *status == 200).collect();
+    assert_eq!(served.len(), 1, "{:?}", answers.iter().map(|a| a.0).collect::<Vec<_>>());
+    assert!(served[0].1.contains("done"));
+    for (_, body) in answers.iter().filter(|(status, _)| *status != 200) {
+        assert!(body.contains("MAX_BUFFERED_BYTES"), "{}", body);
+    }
+    assert_eq!(answers.iter().filter(|(status, _)| *status == 503).count(), CLIENTS - 1);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+
+    // Everything held is given back once the served request is stored
+    eventually("buffers to be released", || {
+        (buffers(&server)["current_bytes"] == 0).then_some(())
+    });
+    let tally = buffers(&server);
+    assert_eq!(tally["cap_bytes"], 100_000);
+    assert_eq!(tally["requests_rejected"], CLIENTS as u64 - 1);
+    assert_eq!(tally["growth_refused"], 0);
+    let peak = tally["peak_bytes"].as_u64().unwrap();
+    assert!(peak >= PROMPT_BYTES as u64, "{}", tally);
+    // The refused requests are stored as failures reading the body, without a prompt
+    let rows = eventually("every request to be stored", || {
+        let rows = server.recent();
+        (rows.len() == CLIENTS).then_some(rows)
+    });
+    let refused: Vec<Value> = rows
+        .iter()
+        .map(|row| {
+            let id = row["proxy_request_id"].as_str().unwrap();
+            server.get_json(&format!("/stats/request/{}", id))
+        })
+        .filter(|stored| stored["failure_stage"] == "body_read")
+        .collect();
+    assert_eq!(refused.len(), CLIENTS - 1);
+    assert!(refused.iter().all(|stored| stored["prompt"] == ""), "{:?}", refused);
+
+    let (status, metrics) = server.get("/metrics");
+    assert_eq!(status, 200);
+    assert!(metrics.contains("\nlms_proxy_buffered_bytes 0\n"), "{}", metrics);
+    assert!(metrics.contains(&format!("lms_proxy_buffered_bytes_peak {}\n", peak)));
+    assert!(metrics.contains(&format!(
+        "lms_proxy_buffer_rejected_requests_total {}\n",
+        CLIENTS - 1
+    )));
+    assert!(metrics.contains("lms_proxy_buffered_bytes_cap 100000\n"));
+
+    // With the tally back at zero the next request fits again
+    let (status, _) = request(port, "POST", "/v1/chat/completions", &[], &large_chat());
+    assert_eq!(status, 200);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn body_larger_than_the_cap_is_never_forwarded() {
+    let forwarded = Arc::new(AtomicUsize::new(0));
+    let upstream = slow_upstream(forwarded.clone());
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("MAX_BUFFERED_BYTES", "1000".to_string()),
+    ]);
+
+    let (status, _) = request(server.port, "POST", "/v1/chat/completions", &[], &large_chat());
+    assert_eq!(status, 503);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 0);
+
+    let tally = buffers(&server);
+    assert_eq!(tally["current_bytes"], 0);
+    assert_eq!(tally["peak_bytes"], 0);
+    assert_eq!(tally["requests_rejected"], 1);
+    let stored = eventually("the refusal to be stored", || server.recent().into_iter().next());
+    assert_eq!(stored["model"], "unknown");
+}
+
+#[test]
+fn without_a_cap_large_bodies_are_counted_but_not_refused() {
+    let forwarded = Arc::new(AtomicUsize::new(0));
+    let upstream = slow_upstream(forwarded.clone());
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+
+    let port = server.port;
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            std::thread::spawn(move || {
+                request(port, "POST", "/v1/chat/completions", &[], &large_chat()).0
+            })
+        })
+        .collect();
+    for client in clients {
+        assert_eq!(client.join().unwrap(), 200);
+    }
+
+    eventually("buffers to be released", || {
+        (buffers(&server)["current_bytes"] == 0).then_some(())
+    });
+    let tally = buffers(&server);
+    assert_eq!(tally["cap_bytes"], Value::Null);
+    assert_eq!(tally["requests_rejected"], 0);
+    // The clients overlapped upstream, so their bodies were held at once
+    assert!(tally["peak_bytes"].as_u64().unwrap() >= (PROMPT_BYTES * CLIENTS) as u64);
+    let (_, metrics) = server.get("/metrics");
+    assert!(!metrics.contains("lms_proxy_buffered_bytes_cap"), "{}", metrics);
+}
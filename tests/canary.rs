@@ -0,0 +1,102 @@
+//! Weighted canary routing through the running proxy: sticky sessions, weights changed
+//! at runtime and recorded, and `/stats/canary` comparing the upstreams.
+
+mod common;
+
+use common::{Server, Upstream, completion_body, eventually, request, respond_json};
+use serde_json::{Value, json};
+use sqlx::Connection;
+use sqlx::sqlite::SqliteConnection;
+
+const ADMIN: (&str, &str) = ("Authorization", "Bearer secret");
+const CHAT: &str = r#"{"model":"m","messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+/// An upstream that names itself in its answers.
+fn named(name: &'static str) -> Upstream {
+    Upstream::start(move |_, stream| respond_json(stream, 200, &completion_body(name, 5, 1)))
+}
+
+/// Which upstream answered a request in `session`.
+fn answered_by(server: &Server, session: &str) -> String {
+    let (status, body) = request(
+        server.port,
+        "POST",
+        "/v1/chat/completions",
+        &[("X-Session-Id", session)],
+        CHAT,
+    );
+    assert_eq!(status, 200, "{}", body);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    body["choices"][0]["message"]["content"].as_str().unwrap().to_string()
+}
+
+fn set_weights(server: &Server, weights: Value) -> (u16, Value) {
+    let body = json!({ "weights": weights }).to_string();
+    let headers = [ADMIN, ("Content-Type", "application/json")];
+    let (status, body) =
+        request(server.port, "POST", "/admin/routing/weights", &headers, &body);
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn weights_shift_sessions_without_a_restart() {
+    let (primary, canary) = (named("primary"), named("new"));
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", primary.url()),
+        ("CANARY_UPSTREAMS", format!("new={}", canary.url())),
+        ("CANARY_WEIGHTS", "new=50".to_string()),
+        ("ADMIN_TOKEN", "secret".to_string()),
+    ]);
+
+    // Each session sticks to one upstream, and both get some
+    let sessions: Vec<String> = (0..20).map(|i| format!("conversation-{}", i)).collect();
+    let first: Vec<String> = sessions.iter().map(|s| answered_by(&server, s)).collect();
+    for (session, upstream) in sessions.iter().zip(&first) {
+        for _ in 0..3 {
+            assert_eq!(&answered_by(&server, session), upstream, "{}", session);
+        }
+    }
+    assert!(first.iter().any(|u| u == "new") && first.iter().any(|u| u == "primary"));
+
+    let (status, change) = set_weights(&server, json!({ "new": 100 }));
+    assert_eq!(status, 200, "{}", change);
+    assert_eq!(change["previous"], json!({ "new": 50.0, "primary": 50.0 }));
+    assert_eq!(change["weights"], json!({ "new": 100.0, "primary": 0.0 }));
+    assert!(sessions.iter().all(|s| answered_by(&server, s) == "new"));
+
+    let (status, _) = set_weights(&server, json!({ "missing": 10 }));
+    assert_eq!(status, 400);
+    let (status, _) = set_weights(&server, json!({ "new": 101 }));
+    assert_eq!(status, 400);
+
+    // The change is in the audit trail
+    let url = format!("sqlite:{}?mode=ro", server.dir.join("metrics.db").display());
+    let mut conn = SqliteConnection::connect(&url).await.unwrap();
+    let (id, detail): (i64, String) =
+        sqlx::query_as("SELECT id, detail FROM proxy_events WHERE kind = 'routing_weights'")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+    assert_eq!(change["event_id"], id);
+    let detail: Value = serde_json::from_str(&detail).unwrap();
+    assert_eq!(detail["weights"]["new"], 100.0);
+    conn.close().await.unwrap();
+
+    // 40 requests before the change went to either upstream, 100 after all to the canary
+    let report = eventually("every request to be stored", || {
+        let report = server.get_json("/stats/canary");
+        let total: i64 = report["upstreams"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|u| u["requests"].as_i64().unwrap())
+            .sum();
+        (total == 100).then_some(report)
+    });
+    assert_eq!(report["weights"], change["weights"]);
+    assert_eq!(report["upstreams"][0]["upstream"], "primary");
+    assert_eq!(report["upstreams"][1]["upstream"], "new");
+    let primary_requests = 4 * first.iter().filter(|u| *u == "primary").count();
+    assert_eq!(report["upstreams"][0]["requests"], primary_requests);
+    assert_eq!(report["verdicts"][0]["upstream"], "new");
+}
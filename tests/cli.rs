@@ -0,0 +1,123 @@
+//! The offline subcommands, run as a user would against a seeded database file.
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{TempDir, empty_database, proxy, seed_request};
+use serde_json::Value;
+
+#[tokio::test]
+async fn stats_reports_seeded_totals() {
+    let dir = TempDir::new();
+    let (path, pool) = empty_database(&dir).await;
+    seed_request(&pool, "qwen", Utc::now(), 100, 20).await;
+    seed_request(&pool, "qwen", Utc::now(), 50, 10).await;
+    seed_request(&pool, "llama", Utc::now(), 7, 3).await;
+    pool.close().await;
+
+    let output = proxy(&dir).args(["stats", "--json", "--db"]).arg(&path).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["summary"]["total_requests"], 3);
+    assert_eq!(report["summary"]["total_input_tokens"], 157);
+    assert_eq!(report["summary"]["total_output_tokens"], 33);
+    let models = report["models"].as_array().unwrap();
+    assert_eq!(models[0]["model"], "qwen");
+    assert_eq!(models[0]["requests"], 2);
+    assert_eq!(models[1]["model"], "llama");
+
+    let output = proxy(&dir).args(["stats", "--db"]).arg(&path).output().unwrap();
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(text.lines().any(|line| line.trim() == "Total requests        3"), "{}", text);
+    assert!(text.lines().any(|line| line.starts_with("qwen ")), "{}", text);
+}
+
+#[tokio::test]
+async fn empty_database_exits_with_the_empty_code() {
+    let dir = TempDir::new();
+    let (path, _pool) = empty_database(&dir).await;
+    for args in [&["stats"][..], &["export"], &["prune", "--older-than", "1d"]] {
+        let output = proxy(&dir).args(args).arg("--db").arg(&path).output().unwrap();
+        assert_eq!(output.status.code(), Some(3), "{:?} {:?}", args, output);
+    }
+}
+
+#[tokio::test]
+async fn missing_database_is_an_error() {
+    let dir = TempDir::new();
+    let output = proxy(&dir).args(["stats", "--db", "nowhere.db"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Database file not found: nowhere.db"), "{}", stderr);
+    assert!(!dir.join("nowhere.db").exists());
+}
+
+#[tokio::test]
+async fn export_writes_one_csv_line_per_request() {
+    let dir = TempDir::new();
+    let (path, pool) = empty_database(&dir).await;
+    seed_request(&pool, "qwen", Utc::now(), 1, 1).await;
+    seed_request(&pool, "llama", Utc::now(), 1, 1).await;
+    pool.close().await;
+
+    let output = proxy(&dir).args(["export", "--db"]).arg(&path).output().unwrap();
+    assert!(output.status.success());
+    let csv = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", csv);
+    assert!(lines[0].split(',').any(|field| field == "model"), "{}", lines[0]);
+    assert!(lines[1..].iter().any(|line| line.contains("qwen")));
+    assert!(lines[1..].iter().any(|line| line.contains("llama")));
+
+    let out = dir.join("export.csv");
+    let output = proxy(&dir)
+        .args(["export", "--db"])
+        .arg(&path)
+        .arg("--out")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Exported 2 requests"));
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), csv);
+}
+
+#[tokio::test]
+async fn prune_dry_run_matches_what_prune_deletes() {
+    let dir = TempDir::new();
+    let (path, pool) = empty_database(&dir).await;
+    seed_request(&pool, "old", Utc::now() - Duration::days(40), 1, 1).await;
+    seed_request(&pool, "old", Utc::now() - Duration::days(35), 1, 1).await;
+    seed_request(&pool, "new", Utc::now(), 1, 1).await;
+    pool.close().await;
+
+    let prune = |dry_run: bool| {
+        let mut command = proxy(&dir);
+        command.args(["prune", "--older-than", "30d", "--db"]).arg(&path);
+        if dry_run {
+            command.arg("--dry-run");
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let preview = prune(true);
+    assert!(preview.starts_with("Would delete 2 requests"), "{}", preview);
+    assert!(preview.lines().any(|line| line.trim() == format!("{:<22}2", "old")), "{}", preview);
+    assert!(prune(true).starts_with("Would delete 2 requests"));
+
+    assert!(prune(false).starts_with("Deleted 2 requests"));
+    let output = proxy(&dir).args(["stats", "--json", "--db"]).arg(&path).output().unwrap();
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["summary"]["total_rows"], 1);
+
+    let output = proxy(&dir)
+        .args(["prune", "--older-than", "30d", "--db"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+}
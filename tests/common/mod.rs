@@ -0,0 +1,518 @@
+//! Helpers for tests that run the built binary: scratch directories, seeded databases
+//! and a server on free ports.
+
+#![allow(dead_code)]
+
+use sqlx::sqlite::{SqliteConnection, SqlitePoolOptions};
+use sqlx::{Connection, SqlitePool};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+/// A directory under the system temp dir, removed on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "lms-metrics-proxy-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).expect("temp dir");
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The binary, run from `dir` with a clean environment so no `.env` or shell settings
+/// leak in.
+pub fn proxy(dir: &TempDir) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_lms_metrics_proxy"));
+    command
+        .current_dir(dir.path())
+        .env_clear()
+        .env("RUST_LOG", "warn");
+    command
+}
+
+/// Creates a database with the full schema, by letting the binary initialise an empty
+/// file, and opens it for seeding.
+pub async fn empty_database(dir: &TempDir) -> (PathBuf, SqlitePool) {
+    let path = dir.join("metrics.db");
+    std::fs::File::create(&path).expect("database file");
+    let output = proxy(dir)
+        .args(["stats", "--db"])
+        .arg(&path)
+        .output()
+        .expect("run stats");
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&format!("sqlite:{}?mode=rw", path.display()))
+        .await
+        .expect("open database");
+    (path, pool)
+}
+
+/// Inserts a request row directly, the way rows written before blobs look.
+pub async fn seed_request(
+    pool: &SqlitePool,
+    model: &str,
+    start_time: chrono::DateTime<chrono::Utc>,
+    input_tokens: i64,
+    output_tokens: i64,
+) {
+    sqlx::query(
+        r#"
+        INSERT INTO requests (
+            endpoint, model, start_time, end_time, duration_ms,
+            input_tokens, output_tokens, total_tokens, prompt, output, http_status
+        )
+        VALUES ('/v1/chat/completions', ?1, ?2, ?2, 100, ?3, ?4, ?3 + ?4, 'hi', 'hello', 200)
+        "#,
+    )
+    .bind(model)
+    .bind(start_time.to_rfc3339())
+    .bind(input_tokens)
+    .bind(output_tokens)
+    .execute(pool)
+    .await
+    .expect("seed request");
+}
+
+/// A port nothing listens on right now.
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("free port")
+        .port()
+}
+
+/// The proxy server, killed on drop.
+pub struct Server {
+    child: Child,
+    pub port: u16,
+    pub dir: TempDir,
+}
+
+impl Server {
+    /// Starts the server on a free port with a fresh database and `env` on top, and
+    /// waits for `/health` to answer.
+    pub fn start(env: &[(&str, String)]) -> Self {
+        let server = Self::launch(env);
+        server.wait_for(server.port);
+        server
+    }
+
+    /// Starts the server like [`Server::start`] on the database already in `dir`.
+    pub fn start_in(dir: TempDir, env: &[(&str, String)]) -> Self {
+        let server = Self::launch_in(dir, env);
+        server.wait_for(server.port);
+        server
+    }
+
+    /// Starts the server like [`Server::start`] without waiting for it.
+    pub fn launch(env: &[(&str, String)]) -> Self {
+        Self::launch_in(TempDir::new(), env)
+    }
+
+    fn launch_in(dir: TempDir, env: &[(&str, String)]) -> Self {
+        let port = free_port();
+        let mut command = proxy(&dir);
+        command
+            .env("PORT", port.to_string())
+            .env("DATABASE_URL", format!("sqlite:{}", dir.join("metrics.db").display()))
+            // Nothing listens here, so forwarded requests fail fast
+            .env("LM_STUDIO_URL", format!("http://127.0.0.1:{}", free_port()))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        for (name, value) in env {
+            command.env(name, value);
+        }
+        let child = command.spawn().expect("start server");
+        Server { child, port, dir }
+    }
+
+    /// Waits until `port` answers `/health`.
+    pub fn wait_for(&self, port: u16) {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while Instant::now() < deadline {
+            if let Ok((200, _)) = try_request(port, "GET", "/health", &[], "") {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("server on port {} never became healthy", port);
+    }
+
+    pub fn get(&self, path: &str) -> (u16, String) {
+        request(self.port, "GET", path, &[], "")
+    }
+
+    /// `GET path`, parsed as JSON.
+    pub fn get_json(&self, path: &str) -> serde_json::Value {
+        let (status, body) = self.get(path);
+        assert_eq!(status, 200, "GET {}: {}", path, body);
+        serde_json::from_str(&body).expect("JSON response")
+    }
+
+    /// The most recent requests, newest first.
+    pub fn recent(&self) -> Vec<serde_json::Value> {
+        self.get_json("/stats/recent")["requests"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Sends SIGTERM and waits for the server to exit.
+    pub fn stop(mut self) -> ExitStatus {
+        let sent = Command::new("kill")
+            .args(["-TERM", &self.child.id().to_string()])
+            .status()
+            .expect("send SIGTERM");
+        assert!(sent.success());
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait().expect("wait for server") {
+                return status;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("server did not shut down");
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Takes the database's write lock so every write waits until [`release`].
+///
+/// Hold it for less than the pool's five-second busy timeout, or the writes fail instead.
+pub async fn stall_writes(server: &Server) -> SqliteConnection {
+    let url = format!("sqlite:{}?mode=rw", server.dir.join("metrics.db").display());
+    let mut conn = SqliteConnection::connect(&url).await.expect("open database");
+    sqlx::query("BEGIN IMMEDIATE")
+        .execute(&mut conn)
+        .await
+        .expect("take the write lock");
+    conn
+}
+
+pub async fn release(mut conn: SqliteConnection) {
+    sqlx::query("ROLLBACK").execute(&mut conn).await.unwrap();
+    conn.close().await.unwrap();
+}
+
+/// Sends one HTTP/1.1 request and returns the status and body.
+pub fn request(
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> (u16, String) {
+    try_request(port, method, path, headers, body).expect("request")
+}
+
+fn try_request(
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> std::io::Result<(u16, String)> {
+    let (status, body) = try_request_bytes(port, method, path, headers, body)?;
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// Like [`request`], with the body exactly as sent.
+pub fn request_bytes(
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> (u16, Vec<u8>) {
+    try_request_bytes(port, method, path, headers, body).expect("request")
+}
+
+fn try_request_bytes(
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> std::io::Result<(u16, Vec<u8>)> {
+    let mut stream = TcpStream::connect(SocketAddr::from(([127, 0, 0, 1], port)))?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let head_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| std::io::Error::other("incomplete response"))?;
+    let head = String::from_utf8_lossy(&response[..head_end]);
+    let body = &response[head_end + 4..];
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| std::io::Error::other("no status line"))?;
+    let body = if head.to_ascii_lowercase().contains("transfer-encoding: chunked") {
+        dechunk_bytes(body)
+    } else {
+        body.to_vec()
+    };
+    Ok((status, body))
+}
+
+/// Joins the chunks of a chunked response body.
+pub fn dechunk(body: &str) -> String {
+    String::from_utf8_lossy(&dechunk_bytes(body.as_bytes())).into_owned()
+}
+
+/// Joins the chunks of a chunked response body, which may split characters.
+pub fn dechunk_bytes(mut body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    while let Some(line_end) = body.windows(2).position(|w| w == b"\r\n") {
+        let size = String::from_utf8_lossy(&body[..line_end]);
+        let Ok(size) = usize::from_str_radix(size.trim(), 16) else {
+            break;
+        };
+        let rest = &body[line_end + 2..];
+        if size == 0 || rest.len() < size {
+            break;
+        }
+        out.extend_from_slice(&rest[..size]);
+        body = rest[size..].strip_prefix(b"\r\n").unwrap_or(&rest[size..]);
+    }
+    out
+}
+
+/// A request the mock upstream received.
+#[derive(Clone)]
+pub struct Received {
+    pub method: String,
+    pub path: String,
+    pub head: String,
+    pub body: String,
+}
+
+/// A stand-in for LM Studio answering every request with `respond`, each connection on
+/// its own thread.
+pub struct Upstream {
+    pub port: u16,
+}
+
+impl Upstream {
+    pub fn start<F>(respond: F) -> Self
+    where
+        F: Fn(&Received, &mut TcpStream) -> std::io::Result<()> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock upstream");
+        let port = listener.local_addr().unwrap().port();
+        let respond = std::sync::Arc::new(respond);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let respond = respond.clone();
+                std::thread::spawn(move || {
+                    if let Ok(received) = read_request(&mut stream) {
+                        let _ = respond(&received, &mut stream);
+                    }
+                });
+            }
+        });
+        Upstream { port }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> std::io::Result<Received> {
+    let mut data = Vec::new();
+    let mut buf = [0; 8192];
+    let head_end = loop {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Err(std::io::Error::other("connection closed"));
+        }
+        data.extend_from_slice(&buf[..n]);
+        if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+    let lower = head.to_ascii_lowercase();
+    let chunked = lower.contains("transfer-encoding: chunked");
+    let length = lower
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|len| len.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    loop {
+        let body = &data[head_end..];
+        let complete = if chunked {
+            body.ends_with(b"0\r\n\r\n")
+        } else {
+            body.len() >= length
+        };
+        if complete {
+            break;
+        }
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    let body = String::from_utf8_lossy(&data[head_end..]).into_owned();
+    let mut request_line = head.split_whitespace();
+    Ok(Received {
+        method: request_line.next().unwrap_or_default().to_string(),
+        path: request_line.next().unwrap_or_default().to_string(),
+        body: if chunked { dechunk(&body) } else { body },
+        head,
+    })
+}
+
+/// Writes a complete JSON response and closes the connection.
+pub fn respond_json(stream: &mut TcpStream, status: u16, body: &str) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Starts a chunked `text/event-stream` response; follow with [`send_chunk`].
+pub fn start_event_stream(stream: &mut TcpStream) -> std::io::Result<()> {
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+          Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+    )?;
+    stream.flush()
+}
+
+/// Sends `data` as one HTTP chunk of an event stream.
+pub fn send_chunk(stream: &mut TcpStream, data: &str) -> std::io::Result<()> {
+    send_chunk_bytes(stream, data.as_bytes())
+}
+
+/// Sends `data` as one HTTP chunk, which need not end on a character boundary.
+pub fn send_chunk_bytes(stream: &mut TcpStream, data: &[u8]) -> std::io::Result<()> {
+    write!(stream, "{:x}\r\n", data.len())?;
+    stream.write_all(data)?;
+    stream.write_all(b"\r\n")?;
+    stream.flush()
+}
+
+/// Ends a chunked response.
+pub fn end_chunks(stream: &mut TcpStream) -> std::io::Result<()> {
+    stream.write_all(b"0\r\n\r\n")?;
+    stream.flush()
+}
+
+/// An SSE line carrying one streamed chat delta.
+pub fn delta_event(text: &str) -> String {
+    let chunk = serde_json::json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "model": "m",
+        "choices": [{ "index": 0, "delta": { "content": text }, "finish_reason": null }]
+    });
+    format!("data: {}\n\n", chunk)
+}
+
+/// The closing SSE events of a chat stream: a finish reason with usage, then `[DONE]`.
+pub fn final_events(input_tokens: i64, output_tokens: i64) -> String {
+    let chunk = serde_json::json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "model": "m",
+        "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }],
+        "usage": {
+            "prompt_tokens": input_tokens,
+            "completion_tokens": output_tokens,
+            "total_tokens": input_tokens + output_tokens
+        }
+    });
+    format!("data: {}\n\ndata: [DONE]\n\n", chunk)
+}
+
+/// A non-streamed chat completion body.
+pub fn completion_body(text: &str, input_tokens: i64, output_tokens: i64) -> String {
+    serde_json::json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion",
+        "model": "m",
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": text },
+            "finish_reason": "stop"
+        }],
+        "usage": {
+            "prompt_tokens": input_tokens,
+            "completion_tokens": output_tokens,
+            "total_tokens": input_tokens + output_tokens
+        }
+    })
+    .to_string()
+}
+
+/// Retries `check` until it returns something, for effects that land asynchronously
+/// such as rows written by the background spool.
+pub fn eventually<T>(what: &str, check: impl Fn() -> Option<T>) -> T {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Some(value) = check() {
+            return value;
+        }
+        if Instant::now() > deadline {
+            panic!("timed out waiting for {}", what);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,129 @@
+//! `/admin/db` and `/admin/db/checkpoint`: the data directory inventory, and truncating
+//! a WAL grown by real request writes, on demand or past `WAL_CHECKPOINT_MB`.
+
+mod common;
+
+use common::{Server, TempDir, Upstream, completion_body, eventually, request, respond_json};
+use serde_json::{Value, json};
+
+const ADMIN: (&str, &str) = ("Authorization", "Bearer secret");
+
+fn on_disk(dir: &TempDir, name: &str) -> u64 {
+    std::fs::metadata(dir.join(name)).map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+/// Sends `count` requests with distinct 20 kB prompts, so every insert adds to the WAL.
+fn drive_writes(server: &Server, count: usize) {
+    for i in 0..count {
+        let prompt = format!("{} {}", i, "lorem ipsum ".repeat(1_700));
+        let body = json!({"model": "m", "messages": [{"role": "user", "content": prompt}]});
+        let (status, _) = request(
+            server.port,
+            "POST",
+            "/v1/chat/completions",
+            &[],
+            &body.to_string(),
+        );
+        assert_eq!(status, 200);
+    }
+    eventually("every request to be stored", || {
+        let stored = server.get_json("/stats/summary")["total_requests"].as_u64()?;
+        (stored == count as u64).then_some(())
+    });
+}
+
+fn admin(server: &Server, method: &str, path: &str) -> (u16, Value) {
+    let (status, body) = request(server.port, method, path, &[ADMIN], "");
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+#[test]
+fn the_inventory_and_a_checkpoint_match_the_filesystem() {
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("Hello", 5_000, 2))
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("ADMIN_TOKEN", "secret".to_string()),
+    ]);
+    for (name, contents) in [("old.db-wal", "stale"), ("metrics.db.bak", "copy"), ("notes", "x")] {
+        std::fs::write(server.dir.join(name), contents).unwrap();
+    }
+    drive_writes(&server, 60);
+
+    let (status, _) = request(server.port, "GET", "/admin/db", &[], "");
+    assert_eq!(status, 401);
+    let (status, _) = request(server.port, "POST", "/admin/db/checkpoint", &[], "");
+    assert_eq!(status, 401);
+
+    let (status, inventory) = admin(&server, "GET", "/admin/db");
+    assert_eq!(status, 200, "{}", inventory);
+    let file = |name: &str| {
+        inventory["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|file| file["name"] == name)
+            .cloned()
+            .unwrap_or_else(|| panic!("{} missing from {}", name, inventory))
+    };
+    let wal = file("metrics.db-wal");
+    assert_eq!((&wal["purpose"], &wal["in_use"]), (&json!("wal"), &json!(true)));
+    assert!(wal["bytes"].as_u64().unwrap() > 1_000_000, "{}", wal);
+    assert_eq!(file("metrics.db")["purpose"], "database");
+    assert_eq!(file("metrics.db-shm")["purpose"], "shared_memory");
+    let stale = file("old.db-wal");
+    assert_eq!((&stale["purpose"], &stale["in_use"]), (&json!("wal"), &json!(false)));
+    assert_eq!(stale["bytes"], 5);
+    assert_eq!(file("metrics.db.bak")["purpose"], "backup");
+    assert_eq!(file("notes")["purpose"], "unknown");
+    let listed: u64 = inventory["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["bytes"].as_u64().unwrap())
+        .sum();
+    assert_eq!(inventory["total_bytes"], listed);
+
+    let before = on_disk(&server.dir, "metrics.db-wal");
+    let (status, report) = admin(&server, "POST", "/admin/db/checkpoint");
+    assert_eq!(status, 200, "{}", report);
+    assert_eq!(report["busy"], false, "{}", report);
+    assert_eq!(report["wal_bytes_after"], 0, "{}", report);
+    assert_eq!(on_disk(&server.dir, "metrics.db-wal"), 0);
+    let reported = report["wal_bytes_before"].as_u64().unwrap();
+    // Only a counter flush can have written in between
+    assert!(reported >= before, "{} vs {}", reported, before);
+    assert_eq!(report["reclaimed_bytes"], reported);
+
+    // Files the proxy doesn't own are left alone
+    for name in ["old.db-wal", "metrics.db.bak", "notes"] {
+        assert!(server.dir.join(name).exists(), "{}", name);
+    }
+    assert_eq!(server.get_json("/stats/summary")["total_requests"], 60);
+}
+
+#[test]
+fn the_maintenance_check_truncates_a_wal_past_the_limit() {
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("Hello", 5_000, 2))
+    });
+
+    // Without a limit, startup's own writes stay in the WAL
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    assert!(on_disk(&server.dir, "metrics.db-wal") > 0);
+    drop(server);
+
+    // The first check runs at startup and finds the WAL past a limit of 0 MB
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("WAL_CHECKPOINT_MB", "0".to_string()),
+    ]);
+    eventually("the WAL to be truncated", || {
+        (on_disk(&server.dir, "metrics.db-wal") == 0).then_some(())
+    });
+    // and later writes are kept as usual
+    drive_writes(&server, 5);
+    assert!(on_disk(&server.dir, "metrics.db-wal") > 0);
+}
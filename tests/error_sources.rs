@@ -0,0 +1,125 @@
+//! Every failing path records who produced its status: LM Studio's own errors as
+//! `upstream`, the proxy's refusals and failures as `proxy`, and `/stats/errors`
+//! cross-tabulates them.
+
+mod common;
+
+use common::{Server, Upstream, eventually, request, respond_json};
+use serde_json::{Value, json};
+use std::time::Duration;
+
+/// Request headers, prompt, and the status the client should get
+type Case<'a> = (&'a [(&'a str, &'a str)], &'a str, u16);
+
+fn chat(content: &str) -> String {
+    json!({ "model": "m", "messages": [{ "role": "user", "content": content }] }).to_string()
+}
+
+/// Rejects or crashes when the prompt says so, stalls on "slow", and otherwise answers
+/// with a body that isn't JSON.
+fn upstream() -> Upstream {
+    Upstream::start(|received, stream| {
+        if received.body.contains("slow") {
+            std::thread::sleep(Duration::from_secs(2));
+        }
+        if received.body.contains("reject") {
+            respond_json(stream, 400, r#"{"error":"bad request"}"#)
+        } else if received.body.contains("crash") {
+            respond_json(stream, 500, r#"{"error":"model crashed"}"#)
+        } else {
+            respond_json(stream, 200, "{not json")
+        }
+    })
+}
+
+fn send(server: &Server, headers: &[(&str, &str)], content: &str) -> u16 {
+    request(server.port, "POST", "/v1/chat/completions", headers, &chat(content)).0
+}
+
+/// `(source, class) -> errors` from `/stats/errors`.
+fn by_source(report: &Value) -> Vec<(String, String, i64)> {
+    report["by_source"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|group| {
+            (
+                group["source"].as_str().unwrap().to_string(),
+                group["status_class"].as_str().unwrap().to_string(),
+                group["errors"].as_i64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn each_source_and_class_is_recorded() {
+    let upstream = upstream();
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+
+    let cases: [Case; 5] = [
+        (&[], "Please reject this", 400),
+        (&[], "Please crash", 500),
+        (&[("x-proxy-deadline-ms", "soon")], "Tell me a story", 400),
+        // Passed through as LM Studio sent it, but logged as a failure
+        (&[], "Tell me a story", 200),
+        (&[("x-proxy-deadline-ms", "300")], "Tell me a slow story", 504),
+    ];
+    for (headers, content, status) in cases {
+        assert_eq!(send(&server, headers, content), status, "{}", content);
+    }
+
+    let report = eventually("every error to be stored", || {
+        let report = server.get_json("/stats/errors?since=1h");
+        (report["total_errors"] == 5).then_some(report)
+    });
+    let mut groups = by_source(&report);
+    groups.sort();
+    assert_eq!(
+        groups,
+        [
+            ("proxy".to_string(), "4xx".to_string(), 1),
+            ("proxy".to_string(), "5xx".to_string(), 1),
+            ("upstream".to_string(), "2xx".to_string(), 1),
+            ("upstream".to_string(), "4xx".to_string(), 1),
+            ("upstream".to_string(), "5xx".to_string(), 1),
+        ]
+    );
+    let mut categories: Vec<_> = report["by_category"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|group| {
+            let category = group["category"].as_str().unwrap().to_string();
+            (category, group["http_status"].as_i64().unwrap())
+        })
+        .collect();
+    categories.sort();
+    assert_eq!(
+        categories,
+        [
+            ("bad_request".to_string(), 400),
+            ("parse_error".to_string(), 200),
+            ("timeout".to_string(), 504),
+            ("upstream_4xx".to_string(), 400),
+            ("upstream_5xx".to_string(), 500),
+        ]
+    );
+    assert_eq!(report["by_model"][0]["key"], "m");
+}
+
+#[test]
+fn unreachable_upstream_is_a_proxy_error_that_never_reached_it() {
+    // The default upstream has nothing listening
+    let server = Server::start(&[]);
+    assert_eq!(send(&server, &[], "Tell me a story"), 502);
+
+    let report = eventually("the error to be stored", || {
+        let report = server.get_json("/stats/errors");
+        (report["total_errors"] == 1).then_some(report)
+    });
+    assert_eq!(by_source(&report), [("proxy".to_string(), "5xx".to_string(), 1)]);
+    assert_eq!(report["by_category"][0]["category"], "connection_error");
+    assert_eq!(report["never_reached_upstream"], 1);
+    assert_eq!(report["recent"][0]["source"], "proxy");
+}
@@ -0,0 +1,136 @@
+//! Stored requests shipped to an HTTP sink by the running proxy: batches on the wire,
+//! the dead-letter file while the sink is down, and requests never waiting on it.
+
+mod common;
+
+use common::{
+    Received, Server, Upstream, completion_body, eventually, free_port, request, respond_json,
+};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const CHAT: &str = r#"{"model":"m","messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+fn upstream() -> Upstream {
+    Upstream::start(|_, stream| respond_json(stream, 200, &completion_body("The end", 5, 2)))
+}
+
+fn chat(server: &Server) {
+    let (status, body) = request(server.port, "POST", "/v1/chat/completions", &[], CHAT);
+    assert_eq!(status, 200, "{}", body);
+}
+
+fn self_stats(server: &Server, field: &str) -> u64 {
+    server.get_json("/stats/self")[field].as_u64().unwrap()
+}
+
+#[test]
+fn stored_requests_arrive_in_batches_without_bodies() {
+    let upstream = upstream();
+    let batches: Arc<Mutex<Vec<Received>>> = Arc::default();
+    let received = batches.clone();
+    let sink = Upstream::start(move |batch, stream| {
+        received.lock().unwrap().push(batch.clone());
+        respond_json(stream, 200, "")
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("EXPORT_SINK_URL", format!("{}/ingest?format=JSONEachRow", sink.url())),
+        ("EXPORT_SINK_HEADERS", "X-Token=secret".to_string()),
+        ("EXPORT_SINK_BATCH_SIZE", "2".to_string()),
+        ("EXPORT_SINK_INTERVAL_MS", "300".to_string()),
+    ]);
+
+    for _ in 0..3 {
+        chat(&server);
+    }
+    eventually("all three records to be sent", || {
+        (self_stats(&server, "export_sink_sent") == 3).then_some(())
+    });
+
+    let batches = batches.lock().unwrap();
+    let mut exported = Vec::new();
+    for batch in batches.iter() {
+        assert_eq!(batch.method, "POST");
+        assert_eq!(batch.path, "/ingest?format=JSONEachRow");
+        let head = batch.head.to_ascii_lowercase();
+        assert!(head.contains("x-token: secret"), "{}", batch.head);
+        assert!(head.contains("content-type: application/x-ndjson"), "{}", batch.head);
+        for line in batch.body.lines() {
+            exported.push(serde_json::from_str::<Value>(line).unwrap());
+        }
+    }
+    let sizes: BTreeSet<usize> = batches.iter().map(|b| b.body.lines().count()).collect();
+    assert!(sizes.iter().all(|size| *size <= 2), "{:?}", sizes);
+
+    for record in &exported {
+        assert!(record["id"].as_i64().unwrap() > 0);
+        assert_eq!(record["output_tokens"], 2);
+        assert!(record.get("prompt").is_none() && record.get("output").is_none());
+    }
+    let exported: BTreeSet<String> =
+        exported.iter().map(|r| r["proxy_request_id"].to_string()).collect();
+    let stored: BTreeSet<String> =
+        server.recent().iter().map(|r| r["proxy_request_id"].to_string()).collect();
+    assert_eq!(exported, stored);
+    assert_eq!(self_stats(&server, "export_sink_pending"), 0);
+}
+
+#[test]
+fn a_down_sink_fills_the_dead_letter_file() {
+    let upstream = upstream();
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("EXPORT_SINK_URL", format!("http://127.0.0.1:{}/", free_port())),
+        ("EXPORT_SINK_INTERVAL_MS", "100".to_string()),
+        ("EXPORT_SINK_DEAD_LETTER_FILE", "dead-letter.ndjson".to_string()),
+        ("EXPORT_SINK_DEAD_LETTER_AFTER_SECS", "0".to_string()),
+    ]);
+
+    for _ in 0..3 {
+        chat(&server);
+    }
+    eventually("the records to be dead-lettered", || {
+        (self_stats(&server, "export_sink_dead_lettered") == 3).then_some(())
+    });
+    let written = std::fs::read_to_string(server.dir.join("dead-letter.ndjson")).unwrap();
+    let ids: BTreeSet<i64> = written
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids.len(), 3);
+    assert_eq!(self_stats(&server, "export_sink_sent"), 0);
+    assert_eq!(self_stats(&server, "export_sink_dropped"), 0);
+}
+
+#[test]
+fn a_hanging_sink_holds_up_no_request() {
+    let upstream = upstream();
+    let sink = Upstream::start(|_, _| {
+        std::thread::sleep(Duration::from_secs(30));
+        Ok(())
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("EXPORT_SINK_URL", sink.url()),
+        ("EXPORT_SINK_BATCH_SIZE", "1".to_string()),
+        ("EXPORT_SINK_BUFFER", "2".to_string()),
+    ]);
+
+    // One record out for delivery, two buffered, the rest dropped
+    let started = Instant::now();
+    for _ in 0..6 {
+        chat(&server);
+    }
+    assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+    eventually("the overflow to be counted", || {
+        (self_stats(&server, "export_sink_dropped") >= 3).then_some(())
+    });
+    assert_eq!(self_stats(&server, "export_sink_sent"), 0);
+    assert_eq!(
+        self_stats(&server, "export_sink_pending") + self_stats(&server, "export_sink_dropped"),
+        6
+    );
+}
@@ -0,0 +1,151 @@
+//! Protective limits, triggered against a mock upstream and read back from
+//! `/stats/limits/triggers`.
+
+mod common;
+
+use common::{
+    Server, Upstream, completion_body, delta_event, end_chunks, eventually, final_events,
+    request, respond_json, send_chunk, start_event_stream,
+};
+use serde_json::Value;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+const CHAT: &str = r#"{"model":"m","messages":[{"role":"user","content":"Tell me a story"}]}"#;
+const STREAM: &str =
+    r#"{"model":"m","stream":true,"messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+/// The trigger report once `limit` shows up in it.
+fn triggers_of(server: &Server, limit: &str) -> Value {
+    eventually(limit, || {
+        server.get_json("/stats/limits/triggers")["limits"]
+            .as_array()?
+            .iter()
+            .find(|entry| entry["limit"] == limit)
+            .cloned()
+    })
+}
+
+#[test]
+fn unmet_deadline_is_rejected_up_front() {
+    let server = Server::start(&[("DEADLINE_OVERHEAD_MS", "500".to_string())]);
+    let (status, _) = request(
+        server.port,
+        "POST",
+        "/v1/chat/completions",
+        &[("X-Proxy-Deadline-Ms", "100")],
+        CHAT,
+    );
+    assert_eq!(status, 504);
+
+    let hit = triggers_of(&server, "deadline_rejected");
+    assert_eq!(hit["requests"], 1);
+    assert_eq!(hit["triggers"], 1);
+    assert_eq!(hit["events_dropped"], 1);
+    assert_eq!(hit["bytes_truncated"], 0);
+}
+
+#[test]
+fn recent_latency_rejects_a_deadline_it_cannot_meet() {
+    let forwarded = Arc::new(AtomicUsize::new(0));
+    let counted = forwarded.clone();
+    let upstream = Upstream::start(move |_, stream| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(300));
+        respond_json(stream, 200, &completion_body("slow", 1, 1))
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("DEADLINE_OVERHEAD_MS", "0".to_string()),
+    ]);
+    // Ten successful requests are enough history for a p95 of at least 300 ms
+    for _ in 0..10 {
+        let (status, _) = request(server.port, "POST", "/v1/chat/completions", &[], CHAT);
+        assert_eq!(status, 200);
+    }
+    eventually("the history to be logged", || (server.recent().len() == 10).then_some(()));
+
+    let deadline = [("X-Proxy-Deadline-Ms", "250")];
+    let (status, body) = request(server.port, "POST", "/v1/chat/completions", &deadline, CHAT);
+    assert_eq!(status, 504);
+    assert!(body.contains("expected completion"), "{}", body);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 10);
+    assert_eq!(triggers_of(&server, "deadline_rejected")["requests"], 1);
+}
+
+#[test]
+fn expired_deadline_cuts_off_the_upstream_call() {
+    let upstream = Upstream::start(|_, stream| {
+        std::thread::sleep(Duration::from_secs(3));
+        respond_json(stream, 200, &completion_body("late", 1, 1))
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("DEADLINE_OVERHEAD_MS", "100".to_string()),
+    ]);
+    let (status, _) = request(
+        server.port,
+        "POST",
+        "/v1/chat/completions",
+        &[("X-Proxy-Deadline-Ms", "600")],
+        CHAT,
+    );
+    assert_eq!(status, 504);
+
+    let hit = triggers_of(&server, "deadline_timeout");
+    assert_eq!(hit["triggers"], 1);
+    assert_eq!(hit["events_dropped"], 1);
+}
+
+#[test]
+fn buffer_cap_counts_the_bytes_not_kept() {
+    let chunks: Vec<String> = (0..20).map(|i| delta_event(&format!("{:0>60}", i))).collect();
+    let streamed: usize = chunks.iter().map(String::len).sum();
+    let upstream_chunks = chunks.clone();
+    let upstream = Upstream::start(move |_, stream| {
+        start_event_stream(stream)?;
+        for chunk in &upstream_chunks {
+            send_chunk(stream, chunk)?;
+        }
+        send_chunk(stream, &final_events(3, 20))?;
+        end_chunks(stream)
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("MAX_BUFFERED_BYTES", "1024".to_string()),
+    ]);
+
+    let (status, body) = request(server.port, "POST", "/v1/chat/completions", &[], STREAM);
+    assert_eq!(status, 200);
+    // The client still gets everything
+    for chunk in &chunks {
+        assert!(body.contains(chunk.trim_end()));
+    }
+
+    let hit = triggers_of(&server, "buffer_cap");
+    assert_eq!(hit["triggers"], 1);
+    let truncated = hit["bytes_truncated"].as_i64().unwrap();
+    assert!(truncated > 0 && (truncated as usize) < streamed, "{}", hit);
+    assert_eq!(hit["events_dropped"], 0);
+
+    // Usage past the cap is still read
+    let request = eventually("the stored request", || server.recent().into_iter().next());
+    assert_eq!(request["output_tokens"], 20);
+}
+
+#[test]
+fn requests_within_every_limit_record_nothing() {
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("hello", 1, 1))
+    });
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+    let (status, _) = request(server.port, "POST", "/v1/chat/completions", &[], CHAT);
+    assert_eq!(status, 200);
+
+    eventually("the stored request", || {
+        (server.get_json("/stats/summary")["total_requests"] == 1).then_some(())
+    });
+    let report = server.get_json("/stats/limits/triggers");
+    assert_eq!(report["limits"], serde_json::json!([]));
+}
@@ -0,0 +1,128 @@
+//! Paced streams: metered out to the client, recorded as upstream sent them, and left
+//! untouched when no pace is asked for.
+
+mod common;
+
+use common::{
+    Server, Upstream, dechunk, delta_event, end_chunks, eventually, final_events, request,
+    send_chunk, start_event_stream,
+};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+const STREAM: &str =
+    r#"{"model":"m","stream":true,"messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+const WORDS: usize = 10;
+
+/// Every event the mock upstream sends, in order.
+fn upstream_events() -> Vec<String> {
+    let mut events: Vec<String> = (0..WORDS).map(|i| delta_event(&format!("w{} ", i))).collect();
+    events.push(final_events(5, WORDS as i64));
+    events
+}
+
+/// Sends the deltas at once, then holds the stream open for `hold` before finishing.
+fn bursty_upstream(hold: Duration) -> Upstream {
+    Upstream::start(move |_, stream| {
+        start_event_stream(stream)?;
+        let events = upstream_events();
+        send_chunk(stream, &events[..WORDS].concat())?;
+        std::thread::sleep(hold);
+        send_chunk(stream, &events[WORDS])?;
+        end_chunks(stream)
+    })
+}
+
+fn stream_request(pace: Option<&str>) -> String {
+    let pace = pace
+        .map(|rate| format!("X-Proxy-Pace-Tokens-Per-Sec: {}\r\n", rate))
+        .unwrap_or_default();
+    format!(
+        "POST /v1/chat/completions HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n{}\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        pace,
+        STREAM.len(),
+        STREAM
+    )
+}
+
+/// Deltas the client has received after `wait`, then the whole body once it ends.
+fn stream_through(server: &Server, pace: Option<&str>, wait: Duration) -> (usize, String) {
+    let mut client = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    client.write_all(stream_request(pace).as_bytes()).unwrap();
+
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    let until = Instant::now() + wait;
+    while let Some(left) = until.checked_duration_since(Instant::now()) {
+        client.set_read_timeout(Some(left.max(Duration::from_millis(1)))).unwrap();
+        match client.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(_) => break,
+        }
+    }
+    let early = String::from_utf8_lossy(&response).matches("chat.completion.chunk").count();
+
+    client.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+    client.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    (early, dechunk(body))
+}
+
+#[test]
+fn pacing_meters_events_out_in_order_and_records_the_real_stream() {
+    let upstream = bursty_upstream(Duration::from_millis(1500));
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("STREAM_PACING", "true".to_string()),
+    ]);
+
+    // 10 per second: a few deltas in the first 350 ms, not the whole burst
+    let (early, body) = stream_through(&server, Some("10"), Duration::from_millis(350));
+    assert!((1..=5).contains(&early), "{} deltas in the first 350 ms", early);
+    assert_eq!(body, upstream_events().concat());
+
+    let stored = eventually("the stream to be stored", || server.recent().into_iter().next());
+    let id = stored["id"].as_i64().unwrap();
+    let request = server.get_json(&format!("/stats/request/{}", id));
+    let output: String = (0..WORDS).map(|i| format!("w{} ", i)).collect();
+    assert_eq!(request["output"], output);
+    assert_eq!(request["output_tokens"], WORDS as i64);
+    // The first token came from upstream at once, however slowly it reached the client
+    assert!(request["ttft_ms"].as_i64().unwrap() < 300, "{}", request);
+}
+
+#[test]
+fn unpaced_streams_pass_through_byte_for_byte() {
+    for env in [vec![], vec![("STREAM_PACING", "true".to_string())]] {
+        let upstream = bursty_upstream(Duration::from_millis(500));
+        let mut env = env;
+        env.push(("LM_STUDIO_URL", upstream.url()));
+        let server = Server::start(&env);
+
+        let (early, body) = stream_through(&server, None, Duration::from_millis(300));
+        assert_eq!(early, WORDS, "the burst arrives as it was sent");
+        assert_eq!(body, upstream_events().concat());
+    }
+}
+
+#[test]
+fn pace_header_is_validated() {
+    let server = Server::start(&[]);
+    let pace = [("X-Proxy-Pace-Tokens-Per-Sec", "10")];
+    let (status, body) = request(server.port, "POST", "/v1/chat/completions", &pace, STREAM);
+    assert_eq!(status, 400);
+    assert!(body.contains("STREAM_PACING"), "{}", body);
+
+    let server = Server::start(&[("STREAM_PACING", "true".to_string())]);
+    for rate in ["0", "-3", "fast", "5000"] {
+        let pace = [("X-Proxy-Pace-Tokens-Per-Sec", rate)];
+        let (status, body) = request(server.port, "POST", "/v1/chat/completions", &pace, STREAM);
+        assert_eq!(status, 400, "rate {}: {}", rate, body);
+    }
+}
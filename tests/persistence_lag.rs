@@ -0,0 +1,55 @@
+//! The unwritten-request gauge on `/health` while the database is stalled, and the lag
+//! the stalled request ends up recorded with.
+
+mod common;
+
+use common::{
+    Server, Upstream, completion_body, eventually, release, request, respond_json, stall_writes,
+};
+use std::time::Duration;
+
+const CHAT: &str = r#"{"model":"m","messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+#[tokio::test]
+async fn gauge_rises_while_writes_stall_and_falls_once_written() {
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("Once upon a time", 5, 4))
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("PERSIST_LAG_ALERT_MS", "300".to_string()),
+    ]);
+    let health = server.get_json("/health");
+    assert_eq!(health["oldest_unpersisted_ms"], 0);
+    assert_eq!(health["persistence_lagging"], false);
+
+    let lock = stall_writes(&server).await;
+    let port = server.port;
+    // The write may hold up the response, so the request runs beside the checks
+    let client =
+        std::thread::spawn(move || request(port, "POST", "/v1/chat/completions", &[], CHAT));
+
+    eventually("the gauge to pass the alert threshold", || {
+        let health = server.get_json("/health");
+        (health["persistence_lagging"] == true).then_some(())
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let stalled = server.get_json("/health")["oldest_unpersisted_ms"].as_u64().unwrap();
+    assert!(stalled >= 500, "{}", stalled);
+
+    release(lock).await;
+    let (status, body) = client.join().unwrap();
+    assert_eq!(status, 200, "{}", body);
+    eventually("the gauge to fall back", || {
+        let health = server.get_json("/health");
+        (health["oldest_unpersisted_ms"] == 0).then_some(health)
+    });
+    assert_eq!(server.get_json("/health")["persistence_lagging"], false);
+
+    let report = server.get_json("/stats/persistence-lag");
+    assert_eq!(report["alert_ms"], 300);
+    assert_eq!(report["totals"]["requests"], 1);
+    assert_eq!(report["totals"]["over_alert"], 1);
+    let lag = report["totals"]["max_ms"].as_i64().unwrap();
+    assert!(lag >= stalled as i64, "{} < {}", lag, stalled);
+}
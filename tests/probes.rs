@@ -0,0 +1,142 @@
+//! Warm-up probes: tiny pings from client libraries are proxied and stored with
+//! `is_probe`, left out of the summary unless asked for, and counted per library.
+
+mod common;
+
+use common::{
+    Server, TempDir, Upstream, completion_body, eventually, free_port, request, respond_json,
+};
+use serde_json::{Value, json};
+
+fn chat(server: &Server, user_agent: &str, body: Value) {
+    let (status, _) = request(
+        server.port,
+        "POST",
+        "/v1/chat/completions",
+        &[("User-Agent", user_agent)],
+        &body.to_string(),
+    );
+    assert_eq!(status, 200);
+}
+
+fn message(text: &str) -> Value {
+    json!([{"role": "user", "content": text}])
+}
+
+/// Whether each stored request is a probe, oldest first.
+fn stored_probes(server: &Server, count: usize) -> Vec<bool> {
+    let rows = eventually("every request to be stored", || {
+        let rows = server.recent();
+        (rows.len() == count).then_some(rows)
+    });
+    rows.iter()
+        .rev()
+        .map(|row| {
+            let id = row["proxy_request_id"].as_str().unwrap();
+            let record = server.get_json(&format!("/stats/request/{}", id));
+            record["is_probe"].as_bool().unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn probes_are_stored_and_kept_out_of_the_summary() {
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("Hello", 10, 1))
+    });
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+
+    chat(&server, "curl/8.4.0", json!({"model": "m", "messages": message("ping")}));
+    chat(
+        &server,
+        "curl/8.4.0",
+        json!({"model": "m", "messages": message("Hi!"), "max_tokens": 1}),
+    );
+    chat(
+        &server,
+        "OpenAI/Python 1.30.1",
+        json!({"model": "m", "messages": message("Say OK"), "max_tokens": 1}),
+    );
+    for question in ["What is the capital of France?", "hi, summarize this file"] {
+        chat(
+            &server,
+            "OpenAI/Python 1.30.1",
+            json!({"model": "m", "messages": message(question)}),
+        );
+    }
+    assert_eq!(stored_probes(&server, 5), [true, true, true, false, false]);
+
+    let summary = server.get_json("/stats/summary");
+    assert_eq!(summary["total_requests"], 2, "{}", summary);
+    let summary = server.get_json("/stats/summary?include_probes=true");
+    assert_eq!(summary["total_requests"], 5, "{}", summary);
+    let models = server.get_json("/stats/by-model");
+    assert_eq!(models["models"][0]["requests"], 2, "{}", models);
+    let models = server.get_json("/stats/by-model?include_probes=true");
+    assert_eq!(models["models"][0]["requests"], 5, "{}", models);
+
+    let sdks = server.get_json("/stats/by-sdk");
+    let counts: Vec<_> = sdks["sdks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|sdk| {
+            (
+                sdk["sdk_name"].as_str().unwrap().to_string(),
+                sdk["requests"].as_i64().unwrap(),
+                sdk["probe_requests"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        counts,
+        [("openai-python".to_string(), 3, 1), ("curl".to_string(), 2, 2)]
+    );
+}
+
+#[test]
+fn thresholds_and_prompts_come_from_the_environment() {
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("Hello", 10, 1))
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("PROBE_MAX_PROMPT_CHARS", "10".to_string()),
+        ("PROBE_MAX_TOKENS", "1".to_string()),
+        ("PROBE_PROMPTS", "wake up, you up".to_string()),
+    ]);
+    let requests = [
+        json!({"model": "m", "messages": message("Wake up!")}),
+        json!({"model": "m", "messages": message("hi")}),
+        json!({"model": "m", "messages": message("Say OK"), "max_tokens": 1}),
+        json!({"model": "m", "messages": message("Say OK"), "max_tokens": 2}),
+        json!({"model": "m", "messages": message("Say OK right now"), "max_tokens": 1}),
+    ];
+    for body in requests {
+        chat(&server, "curl/8.4.0", body);
+    }
+    assert_eq!(stored_probes(&server, 5), [true, false, true, false, false]);
+    drop(server);
+
+    // A zero character limit turns detection off
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("PROBE_MAX_PROMPT_CHARS", "0".to_string()),
+    ]);
+    chat(&server, "curl/8.4.0", json!({"model": "m", "messages": message("hi")}));
+    assert_eq!(stored_probes(&server, 1), [false]);
+}
+
+#[test]
+fn invalid_thresholds_fail_startup() {
+    let dir = TempDir::new();
+    let output = common::proxy(&dir)
+        .env("PORT", free_port().to_string())
+        .env("DATABASE_URL", format!("sqlite:{}", dir.join("metrics.db").display()))
+        .env("PROBE_MAX_TOKENS", "few")
+        .output()
+        .expect("run server");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid PROBE_MAX_TOKENS value"), "{}", stderr);
+}
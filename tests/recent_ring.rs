@@ -0,0 +1,86 @@
+//! `/stats/recent` served from the in-memory ring once the database stops answering.
+
+mod common;
+
+use common::{Server, Upstream, completion_body, request, respond_json};
+use serde_json::Value;
+use sqlx::sqlite::SqlitePoolOptions;
+
+const CHAT: &str = r#"{"model":"m","messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+fn chat(server: &Server) {
+    let (status, body) = request(server.port, "POST", "/v1/chat/completions", &[], CHAT);
+    assert_eq!(status, 200, "{}", body);
+}
+
+/// Removes the requests table out from under the running server, so every query and
+/// insert it makes from then on fails.
+async fn break_database(server: &Server) {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&format!("sqlite:{}?mode=rw", server.dir.join("metrics.db").display()))
+        .await
+        .expect("open database");
+    sqlx::query("ALTER TABLE requests RENAME TO requests_gone")
+        .execute(&pool)
+        .await
+        .expect("rename requests table");
+    pool.close().await;
+}
+
+fn ids(page: &Value) -> Vec<Value> {
+    page["requests"]
+        .as_array()
+        .expect("requests")
+        .iter()
+        .map(|request| request["id"].clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn recent_traffic_is_served_from_memory_without_the_database() {
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("Once upon a time", 5, 4))
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("RECENT_RING_SIZE", "3".to_string()),
+    ]);
+
+    chat(&server);
+    chat(&server);
+    let stored = server.get_json("/stats/recent");
+    assert_eq!(stored["source"], "database");
+    assert_eq!(ids(&stored), [Value::from(2), Value::from(1)]);
+
+    break_database(&server).await;
+
+    // Completed while the database is gone: kept in the ring without a row id
+    chat(&server);
+    chat(&server);
+
+    let fallback = server.get_json("/stats/recent");
+    assert_eq!(fallback["source"], "memory");
+    assert_eq!(fallback["fallback_reason"], "database query failed");
+    assert_eq!(ids(&fallback), [Value::Null, Value::Null, Value::from(2)]);
+    let newest = &fallback["requests"][0];
+    assert_eq!(newest["model"], "m");
+    assert_eq!(newest["output_tokens"], 4);
+    assert_eq!(newest["is_error"], false);
+
+    let asked = server.get_json("/stats/recent?source=memory&limit=1");
+    assert_eq!(asked["source"], "memory");
+    assert_eq!(asked["fallback_reason"], Value::Null);
+    assert_eq!(ids(&asked), [Value::Null]);
+}
+
+#[test]
+fn memory_source_answers_while_the_database_is_healthy() {
+    let server = Server::start(&[]);
+    let page = server.get_json("/stats/recent?source=memory");
+    assert_eq!(page["source"], "memory");
+    assert_eq!(page["requests"], serde_json::json!([]));
+
+    let (status, _) = server.get("/stats/recent?source=disk");
+    assert_eq!(status, 400);
+}
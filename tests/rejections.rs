@@ -0,0 +1,96 @@
+//! Requests hyper rejects before any handler runs, sent as raw bytes over TCP, show up
+//! in `/stats/self`.
+
+mod common;
+
+use common::{Server, eventually};
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::Duration;
+
+/// Writes `bytes`, stops writing, and returns whatever the server answered.
+fn send_raw(server: &Server, bytes: &[u8]) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    // The server may answer and close before reading everything
+    let _ = stream.write_all(bytes);
+    let _ = stream.shutdown(Shutdown::Write);
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+fn diagnostics(server: &Server) -> Value {
+    server.get_json("/stats/self")
+}
+
+/// Waits for `total` rejections and returns the diagnostics.
+fn rejections(server: &Server, total: u64) -> Value {
+    eventually("the rejections to be counted", || {
+        let stats = diagnostics(server);
+        (stats["connection_rejections"] == total).then_some(stats)
+    })
+}
+
+#[test]
+fn malformed_requests_are_counted_by_kind() {
+    let server = Server::start(&[]);
+    assert_eq!(diagnostics(&server)["connection_rejections"], 0);
+
+    let response = send_raw(&server, b"GET /v1/models HTTP/9.9\r\nHost: x\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 "), "{}", response);
+    let stats = rejections(&server, 1);
+    let latest = &stats["recent_rejections"][0];
+    assert_eq!(latest["kind"], "parse_error");
+    assert_eq!(latest["method"], "GET");
+    assert_eq!(latest["path"], "/v1/models");
+    assert!(latest["peer"].as_str().unwrap().starts_with("127.0.0.1:"), "{}", latest);
+
+    let mut oversized = b"POST /v1/chat/completions HTTP/1.1\r\nX-Padding: ".to_vec();
+    oversized.extend(std::iter::repeat_n(b'a', 1024 * 1024));
+    oversized.extend_from_slice(b"\r\n\r\n");
+    let response = send_raw(&server, &oversized);
+    assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+    let stats = rejections(&server, 2);
+    assert_eq!(stats["recent_rejections"][0]["kind"], "too_large");
+    assert_eq!(stats["recent_rejections"][0]["path"], "/v1/chat/completions");
+
+    // The client hangs up halfway through the head
+    send_raw(&server, b"POST /v1/completions HTTP/1.1\r\nHost: x\r\n");
+    let stats = rejections(&server, 3);
+    assert_eq!(stats["recent_rejections"][0]["kind"], "incomplete_request");
+    assert_eq!(stats["recent_rejections"][0]["method"], "POST");
+
+    assert_eq!(
+        stats["connection_rejections_by_kind"],
+        serde_json::json!({ "incomplete_request": 1, "parse_error": 1, "too_large": 1 })
+    );
+}
+
+#[test]
+fn handled_requests_are_not_rejections() {
+    let server = Server::start(&[]);
+
+    // A complete request, then a connection opened and closed without a byte. The first
+    // client keeps its write side open, since hyper may drop a half-closed connection
+    // before answering.
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream
+        .write_all(b"GET /health HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    send_raw(&server, b"");
+    // A request whose client leaves before the response
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    stream.write_all(b"GET /stats/summary HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    drop(stream);
+
+    std::thread::sleep(Duration::from_millis(300));
+    let stats = diagnostics(&server);
+    assert_eq!(stats["connection_rejections"], 0, "{}", stats);
+    assert_eq!(stats["recent_rejections"], serde_json::json!([]));
+}
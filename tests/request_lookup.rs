@@ -0,0 +1,29 @@
+//! `/stats/request/{id}` finds a stored request by its proxy request id or its row id.
+
+mod common;
+
+use common::{Server, Upstream, completion_body, eventually, request, respond_json};
+
+#[test]
+fn either_id_finds_the_same_request() {
+    let upstream =
+        Upstream::start(|_, stream| respond_json(stream, 200, &completion_body("Hi", 4, 1)));
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+    let body = r#"{"model":"m","messages":[{"role":"user","content":"Hello"}]}"#;
+    let (status, _) = request(server.port, "POST", "/v1/chat/completions", &[], body);
+    assert_eq!(status, 200);
+
+    let stored = eventually("the request to be stored", || server.recent().into_iter().next());
+    let proxy_request_id = stored["proxy_request_id"].as_str().unwrap();
+    let by_proxy_id = server.get_json(&format!("/stats/request/{}", proxy_request_id));
+    let by_row_id = server.get_json(&format!("/stats/request/{}", stored["id"]));
+    assert_eq!(by_proxy_id, by_row_id);
+    assert_eq!(by_row_id["id"], stored["id"]);
+    assert_eq!(by_row_id["proxy_request_id"], proxy_request_id);
+    assert!(by_row_id["prompt"].as_str().unwrap().contains("Hello"));
+
+    for missing in ["999999", "not-a-stored-id"] {
+        let (status, body) = server.get(&format!("/stats/request/{}", missing));
+        assert_eq!(status, 404, "{}", body);
+    }
+}
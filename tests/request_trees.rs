@@ -0,0 +1,79 @@
+//! `X-Proxy-Parent-Id`: requests of a tool loop linked into a tree, read back from
+//! `/stats/request/{id}/tree` and nested within the turns of `/stats/sessions/{id}`.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{Server, Upstream, completion_body, eventually, request, respond_json};
+use serde_json::Value;
+
+const CHAT: &str = r#"{"model":"m","messages":[{"role":"user","content":"Use the tools"}]}"#;
+
+/// Sends a chat in session `s` under `parent`, returning its proxy request id once stored.
+fn chat(server: &Server, parent: Option<&str>) -> String {
+    let stored = server.recent().len();
+    let mut headers = vec![("X-Session-Id", "s")];
+    headers.extend(parent.map(|parent| ("X-Proxy-Parent-Id", parent)));
+    let (status, body) = request(server.port, "POST", "/v1/chat/completions", &headers, CHAT);
+    assert_eq!(status, 200, "{}", body);
+    let rows = eventually("the request to be stored", || {
+        let rows = server.recent();
+        (rows.len() == stored + 1).then_some(rows)
+    });
+    rows[0]["proxy_request_id"].as_str().unwrap().to_string()
+}
+
+fn ids(nodes: &Value) -> Vec<&str> {
+    nodes
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| node["proxy_request_id"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn linked_requests_form_a_tree_nested_in_their_session_turn() {
+    let heads = Arc::new(Mutex::new(Vec::new()));
+    let seen = heads.clone();
+    let upstream = Upstream::start(move |received, stream| {
+        seen.lock().unwrap().push(received.head.to_ascii_lowercase());
+        respond_json(stream, 200, &completion_body("Calling a tool", 10, 2))
+    });
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+
+    let root = chat(&server, None);
+    let tool = chat(&server, Some(&root));
+    let answer = chat(&server, Some(&tool));
+    let side = chat(&server, Some(&root));
+    let stray = chat(&server, Some("never-stored"));
+    assert!(heads.lock().unwrap().iter().all(|head| !head.contains("x-proxy-parent-id")));
+
+    let tree = server.get_json(&format!("/stats/request/{}/tree", root));
+    assert_eq!(tree["parent_missing"], false);
+    assert_eq!(ids(&tree["children"]), [tool.as_str(), side.as_str()]);
+    assert_eq!(ids(&tree["children"][0]["children"]), [answer.as_str()]);
+    assert_eq!(tree["cumulative"]["requests"], 4);
+    assert_eq!(tree["cumulative"]["input_tokens"], 40);
+    assert_eq!(tree["children"][0]["cumulative"]["requests"], 2);
+
+    let stray_tree = server.get_json(&format!("/stats/request/{}/tree", stray));
+    assert_eq!(stray_tree["parent_missing"], true);
+    assert_eq!(stray_tree["children"], Value::Array(Vec::new()));
+
+    let session = server.get_json("/stats/sessions/s");
+    assert_eq!(session["requests"].as_array().unwrap().len(), 5);
+    let turns = session["turns"].as_array().unwrap();
+    let tool_loop = turns.iter().find(|turn| turn["turn_id"] == root.as_str()).unwrap();
+    // The stray request lands in the same turn by client, as a tree of its own.
+    assert_eq!(ids(&tool_loop["trees"]), [root.as_str(), stray.as_str()]);
+    assert_eq!(tool_loop["trees"][0]["cumulative"]["requests"], 4);
+    assert_eq!(ids(&tool_loop["trees"][0]["children"]), [tool.as_str(), side.as_str()]);
+    let nested: usize = turns
+        .iter()
+        .flat_map(|turn| turn["trees"].as_array().unwrap())
+        .map(|tree| tree["cumulative"]["requests"].as_u64().unwrap() as usize)
+        .sum();
+    assert_eq!(nested, 5, "{}", session);
+}
@@ -0,0 +1,177 @@
+//! Pins the field names of every stats response, so a rename shows up as a test failure
+//! rather than a broken dashboard.
+//!
+//! Each JSON route listed by `/api/v1/` is called against a server that has proxied a
+//! few requests, and the shape of its body (every key path, with arrays collapsed to
+//! their elements) is compared with `tests/fixtures/response_fields.json`. Run with
+//! `UPDATE_RESPONSE_FIELDS=1` to rewrite the fixture after an intended change.
+
+mod common;
+
+use common::{
+    Server, Upstream, completion_body, delta_event, end_chunks, eventually, final_events,
+    request, respond_json, send_chunk, start_event_stream,
+};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/response_fields.json");
+const ADMIN: (&str, &str) = ("Authorization", "Bearer secret");
+
+/// Every key path in `value`; array elements share the path of their array plus `[]`.
+fn shape(value: &Value, path: &str, paths: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                paths.insert(path.clone());
+                shape(value, &path, paths);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                shape(item, &format!("{}[]", path), paths);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn serve() -> Server {
+    let upstream = Upstream::start(|received, stream| {
+        if received.path != "/v1/chat/completions" {
+            return respond_json(stream, 404, r#"{"error":"not found"}"#);
+        }
+        if received.body.contains("\"stream\":true") {
+            start_event_stream(stream)?;
+            send_chunk(stream, &delta_event("Once upon"))?;
+            send_chunk(stream, &delta_event(" a time"))?;
+            send_chunk(stream, &final_events(12, 3))?;
+            end_chunks(stream)
+        } else if received.body.contains("fail") {
+            respond_json(stream, 500, r#"{"error":"model crashed"}"#)
+        } else {
+            respond_json(stream, 200, &completion_body("The end.", 10, 3))
+        }
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("ADMIN_TOKEN", "secret".to_string()),
+    ]);
+
+    let chat = |content: &str, stream: bool| {
+        serde_json::json!({
+            "model": "m",
+            "stream": stream,
+            "messages": [{ "role": "user", "content": content }]
+        })
+        .to_string()
+    };
+    for (body, session) in [
+        (chat("Tell me a story", false), "s1"),
+        (chat("Tell me another story", true), "s1"),
+        (chat("Please fail", false), "s2"),
+    ] {
+        request(
+            server.port,
+            "POST",
+            "/v1/chat/completions",
+            &[("X-Session-Id", session), ("User-Agent", "OpenAI/Python 1.40.0")],
+            &body,
+        );
+    }
+    eventually("the requests to be stored", || {
+        (server.get_json("/stats/summary?detail=true")["total_rows"] == 3).then_some(())
+    });
+    server
+}
+
+/// Paths to call for each registered route, with its parameters filled in from the
+/// stored requests; routes that take an id of something not created here are skipped.
+fn calls(server: &Server) -> Vec<String> {
+    let recent = server.recent();
+    let newest = &recent[0];
+    let proxy_request_id = newest["proxy_request_id"].as_str().unwrap().to_string();
+
+    let index = server.get_json("/api/v1/");
+    let mut calls = Vec::new();
+    for route in index["routes"].as_array().unwrap() {
+        let alias = route["alias"].as_str().unwrap();
+        let path = match alias {
+            "/stats/request/{id}" | "/stats/request/{id}/tree" => {
+                alias.replace("{id}", &proxy_request_id)
+            }
+            "/stats/requests/by-request-id/{id}" => alias.replace("{id}", "chatcmpl-1"),
+            "/stats/sessions/{id}" => alias.replace("{id}", "s1"),
+            alias if alias.contains('{') => continue,
+            "/stats/summary" => format!("{}?detail=true", alias),
+            // Routes with a required parameter
+            "/stats/histogram" => format!("{}?field=duration_ms", alias),
+            "/stats/context-fit" => format!("{}?candidate_context=4096", alias),
+            "/stats/chargeback" => format!("{}?namespace=default", alias),
+            "/stats/badge" => format!("{}?metric=requests_today", alias),
+            "/stats/search" => format!("{}?q=story", alias),
+            "/admin/retention/simulate" => format!("{}?days=30", alias),
+            alias => alias.to_string(),
+        };
+        calls.push(path);
+    }
+    // The error body shared by every route
+    calls.push("/stats/request/missing".to_string());
+    calls
+}
+
+#[test]
+fn response_fields_are_pinned() {
+    let server = serve();
+    let mut shapes = BTreeMap::new();
+    for path in calls(&server) {
+        let (status, body) = request(server.port, "GET", &path, &[ADMIN], "");
+        // Routes that only take POST and friends, and the CSV and JSONL exports
+        let Ok(value) = serde_json::from_str::<Value>(&body) else {
+            continue;
+        };
+        if status == 405 {
+            continue;
+        }
+        let mut paths = BTreeSet::new();
+        shape(&value, "", &mut paths);
+        let paths: Vec<Value> = paths.into_iter().map(Value::String).collect();
+        shapes.insert(format!("{} {}", status, path_key(&path)), Value::Array(paths));
+    }
+    let actual = Value::Object(shapes.into_iter().collect::<Map<_, _>>());
+
+    if std::env::var_os("UPDATE_RESPONSE_FIELDS").is_some() {
+        let json = serde_json::to_string_pretty(&actual).unwrap();
+        std::fs::write(FIXTURE, json + "\n").unwrap();
+        return;
+    }
+    let expected: Value = serde_json::from_str(&std::fs::read_to_string(FIXTURE).unwrap()).unwrap();
+    let (actual, expected) = (actual.as_object().unwrap(), expected.as_object().unwrap());
+    for (route, fields) in expected {
+        assert_eq!(actual.get(route), Some(fields), "fields of {}", route);
+    }
+    for route in actual.keys() {
+        assert!(expected.contains_key(route), "{} is missing from the fixture", route);
+    }
+}
+
+/// The route a path was called as, with ids put back as placeholders.
+fn path_key(path: &str) -> String {
+    let path = path.split('?').next().unwrap_or_default();
+    path.split('/')
+        .map(|segment| {
+            let is_id = segment.parse::<i64>().is_ok() || segment.len() == 36 || segment == "s1";
+            if is_id || segment == "chatcmpl-1" {
+                "{id}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
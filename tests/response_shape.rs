@@ -0,0 +1,46 @@
+//! `?case=camel` renames response fields but leaves model names and stored request JSON
+//! as the client sent them; `?pretty=true` only changes the layout.
+
+mod common;
+
+use common::{Server, Upstream, completion_body, eventually, request, respond_json};
+use serde_json::{Value, json};
+
+#[test]
+fn camel_case_leaves_user_data_alone() {
+    let upstream =
+        Upstream::start(|_, stream| respond_json(stream, 200, &completion_body("Hi", 4, 1)));
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+    let chat = json!({
+        "model": "my_model",
+        "messages": [{ "role": "user", "content": "Write a haiku about harbor lights" }],
+        "top_p": 0.9,
+        "seed": 7,
+        "stop": ["stop_here"],
+    });
+    let (status, _) =
+        request(server.port, "POST", "/v1/chat/completions", &[], &chat.to_string());
+    assert_eq!(status, 200);
+    let stored = eventually("the request to be stored", || server.recent().into_iter().next());
+    let id = stored["proxy_request_id"].as_str().unwrap();
+
+    let record = server.get_json(&format!("/stats/request/{}?case=camel", id));
+    assert_eq!(record["proxyRequestId"], id);
+    assert!(record.get("proxy_request_id").is_none(), "{}", record);
+    assert_eq!(record["model"], "my_model");
+    assert_eq!(record["samplingParams"]["top_p"], 0.9, "{}", record);
+
+    let summary = server.get_json("/stats/summary?case=camel");
+    assert_eq!(summary["totalRequests"], 1);
+    let by_model = server.get_json("/stats/by-model?case=camel");
+    assert_eq!(by_model["models"][0]["model"], "my_model");
+    assert_eq!(by_model["models"][0]["totalTokens"], 5, "{}", by_model);
+
+    // Pretty output is the same document, and an unknown case is refused
+    let (_, pretty) = server.get("/stats/summary?case=camel&pretty=true");
+    assert!(pretty.contains("\n  \"totalRequests\": 1"), "{}", pretty);
+    let (status, body) = server.get("/stats/summary?case=kebab");
+    assert_eq!(status, 400);
+    let error: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(error["error"]["type"], "stats_error");
+}
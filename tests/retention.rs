@@ -0,0 +1,53 @@
+//! `/admin/retention/simulate` against a seeded database, checked by pruning it for real
+//! afterwards.
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{Server, TempDir, empty_database, proxy, request, seed_request};
+
+const ADMIN: (&str, &str) = ("Authorization", "Bearer secret");
+
+fn simulate(server: &Server, days: i64) -> (u16, String) {
+    let path = format!("/admin/retention/simulate?days={}", days);
+    request(server.port, "GET", &path, &[ADMIN], "")
+}
+
+#[tokio::test]
+async fn simulation_predicts_the_prune() {
+    let dir = TempDir::new();
+    let (path, pool) = empty_database(&dir).await;
+    for (model, days) in [("old", 45), ("old", 40), ("older", 60), ("new", 10), ("new", 0)] {
+        seed_request(&pool, model, Utc::now() - Duration::days(days), 1, 1).await;
+    }
+    pool.close().await;
+
+    let server = Server::start_in(dir, &[("ADMIN_TOKEN", "secret".to_string())]);
+    let (status, body) = simulate(&server, 30);
+    assert_eq!(status, 200, "{}", body);
+    let simulation: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let (status, _) = server.get("/admin/retention/simulate?days=30");
+    assert_eq!(status, 401);
+    assert_eq!(simulate(&server, -1).0, 400);
+
+    assert_eq!(simulation["requests"], 3);
+    assert_eq!(simulation["remaining_requests"], 2);
+    assert_eq!(simulation["models"][0]["model"], "old");
+    assert_eq!(simulation["models"][0]["requests"], 2);
+    assert_eq!(simulation["models"][1]["model"], "older");
+    assert_eq!(simulation["text_bytes"], 3 * "hihello".len());
+
+    let output = proxy(&server.dir)
+        .args(["prune", "--older-than", "30d", "--db"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Deleted 3 requests"), "{}", stdout);
+
+    let (_, body) = simulate(&server, 30);
+    let after: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(after["requests"], 0);
+    assert_eq!(after["remaining_requests"], 2);
+}
@@ -0,0 +1,55 @@
+//! `/v1` dispatch: local endpoints, forced forwarding and everything else passed through.
+
+mod common;
+
+use common::{Server, Upstream, request, respond_json};
+use std::sync::{Arc, Mutex};
+
+/// A mock upstream that answers every request and remembers its method and path.
+fn recording_upstream() -> (Upstream, Arc<Mutex<Vec<String>>>) {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = seen.clone();
+    let upstream = Upstream::start(move |received, stream| {
+        log.lock()
+            .unwrap()
+            .push(format!("{} {}", received.method, received.path));
+        respond_json(stream, 200, r#"{"from":"upstream"}"#)
+    });
+    (upstream, seen)
+}
+
+#[test]
+fn local_routes_are_answered_without_the_upstream() {
+    let (upstream, seen) = recording_upstream();
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+
+    // The batch endpoint's own validation answers, so it never reached LM Studio
+    let (status, body) = request(server.port, "POST", "/v1/batches", &[], "not json");
+    assert_eq!(status, 400, "{}", body);
+    assert!(body.contains("line 1"), "{}", body);
+
+    // Other methods and unknown paths fall through to the upstream
+    for (method, path) in [("GET", "/v1/batches"), ("POST", "/v1/no/such/endpoint")] {
+        let (status, body) = request(server.port, method, path, &[], "{}");
+        assert_eq!(status, 200, "{} {}: {}", method, path, body);
+        assert!(body.contains("upstream"), "{}", body);
+    }
+    assert_eq!(
+        *seen.lock().unwrap(),
+        ["GET /v1/batches", "POST /v1/no/such/endpoint"]
+    );
+}
+
+#[test]
+fn forward_paths_take_precedence_over_local_routes() {
+    let (upstream, seen) = recording_upstream();
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("FORWARD_PATHS", "/v1/batch*".to_string()),
+    ]);
+
+    let (status, body) = request(server.port, "POST", "/v1/batches", &[], "not json");
+    assert_eq!(status, 200, "{}", body);
+    assert!(body.contains("upstream"), "{}", body);
+    assert_eq!(*seen.lock().unwrap(), ["POST /v1/batches"]);
+}
@@ -0,0 +1,160 @@
+//! `SCHEMA_VALIDATION` and the `X-Proxy-Strict-Schema` header: strict checking refuses
+//! bodies the OpenAI API would reject without forwarding them, warn mode forwards them
+//! and keeps the violations with the prompt warnings.
+
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::{
+    Server, TempDir, Upstream, completion_body, eventually, free_port, request, respond_json,
+};
+use serde_json::{Value, json};
+
+const VALID: &str = r#"{"model":"m","messages":[{"role":"user","content":"Hi"}]}"#;
+const INVALID: &str =
+    r#"{"model":"m","messages":[{"role":"robot","content":"Hi"}],"temprature":0.5}"#;
+const STRICT: (&str, &str) = ("X-Proxy-Strict-Schema", "1");
+
+/// An upstream that counts the requests reaching it.
+fn counting_upstream() -> (Upstream, Arc<AtomicUsize>) {
+    let forwarded = Arc::new(AtomicUsize::new(0));
+    let counter = forwarded.clone();
+    let upstream = Upstream::start(move |_, stream| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        respond_json(stream, 200, &completion_body("Hello", 3, 1))
+    });
+    (upstream, forwarded)
+}
+
+fn chat(server: &Server, headers: &[(&str, &str)], body: &str) -> (u16, Value) {
+    let (status, body) = request(server.port, "POST", "/v1/chat/completions", headers, body);
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+/// The stored record of the newest request.
+fn newest(server: &Server, count: usize) -> Value {
+    let rows = eventually("the request to be stored", || {
+        let rows = server.recent();
+        (rows.len() == count).then_some(rows)
+    });
+    let id = rows[0]["proxy_request_id"].as_str().unwrap();
+    server.get_json(&format!("/stats/request/{}", id))
+}
+
+#[test]
+fn strict_mode_refuses_invalid_bodies_without_forwarding() {
+    let (upstream, forwarded) = counting_upstream();
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("SCHEMA_VALIDATION", "strict".to_string()),
+    ]);
+
+    let (status, body) = chat(&server, &[], INVALID);
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["error"]["type"], "proxy_error");
+    assert_eq!(
+        body["error"]["violations"],
+        json!([
+            {"path": "$.temprature", "problem": "unknown field"},
+            {
+                "path": "$.messages[0].role",
+                "problem": "'robot' is not one of system, developer, user, assistant, tool, function"
+            }
+        ])
+    );
+    let message = body["error"]["message"].as_str().unwrap();
+    assert!(message.contains("$.temprature: unknown field"), "{}", message);
+
+    let (status, body) = chat(&server, &[], "{not json");
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["error"]["violations"][0]["path"], "$");
+    assert_eq!(forwarded.load(Ordering::SeqCst), 0);
+
+    // The refusal is still recorded
+    let row = newest(&server, 2);
+    assert_eq!(row["http_status"], 400, "{}", row);
+    assert_eq!(row["is_error"], true, "{}", row);
+
+    let (status, body) = chat(&server, &[], VALID);
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn the_header_turns_on_strict_checking_for_one_request() {
+    let (upstream, forwarded) = counting_upstream();
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+
+    let (status, _) = chat(&server, &[], INVALID);
+    assert_eq!(status, 200);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+
+    let (status, body) = chat(&server, &[STRICT], INVALID);
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["error"]["violations"].as_array().unwrap().len(), 2);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+
+    // Embeddings are checked against their own schema
+    let (status, body) = request(
+        server.port,
+        "POST",
+        "/v1/embeddings",
+        &[STRICT],
+        r#"{"model":"m","input":{"text":"a"}}"#,
+    );
+    assert_eq!(status, 400, "{}", body);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        body["error"]["violations"],
+        json!([{"path": "$.input", "problem": "expected a string or an array, got an object"}])
+    );
+    assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn warn_mode_forwards_and_records_violations() {
+    let (upstream, forwarded) = counting_upstream();
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("SCHEMA_VALIDATION", "warn".to_string()),
+    ]);
+
+    let (status, body) = chat(&server, &[], INVALID);
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+
+    let row = newest(&server, 1);
+    let schema: Vec<&Value> = row["prompt_warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|warning| warning["type"] == "schema")
+        .collect();
+    assert_eq!(schema.len(), 2, "{}", row);
+    assert_eq!(schema[1]["path"], "$.messages[0].role");
+    assert_eq!(
+        schema[0],
+        &json!({"type": "schema", "path": "$.temprature", "problem": "unknown field"})
+    );
+
+    // The header still makes a single request strict
+    let (status, _) = chat(&server, &[STRICT], INVALID);
+    assert_eq!(status, 400);
+    assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn unknown_modes_fail_startup() {
+    let dir = TempDir::new();
+    let output = common::proxy(&dir)
+        .env("PORT", free_port().to_string())
+        .env("DATABASE_URL", format!("sqlite:{}", dir.join("metrics.db").display()))
+        .env("SCHEMA_VALIDATION", "loud")
+        .output()
+        .expect("run server");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid SCHEMA_VALIDATION value: loud"), "{}", stderr);
+}
@@ -0,0 +1,128 @@
+//! Soak tests: load pushed through a slow consumer moves the `/stats/self` drop counters,
+//! while every request is still answered in full.
+
+mod common;
+
+use common::{
+    Server, Upstream, completion_body, dechunk, delta_event, end_chunks, eventually, final_events,
+    request, respond_json, send_chunk, start_event_stream,
+};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const CHAT: &str = r#"{"model":"m","messages":[{"role":"user","content":"Tell me a story"}]}"#;
+const STREAM: &str =
+    r#"{"model":"m","stream":true,"messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+const CLIENTS: usize = 8;
+const REQUESTS_PER_CLIENT: usize = 10;
+
+fn counter(server: &Server, name: &str) -> u64 {
+    server.get_json("/stats/self")[name]
+        .as_u64()
+        .unwrap_or_else(|| panic!("{} missing from /stats/self", name))
+}
+
+#[test]
+fn slow_export_sink_drops_records_while_requests_are_served() {
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("Once upon a time", 5, 4))
+    });
+    let sink = Upstream::start(|_, stream| {
+        std::thread::sleep(Duration::from_millis(500));
+        respond_json(stream, 200, "{}")
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("EXPORT_SINK_URL", sink.url()),
+        ("EXPORT_SINK_BUFFER", "2".to_string()),
+        ("EXPORT_SINK_BATCH_SIZE", "1".to_string()),
+    ]);
+
+    let port = server.port;
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            std::thread::spawn(move || {
+                for _ in 0..REQUESTS_PER_CLIENT {
+                    let (status, body) = request(port, "POST", "/v1/chat/completions", &[], CHAT);
+                    assert_eq!(status, 200, "{}", body);
+                    assert!(body.contains("Once upon a time"), "{}", body);
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+
+    let total = (CLIENTS * REQUESTS_PER_CLIENT) as u64;
+    let stats = server.get_json("/stats/self");
+    let dropped = stats["export_sink_dropped"].as_u64().unwrap();
+    assert!(dropped > 0, "{}", stats);
+    assert_eq!(stats["requests_not_stored"], 0);
+    let page = server.get_json(&format!("/stats/recent?limit={}", total));
+    assert_eq!(page["requests"].as_array().unwrap().len() as u64, total);
+
+    // Whatever was queued is delivered in the end; nothing goes unaccounted for
+    eventually("export sink to drain", || {
+        (counter(&server, "export_sink_pending") == 0).then_some(())
+    });
+    assert_eq!(counter(&server, "export_sink_sent") + dropped, total);
+}
+
+#[test]
+fn slow_stream_reader_makes_the_proxy_wait_without_losing_output() {
+    // Far more than the socket buffers and the forwarding channel can hold
+    let chunk = delta_event(&"x".repeat(8 * 1024));
+    let chunks = 2000;
+    let upstream_chunk = chunk.clone();
+    let upstream = Upstream::start(move |_, stream| {
+        start_event_stream(stream)?;
+        for _ in 0..chunks {
+            send_chunk(stream, &upstream_chunk)?;
+        }
+        send_chunk(stream, &final_events(5, chunks as i64))?;
+        end_chunks(stream)
+    });
+    // Counting by characters keeps the proxy faster than the socket buffers fill
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("TOKENIZERS", "*=chars/4".to_string()),
+    ]);
+    assert_eq!(counter(&server, "stream_backpressure_waits"), 0);
+
+    let mut client = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+    write!(
+        client,
+        "POST /v1/chat/completions HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        STREAM.len(),
+        STREAM
+    )
+    .unwrap();
+
+    // Not reading yet: the proxy's writes back up while it keeps serving others
+    eventually("the proxy to wait on the reader", || {
+        (counter(&server, "stream_backpressure_waits") > 0).then_some(())
+    });
+    let (status, _) = server.get("/health");
+    assert_eq!(status, 200);
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let body = dechunk(body);
+    assert_eq!(body.matches(&chunk).count(), chunks);
+    assert!(body.ends_with("data: [DONE]\n\n"));
+
+    let recorded = eventually("the stream to be stored", || {
+        server.recent().into_iter().next()
+    });
+    assert_eq!(recorded["output_tokens"], chunks as i64);
+    assert_eq!(recorded["was_streamed"], true);
+    assert_eq!(counter(&server, "requests_not_stored"), 0);
+}
@@ -0,0 +1,101 @@
+//! Stream writes against a stalled database: handed to the spool so the stream's task
+//! ends at once, written when the database frees up, and dropped past the spool's cap.
+
+mod common;
+
+use common::{
+    Server, Upstream, delta_event, end_chunks, eventually, final_events, release, request,
+    send_chunk, stall_writes, start_event_stream,
+};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+const STREAM: &str =
+    r#"{"model":"m","stream":true,"messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+fn streaming_upstream() -> Upstream {
+    Upstream::start(|_, stream| {
+        start_event_stream(stream)?;
+        send_chunk(stream, &delta_event("Once upon a time"))?;
+        send_chunk(stream, &final_events(5, 4))?;
+        end_chunks(stream)
+    })
+}
+
+fn stream(server: &Server) {
+    let (status, body) = request(server.port, "POST", "/v1/chat/completions", &[], STREAM);
+    assert_eq!(status, 200, "{}", body);
+    assert!(body.contains("[DONE]"), "{}", body);
+}
+
+/// Takes the database's write lock, so every insert waits until it is released.
+fn diagnostics(server: &Server) -> Value {
+    server.get_json("/stats/self")
+}
+
+#[tokio::test]
+async fn stalled_writes_are_spooled_and_the_stream_task_exits() {
+    let upstream = streaming_upstream();
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("STREAM_WRITE_TIMEOUT_MS", "200".to_string()),
+    ]);
+
+    let lock = stall_writes(&server).await;
+    let started = Instant::now();
+    stream(&server);
+
+    // The task gave up on the insert, so nothing of the request is held any more
+    eventually("the record to be spooled", || {
+        (diagnostics(&server)["stream_writes_spooled"] == 1).then_some(())
+    });
+    assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+    let stats = diagnostics(&server);
+    assert_eq!(stats["spool_pending"], 1);
+    assert_eq!(stats["buffers"]["current_bytes"], 0);
+    assert_eq!(server.get_json("/stats/active")["count"], 0);
+    assert!(stats["oldest_unpersisted_ms"].as_u64().unwrap() > 0, "{}", stats);
+
+    release(lock).await;
+    let stored = eventually("the spool to write the record", || {
+        let page = server.get_json("/stats/recent");
+        (page["source"] == "database").then_some(page["requests"].as_array()?.first()?.clone())
+    });
+    assert_eq!(stored["output_tokens"], 4);
+    assert_eq!(stored["was_streamed"], true);
+    let stats = diagnostics(&server);
+    assert_eq!(stats["spool_pending"], 0);
+    assert_eq!(stats["requests_not_stored"], 0);
+    assert_eq!(stats["oldest_unpersisted_ms"], 0);
+}
+
+#[tokio::test]
+async fn full_spool_drops_records_instead_of_growing() {
+    let upstream = streaming_upstream();
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("STREAM_WRITE_TIMEOUT_MS", "200".to_string()),
+        ("SPOOL_CAPACITY", "1".to_string()),
+    ]);
+
+    let lock = stall_writes(&server).await;
+    // One being written, one waiting, and no room for the third
+    for _ in 0..3 {
+        stream(&server);
+    }
+    let stats = eventually("the spool to overflow", || {
+        let stats = diagnostics(&server);
+        (stats["stream_writes_spooled"] == 2 && stats["spool_overflows"] == 1).then_some(stats)
+    });
+    assert_eq!(stats["requests_not_stored"], 1);
+    assert_eq!(server.get_json("/stats/active")["count"], 0);
+
+    release(lock).await;
+    eventually("the spooled records to be written", || {
+        (diagnostics(&server)["spool_pending"] == 0).then_some(())
+    });
+    assert_eq!(server.recent().len(), 2);
+    // The dropped one is still in memory, without a row id
+    let memory = server.get_json("/stats/recent?source=memory");
+    assert_eq!(memory["requests"].as_array().unwrap().len(), 3);
+}
@@ -0,0 +1,141 @@
+//! `GET /status`: the public availability page, fed by the background prober, counting
+//! the time the proxy wasn't running as unknown rather than down.
+
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{Duration, Utc};
+use common::{
+    Server, TempDir, Upstream, completion_body, empty_database, eventually, free_port, request,
+    respond_json,
+};
+use serde_json::{Value, json};
+
+/// An upstream answering its model list while `up` is set, and chats always.
+fn upstream(up: Arc<AtomicBool>) -> Upstream {
+    Upstream::start(move |received, stream| {
+        if received.path != "/v1/models" {
+            return respond_json(stream, 200, &completion_body("a private answer", 5, 3));
+        }
+        if up.load(Ordering::SeqCst) {
+            respond_json(stream, 200, &json!({"object": "list", "data": []}).to_string())
+        } else {
+            respond_json(stream, 500, r#"{"error":"model crashed"}"#)
+        }
+    })
+}
+
+fn status(server: &Server) -> Value {
+    let (code, body) = request(server.port, "GET", "/status", &[], "");
+    assert_eq!(code, 200, "{}", body);
+    serde_json::from_str(&body).unwrap()
+}
+
+fn wait_for_status(server: &Server, expected: &str) -> Value {
+    eventually(&format!("status {}", expected), || {
+        let report = status(server);
+        (report["status"] == expected).then_some(report)
+    })
+}
+
+#[tokio::test]
+async fn the_page_tracks_outages_and_leaves_out_restart_gaps() {
+    // An hour up, two to three hours ago, from before a restart
+    let dir = TempDir::new();
+    let (_, pool) = empty_database(&dir).await;
+    let now = Utc::now();
+    sqlx::query("INSERT INTO availability_spans (state, started_at, last_seen_at) VALUES (?, ?, ?)")
+        .bind("up")
+        .bind((now - Duration::hours(3)).to_rfc3339())
+        .bind((now - Duration::hours(2)).to_rfc3339())
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    let up = Arc::new(AtomicBool::new(true));
+    let upstream = upstream(up.clone());
+    let server = Server::start_in(
+        dir,
+        &[
+            ("LM_STUDIO_URL", upstream.url()),
+            ("STATUS_PAGE", "true".to_string()),
+            ("STATUS_PROBE_INTERVAL_SECS", "1".to_string()),
+            ("ADMIN_TOKEN", "secret".to_string()),
+        ],
+    );
+
+    let report = wait_for_status(&server, "up");
+    let day = &report["uptime"]["24h"];
+    assert!(day["up_secs"].as_i64().unwrap() >= 3600, "{}", report);
+    assert_eq!(day["down_secs"], 0, "{}", report);
+    // The two hours the proxy was stopped count neither way
+    assert!(day["unknown_secs"].as_i64().unwrap() > 86_400 - 3600 - 60, "{}", report);
+    assert_eq!(day["uptime_percent"], 100.0, "{}", report);
+    assert_eq!(report["outages"], json!([]));
+
+    // Nothing about usage, even with requests logged
+    let chat = r#"{"model":"secret-model","messages":[{"role":"user","content":"my diary"}]}"#;
+    let (code, _) = request(server.port, "POST", "/v1/chat/completions", &[], chat);
+    assert_eq!(code, 200);
+    let (_, body) = request(server.port, "GET", "/status", &[], "");
+    for private in ["secret-model", "my diary", "private answer", "tokens", "requests"] {
+        assert!(!body.contains(private), "{} in {}", private, body);
+    }
+
+    up.store(false, Ordering::SeqCst);
+    wait_for_status(&server, "down");
+    // The down span covers time once a check after the change has been stored
+    let report = eventually("down time to be counted", || {
+        let report = status(&server);
+        (report["uptime"]["24h"]["down_secs"].as_i64()? > 0).then_some(report)
+    });
+    assert_eq!(report["outages"][0]["ended_at"], Value::Null, "{}", report);
+    assert!(report["uptime"]["24h"]["uptime_percent"].as_f64().unwrap() < 100.0);
+
+    up.store(true, Ordering::SeqCst);
+    let report = wait_for_status(&server, "up");
+    let outage = &report["outages"][0];
+    assert!(outage["ended_at"].is_string(), "{}", report);
+    assert_eq!(report["since"], outage["ended_at"], "{}", report);
+
+    let (code, html) = request(server.port, "GET", "/status", &[("Accept", "text/html,*/*")], "");
+    assert_eq!(code, 200);
+    assert!(html.contains("The LLM is up"), "{}", html);
+    let listed = format!(
+        "<li>{} to {} (",
+        outage["started_at"].as_str().unwrap(),
+        outage["ended_at"].as_str().unwrap()
+    );
+    assert!(html.contains(&listed), "{}", html);
+}
+
+#[test]
+fn the_page_is_public_on_every_port_only_when_enabled() {
+    let admin_port = free_port();
+    let server = Server::start(&[
+        ("STATUS_PAGE", "true".to_string()),
+        ("ADMIN_PORT", admin_port.to_string()),
+        ("ADMIN_BIND_ADDR", "127.0.0.1".to_string()),
+        ("ADMIN_TOKEN", "secret".to_string()),
+    ]);
+    server.wait_for(admin_port);
+    for port in [server.port, admin_port] {
+        let (code, body) = request(port, "GET", "/status", &[], "");
+        assert_eq!(code, 200, "{}", body);
+        // LM Studio isn't running here
+        let report = eventually("a failed check", || {
+            let (_, body) = request(port, "GET", "/status", &[], "");
+            let report: Value = serde_json::from_str(&body).ok()?;
+            (report["status"] == "down").then_some(report)
+        });
+        assert!(report["last_checked"].is_string(), "{}", report);
+    }
+    drop(server);
+
+    let server = Server::start(&[]);
+    let (code, _) = request(server.port, "GET", "/status", &[], "");
+    assert_eq!(code, 404);
+}
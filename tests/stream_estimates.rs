@@ -0,0 +1,59 @@
+//! Output token counts of streams that end without a usage chunk, taken from the count
+//! kept while the stream ran.
+
+mod common;
+
+use common::{
+    Server, Upstream, delta_event, end_chunks, eventually, request, send_chunk,
+    start_event_stream,
+};
+use serde_json::Value;
+
+const STREAM: &str =
+    r#"{"model":"m","stream":true,"messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+/// Streams `words` deltas, then either `[DONE]` or a dropped connection, never usage.
+fn upstream_without_usage(words: usize, finish: bool) -> Upstream {
+    Upstream::start(move |_, stream| {
+        start_event_stream(stream)?;
+        for i in 0..words {
+            send_chunk(stream, &delta_event(&format!("word{} ", i)))?;
+        }
+        if finish {
+            send_chunk(stream, "data: [DONE]\n\n")?;
+            end_chunks(stream)?;
+        }
+        // Otherwise the connection closes mid-body
+        Ok(())
+    })
+}
+
+fn stored_stream(server: &Server) -> Value {
+    let (status, _) = request(server.port, "POST", "/v1/chat/completions", &[], STREAM);
+    assert_eq!(status, 200);
+    let row = eventually("the stream to be stored", || server.recent().into_iter().next());
+    let id = row["proxy_request_id"].as_str().unwrap();
+    server.get_json(&format!("/stats/request/{}", id))
+}
+
+fn chars_estimate(text: &str) -> i64 {
+    (text.chars().count() as i64 + 3) / 4
+}
+
+#[test]
+fn streams_without_usage_keep_the_running_count() {
+    for finish in [true, false] {
+        let upstream = upstream_without_usage(300, finish);
+        let server = Server::start(&[
+            ("LM_STUDIO_URL", upstream.url()),
+            ("TOKENIZERS", "*=chars/4".to_string()),
+        ]);
+
+        let row = stored_stream(&server);
+        let output = row["output"].as_str().unwrap();
+        assert!(output.starts_with("word0 word1 "), "{}", row);
+        assert_eq!(row["tokens_estimated"], true, "{}", row);
+        assert_eq!(row["output_tokens"], chars_estimate(output), "{}", row);
+        assert_eq!(row["estimated_output_tokens"], row["output_tokens"], "{}", row);
+    }
+}
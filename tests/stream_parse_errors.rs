@@ -0,0 +1,87 @@
+//! Streams with chunks that aren't valid JSON, from `tests/fixtures/broken_stream.sse`:
+//! the client still gets every byte as sent, and the stored request counts and samples
+//! the broken lines.
+
+mod common;
+
+use common::{
+    Server, Upstream, end_chunks, eventually, request_bytes, send_chunk_bytes,
+    start_event_stream,
+};
+use serde_json::{Value, json};
+use std::time::Duration;
+
+const FIXTURE: &[u8] = include_bytes!("fixtures/broken_stream.sse");
+
+const STREAM: &str =
+    r#"{"model":"m","stream":true,"messages":[{"role":"user","content":"Tell me a story"}]}"#;
+
+/// Sends the fixture in 7-byte frames, so lines and characters alike are split.
+fn broken_upstream() -> Upstream {
+    Upstream::start(|_, stream| {
+        start_event_stream(stream)?;
+        for frame in FIXTURE.chunks(7) {
+            send_chunk_bytes(stream, frame)?;
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        end_chunks(stream)
+    })
+}
+
+/// Streams through the proxy, checks the client got the fixture unchanged and returns
+/// the stored request.
+fn stream_through(server: &Server, headers: &[(&str, &str)]) -> Value {
+    let (status, body) =
+        request_bytes(server.port, "POST", "/v1/chat/completions", headers, STREAM);
+    assert_eq!(status, 200);
+    assert!(body == FIXTURE, "client got {}", String::from_utf8_lossy(&body));
+
+    let row = eventually("the stream to be stored", || server.recent().into_iter().next());
+    let id = row["proxy_request_id"].as_str().unwrap();
+    server.get_json(&format!("/stats/request/{}", id))
+}
+
+#[test]
+fn broken_chunks_are_counted_and_sampled_while_the_client_gets_every_byte() {
+    let upstream = broken_upstream();
+    let server = Server::start(&[("LM_STUDIO_URL", upstream.url())]);
+
+    let row = stream_through(&server, &[]);
+    // Only the well-formed deltas reach the stored output, characters intact
+    assert_eq!(row["output"], "Hello café 😀 naïve world");
+    assert_eq!(row["output_tokens"], 9);
+    assert_eq!(row["is_error"], false);
+    assert_eq!(row["stream_parse_errors"], 4, "{}", row);
+
+    let samples = row["stream_parse_samples"].as_array().unwrap();
+    assert_eq!(samples.len(), 3);
+    assert!(samples[0].as_str().unwrap().ends_with(r#""content":"line one"#), "{}", row);
+    assert_eq!(samples[1], r#"data: {"choices":[{"index":0,"delta":{"content":"lost"}]}"#);
+    let long = samples[2].as_str().unwrap();
+    assert!(long.len() <= 512 && long.len() > 500, "{}", long.len());
+    assert!(long.starts_with(r#"data: {"choices":[{"index":0,"delta":{"content":"€€€"#));
+
+    let report = server.get_json("/stats/errors");
+    assert_eq!(
+        report["stream_parse_errors"],
+        json!([{
+            "model": "m",
+            "requests": 1,
+            "chunks": 4,
+            "last_seen": row["start_time"],
+        }])
+    );
+}
+
+#[test]
+fn paced_streams_forward_the_same_bytes() {
+    let upstream = broken_upstream();
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("STREAM_PACING", "true".to_string()),
+    ]);
+
+    let row = stream_through(&server, &[("X-Proxy-Pace-Tokens-Per-Sec", "1000")]);
+    assert_eq!(row["output"], "Hello café 😀 naïve world");
+    assert_eq!(row["stream_parse_errors"], 4);
+}
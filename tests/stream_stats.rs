@@ -0,0 +1,155 @@
+//! The opt-in `proxy_stats` event: sent after everything upstream sent, only to clients
+//! asking for it with `X-Proxy-Append-Stats`, and never part of the stored output.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{
+    Server, Upstream, completion_body, delta_event, end_chunks, eventually, final_events,
+    request, respond_json, send_chunk, start_event_stream,
+};
+use serde_json::Value;
+
+const STREAM: &str =
+    r#"{"model":"m","stream":true,"messages":[{"role":"user","content":"Tell me a story"}]}"#;
+const APPEND: (&str, &str) = ("X-Proxy-Append-Stats", "1");
+
+/// Streams "Hello world", then usage and `[DONE]` unless `usage` is off, keeping the
+/// request heads it received. Requests not asking for a stream get a plain completion.
+fn upstream(usage: bool, heads: Arc<Mutex<Vec<String>>>) -> Upstream {
+    Upstream::start(move |received, stream| {
+        heads.lock().unwrap().push(received.head.to_ascii_lowercase());
+        if !received.body.contains(r#""stream":true"#) {
+            return respond_json(stream, 200, &completion_body("Hello", 12, 1));
+        }
+        start_event_stream(stream)?;
+        send_chunk(stream, &delta_event("Hello "))?;
+        send_chunk(stream, &delta_event("world"))?;
+        if usage {
+            send_chunk(stream, &final_events(12, 2))?;
+        } else {
+            // Ends mid-event, without the blank line closing it
+            send_chunk(stream, "data: [DONE]\n")?;
+        }
+        end_chunks(stream)
+    })
+}
+
+fn server(upstream: &Upstream, enabled: bool) -> Server {
+    Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("STREAM_STATS_EVENT", enabled.to_string()),
+        ("MODEL_PRICING", "m:1000:2000".to_string()),
+    ])
+}
+
+fn stream(server: &Server, headers: &[(&str, &str)]) -> (u16, String) {
+    request(server.port, "POST", "/v1/chat/completions", headers, STREAM)
+}
+
+/// The stored record of the newest of `count` requests.
+fn newest(server: &Server, count: usize) -> Value {
+    let rows = eventually("the stream to be stored", || {
+        let rows = server.recent();
+        (rows.len() == count).then_some(rows)
+    });
+    let id = rows[0]["proxy_request_id"].as_str().unwrap();
+    server.get_json(&format!("/stats/request/{}", id))
+}
+
+/// The payload of the body's `proxy_stats` event, which must be its last event.
+fn stats_event(body: &str) -> Value {
+    let (before, event) = body.rsplit_once("event: proxy_stats\n").expect("a stats event");
+    assert!(before.ends_with("\n\n"), "not on an event boundary: {:?}", body);
+    let data = event
+        .strip_prefix("data: ")
+        .and_then(|event| event.strip_suffix("\n\n"))
+        .expect("one data line closing the stream");
+    assert!(!data.contains('\n'), "{:?}", body);
+    serde_json::from_str(data).unwrap()
+}
+
+#[test]
+fn the_event_follows_usage_and_done() {
+    let heads = Arc::new(Mutex::new(Vec::new()));
+    let upstream = upstream(true, heads.clone());
+    let server = server(&upstream, true);
+
+    let (status, body) = stream(&server, &[APPEND]);
+    assert_eq!(status, 200, "{}", body);
+    let usage = body.find("\"usage\"").expect("usage chunk");
+    let done = body.find("data: [DONE]\n\n").expect("[DONE]");
+    let event = body.find("event: proxy_stats").expect("stats event");
+    assert!(usage < done && done < event, "{}", body);
+    assert_eq!(body.matches("event: ").count(), 1, "{}", body);
+
+    let stats = stats_event(&body);
+    let row = newest(&server, 1);
+    assert_eq!(stats["proxy_request_id"], row["proxy_request_id"]);
+    assert_eq!(stats["model"], "m");
+    assert_eq!((&stats["input_tokens"], &stats["output_tokens"]), (&12.into(), &2.into()));
+    assert_eq!(stats["total_tokens"], 14);
+    assert_eq!(stats["tokens_estimated"], false);
+    assert_eq!(stats["duration_ms"], row["duration_ms"]);
+    assert_eq!(stats["ttft_ms"], row["ttft_ms"]);
+    assert!(stats["ttft_ms"].is_i64(), "{}", stats);
+    assert!((stats["estimated_cost"].as_f64().unwrap() - 0.016).abs() < 1e-9, "{}", stats);
+
+    // The stored output and upstream's request know nothing of it
+    assert_eq!(row["output"], "Hello world");
+    assert!(!heads.lock().unwrap()[0].contains("x-proxy-append-stats"));
+}
+
+#[test]
+fn a_stream_without_usage_or_a_closing_blank_line_still_gets_a_separate_event() {
+    let upstream = upstream(false, Arc::default());
+    let server = server(&upstream, true);
+
+    let (status, body) = stream(&server, &[APPEND]);
+    assert_eq!(status, 200, "{}", body);
+    assert!(body.contains("data: [DONE]\n\n\nevent: proxy_stats\n"), "{:?}", body);
+    let stats = stats_event(&body);
+    assert_eq!(stats["tokens_estimated"], true, "{}", stats);
+    assert!(stats["output_tokens"].as_i64().unwrap() > 0, "{}", stats);
+    assert_eq!(newest(&server, 1)["output"], "Hello world");
+}
+
+#[test]
+fn the_event_is_only_sent_when_asked_for() {
+    let upstream = upstream(true, Arc::default());
+    let server = server(&upstream, true);
+
+    for headers in [&[][..], &[("X-Proxy-Append-Stats", "0")][..]] {
+        let (status, body) = stream(&server, headers);
+        assert_eq!(status, 200);
+        assert!(!body.contains("proxy_stats"), "{}", body);
+        assert!(body.ends_with("data: [DONE]\n\n"), "{:?}", body);
+    }
+
+    // Non-streaming requests ignore the header
+    let chat = r#"{"model":"m","messages":[{"role":"user","content":"Hi"}]}"#;
+    let (status, body) = request(server.port, "POST", "/v1/chat/completions", &[APPEND], chat);
+    assert_eq!(status, 200);
+    assert!(!body.contains("proxy_stats"), "{}", body);
+
+    let (status, body) = stream(&server, &[("X-Proxy-Append-Stats", "please")]);
+    assert_eq!(status, 400, "{}", body);
+    assert!(body.contains("Invalid X-Proxy-Append-Stats value: please"), "{}", body);
+}
+
+#[test]
+fn the_header_is_refused_unless_enabled() {
+    let heads = Arc::new(Mutex::new(Vec::new()));
+    let upstream = upstream(true, heads.clone());
+    let server = server(&upstream, false);
+
+    let (status, body) = stream(&server, &[APPEND]);
+    assert_eq!(status, 400, "{}", body);
+    assert!(body.contains("requires STREAM_STATS_EVENT=true"), "{}", body);
+    assert!(heads.lock().unwrap().is_empty());
+
+    let (status, body) = stream(&server, &[]);
+    assert_eq!(status, 200);
+    assert!(!body.contains("proxy_stats"), "{}", body);
+}
@@ -0,0 +1,70 @@
+//! The `Type=notify` protocol, spoken to a mock notify socket.
+
+#![cfg(all(feature = "systemd", target_os = "linux"))]
+
+mod common;
+
+use common::{Server, TempDir};
+use std::net::TcpStream;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+/// Reads notifications until one equals `state`, returning those seen before it.
+fn receive_until(socket: &UnixDatagram, state: &str) -> Vec<String> {
+    let mut seen = Vec::new();
+    let mut buf = [0; 256];
+    loop {
+        let len = socket
+            .recv(&mut buf)
+            .unwrap_or_else(|e| panic!("no {} after {:?}: {}", state, seen, e));
+        let received = String::from_utf8_lossy(&buf[..len]).into_owned();
+        if received == state {
+            return seen;
+        }
+        seen.push(received);
+    }
+}
+
+fn mock_socket(socket: UnixDatagram) -> UnixDatagram {
+    socket
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .unwrap();
+    socket
+}
+
+#[test]
+fn ready_watchdog_and_stopping_are_sent() {
+    let dir = TempDir::new();
+    let path = dir.join("notify.sock");
+    let socket = mock_socket(UnixDatagram::bind(&path).unwrap());
+    let server = Server::launch(&[
+        ("NOTIFY_SOCKET", path.display().to_string()),
+        ("WATCHDOG_USEC", "200000".to_string()),
+    ]);
+
+    let before_ready = receive_until(&socket, "READY=1");
+    assert!(before_ready.iter().all(|state| state == "WATCHDOG=1"), "{:?}", before_ready);
+    // Ready means migrated and listening
+    assert!(TcpStream::connect(("127.0.0.1", server.port)).is_ok());
+    assert_eq!(server.get("/health").0, 200);
+
+    receive_until(&socket, "WATCHDOG=1");
+    receive_until(&socket, "WATCHDOG=1");
+
+    let status = server.stop();
+    assert!(status.success(), "{:?}", status);
+    receive_until(&socket, "STOPPING=1");
+}
+
+#[test]
+fn abstract_notify_socket_is_supported() {
+    let name = format!("lms-metrics-proxy-test-{}", std::process::id());
+    let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+    let socket = mock_socket(UnixDatagram::bind_addr(&addr).unwrap());
+    let server = Server::launch(&[("NOTIFY_SOCKET", format!("@{}", name))]);
+
+    let before_ready = receive_until(&socket, "READY=1");
+    assert!(before_ready.is_empty(), "no watchdog without WATCHDOG_USEC: {:?}", before_ready);
+    assert_eq!(server.get("/health").0, 200);
+}
@@ -0,0 +1,101 @@
+//! `/stats/utilization`: busy time built from stored requests on startup, then kept up
+//! to date as requests finish, with overlapping requests of a model counted once.
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{
+    Server, TempDir, Upstream, completion_body, empty_database, eventually, request,
+    respond_json, seed_request,
+};
+use serde_json::Value;
+
+fn model<'a>(report: &'a Value, name: &str) -> Option<&'a Value> {
+    report["models"]
+        .as_array()?
+        .iter()
+        .find(|entry| entry["model"] == name)
+}
+
+#[tokio::test]
+async fn busy_time_is_backfilled_and_kept_current() {
+    let dir = TempDir::new();
+    let (_, pool) = empty_database(&dir).await;
+    let today = Utc::now().date_naive();
+    let midnight = today.and_hms_opt(0, 0, 0).unwrap().and_utc();
+    // An hour-long request across midnight and one nested inside it
+    seed_request(&pool, "stored", midnight - Duration::minutes(30), 10, 10).await;
+    seed_request(&pool, "stored", midnight - Duration::minutes(10), 10, 10).await;
+    sqlx::query("UPDATE requests SET duration_ms = 3600000 WHERE id = 1")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE requests SET duration_ms = 600000 WHERE id = 2")
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    let upstream = Upstream::start(|_, stream| {
+        std::thread::sleep(std::time::Duration::from_millis(400));
+        respond_json(stream, 200, &completion_body("Hi", 5, 1))
+    });
+    let server = Server::start_in(dir, &[("LM_STUDIO_URL", upstream.url())]);
+
+    let report = server.get_json("/stats/utilization");
+    assert_eq!(report["days"], 30);
+    assert_eq!(report["to"], today.to_string());
+    let stored = model(&report, "stored").expect("backfilled model");
+    assert_eq!(stored["busy_ms"], 3_600_000);
+    assert_eq!(stored["busy_hours"], 1.0);
+    let daily: Vec<_> = stored["daily"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|day| (day["day"].as_str().unwrap().to_string(), day["busy_ms"].clone()))
+        .collect();
+    assert_eq!(
+        daily,
+        [
+            ((today - Duration::days(1)).to_string(), 1_800_000.into()),
+            (today.to_string(), 1_800_000.into()),
+        ]
+    );
+
+    // Only today's half falls in a one-day window
+    let report = server.get_json("/stats/utilization?since=1d");
+    assert_eq!(report["days"], 1);
+    assert_eq!(model(&report, "stored").unwrap()["busy_ms"], 1_800_000);
+
+    // Two requests at once keep the model busy for less than their summed durations
+    let port = server.port;
+    let chat = r#"{"model":"live","messages":[{"role":"user","content":"Hi"}]}"#;
+    let clients: Vec<_> = (0..2)
+        .map(|_| {
+            std::thread::spawn(move || request(port, "POST", "/v1/chat/completions", &[], chat))
+        })
+        .collect();
+    for client in clients {
+        assert_eq!(client.join().unwrap().0, 200);
+    }
+    let durations: Vec<i64> = eventually("both requests stored", || {
+        let durations: Vec<i64> = server
+            .recent()
+            .iter()
+            .filter(|record| record["model"] == "live")
+            .filter_map(|record| record["duration_ms"].as_i64())
+            .collect();
+        (durations.len() == 2).then_some(durations)
+    });
+    let busy = eventually("live busy time", || {
+        let report = server.get_json("/stats/utilization?since=1d");
+        model(&report, "live")?["busy_ms"].as_i64()
+    });
+    assert!(busy >= *durations.iter().max().unwrap(), "{} vs {:?}", busy, durations);
+    assert!(busy < durations.iter().sum(), "{} vs {:?}", busy, durations);
+
+    for since in ["0d", "12h", "soon"] {
+        let (status, body) = server.get(&format!("/stats/utilization?since={}", since));
+        assert_eq!(status, 400, "{}: {}", since, body);
+    }
+}
@@ -0,0 +1,250 @@
+//! Route permission tags: every registered stats route carries an explicit access class,
+//! and viewer tokens reach only the aggregate ones.
+
+mod common;
+
+use common::{Server, Upstream, completion_body, eventually, free_port, request, respond_json};
+use serde_json::Value;
+
+const ADMIN: (&str, &str) = ("Authorization", "Bearer secret");
+const VIEWER: (&str, &str) = ("Authorization", "Bearer wall");
+
+/// Text only the prompt and output of the seeded request contain
+const PROMPT_MARKER: &str = "giraffe";
+const OUTPUT_MARKER: &str = "Zanzibar";
+
+/// Every stats route and who may call it. A new route must be added here with the
+/// access it was registered with, so a route exposed to viewers is always a decision.
+const ROUTE_ACCESS: &[(&str, &str)] = &[
+    ("/stats/summary", "viewer"),
+    ("/stats/by-model", "viewer"),
+    ("/stats/models", "full"),
+    ("/stats/by-endpoint", "full"),
+    ("/stats/by-kind", "full"),
+    ("/stats/daily", "full"),
+    ("/stats/badge", "public"),
+    ("/stats/glance", "full"),
+    ("/stats/timeseries", "viewer"),
+    ("/stats/status-codes", "full"),
+    ("/stats/heatmap", "viewer"),
+    ("/stats/histogram", "full"),
+    ("/stats/utilization", "full"),
+    ("/stats/streaming", "full"),
+    ("/stats/estimation-gap", "full"),
+    ("/stats/params", "full"),
+    ("/stats/rate", "viewer"),
+    ("/stats/latency-trend", "full"),
+    ("/stats/rollups", "full"),
+    ("/stats/rollup/rebuild", "admin"),
+    ("/stats/canary", "full"),
+    ("/stats/by-language", "full"),
+    ("/stats/costs", "full"),
+    ("/stats/by-sdk", "full"),
+    ("/stats/context-fit", "full"),
+    ("/stats/context-utilization", "full"),
+    ("/stats/truncation", "full"),
+    ("/stats/finish-reasons", "full"),
+    ("/stats/by-prompt-version", "full"),
+    ("/stats/determinism", "full"),
+    ("/stats/guardrails", "full"),
+    ("/stats/stops", "full"),
+    ("/stats/export.csv", "full"),
+    ("/stats/export.jsonl", "full"),
+    ("/stats/errors", "full"),
+    ("/stats/retries", "full"),
+    ("/stats/limits/triggers", "full"),
+    ("/stats/agent-overhead", "full"),
+    ("/stats/turn-latency", "full"),
+    ("/stats/prompt-quality", "full"),
+    ("/stats/recent", "full"),
+    ("/stats/active", "full"),
+    ("/stats/search", "full"),
+    ("/stats/self", "full"),
+    ("/metrics", "full"),
+    ("/stats/persistence-lag", "full"),
+    ("/stats/advisor/unload", "full"),
+    ("/stats/reloads", "full"),
+    ("/stats/cache-opportunities", "full"),
+    ("/stats/duplicates", "full"),
+    ("/stats/top-prompts", "full"),
+    ("/stats/kv-cache", "full"),
+    ("/stats/chargeback", "full"),
+    ("/stats/request/{id}", "full"),
+    ("/stats/request/{id}/tree", "full"),
+    ("/stats/by-client", "full"),
+    ("/stats/sessions", "full"),
+    ("/stats/sessions/{id}", "full"),
+    ("/stats/requests/by-request-id/{id}", "full"),
+    ("/admin/verify", "admin"),
+    ("/admin/retention/simulate", "admin"),
+    ("/admin/db", "admin"),
+    ("/admin/db/checkpoint", "admin"),
+    ("/admin/batches/{id}", "admin"),
+    ("/admin/batches/{id}/results", "admin"),
+    ("/admin/batches/{id}/cancel", "admin"),
+    ("/admin/benchmark", "admin"),
+    ("/admin/benchmark/{id}", "admin"),
+    ("/admin/benchmark/{id}/cancel", "admin"),
+    ("/admin/routing/weights", "admin"),
+    ("/admin/prompt-versions/{hash}", "admin"),
+    ("/admin/webhooks", "admin"),
+    ("/admin/webhooks/{id}", "admin"),
+    ("/admin/webhooks/{id}/deliveries", "admin"),
+    ("/admin/incidents", "admin"),
+    ("/admin/incident/start", "admin"),
+    ("/admin/jobs", "admin"),
+    ("/admin/jobs/{id}", "admin"),
+    ("/admin/jobs/{id}/{action}", "admin"),
+];
+
+/// A server with admin and viewer tokens that has proxied one request.
+fn serve(extra_env: &[(&str, String)]) -> Server {
+    let upstream = Upstream::start(|_, stream| {
+        let answer = format!("They live in {}.", OUTPUT_MARKER);
+        respond_json(stream, 200, &completion_body(&answer, 10, 5))
+    });
+    let mut env = vec![
+        ("LM_STUDIO_URL", upstream.url()),
+        ("ADMIN_TOKEN", "secret".to_string()),
+        ("VIEWER_TOKENS", "wall".to_string()),
+    ];
+    env.extend(extra_env.iter().cloned());
+    let server = Server::start(&env);
+
+    let question = format!("Where does the purple {} live?", PROMPT_MARKER);
+    let chat = serde_json::json!({
+        "model": "m",
+        "messages": [{ "role": "user", "content": question }]
+    });
+    let (status, _) = request(server.port, "POST", "/v1/chat/completions", &[], &chat.to_string());
+    assert_eq!(status, 200);
+    server
+}
+
+/// `(alias, access)` of every route in `/api/v1/` on `port`.
+fn index(port: u16) -> Vec<(String, String)> {
+    let (status, body) = request(port, "GET", "/api/v1/", &[ADMIN], "");
+    assert_eq!(status, 200, "{}", body);
+    let index: Value = serde_json::from_str(&body).unwrap();
+    index["routes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|route| {
+            (
+                route["alias"].as_str().unwrap().to_string(),
+                route["access"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+/// A concrete path for `alias`, with placeholders filled and required parameters given.
+fn callable(alias: &str) -> String {
+    let path = alias
+        .replace("{id}", "1")
+        .replace("{hash}", "abc")
+        .replace("{action}", "pause");
+    let query = match alias {
+        "/stats/histogram" => "?field=duration_ms",
+        "/stats/context-fit" => "?candidate_context=4096",
+        "/stats/chargeback" => "?namespace=default",
+        "/stats/badge" => "?metric=requests_today",
+        "/stats/search" => "?q=giraffe",
+        "/admin/retention/simulate" => "?days=30",
+        _ => "",
+    };
+    path + query
+}
+
+#[test]
+fn every_route_has_an_explicit_access_tag() {
+    let server = serve(&[]);
+    let registered = index(server.port);
+
+    let expected: Vec<(String, String)> = ROUTE_ACCESS
+        .iter()
+        .map(|(alias, access)| (alias.to_string(), access.to_string()))
+        .collect();
+    for route in &registered {
+        assert!(
+            expected.contains(route),
+            "{} is registered as {} but not classified in ROUTE_ACCESS",
+            route.0,
+            route.1
+        );
+    }
+    for route in &expected {
+        assert!(registered.contains(route), "{} {} is not registered", route.0, route.1);
+    }
+}
+
+#[test]
+fn viewer_tokens_reach_only_aggregates() {
+    let server = serve(&[]);
+    eventually("the request to be stored", || {
+        (server.get_json("/stats/summary")["total_requests"] == 1).then_some(())
+    });
+
+    for (alias, access) in index(server.port) {
+        let path = callable(&alias);
+        for path in [path.clone(), format!("/api/v1{}", path)] {
+            for method in ["GET", "POST", "DELETE"] {
+                let (status, body) = request(server.port, method, &path, &[VIEWER], "");
+                match access.as_str() {
+                    "full" | "admin" => {
+                        assert_eq!(status, 403, "{} {} ({}) for a viewer", method, path, access);
+                    }
+                    _ => {
+                        assert!(
+                            status != 401 && status != 403,
+                            "{} {} refused a viewer with {}",
+                            method,
+                            path,
+                            status
+                        );
+                        assert!(
+                            !body.contains(PROMPT_MARKER) && !body.contains(OUTPUT_MARKER),
+                            "{} {} shows request text to a viewer: {}",
+                            method,
+                            path,
+                            body
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // The same token holders see text through the routes meant for it
+    let (status, body) = request(server.port, "GET", "/stats/search?q=giraffe", &[ADMIN], "");
+    assert_eq!(status, 200);
+    assert!(body.contains(PROMPT_MARKER), "{}", body);
+}
+
+#[test]
+fn proxy_port_mounts_only_viewer_routes_when_stats_move_away() {
+    let admin_port = free_port();
+    let server = serve(&[
+        ("ADMIN_PORT", admin_port.to_string()),
+        ("ADMIN_BIND_ADDR", "127.0.0.1".to_string()),
+    ]);
+    server.wait_for(admin_port);
+
+    let mounted = index(server.port);
+    assert!(!mounted.is_empty());
+    for (alias, access) in &mounted {
+        assert!(access == "viewer" || access == "public", "{} is {}", alias, access);
+    }
+    for (alias, access) in ROUTE_ACCESS {
+        let path = callable(alias);
+        let (status, _) = request(server.port, "GET", &path, &[ADMIN], "");
+        if *access == "viewer" || *access == "public" {
+            // A token is required there, even for aggregates
+            assert_ne!(status, 404, "{} should be on the proxy port", path);
+            assert_eq!(request(server.port, "GET", &path, &[], "").0, 401, "{}", path);
+        } else {
+            assert_eq!(status, 404, "{} should stay off the proxy port", path);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Webhook subscriptions through the admin API: matching requests reach the subscriber
+//! signed, the rest don't, and every delivery shows up in the history.
+
+mod common;
+
+use common::{Received, Server, Upstream, completion_body, eventually, request, respond_json};
+use serde_json::{Value, json};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const ADMIN: (&str, &str) = ("Authorization", "Bearer secret");
+const JSON: (&str, &str) = ("Content-Type", "application/json");
+
+fn chat(server: &Server, model: &str) {
+    let body = json!({ "model": model, "messages": [{ "role": "user", "content": "Hi" }] });
+    let (status, body) =
+        request(server.port, "POST", "/v1/chat/completions", &[], &body.to_string());
+    assert_eq!(status, 200, "{}", body);
+}
+
+fn admin(server: &Server, method: &str, path: &str, body: Value) -> (u16, Value) {
+    let body = if body.is_null() { String::new() } else { body.to_string() };
+    let (status, body) = request(server.port, method, path, &[ADMIN, JSON], &body);
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+#[test]
+fn matching_requests_are_delivered_signed_and_recorded() {
+    let upstream = Upstream::start(|_, stream| {
+        respond_json(stream, 200, &completion_body("Hello there", 5, 300))
+    });
+    let received: Arc<Mutex<Vec<Received>>> = Arc::default();
+    let deliveries = received.clone();
+    let subscriber = Upstream::start(move |delivery, stream| {
+        deliveries.lock().unwrap().push(delivery.clone());
+        respond_json(stream, 200, "{}")
+    });
+    let server = Server::start(&[
+        ("LM_STUDIO_URL", upstream.url()),
+        ("ADMIN_TOKEN", "secret".to_string()),
+    ]);
+
+    // Bad filters are refused with the reason
+    let hooks = "/admin/webhooks";
+    let url = format!("{}/n8n", subscriber.url());
+    let (status, error) = admin(&server, "POST", hooks, json!({ "filter": "modle", "url": url }));
+    assert_eq!(status, 400);
+    assert!(error.to_string().contains("unknown field 'modle'"), "{}", error);
+    let (status, _) = admin(&server, "POST", hooks, json!({ "filter": "model ==", "url": url }));
+    assert_eq!(status, 400);
+    let unauthorized = json!({ "filter": "model", "url": url }).to_string();
+    let (status, _) = request(server.port, "POST", hooks, &[JSON], &unauthorized);
+    assert_eq!(status, 401);
+
+    let (status, webhook) = admin(
+        &server,
+        "POST",
+        hooks,
+        json!({
+            "name": "big qwen answers",
+            "filter": "model ~ 'qwen*' and output_tokens > 100",
+            "url": url,
+            "secret": "s3cret",
+            "payload": "summary",
+        }),
+    );
+    assert_eq!(status, 200, "{}", webhook);
+    assert_eq!(webhook["signed"], true);
+    assert!(webhook.get("secret").is_none());
+    let id = webhook["id"].as_i64().unwrap();
+    let (_, listed) = admin(&server, "GET", hooks, Value::Null);
+    assert_eq!(listed["webhooks"][0]["id"], id);
+
+    chat(&server, "llama-3");
+    chat(&server, "Qwen2.5-7B");
+    let history_path = format!("/admin/webhooks/{}/deliveries", id);
+    let history = eventually("the delivery to be recorded", || {
+        let (_, history) = admin(&server, "GET", &history_path, Value::Null);
+        (!history["deliveries"].as_array()?.is_empty()).then_some(history)
+    });
+    std::thread::sleep(Duration::from_millis(200));
+    let sent = received.lock().unwrap().clone();
+    assert_eq!(sent.len(), 1);
+
+    let delivery = &history["deliveries"][0];
+    assert_eq!(delivery["status"], "delivered");
+    assert_eq!(delivery["attempts"], 1);
+    assert_eq!(delivery["http_status"], 200);
+    assert_eq!(history["webhook"]["name"], "big qwen answers");
+
+    let sent = &sent[0];
+    assert_eq!(sent.path, "/n8n");
+    assert!(sent.head.to_ascii_lowercase().contains("x-webhook-signature: sha256="));
+    let payload: Value = serde_json::from_str(&sent.body).unwrap();
+    assert_eq!(payload["event"], "request.completed");
+    assert_eq!(payload["webhook_id"], id);
+    assert_eq!(payload["request"]["model"], "Qwen2.5-7B");
+    assert_eq!(payload["request"]["output_tokens"], 300);
+    assert_eq!(payload["request"]["proxy_request_id"], delivery["proxy_request_id"]);
+    assert!(payload["request"].get("sdk_name").is_none());
+
+    // Once removed, nothing more is sent and the history is gone
+    let (status, _) = admin(&server, "DELETE", &format!("{}/{}", hooks, id), Value::Null);
+    assert_eq!(status, 200);
+    chat(&server, "qwen-14b");
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(received.lock().unwrap().len(), 1);
+    let (status, _) = admin(&server, "GET", &history_path, Value::Null);
+    assert_eq!(status, 404);
+}